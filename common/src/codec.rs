@@ -0,0 +1,151 @@
+use crate::crc::crc16;
+use crate::framing::{encode_frame, FrameDecoder, FrameError, MAX_FRAME_LEN};
+use crate::packet::{Packet, PACKET_CRC_LEN};
+
+/// Serialize `packet` followed by the CRC-16 of its bytes, in little endian,
+/// and frame it into `buffer`. `MAX_ENCODED_FRAME_LEN` bytes always fit any
+/// packet. Returns the part of `buffer` the frame was written to.
+pub fn encode_packet<'a>(packet: &Packet, buffer: &'a mut [u8]) -> Result<&'a [u8], FrameError> {
+    let mut contents = [0u8; MAX_FRAME_LEN];
+    let length = postcard::to_slice(packet, &mut contents[..MAX_FRAME_LEN - PACKET_CRC_LEN])
+        .map_err(|_| FrameError::BufferFull)?
+        .len();
+    let crc = crc16(&contents[..length]).to_le_bytes();
+    contents[length..length + PACKET_CRC_LEN].copy_from_slice(&crc);
+    encode_frame(&contents[..length + PACKET_CRC_LEN], buffer)
+}
+
+/// Take the packet out of a frame's decoded contents, checking its CRC.
+pub fn decode_packet(contents: &[u8]) -> Result<Packet, FrameError> {
+    let (packet, crc) =
+        postcard::take_from_bytes::<Packet>(contents).map_err(|_| FrameError::Malformed)?;
+    let [low, high] = crc else {
+        return Err(FrameError::Malformed);
+    };
+    let length = contents.len() - PACKET_CRC_LEN;
+    if crc16(&contents[..length]) != u16::from_le_bytes([*low, *high]) {
+        return Err(FrameError::Corrupt);
+    }
+    Ok(packet)
+}
+
+/// Turns a byte stream back into packets. The one decoder both ends of the
+/// link use, so they agree on what is dropped.
+///
+/// Bytes of a frame which hasn't ended yet are kept until it does, so frames
+/// can be split across reads in any way. Frames which are malformed, fail
+/// their CRC check or outgrow `MAX_ENCODED_FRAME_LEN` are dropped and
+/// counted, never silently, and the stream re-aligns at the next one.
+#[derive(Debug, Clone, Default)]
+pub struct PacketCodec {
+    frames: FrameDecoder,
+}
+
+impl PacketCodec {
+    pub const fn new() -> Self {
+        Self {
+            frames: FrameDecoder::new(),
+        }
+    }
+
+    /// How many bytes of an unfinished frame are held.
+    pub fn pending(&self) -> usize {
+        self.frames.pending()
+    }
+
+    /// Collect `bytes` from the stream, calling `on_packet` with each packet
+    /// they finish. Returns how many frames were dropped.
+    pub fn decode(&mut self, bytes: &[u8], mut on_packet: impl FnMut(Packet)) -> usize {
+        let mut dropped = 0;
+        self.frames
+            .feed(bytes, |contents| match contents.and_then(decode_packet) {
+                Ok(packet) => on_packet(packet),
+                Err(_) => dropped += 1,
+            });
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::{decode_frame, MAX_ENCODED_FRAME_LEN};
+    use crate::packet::PingPacket;
+
+    const PING: Packet = Packet::Ping(PingPacket { nonce: 0x1234_5678 });
+
+    #[test]
+    fn test_packet_round_trip() {
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        let frame = encode_packet(&PING, &mut buffer).unwrap();
+        let mut encoded = [0u8; MAX_ENCODED_FRAME_LEN];
+        let encoded = &mut encoded[..frame.len() - 1];
+        encoded.copy_from_slice(&frame[..frame.len() - 1]);
+        let contents = decode_frame(encoded).unwrap();
+        assert_eq!(decode_packet(contents), Ok(PING));
+        assert_eq!(
+            decode_packet(&contents[..contents.len() - 1]),
+            Err(FrameError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_bit_flip() {
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        let length = encode_packet(&PING, &mut buffer).unwrap().len();
+        // NOTE: The delimiter is left alone, so every flip stays in one frame.
+        for index in 0..length - 1 {
+            let mut frame = buffer;
+            frame[index] ^= 0x04;
+            let mut codec = PacketCodec::new();
+            let mut packets = 0;
+            codec.decode(&frame[..length], |_| packets += 1);
+            assert_eq!(packets, 0);
+        }
+    }
+
+    #[test]
+    fn test_decode_realigns_after_corruption() {
+        let mut stream = [0u8; 4 * MAX_ENCODED_FRAME_LEN];
+        let mut length = 0;
+        let mut frame_starts = [0usize; 4];
+        for nonce in 1..=4 {
+            frame_starts[nonce as usize - 1] = length;
+            let packet = Packet::Ping(PingPacket { nonce });
+            let (_, free) = stream.split_at_mut(length);
+            length += encode_packet(&packet, free).unwrap().len();
+        }
+        stream[frame_starts[1] + 2] ^= 0x10;
+
+        let mut codec = PacketCodec::new();
+        let mut nonces = [0u32; 4];
+        let mut count = 0;
+        let mut on_packet = |packet| {
+            if let Packet::Ping(PingPacket { nonce }) = packet {
+                nonces[count] = nonce;
+                count += 1;
+            }
+        };
+        // NOTE: The last frame is split across reads.
+        let split = frame_starts[3] + 2;
+        let dropped = codec.decode(&stream[..split], &mut on_packet);
+        assert_eq!(codec.pending(), 2);
+        codec.decode(&stream[split..length], &mut on_packet);
+        assert_eq!(dropped, 1);
+        assert_eq!(&nonces[..count], &[1, 3, 4]);
+    }
+
+    #[test]
+    fn test_decode_drops_oversized_frame() {
+        let mut codec = PacketCodec::new();
+        let mut packets = 0;
+        let oversized = [0x01u8; MAX_ENCODED_FRAME_LEN + 10];
+        assert_eq!(codec.decode(&oversized, |_| packets += 1), 0);
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        let frame = encode_packet(&PING, &mut buffer).unwrap();
+        // NOTE: The oversized frame is only dropped once it ends.
+        assert_eq!(codec.decode(&[0], |_| packets += 1), 1);
+        assert_eq!(codec.decode(frame, |_| packets += 1), 0);
+        assert_eq!(packets, 1);
+    }
+}
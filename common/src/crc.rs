@@ -0,0 +1,38 @@
+/// Compute the CRC-16/CCITT-FALSE of `bytes`.
+///
+/// ```
+/// use common::crc::crc16;
+/// assert_eq!(crc16(b"123456789"), 0x29B1);
+/// ```
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16_detects_bit_flip() {
+        let mut bytes = *b"too hot to prandtl";
+        let crc = crc16(&bytes);
+        bytes[3] ^= 0x01;
+        assert_ne!(crc16(&bytes), crc);
+    }
+
+    #[test]
+    fn test_crc16_empty() {
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+}
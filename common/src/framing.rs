@@ -0,0 +1,260 @@
+use thiserror_no_std::Error;
+
+/// Ends every frame on the wire. COBS encoding removes every zero byte from
+/// a frame's contents, so a stream which loses sync re-aligns at the next
+/// delimiter.
+pub const FRAME_DELIMITER: u8 = 0x00;
+
+/// The most bytes a frame can hold before it is encoded.
+pub const MAX_FRAME_LEN: usize = 128;
+
+/// The most bytes a frame of `MAX_FRAME_LEN` takes on the wire, including
+/// its delimiter.
+pub const MAX_ENCODED_FRAME_LEN: usize = max_encoded_len(MAX_FRAME_LEN) + 1;
+
+/// How many bytes the embedded hardware reads from the link at once by
+/// default. Hosts which batch frames into a single write keep each write
+/// within it, so a batch is usually decoded in a single read.
+pub const DEVICE_READ_CHUNK_LEN: usize = 256;
+
+/// Why a frame couldn't be put onto, or taken off of, the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum FrameError {
+    /// The frame didn't fit in the buffer.
+    #[error("Frame doesn't fit in the buffer.")]
+    BufferFull,
+
+    /// The frame isn't valid COBS, or doesn't hold a packet.
+    #[error("Frame is malformed.")]
+    Malformed,
+
+    /// The packet didn't match its CRC, so at least one byte was corrupted.
+    #[error("Frame failed its CRC check.")]
+    Corrupt,
+}
+
+/// The most bytes `len` bytes take once COBS encoded, excluding the
+/// delimiter.
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// COBS encode `contents` into `buffer`, followed by the delimiter. Returns
+/// the part of `buffer` the frame was written to.
+///
+/// ```
+/// use common::framing::encode_frame;
+/// let mut buffer = [0u8; 8];
+/// assert_eq!(
+///     encode_frame(&[0x11, 0x22, 0x00, 0x33], &mut buffer),
+///     Ok(&[0x03, 0x11, 0x22, 0x02, 0x33, 0x00][..])
+/// );
+/// ```
+pub fn encode_frame<'a>(contents: &[u8], buffer: &'a mut [u8]) -> Result<&'a [u8], FrameError> {
+    if buffer.len() < max_encoded_len(contents.len()) + 1 {
+        return Err(FrameError::BufferFull);
+    }
+    let mut code_index = 0;
+    let mut code: u8 = 1;
+    let mut length = 1;
+    for &byte in contents {
+        // NOTE: A full block doesn't imply a zero, so it ends without one.
+        if code == 0xFF {
+            buffer[code_index] = code;
+            code_index = length;
+            length += 1;
+            code = 1;
+        }
+        if byte == 0 {
+            buffer[code_index] = code;
+            code_index = length;
+            length += 1;
+            code = 1;
+        } else {
+            buffer[length] = byte;
+            length += 1;
+            code += 1;
+        }
+    }
+    buffer[code_index] = code;
+    buffer[length] = FRAME_DELIMITER;
+    Ok(&buffer[..length + 1])
+}
+
+/// COBS decode `frame`, without its delimiter, in place. Returns the part of
+/// `frame` holding the contents.
+pub fn decode_frame(frame: &mut [u8]) -> Result<&[u8], FrameError> {
+    let mut read = 0;
+    let mut length = 0;
+    while read < frame.len() {
+        let code = frame[read] as usize;
+        if code == 0 {
+            return Err(FrameError::Malformed);
+        }
+        let end = read + code;
+        if end > frame.len() {
+            return Err(FrameError::Malformed);
+        }
+        frame.copy_within(read + 1..end, length);
+        length += code - 1;
+        read = end;
+        if code != 0xFF && read < frame.len() {
+            frame[length] = 0;
+            length += 1;
+        }
+    }
+    Ok(&frame[..length])
+}
+
+/// Splits a byte stream into frames at each delimiter. Bytes of a frame
+/// which hasn't ended yet are kept until it does, so frames can be split
+/// across reads.
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    buffer: [u8; MAX_ENCODED_FRAME_LEN],
+    length: usize,
+
+    /// Whether the frame being collected outgrew the buffer.
+    overflowed: bool,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; MAX_ENCODED_FRAME_LEN],
+            length: 0,
+            overflowed: false,
+        }
+    }
+
+    /// How many bytes of an unfinished frame are held.
+    pub fn pending(&self) -> usize {
+        self.length
+    }
+
+    /// Collect `bytes` from the stream, calling `on_frame` with the decoded
+    /// contents of every frame they finish, or why it couldn't be decoded.
+    /// Empty frames are skipped.
+    pub fn feed(&mut self, bytes: &[u8], mut on_frame: impl FnMut(Result<&[u8], FrameError>)) {
+        for &byte in bytes {
+            if byte != FRAME_DELIMITER {
+                match self.buffer.get_mut(self.length) {
+                    Some(slot) => {
+                        *slot = byte;
+                        self.length += 1;
+                    }
+                    None => self.overflowed = true,
+                }
+                continue;
+            }
+            if self.overflowed {
+                on_frame(Err(FrameError::BufferFull));
+            } else if self.length > 0 {
+                on_frame(decode_frame(&mut self.buffer[..self.length]));
+            }
+            self.length = 0;
+            self.overflowed = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode and decode `contents`, checking the encoding has no zeros.
+    fn round_trip(contents: &[u8]) {
+        let mut buffer = [0u8; 300];
+        let frame = encode_frame(contents, &mut buffer).unwrap();
+        let (delimiter, encoded) = frame.split_last().unwrap();
+        assert_eq!(*delimiter, FRAME_DELIMITER);
+        assert!(!encoded.contains(&FRAME_DELIMITER));
+        assert!(encoded.len() <= max_encoded_len(contents.len()));
+
+        let mut encoded_copy = [0u8; 300];
+        encoded_copy[..encoded.len()].copy_from_slice(encoded);
+        assert_eq!(
+            decode_frame(&mut encoded_copy[..encoded.len()]),
+            Ok(contents)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(&[]);
+        round_trip(&[0x00]);
+        round_trip(&[0x00, 0x00]);
+        round_trip(&[0x11, 0x00, 0x00, 0x22]);
+
+        let mut long = [0u8; 260];
+        for (index, byte) in long.iter_mut().enumerate() {
+            *byte = (index % 255) as u8 + 1;
+        }
+        round_trip(&long[..254]);
+        round_trip(&long[..255]);
+        round_trip(&long);
+        long[254] = 0;
+        round_trip(&long);
+    }
+
+    #[test]
+    fn test_full_block_has_no_trailing_code() {
+        let contents = [0x01u8; 254];
+        let mut buffer = [0u8; 300];
+        let frame = encode_frame(&contents, &mut buffer).unwrap();
+        assert_eq!(frame.len(), 256);
+        assert_eq!(frame[0], 0xFF);
+    }
+
+    #[test]
+    fn test_encode_frame_buffer_full() {
+        let mut buffer = [0u8; 4];
+        assert_eq!(
+            encode_frame(&[1, 2, 3, 4], &mut buffer),
+            Err(FrameError::BufferFull)
+        );
+    }
+
+    #[test]
+    fn test_decode_frame_malformed() {
+        assert_eq!(decode_frame(&mut [0x05, 0x11]), Err(FrameError::Malformed));
+    }
+
+    #[test]
+    fn test_decoder_keeps_split_frames() {
+        let mut buffer = [0u8; 16];
+        let frame = encode_frame(&[0x11, 0x00, 0x22], &mut buffer).unwrap();
+        let mut decoder = FrameDecoder::new();
+        let mut frames = 0;
+        decoder.feed(&frame[..2], |_| frames += 1);
+        assert_eq!(frames, 0);
+        assert_eq!(decoder.pending(), 2);
+        decoder.feed(&frame[2..], |contents| {
+            assert_eq!(contents, Ok(&[0x11, 0x00, 0x22][..]));
+            frames += 1;
+        });
+        assert_eq!(frames, 1);
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn test_decoder_realigns_after_overflow() {
+        let mut decoder = FrameDecoder::new();
+        let mut results = [Ok(0); 2];
+        let mut count = 0;
+        let garbage = [0x01u8; MAX_ENCODED_FRAME_LEN + 10];
+        decoder.feed(&garbage, |_| unreachable!());
+        decoder.feed(&[0x00, 0x02, 0x11, 0x00], |contents| {
+            results[count] = contents.map(|contents| contents.len());
+            count += 1;
+        });
+        assert_eq!(count, 2);
+        assert_eq!(results, [Err(FrameError::BufferFull), Ok(1)]);
+    }
+}
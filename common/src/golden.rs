@@ -0,0 +1,506 @@
+//! Golden vectors pinning the postcard encoding of every packet variant.
+//!
+//! The host and the firmware are built and released separately but must
+//! agree byte for byte on how packets are serialized. Each crate checks these
+//! vectors round trip through its own build, so a change to the layout, such
+//! as reordering fields or `Packet` variants, fails the tests instead of
+//! silently mis-decoding in the field.
+//!
+//! A vector only changes along with `PROTOCOL_VERSION`, which must be bumped
+//! whenever one does.
+
+use fixedstr::str64;
+
+use crate::crc::crc16;
+use crate::framing::{encode_frame, FrameError, MAX_FRAME_LEN};
+use crate::packet::*;
+use crate::physical::{Celsius, Current, FlowRate, Percentage, Rpm, ValveState};
+
+/// How many golden vectors there are, one per `Packet` variant.
+pub const GOLDEN_PACKET_COUNT: usize = 38;
+
+/// A packet along with the bytes it must serialize to, without the CRC or
+/// framing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenPacket {
+    pub name: &'static str,
+    pub packet: Packet,
+    pub bytes: &'static [u8],
+}
+
+impl GoldenPacket {
+    /// Frame the pinned bytes as they go on the wire, with their CRC, into
+    /// `buffer`. Built from `bytes` rather than `packet` so decoders are
+    /// checked against the pinned layout, not whatever this build serializes.
+    pub fn frame<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], FrameError> {
+        let length = self.bytes.len();
+        if length + PACKET_CRC_LEN > MAX_FRAME_LEN {
+            return Err(FrameError::BufferFull);
+        }
+        let mut contents = [0u8; MAX_FRAME_LEN];
+        contents[..length].copy_from_slice(self.bytes);
+        contents[length..length + PACKET_CRC_LEN].copy_from_slice(&crc16(self.bytes).to_le_bytes());
+        encode_frame(&contents[..length + PACKET_CRC_LEN], buffer)
+    }
+}
+
+/// Why a golden vector didn't round trip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GoldenMismatch {
+    /// The packet didn't serialize to the vector's bytes.
+    Encoded { name: &'static str },
+
+    /// The vector's bytes didn't deserialize to the packet.
+    Decoded { name: &'static str },
+}
+
+/// Get the golden vector of every packet variant, in variant order.
+pub fn golden_packets() -> [GoldenPacket; GOLDEN_PACKET_COUNT] {
+    let mut fan_control_percents = FanChannels::single(Percentage::const_new(40));
+    let _ = fan_control_percents.push(Percentage::const_new(55));
+    let targets = ReportControlTargetsPacket {
+        fan_control_percents,
+        pump_control_percent: Percentage::const_new_quarter_steps(301),
+        sequence: 513,
+    };
+    let echo = EchoPacket::new([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+    let mut pump_sense = SenseCurve::linear(2400);
+    pump_sense.points[1].0 = Percentage::const_new(90);
+    let _ = pump_sense.points.push((Percentage::const_new(100), 2500));
+    let mut batch = ReportSensorsBatchPacket {
+        started_at_ms: None,
+        fan_max_rpm: 1800,
+        pump_max_rpm: 2400,
+        samples: heapless::Vec::new(),
+    };
+    let _ = batch.samples.push(SensorSample {
+        offset_ms: 0,
+        fan_rpm: 900,
+        pump_rpm: 1200,
+        valve_state: ValveState::Open,
+    });
+    let _ = batch.samples.push(SensorSample {
+        offset_ms: 50,
+        fan_rpm: 950,
+        pump_rpm: 1210,
+        valve_state: ValveState::Closing,
+    });
+    let config = FirmwareConfig {
+        pwm_frequency_hz: 25_000,
+        telemetry_rate_hz: 5,
+        pump_max_rpm: 1234,
+        fan_max_rpm: 987,
+        failsafe_duty_percent: 80,
+    };
+    [
+        GoldenPacket {
+            name: "RequestConnection",
+            packet: RequestConnectionPacket::new_packet(),
+            bytes: &[0, 22, 97, 98, 50, 100, 119, 97, 115, 107],
+        },
+        GoldenPacket {
+            name: "AcceptConnection",
+            packet: Packet::AcceptConnection(AcceptConnectionPacket::new()),
+            bytes: &[1, 22, 119, 97, 115, 107, 50, 100, 97, 98],
+        },
+        GoldenPacket {
+            name: "ReportSensors",
+            packet: Packet::ReportSensors(ReportSensorsPacket {
+                fan_speeds_rpm: FanChannels::single(Rpm::const_new(2000, 1500)),
+                pump_speed_rpm: Rpm::const_new(4800, 300),
+                valve_state: ValveState::Open,
+                board_temperature: Celsius::try_from(36.25f32).ok(),
+                coolant_temperature: Celsius::try_from(31.5f32).ok(),
+                pump_current: Current::try_from(0.5f32).ok(),
+                fan_current: None,
+                flow_rate: FlowRate::try_from(1.5f32).ok(),
+                sampled_at_ms: Some(1_700_000_000_000),
+            }),
+            bytes: &[
+                2, 192, 154, 12, 240, 147, 9, 0, 0, 0, 128, 166, 29, 176, 234, 1, 0, 1, 160, 36, 1,
+                192, 31, 1, 128, 8, 0, 1, 128, 12, 1, 128, 208, 149, 255, 188, 49,
+            ],
+        },
+        GoldenPacket {
+            name: "ReportControlTargets",
+            packet: Packet::ReportControlTargets(targets),
+            bytes: &[3, 128, 5, 1, 240, 6, 0, 0, 180, 9, 129, 4],
+        },
+        GoldenPacket {
+            name: "ReportLogLine",
+            packet: Packet::ReportLogLine(ReportLogLinePacket {
+                level: LogLevel::Warn,
+                module: 3,
+                message: str64::from("boot"),
+            }),
+            bytes: &[4, 2, 3, 4, 98, 111, 111, 116],
+        },
+        GoldenPacket {
+            name: "SetParameter",
+            packet: Packet::SetParameter(SetParameterPacket {
+                parameter: Parameter::FanMaxRpm(2200),
+            }),
+            bytes: &[5, 1, 152, 17],
+        },
+        GoldenPacket {
+            name: "ReportStats",
+            packet: Packet::ReportStats(ReportStatsPacket {
+                supply_voltage_mv: Some(4950),
+                uptime_ms: 3_600_000,
+                core_loop_period_ms: 10,
+                decode_errors: 2,
+                incoming_queue_high_water: 3,
+                outgoing_queue_high_water: 16,
+            }),
+            bytes: &[6, 1, 214, 38, 128, 221, 219, 1, 10, 2, 3, 16],
+        },
+        GoldenPacket {
+            name: "ReportError",
+            packet: Packet::ReportError(ReportErrorPacket {
+                error: FirmwareError::SupplyVoltageLow {
+                    supply_voltage_mv: 4400,
+                },
+            }),
+            bytes: &[7, 0, 176, 34],
+        },
+        GoldenPacket {
+            name: "RequestDeviceInfo",
+            packet: Packet::RequestDeviceInfo(RequestDeviceInfoPacket),
+            bytes: &[8],
+        },
+        GoldenPacket {
+            name: "ReportDeviceInfo",
+            packet: Packet::ReportDeviceInfo(ReportDeviceInfoPacket {
+                capabilities: Capabilities {
+                    fan_channels: 1,
+                    thermistors: 2,
+                    valve_driver: true,
+                    valve_sense: false,
+                },
+                settings: SettingsOrigin::Bank {
+                    bank: 1,
+                    version: 300,
+                    corrupt_banks: 0,
+                },
+                reset_cause: ResetCause::Watchdog,
+            }),
+            bytes: &[9, 1, 2, 1, 0, 1, 1, 172, 2, 0, 3],
+        },
+        GoldenPacket {
+            name: "Ping",
+            packet: Packet::Ping(PingPacket { nonce: 0xC0FFEE }),
+            bytes: &[10, 238, 255, 131, 6],
+        },
+        GoldenPacket {
+            name: "Pong",
+            packet: Packet::Pong(PongPacket { nonce: 0xC0FFEE }),
+            bytes: &[11, 238, 255, 131, 6],
+        },
+        GoldenPacket {
+            name: "SetEchoMode",
+            packet: Packet::SetEchoMode(SetEchoModePacket { enabled: true }),
+            bytes: &[12, 1],
+        },
+        GoldenPacket {
+            name: "Echo",
+            packet: Packet::Echo(echo),
+            bytes: &[
+                13, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 183, 118,
+            ],
+        },
+        GoldenPacket {
+            name: "EchoReply",
+            packet: Packet::EchoReply(echo.reply()),
+            bytes: &[
+                14, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 183, 118, 1,
+            ],
+        },
+        GoldenPacket {
+            name: "ReportRpmFast",
+            packet: Packet::ReportRpmFast(ReportRpmFastPacket {
+                fan_speeds_rpm: FanChannels::single(Rpm::const_new(2000, 1000)),
+                pump_speed_rpm: Rpm::const_new(4800, 4800),
+            }),
+            bytes: &[
+                15, 192, 154, 12, 160, 141, 6, 0, 0, 0, 128, 166, 29, 128, 166, 29,
+            ],
+        },
+        GoldenPacket {
+            name: "Ack",
+            packet: Packet::Ack(AckPacket {
+                sequence: 513,
+                missed_control_frames: 70000,
+            }),
+            bytes: &[16, 129, 4, 240, 162, 4],
+        },
+        GoldenPacket {
+            name: "FirmwareInfo",
+            packet: Packet::FirmwareInfo(FirmwareInfoPacket::new(
+                "1.2.300",
+                "abc1234",
+                "rp2040_pico",
+            )),
+            bytes: &[
+                17, 1, 2, 172, 2, 7, 97, 98, 99, 49, 50, 51, 52, 11, 114, 112, 50, 48, 52, 48, 95,
+                112, 105, 99, 111,
+            ],
+        },
+        GoldenPacket {
+            name: "Heartbeat",
+            packet: Packet::Heartbeat(HeartbeatPacket),
+            bytes: &[18],
+        },
+        GoldenPacket {
+            name: "SetTuning",
+            packet: Packet::SetTuning(SetTuningPacket {
+                tuning: TuningParameters {
+                    pump_max_rpm: 2400,
+                    fan_max_rpm: 1800,
+                    pump_sense,
+                    fan_sense: SenseCurve::linear(1800),
+                },
+            }),
+            bytes: &[
+                19, 224, 18, 136, 14, 3, 0, 0, 160, 11, 224, 18, 192, 12, 196, 19, 2, 0, 0, 192,
+                12, 136, 14,
+            ],
+        },
+        GoldenPacket {
+            name: "GetParameter",
+            packet: Packet::GetParameter(GetParameterPacket {
+                kind: ParameterKind::CoreLoopPeriodMs,
+            }),
+            bytes: &[20, 3],
+        },
+        GoldenPacket {
+            name: "ReportParameter",
+            packet: Packet::ReportParameter(ReportParameterPacket {
+                parameter: Parameter::PumpMaxRpm(2400),
+            }),
+            bytes: &[21, 0, 224, 18],
+        },
+        GoldenPacket {
+            name: "TimeSync",
+            packet: Packet::TimeSync(TimeSyncPacket {
+                host_time_ms: 1_700_000_000_000,
+                latency_ms: 3,
+            }),
+            bytes: &[22, 128, 208, 149, 255, 188, 49, 3],
+        },
+        GoldenPacket {
+            name: "TimeSyncReply",
+            packet: Packet::TimeSyncReply(TimeSyncReplyPacket {
+                host_time_ms: 1_700_000_000_000,
+                device_time_ms: Some(1_700_000_000_250),
+            }),
+            bytes: &[
+                23, 128, 208, 149, 255, 188, 49, 1, 250, 209, 149, 255, 188, 49,
+            ],
+        },
+        GoldenPacket {
+            name: "ReportSensorsBatch",
+            packet: Packet::ReportSensorsBatch(batch),
+            bytes: &[
+                24, 0, 136, 14, 224, 18, 2, 0, 132, 7, 176, 9, 0, 50, 182, 7, 186, 9, 3,
+            ],
+        },
+        GoldenPacket {
+            name: "SetSensorBatching",
+            packet: Packet::SetSensorBatching(SetSensorBatchingPacket { enabled: true }),
+            bytes: &[25, 1],
+        },
+        GoldenPacket {
+            name: "RequestSensors",
+            packet: Packet::RequestSensors(RequestSensorsPacket),
+            bytes: &[26],
+        },
+        GoldenPacket {
+            name: "SetCalibration",
+            packet: Packet::SetCalibration(SetCalibrationPacket {
+                pump_sense: SenseCalibration {
+                    offset: -100,
+                    gain: 12_000,
+                },
+                fan_sense: SenseCalibration::IDENTITY,
+            }),
+            bytes: &[27, 199, 1, 224, 93, 0, 144, 78],
+        },
+        GoldenPacket {
+            name: "Reboot",
+            packet: Packet::Reboot(RebootPacket),
+            bytes: &[28],
+        },
+        GoldenPacket {
+            name: "EnterBootloader",
+            packet: Packet::EnterBootloader(EnterBootloaderPacket),
+            bytes: &[29],
+        },
+        GoldenPacket {
+            name: "RequestRawAdc",
+            packet: Packet::RequestRawAdc(RequestRawAdcPacket),
+            bytes: &[30],
+        },
+        GoldenPacket {
+            name: "ReportRawAdc",
+            packet: Packet::ReportRawAdc(ReportRawAdcPacket {
+                pump_sense: Some(2048),
+                fan_sense: None,
+                valve_sense_1: true,
+                valve_sense_2: false,
+            }),
+            bytes: &[31, 1, 128, 16, 0, 1, 0],
+        },
+        GoldenPacket {
+            name: "ReadConfig",
+            packet: Packet::ReadConfig(ReadConfigPacket),
+            bytes: &[32],
+        },
+        GoldenPacket {
+            name: "WriteConfig",
+            packet: Packet::WriteConfig(WriteConfigPacket { config }),
+            bytes: &[33, 168, 195, 1, 5, 210, 9, 219, 7, 80],
+        },
+        GoldenPacket {
+            name: "ReportConfig",
+            packet: Packet::ReportConfig(ReportConfigPacket {
+                config,
+                persisted: true,
+            }),
+            bytes: &[34, 168, 195, 1, 5, 210, 9, 219, 7, 80, 1],
+        },
+        GoldenPacket {
+            name: "ValveCommand",
+            packet: Packet::ValveCommand(ValveCommandPacket {
+                target: ValveState::Closed,
+            }),
+            bytes: &[35, 1],
+        },
+        GoldenPacket {
+            name: "ValveTransitionComplete",
+            packet: Packet::ValveTransitionComplete(ValveTransitionCompletePacket {
+                state: ValveState::Open,
+                confirmed: true,
+                elapsed_ms: 4000,
+            }),
+            bytes: &[36, 0, 1, 160, 31],
+        },
+        GoldenPacket {
+            name: "RequestLogs",
+            packet: Packet::RequestLogs(RequestLogsPacket),
+            bytes: &[37],
+        },
+    ]
+}
+
+/// Check every golden vector serializes to its bytes and back. Returns the
+/// first which doesn't.
+///
+/// ```
+/// use common::golden::check_golden_packets;
+/// assert_eq!(check_golden_packets(), Ok(()));
+/// ```
+pub fn check_golden_packets() -> Result<(), GoldenMismatch> {
+    for golden in golden_packets() {
+        let mut buffer = [0u8; MAX_FRAME_LEN];
+        match postcard::to_slice(&golden.packet, &mut buffer) {
+            Ok(bytes) if *bytes == *golden.bytes => {}
+            _ => return Err(GoldenMismatch::Encoded { name: golden.name }),
+        }
+        match postcard::from_bytes::<Packet>(golden.bytes) {
+            Ok(packet) if packet == golden.packet => {}
+            _ => return Err(GoldenMismatch::Decoded { name: golden.name }),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{encode_packet, PacketCodec};
+    use crate::framing::MAX_ENCODED_FRAME_LEN;
+
+    /// The position of `packet`'s variant in `Packet`. Exhaustive, so adding
+    /// a variant fails to build until it gets a golden vector.
+    fn variant_index(packet: &Packet) -> usize {
+        match packet {
+            Packet::RequestConnection(_) => 0,
+            Packet::AcceptConnection(_) => 1,
+            Packet::ReportSensors(_) => 2,
+            Packet::ReportControlTargets(_) => 3,
+            Packet::ReportLogLine(_) => 4,
+            Packet::SetParameter(_) => 5,
+            Packet::ReportStats(_) => 6,
+            Packet::ReportError(_) => 7,
+            Packet::RequestDeviceInfo(_) => 8,
+            Packet::ReportDeviceInfo(_) => 9,
+            Packet::Ping(_) => 10,
+            Packet::Pong(_) => 11,
+            Packet::SetEchoMode(_) => 12,
+            Packet::Echo(_) => 13,
+            Packet::EchoReply(_) => 14,
+            Packet::ReportRpmFast(_) => 15,
+            Packet::Ack(_) => 16,
+            Packet::FirmwareInfo(_) => 17,
+            Packet::Heartbeat(_) => 18,
+            Packet::SetTuning(_) => 19,
+            Packet::GetParameter(_) => 20,
+            Packet::ReportParameter(_) => 21,
+            Packet::TimeSync(_) => 22,
+            Packet::TimeSyncReply(_) => 23,
+            Packet::ReportSensorsBatch(_) => 24,
+            Packet::SetSensorBatching(_) => 25,
+            Packet::RequestSensors(_) => 26,
+            Packet::SetCalibration(_) => 27,
+            Packet::Reboot(_) => 28,
+            Packet::EnterBootloader(_) => 29,
+            Packet::RequestRawAdc(_) => 30,
+            Packet::ReportRawAdc(_) => 31,
+            Packet::ReadConfig(_) => 32,
+            Packet::WriteConfig(_) => 33,
+            Packet::ReportConfig(_) => 34,
+            Packet::ValveCommand(_) => 35,
+            Packet::ValveTransitionComplete(_) => 36,
+            Packet::RequestLogs(_) => 37,
+        }
+    }
+
+    #[test]
+    fn test_golden_packets_cover_every_variant() {
+        for (index, golden) in golden_packets().iter().enumerate() {
+            assert_eq!(variant_index(&golden.packet), index, "{}", golden.name);
+            assert_eq!(
+                golden.bytes.first(),
+                Some(&(index as u8)),
+                "{}",
+                golden.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_golden_packets_round_trip() {
+        assert_eq!(check_golden_packets(), Ok(()));
+    }
+
+    #[test]
+    fn test_golden_frames_match_encoded_packets() {
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        let mut golden_buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        for golden in golden_packets() {
+            let frame = encode_packet(&golden.packet, &mut buffer).unwrap();
+            assert_eq!(
+                Ok(frame),
+                golden.frame(&mut golden_buffer).as_deref(),
+                "{}",
+                golden.name
+            );
+            let mut frames = 0;
+            let dropped = PacketCodec::new().decode(frame, |packet| {
+                assert_eq!(packet, golden.packet);
+                frames += 1;
+            });
+            assert_eq!((frames, dropped), (1, 0), "{}", golden.name);
+        }
+    }
+}
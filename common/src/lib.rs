@@ -1,4 +1,8 @@
 #![no_std]
 
+pub mod codec;
+pub mod crc;
+pub mod framing;
+pub mod golden;
 pub mod packet;
 pub mod physical;
@@ -1,10 +1,26 @@
-use fixedstr::str8;
+use crate::crc::crc16;
+use crate::physical::{Celsius, Current, FlowRate, Percentage, Rpm, RpmError, ValveState};
+use core::fmt::Display;
+use fixedstr::{str16, str64, str8};
+use heapless::Vec;
 use serde::{Deserialize, Serialize};
-use crate::physical::{Percentage, Rpm, ValveState};
+use thiserror_no_std::Error;
 
 // TODO: Impl Display for Packet
 
+/// How many bytes of CRC follow every serialized packet in a frame.
+pub const PACKET_CRC_LEN: usize = 2;
+
+/// The version of the packet format. Bump it whenever a change means
+/// packets serialized by one build could be mis-decoded by another, such as
+/// adding, removing or reordering fields or `Packet` variants.
+pub const PROTOCOL_VERSION: u16 = 22;
+
 /// Used to communicate with embedded hardware.
+///
+/// NOTE: `RequestConnection` and `AcceptConnection` must stay the first
+/// variants, with `protocol_version` their first field, so builds of any
+/// version can negotiate.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Packet {
     RequestConnection(RequestConnectionPacket),
@@ -12,35 +28,369 @@ pub enum Packet {
     ReportSensors(ReportSensorsPacket),
     ReportControlTargets(ReportControlTargetsPacket),
     ReportLogLine(ReportLogLinePacket),
+    SetParameter(SetParameterPacket),
+    ReportStats(ReportStatsPacket),
+    ReportError(ReportErrorPacket),
+    RequestDeviceInfo(RequestDeviceInfoPacket),
+    ReportDeviceInfo(ReportDeviceInfoPacket),
+    Ping(PingPacket),
+    Pong(PongPacket),
+    SetEchoMode(SetEchoModePacket),
+    Echo(EchoPacket),
+    EchoReply(EchoReplyPacket),
+    ReportRpmFast(ReportRpmFastPacket),
+    Ack(AckPacket),
+    FirmwareInfo(FirmwareInfoPacket),
+    Heartbeat(HeartbeatPacket),
+    SetTuning(SetTuningPacket),
+    GetParameter(GetParameterPacket),
+    ReportParameter(ReportParameterPacket),
+    TimeSync(TimeSyncPacket),
+    TimeSyncReply(TimeSyncReplyPacket),
+    ReportSensorsBatch(ReportSensorsBatchPacket),
+    SetSensorBatching(SetSensorBatchingPacket),
+    RequestSensors(RequestSensorsPacket),
+    SetCalibration(SetCalibrationPacket),
+    Reboot(RebootPacket),
+    EnterBootloader(EnterBootloaderPacket),
+    RequestRawAdc(RequestRawAdcPacket),
+    ReportRawAdc(ReportRawAdcPacket),
+    ReadConfig(ReadConfigPacket),
+    WriteConfig(WriteConfigPacket),
+    ReportConfig(ReportConfigPacket),
+    ValveCommand(ValveCommandPacket),
+    ValveTransitionComplete(ValveTransitionCompletePacket),
+    RequestLogs(RequestLogsPacket),
 }
 
 /// Represents a request to establish connection. Used to determine
-/// which port the embedded hardware is plugged into.
+/// which port the embedded hardware is plugged into, and that it speaks the
+/// same protocol version as the host.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct RequestConnectionPacket {
+    /// The `PROTOCOL_VERSION` of the host.
+    pub protocol_version: u16,
+
     special_pattern: [u8; 8],
 }
 
 /// Represents a response from embedded hardware. Used to determine
-/// which port it was plugged into.
+/// which port it was plugged into. Sent even when the protocol versions
+/// differ, so the host can report the mismatch.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct AcceptConnectionPacket {
+    /// The `PROTOCOL_VERSION` of the embedded hardware.
+    pub protocol_version: u16,
+
     special_pattern: [u8; 8],
 }
 
+/// The most fan channels a device can drive and report, for boards with more
+/// than one fan header.
+pub const MAX_FAN_CHANNELS: usize = 4;
+
+/// A value for each fan channel of a device, in channel order. There is
+/// always a first channel, which is the one the host regulates against.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FanChannels<T> {
+    first: T,
+    #[serde(default = "no_more_channels")]
+    rest: [Option<T>; MAX_FAN_CHANNELS - 1],
+}
+
+fn no_more_channels<T>() -> [Option<T>; MAX_FAN_CHANNELS - 1] {
+    core::array::from_fn(|_| None)
+}
+
+impl<T: Copy> FanChannels<T> {
+    /// A single fan channel.
+    pub const fn single(first: T) -> Self {
+        Self {
+            first,
+            rest: [None; MAX_FAN_CHANNELS - 1],
+        }
+    }
+
+    /// `value` on each of `channels` channels, clamped to
+    /// 1..=`MAX_FAN_CHANNELS`.
+    pub fn repeat(value: T, channels: usize) -> Self {
+        let mut repeated = Self::single(value);
+        for _ in 1..channels {
+            if repeated.push(value).is_err() {
+                break;
+            }
+        }
+        repeated
+    }
+
+    /// Add a channel after the last. Returns `value` back if there are
+    /// already `MAX_FAN_CHANNELS`.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        match self.rest.iter_mut().find(|channel| channel.is_none()) {
+            Some(channel) => {
+                *channel = Some(value);
+                Ok(())
+            }
+            None => Err(value),
+        }
+    }
+
+    pub fn first(&self) -> T {
+        self.first
+    }
+
+    pub fn first_mut(&mut self) -> &mut T {
+        &mut self.first
+    }
+
+    /// Get the value of `channel`, counting from 0, if the device has it.
+    pub fn get(&self, channel: usize) -> Option<T> {
+        match channel {
+            0 => Some(self.first),
+            channel => self.rest.get(channel - 1).copied().flatten(),
+        }
+    }
+
+    /// How many channels there are, at least 1.
+    pub fn channels(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Every channel's value, in channel order.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        core::iter::once(self.first).chain(self.rest.iter().map_while(|channel| *channel))
+    }
+
+    /// Every channel's value but the first's, with `None` past the last.
+    pub fn rest(&self) -> [Option<T>; MAX_FAN_CHANNELS - 1] {
+        self.rest
+    }
+
+    /// Apply `f` to every channel's value.
+    pub fn map<U: Copy>(&self, mut f: impl FnMut(T) -> U) -> FanChannels<U> {
+        FanChannels {
+            first: f(self.first),
+            rest: self.rest.map(|channel| channel.map(&mut f)),
+        }
+    }
+}
+
 /// Represents a snapshot of normalized sensor data from the embedded hardware.
 /// Used for processing into an input into the control system. Will need to be
 /// processed into physical unit representation.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct ReportSensorsPacket {
-    /// Normalized representation of the fan's rpm.
-    pub fan_speed_rpm: Rpm,
+    /// Normalized representation of each fan channel's rpm.
+    pub fan_speeds_rpm: FanChannels<Rpm>,
 
     /// Normalized representation of the pump's rpm.
     pub pump_speed_rpm: Rpm,
 
     /// Valve State
     pub valve_state: ValveState,
+
+    /// Temperature of the microcontroller die, if the hardware has an internal
+    /// temperature sensor. Used to sanity check the thermistor readings.
+    pub board_temperature: Option<Celsius>,
+
+    /// Temperature of the coolant, if the hardware has a coolant thermistor.
+    /// Lets the host react to the loop itself heating up, not just the CPU.
+    pub coolant_temperature: Option<Celsius>,
+
+    /// Current drawn by the pump, if the hardware has a current sensor on the
+    /// pump rail. Rising current at a constant speed hints at bearing wear.
+    pub pump_current: Option<Current>,
+
+    /// Current drawn by the fan, if the hardware has a current sensor on the
+    /// fan rail.
+    pub fan_current: Option<Current>,
+
+    /// Coolant flow rate, if the hardware has a flow meter and its pulses per
+    /// liter are configured. Lets the host catch low flow which the pump
+    /// speed alone can't, such as a blocked loop.
+    pub flow_rate: Option<FlowRate>,
+
+    /// When the readings were taken, in milliseconds since the unix epoch by
+    /// the host's clock, if the embedded hardware has been sent a `TimeSync`.
+    pub sampled_at_ms: Option<u64>,
+}
+
+/// Represents just the fan and pump speeds. Sent at `FAST_RPM_RATE_HZ`
+/// between full `ReportSensorsPacket`s, which are only sent at the telemetry
+/// rate, so feedback on the speeds can run faster than the rest of the
+/// telemetry without the cost of sending all of it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReportRpmFastPacket {
+    /// Normalized representation of each fan channel's rpm.
+    pub fan_speeds_rpm: FanChannels<Rpm>,
+
+    /// Normalized representation of the pump's rpm.
+    pub pump_speed_rpm: Rpm,
+}
+
+/// How often the embedded hardware reports its fan and pump speeds, in Hz.
+/// Capped by the core loop period, like the telemetry rate.
+pub const FAST_RPM_RATE_HZ: u8 = 10;
+
+/// How often the embedded hardware samples its fan and pump speeds and valve
+/// state while batching, in Hz. Capped by the core loop period.
+pub const SENSOR_BATCH_SAMPLE_RATE_HZ: u8 = 20;
+
+/// How often a batch of samples is sent while batching, in milliseconds,
+/// unless it fills up first.
+pub const SENSOR_BATCH_FLUSH_MS: u16 = 500;
+
+/// The most samples a `ReportSensorsBatchPacket` carries. Enough for
+/// `SENSOR_BATCH_FLUSH_MS` at `SENSOR_BATCH_SAMPLE_RATE_HZ`, and few enough
+/// for the largest batch to fit a frame.
+pub const MAX_SENSOR_BATCH_SAMPLES: usize = 10;
+
+/// Represents a single sample of a `ReportSensorsBatchPacket`. Speeds are in
+/// whole RPM rather than `Rpm`s to keep samples small.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SensorSample {
+    /// Milliseconds after the first sample of the batch this was taken.
+    pub offset_ms: u16,
+
+    /// The first fan channel's speed. Batches leave out the rest to keep
+    /// samples small.
+    pub fan_rpm: u16,
+    pub pump_rpm: u16,
+    pub valve_state: ValveState,
+}
+
+/// Represents several samples of the fan and pump speeds and valve state,
+/// sent in place of `ReportRpmFastPacket`s while batching. Sampling faster
+/// than the fast rpm rate but sending less often gives the host finer
+/// telemetry for less USB overhead, at the cost of latency.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReportSensorsBatchPacket {
+    /// When the first sample was taken, in milliseconds since the unix epoch
+    /// by the host's clock, if the embedded hardware has been sent a
+    /// `TimeSync`.
+    pub started_at_ms: Option<u64>,
+
+    /// The maximum speeds the samples are scaled against, in whole RPM.
+    pub fan_max_rpm: u16,
+    pub pump_max_rpm: u16,
+
+    /// In the order they were taken.
+    pub samples: Vec<SensorSample, MAX_SENSOR_BATCH_SAMPLES>,
+}
+
+impl ReportSensorsBatchPacket {
+    /// Get a sample's fan and pump speeds. Like full reports, a speed above
+    /// its maximum raises the maximum to match.
+    pub fn speeds(&self, sample: &SensorSample) -> Result<(Rpm, Rpm), RpmError> {
+        let rpm = |max_rpm: u16, rpm: u16| Rpm::new(max_rpm.max(rpm) as f32, rpm as f32);
+        Ok((
+            rpm(self.fan_max_rpm, sample.fan_rpm)?,
+            rpm(self.pump_max_rpm, sample.pump_rpm)?,
+        ))
+    }
+
+    /// When a sample was taken, in host time, if the batch is timestamped.
+    pub fn sampled_at_ms(&self, sample: &SensorSample) -> Option<u64> {
+        self.started_at_ms
+            .map(|started_at_ms| started_at_ms + sample.offset_ms as u64)
+    }
+}
+
+/// Represents a request from the host to start or stop batching samples in
+/// `ReportSensorsBatchPacket`s instead of sending `ReportRpmFastPacket`s.
+/// Full sensor reports are sent at the telemetry rate either way. Batching
+/// is stopped on reset.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetSensorBatchingPacket {
+    pub enabled: bool,
+}
+
+/// Represents a request from the host for a `ReportSensorsPacket` right away,
+/// rather than at the next telemetry period. Lets the host poll the sensors,
+/// such as for a status command.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestSensorsPacket;
+
+/// Represents a request from the host to reset the embedded hardware, such as
+/// to recover a wedged board without unplugging it. The pump and fan are
+/// parked at full first, and the board comes back reporting a `Software`
+/// reset cause.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebootPacket;
+
+/// Represents a request from the host to reset the embedded hardware into its
+/// bootloader, so new firmware can be flashed without pressing the reset
+/// button. The pump and fan are parked at full first, like a reboot.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnterBootloaderPacket;
+
+/// Represents a request from the host for a `ReportRawAdcPacket` right away.
+/// Lets the host show what the hardware actually reads when the reported
+/// speeds look wrong.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestRawAdcPacket;
+
+/// Represents the sense inputs exactly as the embedded hardware reads them,
+/// before any calibration, sense curve or scaling is applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportRawAdcPacket {
+    /// The 12-bit pump sense reading, if the ADC could be read.
+    pub pump_sense: Option<u16>,
+
+    /// The 12-bit fan sense reading, if the ADC could be read.
+    pub fan_sense: Option<u16>,
+
+    /// The levels of the valve sense pins as they read now, without
+    /// debouncing.
+    pub valve_sense_1: bool,
+    pub valve_sense_2: bool,
+}
+
+/// Represents a request from the host for the embedded hardware's
+/// configuration, answered with a `ReportConfigPacket`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadConfigPacket;
+
+/// Represents a request from the host to replace the embedded hardware's
+/// configuration as a whole. Applied immediately, clamped to what the
+/// hardware honors, persisted, and answered with a `ReportConfigPacket`
+/// holding the configuration as applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteConfigPacket {
+    pub config: FirmwareConfig,
+}
+
+/// Represents the embedded hardware's configuration, in answer to a
+/// `ReadConfigPacket` or `WriteConfigPacket`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportConfigPacket {
+    pub config: FirmwareConfig,
+
+    /// Whether the configuration is stored, so it survives a reset. `false`
+    /// if storing it failed, in which case it only applies until reset.
+    pub persisted: bool,
+}
+
+/// The configuration of the embedded hardware which is read and written as a
+/// whole.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareConfig {
+    /// The frequency the pump and fan are driven at, in Hz. Clamped to
+    /// `MIN_PWM_FREQUENCY_HZ..=MAX_PWM_FREQUENCY_HZ`.
+    pub pwm_frequency_hz: u16,
+
+    /// How often sensor data is reported, in Hz. Clamped to
+    /// `MIN_TELEMETRY_RATE_HZ..=MAX_TELEMETRY_RATE_HZ`.
+    pub telemetry_rate_hz: u8,
+
+    /// The maximum speeds the pump and fan reach at 100% duty, in whole RPM.
+    pub pump_max_rpm: u16,
+    pub fan_max_rpm: u16,
+
+    /// The pump and fan duty output when the link to the host is lost or
+    /// before a reset, in whole percent. Clamped to
+    /// `MIN_FAILSAFE_DUTY_PERCENT..=100`.
+    pub failsafe_duty_percent: u8,
 }
 
 /// Represents a snapshot of raw target control state. Sent from the host
@@ -48,29 +398,760 @@ pub struct ReportSensorsPacket {
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct ReportControlTargetsPacket {
     /// The voltage value which the embedded hardware should immediately output
-    /// for the fan.
-    pub fan_control_percent: Percentage,
+    /// for each fan channel. Channels the embedded hardware drives without a
+    /// target here follow the first.
+    pub fan_control_percents: FanChannels<Percentage>,
 
     /// The voltage value which the embedded hardware should immediately output
     /// for the pump.
     pub pump_control_percent: Percentage,
 
-    /// The valve is either instructed to begin opening or closing.
-    /// Sending the state which the valve is in results in nothing happening.
-    pub valve_control_state: ValveState,
+    /// Numbers each control frame the host sends, wrapping. The embedded
+    /// hardware acknowledges it with an `Ack`, and counts any skipped as
+    /// missed. Stamped by the link just before the frame is written.
+    #[serde(default)]
+    pub sequence: u16,
+}
+
+impl ReportControlTargetsPacket {
+    /// Get the target of fan `channel`, counting from 0, or the first
+    /// channel's if it has none.
+    pub fn fan_control_percent(&self, channel: usize) -> Percentage {
+        self.fan_control_percents
+            .get(channel)
+            .unwrap_or(self.fan_control_percents.first())
+    }
 }
 
+/// Represents a command to move the valve. Sent from the host to the
+/// embedded hardware separately from the control targets, since the valve
+/// takes seconds to move.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValveCommandPacket {
+    /// Either `Open` or `Closed`; anything else is ignored. Commanding the
+    /// state the valve was last commanded to results in nothing happening.
+    pub target: ValveState,
+}
+
+/// How long, in milliseconds, the embedded hardware waits for the valve
+/// sense pins to confirm a commanded transition before reporting it
+/// complete anyway. Twice what a typical valve takes to travel.
+pub const VALVE_TRANSITION_TIMEOUT_MS: u16 = 10_000;
+
+/// Represents the end of a valve transition started by a `ValveCommand`.
+/// Sent from the embedded hardware once the valve sense pins read the
+/// commanded state, or after `VALVE_TRANSITION_TIMEOUT_MS`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValveTransitionCompletePacket {
+    /// The state the valve was commanded to.
+    pub state: ValveState,
+
+    /// Whether the valve sense pins confirmed the valve arrived. `false` if
+    /// the transition timed out, or the hardware has no valve sense wiring.
+    pub confirmed: bool,
+
+    /// How long the transition took, in milliseconds.
+    pub elapsed_ms: u16,
+}
+
+/// Represents the embedded hardware acknowledging a control frame. Sent as
+/// soon as a `ReportControlTargets` packet arrives, even if a newer one
+/// supersedes it before it is output.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckPacket {
+    /// The sequence number of the acknowledged control frame.
+    pub sequence: u16,
+
+    /// How many control frames the embedded hardware has found missing from
+    /// the sequence since it started.
+    pub missed_control_frames: u32,
+}
+
+/// How many sequence numbers were skipped going from `previous` to `next`.
+/// A repeat, a jump backwards or a jump of half the sequence space or more is
+/// taken as the sender restarting its count, not as missed packets.
+///
+/// ```
+/// use common::packet::sequence_gap;
+/// assert_eq!(sequence_gap(4, 5), 0);
+/// assert_eq!(sequence_gap(4, 7), 2);
+/// assert_eq!(sequence_gap(u16::MAX, 1), 1);
+/// assert_eq!(sequence_gap(500, 0), 0);
+/// ```
+pub fn sequence_gap(previous: u16, next: u16) -> u16 {
+    match next.wrapping_sub(previous) {
+        0 | 0x8000.. => 0,
+        gap => gap - 1,
+    }
+}
+
+/// Represents a request from the host for the log lines the embedded
+/// hardware has buffered. Answered with each as a `ReportLogLinePacket`,
+/// oldest first.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLogsPacket;
+
 /// Represents a diagnostic log line from the embedded hardware.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ReportLogLinePacket {
-    pub log_line: str8,
+    pub level: LogLevel,
+
+    /// Which part of the firmware logged it, numbered by the firmware.
+    pub module: u8,
+
+    /// Up to 63 bytes. Longer messages are truncated.
+    pub message: str64,
+}
+
+/// How severe a log line from the embedded hardware is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Trace,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LogLevel::Trace => write!(f, "trace"),
+            LogLevel::Info => write!(f, "info"),
+            LogLevel::Warn => write!(f, "warn"),
+            LogLevel::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Represents a request from the host to change a single firmware setting.
+/// The embedded hardware applies the parameter immediately and persists it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SetParameterPacket {
+    pub parameter: Parameter,
+}
+
+/// A single tunable firmware setting along with its new value.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    /// The maximum speed the pump reaches at 100% duty, in whole RPM.
+    PumpMaxRpm(u16),
+
+    /// The maximum speed the fan reaches at 100% duty, in whole RPM.
+    FanMaxRpm(u16),
+
+    /// How often the embedded hardware reports sensor data, in Hz.
+    /// Clamped to `MIN_TELEMETRY_RATE_HZ..=MAX_TELEMETRY_RATE_HZ`.
+    TelemetryRateHz(u8),
+
+    /// How long the embedded hardware waits between core loop iterations, in
+    /// milliseconds. Shorter periods lower latency, longer ones save power.
+    /// Clamped to `MIN_CORE_LOOP_PERIOD_MS..=MAX_CORE_LOOP_PERIOD_MS`.
+    CoreLoopPeriodMs(u8),
+
+    /// How many pulses the flow meter gives per liter of coolant. Zero means
+    /// there is no flow meter, so no flow rate is reported.
+    FlowPulsesPerLiter(u16),
+}
+
+impl Parameter {
+    /// Which setting this parameter is.
+    pub fn kind(&self) -> ParameterKind {
+        match self {
+            Parameter::PumpMaxRpm(_) => ParameterKind::PumpMaxRpm,
+            Parameter::FanMaxRpm(_) => ParameterKind::FanMaxRpm,
+            Parameter::TelemetryRateHz(_) => ParameterKind::TelemetryRateHz,
+            Parameter::CoreLoopPeriodMs(_) => ParameterKind::CoreLoopPeriodMs,
+            Parameter::FlowPulsesPerLiter(_) => ParameterKind::FlowPulsesPerLiter,
+        }
+    }
+
+    /// The parameter's value, whatever its unit.
+    pub fn value(&self) -> u16 {
+        match *self {
+            Parameter::PumpMaxRpm(rpm) | Parameter::FanMaxRpm(rpm) => rpm,
+            Parameter::TelemetryRateHz(rate_hz) => rate_hz as u16,
+            Parameter::CoreLoopPeriodMs(period_ms) => period_ms as u16,
+            Parameter::FlowPulsesPerLiter(pulses) => pulses,
+        }
+    }
+}
+
+/// Identifies a single tunable firmware setting, without its value.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParameterKind {
+    PumpMaxRpm,
+    FanMaxRpm,
+    TelemetryRateHz,
+    CoreLoopPeriodMs,
+    FlowPulsesPerLiter,
+}
+
+impl ParameterKind {
+    /// Every kind of parameter, in `Parameter` order.
+    pub const ALL: [ParameterKind; 5] = [
+        ParameterKind::PumpMaxRpm,
+        ParameterKind::FanMaxRpm,
+        ParameterKind::TelemetryRateHz,
+        ParameterKind::CoreLoopPeriodMs,
+        ParameterKind::FlowPulsesPerLiter,
+    ];
+}
+
+impl Display for ParameterKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ParameterKind::PumpMaxRpm => write!(f, "pump max rpm"),
+            ParameterKind::FanMaxRpm => write!(f, "fan max rpm"),
+            ParameterKind::TelemetryRateHz => write!(f, "telemetry rate hz"),
+            ParameterKind::CoreLoopPeriodMs => write!(f, "core loop period ms"),
+            ParameterKind::FlowPulsesPerLiter => write!(f, "flow pulses per liter"),
+        }
+    }
+}
+
+/// Represents a request from the host for the current value of a single
+/// firmware setting. Answered with a `ReportParameter`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetParameterPacket {
+    pub kind: ParameterKind,
+}
+
+/// Represents the current value of a single firmware setting, in answer to a
+/// `GetParameter`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportParameterPacket {
+    pub parameter: Parameter,
+}
+
+/// Represents a request from the host to retune the embedded hardware at
+/// runtime, in place of the constants it was built with. Invalid tuning is
+/// ignored as a whole.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SetTuningPacket {
+    pub tuning: TuningParameters,
+}
+
+/// The tunable parts of the embedded hardware's speed sensing. The maximum
+/// speeds are applied and persisted as their `Parameter`s would be, while the
+/// sense curves last until reset.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TuningParameters {
+    /// The maximum speed the pump reaches at 100% duty, in whole RPM.
+    pub pump_max_rpm: u16,
+
+    /// The maximum speed the fan reaches at 100% duty, in whole RPM.
+    pub fan_max_rpm: u16,
+
+    /// Calibrates the pump speed sense input.
+    pub pump_sense: SenseCurve,
+
+    /// Calibrates the fan speed sense input.
+    pub fan_sense: SenseCurve,
+}
+
+impl TuningParameters {
+    /// Whether both sense curves are valid.
+    pub fn is_valid(&self) -> bool {
+        self.pump_sense.is_valid() && self.fan_sense.is_valid()
+    }
+}
+
+/// Represents a request from the host to trim the speed sense inputs, such as
+/// for a sense voltage which reads off in the field. The embedded hardware
+/// persists the calibration and applies it to every normalized reading before
+/// its sense curve. Invalid calibration is ignored as a whole.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetCalibrationPacket {
+    pub pump_sense: SenseCalibration,
+    pub fan_sense: SenseCalibration,
+}
+
+/// Trims a single ADC input. The reading, normalized to full scale, has
+/// `offset` added and is then scaled by `gain`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenseCalibration {
+    /// Added to the reading, in hundredths of a percent of full scale.
+    pub offset: i16,
+
+    /// Scales the reading, in ten-thousandths. `10_000` leaves it as is.
+    pub gain: u16,
+}
+
+impl Default for SenseCalibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl SenseCalibration {
+    /// Leaves readings as they are.
+    pub const IDENTITY: Self = Self {
+        offset: 0,
+        gain: 10_000,
+    };
+
+    /// Whether the gain is above zero, so readings still vary with the input.
+    pub fn is_valid(&self) -> bool {
+        self.gain > 0
+    }
+
+    /// Trim a reading normalized to 0-1.
+    ///
+    /// ```
+    /// use common::packet::SenseCalibration;
+    /// let calibration = SenseCalibration { offset: -100, gain: 12_000 };
+    /// assert!((calibration.apply(0.51f32) - 0.6f32).abs() < 1e-6);
+    /// ```
+    pub fn apply(&self, reading_norm: f32) -> f32 {
+        (reading_norm + self.offset as f32 / 10_000f32) * (self.gain as f32 / 10_000f32)
+    }
+}
+
+/// The most calibration points a `SenseCurve` can have.
+pub const MAX_SENSE_CURVE_POINTS: usize = 4;
+
+/// Calibrates a speed sense input, mapping its reading as a percentage of full
+/// scale to a speed in whole RPM. Speeds are interpolated linearly between
+/// points and clamped to the first and last.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SenseCurve {
+    /// Calibration points in strictly increasing order of reading.
+    pub points: Vec<(Percentage, u16), MAX_SENSE_CURVE_POINTS>,
+}
+
+impl SenseCurve {
+    /// A curve from standstill at no reading to `full_scale_rpm` at a full
+    /// scale reading.
+    ///
+    /// ```
+    /// use common::packet::SenseCurve;
+    /// let curve = SenseCurve::linear(2000);
+    /// assert_eq!(curve.rpm_at(0.25f32), Some(500f32));
+    /// ```
+    pub fn linear(full_scale_rpm: u16) -> Self {
+        let mut points = Vec::new();
+        let _ = points.push((Percentage::const_new(0), 0));
+        let _ = points.push((Percentage::const_new(100), full_scale_rpm));
+        Self { points }
+    }
+
+    /// Whether the curve has at least one point, with readings strictly
+    /// increasing.
+    pub fn is_valid(&self) -> bool {
+        !self.points.is_empty()
+            && self
+                .points
+                .windows(2)
+                .all(|pair| pair[0].0.value() < pair[1].0.value())
+    }
+
+    /// Get the speed for a reading normalized to 0-1. Returns `None` if the
+    /// curve is invalid or the reading is NaN or infinite.
+    pub fn rpm_at(&self, reading_norm: f32) -> Option<f32> {
+        if !self.is_valid() || !reading_norm.is_finite() {
+            return None;
+        }
+        let reading = reading_norm * 100f32;
+        let mut previous: Option<(f32, f32)> = None;
+        for &(point_reading, point_rpm) in self.points.iter() {
+            let (x2, y2): (f32, f32) = (point_reading.into(), point_rpm as f32);
+            if reading <= x2 {
+                return Some(match previous {
+                    None => y2,
+                    Some((x1, y1)) => y1 + (y2 - y1) * ((reading - x1) / (x2 - x1)),
+                });
+            }
+            previous = Some((x2, y2));
+        }
+        previous.map(|(_, y)| y)
+    }
+}
+
+/// The slowest sensor report rate the embedded hardware will honor.
+pub const MIN_TELEMETRY_RATE_HZ: u8 = 1;
+
+/// The fastest sensor report rate the embedded hardware will honor.
+pub const MAX_TELEMETRY_RATE_HZ: u8 = 20;
+
+/// The shortest core loop period the embedded hardware will honor.
+pub const MIN_CORE_LOOP_PERIOD_MS: u8 = 1;
+
+/// The longest core loop period the embedded hardware will honor. Any longer
+/// and the fastest telemetry rate can't be met.
+pub const MAX_CORE_LOOP_PERIOD_MS: u8 = 50;
+
+/// The lowest PWM frequency the embedded hardware will honor. Any lower and
+/// the RP2040's PWM counter overflows at its clock divider.
+pub const MIN_PWM_FREQUENCY_HZ: u16 = 1000;
+
+/// The highest PWM frequency the embedded hardware will honor, that of PC
+/// fans driven directly.
+pub const MAX_PWM_FREQUENCY_HZ: u16 = 25_000;
+
+/// The lowest fail-safe duty the embedded hardware will honor, so a bad
+/// configuration can't leave the loop uncooled without the host.
+pub const MIN_FAILSAFE_DUTY_PERCENT: u8 = 50;
+
+/// How many packets the embedded hardware can queue in each direction. A
+/// queue whose high-water mark reaches this has dropped, or nearly dropped,
+/// packets.
+pub const PACKET_QUEUE_CAPACITY: usize = 16;
+
+/// Represents a snapshot of the embedded hardware's own health.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReportStatsPacket {
+    /// The measured supply voltage in millivolts, if it could be read.
+    pub supply_voltage_mv: Option<u16>,
+
+    /// Milliseconds since reset, counted in core loop periods. Wraps after
+    /// about 49 days.
+    pub uptime_ms: u32,
+
+    /// The period the core loop runs at, in milliseconds.
+    pub core_loop_period_ms: u16,
+
+    /// Received frames which failed to decode since reset.
+    pub decode_errors: u32,
+
+    /// The most packets the incoming queue has held at once since reset.
+    pub incoming_queue_high_water: u8,
+
+    /// The most packets the outgoing queue has held at once since reset.
+    pub outgoing_queue_high_water: u8,
+}
+
+/// Represents a fault detected by the embedded hardware.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReportErrorPacket {
+    pub error: FirmwareError,
+}
+
+/// A fault the embedded hardware can report to the host.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum FirmwareError {
+    /// The supply voltage drooped below the brownout warning threshold.
+    /// Typically caused by an overloaded USB hub.
+    #[error("Supply voltage low: {supply_voltage_mv} mV.")]
+    SupplyVoltageLow { supply_voltage_mv: u16 },
+
+    /// The pump or fan speed couldn't be read from the ADC, so a speed
+    /// report was skipped.
+    #[error("Failed to read pump or fan speed from the ADC.")]
+    AdcFailure,
+
+    /// The valve sense pins couldn't be read, so a sensor report was skipped.
+    #[error("Failed to read valve state from the sense pins.")]
+    ValveReadFailure,
+
+    /// Packets were dropped because a packet queue was full.
+    #[error("Dropped {dropped} packets to a full queue.")]
+    QueueOverflow { dropped: u16 },
+
+    /// Received frames were dropped because they were malformed or failed
+    /// their CRC check.
+    #[error("Dropped {dropped} frames which failed to decode.")]
+    DecodeFailure { dropped: u16 },
+}
+
+/// Represents a request from the host for the embedded hardware to report
+/// what it is capable of. Sent when a device is first connected.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RequestDeviceInfoPacket;
+
+/// Represents the embedded hardware's description of itself. Sent in reply to
+/// a `RequestDeviceInfo` packet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReportDeviceInfoPacket {
+    pub capabilities: Capabilities,
+
+    /// Where the settings in use were loaded from at boot.
+    pub settings: SettingsOrigin,
+
+    /// Why the embedded hardware last reset.
+    pub reset_cause: ResetCause,
+}
+
+/// Why the embedded hardware last reset, as latched by the chip at boot.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResetCause {
+    /// Power was applied.
+    PowerOn,
+
+    /// The supply voltage dropped below the brown-out detector's threshold.
+    BrownOut,
+
+    /// The reset pin was pulled low.
+    External,
+
+    /// The watchdog expired, typically because the firmware hung.
+    Watchdog,
+
+    /// The firmware requested a reset, such as to enter the bootloader.
+    Software,
+
+    /// The chip doesn't latch a cause, or latched one not listed here.
+    #[default]
+    Unknown,
+}
+
+impl ResetCause {
+    /// Whether the reset points to a fault rather than a deliberate reset or
+    /// power cycle.
+    pub fn is_fault(&self) -> bool {
+        matches!(self, ResetCause::BrownOut | ResetCause::Watchdog)
+    }
+}
+
+/// Where the firmware's settings were loaded from at boot. Settings are kept
+/// in two banks, each with a version and CRC, so a write cut short by a reset
+/// only loses the change being written.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsOrigin {
+    /// Nothing valid was stored, so the compiled defaults are in use.
+    Defaults { corrupt_banks: u8 },
+
+    /// The newest bank which passed its CRC check.
+    Bank {
+        bank: u8,
+        version: u32,
+        corrupt_banks: u8,
+    },
+}
+
+impl SettingsOrigin {
+    /// How many banks held data which failed its CRC check. Any at all means
+    /// the newest settings may have been lost.
+    pub fn corrupt_banks(&self) -> u8 {
+        match self {
+            SettingsOrigin::Defaults { corrupt_banks }
+            | SettingsOrigin::Bank { corrupt_banks, .. } => *corrupt_banks,
+        }
+    }
+}
+
+/// The peripherals a firmware build is wired to drive or sense. Anything
+/// missing here is ignored by the firmware even if the host asks for it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// How many independently controlled fan channels there are.
+    pub fan_channels: u8,
+
+    /// How many coolant thermistors are wired up.
+    pub thermistors: u8,
+
+    /// Whether the valve control pins drive a valve.
+    pub valve_driver: bool,
+
+    /// Whether the valve sense pins are wired, so `ValveState` readings mean
+    /// anything.
+    pub valve_sense: bool,
+}
+
+/// Represents which firmware build the embedded hardware is running. Sent
+/// along with the device info, so the host can log exactly which build it
+/// is talking to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareInfoPacket {
+    /// The version of the firmware crate.
+    pub version: FirmwareVersion,
+
+    /// The abbreviated hash of the git commit the firmware was built from.
+    /// Empty if it wasn't built from a checkout.
+    pub git_hash: str8,
+
+    /// The board the firmware was built for.
+    pub board: str16,
+}
+
+impl FirmwareInfoPacket {
+    /// Describe a firmware build. A `version` which isn't semver is reported
+    /// as 0.0.0, and `git_hash` and `board` are truncated to fit.
+    pub fn new(version: &str, git_hash: &str, board: &str) -> Self {
+        Self {
+            version: FirmwareVersion::parse(version).unwrap_or_default(),
+            git_hash: str8::from(git_hash),
+            board: str16::from(board),
+        }
+    }
+}
+
+impl Display for FirmwareInfoPacket {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.version)?;
+        if !self.git_hash.is_empty() {
+            write!(f, " ({})", self.git_hash)?;
+        }
+        write!(f, " for {}", self.board)
+    }
+}
+
+/// A semantic version, `major.minor.patch`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct FirmwareVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl FirmwareVersion {
+    /// Parse a version such as Cargo's `CARGO_PKG_VERSION`. Any pre-release
+    /// or build metadata is ignored.
+    ///
+    /// ```
+    /// use common::packet::FirmwareVersion;
+    /// let version = FirmwareVersion::parse("1.20.3-rc.1").unwrap();
+    /// assert_eq!((version.major, version.minor, version.patch), (1, 20, 3));
+    /// assert_eq!(FirmwareVersion::parse("1.20"), None);
+    /// ```
+    pub fn parse(version: &str) -> Option<Self> {
+        let core = version.split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(|part| part.parse::<u16>().ok());
+        let version = Self {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+        };
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl Display for FirmwareVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Represents a latency probe from the host. The embedded hardware replies
+/// with a `Pong` carrying the same nonce as soon as it processes the packet.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingPacket {
+    pub nonce: u32,
+}
+
+/// Represents the embedded hardware's reply to a `Ping` packet.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PongPacket {
+    pub nonce: u32,
+}
+
+/// Represents proof of life, sent by both the host and the embedded hardware
+/// at `HEARTBEAT_RATE_HZ` so either side can tell a quiet link from a lost
+/// one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatPacket;
+
+/// How often the host and the embedded hardware send heartbeats, in Hz.
+pub const HEARTBEAT_RATE_HZ: u8 = 2;
+
+/// Represents the host's clock, sent periodically so the embedded hardware
+/// can timestamp its sensor reports in host time. The embedded hardware has
+/// no clock of its own beyond its core loop, so it drifts between syncs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSyncPacket {
+    /// Milliseconds since the unix epoch by the host's clock when sent.
+    pub host_time_ms: u64,
+
+    /// How long the host expects the packet to take to arrive, from the round
+    /// trip of the previous sync. Added to `host_time_ms` on arrival.
+    pub latency_ms: u16,
+}
+
+/// Represents the embedded hardware's reply to a `TimeSync` packet, sent once
+/// it has set its clock.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSyncReplyPacket {
+    /// The `host_time_ms` of the sync being replied to, so the host can time
+    /// the round trip.
+    pub host_time_ms: u64,
+
+    /// What the embedded hardware's clock read just before it was set, or
+    /// `None` if it hadn't been synced since reset. Shows how far it drifted
+    /// since the previous sync.
+    pub device_time_ms: Option<u64>,
+}
+
+/// How many bytes of payload an `Echo` packet carries.
+pub const ECHO_PAYLOAD_LEN: usize = 16;
+
+/// Represents a request from the host to enter or leave echo mode. In echo
+/// mode the embedded hardware reflects every packet back instead of acting on
+/// it and stops reporting sensors, so the link can be qualified at full rate
+/// without touching the actuators. Echo mode is left on reset.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetEchoModePacket {
+    pub enabled: bool,
+}
+
+/// Represents a payload for the embedded hardware to echo back while in echo
+/// mode. Carries a CRC so corruption on the way to the device can be told
+/// apart from corruption on the way back.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoPacket {
+    pub payload: [u8; ECHO_PAYLOAD_LEN],
+    pub crc: u16,
+}
+
+/// Represents the embedded hardware's reply to an `Echo` packet.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoReplyPacket {
+    /// The payload as the embedded hardware received it.
+    pub payload: [u8; ECHO_PAYLOAD_LEN],
+
+    /// The CRC of `payload`, computed by the embedded hardware.
+    pub crc: u16,
+
+    /// Whether the received payload matched the CRC it was sent with.
+    pub received_intact: bool,
+}
+
+impl EchoPacket {
+    /// Create an echo packet carrying `payload` and its CRC.
+    pub fn new(payload: [u8; ECHO_PAYLOAD_LEN]) -> Self {
+        Self {
+            payload,
+            crc: crc16(&payload),
+        }
+    }
+
+    /// Check the payload still matches its CRC.
+    pub fn is_intact(&self) -> bool {
+        crc16(&self.payload) == self.crc
+    }
+
+    /// Create the reply to this packet.
+    pub fn reply(&self) -> EchoReplyPacket {
+        EchoReplyPacket {
+            payload: self.payload,
+            crc: crc16(&self.payload),
+            received_intact: self.is_intact(),
+        }
+    }
+}
+
+impl EchoReplyPacket {
+    /// Check the payload still matches the CRC the embedded hardware computed.
+    pub fn is_intact(&self) -> bool {
+        crc16(&self.payload) == self.crc
+    }
+}
+
+impl Default for RequestConnectionPacket {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RequestConnectionPacket {
     /// Used to create an instance of this struct.
     /// Sets the `special_pattern` to a known value.
     pub fn new() -> Self {
+        Self::with_protocol_version(PROTOCOL_VERSION)
+    }
+
+    /// Create a request claiming to speak `protocol_version`.
+    pub fn with_protocol_version(protocol_version: u16) -> Self {
         Self {
+            protocol_version,
             // TODO: DOUBLE CHECK THIS (is *b"..." okay)
             special_pattern: *b"ab2dwask",
         }
@@ -82,3 +1163,195 @@ impl RequestConnectionPacket {
         Packet::RequestConnection(Self::new())
     }
 }
+
+impl Default for AcceptConnectionPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AcceptConnectionPacket {
+    /// Used to create an instance of this struct.
+    /// Sets the `special_pattern` to a known value.
+    pub fn new() -> Self {
+        Self::with_protocol_version(PROTOCOL_VERSION)
+    }
+
+    /// Create a response claiming to speak `protocol_version`.
+    pub fn with_protocol_version(protocol_version: u16) -> Self {
+        Self {
+            protocol_version,
+            special_pattern: *b"wask2dab",
+        }
+    }
+
+    /// Whether the embedded hardware speaks this build's protocol version.
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version == PROTOCOL_VERSION
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codec::encode_packet, framing::MAX_ENCODED_FRAME_LEN};
+
+    #[test]
+    fn test_largest_sensor_batch_fits_a_frame() {
+        let sample = SensorSample {
+            offset_ms: u16::MAX,
+            fan_rpm: u16::MAX,
+            pump_rpm: u16::MAX,
+            valve_state: ValveState::Unknown,
+        };
+        let batch = Packet::ReportSensorsBatch(ReportSensorsBatchPacket {
+            started_at_ms: Some(u64::MAX),
+            fan_max_rpm: u16::MAX,
+            pump_max_rpm: u16::MAX,
+            samples: Vec::from_slice(&[sample; MAX_SENSOR_BATCH_SAMPLES]).unwrap(),
+        });
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        assert!(encode_packet(&batch, &mut buffer).is_ok());
+    }
+
+    #[test]
+    fn test_fan_channels() {
+        let mut channels = FanChannels::single(1u8);
+        assert_eq!(channels.channels(), 1);
+        assert_eq!(channels.get(1), None);
+        for value in 2..=MAX_FAN_CHANNELS as u8 {
+            assert_eq!(channels.push(value), Ok(()));
+        }
+        assert_eq!(channels.push(9), Err(9));
+        assert_eq!(channels.channels(), MAX_FAN_CHANNELS);
+        assert_eq!(
+            channels.get(MAX_FAN_CHANNELS - 1),
+            Some(MAX_FAN_CHANNELS as u8)
+        );
+        assert_eq!(channels.map(|value| value * 2).get(1), Some(4));
+
+        assert_eq!(FanChannels::repeat(5u8, 0), FanChannels::single(5));
+        assert_eq!(FanChannels::repeat(5u8, 2).iter().sum::<u8>(), 10);
+        assert_eq!(FanChannels::repeat(5u8, 10).channels(), MAX_FAN_CHANNELS);
+
+        let mut targets = ReportControlTargetsPacket {
+            fan_control_percents: FanChannels::single(Percentage::const_new(40)),
+            pump_control_percent: Percentage::const_new(60),
+            sequence: 0,
+        };
+        let _ = targets.fan_control_percents.push(Percentage::const_new(80));
+        assert_eq!(targets.fan_control_percent(1), Percentage::const_new(80));
+        assert_eq!(targets.fan_control_percent(2), Percentage::const_new(40));
+    }
+
+    #[test]
+    fn test_longest_log_line_fits_a_frame() {
+        let message = str64::from("0123456789".repeat(10).as_str());
+        assert_eq!(message.len(), 63);
+        let log = Packet::ReportLogLine(ReportLogLinePacket {
+            level: LogLevel::Error,
+            module: u8::MAX,
+            message,
+        });
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        assert!(encode_packet(&log, &mut buffer).is_ok());
+    }
+
+    #[test]
+    fn test_sensor_batch_samples() {
+        let sample = SensorSample {
+            offset_ms: 150,
+            fan_rpm: 1900,
+            pump_rpm: 1200,
+            valve_state: ValveState::Open,
+        };
+        let batch = ReportSensorsBatchPacket {
+            started_at_ms: Some(1_000),
+            fan_max_rpm: 1800,
+            pump_max_rpm: 2000,
+            samples: Vec::new(),
+        };
+        let (fan, pump) = batch.speeds(&sample).unwrap();
+        assert_eq!((fan.max_speed(), fan.speed()), (1900f32, 1900f32));
+        assert_eq!((pump.max_speed(), pump.speed()), (2000f32, 1200f32));
+        assert_eq!(batch.sampled_at_ms(&sample), Some(1_150));
+        assert_eq!(
+            ReportSensorsBatchPacket {
+                started_at_ms: None,
+                ..batch
+            }
+            .sampled_at_ms(&sample),
+            None
+        );
+    }
+
+    #[test]
+    fn test_protocol_version_leads_connection_packets() {
+        // NOTE: Any build has to be able to read the version, whatever else
+        //       changed.
+        let mut buffer = [0u8; 16];
+        let request = Packet::RequestConnection(RequestConnectionPacket::with_protocol_version(6));
+        assert_eq!(
+            postcard::to_slice(&request, &mut buffer).unwrap()[..2],
+            [0, 6]
+        );
+        let accept = Packet::AcceptConnection(AcceptConnectionPacket::with_protocol_version(6));
+        assert_eq!(
+            postcard::to_slice(&accept, &mut buffer).unwrap()[..2],
+            [1, 6]
+        );
+        assert!(!AcceptConnectionPacket::with_protocol_version(6).is_compatible());
+        assert!(AcceptConnectionPacket::new().is_compatible());
+    }
+
+    #[test]
+    fn test_firmware_info() {
+        let info = FirmwareInfoPacket::new("0.4.1", "1a2b3c4", "feather_m0");
+        assert_eq!(
+            fixedstr::str_format!(fixedstr::str64, "{}", info),
+            "0.4.1 (1a2b3c4) for feather_m0"
+        );
+
+        let info = FirmwareInfoPacket::new("unknown", "", "a_board_with_a_long_name");
+        assert_eq!(info.version, FirmwareVersion::default());
+        assert_eq!(
+            fixedstr::str_format!(fixedstr::str64, "{}", info),
+            "0.0.0 for a_board_with_a_"
+        );
+    }
+
+    #[test]
+    fn test_sense_curve_interpolates_and_clamps() {
+        let mut curve = SenseCurve::linear(0);
+        curve.points.clear();
+        curve.points.push((Percentage::const_new(10), 200)).unwrap();
+        curve
+            .points
+            .push((Percentage::const_new(50), 1000))
+            .unwrap();
+        curve
+            .points
+            .push((Percentage::const_new(90), 1400))
+            .unwrap();
+        assert!(curve.is_valid());
+
+        assert_eq!(curve.rpm_at(0f32), Some(200f32));
+        assert_eq!(curve.rpm_at(0.25f32), Some(500f32));
+        assert_eq!(curve.rpm_at(0.75f32), Some(1250f32));
+        assert_eq!(curve.rpm_at(1f32), Some(1400f32));
+        assert_eq!(curve.rpm_at(f32::NAN), None);
+    }
+
+    #[test]
+    fn test_sense_curve_validity() {
+        let mut curve = SenseCurve::linear(1800);
+        assert!(curve.is_valid());
+
+        curve.points.swap(0, 1);
+        assert!(!curve.is_valid());
+        assert_eq!(curve.rpm_at(0.5f32), None);
+
+        curve.points.clear();
+        assert!(!curve.is_valid());
+    }
+}
@@ -0,0 +1,89 @@
+use core::fmt::Display;
+use fixed::types::I10F6;
+use serde::{Deserialize, Serialize};
+use thiserror_no_std::Error;
+
+/// Type alias for how the temperature value is actually stored.
+pub type CelsiusValue = I10F6;
+
+/// Represents a temperature in degrees Celsius. Stores with 1/64 degree steps
+/// so it stays compact on the wire and can be compared exactly.
+///
+/// ```
+/// use common::physical::Celsius;
+/// let raw: f32 = 25.5f32;
+/// let celsius = Celsius::try_from(raw).expect("Failed to get Celsius representation");
+/// assert_eq!(celsius.value(), raw);
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Celsius {
+    value: CelsiusValue,
+}
+
+/// Represents errors in creating or using the `Celsius` type.
+#[derive(Debug, Error)]
+pub enum CelsiusError {
+    /// The `Celsius` was trying to be created with a value outside of the valid
+    /// state space representation.
+    #[error("Value outside of valid state space representation!")]
+    OutOfValidStateSpace,
+
+    /// The `Celsius` was trying to be created with NaN or an infinite value.
+    #[error("Value is not a finite number!")]
+    NotFinite,
+}
+
+impl Celsius {
+    /// Get the temperature in degrees Celsius.
+    pub fn value(&self) -> f32 {
+        self.value.to_num()
+    }
+}
+
+impl TryFrom<f32> for Celsius {
+    type Error = CelsiusError;
+
+    fn try_from(value: f32) -> Result<Self, Self::Error> {
+        if !value.is_finite() {
+            return Err(CelsiusError::NotFinite);
+        }
+        match CelsiusValue::checked_from_num(value) {
+            None => Err(CelsiusError::OutOfValidStateSpace),
+            Some(value) => Ok(Self { value }),
+        }
+    }
+}
+
+impl Display for Celsius {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "<Celsius: {}degC>", self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_creation() {
+        let celsius = Celsius::try_from(-12.25f32).expect("Failed to get Celsius.");
+        assert_eq!(celsius.value(), -12.25f32);
+
+        let celsius = Celsius::try_from(85f32).expect("Failed to get Celsius.");
+        assert_eq!(celsius.value(), 85f32);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert!(Celsius::try_from(600f32).is_err());
+        assert!(Celsius::try_from(-600f32).is_err());
+        assert!(matches!(
+            Celsius::try_from(f32::NAN),
+            Err(CelsiusError::NotFinite)
+        ));
+        assert!(matches!(
+            Celsius::try_from(f32::INFINITY),
+            Err(CelsiusError::NotFinite)
+        ));
+    }
+}
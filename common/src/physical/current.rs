@@ -0,0 +1,89 @@
+use core::fmt::Display;
+use fixed::types::I6F10;
+use serde::{Deserialize, Serialize};
+use thiserror_no_std::Error;
+
+/// Type alias for how the current value is actually stored.
+pub type CurrentValue = I6F10;
+
+/// Represents an electrical current in amps. Stores with 1/1024 amp steps
+/// so it stays compact on the wire and can be compared exactly.
+///
+/// ```
+/// use common::physical::Current;
+/// let raw: f32 = 1.25f32;
+/// let current = Current::try_from(raw).expect("Failed to get Current representation");
+/// assert_eq!(current.value(), raw);
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Current {
+    value: CurrentValue,
+}
+
+/// Represents errors in creating or using the `Current` type.
+#[derive(Debug, Error)]
+pub enum CurrentError {
+    /// The `Current` was trying to be created with a value outside of the valid
+    /// state space representation.
+    #[error("Value outside of valid state space representation!")]
+    OutOfValidStateSpace,
+
+    /// The `Current` was trying to be created with NaN or an infinite value.
+    #[error("Value is not a finite number!")]
+    NotFinite,
+}
+
+impl Current {
+    /// Get the current in amps.
+    pub fn value(&self) -> f32 {
+        self.value.to_num()
+    }
+}
+
+impl TryFrom<f32> for Current {
+    type Error = CurrentError;
+
+    fn try_from(value: f32) -> Result<Self, Self::Error> {
+        if !value.is_finite() {
+            return Err(CurrentError::NotFinite);
+        }
+        match CurrentValue::checked_from_num(value) {
+            None => Err(CurrentError::OutOfValidStateSpace),
+            Some(value) => Ok(Self { value }),
+        }
+    }
+}
+
+impl Display for Current {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "<Current: {}A>", self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_creation() {
+        let current = Current::try_from(-0.5f32).expect("Failed to get Current.");
+        assert_eq!(current.value(), -0.5f32);
+
+        let current = Current::try_from(2.75f32).expect("Failed to get Current.");
+        assert_eq!(current.value(), 2.75f32);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert!(Current::try_from(40f32).is_err());
+        assert!(Current::try_from(-40f32).is_err());
+        assert!(matches!(
+            Current::try_from(f32::NAN),
+            Err(CurrentError::NotFinite)
+        ));
+        assert!(matches!(
+            Current::try_from(f32::NEG_INFINITY),
+            Err(CurrentError::NotFinite)
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+use core::fmt::Display;
+use fixed::types::U6F10;
+use serde::{Deserialize, Serialize};
+use thiserror_no_std::Error;
+
+/// Type alias for how the flow rate value is actually stored.
+pub type FlowRateValue = U6F10;
+
+/// Represents a coolant flow rate in liters per minute. Stores with 1/1024
+/// L/min steps so it stays compact on the wire and can be compared exactly.
+///
+/// ```
+/// use common::physical::FlowRate;
+/// let raw: f32 = 1.5f32;
+/// let flow_rate = FlowRate::try_from(raw).expect("Failed to get FlowRate representation");
+/// assert_eq!(flow_rate.value(), raw);
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FlowRate {
+    value: FlowRateValue,
+}
+
+/// Represents errors in creating or using the `FlowRate` type.
+#[derive(Debug, Error)]
+pub enum FlowRateError {
+    /// The `FlowRate` was trying to be created with a value outside of the
+    /// valid state space representation, such as a negative flow.
+    #[error("Value outside of valid state space representation!")]
+    OutOfValidStateSpace,
+
+    /// The `FlowRate` was trying to be created with NaN or an infinite value.
+    #[error("Value is not a finite number!")]
+    NotFinite,
+}
+
+impl FlowRate {
+    /// Get the flow rate in liters per minute.
+    pub fn value(&self) -> f32 {
+        self.value.to_num()
+    }
+}
+
+impl TryFrom<f32> for FlowRate {
+    type Error = FlowRateError;
+
+    fn try_from(value: f32) -> Result<Self, Self::Error> {
+        if !value.is_finite() {
+            return Err(FlowRateError::NotFinite);
+        }
+        match FlowRateValue::checked_from_num(value) {
+            None => Err(FlowRateError::OutOfValidStateSpace),
+            Some(value) => Ok(Self { value }),
+        }
+    }
+}
+
+impl Display for FlowRate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "<FlowRate: {}L/min>", self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_creation() {
+        let flow_rate = FlowRate::try_from(0f32).expect("Failed to get FlowRate.");
+        assert_eq!(flow_rate.value(), 0f32);
+
+        let flow_rate = FlowRate::try_from(2.25f32).expect("Failed to get FlowRate.");
+        assert_eq!(flow_rate.value(), 2.25f32);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert!(FlowRate::try_from(-0.5f32).is_err());
+        assert!(FlowRate::try_from(100f32).is_err());
+        assert!(matches!(
+            FlowRate::try_from(f32::NAN),
+            Err(FlowRateError::NotFinite)
+        ));
+    }
+}
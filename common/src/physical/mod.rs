@@ -1,9 +1,15 @@
-mod rpm;
-mod voltage;
+mod celsius;
+mod current;
+mod flow_rate;
 mod percentage;
+mod rpm;
 mod valve;
+mod voltage;
 
-pub use rpm::*;
-pub use voltage::*;
+pub use celsius::*;
+pub use current::*;
+pub use flow_rate::*;
 pub use percentage::*;
+pub use rpm::*;
 pub use valve::*;
+pub use voltage::*;
@@ -18,7 +18,7 @@ pub type PercentageValue = I13F3;
 /// let percent = Percentage::try_from(raw).expect("Failed to get Percentage representation");
 /// assert_eq!(percent.value(), raw);
 /// ```
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Percentage {
     value: PercentageValue,
 }
@@ -31,9 +31,45 @@ pub enum PercentageError {
     /// or too high of a value being ued.
     #[error("Value outside of valid state space representation!")]
     OutOfValidStateSpace,
+
+    /// The `Percentage` was trying to be created with NaN or an infinite value.
+    #[error("Value is not a finite number!")]
+    NotFinite,
 }
 
+/// How many quarter percent steps make up 100%.
+const MAX_QUARTER_STEPS: u16 = 400;
+
 impl Percentage {
+    /// Construct a percentage from a whole number of percent in `const`
+    /// context, such as for a curve's control points.
+    /// Panics if `percent` is above 100, which fails the build when const
+    /// evaluated.
+    ///
+    /// ```
+    /// use common::physical::Percentage;
+    /// const HALF: Percentage = Percentage::const_new(50);
+    /// assert_eq!(HALF, Percentage::try_from(50f32).unwrap());
+    /// ```
+    pub const fn const_new(percent: u8) -> Self {
+        Self::const_new_quarter_steps(percent as u16 * 4)
+    }
+
+    /// Construct a percentage from a number of quarter percent steps in
+    /// `const` context, e.g. 1 is 0.25%.
+    /// Panics if it is above 100%, which fails the build when const
+    /// evaluated.
+    pub const fn const_new_quarter_steps(quarter_steps: u16) -> Self {
+        assert!(
+            quarter_steps <= MAX_QUARTER_STEPS,
+            "Percentage is above 100%."
+        );
+        // NOTE: The value has 3 fractional bits, so a quarter step is 2.
+        Self {
+            value: PercentageValue::from_bits((quarter_steps * 2) as i16),
+        }
+    }
+
     /// Get the underlying percentage value.
     pub fn value(&self) -> PercentageValue {
         self.value.clone()
@@ -49,6 +85,9 @@ impl TryFrom<f32> for Percentage {
     type Error = PercentageError;
 
     fn try_from(value: f32) -> Result<Self, Self::Error> {
+        if !value.is_finite() {
+            return Err(PercentageError::NotFinite);
+        }
         if value < 0f32 || value > 100f32 {
             return Err(PercentageError::OutOfValidStateSpace);
         }
@@ -90,6 +129,43 @@ pub mod tests {
         assert!(percent.is_err());
     }
 
+    #[test]
+    fn test_const_new_quarter_steps() {
+        for i in 0..=MAX_QUARTER_STEPS {
+            let raw: f32 = (i as f32) / 4f32;
+            assert_eq!(
+                Percentage::const_new_quarter_steps(i),
+                Percentage::try_from(raw).expect("Failed to get Percentage.")
+            );
+        }
+        assert_eq!(
+            Percentage::const_new(100),
+            Percentage::try_from(100f32).expect("Failed to get Percentage.")
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_const_new_rejects_above_full() {
+        Percentage::const_new(101);
+    }
+
+    #[test]
+    fn test_rejects_non_finite() {
+        assert!(matches!(
+            Percentage::try_from(f32::NAN),
+            Err(PercentageError::NotFinite)
+        ));
+        assert!(matches!(
+            Percentage::try_from(f32::INFINITY),
+            Err(PercentageError::NotFinite)
+        ));
+        assert!(matches!(
+            Percentage::try_from(f32::NEG_INFINITY),
+            Err(PercentageError::NotFinite)
+        ));
+    }
+
     #[test]
     fn test_sub_working_cases() {
         let perc1 = Percentage::try_from(50f32).expect("Failed to get Percentage.");
@@ -31,7 +31,7 @@ fn from_rpm_speed(speed: RpmSpeed) -> f32 {
 /// let underlying_speed: f32 = rpm.speed();
 /// assert_eq!(underlying_speed, 500.2f32);
 /// ```
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Rpm {
     /// The maximum speed this RPM value can represent.
     max_speed_raw: u32,
@@ -56,6 +56,10 @@ pub enum RpmError {
     /// value or too high of value being used.
     #[error("Value outside of valid state space representation!")]
     OutOfValidStateSpace,
+
+    /// The `RPM` was trying to be created with NaN or an infinite value.
+    #[error("Value is not a finite number!")]
+    NotFinite,
 }
 
 impl Rpm {
@@ -63,6 +67,9 @@ impl Rpm {
     /// Will return `OutOfValidStateSpace` if RPM is negative or above
     /// maximum.
     pub fn new(max_speed: f32, speed: f32) -> Result<Self, RpmError> {
+        if !max_speed.is_finite() || !speed.is_finite() {
+            return Err(RpmError::NotFinite);
+        }
         let max_speed = match to_rpm_speed(max_speed) {
             None => return Err(RpmError::OutOfValidStateSpace),
             Some(rpm_speed) => rpm_speed,
@@ -82,6 +89,28 @@ impl Rpm {
         })
     }
 
+    /// Construct a RPM from a whole number max and current speed in `const`
+    /// context.
+    /// Panics if the speed is above maximum or too high to represent, which
+    /// fails the build when const evaluated.
+    ///
+    /// ```
+    /// use common::physical::Rpm;
+    /// const IDLE: Rpm = Rpm::const_new(2000, 500);
+    /// assert_eq!(IDLE, Rpm::new(2000f32, 500f32).unwrap());
+    /// ```
+    pub const fn const_new(max_speed: u32, speed: u32) -> Self {
+        assert!(speed <= max_speed, "RPM is above maximum.");
+        let Some(max_speed_raw) = max_speed.checked_mul(100) else {
+            panic!("RPM is too high to represent.");
+        };
+        Self {
+            max_speed_raw,
+            speed_raw: speed * 100,
+            _private: PhantomData,
+        }
+    }
+
     /// Get the maximum speed that this RPM can represent.
     /// Converts from the underlying storage type.
     pub fn max_speed(&self) -> f32 {
@@ -102,7 +131,7 @@ impl Rpm {
         )
     }
 
-    /// Convert `RPM` into `Percentage`.
+    /// Convert `RPM` into `Percentage`. An RPM with no maximum speed is 0%.
     /// ```
     /// use crate::common::physical::{Rpm,Percentage};
     /// let rpm = Rpm::new(1000f32, 500f32).expect("Failed to generate RPM.");
@@ -110,6 +139,10 @@ impl Rpm {
     /// assert_eq!(percentage, Percentage::try_from(50f32).expect("Failed to generate Percentage"));
     /// ```
     pub fn into_percentage(&self) -> Percentage {
+        // NOTE: Dividing by a zero max speed would produce NaN.
+        if self.max_speed_raw == 0 {
+            return Percentage::try_from(0f32).expect("Failed to generate Percentage.");
+        }
         Percentage::try_from((self.speed() / self.max_speed()) * 100f32)
             .expect("Failed to generate Percentage.")
     }
@@ -131,6 +164,9 @@ impl TryFrom<f32> for Rpm {
     type Error = RpmError;
 
     fn try_from(value: f32) -> Result<Self, Self::Error> {
+        if !value.is_finite() {
+            return Err(RpmError::NotFinite);
+        }
         if value.is_sign_negative() {
             return Err(RpmError::OutOfValidStateSpace);
         }
@@ -157,6 +193,50 @@ mod tests {
         assert!(rpm.is_err());
     }
 
+    #[test]
+    fn test_const_new() {
+        const RPM: Rpm = Rpm::const_new(2300, 1250);
+        assert_eq!(
+            RPM,
+            Rpm::new(2300f32, 1250f32).expect("Failed to get RPM representation.")
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_const_new_rejects_above_maximum() {
+        Rpm::const_new(1000, 2000);
+    }
+
+    #[test]
+    fn test_rejects_non_finite() {
+        assert!(matches!(
+            Rpm::new(f32::NAN, 500f32),
+            Err(RpmError::NotFinite)
+        ));
+        assert!(matches!(
+            Rpm::new(2300f32, f32::NAN),
+            Err(RpmError::NotFinite)
+        ));
+        assert!(matches!(
+            Rpm::new(f32::INFINITY, 500f32),
+            Err(RpmError::NotFinite)
+        ));
+        assert!(matches!(
+            Rpm::try_from(f32::INFINITY),
+            Err(RpmError::NotFinite)
+        ));
+    }
+
+    #[test]
+    fn test_zero_max_speed_into_percentage() {
+        let rpm = Rpm::new(0f32, 0f32).expect("Failed to get RPM representation.");
+        assert_eq!(
+            rpm.into_percentage(),
+            Percentage::try_from(0f32).expect("Failed to get Percentage.")
+        );
+    }
+
     #[test]
     fn test_into_f32() {
         let rpm = Rpm::new(2300f32, 2000f32).expect("Failed to get RPM representation.");
@@ -8,7 +8,7 @@ const VALVE_CLOSED: (bool, bool) = (false, true);
 /// Represents the state of the valve. The valve takes multiple seconds to
 /// change state and so this allows the control system to avoid rapidly
 /// trying to change from open/closed without letting it first finish changing.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub enum ValveState {
     /// Valve is fully open.
     Open,
@@ -38,11 +38,13 @@ impl From<(bool, bool)> for ValveState {
 }
 
 impl Into<f32> for ValveState {
+    /// Note: `Unknown` has no position, so it is NaN rather than passing for
+    /// open. See the host's valve policy for how it is handled.
     fn into(self) -> f32 {
         match self {
             Self::Open | Self::Opening => 1f32,
             Self::Closed | Self::Closing => 0f32,
-            _ => 1f32,
+            Self::Unknown => f32::NAN,
         }
     }
 }
@@ -79,3 +81,18 @@ impl Display for ValveState {
         write!(f, "(ValveState state={:?})", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_has_no_position() {
+        let open: f32 = ValveState::Opening.into();
+        let closed: f32 = ValveState::Closing.into();
+        let unknown: f32 = ValveState::Unknown.into();
+        assert_eq!(open, 1f32);
+        assert_eq!(closed, 0f32);
+        assert!(unknown.is_nan());
+    }
+}
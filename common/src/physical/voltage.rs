@@ -26,6 +26,10 @@ pub enum VoltageError {
     /// or too high of a value being ued.
     #[error("Value outside of valid state space representation!")]
     OutOfValidStateSpace,
+
+    /// The `Voltage` was trying to be created with NaN or an infinite value.
+    #[error("Value is not a finite number!")]
+    NotFinite,
 }
 
 impl Voltage {
@@ -33,6 +37,9 @@ impl Voltage {
     /// Will return `OutOfValidStateSpace` if Voltage is negative or above
     /// maximum.
     pub fn new(max: f32, value: f32) -> Result<Self, VoltageError> {
+        if !max.is_finite() || !value.is_finite() {
+            return Err(VoltageError::NotFinite);
+        }
         if value < 0f32 || value > max {
             return Err(VoltageError::OutOfValidStateSpace);
         }
@@ -97,6 +104,18 @@ mod tests {
         assert!(voltage.is_err());
     }
 
+    #[test]
+    fn test_rejects_non_finite() {
+        assert!(matches!(
+            Voltage::new(5f32, f32::NAN),
+            Err(VoltageError::NotFinite)
+        ));
+        assert!(matches!(
+            Voltage::new(f32::INFINITY, 1f32),
+            Err(VoltageError::NotFinite)
+        ));
+    }
+
     #[test]
     fn test_serialization() {
         let voltage = Voltage::new(3.3f32, 1.8f32).expect("Failed to create valid voltage");
@@ -1,86 +1,397 @@
+use std::time::Duration;
+
 use common::physical::{Percentage, Rpm, ValveState};
-use once_cell::sync::Lazy;
+use thiserror::Error;
 use tracing::warn;
 
+#[cfg(feature = "scripting")]
+use crate::models::control_script::{ControlScript, ScriptInput, ScriptInputs};
 use crate::models::{
-    client_sensor_data::ClientSensorData, control_event::ControlEvent, curve::Curve,
-    host_sensor_data::HostSensorData, temperature::Temperature,
+    client_sensor_data::{ClientSensorData, ReadingQuality},
+    control_event::ControlEvent,
+    control_trace::{ControlTrace, CurveSegment, DutyTrace, PumpFeedback, ValveTrace},
+    curve::{Curve, CurveError},
+    device_id::DeviceId,
+    host_sensor_data::HostSensorData,
+    oscillation::OscillationDamper,
+    temperature::{Temperature, TemperatureUnit},
+    valve_policy::is_valve_assumed,
 };
 
-const PUMP_CURVE: Lazy<Curve<Temperature, Percentage>> = Lazy::new(|| {
-    Curve::new(vec![
-        (
-            0f32.try_into().expect("Failed to get temperature."),
-            Percentage::try_from(30f32).expect("Failed to get percentage."),
-        ),
-        (
-            50f32.try_into().expect("Failed to get temperature."),
-            Percentage::try_from(30f32).expect("Failed to get percentage."),
-        ),
-        (
-            80f32.try_into().expect("Failed to get temperature."),
-            Percentage::try_from(90f32).expect("Failed to get percentage."),
-        ),
-        (
-            85f32.try_into().expect("Failed to get temperature."),
-            Percentage::try_from(100f32).expect("Failed to get percentage."),
-        ),
-    ])
-    .expect("Failed to get pump curve.")
-});
-
-const FAN_CURVE: Lazy<Curve<Temperature, Percentage>> = Lazy::new(|| {
-    Curve::new(vec![
-        (
-            0f32.try_into().expect("Failed to get temperature."),
-            Percentage::try_from(15f32).expect("Failed to get percentage."),
-        ),
-        (
-            60f32.try_into().expect("Failed to get temperature."),
-            Percentage::try_from(15f32).expect("Failed to get percentage."),
-        ),
-        (
-            85f32.try_into().expect("Failed to get temperature."),
-            Percentage::try_from(100f32).expect("Failed to get percentage."),
-        ),
-    ])
-    .expect("Failed to get fan curve.")
-});
-
-const VALVE_CURVE: Lazy<Curve<Temperature, ValveState>> = Lazy::new(|| {
-    Curve::new(vec![
-        (
-            0f32.try_into().expect("Failed to get temperature."),
-            ValveState::Open,
-        ),
-        (
-            59f32.try_into().expect("Failed to get temperature."),
-            ValveState::Open,
-        ),
-        (
-            60f32.try_into().expect("Failed to get temperature."),
-            ValveState::Closed,
-        ),
-    ])
-    .expect("Failed to get valve curve.")
-});
+const PUMP_POINTS: &[(Temperature, Percentage)] = &[
+    (Temperature { value: 0f32 }, Percentage::const_new(30)),
+    (Temperature { value: 50f32 }, Percentage::const_new(30)),
+    (Temperature { value: 80f32 }, Percentage::const_new(90)),
+    (Temperature { value: 85f32 }, Percentage::const_new(100)),
+];
+
+const FAN_POINTS: &[(Temperature, Percentage)] = &[
+    (Temperature { value: 0f32 }, Percentage::const_new(15)),
+    (Temperature { value: 60f32 }, Percentage::const_new(15)),
+    (Temperature { value: 85f32 }, Percentage::const_new(100)),
+];
+
+const VALVE_POINTS: &[(Temperature, ValveState)] = &[
+    (Temperature { value: 0f32 }, ValveState::Open),
+    (Temperature { value: 59f32 }, ValveState::Open),
+    (Temperature { value: 60f32 }, ValveState::Closed),
+];
+
+static PUMP_CURVE: Curve<Temperature, Percentage> = Curve::from_static(PUMP_POINTS);
+static FAN_CURVE: Curve<Temperature, Percentage> = Curve::from_static(FAN_POINTS);
+static VALVE_CURVE: Curve<Temperature, ValveState> = Curve::from_static(VALVE_POINTS);
+
+// NOTE: Curves clamp below their first control point, so these are their floors.
+const PUMP_FLOOR: Percentage = PUMP_POINTS[0].1;
+const FAN_FLOOR: Percentage = FAN_POINTS[0].1;
+const VALVE_FLOOR: ValveState = VALVE_POINTS[0].1;
 
 /// Closed loop feedback sensitivity K.
 /// Higher value means more sensitive;
 const PUMP_SENSITIVITY_K: f32 = 0.15f32;
 
+/// Something wrong with the built-in curves or gains.
+#[derive(Error, Debug)]
+pub enum ControlConfigError {
+    #[error("The {0} curve is invalid. {1}")]
+    Curve(&'static str, CurveError),
+
+    #[error("The pump sensitivity {0} isn't a positive number.")]
+    PumpSensitivity(f32),
+}
+
+/// Check the built-in curves and gains are usable: every curve's points
+/// finite and increasing in temperature, and the pump feedback gain positive.
+pub fn validate_controls() -> Result<(), ControlConfigError> {
+    PUMP_CURVE
+        .validate()
+        .map_err(|e| ControlConfigError::Curve("pump", e))?;
+    FAN_CURVE
+        .validate()
+        .map_err(|e| ControlConfigError::Curve("fan", e))?;
+    VALVE_CURVE
+        .validate()
+        .map_err(|e| ControlConfigError::Curve("valve", e))?;
+    if !(PUMP_SENSITIVITY_K.is_finite() && PUMP_SENSITIVITY_K > 0f32) {
+        return Err(ControlConfigError::PumpSensitivity(PUMP_SENSITIVITY_K));
+    }
+    Ok(())
+}
+
+/// State carried between control steps for a single device.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ControlState {
+    /// The outputs of the previous step, if there was one.
+    pub previous_outputs: Option<ControlEvent>,
+
+    /// Total time the control loop has been stepped for.
+    pub elapsed: Duration,
+
+    /// The blend in progress from the outputs in effect before a switch,
+    /// such as to another profile, to the outputs generated since.
+    pub transition: Option<Transition>,
+
+    /// Watches the generated outputs for oscillation, and damps them while
+    /// they oscillate.
+    pub oscillation: OscillationDamper,
+}
+
+/// A time-based blend between the outputs in effect when a transition began
+/// and the newly generated outputs. Keeps outputs from jumping on a switch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    /// The outputs in effect when the transition began.
+    pub from: ControlEvent,
+
+    /// How long the blend takes to reach the new outputs.
+    pub window: Duration,
+
+    /// How far into the blend the last step was.
+    pub elapsed: Duration,
+}
+
+impl ControlState {
+    /// Begin blending from the current outputs to whatever is generated next
+    /// over `window`. Call this when switching what generates the outputs.
+    /// Does nothing before the first step or with a zero window, since there
+    /// is nothing to blend from.
+    pub fn begin_transition(self, window: Duration) -> Self {
+        let Some(from) = self.previous_outputs else {
+            return self;
+        };
+        if window.is_zero() {
+            return self;
+        }
+        Self {
+            transition: Some(Transition {
+                from,
+                window,
+                elapsed: Duration::ZERO,
+            }),
+            ..self
+        }
+    }
+}
+
+/// Generate the outputs to park `device` at before the host sleeps. These are
+/// the floors of the curves, so the pump and fan idle quietly and the valve
+/// is left as it is when cold.
+pub fn park_outputs(device: DeviceId) -> ControlEvent {
+    ControlEvent {
+        device,
+        fan_activation: FAN_FLOOR,
+        pump_activation: PUMP_FLOOR,
+        valve_state: VALVE_FLOOR,
+        // NOTE: Fans without a target of their own follow the first.
+        fan_channels: 1,
+        sequence: None,
+        generation: 0,
+    }
+}
+
+/// Everything the control loop reads for a single device in one step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControlInputs {
+    pub client: ClientSensorData,
+    pub host: HostSensorData,
+}
+
+/// Generates the outputs of a single control step, along with the trace of
+/// how they were computed. The built-in curves are `CurveStrategy`, and a
+/// `ControlScript` can replace them.
+pub trait ControlStrategy: Send + Sync {
+    /// Compute the outputs for `inputs`, given the state before the step.
+    fn outputs(&self, state: &ControlState, inputs: ControlInputs) -> (ControlEvent, ControlTrace);
+}
+
+/// The built-in control curves and pump feedback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CurveStrategy;
+
+impl ControlStrategy for CurveStrategy {
+    fn outputs(&self, _: &ControlState, inputs: ControlInputs) -> (ControlEvent, ControlTrace) {
+        control_outputs(inputs.client, inputs.host)
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl ControlStrategy for ControlScript {
+    /// Run the script with the curves' outputs as inputs. Outputs the script
+    /// doesn't assign follow the curves, as do all of them if it fails.
+    fn outputs(&self, state: &ControlState, inputs: ControlInputs) -> (ControlEvent, ControlTrace) {
+        let (curve_outputs, curve_trace) = CurveStrategy.outputs(state, inputs);
+        let previous = state.previous_outputs.unwrap_or(curve_outputs);
+        let percent = |percentage: Percentage| -> f32 { percentage.into() };
+        let valve = |valve_state: ValveState| match valve_state {
+            ValveState::Closed => 1f32,
+            _ => 0f32,
+        };
+        let script_inputs = ScriptInputs::default()
+            .with(ScriptInput::Temperature, inputs.host.cpu_temperature.into())
+            .with(ScriptInput::PumpRpm, inputs.client.pump_speed.speed())
+            .with(ScriptInput::FanRpm, inputs.client.fan_speed.speed())
+            .with(
+                ScriptInput::PumpSpeed,
+                percent(inputs.client.pump_speed.into_percentage()),
+            )
+            .with(
+                ScriptInput::FanSpeed,
+                percent(inputs.client.fan_speed.into_percentage()),
+            )
+            .with(ScriptInput::Elapsed, state.elapsed.as_secs_f32())
+            .with(ScriptInput::PreviousPump, percent(previous.pump_activation))
+            .with(ScriptInput::PreviousFan, percent(previous.fan_activation))
+            .with(
+                ScriptInput::CurvePump,
+                percent(curve_outputs.pump_activation),
+            )
+            .with(ScriptInput::CurveFan, percent(curve_outputs.fan_activation))
+            .with(ScriptInput::CurveValve, valve(curve_outputs.valve_state));
+        let script_outputs = match self.run(&script_inputs) {
+            Err(e) => {
+                tracing::error!("Control script failed. Using the curves. Error: {}", e);
+                return (curve_outputs, curve_trace);
+            }
+            Ok(script_outputs) => script_outputs,
+        };
+
+        let (mut outputs, mut trace) = (curve_outputs, curve_trace);
+        if let Some(duty) = script_outputs.pump {
+            (outputs.pump_activation, trace.pump) = scripted_duty(duty);
+        }
+        if let Some(duty) = script_outputs.fan {
+            (outputs.fan_activation, trace.fan) = scripted_duty(duty);
+        }
+        if let Some(closed) = script_outputs.valve_closed {
+            outputs.valve_state = if closed {
+                ValveState::Closed
+            } else {
+                ValveState::Open
+            };
+            trace.valve = ValveTrace {
+                segment: None,
+                state: outputs.valve_state,
+                held: false,
+                scripted: true,
+            };
+        }
+        (outputs, trace)
+    }
+}
+
+/// Convert a duty a control script commanded, clamping it to 0-100%.
+#[cfg(feature = "scripting")]
+fn scripted_duty(duty: f32) -> (Percentage, DutyTrace) {
+    let clamped = duty.clamp(0f32, 100f32);
+    let percentage = Percentage::try_from(clamped).expect("Failed to get Percentage.");
+    let trace = DutyTrace {
+        segment: None,
+        curve: duty,
+        feedback: None,
+        clamped_from: (clamped != duty).then_some(duty),
+        duty: clamped,
+        scripted: true,
+    };
+    (percentage, trace)
+}
+
+/// Advance the control loop for a single device by `dt`.
+/// This is a pure function: the same state, inputs and `dt` always produce
+/// the same next state and outputs. It has no side effects besides logging,
+/// so it can be verified exhaustively and driven by simulations without any
+/// async plumbing.
+pub fn step(
+    state: ControlState,
+    inputs: ControlInputs,
+    dt: Duration,
+) -> (ControlState, ControlEvent) {
+    let (state, outputs, _) = step_explained(state, inputs, dt);
+    (state, outputs)
+}
+
+/// Advance the control loop like `step`, also tracing why the outputs are
+/// what they are.
+pub fn step_explained(
+    state: ControlState,
+    inputs: ControlInputs,
+    dt: Duration,
+) -> (ControlState, ControlEvent, ControlTrace) {
+    step_with_strategy(&CurveStrategy, state, inputs, dt)
+}
+
+/// Advance the control loop like `step_explained`, with the outputs
+/// generated by `strategy` instead of the built-in curves. Holding an
+/// unknown valve, damping oscillation, blending and the low flow interlock
+/// still apply.
+pub fn step_with_strategy(
+    strategy: &dyn ControlStrategy,
+    state: ControlState,
+    inputs: ControlInputs,
+    dt: Duration,
+) -> (ControlState, ControlEvent, ControlTrace) {
+    let (mut outputs, mut trace) = strategy.outputs(&state, inputs);
+    if is_valve_assumed(&inputs.client) {
+        // NOTE: The valve's actual position isn't known, so hold the last
+        //       command rather than drive it blind.
+        outputs.valve_state = state
+            .previous_outputs
+            .map_or(ValveState::Open, |previous| previous.valve_state);
+        trace.valve.held = true;
+    }
+    let oscillation = state.oscillation.observe(&outputs, dt);
+    if let Some(previous) = state.previous_outputs.filter(|_| oscillation.is_damping()) {
+        outputs = oscillation.damp(&previous, outputs, dt);
+        trace.damped = true;
+    }
+    let transition = state.transition.and_then(|transition| {
+        let elapsed = transition.elapsed + dt;
+        if elapsed >= transition.window {
+            return None;
+        }
+        let progress = elapsed.as_secs_f32() / transition.window.as_secs_f32();
+        outputs = blend_outputs(transition.from, outputs, progress);
+        trace = trace.with_blend(transition.from, progress);
+        Some(Transition {
+            elapsed,
+            ..transition
+        })
+    });
+    if inputs.client.low_flow {
+        // NOTE: After any blend, so the fan escalates straight away.
+        outputs = low_flow_outputs(outputs);
+        trace.low_flow = true;
+    }
+    let state = ControlState {
+        previous_outputs: Some(outputs),
+        elapsed: state.elapsed + dt,
+        transition,
+        oscillation,
+    };
+    let trace = ControlTrace {
+        frame: outputs,
+        ..trace
+    };
+    (state, outputs, trace)
+}
+
+/// Hold the valve open, so what flow there is still goes through the
+/// radiator, and run the fan at full to make the most of it.
+fn low_flow_outputs(outputs: ControlEvent) -> ControlEvent {
+    ControlEvent {
+        fan_activation: Percentage::const_new(100),
+        valve_state: ValveState::Open,
+        ..outputs
+    }
+}
+
+/// Blend the fan and pump activations `progress` (0-1) of the way from `from`
+/// to `to`. The valve isn't blended and takes `to`'s state straight away.
+fn blend_outputs(from: ControlEvent, to: ControlEvent, progress: f32) -> ControlEvent {
+    let blend = |from: Percentage, to: Percentage| {
+        let raw_from: f32 = from.into();
+        let raw_to: f32 = to.into();
+        let blended = raw_from + (raw_to - raw_from) * progress.clamp(0f32, 1f32);
+        Percentage::try_from(blended).unwrap_or(to)
+    };
+    ControlEvent {
+        fan_activation: blend(from.fan_activation, to.fan_activation),
+        pump_activation: blend(from.pump_activation, to.pump_activation),
+        ..to
+    }
+}
+
+/// Generate a single control frame from the latest sensor data, without any
+/// state from previous steps.
 pub fn generate_control_frame(
     client_sensor_data: ClientSensorData,
     host_sensor_data: HostSensorData,
 ) -> ControlEvent {
+    let inputs = ControlInputs {
+        client: client_sensor_data,
+        host: host_sensor_data,
+    };
+    let (_, outputs) = step(ControlState::default(), inputs, Duration::ZERO);
+    outputs
+}
+
+/// Compute the outputs of the control curves and pump feedback, along with
+/// the trace of how they were computed.
+fn control_outputs(
+    client_sensor_data: ClientSensorData,
+    host_sensor_data: HostSensorData,
+) -> (ControlEvent, ControlTrace) {
     let temperature = host_sensor_data.cpu_temperature;
-    let target_pump_percent = pump_controller(temperature, client_sensor_data.pump_speed);
+    let (target_pump_percent, pump_trace) = pump_controller(
+        temperature,
+        client_sensor_data.pump_speed,
+        client_sensor_data.quality.pump_speed,
+    );
 
     let target_fan_percent = match FAN_CURVE.lookup(temperature) {
         None => {
             tracing::error!(
                 "Failed to get fan value for temperature {}. Defaulting to 100%!",
-                temperature
+                temperature.display(TemperatureUnit::from_env())
             );
             Percentage::try_from(100f32).expect("Failed to get percentage.")
         }
@@ -90,48 +401,124 @@ pub fn generate_control_frame(
         None => {
             tracing::error!(
                 "Failed to get valve value for temperature {}. Defaulting to Open!",
-                temperature
+                temperature.display(TemperatureUnit::from_env())
             );
             ValveState::Open
         }
         Some(percentage) => percentage,
     };
 
-    ControlEvent {
+    let outputs = ControlEvent {
+        device: client_sensor_data.device,
         fan_activation: target_fan_percent,
         pump_activation: target_pump_percent,
         valve_state: target_valve_state,
-    }
+        fan_channels: client_sensor_data.fan_channels() as u8,
+        sequence: client_sensor_data.sequence,
+        generation: 0,
+    };
+    let trace = ControlTrace {
+        device: client_sensor_data.device,
+        temperature: temperature.into(),
+        fan: DutyTrace {
+            segment: FAN_CURVE.segment(temperature).map(CurveSegment::new),
+            curve: target_fan_percent.into(),
+            feedback: None,
+            clamped_from: None,
+            duty: target_fan_percent.into(),
+            scripted: false,
+        },
+        pump: pump_trace,
+        valve: ValveTrace {
+            segment: VALVE_CURVE.segment(temperature).map(CurveSegment::new),
+            state: target_valve_state,
+            held: false,
+            scripted: false,
+        },
+        blend: None,
+        low_flow: false,
+        damped: false,
+        overridden: false,
+        frame: outputs,
+    };
+    (outputs, trace)
 }
 
 /// Apply the `Pump Controller` control system.
-fn pump_controller(temperature: Temperature, pump_rpm: Rpm) -> Percentage {
+/// Feedback is only applied when the pump speed reading is trustworthy,
+/// otherwise the curve target is used directly.
+fn pump_controller(
+    temperature: Temperature,
+    pump_rpm: Rpm,
+    pump_rpm_quality: ReadingQuality,
+) -> (Percentage, DutyTrace) {
     let target_activation = match PUMP_CURVE.lookup(temperature) {
         None => {
             tracing::error!(
                 "Failed to get pump value for temperature {}. Defaulting to 100%!",
-                temperature
+                temperature.display(TemperatureUnit::from_env())
             );
             Percentage::try_from(100f32).expect("Failed to get percentage.")
         }
         Some(percentage) => percentage,
     };
-    let raw_current_speed_percentage: f32 = pump_rpm.into_percentage().into();
     let raw_target: f32 = target_activation.into();
-    let raw_feedback_target = apply_feedback(raw_current_speed_percentage, raw_target);
+    let trace = DutyTrace {
+        segment: PUMP_CURVE.segment(temperature).map(CurveSegment::new),
+        curve: raw_target,
+        feedback: None,
+        clamped_from: None,
+        duty: raw_target,
+        scripted: false,
+    };
+    if pump_rpm_quality != ReadingQuality::Good {
+        warn!(
+            "Pump speed reading quality is {}. Skipping feedback.",
+            pump_rpm_quality
+        );
+        let feedback = Some(PumpFeedback::SkippedQuality(pump_rpm_quality));
+        return (target_activation, DutyTrace { feedback, ..trace });
+    }
+    let raw_current_speed_percentage: f32 = pump_rpm.into_percentage().into();
+    let Some(raw_feedback_target) = apply_feedback(raw_current_speed_percentage, raw_target) else {
+        tracing::error!("Pump feedback produced a non-finite target. Skipping feedback.");
+        let feedback = Some(PumpFeedback::SkippedNonFinite);
+        return (target_activation, DutyTrace { feedback, ..trace });
+    };
+    let feedback = Some(PumpFeedback::Applied {
+        measured: raw_current_speed_percentage,
+        adjustment: raw_feedback_target - raw_target,
+    });
     match Percentage::try_from(raw_feedback_target) {
         Err(err) => {
             tracing::warn!("Failed to convert target activation percentage into `Percentage`. Clamping to min/max bounds. Error: {}", err);
-            Percentage::try_from(raw_current_speed_percentage.clamp(0f32, 100f32))
-                .expect("Failed to get Percentage.")
+            let clamped = Percentage::try_from(raw_feedback_target.clamp(0f32, 100f32))
+                .expect("Failed to get Percentage.");
+            let trace = DutyTrace {
+                feedback,
+                clamped_from: Some(raw_feedback_target),
+                duty: clamped.into(),
+                ..trace
+            };
+            (clamped, trace)
         }
-        Ok(perc) => perc,
+        Ok(perc) => (
+            perc,
+            DutyTrace {
+                feedback,
+                duty: perc.into(),
+                ..trace
+            },
+        ),
     }
 }
 
 /// Apply basic feedback with `PUMP_SENSITIVITY_K` parameter.
-fn apply_feedback(current: f32, target: f32) -> f32 {
-    target + ((target - current) * PUMP_SENSITIVITY_K)
+/// Returns `None` rather than propagating NaN or infinity if either input
+/// isn't finite.
+fn apply_feedback(current: f32, target: f32) -> Option<f32> {
+    let feedback = target + ((target - current) * PUMP_SENSITIVITY_K);
+    feedback.is_finite().then_some(feedback)
 }
 
 #[cfg(test)]
@@ -139,22 +526,36 @@ mod testing {
     use common::physical::Rpm;
 
     use super::*;
+    use crate::models::{client_sensor_data::SensorQuality, device_id::DeviceId};
 
     #[test]
     fn test_generate_control_frame() {
         let client = ClientSensorData {
+            device: DeviceId::new("1324"),
             pump_speed: Rpm::new(500f32, 500f32).expect("Failed to get RPM."),
             fan_speed: Rpm::new(500f32, 500f32).expect("Failed to get RPM."),
+            extra_fan_speeds: [None; 3],
             valve_state: ValveState::Open,
+            board_temperature: None,
+            coolant_temperature: None,
+            pump_current: None,
+            fan_current: None,
+            flow_rate: None,
+            low_flow: false,
+            quality: SensorQuality::GOOD,
+            sequence: None,
+            sampled_at_ms: None,
         };
 
         for i in 0..100 {
             let host = HostSensorData {
                 cpu_temperature: Temperature::try_from(i as f32)
                     .expect("Failed to get Temperature."),
+                raw_cpu_temperature: None,
             };
 
             let control_frame = generate_control_frame(client, host);
+            assert_eq!(control_frame.device, client.device);
 
             assert_eq!(
                 control_frame.fan_activation,
@@ -169,8 +570,11 @@ mod testing {
                 .into();
             assert_eq!(
                 control_frame.pump_activation,
-                Percentage::try_from(apply_feedback(raw_current_pump_speed, raw_target))
-                    .expect("Failed to get Percentage.")
+                Percentage::try_from(
+                    apply_feedback(raw_current_pump_speed, raw_target)
+                        .expect("Failed to apply feedback.")
+                )
+                .expect("Failed to get Percentage.")
             );
             assert_eq!(
                 control_frame.valve_state,
@@ -181,6 +585,101 @@ mod testing {
         }
     }
 
+    #[test]
+    fn test_pump_controller_skips_feedback_on_bad_quality() {
+        let temperature = Temperature::try_from(60f32).expect("Failed to get Temperature.");
+        let pump_rpm = Rpm::new(2000f32, 2000f32).expect("Failed to get RPM.");
+
+        assert_eq!(
+            pump_controller(temperature, pump_rpm, ReadingQuality::Saturated).0,
+            PUMP_CURVE
+                .lookup(temperature)
+                .expect("Failed to get curve value.")
+        );
+    }
+
+    fn client_with_pump_speed(pump_rpm: f32) -> ClientSensorData {
+        ClientSensorData {
+            device: DeviceId::new("1324"),
+            pump_speed: Rpm::new(2000f32, pump_rpm).expect("Failed to get RPM."),
+            fan_speed: Rpm::new(2000f32, 500f32).expect("Failed to get RPM."),
+            extra_fan_speeds: [None; 3],
+            valve_state: ValveState::Open,
+            board_temperature: None,
+            coolant_temperature: None,
+            pump_current: None,
+            fan_current: None,
+            flow_rate: None,
+            low_flow: false,
+            quality: SensorQuality::GOOD,
+            sequence: None,
+            sampled_at_ms: None,
+        }
+    }
+
+    fn host_with_temperature(temperature: u8) -> HostSensorData {
+        HostSensorData {
+            cpu_temperature: Temperature::try_from(temperature as f32)
+                .expect("Failed to get Temperature."),
+            raw_cpu_temperature: None,
+        }
+    }
+
+    #[test]
+    fn test_step_is_deterministic_and_tracks_state() {
+        let inputs = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(70),
+        };
+        let dt = Duration::from_millis(500);
+
+        let (state, outputs) = step(ControlState::default(), inputs, dt);
+        assert_eq!(step(ControlState::default(), inputs, dt), (state, outputs));
+        assert_eq!(state.previous_outputs, Some(outputs));
+        assert_eq!(state.elapsed, dt);
+
+        let (state, _) = step(state, inputs, dt);
+        assert_eq!(state.elapsed, dt * 2);
+    }
+
+    #[test]
+    fn test_step_outputs_are_monotone_in_temperature() {
+        for pump_rpm in (0..=2000).step_by(100) {
+            let client = client_with_pump_speed(pump_rpm as f32);
+            let mut previous: Option<ControlEvent> = None;
+            for temperature in 0..=100 {
+                let inputs = ControlInputs {
+                    client,
+                    host: host_with_temperature(temperature),
+                };
+                let (_, outputs) = step(ControlState::default(), inputs, Duration::ZERO);
+
+                let fan: f32 = outputs.fan_activation.into();
+                let pump: f32 = outputs.pump_activation.into();
+                assert!((0f32..=100f32).contains(&fan));
+                assert!((0f32..=100f32).contains(&pump));
+
+                if let Some(previous) = previous {
+                    let previous_fan: f32 = previous.fan_activation.into();
+                    let previous_pump: f32 = previous.pump_activation.into();
+                    assert!(
+                        fan >= previous_fan,
+                        "Fan output dropped at {} degC with pump at {} rpm.",
+                        temperature,
+                        pump_rpm
+                    );
+                    assert!(
+                        pump >= previous_pump,
+                        "Pump output dropped at {} degC with pump at {} rpm.",
+                        temperature,
+                        pump_rpm
+                    );
+                }
+                previous = Some(outputs);
+            }
+        }
+    }
+
     #[test]
     fn test_apply_feedback() {
         for current in 0..100 {
@@ -191,8 +690,291 @@ mod testing {
 
                 let correct = target + ((target - current) * PUMP_SENSITIVITY_K);
 
-                assert_eq!(result, correct);
+                assert_eq!(result, Some(correct));
+            }
+        }
+
+        assert_eq!(apply_feedback(f32::NAN, 50f32), None);
+        assert_eq!(apply_feedback(50f32, f32::INFINITY), None);
+    }
+
+    #[test]
+    fn test_non_finite_temperature_fails_safe() {
+        // NOTE: Built directly to bypass `Temperature`'s validation.
+        let inputs = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: HostSensorData {
+                cpu_temperature: Temperature { value: f32::NAN },
+                raw_cpu_temperature: None,
+            },
+        };
+        let (_, outputs) = step(ControlState::default(), inputs, Duration::ZERO);
+
+        let full = Percentage::try_from(100f32).expect("Failed to get Percentage.");
+        assert_eq!(outputs.fan_activation, full);
+        assert_eq!(outputs.pump_activation, full);
+        assert_eq!(outputs.valve_state, ValveState::Open);
+    }
+
+    #[test]
+    fn test_transition_blends_outputs() {
+        let window = Duration::from_secs(10);
+        let cool = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(40),
+        };
+        let hot = ControlInputs {
+            host: host_with_temperature(85),
+            ..cool
+        };
+        let (state, cool_outputs) = step(ControlState::default(), cool, Duration::ZERO);
+        let (_, hot_outputs) = step(ControlState::default(), hot, Duration::ZERO);
+
+        let state = state.begin_transition(window);
+        let (state, halfway) = step(state, hot, window / 2);
+        let fan: f32 = halfway.fan_activation.into();
+        let cool_fan: f32 = cool_outputs.fan_activation.into();
+        let hot_fan: f32 = hot_outputs.fan_activation.into();
+        assert!((fan - (cool_fan + hot_fan) / 2f32).abs() < 0.01f32);
+        assert_eq!(halfway.valve_state, hot_outputs.valve_state);
+        assert!(state.transition.is_some());
+
+        let (state, done) = step(state, hot, window / 2);
+        assert_eq!(done, hot_outputs);
+        assert_eq!(state.transition, None);
+    }
+
+    #[test]
+    fn test_warm_up_from_park_outputs() {
+        let device = DeviceId::new("1324");
+        let parked = park_outputs(device);
+        assert_eq!(
+            parked.fan_activation,
+            Percentage::try_from(15f32).expect("Failed to get percentage.")
+        );
+        assert_eq!(
+            parked.pump_activation,
+            Percentage::try_from(30f32).expect("Failed to get percentage.")
+        );
+        assert_eq!(parked.valve_state, ValveState::Open);
+
+        let state = ControlState {
+            previous_outputs: Some(parked),
+            ..ControlState::default()
+        }
+        .begin_transition(Duration::from_secs(10));
+        let inputs = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(85),
+        };
+        let (_, outputs) = step(state, inputs, Duration::ZERO);
+        assert_eq!(outputs.fan_activation, parked.fan_activation);
+        assert_eq!(outputs.pump_activation, parked.pump_activation);
+    }
+
+    #[test]
+    fn test_transition_needs_previous_outputs() {
+        let state = ControlState::default().begin_transition(Duration::from_secs(10));
+        assert_eq!(state.transition, None);
+    }
+
+    #[test]
+    fn test_step_carries_sequence() {
+        let mut client = client_with_pump_speed(800f32);
+        client.sequence = Some(42);
+        let inputs = ControlInputs {
+            client,
+            host: host_with_temperature(70),
+        };
+        let (_, outputs) = step(ControlState::default(), inputs, Duration::ZERO);
+        assert_eq!(outputs.sequence, Some(42));
+    }
+
+    #[test]
+    fn test_step_explained_traces_outputs() {
+        let inputs = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(70),
+        };
+        let (_, outputs, trace) = step_explained(ControlState::default(), inputs, Duration::ZERO);
+        assert_eq!(trace.frame, outputs);
+        assert_eq!(trace.temperature, 70f32);
+        assert_eq!(
+            trace.fan.segment,
+            Some(CurveSegment {
+                from: (60f32, 15f32),
+                to: (85f32, 100f32),
+            })
+        );
+        assert_eq!(trace.fan.feedback, None);
+        let PumpFeedback::Applied {
+            measured,
+            adjustment,
+        } = trace.pump.feedback.expect("Failed to get pump feedback.")
+        else {
+            panic!("Pump feedback wasn't applied.");
+        };
+        assert_eq!(measured, 40f32);
+        assert!((trace.pump.curve + adjustment - trace.pump.duty).abs() < 0.01f32);
+        assert_eq!(trace.valve.state, outputs.valve_state);
+        assert_eq!(trace.blend, None);
+
+        let cold = ControlInputs {
+            host: host_with_temperature(0),
+            ..inputs
+        };
+        let (_, _, trace) = step_explained(ControlState::default(), cold, Duration::ZERO);
+        assert!(trace
+            .fan
+            .segment
+            .expect("Failed to get fan segment.")
+            .is_clamped());
+    }
+
+    #[test]
+    fn test_step_explained_traces_blend() {
+        let inputs = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(85),
+        };
+        let state = ControlState {
+            previous_outputs: Some(park_outputs(DeviceId::new("1324"))),
+            ..ControlState::default()
+        }
+        .begin_transition(Duration::from_secs(10));
+        let (_, _, trace) = step_explained(state, inputs, Duration::from_secs(5));
+        let blend = trace.blend.expect("Failed to get blend.");
+        assert_eq!(blend.progress, 0.5f32);
+        assert_eq!(blend.from_fan, 15f32);
+    }
+
+    #[test]
+    fn test_step_explained_traces_fallback() {
+        let inputs = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: HostSensorData {
+                cpu_temperature: Temperature { value: f32::NAN },
+                raw_cpu_temperature: None,
+            },
+        };
+        let (_, _, trace) = step_explained(ControlState::default(), inputs, Duration::ZERO);
+        assert_eq!(trace.fan.segment, None);
+        assert_eq!(trace.fan.duty, 100f32);
+        assert_eq!(trace.valve.segment, None);
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn test_control_script_strategy() {
+        let inputs = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(70),
+        };
+        let (_, curve_outputs, _) = step_explained(ControlState::default(), inputs, Duration::ZERO);
+
+        let script = ControlScript::parse("pump = curve_fan * 3;\nvalve = temperature < 60;")
+            .expect("Failed to parse script.");
+        let (_, outputs, trace) =
+            step_with_strategy(&script, ControlState::default(), inputs, Duration::ZERO);
+        assert_eq!(outputs.fan_activation, curve_outputs.fan_activation);
+        assert_eq!(outputs.pump_activation, Percentage::const_new(100));
+        assert_eq!(outputs.valve_state, ValveState::Open);
+        assert!(trace.pump.scripted);
+        assert_eq!(trace.pump.clamped_from, Some(147f32));
+        assert!(!trace.fan.scripted);
+        assert!(trace.valve.scripted);
+
+        // NOTE: Everything follows the curves when the script fails.
+        let script =
+            ControlScript::parse("fan = 1 / (temperature - 70)").expect("Failed to parse script.");
+        let (_, outputs, trace) =
+            step_with_strategy(&script, ControlState::default(), inputs, Duration::ZERO);
+        assert_eq!(outputs, curve_outputs);
+        assert!(!trace.fan.scripted);
+    }
+
+    #[test]
+    fn test_assumed_valve_holds_commands() {
+        let client = client_with_pump_speed(800f32).with_assumed_valve_state(ValveState::Open);
+        let cool = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(40),
+        };
+        let (state, outputs) = step(ControlState::default(), cool, Duration::ZERO);
+        assert_eq!(outputs.valve_state, ValveState::Open);
+
+        let hot = ControlInputs {
+            client,
+            host: host_with_temperature(70),
+        };
+        let (state, outputs, trace) = step_explained(state, hot, Duration::ZERO);
+        assert_eq!(outputs.valve_state, ValveState::Open);
+        assert_eq!(trace.valve.state, ValveState::Closed);
+        assert!(trace.valve.held);
+
+        let recovered = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            ..hot
+        };
+        let (_, outputs, trace) = step_explained(state, recovered, Duration::ZERO);
+        assert_eq!(outputs.valve_state, ValveState::Closed);
+        assert!(!trace.valve.held);
+    }
+
+    #[test]
+    fn test_low_flow_holds_valve_open_at_full_fan() {
+        let hot = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(70),
+        };
+        let (state, outputs) = step(ControlState::default(), hot, Duration::ZERO);
+        assert_eq!(outputs.valve_state, ValveState::Closed);
+
+        let low_flow = ControlInputs {
+            client: ClientSensorData {
+                low_flow: true,
+                ..hot.client
+            },
+            ..hot
+        };
+        let (_, outputs, trace) = step_explained(
+            state.begin_transition(Duration::from_secs(10)),
+            low_flow,
+            Duration::ZERO,
+        );
+        assert_eq!(outputs.valve_state, ValveState::Open);
+        assert_eq!(outputs.fan_activation, Percentage::const_new(100));
+        assert!(trace.low_flow);
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn test_oscillating_outputs_are_damped() {
+        let inputs = ControlInputs {
+            client: client_with_pump_speed(800f32),
+            host: host_with_temperature(70),
+        };
+        // NOTE: Bang-bang control, like a wildly aggressive gain.
+        let script = ControlScript::parse("pump = if previous_pump > 50 { 30 } else { 90 };")
+            .expect("Failed to parse script.");
+        let dt = Duration::from_millis(500);
+        let mut state = ControlState::default();
+        let mut damped = vec![];
+        for _ in 0..20 {
+            let previous = state.previous_outputs;
+            let (next_state, outputs, trace) = step_with_strategy(&script, state, inputs, dt);
+            if trace.damped {
+                let previous: f32 = previous
+                    .expect("Damped without previous outputs.")
+                    .pump_activation
+                    .into();
+                let pump: f32 = outputs.pump_activation.into();
+                damped.push((pump - previous).abs());
             }
+            state = next_state;
         }
+        assert!(state.oscillation.is_damping());
+        assert!(!damped.is_empty());
+        assert!(damped.iter().all(|change| *change <= 1f32));
     }
 }
@@ -0,0 +1,160 @@
+use std::{io, time::Duration};
+
+use common::framing::FrameError;
+use thiserror::Error;
+
+#[cfg(feature = "scripting")]
+use crate::models::control_script::LoadScriptError;
+#[cfg(feature = "control-socket")]
+use crate::models::{
+    control_auth::{Permission, CONTROL_TOKEN_ENV_VAR},
+    control_socket::ControlResponse,
+};
+use crate::{
+    controls::ControlConfigError,
+    models::{
+        alarm::AlarmLogError, client_sensor_data::ClientSensorDataError,
+        control_event::ControlEventError, device_registry::DeviceRegistryError,
+    },
+};
+
+/// Any failure of the library, for callers which only need to know which
+/// layer it came from.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Comm(#[from] CommError),
+
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[cfg(feature = "control-socket")]
+    #[error(transparent)]
+    Control(#[from] ControlError),
+
+    /// Failed to ask the user to confirm something on the terminal.
+    #[error("Failed to read the answer. Error: {0}")]
+    Prompt(io::Error),
+}
+
+/// Failing to talk to a device over its serial port.
+#[derive(Error, Debug)]
+pub enum CommError {
+    #[error("No connected devices found.")]
+    NoDevice,
+
+    #[error("Couldn't identify the device on the port.")]
+    UnknownDevice,
+
+    #[error("Failed to open or query the port. Error: {0}")]
+    Port(#[from] serialport::Error),
+
+    #[error("Failed to read or write the port. Error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to encode packet. Error: {0}")]
+    Encode(FrameError),
+
+    /// The device didn't reply with what was asked for, e.g. `its sensors`,
+    /// in time.
+    #[error("Device didn't report {0} within {1:?}. Is the firmware up to date?")]
+    NoReply(&'static str, Duration),
+
+    #[error("Link qualification failed.")]
+    LinkQualification,
+
+    #[error("Device reported invalid sensor data. Error: {0}")]
+    SensorData(#[from] ClientSensorDataError),
+
+    #[error("Failed to convert control frame to a packet. Error: {0}")]
+    ControlFrame(#[from] ControlEventError),
+
+    /// Nothing is listening for what was to be sent, e.g. `packets`.
+    #[error("Nothing is listening for {0}.")]
+    Closed(&'static str),
+}
+
+/// The configuration, or state persisted alongside it, being unusable.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Controls(#[from] ControlConfigError),
+
+    #[cfg(feature = "scripting")]
+    #[error(transparent)]
+    Script(#[from] LoadScriptError),
+
+    #[error("{0}")]
+    Registry(#[from] DeviceRegistryError),
+
+    #[error("{0}")]
+    Alarms(#[from] AlarmLogError),
+
+    /// `check` found this many problems.
+    #[error("The configuration has {0} problems.")]
+    Problems(usize),
+}
+
+#[cfg(feature = "control-socket")]
+/// Failing to ask the running control system something over its control
+/// socket.
+#[derive(Error, Debug)]
+pub enum ControlError {
+    #[error("Failed to reach the control system. Error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to encode or decode a control message. Error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Control system closed the connection without answering.")]
+    NoAnswer,
+
+    #[error(
+        "Not allowed to do that without {required} permission. Set {} or run as a permitted user.",
+        CONTROL_TOKEN_ENV_VAR
+    )]
+    Forbidden { required: Permission },
+
+    /// The control system answered with an error.
+    #[error("{0}")]
+    Rejected(String),
+
+    #[error("Unexpected response: {0:?}")]
+    UnexpectedResponse(Box<ControlResponse>),
+}
+
+#[cfg(feature = "control-socket")]
+impl ControlError {
+    /// The error for a response the caller can't handle.
+    pub fn from_response(response: ControlResponse) -> Self {
+        match response {
+            ControlResponse::Forbidden { required } => ControlError::Forbidden { required },
+            ControlResponse::Error(e) => ControlError::Rejected(e),
+            response => ControlError::UnexpectedResponse(Box::new(response)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "control-socket"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_error_from_response() {
+        let error = ControlError::from_response(ControlResponse::Forbidden {
+            required: Permission::Control,
+        });
+        assert_eq!(
+            error.to_string(),
+            "Not allowed to do that without control permission. Set PRANDTL_CONTROL_TOKEN or run as a permitted user."
+        );
+        assert!(matches!(
+            ControlError::from_response(ControlResponse::Error("Unknown device.".to_string())),
+            ControlError::Rejected(e) if e == "Unknown device."
+        ));
+        assert!(matches!(
+            Error::from(ControlError::NoAnswer),
+            Error::Control(ControlError::NoAnswer)
+        ));
+    }
+}
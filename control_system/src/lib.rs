@@ -0,0 +1,10 @@
+//! The host side of the control system. The `control_system` binary runs it
+//! with `runtime::run`, which programs embedding it can call the same way,
+//! observing and steering it through an `models::event_bus::EventBus`. Failures
+//! are reported as the typed errors of `error`.
+
+pub mod controls;
+pub mod error;
+pub mod models;
+pub mod runtime;
+pub mod tasks;
@@ -1,24 +1,234 @@
-pub mod models;
-pub mod tasks;
+#[cfg(feature = "control-socket")]
+use std::path::PathBuf;
 
-pub mod controls;
-
-use anyhow::Result;
-use tasks::control_system::task_core_system;
-use tasks::host_sensors::{
-    services::HostCpuTemperatureServiceActual, task::task_poll_host_sensors,
+use anyhow::{bail, Result};
+#[cfg(feature = "control-socket")]
+use control_system::models::alarm::ALARMS_PATH;
+#[cfg(feature = "control-socket")]
+use control_system::models::control_socket::{control_socket_path_from_env, parse_alarms_args};
+#[cfg(feature = "control-socket")]
+use control_system::models::control_trace::parse_explain_args;
+#[cfg(feature = "control-socket")]
+use control_system::models::curve_preview::parse_curve_args;
+#[cfg(feature = "recording")]
+use control_system::models::device_registry::DEVICE_REGISTRY_PATH;
+#[cfg(feature = "recording")]
+use control_system::models::diagnostics::{parse_bundle_args, DiagnosticBundle};
+use control_system::models::event_bus::EventBus;
+#[cfg(feature = "simulate")]
+use control_system::models::fault_simulation::parse_simulate_fault_args;
+use control_system::models::firmware_config::parse_config_args;
+use control_system::models::injection::parse_send_args;
+use control_system::models::latency::parse_ping_args;
+use control_system::models::link_qualification::parse_echo_args;
+#[cfg(feature = "http")]
+use control_system::models::metrics::parse_metrics_args;
+use control_system::models::parameter_sync::parse_params_args;
+use control_system::models::reboot::parse_reboot_args;
+use control_system::models::sensor_status::parse_status_args;
+use control_system::models::startup_check::parse_check_args;
+#[cfg(feature = "control-socket")]
+use control_system::models::telemetry_rate::parse_telemetry_rate_args;
+#[cfg(feature = "recording")]
+use control_system::models::telemetry_store::JournalBackend;
+#[cfg(feature = "control-socket")]
+use control_system::models::temperature::TemperatureUnit;
+use control_system::runtime::run;
+#[cfg(feature = "recording")]
+use control_system::tasks::alarms::handle_alarm_history_command;
+#[cfg(feature = "control-socket")]
+use control_system::tasks::alarms::handle_alarms_command;
+use control_system::tasks::client_sensors::injection::{
+    configure_device, enter_bootloader, ping_device, qualify_link, reboot_device, report_raw_adc,
+    report_sensor_status, send_packet_to_device, sync_parameters,
 };
-use tokio::{signal, sync::broadcast};
-use tokio_util::{sync::CancellationToken, task::TaskTracker};
+#[cfg(feature = "control-socket")]
+use control_system::tasks::curve_preview::handle_curve_command;
+#[cfg(feature = "recording")]
+use control_system::tasks::diagnostics::{request_daemon_config, request_device_logs};
+#[cfg(feature = "control-socket")]
+use control_system::tasks::explain::handle_explain_command;
+#[cfg(feature = "simulate")]
+use control_system::tasks::fault_simulation::handle_simulate_fault_command;
+#[cfg(feature = "http")]
+use control_system::tasks::metrics::handle_grafana_dashboard_command;
+use control_system::tasks::startup_check::handle_check_command;
+#[cfg(feature = "control-socket")]
+use control_system::tasks::state_machines::handle_state_machines_command;
+#[cfg(feature = "control-socket")]
+use control_system::tasks::telemetry_rate::handle_telemetry_rate_command;
+use tokio::signal;
+use tokio_util::sync::CancellationToken;
 use tracing::level_filters::LevelFilter;
 
-use crate::tasks::client_sensors::task::{
-    task_handle_client_communication, task_lifetime_management_of_client_communication_task,
-    task_process_client_sensor_packets, task_send_control_frames_to_client,
-};
+/// Printed for `help`, and alongside the error when the arguments don't name
+/// a command.
+const USAGE: &str = "\
+Usage: control_system [run]
+       control_system <COMMAND> [OPTIONS]
+
+Runs the control system when given no command, or `run`.
+
+Commands:
+  alarms [ack <ID> | ack all | history]   List or acknowledge latched alarms
+  bootloader                              Reset a device into its bootloader
+  check                                   Validate the configuration
+  config                                  Read or write the firmware's configuration
+  curve <duty|valve> <degC>:<value>...    Preview a curve
+  diag bundle                             Write a diagnostic bundle for bug reports
+  echo                                    Qualify the link to a device
+  explain [SERIAL]                        Explain the latest control frames
+  metrics grafana-dashboard               Print a Grafana dashboard for the metrics
+  params <diff|push|pull>                 Compare or sync settings with a device
+  ping                                    Measure the round trip time to a device
+  rawadc                                  Print a device's raw sense readings
+  reboot                                  Reset a device
+  send --packet <JSON>                    Send a single packet to a device
+  simulate-fault <FAULT|clear> [SERIAL]   Simulate a plant fault
+  states [SERIAL]                         Export the control state machines
+  status                                  Print a device's sensor report
+  telemetry-rate <HZ> <SERIAL>            Set a device's telemetry rate
+  help                                    Print this message
+
+See the README for each command's options.";
+
+/// Commands left out of this build, with the feature they need. Matched
+/// before the commands which are built, so `alarms history` isn't taken for
+/// an `alarms` command.
+const MISSING_COMMANDS: &[(&str, &str)] = &[
+    #[cfg(not(feature = "recording"))]
+    ("alarms history", "recording"),
+    #[cfg(not(feature = "recording"))]
+    ("diag", "recording"),
+    #[cfg(not(feature = "control-socket"))]
+    ("alarms", "control-socket"),
+    #[cfg(not(feature = "control-socket"))]
+    ("curve", "control-socket"),
+    #[cfg(not(feature = "control-socket"))]
+    ("explain", "control-socket"),
+    #[cfg(not(feature = "control-socket"))]
+    ("states", "control-socket"),
+    #[cfg(not(feature = "control-socket"))]
+    ("telemetry-rate", "control-socket"),
+    #[cfg(not(feature = "simulate"))]
+    ("simulate-fault", "simulate"),
+    #[cfg(not(feature = "http"))]
+    ("metrics", "http"),
+];
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some((command, feature)) = MISSING_COMMANDS.iter().find(|(command, _)| {
+        let words: Vec<&str> = command.split(' ').collect();
+        args.len() >= words.len() && words.iter().zip(&args).all(|(word, arg)| word == arg)
+    }) {
+        bail!(
+            "'{}' isn't available, the control system was built without the `{}` feature.",
+            command,
+            feature
+        );
+    }
+    #[cfg(feature = "recording")]
+    if let [command, subcommand, options @ ..] = args.as_slice() {
+        if command == "diag" && subcommand == "bundle" {
+            return write_diagnostic_bundle(options);
+        }
+        if command == "alarms" && subcommand == "history" && options.is_empty() {
+            let journal = JournalBackend::from_env().open()?;
+            return Ok(handle_alarm_history_command(journal.as_ref())?);
+        }
+    }
+    if let [command, options @ ..] = args.as_slice() {
+        if command == "send" {
+            return Ok(send_packet_to_device(&parse_send_args(options)?)?);
+        }
+        if command == "ping" {
+            return Ok(ping_device(&parse_ping_args(options)?)?);
+        }
+        if command == "echo" {
+            return Ok(qualify_link(&parse_echo_args(options)?)?);
+        }
+        if command == "params" {
+            return Ok(sync_parameters(&parse_params_args(options)?)?);
+        }
+        if command == "status" {
+            return Ok(report_sensor_status(&parse_status_args(options)?)?);
+        }
+        if command == "rawadc" {
+            return Ok(report_raw_adc(&parse_status_args(options)?)?);
+        }
+        if command == "reboot" {
+            return Ok(reboot_device(&parse_reboot_args(options)?)?);
+        }
+        if command == "bootloader" {
+            return Ok(enter_bootloader(&parse_reboot_args(options)?)?);
+        }
+        if command == "config" {
+            return Ok(configure_device(&parse_config_args(options)?)?);
+        }
+        if command == "check" {
+            return Ok(handle_check_command(&parse_check_args(options)?)?);
+        }
+        #[cfg(feature = "control-socket")]
+        if command == "alarms" {
+            return Ok(handle_alarms_command(
+                &control_socket_path_from_env(),
+                &PathBuf::from(ALARMS_PATH),
+                parse_alarms_args(options)?,
+            )?);
+        }
+        #[cfg(feature = "control-socket")]
+        if command == "curve" {
+            return Ok(handle_curve_command(
+                &control_socket_path_from_env(),
+                parse_curve_args(options, TemperatureUnit::from_env())?,
+            )?);
+        }
+        #[cfg(feature = "control-socket")]
+        if command == "explain" {
+            return Ok(handle_explain_command(
+                &control_socket_path_from_env(),
+                parse_explain_args(options)?,
+            )?);
+        }
+        #[cfg(feature = "control-socket")]
+        if command == "states" {
+            return Ok(handle_state_machines_command(
+                &control_socket_path_from_env(),
+                parse_explain_args(options)?,
+            )?);
+        }
+        #[cfg(feature = "simulate")]
+        if command == "simulate-fault" {
+            return Ok(handle_simulate_fault_command(
+                &control_socket_path_from_env(),
+                parse_simulate_fault_args(options)?,
+            )?);
+        }
+        #[cfg(feature = "http")]
+        if command == "metrics" {
+            parse_metrics_args(options)?;
+            return Ok(handle_grafana_dashboard_command()?);
+        }
+        #[cfg(feature = "control-socket")]
+        if command == "telemetry-rate" {
+            return Ok(handle_telemetry_rate_command(
+                &control_socket_path_from_env(),
+                parse_telemetry_rate_args(options)?,
+            )?);
+        }
+    }
+    match args.as_slice() {
+        [] => {}
+        [command] if command == "run" => {}
+        [command] if command == "help" || command == "--help" || command == "-h" => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        _ => bail!("Unknown command '{}'.\n\n{}", args.join(" "), USAGE),
+    }
+
     let subscriber = tracing_subscriber::fmt()
         .compact()
         .with_file(true)
@@ -29,94 +239,45 @@ async fn main() -> Result<()> {
         .finish();
 
     tracing::subscriber::set_global_default(subscriber)?;
-    let tracker = TaskTracker::new();
-
     let token = CancellationToken::new();
-
-    let (tx_client_sensor_data, rx_client_sensor_data) = broadcast::channel(32);
-    let (tx_host_sensor_data, rx_host_sensor_data) = broadcast::channel(32);
-    let (tx_control_frame, rx_control_frame) = broadcast::channel(32);
-
-    // NOTE: Used to handle packets received from embedded hardware.
-    let (tx_packets_from_hw, rx_packets_from_hw) = broadcast::channel(32);
-
-    // NOTE: Used to handle packets to be sent to embedded hardware.
-    let (tx_send_packets_to_hw, rx_send_packets_to_hw) = broadcast::channel(32);
-
     let token_clone = token.clone();
-    let tx_control_frame_clone = tx_control_frame.clone();
-    tracker.spawn(async {
-        task_core_system(
-            token_clone,
-            rx_client_sensor_data,
-            rx_host_sensor_data,
-            tx_control_frame_clone,
-        )
-        .await
+    tokio::spawn(async move {
+        if let Err(e) = signal::ctrl_c().await {
+            tracing::error!("Failed to listen for ctrl_c. Error: {}", e);
+        }
+        token_clone.cancel();
     });
 
-    let token_clone = token.clone();
-    let host_cpu_service = HostCpuTemperatureServiceActual;
-    tracker.spawn(async move {
-        task_poll_host_sensors(token_clone, &host_cpu_service, tx_host_sensor_data).await
-    });
-
-    let token_clone = token.clone();
-    let tx_send_packets_to_hw_clone = tx_send_packets_to_hw.clone();
-    tracker.spawn(async {
-        task_lifetime_management_of_client_communication_task(
-            token_clone,
-            tx_packets_from_hw,
-            tx_send_packets_to_hw_clone,
-        )
-        .await;
-    });
-
-    let token_clone = token.clone();
-    let tx_client_sensor_data_clone = tx_client_sensor_data.clone();
-    tracker.spawn(async {
-        task_process_client_sensor_packets(
-            token_clone,
-            tx_client_sensor_data_clone,
-            rx_packets_from_hw,
-        )
-        .await
-    });
-
-    let token_clone = token.clone();
-    let tx_control_frame_clone = tx_control_frame.clone();
-    let rx_control_frame_clone = tx_control_frame_clone.subscribe();
-    tracker.spawn(async {
-        task_send_control_frames_to_client(
-            token_clone,
-            rx_control_frame_clone,
-            tx_send_packets_to_hw,
-        )
-        .await
-    });
+    run(EventBus::new(), token).await;
 
-    let token_clone = token.clone();
-    let tx_client_sensor_data_clone = tx_client_sensor_data.clone();
+    Ok(())
+}
 
-    let token_clone = token.clone();
+/// Handle `diag bundle`: collect the running control system's config and
+/// device logs, the device registry, link stats and recent telemetry into a
+/// single archive to attach to bug reports.
+#[cfg(feature = "recording")]
+fn write_diagnostic_bundle(args: &[String]) -> Result<()> {
+    let options = parse_bundle_args(args)?;
+    let journal = JournalBackend::from_env().open()?;
+    let socket_path = control_socket_path_from_env();
+    let bundle = DiagnosticBundle::collect(
+        &options,
+        std::time::SystemTime::now(),
+        request_daemon_config(&socket_path),
+        request_device_logs(&socket_path),
+        &PathBuf::from(DEVICE_REGISTRY_PATH),
+        journal.as_ref(),
+    );
+    let path = options
+        .output
+        .clone()
+        .unwrap_or_else(|| bundle.default_file_name());
+    bundle.write(&path)?;
 
-    tokio::select! {
-        _ = token_clone.cancelled() => {}
-        res = signal::ctrl_c() => {
-            match res {
-                Ok(_) => {
-                    token.cancel();
-                },
-                Err(e)=>{
-                    tracing::error!("Failed to listen for ctrl_c. Error: {}", e);
-                    token.cancel();
-                }
-            };
-        },
+    println!("Wrote diagnostic bundle to {}.", path.display());
+    for problem in &bundle.problems {
+        println!("Note: {}", problem);
     }
-
-    tracker.close();
-    tracker.wait().await;
-
     Ok(())
 }
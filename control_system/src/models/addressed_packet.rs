@@ -0,0 +1,100 @@
+use common::packet::Packet;
+
+use super::{
+    control_event::{ControlEvent, ControlEventError},
+    device_id::DeviceId,
+};
+
+/// A packet along with the device it was received from or should be sent to.
+/// The communication layer uses `device` to route packets to the right port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressedPacket {
+    pub device: DeviceId,
+    pub packet: Packet,
+
+    /// Sequence number the communication task assigned to the received packet
+    /// this packet is, or was derived from. Recorded on tracing spans as
+    /// `sequence` so one control decision can be followed across tasks.
+    /// `None` for packets which didn't originate from a received packet.
+    pub sequence: Option<u64>,
+
+    /// Generation of the control event this packet was made from. `None` for
+    /// packets which aren't control frames. See `ControlEvent::generation`.
+    pub generation: Option<u64>,
+}
+
+impl AddressedPacket {
+    pub fn new(device: DeviceId, packet: Packet) -> Self {
+        Self {
+            device,
+            packet,
+            sequence: None,
+            generation: None,
+        }
+    }
+
+    /// Tag the packet with the sequence number of a received packet.
+    pub fn with_sequence(self, sequence: u64) -> Self {
+        Self {
+            sequence: Some(sequence),
+            ..self
+        }
+    }
+}
+
+impl AddressedPacket {
+    /// Map a control event onto its control targets and its valve command,
+    /// both addressed to the event's device.
+    pub fn from_control_event(value: ControlEvent) -> Result<[Self; 2], ControlEventError> {
+        let targets = AddressedPacket::try_from(value)?;
+        let valve = AddressedPacket {
+            packet: value.valve_command(),
+            ..targets.clone()
+        };
+        Ok([targets, valve])
+    }
+}
+
+impl TryFrom<ControlEvent> for AddressedPacket {
+    type Error = ControlEventError;
+
+    /// Map a control event onto a packet addressed to the event's device.
+    fn try_from(value: ControlEvent) -> Result<Self, Self::Error> {
+        Ok(AddressedPacket {
+            device: value.device,
+            packet: Packet::try_from(value)?,
+            sequence: value.sequence,
+            generation: Some(value.generation),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::physical::ValveState;
+
+    use super::*;
+
+    #[test]
+    fn test_control_event_is_addressed_to_its_device() {
+        let device = DeviceId::new("1324");
+        let mut event = ControlEvent::new(device, 25f32, 50f32, ValveState::Open)
+            .expect("Failed to get ControlEvent.");
+        event.sequence = Some(7);
+
+        let addressed = AddressedPacket::try_from(event).expect("Failed to get AddressedPacket.");
+        assert_eq!(addressed.device, device);
+        assert_eq!(addressed.sequence, Some(7));
+        assert_eq!(
+            addressed.packet,
+            Packet::try_from(event).expect("Failed to get Packet.")
+        );
+
+        let [targets, valve] =
+            AddressedPacket::from_control_event(event).expect("Failed to get AddressedPacket.");
+        assert_eq!(targets, addressed);
+        assert_eq!(valve.device, device);
+        assert_eq!(valve.generation, addressed.generation);
+        assert_eq!(valve.packet, event.valve_command());
+    }
+}
@@ -0,0 +1,623 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use common::packet::{FirmwareError, ResetCause};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::device_id::DeviceId;
+
+/// Default location of the persisted latched alarms.
+pub const ALARMS_PATH: &str = "prandtl_alarms.json";
+
+/// Represents a fault worth an operator's attention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlarmKind {
+    /// The pump speed isn't converging toward its commanded speed, such as a
+    /// stalled pump or frozen PWM output.
+    PumpNotTakingEffect,
+
+    /// The fan speed isn't converging toward its commanded speed.
+    FanNotTakingEffect,
+
+    /// The embedded hardware reported its supply voltage drooping.
+    SupplyVoltageLow,
+
+    /// The embedded hardware was reset by its watchdog, typically because
+    /// the firmware hung.
+    WatchdogReset,
+
+    /// The embedded hardware was reset by a brown-out.
+    BrownOutReset,
+
+    /// The valve sense pins have disagreed for too long, likely a wiring
+    /// fault. The valve is assumed open and commands to it are held.
+    ValveStateUnknown,
+
+    /// The embedded hardware failed to read its speed sense inputs or valve
+    /// sense pins, so it skipped a sensor report.
+    SensorReadFailure,
+
+    /// The pump has slowly lost speed at the same duty over days, an early
+    /// sign of a clogging loop or worn bearings. A maintenance advisory.
+    PumpSpeedDrifting,
+
+    /// The fan has slowly lost speed at the same duty over days, such as from
+    /// dust build-up or worn bearings. A maintenance advisory.
+    FanSpeedDrifting,
+
+    /// The coolant flow dropped too low, such as from a blocked loop or a
+    /// dry pump. The valve is held open and the fan run at full until it
+    /// recovers.
+    LowFlow,
+}
+
+/// Whether the condition behind an alarm is present or has cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlarmCondition {
+    pub device: DeviceId,
+    pub kind: AlarmKind,
+    pub present: bool,
+}
+
+/// A single alarm. Alarms latch: they stay listed after their condition
+/// clears until someone acknowledges them, so faults which come and go while
+/// nobody is watching aren't missed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alarm {
+    pub id: u64,
+    pub device: DeviceId,
+    pub kind: AlarmKind,
+
+    /// Milliseconds since the unix epoch when the condition appeared.
+    pub raised_at_ms: u64,
+
+    /// Milliseconds since the unix epoch when the condition cleared.
+    pub cleared_at_ms: Option<u64>,
+
+    /// Milliseconds since the unix epoch when the alarm was acknowledged.
+    pub acknowledged_at_ms: Option<u64>,
+}
+
+/// Every alarm which is still active or not yet acknowledged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlarmLog {
+    next_id: u64,
+    alarms: Vec<Alarm>,
+}
+
+/// How often a kind of alarm has occurred on a device. Kept for good rather
+/// than pruned with the journal, so intermittent faults can be counted over
+/// weeks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlarmOccurrences {
+    pub device: DeviceId,
+    pub kind: AlarmKind,
+
+    /// Milliseconds since the unix epoch when it was first raised.
+    pub first_seen_ms: u64,
+
+    /// Milliseconds since the unix epoch when its condition was last known
+    /// to be present.
+    pub last_seen_ms: u64,
+
+    /// How many times it was raised.
+    pub count: u64,
+}
+
+/// The occurrences of every alarm which has ever been raised, in the order
+/// they were first raised.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlarmHistory {
+    occurrences: Vec<AlarmOccurrences>,
+}
+
+/// What the alarm journaled after a change says about its condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmSighting {
+    /// The alarm was raised.
+    Occurred { at_ms: u64 },
+
+    /// The condition was present until it cleared.
+    Cleared { at_ms: u64 },
+}
+
+/// An alarm log shared between the task raising alarms and the control socket
+/// acknowledging them.
+/// NOTE: Never hold the lock across an `await`.
+pub type SharedAlarmLog = Arc<Mutex<AlarmLog>>;
+
+#[derive(Error, Debug)]
+pub enum AlarmLogError {
+    /// This occurs if the alarms file can't be read or written.
+    #[error("Failed to access alarms file.")]
+    Io(io::Error),
+
+    /// This occurs if the alarms file contents are invalid.
+    #[error("Failed to parse alarms.")]
+    Parse(serde_json::Error),
+}
+
+impl Alarm {
+    /// Check whether the condition behind the alarm is still present.
+    pub fn is_active(&self) -> bool {
+        self.cleared_at_ms.is_none()
+    }
+
+    /// Check whether the alarm has been acknowledged.
+    pub fn is_acknowledged(&self) -> bool {
+        self.acknowledged_at_ms.is_some()
+    }
+
+    /// Get what this alarm, as journaled after a change, says about its
+    /// condition. `None` for acknowledging an active alarm.
+    pub fn sighting(&self) -> Option<AlarmSighting> {
+        match (self.cleared_at_ms, self.acknowledged_at_ms) {
+            (None, None) => Some(AlarmSighting::Occurred {
+                at_ms: self.raised_at_ms,
+            }),
+            (None, Some(_)) => None,
+            // NOTE: Momentary faults are latched already cleared.
+            (Some(cleared_at_ms), None) if cleared_at_ms == self.raised_at_ms => {
+                Some(AlarmSighting::Occurred {
+                    at_ms: self.raised_at_ms,
+                })
+            }
+            (Some(cleared_at_ms), _) => Some(AlarmSighting::Cleared {
+                at_ms: cleared_at_ms,
+            }),
+        }
+    }
+
+    /// Describe the alarm with its times relative to `now_ms`, e.g.
+    /// `#3 1324 pump not taking effect, raised 7h 12m ago, cleared 7h 11m ago`.
+    pub fn describe(&self, now_ms: u64) -> String {
+        let age = |at_ms: u64| format_age(Duration::from_millis(now_ms.saturating_sub(at_ms)));
+        let mut description = format!(
+            "#{} {} {}, raised {} ago",
+            self.id,
+            self.device.as_str(),
+            self.kind,
+            age(self.raised_at_ms)
+        );
+        match self.cleared_at_ms {
+            Some(cleared_at_ms) => {
+                description.push_str(&format!(", cleared {} ago", age(cleared_at_ms)))
+            }
+            None => description.push_str(", still active"),
+        }
+        if let Some(acknowledged_at_ms) = self.acknowledged_at_ms {
+            description.push_str(&format!(", acknowledged {} ago", age(acknowledged_at_ms)));
+        }
+        description
+    }
+}
+
+impl AlarmLog {
+    /// Load the alarms from `path`. A missing file results in an empty log.
+    pub fn load(path: &Path) -> Result<Self, AlarmLogError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(AlarmLogError::Io(e)),
+        };
+        serde_json::from_str(&contents).map_err(AlarmLogError::Parse)
+    }
+
+    /// Persist the alarms to `path`, replacing any previous contents.
+    pub fn save(&self, path: &Path) -> Result<(), AlarmLogError> {
+        let contents = serde_json::to_string_pretty(self).map_err(AlarmLogError::Parse)?;
+        fs::write(path, contents).map_err(AlarmLogError::Io)
+    }
+
+    /// Apply a change in a condition. Returns the alarm if it changed.
+    pub fn update(&mut self, condition: AlarmCondition, now_ms: u64) -> Option<Alarm> {
+        if condition.present {
+            self.raise(condition.device, condition.kind, now_ms)
+        } else {
+            self.clear(condition.device, condition.kind, now_ms)
+        }
+    }
+
+    /// Raise an alarm. Returns `None` if the same alarm is already active.
+    pub fn raise(&mut self, device: DeviceId, kind: AlarmKind, now_ms: u64) -> Option<Alarm> {
+        if self.active_mut(device, kind).is_some() {
+            return None;
+        }
+        let alarm = Alarm {
+            id: self.next_id,
+            device,
+            kind,
+            raised_at_ms: now_ms,
+            cleared_at_ms: None,
+            acknowledged_at_ms: None,
+        };
+        self.next_id += 1;
+        self.alarms.push(alarm);
+        Some(alarm)
+    }
+
+    /// Latch an alarm for a momentary fault, such as one the firmware reports
+    /// once, by raising and immediately clearing it. Returns `None` if the same
+    /// alarm is already latched so a recurring fault doesn't flood the log.
+    pub fn occur(&mut self, device: DeviceId, kind: AlarmKind, now_ms: u64) -> Option<Alarm> {
+        if self
+            .alarms
+            .iter()
+            .any(|alarm| alarm.device == device && alarm.kind == kind && !alarm.is_acknowledged())
+        {
+            return None;
+        }
+        self.raise(device, kind, now_ms)?;
+        self.clear(device, kind, now_ms)
+    }
+
+    /// Clear an active alarm. It stays latched until acknowledged, unless it
+    /// already was. Returns `None` if the alarm wasn't active.
+    pub fn clear(&mut self, device: DeviceId, kind: AlarmKind, now_ms: u64) -> Option<Alarm> {
+        let alarm = self.active_mut(device, kind)?;
+        alarm.cleared_at_ms = Some(now_ms);
+        let alarm = *alarm;
+        self.forget_resolved();
+        Some(alarm)
+    }
+
+    /// Clear every active alarm, such as those persisted by a previous run
+    /// whose conditions can no longer be confirmed. Returns the cleared alarms.
+    pub fn clear_all(&mut self, now_ms: u64) -> Vec<Alarm> {
+        let cleared = self
+            .alarms
+            .iter_mut()
+            .filter(|alarm| alarm.is_active())
+            .map(|alarm| {
+                alarm.cleared_at_ms = Some(now_ms);
+                *alarm
+            })
+            .collect();
+        self.forget_resolved();
+        cleared
+    }
+
+    /// Acknowledge a single alarm. An acknowledged alarm which is still active
+    /// stays listed until it clears. Returns `None` if there is no such alarm
+    /// or it was already acknowledged.
+    pub fn acknowledge(&mut self, id: u64, now_ms: u64) -> Option<Alarm> {
+        let alarm = self
+            .alarms
+            .iter_mut()
+            .find(|alarm| alarm.id == id && !alarm.is_acknowledged())?;
+        alarm.acknowledged_at_ms = Some(now_ms);
+        let alarm = *alarm;
+        self.forget_resolved();
+        Some(alarm)
+    }
+
+    /// Acknowledge every alarm. Returns the alarms which were acknowledged.
+    pub fn acknowledge_all(&mut self, now_ms: u64) -> Vec<Alarm> {
+        let acknowledged = self
+            .alarms
+            .iter_mut()
+            .filter(|alarm| !alarm.is_acknowledged())
+            .map(|alarm| {
+                alarm.acknowledged_at_ms = Some(now_ms);
+                *alarm
+            })
+            .collect();
+        self.forget_resolved();
+        acknowledged
+    }
+
+    /// Get every alarm which is still active or not yet acknowledged, oldest
+    /// first.
+    pub fn alarms(&self) -> &[Alarm] {
+        &self.alarms
+    }
+
+    fn active_mut(&mut self, device: DeviceId, kind: AlarmKind) -> Option<&mut Alarm> {
+        self.alarms
+            .iter_mut()
+            .find(|alarm| alarm.device == device && alarm.kind == kind && alarm.is_active())
+    }
+
+    /// Drop alarms which have both cleared and been acknowledged.
+    fn forget_resolved(&mut self) {
+        self.alarms
+            .retain(|alarm| alarm.is_active() || !alarm.is_acknowledged());
+    }
+}
+
+impl AlarmOccurrences {
+    /// Describe the occurrences with their times relative to `now_ms`, e.g.
+    /// `1324 valve state unknown, raised 14 times, first 20d 3h ago, last 7h 12m ago`.
+    pub fn describe(&self, now_ms: u64) -> String {
+        let age = |at_ms: u64| format_age(Duration::from_millis(now_ms.saturating_sub(at_ms)));
+        format!(
+            "{} {}, raised {} {}, first {} ago, last {} ago",
+            self.device.as_str(),
+            self.kind,
+            self.count,
+            if self.count == 1 { "time" } else { "times" },
+            age(self.first_seen_ms),
+            age(self.last_seen_ms)
+        )
+    }
+}
+
+impl AlarmHistory {
+    /// Count the alarm journaled after a change. Acknowledgements and
+    /// clearing an alarm which was never raised change nothing.
+    pub fn record(&mut self, alarm: &Alarm) {
+        let existing = self.occurrences.iter_mut().find(|occurrences| {
+            occurrences.device == alarm.device && occurrences.kind == alarm.kind
+        });
+        match (alarm.sighting(), existing) {
+            (Some(AlarmSighting::Occurred { at_ms }), Some(occurrences)) => {
+                occurrences.first_seen_ms = occurrences.first_seen_ms.min(at_ms);
+                occurrences.last_seen_ms = occurrences.last_seen_ms.max(at_ms);
+                occurrences.count += 1;
+            }
+            (Some(AlarmSighting::Occurred { at_ms }), None) => {
+                self.occurrences.push(AlarmOccurrences {
+                    device: alarm.device,
+                    kind: alarm.kind,
+                    first_seen_ms: at_ms,
+                    last_seen_ms: at_ms,
+                    count: 1,
+                })
+            }
+            (Some(AlarmSighting::Cleared { at_ms }), Some(occurrences)) => {
+                occurrences.last_seen_ms = occurrences.last_seen_ms.max(at_ms);
+            }
+            (Some(AlarmSighting::Cleared { .. }) | None, _) => {}
+        }
+    }
+
+    /// Get the occurrences of every alarm ever raised.
+    pub fn occurrences(&self) -> &[AlarmOccurrences] {
+        &self.occurrences
+    }
+}
+
+/// Format a duration as days and hours, hours and minutes, or seconds if
+/// under a minute.
+fn format_age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    let hours = minutes / 60;
+    match (hours / 24, hours % 24, minutes % 60) {
+        (0, 0, 0) => format!("{}s", age.as_secs()),
+        (0, 0, minutes) => format!("{}m", minutes),
+        (0, hours, minutes) => format!("{}h {}m", hours, minutes),
+        (days, hours, _) => format!("{}d {}h", days, hours),
+    }
+}
+
+impl AlarmKind {
+    /// Get the alarm for an error the embedded hardware reported, if it
+    /// points to a fault. Dropped packets and frames point to a poor link
+    /// rather than a fault, so they are only logged.
+    pub fn from_firmware_error(error: FirmwareError) -> Option<Self> {
+        match error {
+            FirmwareError::SupplyVoltageLow { .. } => Some(AlarmKind::SupplyVoltageLow),
+            FirmwareError::AdcFailure | FirmwareError::ValveReadFailure => {
+                Some(AlarmKind::SensorReadFailure)
+            }
+            FirmwareError::QueueOverflow { .. } | FirmwareError::DecodeFailure { .. } => None,
+        }
+    }
+
+    /// Get the alarm for a reset cause, if it points to a fault.
+    pub fn from_reset_cause(reset_cause: ResetCause) -> Option<Self> {
+        match reset_cause {
+            ResetCause::Watchdog => Some(AlarmKind::WatchdogReset),
+            ResetCause::BrownOut => Some(AlarmKind::BrownOutReset),
+            _ => None,
+        }
+    }
+}
+
+impl Display for AlarmKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlarmKind::PumpNotTakingEffect => write!(f, "pump not taking effect"),
+            AlarmKind::FanNotTakingEffect => write!(f, "fan not taking effect"),
+            AlarmKind::SupplyVoltageLow => write!(f, "supply voltage low"),
+            AlarmKind::WatchdogReset => write!(f, "watchdog reset"),
+            AlarmKind::BrownOutReset => write!(f, "brown-out reset"),
+            AlarmKind::ValveStateUnknown => write!(f, "valve state unknown"),
+            AlarmKind::SensorReadFailure => write!(f, "sensor read failure"),
+            AlarmKind::PumpSpeedDrifting => write!(f, "pump speed drifting down"),
+            AlarmKind::FanSpeedDrifting => write!(f, "fan speed drifting down"),
+            AlarmKind::LowFlow => write!(f, "coolant flow low"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUMP: AlarmKind = AlarmKind::PumpNotTakingEffect;
+
+    #[test]
+    fn test_alarm_latches_until_acknowledged() {
+        let device = DeviceId::new("1324");
+        let mut log = AlarmLog::default();
+
+        let alarm = log.raise(device, PUMP, 1_000).expect("Failed to raise.");
+        assert_eq!(log.raise(device, PUMP, 2_000), None);
+
+        let cleared = log.clear(device, PUMP, 3_000).expect("Failed to clear.");
+        assert_eq!(cleared.cleared_at_ms, Some(3_000));
+        assert_eq!(log.alarms(), &[cleared]);
+
+        let acknowledged = log.acknowledge(alarm.id, 4_000).expect("Failed to ack.");
+        assert_eq!(acknowledged.acknowledged_at_ms, Some(4_000));
+        assert!(log.alarms().is_empty());
+        assert_eq!(log.acknowledge(alarm.id, 5_000), None);
+    }
+
+    #[test]
+    fn test_acknowledged_alarm_stays_until_cleared() {
+        let device = DeviceId::new("1324");
+        let mut log = AlarmLog::default();
+
+        let alarm = log.raise(device, PUMP, 1_000).expect("Failed to raise.");
+        log.acknowledge(alarm.id, 2_000).expect("Failed to ack.");
+        assert_eq!(log.alarms().len(), 1);
+
+        // NOTE: Still active, so raising again doesn't latch a duplicate.
+        assert_eq!(log.raise(device, PUMP, 3_000), None);
+        log.clear(device, PUMP, 4_000).expect("Failed to clear.");
+        assert!(log.alarms().is_empty());
+    }
+
+    #[test]
+    fn test_recurring_alarm_latches_again() {
+        let device = DeviceId::new("1324");
+        let mut log = AlarmLog::default();
+
+        let first = log.raise(device, PUMP, 1_000).expect("Failed to raise.");
+        log.clear(device, PUMP, 2_000);
+        let second = log.raise(device, PUMP, 3_000).expect("Failed to raise.");
+        assert_ne!(first.id, second.id);
+        assert_eq!(log.alarms().len(), 2);
+
+        let fan = log
+            .raise(device, AlarmKind::FanNotTakingEffect, 4_000)
+            .expect("Failed to raise.");
+        assert_eq!(log.acknowledge_all(5_000).len(), 3);
+        assert_eq!(log.alarms().len(), 2);
+        assert!(log.alarms().iter().all(Alarm::is_active));
+        assert!(log.alarms().iter().any(|alarm| alarm.id == fan.id));
+
+        assert_eq!(log.clear_all(6_000).len(), 2);
+        assert!(log.alarms().is_empty());
+    }
+
+    #[test]
+    fn test_momentary_fault_latches_once() {
+        let device = DeviceId::new("1324");
+        let kind = AlarmKind::from_firmware_error(FirmwareError::SupplyVoltageLow {
+            supply_voltage_mv: 4400,
+        })
+        .expect("Supply voltage low should raise an alarm.");
+        let mut log = AlarmLog::default();
+
+        let alarm = log.occur(device, kind, 1_000).expect("Failed to latch.");
+        assert!(!alarm.is_active());
+        assert_eq!(log.occur(device, kind, 2_000), None);
+        assert_eq!(log.alarms(), &[alarm]);
+
+        log.acknowledge(alarm.id, 3_000);
+        assert!(log.occur(device, kind, 4_000).is_some());
+    }
+
+    #[test]
+    fn test_only_faults_raise_firmware_error_alarms() {
+        assert_eq!(
+            AlarmKind::from_firmware_error(FirmwareError::ValveReadFailure),
+            Some(AlarmKind::SensorReadFailure)
+        );
+        assert_eq!(
+            AlarmKind::from_firmware_error(FirmwareError::DecodeFailure { dropped: 3 }),
+            None
+        );
+    }
+
+    #[test]
+    fn test_only_faulty_resets_alarm() {
+        assert_eq!(
+            AlarmKind::from_reset_cause(ResetCause::Watchdog),
+            Some(AlarmKind::WatchdogReset)
+        );
+        assert_eq!(
+            AlarmKind::from_reset_cause(ResetCause::BrownOut),
+            Some(AlarmKind::BrownOutReset)
+        );
+        assert_eq!(AlarmKind::from_reset_cause(ResetCause::PowerOn), None);
+        assert_eq!(AlarmKind::from_reset_cause(ResetCause::Software), None);
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut log = AlarmLog::default();
+        log.raise(DeviceId::new("1324"), PUMP, 0);
+        let alarm = log
+            .clear(DeviceId::new("1324"), PUMP, 60_000)
+            .expect("Failed to clear.");
+
+        assert_eq!(
+            alarm.describe(3 * 60 * 60 * 1000 + 90_000),
+            "#0 1324 pump not taking effect, raised 3h 1m ago, cleared 3h 0m ago"
+        );
+        assert_eq!(format_age(Duration::from_secs(42)), "42s");
+        assert_eq!(format_age(Duration::from_secs(125)), "2m");
+        assert_eq!(format_age(Duration::from_secs(50 * 60 * 60 + 125)), "2d 2h");
+    }
+
+    #[test]
+    fn test_history_counts_occurrences() {
+        let device = DeviceId::new("1324");
+        let mut log = AlarmLog::default();
+        let mut history = AlarmHistory::default();
+        let mut record =
+            |alarm: Option<Alarm>| history.record(&alarm.expect("Alarm didn't change."));
+
+        record(log.raise(device, PUMP, 1_000));
+        // NOTE: Acknowledging while active isn't another occurrence.
+        record(log.acknowledge(0, 2_000));
+        record(log.clear(device, PUMP, 3_000));
+        record(log.raise(device, PUMP, 86_400_000));
+        record(log.occur(device, AlarmKind::BrownOutReset, 90_000_000));
+        record(log.clear(device, PUMP, 90_000_000));
+        // NOTE: Acknowledging after it cleared was already counted.
+        record(log.acknowledge(1, 95_000_000));
+
+        assert_eq!(
+            history.occurrences(),
+            &[
+                AlarmOccurrences {
+                    device,
+                    kind: PUMP,
+                    first_seen_ms: 1_000,
+                    last_seen_ms: 90_000_000,
+                    count: 2,
+                },
+                AlarmOccurrences {
+                    device,
+                    kind: AlarmKind::BrownOutReset,
+                    first_seen_ms: 90_000_000,
+                    last_seen_ms: 90_000_000,
+                    count: 1,
+                }
+            ]
+        );
+        assert_eq!(
+            history.occurrences()[0].describe(3 * 86_400_000),
+            "1324 pump not taking effect, raised 2 times, first 2d 23h ago, last 1d 23h ago"
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("prandtl_alarms_test_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            AlarmLog::load(&path).expect("Failed to load missing alarms."),
+            AlarmLog::default()
+        );
+
+        let mut log = AlarmLog::default();
+        log.raise(DeviceId::new("1324"), PUMP, 1_000);
+        log.save(&path).expect("Failed to save alarms.");
+        assert_eq!(AlarmLog::load(&path).expect("Failed to load alarms."), log);
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,180 @@
+use std::{env, time::Duration};
+
+use common::packet::Capabilities;
+use thiserror::Error;
+
+/// Environment variable used to declare a second fan channel is in use.
+pub const EXPECT_SECOND_FAN_ENV_VAR: &str = "PRANDTL_EXPECT_SECOND_FAN";
+
+/// Environment variable used to declare how many coolant thermistors are in
+/// use.
+pub const EXPECT_THERMISTORS_ENV_VAR: &str = "PRANDTL_EXPECT_THERMISTORS";
+
+/// Environment variable used to declare whether a valve is driven.
+pub const EXPECT_VALVE_ENV_VAR: &str = "PRANDTL_EXPECT_VALVE";
+
+/// Environment variable used to declare a flow meter is fitted, as how many
+/// pulses it gives per liter.
+pub const FLOW_PULSES_PER_LITER_ENV_VAR: &str = "PRANDTL_FLOW_PULSES_PER_LITER";
+
+/// Environment variable used to refuse to run devices which fail the
+/// self-check rather than degrading with warnings.
+pub const STRICT_SELF_CHECK_ENV_VAR: &str = "PRANDTL_STRICT_SELF_CHECK";
+
+/// How long a newly connected device has to report its capabilities before
+/// the self-check is given up on. Firmware which predates `DeviceInfo` never
+/// replies.
+pub const DEVICE_INFO_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What the configuration expects the embedded hardware to be wired for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardwareExpectations {
+    pub second_fan: bool,
+    pub thermistors: u8,
+    pub valve_driver: bool,
+
+    /// How many pulses per liter the flow meter gives, if one is fitted.
+    /// Configured on every device as it connects.
+    pub flow_pulses_per_liter: Option<u16>,
+
+    /// Whether a device which fails the self-check is refused. Otherwise it
+    /// is run with a warning for every mismatch.
+    pub strict: bool,
+}
+
+/// Something the configuration expects which the embedded hardware reported
+/// it can't do.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapabilityMismatch {
+    #[error("A second fan channel is expected but the firmware has {fan_channels}.")]
+    MissingSecondFan { fan_channels: u8 },
+
+    #[error("{expected} thermistors are expected but the firmware has {reported}.")]
+    MissingThermistors { expected: u8, reported: u8 },
+
+    #[error("A valve is expected but the firmware has no valve driver.")]
+    MissingValveDriver,
+}
+
+impl Default for HardwareExpectations {
+    fn default() -> Self {
+        Self {
+            second_fan: false,
+            thermistors: 0,
+            valve_driver: true,
+            flow_pulses_per_liter: None,
+            strict: false,
+        }
+    }
+}
+
+impl HardwareExpectations {
+    /// Build the expectations from the environment, falling back to the
+    /// defaults for anything unset or invalid.
+    pub fn from_env() -> Self {
+        let var = |name| env::var(name).ok();
+        let defaults = Self::default();
+        Self {
+            second_fan: parse_flag(var(EXPECT_SECOND_FAN_ENV_VAR).as_deref())
+                .unwrap_or(defaults.second_fan),
+            thermistors: var(EXPECT_THERMISTORS_ENV_VAR)
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(defaults.thermistors),
+            valve_driver: parse_flag(var(EXPECT_VALVE_ENV_VAR).as_deref())
+                .unwrap_or(defaults.valve_driver),
+            flow_pulses_per_liter: var(FLOW_PULSES_PER_LITER_ENV_VAR)
+                .and_then(|value| value.trim().parse().ok())
+                .filter(|&pulses| pulses > 0),
+            strict: parse_flag(var(STRICT_SELF_CHECK_ENV_VAR).as_deref())
+                .unwrap_or(defaults.strict),
+        }
+    }
+
+    /// Compare the expectations against the capabilities a device reported.
+    /// Returns every mismatch, so an empty list means the self-check passed.
+    pub fn check(&self, capabilities: &Capabilities) -> Vec<CapabilityMismatch> {
+        let mut mismatches = vec![];
+        if self.second_fan && capabilities.fan_channels < 2 {
+            mismatches.push(CapabilityMismatch::MissingSecondFan {
+                fan_channels: capabilities.fan_channels,
+            });
+        }
+        if capabilities.thermistors < self.thermistors {
+            mismatches.push(CapabilityMismatch::MissingThermistors {
+                expected: self.thermistors,
+                reported: capabilities.thermistors,
+            });
+        }
+        if self.valve_driver && !capabilities.valve_driver {
+            mismatches.push(CapabilityMismatch::MissingValveDriver);
+        }
+        mismatches
+    }
+}
+
+/// Parse a boolean flag. Accepts `1`/`true`/`yes` and `0`/`false`/`no` in
+/// any case. Returns `None` if it is missing or unrecognized.
+pub(crate) fn parse_flag(value: Option<&str>) -> Option<bool> {
+    match value?.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE_FAN: Capabilities = Capabilities {
+        fan_channels: 1,
+        thermistors: 0,
+        valve_driver: true,
+        valve_sense: true,
+    };
+
+    #[test]
+    fn test_parse_flag() {
+        assert_eq!(parse_flag(Some("TRUE")), Some(true));
+        assert_eq!(parse_flag(Some(" 0 ")), Some(false));
+        assert_eq!(parse_flag(Some("maybe")), None);
+        assert_eq!(parse_flag(None), None);
+    }
+
+    #[test]
+    fn test_default_expectations_pass() {
+        assert!(HardwareExpectations::default()
+            .check(&SINGLE_FAN)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_check_reports_every_mismatch() {
+        let expectations = HardwareExpectations {
+            second_fan: true,
+            thermistors: 2,
+            ..HardwareExpectations::default()
+        };
+        let capabilities = Capabilities {
+            valve_driver: false,
+            ..SINGLE_FAN
+        };
+        assert_eq!(
+            expectations.check(&capabilities),
+            vec![
+                CapabilityMismatch::MissingSecondFan { fan_channels: 1 },
+                CapabilityMismatch::MissingThermistors {
+                    expected: 2,
+                    reported: 0
+                },
+                CapabilityMismatch::MissingValveDriver,
+            ]
+        );
+
+        let expectations = HardwareExpectations {
+            valve_driver: false,
+            ..HardwareExpectations::default()
+        };
+        assert!(expectations.check(&capabilities).is_empty());
+    }
+}
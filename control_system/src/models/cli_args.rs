@@ -0,0 +1,208 @@
+use std::{fmt::Display, ops::RangeInclusive, slice::Iter, str::FromStr, time::Duration};
+
+use thiserror::Error;
+
+/// Flag choosing the port of the device a command talks to.
+pub const PORT_FLAG: &str = "--port";
+
+/// Flag setting how long a command waits for the device to reply.
+pub const TIMEOUT_FLAG: &str = "--timeout-ms";
+
+/// Failing to parse the arguments to a command.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ArgsError {
+    /// A required argument wasn't given, e.g. `'pull', 'push' or 'diff'`.
+    #[error("Expected {0}.")]
+    Missing(String),
+
+    #[error("Expected a value after '{0}'.")]
+    MissingValue(String),
+
+    /// The value given for an argument, e.g. `'--count'`, which expected
+    /// something else, e.g. `a positive number`.
+    #[error("Invalid value '{1}' for {0}, expected {2}.")]
+    InvalidValue(String, String, String),
+
+    #[error("Unknown argument '{0}'.")]
+    UnknownArgument(String),
+}
+
+/// Walks the arguments to a command, handing out the values which follow
+/// flags.
+pub struct Args<'a> {
+    args: Iter<'a, String>,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(args: &'a [String]) -> Self {
+        Self { args: args.iter() }
+    }
+
+    /// Take the value following `flag`.
+    pub fn value(&mut self, flag: &str) -> Result<&'a String, ArgsError> {
+        self.args
+            .next()
+            .ok_or_else(|| ArgsError::MissingValue(flag.to_string()))
+    }
+
+    /// Take and parse the value following `flag`, described by `expected` if
+    /// it doesn't parse or isn't `valid`.
+    pub fn parse<T: FromStr>(
+        &mut self,
+        flag: &str,
+        expected: &str,
+        valid: impl Fn(&T) -> bool,
+    ) -> Result<T, ArgsError> {
+        parse_value(&format!("'{}'", flag), self.value(flag)?, expected, valid)
+    }
+
+    /// Take the value following `flag` as a positive number, e.g. a count.
+    pub fn positive_number(&mut self, flag: &str) -> Result<u32, ArgsError> {
+        self.parse(flag, "a positive number", |&count| count > 0)
+    }
+
+    /// Take the value following `flag` as a positive number of milliseconds.
+    pub fn timeout(&mut self, flag: &str) -> Result<Duration, ArgsError> {
+        self.parse(flag, "a positive number of milliseconds", |&ms| ms > 0)
+            .map(Duration::from_millis)
+    }
+
+    /// Take the value following `flag` as a number within `range`.
+    pub fn in_range<T: FromStr + PartialOrd + Display>(
+        &mut self,
+        flag: &str,
+        range: RangeInclusive<T>,
+    ) -> Result<T, ArgsError> {
+        parse_in_range(&format!("'{}'", flag), self.value(flag)?, range)
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.args.next()
+    }
+}
+
+/// Parse `value`, given for `what`, described by `expected` if it doesn't
+/// parse or isn't `valid`.
+pub fn parse_value<T: FromStr>(
+    what: &str,
+    value: &str,
+    expected: &str,
+    valid: impl Fn(&T) -> bool,
+) -> Result<T, ArgsError> {
+    value.parse().ok().filter(valid).ok_or_else(|| {
+        ArgsError::InvalidValue(what.to_string(), value.to_string(), expected.to_string())
+    })
+}
+
+/// Parse `value`, given for `what`, as a number within `range`.
+pub fn parse_in_range<T: FromStr + PartialOrd + Display>(
+    what: &str,
+    value: &str,
+    range: RangeInclusive<T>,
+) -> Result<T, ArgsError> {
+    let expected = format!("{} to {}", range.start(), range.end());
+    parse_value(what, value, &expected, |parsed| range.contains(parsed))
+}
+
+/// Options for commands which talk to a single device over its port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceOptions {
+    /// The port of the device. Defaults to the first connected device.
+    pub port: Option<String>,
+
+    /// How long to wait for the device to reply.
+    pub timeout: Duration,
+}
+
+impl DeviceOptions {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            port: None,
+            timeout,
+        }
+    }
+
+    /// Take `--port <PATH>` or `--timeout-ms <MS>` if `arg` is either of
+    /// them. Returns whether it was.
+    pub fn parse_arg(&mut self, arg: &str, args: &mut Args) -> Result<bool, ArgsError> {
+        match arg {
+            PORT_FLAG => self.port = Some(args.value(arg)?.clone()),
+            TIMEOUT_FLAG => self.timeout = args.timeout(arg)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Build the arguments to a command, for tests.
+#[cfg(test)]
+pub fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_device_args(args: &[String]) -> Result<DeviceOptions, ArgsError> {
+        let mut options = DeviceOptions::new(Duration::from_secs(3));
+        let mut args = Args::new(args);
+        while let Some(arg) = args.next() {
+            if !options.parse_arg(arg, &mut args)? {
+                return Err(ArgsError::UnknownArgument(arg.clone()));
+            }
+        }
+        Ok(options)
+    }
+
+    #[test]
+    fn test_device_options() {
+        assert_eq!(
+            parse_device_args(&[]),
+            Ok(DeviceOptions::new(Duration::from_secs(3)))
+        );
+        assert_eq!(
+            parse_device_args(&args(&["--port", "/dev/ttyACM0", "--timeout-ms", "500"])),
+            Ok(DeviceOptions {
+                port: Some("/dev/ttyACM0".to_string()),
+                timeout: Duration::from_millis(500),
+            })
+        );
+        assert_eq!(
+            parse_device_args(&args(&["--timeout-ms", "0"])),
+            Err(ArgsError::InvalidValue(
+                "'--timeout-ms'".to_string(),
+                "0".to_string(),
+                "a positive number of milliseconds".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_device_args(&args(&["--port"])),
+            Err(ArgsError::MissingValue("--port".to_string()))
+        );
+        assert_eq!(
+            parse_device_args(&args(&["--yes"])),
+            Err(ArgsError::UnknownArgument("--yes".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_in_range() {
+        let values = args(&["--hz", "25", "--hz", "26", "--hz", "fast"]);
+        let mut args = Args::new(&values);
+        args.next();
+        assert_eq!(args.in_range("--hz", 1..=25), Ok(25));
+        args.next();
+        assert_eq!(
+            args.in_range("--hz", 1..=25).map_err(|e| e.to_string()),
+            Err("Invalid value '26' for '--hz', expected 1 to 25.".to_string())
+        );
+        args.next();
+        assert!(args.in_range("--hz", 1..=25).is_err());
+        assert_eq!(args.next(), None);
+    }
+}
@@ -1,42 +1,458 @@
 use std::fmt::Display;
 
 use common::{
-    packet::ReportSensorsPacket,
-    physical::{Rpm, ValveState},
+    packet::{ReportSensorsPacket, MAX_FAN_CHANNELS},
+    physical::{Celsius, Current, FlowRate, Rpm, ValveState},
 };
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Debug, Clone, Copy)]
+use super::{
+    device_id::DeviceId,
+    temperature::{Temperature, TemperatureUnit},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ClientSensorData {
+    /// The device these readings came from.
+    pub device: DeviceId,
     pub pump_speed: Rpm,
     pub fan_speed: Rpm,
+
+    /// Speeds of the fans after the first, in channel order, on devices with
+    /// more than one fan header. `fan_speed` is the first.
+    #[serde(default)]
+    pub extra_fan_speeds: [Option<Rpm>; MAX_FAN_CHANNELS - 1],
+
     pub valve_state: ValveState,
+
+    /// Temperature of the embedded hardware's microcontroller, if reported.
+    pub board_temperature: Option<Celsius>,
+
+    /// Temperature of the coolant, if the device has a coolant thermistor.
+    #[serde(default)]
+    pub coolant_temperature: Option<Celsius>,
+
+    /// Current drawn by the pump, if reported.
+    pub pump_current: Option<Current>,
+
+    /// Current drawn by the fan, if reported.
+    pub fan_current: Option<Current>,
+
+    /// Coolant flow rate, if the device has a flow meter.
+    #[serde(default)]
+    pub flow_rate: Option<FlowRate>,
+
+    /// Whether the coolant flow is too low to trust the loop, so the valve is
+    /// held open and the fan run at full. See `LowFlowPolicy`.
+    #[serde(default)]
+    pub low_flow: bool,
+
+    /// How trustworthy each of the readings above is.
+    pub quality: SensorQuality,
+
+    /// Sequence number of the received packet these readings came from, if
+    /// any. See `AddressedPacket::sequence`.
+    #[serde(default)]
+    pub sequence: Option<u64>,
+
+    /// When the readings were taken, in milliseconds since the unix epoch by
+    /// the host's clock, if the device's clock was synced.
+    #[serde(default)]
+    pub sampled_at_ms: Option<u64>,
 }
 
+/// Represents how trustworthy a single sensor reading is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ReadingQuality {
+    /// The reading is plausible.
+    Good,
+
+    /// The reading is pegged at the top of its range. Likely a saturated ADC
+    /// or an overspeed, so the true value may be higher.
+    Saturated,
+
+    /// The reading could not be determined.
+    /// Likely an invalid combination of hi/lo for the valve sense pins.
+    Invalid,
+
+    /// The reading couldn't be determined for so long that a fail-safe value
+    /// was assumed in its place. See `ValvePolicy`.
+    Assumed,
+}
+
+/// Represents the quality of every reading in a `ClientSensorData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SensorQuality {
+    pub pump_speed: ReadingQuality,
+    pub fan_speed: ReadingQuality,
+    pub valve_state: ReadingQuality,
+    pub board_temperature: ReadingQuality,
+}
+
+/// The operating range of the embedded hardware's microcontroller.
+/// Board temperatures outside of this range can't be trusted.
+const BOARD_TEMPERATURE_RANGE: (f32, f32) = (-40f32, 85f32);
+
 #[derive(Error, Debug)]
 pub enum ClientSensorDataError {
     #[error("Generic catch all error.")]
     Invalid,
 }
 
-impl Display for ClientSensorData {
+impl ClientSensorData {
+    /// Construct client sensor data from raw readings.
+    /// Each reading is validated and flagged in `quality`.
+    pub fn new(
+        device: DeviceId,
+        pump_speed: Rpm,
+        fan_speed: Rpm,
+        valve_state: ValveState,
+        board_temperature: Option<Celsius>,
+        pump_current: Option<Current>,
+        fan_current: Option<Current>,
+    ) -> Self {
+        let quality = SensorQuality {
+            pump_speed: validate_rpm(pump_speed),
+            fan_speed: validate_rpm(fan_speed),
+            valve_state: validate_valve_state(valve_state),
+            board_temperature: validate_board_temperature(board_temperature),
+        };
+        Self {
+            device,
+            pump_speed,
+            fan_speed,
+            extra_fan_speeds: [None; MAX_FAN_CHANNELS - 1],
+            valve_state,
+            board_temperature,
+            coolant_temperature: None,
+            pump_current,
+            fan_current,
+            flow_rate: None,
+            low_flow: false,
+            quality,
+            sequence: None,
+            sampled_at_ms: None,
+        }
+    }
+
+    /// How many fans the device reported speeds for.
+    pub fn fan_channels(&self) -> usize {
+        1 + self.extra_fan_speeds.iter().flatten().count()
+    }
+
+    /// Replace the valve state reading, such as with one inferred on the host,
+    /// and revalidate it.
+    pub fn with_valve_state(self, valve_state: ValveState) -> Self {
+        Self {
+            valve_state,
+            quality: SensorQuality {
+                valve_state: validate_valve_state(valve_state),
+                ..self.quality
+            },
+            ..self
+        }
+    }
+
+    /// Replace the valve state reading with one assumed in place of a reading
+    /// which couldn't be determined.
+    pub fn with_assumed_valve_state(self, valve_state: ValveState) -> Self {
+        Self {
+            valve_state,
+            quality: SensorQuality {
+                valve_state: ReadingQuality::Assumed,
+                ..self.quality
+            },
+            ..self
+        }
+    }
+
+    /// Replace the speed readings, such as with a fast rpm report received
+    /// between full reports, and revalidate them.
+    pub fn with_speeds(self, pump_speed: Rpm, fan_speed: Rpm) -> Self {
+        Self {
+            pump_speed,
+            fan_speed,
+            quality: SensorQuality {
+                pump_speed: validate_rpm(pump_speed),
+                fan_speed: validate_rpm(fan_speed),
+                ..self.quality
+            },
+            ..self
+        }
+    }
+}
+
+impl SensorQuality {
+    /// Every reading is trustworthy.
+    pub const GOOD: SensorQuality = SensorQuality {
+        pump_speed: ReadingQuality::Good,
+        fan_speed: ReadingQuality::Good,
+        valve_state: ReadingQuality::Good,
+        board_temperature: ReadingQuality::Good,
+    };
+
+    /// Check that every reading is trustworthy.
+    pub fn is_good(&self) -> bool {
+        *self == Self::GOOD
+    }
+}
+
+/// Validate a speed reading. A speed at exactly its maximum is pegged.
+fn validate_rpm(rpm: Rpm) -> ReadingQuality {
+    if rpm.max_speed() > 0f32 && rpm.speed() >= rpm.max_speed() {
+        ReadingQuality::Saturated
+    } else {
+        ReadingQuality::Good
+    }
+}
+
+/// Validate a valve state reading. `Unknown` means the sense pins disagree.
+fn validate_valve_state(valve_state: ValveState) -> ReadingQuality {
+    match valve_state {
+        ValveState::Unknown => ReadingQuality::Invalid,
+        _ => ReadingQuality::Good,
+    }
+}
+
+/// Validate a board temperature reading. A missing reading is fine since not
+/// all hardware has a temperature sensor.
+fn validate_board_temperature(board_temperature: Option<Celsius>) -> ReadingQuality {
+    let Some(board_temperature) = board_temperature else {
+        return ReadingQuality::Good;
+    };
+    let (min, max) = BOARD_TEMPERATURE_RANGE;
+    if board_temperature.value() < min || board_temperature.value() > max {
+        ReadingQuality::Invalid
+    } else {
+        ReadingQuality::Good
+    }
+}
+
+impl Display for ReadingQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Display for SensorQuality {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "(ClientSensorData: pump_speed={}, fan_speed={}, valve_state={})",
-            self.pump_speed, self.fan_speed, self.valve_state
+            "(SensorQuality: pump_speed={}, fan_speed={}, valve_state={}, board_temperature={})",
+            self.pump_speed, self.fan_speed, self.valve_state, self.board_temperature
+        )
+    }
+}
+
+impl ClientSensorData {
+    /// Describe the readings with their temperatures in `unit`.
+    pub fn describe(&self, unit: TemperatureUnit) -> String {
+        let temperature = |temperature: Option<Celsius>| match temperature {
+            Some(temperature) => Temperature {
+                value: temperature.value(),
+            }
+            .display(unit)
+            .to_string(),
+            None => "None".to_string(),
+        };
+        format!(
+            "(ClientSensorData: device={}, pump_speed={}, fan_speed={}, valve_state={}, board_temperature={}, coolant_temperature={}, pump_current={:?}, fan_current={:?}, flow_rate={:?}, quality={})",
+            self.device,
+            self.pump_speed,
+            self.fan_speed,
+            self.valve_state,
+            temperature(self.board_temperature),
+            temperature(self.coolant_temperature),
+            self.pump_current.map(|current| current.value()),
+            self.fan_current.map(|current| current.value()),
+            self.flow_rate.map(|flow_rate| flow_rate.value()),
+            self.quality
         )
     }
 }
 
-impl TryFrom<ReportSensorsPacket> for ClientSensorData {
+impl TryFrom<(DeviceId, ReportSensorsPacket)> for ClientSensorData {
     type Error = ClientSensorDataError;
 
-    fn try_from(value: ReportSensorsPacket) -> Result<Self, Self::Error> {
+    /// Convert and validate a sensor packet received from `device`.
+    /// Implausible readings are kept but flagged in `quality` so consumers
+    /// can decide how to weigh them.
+    fn try_from((device, value): (DeviceId, ReportSensorsPacket)) -> Result<Self, Self::Error> {
         Ok(ClientSensorData {
-            pump_speed: value.pump_speed_rpm,
-            fan_speed: value.fan_speed_rpm,
-            valve_state: value.valve_state,
+            extra_fan_speeds: value.fan_speeds_rpm.rest(),
+            coolant_temperature: value.coolant_temperature,
+            flow_rate: value.flow_rate,
+            sampled_at_ms: value.sampled_at_ms,
+            ..ClientSensorData::new(
+                device,
+                value.pump_speed_rpm,
+                value.fan_speeds_rpm.first(),
+                value.valve_state,
+                value.board_temperature,
+                value.pump_current,
+                value.fan_current,
+            )
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::packet::FanChannels;
+
+    fn packet(
+        pump_speed: f32,
+        fan_speed: f32,
+        valve_state: ValveState,
+    ) -> (DeviceId, ReportSensorsPacket) {
+        (
+            DeviceId::new("1324"),
+            ReportSensorsPacket {
+                pump_speed_rpm: Rpm::new(2000f32, pump_speed).expect("Failed to get RPM."),
+                fan_speeds_rpm: FanChannels::single(
+                    Rpm::new(1800f32, fan_speed).expect("Failed to get RPM."),
+                ),
+                valve_state,
+                board_temperature: None,
+                coolant_temperature: None,
+                pump_current: None,
+                fan_current: None,
+                flow_rate: None,
+                sampled_at_ms: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_plausible_readings_are_good() {
+        let data = ClientSensorData::try_from(packet(1000f32, 900f32, ValveState::Open))
+            .expect("Failed to get ClientSensorData.");
+        assert!(data.quality.is_good());
+
+        let data = ClientSensorData::try_from(packet(0f32, 0f32, ValveState::Closed))
+            .expect("Failed to get ClientSensorData.");
+        assert!(data.quality.is_good());
+    }
+
+    #[test]
+    fn test_pegged_rpm_is_saturated() {
+        let data = ClientSensorData::try_from(packet(2000f32, 900f32, ValveState::Open))
+            .expect("Failed to get ClientSensorData.");
+        assert_eq!(data.quality.pump_speed, ReadingQuality::Saturated);
+        assert_eq!(data.quality.fan_speed, ReadingQuality::Good);
+        assert!(!data.quality.is_good());
+
+        let data = ClientSensorData::try_from(packet(1000f32, 1800f32, ValveState::Open))
+            .expect("Failed to get ClientSensorData.");
+        assert_eq!(data.quality.fan_speed, ReadingQuality::Saturated);
+    }
+
+    #[test]
+    fn test_unknown_valve_is_invalid() {
+        let data = ClientSensorData::try_from(packet(1000f32, 900f32, ValveState::Unknown))
+            .expect("Failed to get ClientSensorData.");
+        assert_eq!(data.quality.valve_state, ReadingQuality::Invalid);
+        assert!(!data.quality.is_good());
+
+        let data = data.with_valve_state(ValveState::Opening);
+        assert_eq!(data.valve_state, ValveState::Opening);
+        assert!(data.quality.is_good());
+    }
+
+    #[test]
+    fn test_with_speeds_revalidates() {
+        let data = ClientSensorData::try_from(packet(1000f32, 900f32, ValveState::Open))
+            .expect("Failed to get ClientSensorData.");
+
+        let data = data.with_speeds(
+            Rpm::new(2000f32, 2000f32).expect("Failed to get RPM."),
+            Rpm::new(1800f32, 450f32).expect("Failed to get RPM."),
+        );
+        assert_eq!(data.pump_speed.speed(), 2000f32);
+        assert_eq!(data.fan_speed.speed(), 450f32);
+        assert_eq!(data.quality.pump_speed, ReadingQuality::Saturated);
+        assert_eq!(data.quality.fan_speed, ReadingQuality::Good);
+        assert_eq!(data.valve_state, ValveState::Open);
+    }
+
+    #[test]
+    fn test_board_temperature_quality() {
+        let (device, mut report) = packet(1000f32, 900f32, ValveState::Open);
+        report.board_temperature =
+            Some(Celsius::try_from(32.5f32).expect("Failed to get Celsius."));
+        let data = ClientSensorData::try_from((device, report.clone()))
+            .expect("Failed to get ClientSensorData.");
+        assert_eq!(data.board_temperature, report.board_temperature);
+        assert!(data.quality.is_good());
+
+        report.board_temperature = Some(Celsius::try_from(150f32).expect("Failed to get Celsius."));
+        let data =
+            ClientSensorData::try_from((device, report)).expect("Failed to get ClientSensorData.");
+        assert_eq!(data.quality.board_temperature, ReadingQuality::Invalid);
+        assert!(!data.quality.is_good());
+    }
+
+    #[test]
+    fn test_current_is_carried_over() {
+        let (device, mut report) = packet(1000f32, 900f32, ValveState::Open);
+        report.pump_current = Some(Current::try_from(0.75f32).expect("Failed to get Current."));
+        let data = ClientSensorData::try_from((device, report.clone()))
+            .expect("Failed to get ClientSensorData.");
+        assert_eq!(data.pump_current, report.pump_current);
+        assert_eq!(data.fan_current, None);
+        assert!(data.quality.is_good());
+    }
+
+    #[test]
+    fn test_coolant_temperature_is_carried_over() {
+        let (device, mut report) = packet(1000f32, 900f32, ValveState::Open);
+        report.coolant_temperature =
+            Some(Celsius::try_from(31.5f32).expect("Failed to get Celsius."));
+        let data = ClientSensorData::try_from((device, report.clone()))
+            .expect("Failed to get ClientSensorData.");
+        assert_eq!(data.coolant_temperature, report.coolant_temperature);
+    }
+
+    #[test]
+    fn test_flow_rate_is_carried_over() {
+        let (device, mut report) = packet(1000f32, 900f32, ValveState::Open);
+        report.flow_rate = Some(FlowRate::try_from(1.25f32).expect("Failed to get FlowRate."));
+        let data = ClientSensorData::try_from((device, report.clone()))
+            .expect("Failed to get ClientSensorData.");
+        assert_eq!(data.flow_rate, report.flow_rate);
+    }
+
+    #[test]
+    fn test_extra_fan_speeds_are_carried_over() {
+        let (device, mut report) = packet(1000f32, 900f32, ValveState::Open);
+        assert_eq!(
+            ClientSensorData::try_from((device, report.clone()))
+                .expect("Failed to get ClientSensorData.")
+                .fan_channels(),
+            1
+        );
+
+        let second = Rpm::new(1800f32, 700f32).expect("Failed to get RPM.");
+        report
+            .fan_speeds_rpm
+            .push(second)
+            .expect("Failed to add a fan channel.");
+        let data =
+            ClientSensorData::try_from((device, report)).expect("Failed to get ClientSensorData.");
+        assert_eq!(data.fan_speed.speed(), 900f32);
+        assert_eq!(data.extra_fan_speeds, [Some(second), None, None]);
+        assert_eq!(data.fan_channels(), 2);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let data = ClientSensorData::try_from(packet(1000f32, 1800f32, ValveState::Closed))
+            .expect("Failed to get ClientSensorData.");
+        let data_ser = serde_json::to_string(&data).expect("Failed to serialize ClientSensorData.");
+        let data_deser: ClientSensorData =
+            serde_json::from_str(&data_ser).expect("Failed to deserialize ClientSensorData.");
+        assert_eq!(data, data_deser);
+    }
+}
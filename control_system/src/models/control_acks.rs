@@ -0,0 +1,135 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use common::packet::Packet;
+
+/// How long a control frame may go without an `Ack` before it is counted as
+/// unacknowledged. Far longer than a round trip over a healthy link.
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Numbers the control frames sent to a single device and tracks which of
+/// them the device has acknowledged.
+#[derive(Debug, Clone, Default)]
+pub struct ControlAcks {
+    next_sequence: u16,
+
+    /// Frames awaiting an ack, oldest first, with when they were sent.
+    awaiting: VecDeque<(u16, Instant)>,
+}
+
+impl ControlAcks {
+    /// Give `packet` the next sequence number if it is a control frame, and
+    /// await its ack from `now`. Returns the sequence number given, if any.
+    pub fn stamp(&mut self, packet: &mut Packet, now: Instant) -> Option<u16> {
+        let Packet::ReportControlTargets(targets) = packet else {
+            return None;
+        };
+        let sequence = self.next_sequence;
+        self.next_sequence = sequence.wrapping_add(1);
+        targets.sequence = sequence;
+        self.awaiting.push_back((sequence, now));
+        Some(sequence)
+    }
+
+    /// Record an ack for `sequence` received at `now`. Returns how long the
+    /// frame took to be acknowledged, or `None` if it wasn't awaited, such as
+    /// when its ack came after `ACK_TIMEOUT`.
+    pub fn acknowledge(&mut self, sequence: u16, now: Instant) -> Option<Duration> {
+        let index = self
+            .awaiting
+            .iter()
+            .position(|(awaited, _)| *awaited == sequence)?;
+        let (_, sent) = self.awaiting.remove(index)?;
+        Some(now.saturating_duration_since(sent))
+    }
+
+    /// Stop awaiting frames sent `ACK_TIMEOUT` or longer before `now`.
+    /// Returns how many there were.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let mut expired = 0;
+        while self
+            .awaiting
+            .front()
+            .is_some_and(|(_, sent)| now.saturating_duration_since(*sent) >= ACK_TIMEOUT)
+        {
+            self.awaiting.pop_front();
+            expired += 1;
+        }
+        expired
+    }
+
+    /// How many frames are awaiting an ack.
+    pub fn awaiting(&self) -> usize {
+        self.awaiting.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        packet::{FanChannels, PingPacket, ReportControlTargetsPacket},
+        physical::Percentage,
+    };
+
+    use super::*;
+
+    fn control_targets() -> Packet {
+        Packet::ReportControlTargets(ReportControlTargetsPacket {
+            fan_control_percents: FanChannels::single(Percentage::const_new(50)),
+            pump_control_percent: Percentage::const_new(50),
+            sequence: 0,
+        })
+    }
+
+    #[test]
+    fn test_stamp_numbers_control_frames() {
+        let mut acks = ControlAcks::default();
+        let now = Instant::now();
+        for expected in 0..3 {
+            let mut packet = control_targets();
+            assert_eq!(acks.stamp(&mut packet, now), Some(expected));
+            let Packet::ReportControlTargets(targets) = packet else {
+                unreachable!();
+            };
+            assert_eq!(targets.sequence, expected);
+        }
+
+        let mut ping = Packet::Ping(PingPacket { nonce: 1 });
+        assert_eq!(acks.stamp(&mut ping, now), None);
+        assert_eq!(acks.awaiting(), 3);
+    }
+
+    #[test]
+    fn test_stamp_wraps() {
+        let mut acks = ControlAcks {
+            next_sequence: u16::MAX,
+            ..ControlAcks::default()
+        };
+        let now = Instant::now();
+        assert_eq!(acks.stamp(&mut control_targets(), now), Some(u16::MAX));
+        assert_eq!(acks.stamp(&mut control_targets(), now), Some(0));
+    }
+
+    #[test]
+    fn test_acknowledge_and_expire() {
+        let mut acks = ControlAcks::default();
+        let start = Instant::now();
+        acks.stamp(&mut control_targets(), start);
+        acks.stamp(&mut control_targets(), start + Duration::from_millis(500));
+        acks.stamp(&mut control_targets(), start + Duration::from_millis(900));
+
+        let round_trip = acks.acknowledge(1, start + Duration::from_millis(520));
+        assert_eq!(round_trip, Some(Duration::from_millis(20)));
+        assert_eq!(
+            acks.acknowledge(1, start + Duration::from_millis(530)),
+            None
+        );
+
+        assert_eq!(acks.expire(start + Duration::from_millis(999)), 0);
+        assert_eq!(acks.expire(start + ACK_TIMEOUT), 1);
+        assert_eq!(acks.acknowledge(0, start + ACK_TIMEOUT), None);
+        assert_eq!(acks.awaiting(), 1);
+    }
+}
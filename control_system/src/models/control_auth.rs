@@ -0,0 +1,148 @@
+use std::{env, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+/// Environment variable listing the uids allowed to control the system over
+/// the control socket, e.g. `1000,1001`.
+pub const CONTROL_UIDS_ENV_VAR: &str = "PRANDTL_CONTROL_UIDS";
+
+/// Environment variable holding a token which grants control to whoever
+/// presents it. The CLI sends it with every request when set.
+pub const CONTROL_TOKEN_ENV_VAR: &str = "PRANDTL_CONTROL_TOKEN";
+
+/// What a control socket client is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Permission {
+    /// Can query state, such as listing alarms.
+    ReadOnly,
+
+    /// Can also change state, such as acknowledging alarms.
+    Control,
+}
+
+/// Decides the permission of each control socket client. Anyone who can
+/// connect to the socket can read. Control is granted to the uids in
+/// `control_uids` and to requests carrying `token`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlAuth {
+    /// Peers running as these uids can control the system.
+    pub control_uids: Vec<u32>,
+
+    /// Requests carrying this token can control the system.
+    pub token: Option<String>,
+}
+
+impl ControlAuth {
+    /// Get the auth config from the environment. Without `PRANDTL_CONTROL_UIDS`
+    /// control is limited to `owner_uid`, the user running the control system,
+    /// and root.
+    pub fn from_env(owner_uid: u32) -> Self {
+        let uids = env::var(CONTROL_UIDS_ENV_VAR).ok();
+        Self {
+            control_uids: parse_uids(uids.as_deref()).unwrap_or_else(|| vec![owner_uid, 0]),
+            token: parse_token(env::var(CONTROL_TOKEN_ENV_VAR).ok().as_deref()),
+        }
+    }
+
+    /// Get the permission of a peer running as `peer_uid`, if known, for a
+    /// request carrying `token`.
+    pub fn permission(&self, peer_uid: Option<u32>, token: Option<&str>) -> Permission {
+        let uid_allowed = peer_uid.is_some_and(|uid| self.control_uids.contains(&uid));
+        let token_allowed = match (&self.token, token) {
+            (Some(expected), Some(token)) => {
+                constant_time_eq(expected.as_bytes(), token.as_bytes())
+            }
+            _ => false,
+        };
+        if uid_allowed || token_allowed {
+            Permission::Control
+        } else {
+            Permission::ReadOnly
+        }
+    }
+}
+
+/// Get the token to send with CLI requests from the environment.
+pub fn control_token_from_env() -> Option<String> {
+    parse_token(env::var(CONTROL_TOKEN_ENV_VAR).ok().as_deref())
+}
+
+/// Parse a comma separated list of uids. Returns `None` if it is missing or
+/// any uid is invalid. An empty list is valid and grants control by uid to
+/// nobody.
+fn parse_uids(value: Option<&str>) -> Option<Vec<u32>> {
+    value?
+        .split(',')
+        .map(str::trim)
+        .filter(|uid| !uid.is_empty())
+        .map(|uid| uid.parse().ok())
+        .collect()
+}
+
+/// Parse a token. Blank tokens are ignored so an empty variable can't grant
+/// control to requests without one.
+fn parse_token(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+}
+
+/// Compare two byte strings without exiting early on the first difference, so
+/// the time taken doesn't reveal how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::ReadOnly => write!(f, "read-only"),
+            Permission::Control => write!(f, "control"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_uids() {
+        assert_eq!(parse_uids(Some("1000, 1001")), Some(vec![1000, 1001]));
+        assert_eq!(parse_uids(Some("")), Some(vec![]));
+        assert_eq!(parse_uids(Some("1000,root")), None);
+        assert_eq!(parse_uids(None), None);
+    }
+
+    #[test]
+    fn test_parse_token() {
+        assert_eq!(parse_token(Some(" secret ")), Some("secret".to_string()));
+        assert_eq!(parse_token(Some(" ")), None);
+        assert_eq!(parse_token(None), None);
+    }
+
+    #[test]
+    fn test_permission_by_uid() {
+        let auth = ControlAuth {
+            control_uids: vec![1000],
+            token: None,
+        };
+        assert_eq!(auth.permission(Some(1000), None), Permission::Control);
+        assert_eq!(auth.permission(Some(1001), None), Permission::ReadOnly);
+        assert_eq!(auth.permission(None, None), Permission::ReadOnly);
+        assert_eq!(auth.permission(Some(1001), Some("")), Permission::ReadOnly);
+    }
+
+    #[test]
+    fn test_permission_by_token() {
+        let auth = ControlAuth {
+            control_uids: vec![],
+            token: Some("secret".to_string()),
+        };
+        assert_eq!(auth.permission(None, Some("secret")), Permission::Control);
+        assert_eq!(auth.permission(None, Some("secreT")), Permission::ReadOnly);
+        assert_eq!(auth.permission(None, Some("secret!")), Permission::ReadOnly);
+        assert_eq!(auth.permission(Some(0), None), Permission::ReadOnly);
+    }
+}
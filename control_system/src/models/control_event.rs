@@ -1,15 +1,39 @@
 use common::{
-    packet::{Packet, ReportControlTargetsPacket},
+    packet::{FanChannels, Packet, ReportControlTargetsPacket, ValveCommandPacket},
     physical::{Percentage, ValveState},
 };
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use thiserror::Error;
 
-#[derive(Debug, Clone, Copy)]
+use super::device_id::DeviceId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ControlEvent {
+    /// The device this event should be sent to.
+    pub device: DeviceId,
     pub fan_activation: Percentage,  // NOTE: placeholder
     pub pump_activation: Percentage, // NOTE: placeholder
     pub valve_state: ValveState,
+
+    /// How many fan channels of the device `fan_activation` is sent to.
+    #[serde(default = "default_fan_channels")]
+    pub fan_channels: u8,
+
+    /// Sequence number of the received packet whose sensor data this event
+    /// was generated from, if any. See `AddressedPacket::sequence`.
+    #[serde(default)]
+    pub sequence: Option<u64>,
+
+    /// The control generation this event was generated in. Events from
+    /// before a switch of what generates them, such as parking for sleep or
+    /// an override, are superseded. See `SharedGeneration`.
+    #[serde(default)]
+    pub generation: u64,
+}
+
+fn default_fan_channels() -> u8 {
+    1
 }
 
 #[derive(Error, Debug)]
@@ -18,12 +42,53 @@ pub enum ControlEventError {
     InvalidRange,
 }
 
+impl ControlEvent {
+    /// Construct a control event from raw 0-100% activations.
+    /// Will return `InvalidRange` if either activation is outside 0-100%.
+    pub fn new(
+        device: DeviceId,
+        fan_activation: f32,
+        pump_activation: f32,
+        valve_state: ValveState,
+    ) -> Result<Self, ControlEventError> {
+        Ok(Self {
+            device,
+            fan_activation: Percentage::try_from(fan_activation)
+                .map_err(|_| ControlEventError::InvalidRange)?,
+            pump_activation: Percentage::try_from(pump_activation)
+                .map_err(|_| ControlEventError::InvalidRange)?,
+            valve_state,
+            fan_channels: default_fan_channels(),
+            sequence: None,
+            generation: 0,
+        })
+    }
+
+    /// Whether both events command the same outputs, regardless of which
+    /// sensor data or generation they were generated from.
+    pub fn same_targets(&self, other: &Self) -> bool {
+        self.fan_activation == other.fan_activation
+            && self.pump_activation == other.pump_activation
+            && self.valve_state == other.valve_state
+            && self.fan_channels == other.fan_channels
+    }
+
+    /// The valve command for this event. Sent along with the control targets
+    /// for every event, since repeating a command changes nothing and a
+    /// device which reset or missed one still ends up at the right state.
+    pub fn valve_command(&self) -> Packet {
+        Packet::ValveCommand(ValveCommandPacket {
+            target: self.valve_state,
+        })
+    }
+}
+
 impl Display for ControlEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "<Control Event | fan_speed:{}, pump_pwm:{}, valve_state:{}>",
-            self.fan_activation, self.pump_activation, self.valve_state
+            "<Control Event | device:{}, fan_speed:{}, pump_pwm:{}, valve_state:{}>",
+            self.device, self.fan_activation, self.pump_activation, self.valve_state
         )
     }
 }
@@ -33,9 +98,75 @@ impl TryFrom<ControlEvent> for Packet {
 
     fn try_from(value: ControlEvent) -> Result<Self, Self::Error> {
         Ok(Packet::ReportControlTargets(ReportControlTargetsPacket {
-            fan_control_percent: value.fan_activation,
+            fan_control_percents: FanChannels::repeat(
+                value.fan_activation,
+                value.fan_channels as usize,
+            ),
             pump_control_percent: value.pump_activation,
-            valve_control_state: value.valve_state,
+            // NOTE: Stamped by the link just before it is written.
+            sequence: 0,
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let event = ControlEvent::new(DeviceId::new("1324"), 25f32, 50f32, ValveState::Open)
+            .expect("Failed to get ControlEvent.");
+        assert_eq!(
+            event.fan_activation,
+            Percentage::try_from(25f32).expect("Failed to get Percentage.")
+        );
+        assert_eq!(
+            event.pump_activation,
+            Percentage::try_from(50f32).expect("Failed to get Percentage.")
+        );
+        assert_eq!(event.valve_state, ValveState::Open);
+
+        assert!(ControlEvent::new(DeviceId::new("1324"), -1f32, 50f32, ValveState::Open).is_err());
+        assert!(ControlEvent::new(DeviceId::new("1324"), 25f32, 101f32, ValveState::Open).is_err());
+    }
+
+    #[test]
+    fn test_same_targets() {
+        let event = ControlEvent::new(DeviceId::new("1324"), 25f32, 50f32, ValveState::Open)
+            .expect("Failed to get ControlEvent.");
+        let later = ControlEvent {
+            sequence: Some(7),
+            ..event
+        };
+        let changed = ControlEvent::new(DeviceId::new("1324"), 25f32, 50f32, ValveState::Closed)
+            .expect("Failed to get ControlEvent.");
+
+        assert!(event.same_targets(&later));
+        assert!(!event.same_targets(&changed));
+    }
+
+    #[test]
+    fn test_targets_every_fan_channel() {
+        let event = ControlEvent {
+            fan_channels: 2,
+            ..ControlEvent::new(DeviceId::new("1324"), 25f32, 50f32, ValveState::Open)
+                .expect("Failed to get ControlEvent.")
+        };
+        let Ok(Packet::ReportControlTargets(targets)) = Packet::try_from(event) else {
+            panic!("Expected control targets.");
+        };
+        assert_eq!(targets.fan_control_percents.channels(), 2);
+        assert_eq!(targets.fan_control_percent(1), event.fan_activation);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let event = ControlEvent::new(DeviceId::new("1324"), 25.25f32, 50f32, ValveState::Closed)
+            .expect("Failed to get ControlEvent.");
+        let event_ser = serde_json::to_string(&event).expect("Failed to serialize ControlEvent.");
+        let event_deser: ControlEvent =
+            serde_json::from_str(&event_ser).expect("Failed to deserialize ControlEvent.");
+        assert_eq!(event, event_deser);
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use super::addressed_packet::AddressedPacket;
+
+/// Counts switches of what generates control frames, such as parking for
+/// sleep, resuming or an override. Frames are tagged with the generation they
+/// were generated in, so ones from before a switch which are still in flight
+/// can be dropped. Shared so the count survives the control task being
+/// restarted, which would otherwise make its new frames look stale.
+#[derive(Debug, Clone, Default)]
+pub struct SharedGeneration(Arc<AtomicU64>);
+
+impl SharedGeneration {
+    /// Get the generation frames are generated in now.
+    pub fn current(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Start a new generation, superseding every frame generated so far.
+    /// Returns the new generation.
+    pub fn advance(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+/// Tracks the newest generation of control frames queued for a device, so
+/// frames from superseded generations can be dropped before being written.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GenerationFilter {
+    newest: u64,
+}
+
+impl GenerationFilter {
+    /// Whether `packet` may be queued, remembering its generation if it is
+    /// newer than any seen. Packets which aren't control frames are always
+    /// admitted.
+    pub fn admit(&mut self, packet: &AddressedPacket) -> bool {
+        let Some(generation) = packet.generation else {
+            return true;
+        };
+        if generation < self.newest {
+            return false;
+        }
+        self.newest = generation;
+        true
+    }
+
+    /// Whether `packet` is still current, without remembering anything.
+    pub fn is_current(&self, packet: &AddressedPacket) -> bool {
+        match packet.generation {
+            Some(generation) => generation >= self.newest,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        packet::{Packet, PingPacket},
+        physical::ValveState,
+    };
+
+    use super::*;
+    use crate::models::{control_event::ControlEvent, device_id::DeviceId};
+
+    fn frame(generation: u64) -> AddressedPacket {
+        let event = ControlEvent {
+            generation,
+            ..ControlEvent::new(DeviceId::new("1324"), 25f32, 50f32, ValveState::Open)
+                .expect("Failed to get ControlEvent.")
+        };
+        AddressedPacket::try_from(event).expect("Failed to get AddressedPacket.")
+    }
+
+    #[test]
+    fn test_advance() {
+        let generation = SharedGeneration::default();
+        let restarted_task = generation.clone();
+        assert_eq!(generation.current(), 0);
+        assert_eq!(generation.advance(), 1);
+        assert_eq!(restarted_task.current(), 1);
+    }
+
+    #[test]
+    fn test_filter_drops_superseded_frames() {
+        let mut filter = GenerationFilter::default();
+        let superseded = frame(1);
+        assert!(filter.admit(&superseded));
+        assert!(filter.admit(&frame(2)));
+        assert!(!filter.is_current(&superseded));
+        assert!(!filter.admit(&superseded));
+        assert!(filter.admit(&frame(2)));
+
+        let ping =
+            AddressedPacket::new(DeviceId::new("1324"), Packet::Ping(PingPacket { nonce: 1 }));
+        assert!(filter.admit(&ping));
+        assert!(filter.is_current(&ping));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    env,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use super::control_event::ControlEvent;
+
+/// Environment variable used to choose when the control loop steps and
+/// sends control frames.
+pub const CONTROL_LOOP_ENV_VAR: &str = "PRANDTL_CONTROL_LOOP";
+
+/// Default longest time an unchanged control frame is held back for in
+/// `ControlLoopMode::EventDriven`.
+pub const DEFAULT_CONTROL_KEEPALIVE: Duration = Duration::from_secs(1);
+
+/// How many times per keepalive stale control frames are looked for, so a
+/// frame is resent at most a quarter keepalive late.
+const KEEPALIVE_CHECKS: u32 = 4;
+
+/// When the control loop steps and sends control frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlLoopMode {
+    /// Step and send a frame for every device whenever any sensor data
+    /// arrives.
+    #[default]
+    EveryUpdate,
+
+    /// Step a device only when its own sensor data or the host's arrives,
+    /// and only send its frame when its targets changed or `keepalive` has
+    /// passed since it was last sent.
+    EventDriven { keepalive: Duration },
+}
+
+impl Display for ControlLoopMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlLoopMode::EveryUpdate => write!(f, "every_update"),
+            ControlLoopMode::EventDriven { keepalive } => {
+                write!(f, "event:{}", keepalive.as_millis())
+            }
+        }
+    }
+}
+
+impl ControlLoopMode {
+    /// Get the mode from the environment, falling back to the default if it
+    /// is unset or invalid.
+    pub fn from_env() -> Self {
+        let mode = env::var(CONTROL_LOOP_ENV_VAR).ok();
+        parse_mode(mode.as_deref()).unwrap_or_default()
+    }
+
+    /// Get how often to look for control frames held back past the
+    /// keepalive. `None` if frames are never held back.
+    pub fn keepalive_check_interval(&self) -> Option<Duration> {
+        match self {
+            ControlLoopMode::EveryUpdate => None,
+            ControlLoopMode::EventDriven { keepalive } => Some(*keepalive / KEEPALIVE_CHECKS),
+        }
+    }
+
+    /// Whether a device's newly stepped `frame` should be sent, given the
+    /// frame last sent to it and when.
+    pub fn should_send(
+        &self,
+        last_sent: Option<&(ControlEvent, Instant)>,
+        frame: &ControlEvent,
+        now: Instant,
+    ) -> bool {
+        let ControlLoopMode::EventDriven { keepalive } = self else {
+            return true;
+        };
+        let Some((last_frame, sent_at)) = last_sent else {
+            return true;
+        };
+        !last_frame.same_targets(frame) || now.saturating_duration_since(*sent_at) >= *keepalive
+    }
+}
+
+/// Parse a control loop mode: `every_update`, `event` or `event:<keepalive
+/// ms>`. Returns `None` if it is missing or invalid, which includes a
+/// keepalive which is zero or not a number.
+fn parse_mode(value: Option<&str>) -> Option<ControlLoopMode> {
+    let value = value?.trim();
+    let (mode, keepalive) = value.split_once(':').unwrap_or((value, ""));
+    match mode.trim().to_lowercase().as_str() {
+        "every_update" if keepalive.is_empty() => Some(ControlLoopMode::EveryUpdate),
+        "event" if keepalive.trim().is_empty() => Some(ControlLoopMode::EventDriven {
+            keepalive: DEFAULT_CONTROL_KEEPALIVE,
+        }),
+        "event" => keepalive
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&millis| millis > 0)
+            .map(|millis| ControlLoopMode::EventDriven {
+                keepalive: Duration::from_millis(millis),
+            }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::physical::ValveState;
+
+    use super::*;
+    use crate::models::device_id::DeviceId;
+
+    const EVENT_DRIVEN: ControlLoopMode = ControlLoopMode::EventDriven {
+        keepalive: Duration::from_secs(1),
+    };
+
+    fn frame(pump: f32) -> ControlEvent {
+        ControlEvent::new(DeviceId::new("1324"), pump, 50f32, ValveState::Open)
+            .expect("Failed to get ControlEvent.")
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(
+            parse_mode(Some("every_update")),
+            Some(ControlLoopMode::EveryUpdate)
+        );
+        assert_eq!(
+            parse_mode(Some(" EVENT ")),
+            Some(ControlLoopMode::EventDriven {
+                keepalive: DEFAULT_CONTROL_KEEPALIVE
+            })
+        );
+        assert_eq!(
+            parse_mode(Some("event:250")),
+            Some(ControlLoopMode::EventDriven {
+                keepalive: Duration::from_millis(250)
+            })
+        );
+        assert_eq!(parse_mode(Some("event:0")), None);
+        assert_eq!(parse_mode(Some("event:soon")), None);
+        assert_eq!(parse_mode(Some("every_update:250")), None);
+        assert_eq!(parse_mode(Some("periodic")), None);
+        assert_eq!(parse_mode(None), None);
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for mode in [ControlLoopMode::EveryUpdate, EVENT_DRIVEN] {
+            assert_eq!(parse_mode(Some(&mode.to_string())), Some(mode));
+        }
+    }
+
+    #[test]
+    fn test_every_update_always_sends() {
+        let now = Instant::now();
+        let last_sent = (frame(25f32), now);
+        assert!(ControlLoopMode::EveryUpdate.should_send(Some(&last_sent), &frame(25f32), now));
+        assert_eq!(
+            ControlLoopMode::EveryUpdate.keepalive_check_interval(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_event_driven_holds_back_unchanged_frames() {
+        let now = Instant::now();
+        let last_sent = (frame(25f32), now);
+
+        assert!(EVENT_DRIVEN.should_send(None, &frame(25f32), now));
+        let from_later_data = ControlEvent {
+            sequence: Some(7),
+            ..frame(25f32)
+        };
+        assert!(!EVENT_DRIVEN.should_send(Some(&last_sent), &from_later_data, now));
+        assert!(EVENT_DRIVEN.should_send(Some(&last_sent), &frame(30f32), now));
+        assert!(!EVENT_DRIVEN.should_send(
+            Some(&last_sent),
+            &frame(25f32),
+            now + Duration::from_millis(999)
+        ));
+        assert!(EVENT_DRIVEN.should_send(
+            Some(&last_sent),
+            &frame(25f32),
+            now + Duration::from_secs(1)
+        ));
+        assert_eq!(
+            EVENT_DRIVEN.keepalive_check_interval(),
+            Some(Duration::from_millis(250))
+        );
+    }
+}
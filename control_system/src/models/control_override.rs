@@ -0,0 +1,90 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use super::{control_event::ControlEvent, device_id::DeviceId};
+
+/// A request to take a device's outputs over from the control loop, or to
+/// hand them back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverrideRequest {
+    /// Send `frame` to its device instead of the control loop's outputs
+    /// until released.
+    Hold(ControlEvent),
+
+    /// Hand the device back to the control loop, blending from the held
+    /// outputs.
+    Release(DeviceId),
+}
+
+impl OverrideRequest {
+    /// Get the device the request is for.
+    pub fn device(&self) -> DeviceId {
+        match self {
+            OverrideRequest::Hold(frame) => frame.device,
+            OverrideRequest::Release(device) => *device,
+        }
+    }
+}
+
+impl Display for OverrideRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverrideRequest::Hold(frame) => write!(
+                f,
+                "hold {} at fan={}, pump={}, valve={:?}",
+                frame.device, frame.fan_activation, frame.pump_activation, frame.valve_state
+            ),
+            OverrideRequest::Release(device) => write!(f, "release {}", device),
+        }
+    }
+}
+
+/// Apply any held override to `frame`, the outputs the control loop stepped
+/// for its device. The sequence of the sensor data it was stepped from and
+/// its generation are kept, so latency is still measured while overridden.
+pub fn apply_override(held: Option<&ControlEvent>, frame: ControlEvent) -> ControlEvent {
+    match held {
+        Some(held) => ControlEvent {
+            sequence: frame.sequence,
+            generation: frame.generation,
+            ..*held
+        },
+        None => frame,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::physical::ValveState;
+
+    use super::*;
+
+    fn frame(pump: f32, sequence: Option<u64>) -> ControlEvent {
+        ControlEvent {
+            sequence,
+            ..ControlEvent::new(DeviceId::new("1324"), 50f32, pump, ValveState::Open)
+                .expect("Failed to get ControlEvent.")
+        }
+    }
+
+    #[test]
+    fn test_apply_override() {
+        let held = frame(100f32, None);
+        assert_eq!(
+            apply_override(Some(&held), frame(25f32, Some(7))),
+            frame(100f32, Some(7))
+        );
+        assert_eq!(
+            apply_override(None, frame(25f32, Some(7))),
+            frame(25f32, Some(7))
+        );
+    }
+
+    #[test]
+    fn test_device() {
+        let device = DeviceId::new("1324");
+        assert_eq!(OverrideRequest::Hold(frame(100f32, None)).device(), device);
+        assert_eq!(OverrideRequest::Release(device).device(), device);
+    }
+}
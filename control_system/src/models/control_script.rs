@@ -0,0 +1,418 @@
+use std::{
+    cell::Cell,
+    env, fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST, FLOAT};
+use thiserror::Error;
+use tracing::{debug, error};
+
+/// Environment variable used to replace the built-in control curves with a
+/// control script, given its path.
+pub const CONTROL_SCRIPT_ENV_VAR: &str = "PRANDTL_CONTROL_SCRIPT";
+
+/// How long a script may run for in a single control step before it is
+/// stopped and the step falls back to the curves.
+pub const SCRIPT_TIME_BUDGET: Duration = Duration::from_millis(10);
+
+/// The longest script accepted, in bytes.
+const MAX_SCRIPT_LEN: usize = 64 * 1024;
+
+/// How deeply expressions and functions may nest, so neither compiling nor
+/// running a script can overflow the stack.
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPR_DEPTH: usize = 32;
+const MAX_CALL_LEVELS: usize = 32;
+
+/// The largest string, array or map a script may build, so it can't run the
+/// host out of memory within its time budget.
+const MAX_COLLECTION_SIZE: usize = 1024;
+
+thread_local! {
+    /// When the script running on this thread must stop by, if one is.
+    static SCRIPT_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// A value a script can read. Percentages are 0-100, the valve is 1 when
+/// closed and 0 when open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptInput {
+    /// The cpu temperature, in degC.
+    Temperature,
+    PumpRpm,
+    FanRpm,
+
+    /// The pump and fan speeds as a percentage of their maximum.
+    PumpSpeed,
+    FanSpeed,
+
+    /// Seconds the control loop has been stepped for.
+    Elapsed,
+
+    /// The duties of the previous step, or the curves' on the first step.
+    PreviousPump,
+    PreviousFan,
+
+    /// What the built-in curves would command.
+    CurvePump,
+    CurveFan,
+    CurveValve,
+}
+
+impl ScriptInput {
+    pub const ALL: [ScriptInput; 11] = [
+        ScriptInput::Temperature,
+        ScriptInput::PumpRpm,
+        ScriptInput::FanRpm,
+        ScriptInput::PumpSpeed,
+        ScriptInput::FanSpeed,
+        ScriptInput::Elapsed,
+        ScriptInput::PreviousPump,
+        ScriptInput::PreviousFan,
+        ScriptInput::CurvePump,
+        ScriptInput::CurveFan,
+        ScriptInput::CurveValve,
+    ];
+
+    /// The name scripts read it by.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScriptInput::Temperature => "temperature",
+            ScriptInput::PumpRpm => "pump_rpm",
+            ScriptInput::FanRpm => "fan_rpm",
+            ScriptInput::PumpSpeed => "pump_speed",
+            ScriptInput::FanSpeed => "fan_speed",
+            ScriptInput::Elapsed => "elapsed",
+            ScriptInput::PreviousPump => "previous_pump",
+            ScriptInput::PreviousFan => "previous_fan",
+            ScriptInput::CurvePump => "curve_pump",
+            ScriptInput::CurveFan => "curve_fan",
+            ScriptInput::CurveValve => "curve_valve",
+        }
+    }
+}
+
+/// The values of every `ScriptInput` for a single control step.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScriptInputs {
+    values: [f32; ScriptInput::ALL.len()],
+}
+
+impl ScriptInputs {
+    /// Set an input, returning the inputs for chaining.
+    pub fn with(mut self, input: ScriptInput, value: f32) -> Self {
+        self.values[input as usize] = value;
+        self
+    }
+
+    pub fn get(&self, input: ScriptInput) -> f32 {
+        self.values[input as usize]
+    }
+}
+
+/// What a script commanded in a single step. Anything it didn't assign is
+/// `None`, and left to the built-in curves.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScriptOutputs {
+    /// Pump and fan duty, in percent. Not yet clamped to 0-100.
+    pub pump: Option<f32>,
+    pub fan: Option<f32>,
+
+    /// Whether the valve should be closed.
+    pub valve_closed: Option<bool>,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    #[error("The script is longer than {0} bytes.")]
+    TooLong(usize),
+
+    #[error("{0}")]
+    Compile(String),
+
+    #[error("{0}")]
+    Run(String),
+
+    /// This occurs if a step runs for longer than `SCRIPT_TIME_BUDGET`.
+    #[error("Ran for longer than {0:?} in a single step.")]
+    TimedOut(Duration),
+
+    #[error("'{0}' must be a number, not {1}.")]
+    NotANumber(&'static str, String),
+
+    #[error("'{0}' isn't a finite number.")]
+    NonFinite(&'static str),
+}
+
+/// Failing to load the control script named by `CONTROL_SCRIPT_ENV_VAR`.
+#[derive(Error, Debug)]
+pub enum LoadScriptError {
+    #[error("Failed to read control script {}. Error: {1}", .0.display())]
+    Read(PathBuf, std::io::Error),
+
+    #[error("Failed to parse control script {}. Error: {1}", .0.display())]
+    Parse(PathBuf, ScriptError),
+}
+
+/// The variables a script assigns to command the outputs.
+const OUTPUT_NAMES: [&str; 3] = ["pump", "fan", "valve"];
+
+/// A control script in Rhai, run every control step. Each `ScriptInput` is a
+/// constant, along with `open` and `closed` for the valve. Assigning `pump`
+/// or `fan` (duty in percent) or `valve` (`closed`, `open` or a condition
+/// which is true to close it) commands that output.
+///
+/// Scripts are sandboxed: they have no modules, `eval` or output, and can't
+/// keep state between steps. Each step is stopped once it has run for
+/// `SCRIPT_TIME_BUDGET`, and the memory and nesting it can use are limited.
+#[derive(Debug)]
+pub struct ControlScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl ControlScript {
+    /// Compile a script.
+    pub fn parse(source: &str) -> Result<Self, ScriptError> {
+        if source.len() > MAX_SCRIPT_LEN {
+            return Err(ScriptError::TooLong(MAX_SCRIPT_LEN));
+        }
+        let engine = sandboxed_engine();
+        let ast = engine
+            .compile(source)
+            .map_err(|e| ScriptError::Compile(e.to_string()))?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Run the script for a single step.
+    pub fn run(&self, inputs: &ScriptInputs) -> Result<ScriptOutputs, ScriptError> {
+        let mut scope = Scope::new();
+        for input in ScriptInput::ALL {
+            scope.push_constant(input.name(), inputs.get(input) as FLOAT);
+        }
+        scope.push_constant("open", 0 as FLOAT);
+        scope.push_constant("closed", 1 as FLOAT);
+        for output in OUTPUT_NAMES {
+            scope.push(output, Dynamic::UNIT);
+        }
+
+        SCRIPT_DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + SCRIPT_TIME_BUDGET)));
+        let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+        SCRIPT_DEADLINE.with(|deadline| deadline.set(None));
+        if let Err(e) = result {
+            return Err(match *e {
+                EvalAltResult::ErrorTerminated(..) => ScriptError::TimedOut(SCRIPT_TIME_BUDGET),
+                e => ScriptError::Run(e.to_string()),
+            });
+        }
+
+        let [pump, fan, valve] = OUTPUT_NAMES.map(|name| {
+            let value = scope.get(name).cloned().unwrap_or(Dynamic::UNIT);
+            output_value(name, value)
+        });
+        Ok(ScriptOutputs {
+            pump: pump?,
+            fan: fan?,
+            valve_closed: valve?.map(|valve| valve != 0f32),
+        })
+    }
+}
+
+/// Build an engine with nothing to reach outside the step with, which stops
+/// scripts once they pass their `SCRIPT_DEADLINE`.
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.disable_symbol("eval");
+    engine.on_print(|text| debug!("Control script printed: {}", text));
+    engine.on_debug(|text, _, _| debug!("Control script printed: {}", text));
+    engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_FUNCTION_EXPR_DEPTH);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.set_max_string_size(MAX_COLLECTION_SIZE);
+    engine.set_max_array_size(MAX_COLLECTION_SIZE);
+    engine.set_max_map_size(MAX_COLLECTION_SIZE);
+    engine.on_progress(|_| {
+        let deadline = SCRIPT_DEADLINE.with(Cell::get)?;
+        (Instant::now() >= deadline).then_some(Dynamic::UNIT)
+    });
+    engine.register_fn("clamp", clamp);
+    engine
+}
+
+/// `clamp(value, min, max)` for any mix of integers and floats.
+fn clamp(value: Dynamic, min: Dynamic, max: Dynamic) -> Result<FLOAT, Box<EvalAltResult>> {
+    let [value, min, max] = [value, min, max].map(|value| {
+        value
+            .as_float()
+            .or_else(|_| value.as_int().map(|value| value as FLOAT))
+    });
+    match (value, min, max) {
+        (Ok(value), Ok(min), Ok(max)) => Ok(value.max(min).min(max)),
+        _ => Err("clamp takes numbers.".into()),
+    }
+}
+
+/// Get what a script assigned to the output `name`, or `None` if it was left
+/// alone. Conditions are 1 if true and 0 if false.
+fn output_value(name: &'static str, value: Dynamic) -> Result<Option<f32>, ScriptError> {
+    if value.is_unit() {
+        return Ok(None);
+    }
+    let number = if let Ok(value) = value.as_float() {
+        value as f32
+    } else if let Ok(value) = value.as_int() {
+        value as f32
+    } else if let Ok(value) = value.as_bool() {
+        if value {
+            1f32
+        } else {
+            0f32
+        }
+    } else {
+        return Err(ScriptError::NotANumber(name, value.type_name().to_string()));
+    };
+    if !number.is_finite() {
+        return Err(ScriptError::NonFinite(name));
+    }
+    Ok(Some(number))
+}
+
+/// Get the control script from the environment, if one is set. A script
+/// which can't be read or parsed is logged and `None` returned, so the
+/// built-in curves are used instead.
+pub fn control_script_from_env() -> Option<ControlScript> {
+    match load_control_script_from_env()? {
+        Ok(script) => Some(script),
+        Err(e) => {
+            error!("{} Using the built-in curves.", e);
+            None
+        }
+    }
+}
+
+/// Read and parse the control script at the path in `CONTROL_SCRIPT_ENV_VAR`.
+/// Returns `None` if it isn't set.
+pub fn load_control_script_from_env() -> Option<Result<ControlScript, LoadScriptError>> {
+    let path = PathBuf::from(env::var_os(CONTROL_SCRIPT_ENV_VAR)?);
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) => return Some(Err(LoadScriptError::Read(path, e))),
+    };
+    Some(ControlScript::parse(&source).map_err(|e| LoadScriptError::Parse(path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(temperature: f32) -> ScriptInputs {
+        ScriptInputs::default()
+            .with(ScriptInput::Temperature, temperature)
+            .with(ScriptInput::CurvePump, 30f32)
+    }
+
+    #[test]
+    fn test_runs_script() {
+        let script = ControlScript::parse(
+            "// Ramp the pump from 50 degC.\n\
+             let hot = temperature >= 60;\n\
+             pump = clamp(curve_pump + (temperature - 50) * 2, 30, 100);\n\
+             \n\
+             // Bypass the radiator when hot.\n\
+             valve = if hot && !(temperature > 90) { closed } else { open };\n",
+        )
+        .expect("Should parse.");
+        assert_eq!(
+            script.run(&inputs(55f32)),
+            Ok(ScriptOutputs {
+                pump: Some(40f32),
+                fan: None,
+                valve_closed: Some(false),
+            })
+        );
+        let outputs = script.run(&inputs(70f32)).expect("Should run.");
+        assert_eq!(outputs.pump, Some(70f32));
+        assert_eq!(outputs.valve_closed, Some(true));
+        assert_eq!(
+            script.run(&inputs(95f32)).map(|o| o.valve_closed),
+            Ok(Some(false))
+        );
+
+        let script = ControlScript::parse("fan = max(2, curve_pump) / 3; valve = temperature < 60")
+            .expect("Should parse.");
+        assert_eq!(
+            script.run(&inputs(40f32)),
+            Ok(ScriptOutputs {
+                pump: None,
+                fan: Some(10f32),
+                valve_closed: Some(true),
+            })
+        );
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let parse = |source: &str| ControlScript::parse(source).err();
+        assert!(matches!(
+            parse("pump = (1 + 2"),
+            Some(ScriptError::Compile(_))
+        ));
+        assert!(matches!(
+            parse(&format!("pump = {}1", "(".repeat(100))),
+            Some(ScriptError::Compile(_))
+        ));
+        assert!(matches!(
+            parse("pump = eval(\"1\")"),
+            Some(ScriptError::Compile(_))
+        ));
+        assert!(matches!(
+            parse("import \"std\" as std;"),
+            Some(ScriptError::Compile(_))
+        ));
+        assert_eq!(
+            parse(&" ".repeat(MAX_SCRIPT_LEN + 1)),
+            Some(ScriptError::TooLong(MAX_SCRIPT_LEN))
+        );
+    }
+
+    #[test]
+    fn test_run_errors() {
+        let run = |source: &str| {
+            ControlScript::parse(source)
+                .expect("Should parse.")
+                .run(&inputs(0f32))
+        };
+        assert_eq!(
+            run("pump = 1.0 / temperature"),
+            Err(ScriptError::NonFinite("pump"))
+        );
+        assert_eq!(
+            run("fan = \"fast\""),
+            Err(ScriptError::NotANumber("fan", "string".to_string()))
+        );
+        assert!(matches!(run("pump = x + 1"), Err(ScriptError::Run(_))));
+        assert!(matches!(run("temperature = 5"), Err(ScriptError::Run(_))));
+        assert!(matches!(
+            run("let s = \"\"; loop { s += \"more\"; }"),
+            Err(ScriptError::Run(_))
+        ));
+    }
+
+    #[test]
+    fn test_time_budget() {
+        let script = ControlScript::parse("let i = 0; loop { i += 1; }").expect("Should parse.");
+        let started = Instant::now();
+        assert_eq!(
+            script.run(&inputs(0f32)),
+            Err(ScriptError::TimedOut(SCRIPT_TIME_BUDGET))
+        );
+        let elapsed = started.elapsed();
+        assert!(elapsed >= SCRIPT_TIME_BUDGET);
+        assert!(elapsed < SCRIPT_TIME_BUDGET * 20, "{:?}", elapsed);
+
+        // NOTE: The budget is per step, so the next one runs in full.
+        let script = ControlScript::parse("pump = 50").expect("Should parse.");
+        assert_eq!(script.run(&inputs(0f32)).map(|o| o.pump), Ok(Some(50f32)));
+    }
+}
@@ -0,0 +1,304 @@
+use std::{env, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "simulate")]
+use super::fault_simulation::{PlantFault, SimulatedFault};
+use super::{
+    alarm::Alarm,
+    cli_args::{parse_value, ArgsError},
+    control_auth::Permission,
+    control_trace::ControlTrace,
+    curve_preview::{CurveDefinition, CurveSample},
+    daemon_config::DaemonConfig,
+    device_id::DeviceId,
+    device_log::DeviceLog,
+};
+
+/// Environment variable used to override where the control socket is bound.
+pub const CONTROL_SOCKET_ENV_VAR: &str = "PRANDTL_CONTROL_SOCKET";
+
+/// Default location of the control socket.
+pub const DEFAULT_CONTROL_SOCKET_PATH: &str = "prandtl.sock";
+
+/// A request to the running control system.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ControlRequest {
+    ListAlarms,
+    AcknowledgeAlarm {
+        id: u64,
+    },
+    AcknowledgeAllAlarms,
+
+    /// Sample a candidate curve as the controller would evaluate it.
+    EvaluateCurve {
+        curve: CurveDefinition,
+        samples: usize,
+    },
+
+    /// Get the trace of the latest control frame for `device`, or every
+    /// device if `None`. Needs explain mode to be on.
+    Explain {
+        device: Option<DeviceId>,
+    },
+
+    /// Export the strategy, valve and fail-safe state machines as a Graphviz
+    /// DOT graph, with the current state of `device`, or of every device if
+    /// `None`, filled in when explain mode is on.
+    StateMachines {
+        device: Option<DeviceId>,
+    },
+
+    /// Simulate `fault` on `device`, or on every device if `None`. Stops
+    /// simulating faults there if `fault` is `None`. Needs fault simulation
+    /// to be on.
+    #[cfg(feature = "simulate")]
+    SimulateFault {
+        device: Option<DeviceId>,
+        fault: Option<PlantFault>,
+    },
+
+    /// Ask `device` to report sensor data at `rate_hz` until it resets.
+    SetTelemetryRate {
+        device: DeviceId,
+        rate_hz: u8,
+    },
+
+    /// Get the configuration the control system is running with.
+    DaemonConfig,
+
+    /// Get the log buffer of every device which has connected.
+    DeviceLogs,
+}
+
+/// A request along with the token authorizing it. Sent over the control
+/// socket as a single line of JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlMessage {
+    pub request: ControlRequest,
+
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// The reply to a `ControlRequest`. Sent back as a single line of JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ControlResponse {
+    /// Every alarm which is still active or not yet acknowledged.
+    Alarms(Vec<Alarm>),
+
+    /// The alarms which were acknowledged by the request.
+    Acknowledged(Vec<Alarm>),
+
+    /// The points sampled from a curve.
+    CurveSamples(Vec<CurveSample>),
+
+    /// Why the latest control frames have the outputs they do.
+    Traces(Vec<ControlTrace>),
+
+    /// The state machines as a Graphviz DOT graph.
+    Dot(String),
+
+    /// Every fault being simulated once the request was handled.
+    #[cfg(feature = "simulate")]
+    SimulatedFaults(Vec<SimulatedFault>),
+
+    /// The telemetry rate was queued to be sent to the device.
+    TelemetryRate { device: DeviceId, rate_hz: u8 },
+
+    /// The configuration the control system is running with.
+    DaemonConfig(DaemonConfig),
+
+    /// The log lines each device sent from its log buffer.
+    DeviceLogs(Vec<DeviceLog>),
+
+    /// The client isn't allowed to make the request.
+    Forbidden { required: Permission },
+
+    /// The request couldn't be handled.
+    Error(String),
+}
+
+impl ControlRequest {
+    /// Get the permission needed to make the request.
+    pub fn required_permission(&self) -> Permission {
+        match self {
+            ControlRequest::ListAlarms
+            | ControlRequest::EvaluateCurve { .. }
+            | ControlRequest::Explain { .. }
+            | ControlRequest::StateMachines { .. }
+            | ControlRequest::DaemonConfig
+            | ControlRequest::DeviceLogs => Permission::ReadOnly,
+            ControlRequest::AcknowledgeAlarm { .. }
+            | ControlRequest::AcknowledgeAllAlarms
+            | ControlRequest::SetTelemetryRate { .. } => Permission::Control,
+            #[cfg(feature = "simulate")]
+            ControlRequest::SimulateFault { .. } => Permission::Control,
+        }
+    }
+}
+
+/// Get the control socket path from the environment, falling back to the
+/// default if it is unset or empty.
+pub fn control_socket_path_from_env() -> PathBuf {
+    let path = env::var(CONTROL_SOCKET_ENV_VAR).ok();
+    PathBuf::from(parse_control_socket_path(path.as_deref()))
+}
+
+fn parse_control_socket_path(value: Option<&str>) -> &str {
+    value
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .unwrap_or(DEFAULT_CONTROL_SOCKET_PATH)
+}
+
+/// What `alarms` should do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmsCommand {
+    /// List every latched alarm.
+    List,
+
+    /// Acknowledge a single alarm by id.
+    Acknowledge(u64),
+
+    /// Acknowledge every alarm.
+    AcknowledgeAll,
+}
+
+/// Parse the arguments to `alarms`: nothing to list, or `ack <id|all>`.
+pub fn parse_alarms_args(args: &[String]) -> Result<AlarmsCommand, ArgsError> {
+    match args {
+        [] => Ok(AlarmsCommand::List),
+        [ack] if ack == "ack" => Err(ArgsError::Missing(
+            "an alarm id or 'all' after 'ack'".to_string(),
+        )),
+        [ack, id] if ack == "ack" && id == "all" => Ok(AlarmsCommand::AcknowledgeAll),
+        [ack, id] if ack == "ack" => parse_value("the alarm id", id, "a number or 'all'", |_| true)
+            .map(AlarmsCommand::Acknowledge),
+        [ack, _, unknown, ..] if ack == "ack" => Err(ArgsError::UnknownArgument(unknown.clone())),
+        [unknown, ..] => Err(ArgsError::UnknownArgument(unknown.clone())),
+    }
+}
+
+impl From<AlarmsCommand> for ControlRequest {
+    fn from(command: AlarmsCommand) -> Self {
+        match command {
+            AlarmsCommand::List => ControlRequest::ListAlarms,
+            AlarmsCommand::Acknowledge(id) => ControlRequest::AcknowledgeAlarm { id },
+            AlarmsCommand::AcknowledgeAll => ControlRequest::AcknowledgeAllAlarms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{cli_args::args, curve_preview::CurveOutput};
+
+    #[test]
+    fn test_parse_alarms_args() {
+        assert_eq!(parse_alarms_args(&args(&[])), Ok(AlarmsCommand::List));
+        assert_eq!(
+            parse_alarms_args(&args(&["ack", "3"])),
+            Ok(AlarmsCommand::Acknowledge(3))
+        );
+        assert_eq!(
+            parse_alarms_args(&args(&["ack", "all"])),
+            Ok(AlarmsCommand::AcknowledgeAll)
+        );
+        assert_eq!(
+            parse_alarms_args(&args(&["ack"])),
+            Err(ArgsError::Missing(
+                "an alarm id or 'all' after 'ack'".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_alarms_args(&args(&["ack", "three"])),
+            Err(ArgsError::InvalidValue(
+                "the alarm id".to_string(),
+                "three".to_string(),
+                "a number or 'all'".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_alarms_args(&args(&["ack", "3", "4"])),
+            Err(ArgsError::UnknownArgument("4".to_string()))
+        );
+        assert_eq!(
+            parse_alarms_args(&args(&["clear"])),
+            Err(ArgsError::UnknownArgument("clear".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_control_socket_path() {
+        assert_eq!(parse_control_socket_path(None), DEFAULT_CONTROL_SOCKET_PATH);
+        assert_eq!(
+            parse_control_socket_path(Some(" ")),
+            DEFAULT_CONTROL_SOCKET_PATH
+        );
+        assert_eq!(
+            parse_control_socket_path(Some("/run/prandtl.sock")),
+            "/run/prandtl.sock"
+        );
+    }
+
+    #[test]
+    fn test_message_round_trips() {
+        for request in [
+            ControlRequest::ListAlarms,
+            ControlRequest::AcknowledgeAlarm { id: 7 },
+            ControlRequest::AcknowledgeAllAlarms,
+            ControlRequest::EvaluateCurve {
+                curve: CurveDefinition {
+                    output: CurveOutput::Duty,
+                    points: vec![(50f32, 30f32), (80f32, 90f32)],
+                },
+                samples: 5,
+            },
+        ] {
+            let message = ControlMessage {
+                request,
+                token: Some("secret".to_string()),
+            };
+            let json = serde_json::to_string(&message).expect("Failed to encode message.");
+            assert_eq!(
+                serde_json::from_str::<ControlMessage>(&json).expect("Failed to decode message."),
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_message_without_token() {
+        assert_eq!(
+            serde_json::from_str::<ControlMessage>(r#"{"request": "ListAlarms"}"#)
+                .expect("Failed to decode message."),
+            ControlMessage {
+                request: ControlRequest::ListAlarms,
+                token: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_required_permission() {
+        assert_eq!(
+            ControlRequest::ListAlarms.required_permission(),
+            Permission::ReadOnly
+        );
+        assert_eq!(
+            ControlRequest::AcknowledgeAllAlarms.required_permission(),
+            Permission::Control
+        );
+        #[cfg(feature = "simulate")]
+        assert_eq!(
+            ControlRequest::SimulateFault {
+                device: None,
+                fault: Some(PlantFault::PumpStall),
+            }
+            .required_permission(),
+            Permission::Control
+        );
+    }
+}
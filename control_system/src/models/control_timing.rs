@@ -0,0 +1,133 @@
+use std::{fmt::Display, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Statistics of the intervals between a device's control steps over one
+/// reporting window.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ControlTimingStats {
+    /// Steps taken in the window.
+    pub steps: u64,
+
+    pub mean_interval_ms: f32,
+    pub min_interval_ms: f32,
+    pub max_interval_ms: f32,
+
+    /// Standard deviation of the intervals.
+    pub jitter_ms: f32,
+
+    /// Steps whose interval was longer than the control loop credits, such as
+    /// after the host stalled.
+    pub clamped_steps: u64,
+}
+
+impl Display for ControlTimingStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "(ControlTimingStats: steps={}, mean_interval_ms={:.1}, min_interval_ms={:.1}, max_interval_ms={:.1}, jitter_ms={:.1}, clamped_steps={})",
+            self.steps,
+            self.mean_interval_ms,
+            self.min_interval_ms,
+            self.max_interval_ms,
+            self.jitter_ms,
+            self.clamped_steps
+        )
+    }
+}
+
+/// Accumulates the intervals between a device's control steps until the
+/// window is finished.
+#[derive(Debug, Clone, Default)]
+pub struct ControlTiming {
+    steps: u64,
+
+    /// Sum of the intervals and of their squares, in milliseconds.
+    total_ms: f64,
+    total_squares_ms: f64,
+
+    min_interval: Option<Duration>,
+    max_interval: Duration,
+    clamped_steps: u64,
+}
+
+impl ControlTiming {
+    /// Record the interval since the previous step, and whether it was longer
+    /// than the control loop credits.
+    pub fn record(&mut self, interval: Duration, clamped: bool) {
+        let interval_ms = interval.as_secs_f64() * 1000f64;
+        self.steps += 1;
+        self.total_ms += interval_ms;
+        self.total_squares_ms += interval_ms * interval_ms;
+        self.min_interval = Some(self.min_interval.map_or(interval, |min| min.min(interval)));
+        self.max_interval = self.max_interval.max(interval);
+        if clamped {
+            self.clamped_steps += 1;
+        }
+    }
+
+    /// Get the statistics of the window so far and start a new one. Returns
+    /// `None` if there were no steps.
+    pub fn finish(&mut self) -> Option<ControlTimingStats> {
+        let timing = std::mem::take(self);
+        let min_interval = timing.min_interval?;
+        let steps = timing.steps as f64;
+        let mean_ms = timing.total_ms / steps;
+        // NOTE: Rounding can leave the variance just below zero.
+        let variance = (timing.total_squares_ms / steps - mean_ms * mean_ms).max(0f64);
+        Some(ControlTimingStats {
+            steps: timing.steps,
+            mean_interval_ms: mean_ms as f32,
+            min_interval_ms: min_interval.as_secs_f32() * 1000f32,
+            max_interval_ms: timing.max_interval.as_secs_f32() * 1000f32,
+            jitter_ms: variance.sqrt() as f32,
+            clamped_steps: timing.clamped_steps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steady_intervals_have_no_jitter() {
+        let mut timing = ControlTiming::default();
+        for _ in 0..10 {
+            timing.record(Duration::from_millis(500), false);
+        }
+
+        let stats = timing.finish().expect("Expected stats.");
+        assert_eq!(stats.steps, 10);
+        assert_eq!(stats.mean_interval_ms, 500f32);
+        assert_eq!(stats.min_interval_ms, 500f32);
+        assert_eq!(stats.max_interval_ms, 500f32);
+        assert!(stats.jitter_ms < 0.01f32);
+    }
+
+    #[test]
+    fn test_jitter_and_clamped_steps() {
+        let mut timing = ControlTiming::default();
+        timing.record(Duration::from_millis(400), false);
+        timing.record(Duration::from_millis(600), false);
+        timing.record(Duration::from_millis(3000), true);
+        timing.record(Duration::from_millis(200), false);
+
+        let stats = timing.finish().expect("Expected stats.");
+        assert_eq!(stats.mean_interval_ms, 1050f32);
+        assert_eq!(stats.min_interval_ms, 200f32);
+        assert_eq!(stats.max_interval_ms, 3000f32);
+        assert!((stats.jitter_ms - 1134.7f32).abs() < 0.1f32);
+        assert_eq!(stats.clamped_steps, 1);
+    }
+
+    #[test]
+    fn test_finish_starts_a_new_window() {
+        let mut timing = ControlTiming::default();
+        assert_eq!(timing.finish(), None);
+
+        timing.record(Duration::from_millis(500), true);
+        assert!(timing.finish().is_some());
+        assert_eq!(timing.finish(), None);
+    }
+}
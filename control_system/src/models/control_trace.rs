@@ -0,0 +1,364 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+
+use common::physical::ValveState;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    capabilities::parse_flag,
+    cli_args::ArgsError,
+    client_sensor_data::ReadingQuality,
+    control_event::ControlEvent,
+    device_id::DeviceId,
+    temperature::{Temperature, TemperatureUnit},
+};
+
+/// Environment variable used to record why each control frame has the
+/// outputs it does.
+pub const EXPLAIN_ENV_VAR: &str = "PRANDTL_EXPLAIN";
+
+/// The latest trace of every device, shared between the control loop and
+/// the control socket.
+pub type SharedControlTraces = Arc<Mutex<HashMap<DeviceId, ControlTrace>>>;
+
+/// Get shared traces for the control loop to record into if explain mode
+/// is on in the environment. Off if unset or invalid.
+pub fn control_traces_from_env() -> Option<SharedControlTraces> {
+    let value = env::var(EXPLAIN_ENV_VAR).ok();
+    parse_flag(value.as_deref())
+        .unwrap_or(false)
+        .then(SharedControlTraces::default)
+}
+
+/// The control points a curve interpolated between, as `(degC, output)`.
+/// Both are the same point when the temperature is clamped to either end of
+/// the curve, which is where the curve's floor and ceiling come from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CurveSegment {
+    pub from: (f32, f32),
+    pub to: (f32, f32),
+}
+
+impl CurveSegment {
+    pub fn new<Y: Into<f32>>(segment: ((Temperature, Y), (Temperature, Y))) -> Self {
+        let ((x1, y1), (x2, y2)) = segment;
+        Self {
+            from: (x1.into(), y1.into()),
+            to: (x2.into(), y2.into()),
+        }
+    }
+
+    /// Whether the temperature was outside the curve, so its end was used.
+    pub fn is_clamped(&self) -> bool {
+        self.from == self.to
+    }
+
+    /// Describe the segment with its temperatures in `unit`, e.g.
+    /// `between 60.0 degC -> 15.00 and 85.0 degC -> 100.00`.
+    pub fn describe(&self, unit: TemperatureUnit) -> String {
+        let x = |value: f32| Temperature { value }.in_unit(unit);
+        if self.is_clamped() {
+            return format!(
+                "clamped to the curve's end at {:.1} {} -> {:.2}",
+                x(self.from.0),
+                unit,
+                self.from.1
+            );
+        }
+        format!(
+            "between {:.1} {} -> {:.2} and {:.1} {} -> {:.2}",
+            x(self.from.0),
+            unit,
+            self.from.1,
+            x(self.to.0),
+            unit,
+            self.to.1
+        )
+    }
+}
+
+/// What the pump's closed loop feedback did to its curve duty.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PumpFeedback {
+    /// The pump was at `measured`% of its maximum speed, so `adjustment`% was
+    /// added to the curve duty.
+    Applied { measured: f32, adjustment: f32 },
+
+    /// The pump speed reading couldn't be trusted.
+    SkippedQuality(ReadingQuality),
+
+    /// The feedback produced a non-finite duty.
+    SkippedNonFinite,
+}
+
+impl Display for PumpFeedback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PumpFeedback::Applied {
+                measured,
+                adjustment,
+            } => write!(
+                f,
+                "feedback {:+.2}% with the pump at {:.2}%",
+                adjustment, measured
+            ),
+            PumpFeedback::SkippedQuality(quality) => {
+                write!(f, "feedback skipped, pump speed is {}", quality)
+            }
+            PumpFeedback::SkippedNonFinite => write!(f, "feedback skipped, it wasn't finite"),
+        }
+    }
+}
+
+/// Why a fan or pump duty has the value it does.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DutyTrace {
+    /// The curve segment used, or `None` if the lookup failed and the duty
+    /// fell back to 100%.
+    pub segment: Option<CurveSegment>,
+
+    /// The duty the curve gave, or its fallback.
+    pub curve: f32,
+
+    /// The pump's closed loop feedback. Always `None` for the fan.
+    pub feedback: Option<PumpFeedback>,
+
+    /// The duty before it was clamped to 0-100%, if it was outside.
+    pub clamped_from: Option<f32>,
+
+    /// The duty generated, before any blend.
+    pub duty: f32,
+
+    /// Whether a control script commanded the duty, in which case `curve` is
+    /// what the script gave and there is no segment.
+    #[serde(default)]
+    pub scripted: bool,
+}
+
+impl DutyTrace {
+    /// Describe the duty with the curve's temperatures in `unit`.
+    pub fn describe(&self, unit: TemperatureUnit) -> String {
+        let mut description = format!("{:.2}%", self.duty);
+        match self.segment {
+            _ if self.scripted => description.push_str(" from the control script"),
+            Some(segment) => description.push_str(&format!(
+                " from curve {:.2}% {}",
+                self.curve,
+                segment.describe(unit)
+            )),
+            None => description.push_str(" from the 100% fallback, the curve lookup failed"),
+        }
+        if let Some(feedback) = self.feedback {
+            description.push_str(&format!(", {}", feedback));
+        }
+        if let Some(clamped_from) = self.clamped_from {
+            description.push_str(&format!(", clamped from {:.2}%", clamped_from));
+        }
+        description
+    }
+}
+
+/// Why the valve has the state it does.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ValveTrace {
+    /// The curve segment used, or `None` if the lookup failed and the valve
+    /// fell back to open.
+    pub segment: Option<CurveSegment>,
+
+    pub state: ValveState,
+
+    /// Whether the previous command was held instead, because the valve's
+    /// actual state is unknown. See `ValvePolicy`.
+    #[serde(default)]
+    pub held: bool,
+
+    /// Whether a control script commanded the state, in which case there is
+    /// no segment.
+    #[serde(default)]
+    pub scripted: bool,
+}
+
+impl ValveTrace {
+    /// Describe the valve state with the curve's temperatures in `unit`.
+    pub fn describe(&self, unit: TemperatureUnit) -> String {
+        let mut description = match self.segment {
+            _ if self.scripted => format!("{:?} from the control script", self.state),
+            Some(segment) => format!("{:?} from curve {}", self.state, segment.describe(unit)),
+            None => format!(
+                "{:?} from the fallback, the curve lookup failed",
+                self.state
+            ),
+        };
+        if self.held {
+            description.push_str(", held while the valve state is unknown");
+        }
+        description
+    }
+}
+
+/// A blend in progress between the outputs before a switch and the
+/// generated outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BlendTrace {
+    /// How far into the blend the step was, from 0 to 1.
+    pub progress: f32,
+
+    pub from_fan: f32,
+    pub from_pump: f32,
+}
+
+/// Why a single control frame has the outputs it does: the curve segments
+/// used, the pump feedback, clamping, any blend in progress, whether the low
+/// flow interlock was engaged or oscillation damped and whether an override
+/// replaced it all.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ControlTrace {
+    pub device: DeviceId,
+
+    /// The cpu temperature the curves were looked up at, in degC.
+    pub temperature: f32,
+
+    pub fan: DutyTrace,
+    pub pump: DutyTrace,
+    pub valve: ValveTrace,
+    pub blend: Option<BlendTrace>,
+
+    /// Whether the low flow interlock held the valve open and ran the fan at
+    /// full. See `LowFlowPolicy`.
+    #[serde(default)]
+    pub low_flow: bool,
+
+    /// Whether the generated outputs were slew limited because they were
+    /// oscillating. See `OscillationDamper`.
+    #[serde(default)]
+    pub damped: bool,
+
+    /// Whether an override replaced the generated outputs.
+    pub overridden: bool,
+
+    /// The frame which was emitted.
+    pub frame: ControlEvent,
+}
+
+impl ControlTrace {
+    /// Describe the frame over several lines, with its temperatures in
+    /// `unit`.
+    pub fn describe(&self, unit: TemperatureUnit) -> String {
+        let mut description = self.device.to_string();
+        if let Some(sequence) = self.frame.sequence {
+            description.push_str(&format!(" frame {}", sequence));
+        }
+        description.push_str(&format!(
+            " (generation {}) at {:.1} {}:\n",
+            self.frame.generation,
+            Temperature {
+                value: self.temperature
+            }
+            .in_unit(unit),
+            unit
+        ));
+        description.push_str(&format!("  fan: {}\n", self.fan.describe(unit)));
+        description.push_str(&format!("  pump: {}\n", self.pump.describe(unit)));
+        description.push_str(&format!("  valve: {}\n", self.valve.describe(unit)));
+        if let Some(blend) = self.blend {
+            description.push_str(&format!(
+                "  blending {:.0}% of the way from fan {:.2}%, pump {:.2}%\n",
+                blend.progress * 100f32,
+                blend.from_fan,
+                blend.from_pump
+            ));
+        }
+        if self.damped {
+            description.push_str("  oscillating, slew limiting the fan and pump\n");
+        }
+        if self.low_flow {
+            description.push_str("  low flow, holding the valve open with the fan at full\n");
+        }
+        if self.overridden {
+            description.push_str("  overridden, sending the held outputs\n");
+        }
+        let fan: f32 = self.frame.fan_activation.into();
+        let pump: f32 = self.frame.pump_activation.into();
+        description.push_str(&format!(
+            "  sent: fan {:.2}%, pump {:.2}%, valve {:?}",
+            fan, pump, self.frame.valve_state
+        ));
+        description
+    }
+
+    /// Record the blend of `trace`'s outputs `progress` of the way from
+    /// `from`.
+    pub fn with_blend(self, from: ControlEvent, progress: f32) -> Self {
+        let from_fan: f32 = from.fan_activation.into();
+        let from_pump: f32 = from.pump_activation.into();
+        Self {
+            blend: Some(BlendTrace {
+                progress,
+                from_fan,
+                from_pump,
+            }),
+            ..self
+        }
+    }
+}
+
+/// Parse the arguments to `explain`: nothing for every device, or a device's
+/// serial number.
+pub fn parse_explain_args(args: &[String]) -> Result<Option<DeviceId>, ArgsError> {
+    match args {
+        [] => Ok(None),
+        [device] => Ok(Some(DeviceId::new(device))),
+        [_, unknown, ..] => Err(ArgsError::UnknownArgument(unknown.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_explain_args() {
+        assert_eq!(parse_explain_args(&args(&[])), Ok(None));
+        assert_eq!(
+            parse_explain_args(&args(&["1324"])),
+            Ok(Some(DeviceId::new("1324")))
+        );
+        assert_eq!(
+            parse_explain_args(&args(&["1324", "extra"])),
+            Err(ArgsError::UnknownArgument("extra".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_curve_segment_display() {
+        let segment = CurveSegment {
+            from: (60f32, 15f32),
+            to: (85f32, 100f32),
+        };
+        assert!(!segment.is_clamped());
+        assert_eq!(
+            segment.describe(TemperatureUnit::Celsius),
+            "between 60.0 degC -> 15.00 and 85.0 degC -> 100.00"
+        );
+        assert_eq!(
+            segment.describe(TemperatureUnit::Fahrenheit),
+            "between 140.0 degF -> 15.00 and 185.0 degF -> 100.00"
+        );
+
+        let clamped = CurveSegment {
+            from: (0f32, 15f32),
+            to: (0f32, 15f32),
+        };
+        assert!(clamped.is_clamped());
+        assert_eq!(
+            clamped.describe(TemperatureUnit::Fahrenheit),
+            "clamped to the curve's end at 32.0 degF -> 15.00"
+        );
+    }
+}
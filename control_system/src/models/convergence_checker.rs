@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+use common::physical::Rpm;
+
+/// Represents a change in whether a channel's speed is following its command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConvergenceStatus {
+    /// The measured speed hasn't come within tolerance of the expected speed
+    /// for a full window. Likely the output is frozen while telemetry continues.
+    NotTakingEffect { expected: f32, measured: f32 },
+
+    /// The measured speed is back within tolerance after `NotTakingEffect`.
+    Recovered,
+}
+
+/// Verifies that the measured speed of a pump or fan converges toward the
+/// speed expected from its command within a window.
+#[derive(Debug, Clone)]
+pub struct ConvergenceChecker {
+    /// How long the speed may stay out of tolerance before raising an alarm.
+    window: Duration,
+
+    /// Allowed difference between expected and measured speed, relative to
+    /// the channel's maximum speed.
+    tolerance: f32,
+
+    /// The expected speed the current window was started for.
+    expected: Option<f32>,
+
+    /// When the speed was last within tolerance or the command last changed.
+    since: Option<Instant>,
+
+    /// Whether `NotTakingEffect` has been raised and not yet recovered.
+    alarmed: bool,
+}
+
+impl ConvergenceChecker {
+    pub fn new(window: Duration, tolerance: f32) -> Self {
+        Self {
+            window,
+            tolerance,
+            expected: None,
+            since: None,
+            alarmed: false,
+        }
+    }
+
+    /// Observe the expected speed for the current command and the measured speed.
+    /// Returns a status only when it changes, so each alarm is raised once.
+    pub fn observe(
+        &mut self,
+        expected: f32,
+        measured: Rpm,
+        now: Instant,
+    ) -> Option<ConvergenceStatus> {
+        let band = measured.max_speed() * self.tolerance;
+
+        // NOTE: A meaningfully different command gets a fresh window to settle.
+        let command_changed = match self.expected {
+            Some(previous) => (previous - expected).abs() > band,
+            None => true,
+        };
+        if command_changed {
+            self.expected = Some(expected);
+            self.since = Some(now);
+        }
+
+        if (measured.speed() - expected).abs() <= band {
+            self.since = Some(now);
+            if self.alarmed {
+                self.alarmed = false;
+                return Some(ConvergenceStatus::Recovered);
+            }
+            return None;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if self.alarmed || now.duration_since(since) < self.window {
+            return None;
+        }
+
+        self.alarmed = true;
+        Some(ConvergenceStatus::NotTakingEffect {
+            expected,
+            measured: measured.speed(),
+        })
+    }
+
+    /// Check whether `NotTakingEffect` has been raised and not yet recovered.
+    pub fn is_alarmed(&self) -> bool {
+        self.alarmed
+    }
+
+    /// Forget the current window, e.g. while the channel isn't being checked.
+    pub fn reset(&mut self) {
+        self.expected = None;
+        self.since = None;
+        self.alarmed = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpm(speed: f32) -> Rpm {
+        Rpm::new(2000f32, speed).expect("Failed to get RPM.")
+    }
+
+    fn checker() -> ConvergenceChecker {
+        ConvergenceChecker::new(Duration::from_secs(10), 0.1f32)
+    }
+
+    #[test]
+    fn test_converged_speed_raises_nothing() {
+        let mut checker = checker();
+        let start = Instant::now();
+
+        assert_eq!(checker.observe(1000f32, rpm(400f32), start), None);
+        assert_eq!(
+            checker.observe(1000f32, rpm(950f32), start + Duration::from_secs(5)),
+            None
+        );
+        assert_eq!(
+            checker.observe(1000f32, rpm(1050f32), start + Duration::from_secs(30)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_frozen_output_raises_alarm_once() {
+        let mut checker = checker();
+        let start = Instant::now();
+
+        assert_eq!(checker.observe(1500f32, rpm(600f32), start), None);
+        assert_eq!(
+            checker.observe(1500f32, rpm(600f32), start + Duration::from_secs(10)),
+            Some(ConvergenceStatus::NotTakingEffect {
+                expected: 1500f32,
+                measured: 600f32
+            })
+        );
+        assert_eq!(
+            checker.observe(1500f32, rpm(600f32), start + Duration::from_secs(20)),
+            None
+        );
+        assert_eq!(
+            checker.observe(1500f32, rpm(1480f32), start + Duration::from_secs(25)),
+            Some(ConvergenceStatus::Recovered)
+        );
+    }
+
+    #[test]
+    fn test_new_command_restarts_window() {
+        let mut checker = checker();
+        let start = Instant::now();
+
+        checker.observe(1500f32, rpm(400f32), start);
+        assert_eq!(
+            checker.observe(800f32, rpm(400f32), start + Duration::from_secs(8)),
+            None
+        );
+        assert_eq!(
+            checker.observe(800f32, rpm(400f32), start + Duration::from_secs(12)),
+            None
+        );
+        assert!(checker
+            .observe(800f32, rpm(400f32), start + Duration::from_secs(18))
+            .is_some());
+    }
+
+    #[test]
+    fn test_small_command_changes_keep_window() {
+        let mut checker = checker();
+        let start = Instant::now();
+
+        checker.observe(1500f32, rpm(600f32), start);
+        checker.observe(1550f32, rpm(600f32), start + Duration::from_secs(5));
+        assert!(checker
+            .observe(1520f32, rpm(600f32), start + Duration::from_secs(10))
+            .is_some());
+    }
+}
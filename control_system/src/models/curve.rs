@@ -1,4 +1,4 @@
-use std::marker::PhantomData;
+use std::{borrow::Cow, marker::PhantomData};
 use thiserror::Error;
 
 /// This represents a curve mapping some `X` type to some `Y` type.
@@ -6,9 +6,9 @@ use thiserror::Error;
 /// This supports unit based curves. (e.g. RPM vs degC)
 ///
 /// Curves can't be empty.
-pub struct Curve<X: Into<f32>, Y: Into<f32>> {
+pub struct Curve<X: Into<f32> + Clone + 'static, Y: Into<f32> + Clone + 'static> {
     /// Control points for interpolation.
-    points: Vec<(X, Y)>,
+    points: Cow<'static, [(X, Y)]>,
     _marker: PhantomData<()>,
 }
 
@@ -16,6 +16,12 @@ pub struct Curve<X: Into<f32>, Y: Into<f32>> {
 pub enum CurveError {
     #[error("Curves can't be empty.")]
     Empty,
+
+    #[error("Point {0} isn't a finite number.")]
+    NotFinite(usize),
+
+    #[error("Point {0} isn't above the point before it.")]
+    NotIncreasing(usize),
 }
 
 impl<X: Clone + Copy + Into<f32>, Y: Clone + Copy + Into<f32> + TryFrom<f32>> Curve<X, Y> {
@@ -26,15 +32,49 @@ impl<X: Clone + Copy + Into<f32>, Y: Clone + Copy + Into<f32> + TryFrom<f32>> Cu
             return Err(CurveError::Empty);
         }
         Ok(Self {
-            points,
+            points: Cow::Owned(points),
             _marker: PhantomData,
         })
     }
 
+    /// Create a curve from a fixed set of control points in `const` context,
+    /// so default curves need no lazy initialization.
+    /// Panics if `points` is empty, which fails the build when const
+    /// evaluated.
+    pub const fn from_static(points: &'static [(X, Y)]) -> Self {
+        assert!(!points.is_empty(), "Curves can't be empty.");
+        Self {
+            points: Cow::Borrowed(points),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Check every control point is finite and above the one before it, so
+    /// lookups interpolate the way the points read. Points are counted from 0.
+    pub fn validate(&self) -> Result<(), CurveError> {
+        let mut previous_x: Option<f32> = None;
+        for (index, &(x, y)) in self.points.iter().enumerate() {
+            let (x, y): (f32, f32) = (x.into(), y.into());
+            if !x.is_finite() || !y.is_finite() {
+                return Err(CurveError::NotFinite(index));
+            }
+            if previous_x.is_some_and(|previous_x| x <= previous_x) {
+                return Err(CurveError::NotIncreasing(index));
+            }
+            previous_x = Some(x);
+        }
+        Ok(())
+    }
+
     /// Perform a linear interpolation to determine the value for a given x.
     /// This will clamp to the lowest value if `x` is lower than the lowest control point.
     /// This will clamp to the highest value if `x` is higher than the highest control point.
+    /// Returns `None` if `x` is NaN or infinite so it can't poison the output.
     pub fn lookup(&self, x: X) -> Option<Y> {
+        let raw_x: f32 = x.into();
+        if !raw_x.is_finite() {
+            return None;
+        }
         let xy1 = self.find_last_point_before_x(x.clone()).unwrap();
         let xy2 = self.find_first_point_after_x(x.clone()).unwrap();
 
@@ -54,6 +94,24 @@ impl<X: Clone + Copy + Into<f32>, Y: Clone + Copy + Into<f32> + TryFrom<f32>> Cu
         }
     }
 
+    /// Get the control points `lookup` interpolates between for a given x.
+    /// Both are the same point if `x` is clamped to either end of the curve.
+    /// Returns `None` if `x` is NaN or infinite.
+    pub fn segment(&self, x: X) -> Option<((X, Y), (X, Y))> {
+        let raw_x: f32 = x.into();
+        if !raw_x.is_finite() {
+            return None;
+        }
+        let xy1 = self.find_last_point_before_x(x)?;
+        let xy2 = self.find_first_point_after_x(x)?;
+        let x1: f32 = xy1.0.into();
+        let x2: f32 = xy2.0.into();
+        if x1 == x2 {
+            return Some((xy1, xy1));
+        }
+        Some((xy1, xy2))
+    }
+
     /// Find the last point before `x` or the earliest point.
     /// E.g. for the curve containing [(0,0), (10,1)]:
     ///     find_last_point_before_x(-3) -> (0,0)
@@ -62,15 +120,15 @@ impl<X: Clone + Copy + Into<f32>, Y: Clone + Copy + Into<f32> + TryFrom<f32>> Cu
     fn find_last_point_before_x(&self, x: X) -> Option<(X, Y)> {
         let mut point_xs = self
             .points
-            .clone()
-            .into_iter()
+            .iter()
+            .copied()
             .filter(|xi| xi.0.into() <= x.into())
             .collect::<Vec<_>>();
         point_xs.sort_by(|x, y| x.0.into().partial_cmp(&y.0.into()).unwrap());
         point_xs.into_iter().last().or(self
             .points
-            .clone()
-            .into_iter()
+            .iter()
+            .copied()
             .min_by(|x, y| x.0.into().partial_cmp(&y.0.into()).unwrap()))
     }
 
@@ -82,15 +140,15 @@ impl<X: Clone + Copy + Into<f32>, Y: Clone + Copy + Into<f32> + TryFrom<f32>> Cu
     fn find_first_point_after_x(&self, x: X) -> Option<(X, Y)> {
         let mut point_xs = self
             .points
-            .clone()
-            .into_iter()
+            .iter()
+            .copied()
             .filter(|xi| x.into() <= xi.0.into())
             .collect::<Vec<_>>();
         point_xs.sort_by(|x, y| x.0.into().partial_cmp(&y.0.into()).unwrap());
         point_xs.into_iter().rev().last().or(self
             .points
-            .clone()
-            .into_iter()
+            .iter()
+            .copied()
             .max_by(|x, y| x.0.into().partial_cmp(&y.0.into()).unwrap()))
     }
 }
@@ -146,6 +204,21 @@ mod tests {
         assert_eq!(curve.lookup(100f32).expect("Failed to lookup value"), 10f32);
     }
 
+    #[test]
+    fn test_validate() {
+        let curve = |points: &[(f32, f32)]| Curve::new(points.to_vec()).unwrap().validate();
+
+        assert!(curve(&[(0f32, 0f32), (3f32, 3f32), (10f32, 10f32)]).is_ok());
+        assert!(matches!(
+            curve(&[(0f32, 0f32), (3f32, 3f32), (3f32, 10f32)]),
+            Err(CurveError::NotIncreasing(2))
+        ));
+        assert!(matches!(
+            curve(&[(0f32, 0f32), (f32::NAN, 3f32)]),
+            Err(CurveError::NotFinite(1))
+        ));
+    }
+
     #[derive(Copy, Clone, PartialEq, PartialOrd)]
     struct TempC {
         value: f32,
@@ -202,4 +275,24 @@ mod tests {
             100f32
         );
     }
+
+    #[test]
+    fn test_lookup_rejects_non_finite() {
+        let curve = Curve::new(vec![(0f32, 0f32), (10f32, 10f32)]).unwrap();
+        assert_eq!(curve.lookup(f32::NAN), None);
+        assert_eq!(curve.lookup(f32::INFINITY), None);
+    }
+
+    #[test]
+    fn test_segment() {
+        let curve = Curve::new(vec![(0f32, 0f32), (3f32, 3f32), (10f32, 10f32)]).unwrap();
+        assert_eq!(curve.segment(-3f32), Some(((0f32, 0f32), (0f32, 0f32))));
+        assert_eq!(curve.segment(1f32), Some(((0f32, 0f32), (3f32, 3f32))));
+        assert_eq!(curve.segment(3f32), Some(((3f32, 3f32), (3f32, 3f32))));
+        assert_eq!(
+            curve.segment(100f32),
+            Some(((10f32, 10f32), (10f32, 10f32)))
+        );
+        assert_eq!(curve.segment(f32::NAN), None);
+    }
 }
@@ -0,0 +1,365 @@
+use common::physical::{Percentage, ValveState};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    cli_args::{Args, ArgsError},
+    curve::Curve,
+    temperature::{Temperature, TemperatureUnit},
+};
+
+/// How many samples a preview takes when none are asked for.
+pub const DEFAULT_CURVE_SAMPLES: usize = 21;
+
+/// The most samples a single preview can ask for.
+pub const MAX_CURVE_SAMPLES: usize = 1000;
+
+/// How far past its first and last control points a curve is sampled, in
+/// degC, so the clamping at either end shows up in the preview.
+const CURVE_PREVIEW_MARGIN: f32 = 10f32;
+
+/// What a curve drives, which decides how its control points are checked and
+/// how it is evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CurveOutput {
+    /// Pump or fan duty in percent, like the pump and fan curves.
+    Duty,
+
+    /// Valve state, 0 for closed and 1 for open, like the valve curve.
+    Valve,
+}
+
+/// A candidate curve, as edited in a curve editor. Control points are
+/// `(degC, output)` pairs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CurveDefinition {
+    pub output: CurveOutput,
+    pub points: Vec<(f32, f32)>,
+}
+
+/// A single point sampled from a curve. `y` is `None` where the controller
+/// would get no output, such as above the highest valid temperature.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CurveSample {
+    pub x: f32,
+    pub y: Option<f32>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CurvePreviewError {
+    #[error("Curves can't be empty.")]
+    Empty,
+
+    #[error("Invalid temperature {0} degC in control point.")]
+    InvalidTemperature(f32),
+
+    #[error("Invalid duty {0}% in control point, expected 0 to 100.")]
+    InvalidDuty(f32),
+
+    #[error("Invalid valve state {0} in control point, expected 0 (closed) or 1 (open).")]
+    InvalidValveState(f32),
+
+    #[error("Expected 2 to {MAX_CURVE_SAMPLES} samples but got {0}.")]
+    InvalidSampleCount(usize),
+}
+
+impl CurveDefinition {
+    /// Sample the curve at `samples` evenly spaced temperatures, from a little
+    /// below its first control point to a little above its last. Each sample
+    /// is evaluated by the same `Curve` and unit types the controller uses, so
+    /// the preview clamps and rejects values exactly as the controller would.
+    pub fn sample(&self, samples: usize) -> Result<Vec<CurveSample>, CurvePreviewError> {
+        if !(2..=MAX_CURVE_SAMPLES).contains(&samples) {
+            return Err(CurvePreviewError::InvalidSampleCount(samples));
+        }
+        let lookup = self.lookup()?;
+
+        let from = self
+            .points
+            .iter()
+            .map(|point| point.0)
+            .fold(f32::MAX, f32::min)
+            - CURVE_PREVIEW_MARGIN;
+        let to = self
+            .points
+            .iter()
+            .map(|point| point.0)
+            .fold(f32::MIN, f32::max)
+            + CURVE_PREVIEW_MARGIN;
+        let step = (to - from) / (samples - 1) as f32;
+
+        Ok((0..samples)
+            .map(|index| {
+                let x = from + step * index as f32;
+                let y = Temperature::try_from(x).ok().and_then(&lookup);
+                CurveSample { x, y }
+            })
+            .collect())
+    }
+
+    /// Build the curve, returning a function which looks up its raw output.
+    fn lookup(&self) -> Result<Box<dyn Fn(Temperature) -> Option<f32>>, CurvePreviewError> {
+        if self.points.is_empty() {
+            return Err(CurvePreviewError::Empty);
+        }
+        let temperatures = self
+            .points
+            .iter()
+            .map(|&(x, _)| {
+                Temperature::try_from(x).map_err(|_| CurvePreviewError::InvalidTemperature(x))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(match self.output {
+            CurveOutput::Duty => {
+                let duties = self
+                    .points
+                    .iter()
+                    .map(|&(_, y)| {
+                        Percentage::try_from(y).map_err(|_| CurvePreviewError::InvalidDuty(y))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let curve = Curve::new(temperatures.into_iter().zip(duties).collect())
+                    .map_err(|_| CurvePreviewError::Empty)?;
+                Box::new(move |x| curve.lookup(x).map(Into::into))
+            }
+            CurveOutput::Valve => {
+                let states = self
+                    .points
+                    .iter()
+                    .map(|&(_, y)| {
+                        ValveState::try_from(y).map_err(|_| CurvePreviewError::InvalidValveState(y))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let curve = Curve::new(temperatures.into_iter().zip(states).collect())
+                    .map_err(|_| CurvePreviewError::Empty)?;
+                Box::new(move |x| curve.lookup(x).map(Into::into))
+            }
+        })
+    }
+}
+
+/// What `curve` should preview.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurveCommand {
+    pub curve: CurveDefinition,
+    pub samples: usize,
+}
+
+/// Parse the arguments to `curve`: the output, `duty` or `valve`, followed by
+/// control points like `50:30` with temperatures in `unit` and optionally
+/// `--samples <N>`.
+pub fn parse_curve_args(args: &[String], unit: TemperatureUnit) -> Result<CurveCommand, ArgsError> {
+    let (output, args) = args.split_first().ok_or_else(|| {
+        ArgsError::Missing("'duty' or 'valve' followed by control points like '50:30'".to_string())
+    })?;
+    let output = match output.as_str() {
+        "duty" => CurveOutput::Duty,
+        "valve" => CurveOutput::Valve,
+        _ => {
+            return Err(ArgsError::InvalidValue(
+                "the curve output".to_string(),
+                output.clone(),
+                "'duty' or 'valve'".to_string(),
+            ))
+        }
+    };
+
+    let mut command = CurveCommand {
+        curve: CurveDefinition {
+            output,
+            points: vec![],
+        },
+        samples: DEFAULT_CURVE_SAMPLES,
+    };
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        if arg == "--samples" {
+            command.samples = args.parse(arg, "a number of samples", |_| true)?;
+        } else if arg.starts_with("--") {
+            return Err(ArgsError::UnknownArgument(arg.clone()));
+        } else {
+            command.curve.points.push(parse_point(arg, unit)?);
+        }
+    }
+    Ok(command)
+}
+
+/// Parse a control point like `50:30`, with its temperature given in `unit`,
+/// into a degC control point.
+fn parse_point(value: &str, unit: TemperatureUnit) -> Result<(f32, f32), ArgsError> {
+    let invalid = || {
+        ArgsError::InvalidValue(
+            "a control point".to_string(),
+            value.to_string(),
+            format!("'<{}>:<value>'", unit),
+        )
+    };
+    let (x, y) = value.split_once(':').ok_or_else(invalid)?;
+    let x: f32 = x.trim().parse().map_err(|_| invalid())?;
+    Ok((unit.to_celsius(x), y.trim().parse().map_err(|_| invalid())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::cli_args::args;
+
+    fn duty_curve(points: &[(f32, f32)]) -> CurveDefinition {
+        CurveDefinition {
+            output: CurveOutput::Duty,
+            points: points.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_sample_clamps_like_the_controller() {
+        let curve = duty_curve(&[(50f32, 30f32), (70f32, 70f32)]);
+        let samples = curve.sample(5).expect("Failed to sample curve.");
+
+        assert_eq!(
+            samples,
+            vec![
+                CurveSample {
+                    x: 40f32,
+                    y: Some(30f32)
+                },
+                CurveSample {
+                    x: 50f32,
+                    y: Some(30f32)
+                },
+                CurveSample {
+                    x: 60f32,
+                    y: Some(50f32)
+                },
+                CurveSample {
+                    x: 70f32,
+                    y: Some(70f32)
+                },
+                CurveSample {
+                    x: 80f32,
+                    y: Some(70f32)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sample_above_max_temperature() {
+        let curve = duty_curve(&[(80f32, 50f32), (95f32, 100f32)]);
+        let samples = curve.sample(2).expect("Failed to sample curve.");
+        assert_eq!(samples[1], CurveSample { x: 105f32, y: None });
+    }
+
+    #[test]
+    fn test_sample_valve() {
+        let curve = CurveDefinition {
+            output: CurveOutput::Valve,
+            points: vec![(59f32, 1f32), (60f32, 0f32)],
+        };
+        let samples = curve.sample(3).expect("Failed to sample curve.");
+        assert_eq!(
+            samples.iter().map(|sample| sample.y).collect::<Vec<_>>(),
+            vec![Some(1f32), Some(1f32), Some(0f32)]
+        );
+    }
+
+    #[test]
+    fn test_sample_errors() {
+        assert_eq!(duty_curve(&[]).sample(5), Err(CurvePreviewError::Empty));
+        assert_eq!(
+            duty_curve(&[(50f32, 120f32)]).sample(5),
+            Err(CurvePreviewError::InvalidDuty(120f32))
+        );
+        assert_eq!(
+            duty_curve(&[(150f32, 50f32)]).sample(5),
+            Err(CurvePreviewError::InvalidTemperature(150f32))
+        );
+        assert_eq!(
+            duty_curve(&[(50f32, 50f32)]).sample(1),
+            Err(CurvePreviewError::InvalidSampleCount(1))
+        );
+        let valve = CurveDefinition {
+            output: CurveOutput::Valve,
+            points: vec![(50f32, 2f32)],
+        };
+        assert_eq!(
+            valve.sample(5),
+            Err(CurvePreviewError::InvalidValveState(2f32))
+        );
+    }
+
+    #[test]
+    fn test_parse_curve_args() {
+        let unit = TemperatureUnit::Celsius;
+        assert_eq!(
+            parse_curve_args(&args(&["duty", "50:30", "80:90", "--samples", "5"]), unit),
+            Ok(CurveCommand {
+                curve: duty_curve(&[(50f32, 30f32), (80f32, 90f32)]),
+                samples: 5,
+            })
+        );
+        assert_eq!(
+            parse_curve_args(&args(&["valve", "60:0"]), unit),
+            Ok(CurveCommand {
+                curve: CurveDefinition {
+                    output: CurveOutput::Valve,
+                    points: vec![(60f32, 0f32)],
+                },
+                samples: DEFAULT_CURVE_SAMPLES,
+            })
+        );
+        assert_eq!(
+            parse_curve_args(&args(&[]), unit),
+            Err(ArgsError::Missing(
+                "'duty' or 'valve' followed by control points like '50:30'".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_curve_args(&args(&["pump"]), unit),
+            Err(ArgsError::InvalidValue(
+                "the curve output".to_string(),
+                "pump".to_string(),
+                "'duty' or 'valve'".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_curve_args(&args(&["duty", "50"]), unit),
+            Err(ArgsError::InvalidValue(
+                "a control point".to_string(),
+                "50".to_string(),
+                "'<degC>:<value>'".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_curve_args(&args(&["duty", "122:30"]), TemperatureUnit::Fahrenheit)
+                .map(|command| command.curve.points),
+            Ok(vec![(50f32, 30f32)])
+        );
+        assert_eq!(
+            parse_curve_args(&args(&["duty", "50"]), TemperatureUnit::Fahrenheit),
+            Err(ArgsError::InvalidValue(
+                "a control point".to_string(),
+                "50".to_string(),
+                "'<degF>:<value>'".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_curve_args(&args(&["duty", "--samples"]), unit),
+            Err(ArgsError::MissingValue("--samples".to_string()))
+        );
+        assert_eq!(
+            parse_curve_args(&args(&["duty", "--samples", "many"]), unit),
+            Err(ArgsError::InvalidValue(
+                "'--samples'".to_string(),
+                "many".to_string(),
+                "a number of samples".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_curve_args(&args(&["duty", "--from", "20"]), unit),
+            Err(ArgsError::UnknownArgument("--from".to_string()))
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use std::{collections::BTreeMap, env};
+
+use serde::{Deserialize, Serialize};
+
+use super::link::LinkConfig;
+
+/// Prefix of the environment variables which configure the control system.
+const CONFIG_ENV_PREFIX: &str = "PRANDTL_";
+
+/// Configuration values whose names contain any of these are redacted.
+const SECRET_MARKERS: [&str; 5] = ["SECRET", "TOKEN", "PASSWORD", "KEY", "CREDENTIAL"];
+
+const REDACTED: &str = "<redacted>";
+
+/// The configuration of the running control system, as served over the
+/// control socket. What the CLI was started with can differ from it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaemonConfig {
+    pub version: String,
+
+    /// `PRANDTL_*` environment variables with secrets redacted.
+    pub config: BTreeMap<String, String>,
+    pub link_config: LinkConfig,
+}
+
+impl DaemonConfig {
+    /// Get the configuration of this process from the environment.
+    pub fn from_env() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            config: redacted_config(env::vars()),
+            link_config: LinkConfig::from_env(),
+        }
+    }
+}
+
+/// Get the configuration variables from `vars`, redacting anything which
+/// looks like a secret.
+fn redacted_config(vars: impl Iterator<Item = (String, String)>) -> BTreeMap<String, String> {
+    vars.filter(|(name, _)| name.starts_with(CONFIG_ENV_PREFIX))
+        .map(|(name, value)| {
+            let upper = name.to_uppercase();
+            if SECRET_MARKERS.iter().any(|marker| upper.contains(marker)) {
+                (name, REDACTED.to_string())
+            } else {
+                (name, value)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted_config() {
+        let vars = vec![
+            ("PRANDTL_BAUD_RATE".to_string(), "9600".to_string()),
+            ("PRANDTL_API_TOKEN".to_string(), "hunter2".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        let config = redacted_config(vars.into_iter());
+        assert_eq!(config.len(), 2);
+        assert_eq!(config["PRANDTL_BAUD_RATE"], "9600");
+        assert_eq!(config["PRANDTL_API_TOKEN"], REDACTED);
+    }
+}
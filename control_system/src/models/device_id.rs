@@ -0,0 +1,51 @@
+use std::fmt::Display;
+
+use fixedstr::str16;
+use serde::{Deserialize, Serialize};
+
+/// Identifies a single embedded hardware device by its USB serial number.
+/// Used to address control events and to tag sensor data with its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DeviceId(str16);
+
+impl DeviceId {
+    /// Create a device id from a USB serial number.
+    /// Serial numbers longer than 15 characters are truncated.
+    pub fn new(serial_number: &str) -> Self {
+        Self(str16::make(serial_number))
+    }
+
+    /// Get the serial number this id represents.
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(DeviceId {})", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let device = DeviceId::new("1324");
+        assert_eq!(device.as_str(), "1324");
+        assert_eq!(device, DeviceId::new("1324"));
+        assert_ne!(device, DeviceId::new("1325"));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let device = DeviceId::new("1324");
+        let device_ser = serde_json::to_string(&device).expect("Failed to serialize DeviceId.");
+        assert_eq!(device_ser, "\"1324\"");
+        let device_deser: DeviceId =
+            serde_json::from_str(&device_ser).expect("Failed to deserialize DeviceId.");
+        assert_eq!(device, device_deser);
+    }
+}
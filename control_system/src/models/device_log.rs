@@ -0,0 +1,112 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    env,
+    sync::{Arc, Mutex},
+};
+
+use common::packet::ReportLogLinePacket;
+use serde::{Deserialize, Serialize};
+
+use super::{capabilities::parse_flag, device_id::DeviceId};
+
+/// Environment variable used to journal the log lines devices report, on top
+/// of forwarding them into the host's logs.
+pub const JOURNAL_DEVICE_LOGS_ENV_VAR: &str = "PRANDTL_JOURNAL_DEVICE_LOGS";
+
+/// How many log lines are kept for each device. The oldest are dropped first.
+pub const DEVICE_LOG_CAPACITY: usize = 64;
+
+/// Get whether device log lines are journaled from the environment. Off if
+/// unset or invalid, since chatty firmware would crowd out the rest of the
+/// journal.
+pub fn journal_device_logs_from_env() -> bool {
+    let value = env::var(JOURNAL_DEVICE_LOGS_ENV_VAR).ok();
+    parse_flag(value.as_deref()).unwrap_or(false)
+}
+
+/// The log lines a device sent, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceLog {
+    pub device: DeviceId,
+    pub lines: Vec<ReportLogLinePacket>,
+}
+
+/// The latest log lines from each device which has connected.
+#[derive(Debug, Default)]
+pub struct DeviceLogs {
+    devices: BTreeMap<DeviceId, VecDeque<ReportLogLinePacket>>,
+}
+
+impl DeviceLogs {
+    /// Start keeping lines for `device`, if they aren't kept already.
+    pub fn track(&mut self, device: DeviceId) {
+        self.devices.entry(device).or_default();
+    }
+
+    /// Keep a line from `device`, dropping its oldest if it has
+    /// `DEVICE_LOG_CAPACITY` already.
+    pub fn record(&mut self, device: DeviceId, line: ReportLogLinePacket) {
+        let lines = self.devices.entry(device).or_default();
+        if lines.len() == DEVICE_LOG_CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Forget every line kept so far, ahead of the devices sending their
+    /// log buffers again. Returns the devices being tracked.
+    pub fn clear(&mut self) -> Vec<DeviceId> {
+        self.devices.values_mut().for_each(VecDeque::clear);
+        self.devices.keys().copied().collect()
+    }
+
+    /// Get the lines kept for every device.
+    pub fn logs(&self) -> Vec<DeviceLog> {
+        self.devices
+            .iter()
+            .map(|(device, lines)| DeviceLog {
+                device: *device,
+                lines: lines.iter().copied().collect(),
+            })
+            .collect()
+    }
+}
+
+/// Device logs shared between the task forwarding them and the control
+/// socket.
+/// NOTE: Never hold the lock across an `await`.
+pub type SharedDeviceLogs = Arc<Mutex<DeviceLogs>>;
+
+#[cfg(test)]
+mod tests {
+    use common::packet::LogLevel;
+
+    use super::*;
+
+    fn line(message: &str) -> ReportLogLinePacket {
+        ReportLogLinePacket {
+            level: LogLevel::Info,
+            module: 0,
+            message: message.into(),
+        }
+    }
+
+    #[test]
+    fn test_device_logs() {
+        let (first, second) = (DeviceId::new("1324"), DeviceId::new("5768"));
+        let mut logs = DeviceLogs::default();
+        logs.track(first);
+        for index in 0..DEVICE_LOG_CAPACITY + 1 {
+            logs.record(second, line(&index.to_string()));
+        }
+
+        let dumped = logs.logs();
+        assert_eq!(dumped.len(), 2);
+        assert_eq!(dumped[0].lines, []);
+        assert_eq!(dumped[1].lines.len(), DEVICE_LOG_CAPACITY);
+        assert_eq!(dumped[1].lines[0], line("1"));
+
+        assert_eq!(logs.clear(), [first, second]);
+        assert!(logs.logs().iter().all(|log| log.lines.is_empty()));
+    }
+}
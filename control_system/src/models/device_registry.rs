@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::duty_rpm_model::DutyRpmModel;
+
+/// Default location of the persisted device registry.
+pub const DEVICE_REGISTRY_PATH: &str = "device_registry.json";
+
+/// Represents everything the host has learned about a single device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    /// The learned maximum pump speed at 100% duty.
+    pub pump_max_rpm: Option<f32>,
+
+    /// The learned maximum fan speed at 100% duty.
+    pub fan_max_rpm: Option<f32>,
+
+    /// The learned pump speed at each duty.
+    #[serde(default)]
+    pub pump_duty_rpm: DutyRpmModel,
+
+    /// The learned fan speed at each duty.
+    #[serde(default)]
+    pub fan_duty_rpm: DutyRpmModel,
+}
+
+/// Persistent registry of learned device characteristics keyed by the
+/// device's USB serial number.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceRegistry {
+    devices: HashMap<String, DeviceRecord>,
+}
+
+/// A device registry shared between the tasks which learn and use it.
+/// NOTE: Never hold the lock across an `await`.
+pub type SharedDeviceRegistry = Arc<RwLock<DeviceRegistry>>;
+
+#[derive(Error, Debug)]
+pub enum DeviceRegistryError {
+    /// This occurs if the registry file can't be read or written.
+    #[error("Failed to access device registry file.")]
+    Io(io::Error),
+
+    /// This occurs if the registry file contents are invalid.
+    #[error("Failed to parse device registry.")]
+    Parse(serde_json::Error),
+}
+
+impl DeviceRegistry {
+    /// Load the registry from `path`. A missing file results in an empty registry.
+    pub fn load(path: &Path) -> Result<Self, DeviceRegistryError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(DeviceRegistryError::Io(e)),
+        };
+        serde_json::from_str(&contents).map_err(DeviceRegistryError::Parse)
+    }
+
+    /// Persist the registry to `path`, replacing any previous contents.
+    pub fn save(&self, path: &Path) -> Result<(), DeviceRegistryError> {
+        let contents = serde_json::to_string_pretty(self).map_err(DeviceRegistryError::Parse)?;
+        fs::write(path, contents).map_err(DeviceRegistryError::Io)
+    }
+
+    /// Get a copy of the record for a device. Unknown devices get an empty record.
+    pub fn record(&self, serial_number: &str) -> DeviceRecord {
+        self.devices.get(serial_number).cloned().unwrap_or_default()
+    }
+
+    /// Get a mutable reference to the record for a device, creating it if needed.
+    pub fn record_mut(&mut self, serial_number: &str) -> &mut DeviceRecord {
+        self.devices.entry(serial_number.to_string()).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_defaults_to_empty() {
+        let registry = DeviceRegistry::default();
+        assert_eq!(registry.record("1324"), DeviceRecord::default());
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "prandtl_device_registry_test_{}.json",
+            std::process::id()
+        ));
+
+        let mut registry = DeviceRegistry::default();
+        registry.record_mut("1324").pump_max_rpm = Some(1750f32);
+        registry.save(&path).expect("Failed to save registry.");
+
+        let loaded = DeviceRegistry::load(&path).expect("Failed to load registry.");
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(loaded, registry);
+        assert_eq!(loaded.record("1324").pump_max_rpm, Some(1750f32));
+        assert_eq!(loaded.record("1324").fan_max_rpm, None);
+    }
+
+    #[test]
+    fn test_load_without_duty_rpm_models() {
+        let registry: DeviceRegistry = serde_json::from_str(
+            r#"{"devices":{"1324":{"pump_max_rpm":1750.0,"fan_max_rpm":null}}}"#,
+        )
+        .expect("Failed to parse registry.");
+        assert_eq!(registry.record("1324").pump_max_rpm, Some(1750f32));
+        assert!(registry.record("1324").pump_duty_rpm.is_empty());
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let path = std::env::temp_dir().join("prandtl_device_registry_does_not_exist.json");
+        let registry = DeviceRegistry::load(&path).expect("Failed to load registry.");
+        assert_eq!(registry, DeviceRegistry::default());
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::error::ControlError;
+
+use super::{
+    cli_args::{Args, ArgsError},
+    daemon_config::DaemonConfig,
+    device_id::DeviceId,
+    device_log::DeviceLog,
+    device_registry::DeviceRegistry,
+    journal::{unix_time_ms, JournalEntry, JournalRecord},
+    link::LinkStats,
+    telemetry_store::TelemetryStore,
+};
+
+/// How much telemetry is bundled if not specified.
+pub const DEFAULT_BUNDLE_MINUTES: u64 = 10;
+
+/// Options for `diag bundle`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleOptions {
+    /// How many minutes of journaled telemetry to include.
+    pub minutes: u64,
+
+    /// Where to write the bundle. Defaults to a timestamped file in the
+    /// working directory.
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error("Failed to encode diagnostic bundle.")]
+    Encode(serde_json::Error),
+
+    #[error("Failed to write diagnostic bundle.")]
+    Io(std::io::Error),
+}
+
+/// Everything needed to make sense of a bug report, collected into a single
+/// tar archive. `bundle.json` holds everything but the journal, which is in
+/// `journal.jsonl`, and the device logs, which are in `firmware_logs/` with a
+/// file for each device.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticBundle {
+    /// Milliseconds since the unix epoch when the bundle was collected.
+    pub created_at_ms: u64,
+
+    /// The version of the CLI which collected the bundle.
+    pub version: &'static str,
+    pub minutes: u64,
+
+    /// The configuration of the running control system, if it answered.
+    pub daemon_config: Option<DaemonConfig>,
+
+    pub device_registry: Option<DeviceRegistry>,
+
+    /// The most recently journaled link stats for each device.
+    pub link_stats: BTreeMap<String, LinkStats>,
+
+    /// Journaled telemetry, control frames and link stats in journal order.
+    #[serde(skip)]
+    pub journal: Vec<JournalEntry>,
+
+    /// The log buffer of each device connected to the running control system.
+    #[serde(skip)]
+    pub device_logs: Vec<DeviceLog>,
+
+    /// Anything which was expected but couldn't be collected, and why.
+    pub problems: Vec<String>,
+}
+
+/// Parse the arguments following `diag bundle`.
+/// Accepts `--minutes <N>` and `--output <PATH>`.
+pub fn parse_bundle_args(args: &[String]) -> Result<BundleOptions, ArgsError> {
+    let mut options = BundleOptions {
+        minutes: DEFAULT_BUNDLE_MINUTES,
+        output: None,
+    };
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--minutes" => {
+                options.minutes =
+                    args.parse(arg, "a positive number of minutes", |&minutes| minutes > 0)?;
+            }
+            "--output" => options.output = Some(PathBuf::from(args.value(arg)?)),
+            _ => return Err(ArgsError::UnknownArgument(arg.clone())),
+        }
+    }
+    Ok(options)
+}
+
+/// Get the newest link stats journaled for each device.
+fn latest_link_stats(journal: &[JournalEntry]) -> BTreeMap<String, LinkStats> {
+    let latest: HashMap<DeviceId, LinkStats> = journal
+        .iter()
+        .filter_map(|entry| match entry.record {
+            JournalRecord::LinkStats { device, stats } => Some((device, stats)),
+            _ => None,
+        })
+        .collect();
+    latest
+        .into_iter()
+        .map(|(device, stats)| (device.as_str().to_string(), stats))
+        .collect()
+}
+
+impl DiagnosticBundle {
+    /// Collect a bundle from the running system's persisted state, along
+    /// with the config and device logs it reported over the control socket.
+    /// Failures are recorded in `problems` rather than aborting, since a
+    /// partial bundle is still useful.
+    pub fn collect(
+        options: &BundleOptions,
+        now: SystemTime,
+        daemon_config: Result<DaemonConfig, ControlError>,
+        device_logs: Result<Vec<DeviceLog>, ControlError>,
+        registry_path: &Path,
+        journal: &dyn TelemetryStore,
+    ) -> Self {
+        let mut problems = vec![];
+
+        let daemon_config = match daemon_config {
+            Ok(config) => Some(config),
+            Err(e) => {
+                problems.push(format!(
+                    "Failed to get the control system's config. Error: {}",
+                    e
+                ));
+                None
+            }
+        };
+        let device_logs = match device_logs {
+            Ok(logs) if logs.is_empty() => {
+                problems.push("No devices have connected to dump logs from.".to_string());
+                logs
+            }
+            Ok(logs) => logs,
+            Err(e) => {
+                problems.push(format!("Failed to get device logs. Error: {}", e));
+                vec![]
+            }
+        };
+
+        let device_registry = match DeviceRegistry::load(registry_path) {
+            Ok(registry) => Some(registry),
+            Err(e) => {
+                problems.push(format!("Failed to load device registry. Error: {}", e));
+                None
+            }
+        };
+
+        let since = now
+            .checked_sub(Duration::from_secs(options.minutes * 60))
+            .map(unix_time_ms)
+            .unwrap_or(0);
+        let journal = match journal.query(since, u64::MAX) {
+            Ok(journal) => journal,
+            Err(e) => {
+                problems.push(format!("Failed to read journal. Error: {}", e));
+                vec![]
+            }
+        };
+        if journal.is_empty() {
+            problems.push(format!(
+                "No journal entries in the last {} minutes. Is the control system running?",
+                options.minutes
+            ));
+        }
+
+        Self {
+            created_at_ms: unix_time_ms(now),
+            version: env!("CARGO_PKG_VERSION"),
+            minutes: options.minutes,
+            daemon_config,
+            device_registry,
+            link_stats: latest_link_stats(&journal),
+            journal,
+            device_logs,
+            problems,
+        }
+    }
+
+    /// Get the default file name for a bundle.
+    pub fn default_file_name(&self) -> PathBuf {
+        PathBuf::from(format!("prandtl_diag_{}.tar", self.created_at_ms))
+    }
+
+    /// Write the bundle to `path` as a tar archive.
+    pub fn write(&self, path: &Path) -> Result<(), BundleError> {
+        let file = File::create(path).map_err(BundleError::Io)?;
+        let mut archive = tar::Builder::new(file);
+        let modified_s = self.created_at_ms / 1000;
+
+        let summary = serde_json::to_vec_pretty(self).map_err(BundleError::Encode)?;
+        append_file(&mut archive, "bundle.json", &summary, modified_s)?;
+
+        let mut journal = Vec::new();
+        for entry in &self.journal {
+            serde_json::to_writer(&mut journal, entry).map_err(BundleError::Encode)?;
+            journal.push(b'\n');
+        }
+        append_file(&mut archive, "journal.jsonl", &journal, modified_s)?;
+
+        for log in &self.device_logs {
+            let lines: String = log
+                .lines
+                .iter()
+                .map(|line| format!("{} module {}: {}\n", line.level, line.module, line.message))
+                .collect();
+            let name = format!("firmware_logs/{}.log", log.device.as_str());
+            append_file(&mut archive, &name, lines.as_bytes(), modified_s)?;
+        }
+
+        archive
+            .into_inner()
+            .and_then(|file| file.sync_all())
+            .map_err(BundleError::Io)
+    }
+}
+
+/// Add a file called `name` holding `contents` to `archive`.
+fn append_file(
+    archive: &mut tar::Builder<File>,
+    name: &str,
+    contents: &[u8],
+    modified_s: u64,
+) -> Result<(), BundleError> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(modified_s);
+    archive
+        .append_data(&mut header, name, contents)
+        .map_err(BundleError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Read};
+
+    use common::packet::{LogLevel, ReportLogLinePacket};
+
+    use crate::models::{cli_args::args, link::LinkConfig, telemetry_store::JsonlStore};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_bundle_args() {
+        assert_eq!(
+            parse_bundle_args(&args(&[])),
+            Ok(BundleOptions {
+                minutes: DEFAULT_BUNDLE_MINUTES,
+                output: None
+            })
+        );
+        assert_eq!(
+            parse_bundle_args(&args(&["--minutes", "5", "--output", "bug.tar"])),
+            Ok(BundleOptions {
+                minutes: 5,
+                output: Some(PathBuf::from("bug.tar"))
+            })
+        );
+        assert_eq!(
+            parse_bundle_args(&args(&["--minutes", "0"])),
+            Err(ArgsError::InvalidValue(
+                "'--minutes'".to_string(),
+                "0".to_string(),
+                "a positive number of minutes".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_bundle_args(&args(&["--output"])),
+            Err(ArgsError::MissingValue("--output".to_string()))
+        );
+        assert_eq!(
+            parse_bundle_args(&args(&["--verbose"])),
+            Err(ArgsError::UnknownArgument("--verbose".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_collect_recent_journal() {
+        let journal_path = std::env::temp_dir().join(format!(
+            "prandtl_diag_journal_test_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&journal_path);
+
+        let now = SystemTime::now();
+        let device = DeviceId::new("1324");
+        let stats = |packets_sent| LinkStats {
+            packets_sent,
+            ..LinkStats::default()
+        };
+        let entry = |age: Duration, stats| JournalEntry {
+            timestamp_ms: unix_time_ms(now - age),
+            record: JournalRecord::LinkStats { device, stats },
+        };
+        let mut journal = JsonlStore::new(&journal_path);
+        journal
+            .append(&[
+                entry(Duration::from_secs(600), stats(1)),
+                entry(Duration::from_secs(60), stats(2)),
+                entry(Duration::from_secs(30), stats(3)),
+            ])
+            .expect("Failed to append entries.");
+
+        let options = BundleOptions {
+            minutes: 5,
+            output: None,
+        };
+        let config = DaemonConfig {
+            version: "0.1.0".to_string(),
+            config: BTreeMap::from([("PRANDTL_BAUD_RATE".to_string(), "9600".to_string())]),
+            link_config: LinkConfig::default(),
+        };
+        let log = DeviceLog {
+            device,
+            lines: vec![ReportLogLinePacket {
+                level: LogLevel::Warn,
+                module: 0,
+                message: "Booted after a Watchdog reset.".into(),
+            }],
+        };
+        let bundle = DiagnosticBundle::collect(
+            &options,
+            now,
+            Ok(config.clone()),
+            Ok(vec![log]),
+            &journal_path.with_extension("missing"),
+            &journal,
+        );
+        let _ = fs::remove_file(&journal_path);
+
+        assert_eq!(bundle.journal.len(), 2);
+        assert_eq!(bundle.link_stats["1324"], stats(3));
+        assert_eq!(bundle.device_registry, Some(DeviceRegistry::default()));
+        assert_eq!(bundle.daemon_config, Some(config));
+        assert_eq!(bundle.problems, Vec::<String>::new());
+
+        let archive_path = journal_path.with_extension("tar");
+        bundle
+            .write(&archive_path)
+            .expect("Failed to write bundle.");
+        let mut archive = tar::Archive::new(File::open(&archive_path).unwrap());
+        let files: BTreeMap<String, String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                (entry.path().unwrap().display().to_string(), contents)
+            })
+            .collect();
+        let _ = fs::remove_file(&archive_path);
+
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            ["bundle.json", "firmware_logs/1324.log", "journal.jsonl"]
+        );
+        assert!(files["bundle.json"].contains("PRANDTL_BAUD_RATE"));
+        assert_eq!(files["journal.jsonl"].lines().count(), 2);
+        assert_eq!(
+            files["firmware_logs/1324.log"],
+            "warn module 0: Booted after a Watchdog reset.\n"
+        );
+    }
+
+    #[test]
+    fn test_collect_without_control_system() {
+        let journal_path = std::env::temp_dir().join(format!(
+            "prandtl_diag_unreachable_test_{}.jsonl",
+            std::process::id()
+        ));
+        let options = BundleOptions {
+            minutes: 5,
+            output: None,
+        };
+        let bundle = DiagnosticBundle::collect(
+            &options,
+            SystemTime::now(),
+            Err(ControlError::NoAnswer),
+            Err(ControlError::NoAnswer),
+            &journal_path.with_extension("missing"),
+            &JsonlStore::new(&journal_path),
+        );
+
+        assert_eq!(bundle.daemon_config, None);
+        assert_eq!(
+            bundle.problems,
+            [
+                "Failed to get the control system's config. Error: Control system closed the connection without answering.",
+                "Failed to get device logs. Error: Control system closed the connection without answering.",
+                "No journal entries in the last 5 minutes. Is the control system running?",
+            ]
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use std::time::{Duration, Instant};
+
+use common::physical::{Percentage, Rpm};
+use serde::{Deserialize, Serialize};
+
+/// Samples closer than this in duty (percent) are merged into one point.
+const POINT_MERGE_DISTANCE: f32 = 2f32;
+
+/// The most points a model keeps. The closest pair is merged beyond this.
+const MAX_POINTS: usize = 24;
+
+/// How strongly a new sample moves an existing point toward it.
+const SMOOTHING: f32 = 0.2f32;
+
+/// A single observed duty and the speed it settled at.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DutyRpmPoint {
+    /// Commanded duty, 0-100%.
+    pub duty: f32,
+
+    /// Speed the channel settled at for `duty`.
+    pub rpm: f32,
+}
+
+/// Learned mapping from commanded duty to the speed a pump or fan settles at.
+/// Built from settled observations and fit to be monotone, since a higher
+/// duty never makes a healthy channel slower.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DutyRpmModel {
+    /// Observed points sorted by duty.
+    points: Vec<DutyRpmPoint>,
+}
+
+impl DutyRpmModel {
+    /// Record a settled observation. Nearby points are smoothed together.
+    pub fn record(&mut self, duty: f32, rpm: f32) {
+        let index = self.points.partition_point(|point| point.duty < duty);
+        let nearest = [index.checked_sub(1), Some(index)]
+            .into_iter()
+            .flatten()
+            .filter(|&i| i < self.points.len())
+            .min_by(|&a, &b| {
+                let distance = |i: usize| (self.points[i].duty - duty).abs();
+                distance(a).total_cmp(&distance(b))
+            });
+
+        match nearest {
+            Some(i) if (self.points[i].duty - duty).abs() <= POINT_MERGE_DISTANCE => {
+                self.points[i].rpm += (rpm - self.points[i].rpm) * SMOOTHING;
+            }
+            _ => self.points.insert(index, DutyRpmPoint { duty, rpm }),
+        }
+
+        if self.points.len() > MAX_POINTS {
+            self.merge_closest_points();
+        }
+    }
+
+    /// Check if nothing has been learned yet.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Get the speed expected at `duty`.
+    /// Returns `None` outside of the range of duties observed so far.
+    pub fn expected_rpm(&self, duty: f32) -> Option<f32> {
+        let fitted = self.fitted();
+        interpolate(&fitted, duty, |point| (point.duty, point.rpm))
+    }
+
+    /// Get the duty expected to reach `rpm`, for strategies targeting a speed.
+    /// Returns `None` outside of the range of speeds observed so far.
+    pub fn duty_for_rpm(&self, rpm: f32) -> Option<f32> {
+        let fitted = self.fitted();
+        interpolate(&fitted, rpm, |point| (point.rpm, point.duty))
+    }
+
+    /// Fit the points to be monotone using pool adjacent violators.
+    fn fitted(&self) -> Vec<DutyRpmPoint> {
+        // NOTE: Each block is (summed rpm, number of points pooled).
+        let mut blocks: Vec<(f32, usize)> = Vec::with_capacity(self.points.len());
+        for point in &self.points {
+            blocks.push((point.rpm, 1));
+            while blocks.len() > 1 {
+                let (last_sum, last_count) = blocks[blocks.len() - 1];
+                let (prev_sum, prev_count) = blocks[blocks.len() - 2];
+                if prev_sum / prev_count as f32 <= last_sum / last_count as f32 {
+                    break;
+                }
+                blocks.pop();
+                let len = blocks.len();
+                blocks[len - 1] = (prev_sum + last_sum, prev_count + last_count);
+            }
+        }
+
+        let mut points = self.points.iter();
+        blocks
+            .into_iter()
+            .flat_map(|(sum, count)| {
+                let rpm = sum / count as f32;
+                points
+                    .by_ref()
+                    .take(count)
+                    .map(move |point| DutyRpmPoint {
+                        duty: point.duty,
+                        rpm,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Merge the adjacent pair of points closest in duty.
+    fn merge_closest_points(&mut self) {
+        let Some(i) = (0..self.points.len() - 1).min_by(|&a, &b| {
+            let gap = |i: usize| self.points[i + 1].duty - self.points[i].duty;
+            gap(a).total_cmp(&gap(b))
+        }) else {
+            return;
+        };
+        let next = self.points.remove(i + 1);
+        let point = &mut self.points[i];
+        point.duty = (point.duty + next.duty) / 2f32;
+        point.rpm = (point.rpm + next.rpm) / 2f32;
+    }
+}
+
+/// Linearly interpolate along monotone points, mapping each point to (x, y).
+fn interpolate(
+    points: &[DutyRpmPoint],
+    x: f32,
+    axes: impl Fn(&DutyRpmPoint) -> (f32, f32),
+) -> Option<f32> {
+    let (first_x, first_y) = axes(points.first()?);
+    let (last_x, _) = axes(points.last()?);
+    if x < first_x || x > last_x {
+        return None;
+    }
+    if x == first_x {
+        return Some(first_y);
+    }
+
+    points.windows(2).find_map(|pair| {
+        let (x0, y0) = axes(&pair[0]);
+        let (x1, y1) = axes(&pair[1]);
+        if x < x0 || x > x1 {
+            return None;
+        }
+        if x1 == x0 {
+            return Some(y0);
+        }
+        Some(y0 + (y1 - y0) * (x - x0) / (x1 - x0))
+    })
+}
+
+/// Produces settled (duty, rpm) samples for a `DutyRpmModel` by waiting for a
+/// command to be held long enough for the speed to settle.
+#[derive(Debug, Clone)]
+pub struct DutyRpmLearner {
+    /// How long a command must be held before the speed is considered settled.
+    settle: Duration,
+
+    /// The command the current settle period was started for.
+    commanded: Option<f32>,
+
+    /// When the current command was first seen.
+    since: Option<Instant>,
+}
+
+impl DutyRpmLearner {
+    pub fn new(settle: Duration) -> Self {
+        Self {
+            settle,
+            commanded: None,
+            since: None,
+        }
+    }
+
+    /// Observe the currently commanded activation and the measured speed.
+    /// Returns a settled (duty, rpm) sample once the command has been held
+    /// for the settle period. A different command restarts the period.
+    pub fn observe(
+        &mut self,
+        commanded: Percentage,
+        measured: Rpm,
+        now: Instant,
+    ) -> Option<(f32, f32)> {
+        let commanded: f32 = commanded.into();
+        let command_changed = match self.commanded {
+            Some(previous) => (previous - commanded).abs() > POINT_MERGE_DISTANCE / 2f32,
+            None => true,
+        };
+        if command_changed {
+            self.commanded = Some(commanded);
+            self.since = Some(now);
+            return None;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if now.duration_since(since) < self.settle {
+            return None;
+        }
+        Some((commanded, measured.speed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(points: &[(f32, f32)]) -> DutyRpmModel {
+        let mut model = DutyRpmModel::default();
+        for &(duty, rpm) in points {
+            model.record(duty, rpm);
+        }
+        model
+    }
+
+    #[test]
+    fn test_expected_rpm_interpolates() {
+        let model = model(&[(30f32, 600f32), (100f32, 2000f32), (50f32, 1000f32)]);
+
+        assert_eq!(model.expected_rpm(30f32), Some(600f32));
+        assert_eq!(model.expected_rpm(40f32), Some(800f32));
+        assert_eq!(model.expected_rpm(75f32), Some(1500f32));
+        assert_eq!(model.expected_rpm(100f32), Some(2000f32));
+
+        assert_eq!(model.expected_rpm(20f32), None);
+        assert_eq!(DutyRpmModel::default().expected_rpm(50f32), None);
+    }
+
+    #[test]
+    fn test_duty_for_rpm() {
+        let model = model(&[(30f32, 600f32), (100f32, 2000f32)]);
+        assert_eq!(model.duty_for_rpm(1300f32), Some(65f32));
+        assert_eq!(model.duty_for_rpm(2100f32), None);
+    }
+
+    #[test]
+    fn test_nearby_samples_are_smoothed() {
+        let mut model = model(&[(50f32, 1000f32)]);
+        model.record(51f32, 1500f32);
+
+        assert_eq!(model.points.len(), 1);
+        assert_eq!(model.expected_rpm(50f32), Some(1100f32));
+    }
+
+    #[test]
+    fn test_fit_is_monotone() {
+        let model = model(&[(30f32, 800f32), (50f32, 600f32), (100f32, 2000f32)]);
+
+        assert_eq!(model.expected_rpm(30f32), Some(700f32));
+        assert_eq!(model.expected_rpm(50f32), Some(700f32));
+        assert!(model.expected_rpm(75f32).unwrap() > 700f32);
+    }
+
+    #[test]
+    fn test_points_are_bounded() {
+        let mut model = DutyRpmModel::default();
+        for i in 0..=100 {
+            model.record(i as f32, i as f32 * 20f32);
+        }
+        assert!(model.points.len() <= MAX_POINTS);
+        assert!(model
+            .points
+            .windows(2)
+            .all(|pair| pair[0].duty < pair[1].duty));
+    }
+
+    #[test]
+    fn test_learner_waits_for_settle() {
+        let mut learner = DutyRpmLearner::new(Duration::from_secs(5));
+        let start = Instant::now();
+        let percent = |value: f32| Percentage::try_from(value).expect("Failed to get Percentage.");
+        let rpm = |speed: f32| Rpm::new(2000f32, speed).expect("Failed to get RPM.");
+
+        assert_eq!(learner.observe(percent(50f32), rpm(700f32), start), None);
+        assert_eq!(
+            learner.observe(percent(50f32), rpm(950f32), start + Duration::from_secs(3)),
+            None
+        );
+        assert_eq!(
+            learner.observe(percent(50f32), rpm(1000f32), start + Duration::from_secs(5)),
+            Some((50f32, 1000f32))
+        );
+
+        assert_eq!(
+            learner.observe(percent(80f32), rpm(1000f32), start + Duration::from_secs(6)),
+            None
+        );
+        assert_eq!(
+            learner.observe(percent(80f32), rpm(1500f32), start + Duration::from_secs(9)),
+            None
+        );
+    }
+}
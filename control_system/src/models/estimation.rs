@@ -0,0 +1,258 @@
+use std::time::{Duration, Instant};
+
+use common::physical::Rpm;
+
+use super::client_sensor_data::ClientSensorData;
+
+/// How long a channel takes to cover most of the way to a new commanded
+/// speed. Used to predict the speed between reports.
+const RESPONSE_TIME: Duration = Duration::from_secs(2);
+
+/// How much the speed is assumed to wander from the prediction, as a
+/// variance in RPM squared per second.
+const PROCESS_NOISE_RATE: f32 = 2500f32;
+
+/// Variance of a single reported speed, in RPM squared. Tachometer readings
+/// jitter by a few tens of RPM between reports.
+const MEASUREMENT_NOISE: f32 = 1600f32;
+
+/// Residuals further than this many standard deviations from the prediction
+/// are treated as outliers and not fused.
+const OUTLIER_THRESHOLD: f32 = 4f32;
+
+/// After this many outliers in a row the speed has really changed, so the
+/// estimate restarts from the reported speed.
+const MAX_CONSECUTIVE_OUTLIERS: usize = 3;
+
+/// The estimated speed of a single channel after a report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelEstimate {
+    /// Smoothed speed in RPM.
+    pub rpm: f32,
+
+    /// Reported speed minus the predicted speed, in standard deviations of
+    /// the prediction. `None` if no usable speed was reported.
+    pub residual: Option<f32>,
+
+    /// Whether the reported speed was too far from the prediction to be
+    /// fused.
+    pub outlier: bool,
+}
+
+impl ChannelEstimate {
+    /// Get the smoothed speed in the same range as `measured`.
+    pub fn smoothed(&self, measured: Rpm) -> Rpm {
+        let rpm = self.rpm.clamp(0f32, measured.max_speed());
+        Rpm::new(measured.max_speed(), rpm).unwrap_or(measured)
+    }
+}
+
+/// Estimated speeds of both channels of a device, alongside the report they
+/// were estimated from. A channel is `None` until it has a usable reading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RpmEstimate {
+    pub data: ClientSensorData,
+    pub pump: Option<ChannelEstimate>,
+    pub fan: Option<ChannelEstimate>,
+}
+
+impl RpmEstimate {
+    /// Get the smoothed pump speed, or the reported one without an estimate.
+    pub fn pump_speed(&self) -> Rpm {
+        self.pump.map_or(self.data.pump_speed, |pump| {
+            pump.smoothed(self.data.pump_speed)
+        })
+    }
+
+    /// Get the smoothed fan speed, or the reported one without an estimate.
+    pub fn fan_speed(&self) -> Rpm {
+        self.fan
+            .map_or(self.data.fan_speed, |fan| fan.smoothed(self.data.fan_speed))
+    }
+}
+
+/// Kalman filter fusing the speed expected from a channel's command with its
+/// noisy reported speed. Between reports the speed is predicted to settle
+/// toward the expected speed, then each report corrects the prediction.
+#[derive(Debug, Clone, Default)]
+pub struct RpmEstimator {
+    /// Estimated speed and its variance, once there has been a reading.
+    state: Option<(f32, f32)>,
+
+    /// When the estimate was last updated.
+    last: Option<Instant>,
+
+    /// How many outliers have been reported in a row.
+    outliers: usize,
+}
+
+impl RpmEstimator {
+    /// Update the estimate with the speed expected from the current command,
+    /// if known, and the reported speed, if it can be trusted.
+    /// Returns `None` until there has been a reported speed to start from.
+    pub fn observe(
+        &mut self,
+        expected: Option<f32>,
+        measured: Option<f32>,
+        now: Instant,
+    ) -> Option<ChannelEstimate> {
+        let dt = self
+            .last
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last))
+            .as_secs_f32();
+        self.last = Some(now);
+
+        let Some((mut rpm, mut variance)) = self.state else {
+            let measured = measured?;
+            self.state = Some((measured, MEASUREMENT_NOISE));
+            return Some(ChannelEstimate {
+                rpm: measured,
+                residual: Some(0f32),
+                outlier: false,
+            });
+        };
+
+        // NOTE: Without a learned model for the command the speed is assumed
+        //       to hold, leaving the reports to correct it.
+        if let Some(expected) = expected {
+            rpm += (expected - rpm) * (1f32 - (-dt / RESPONSE_TIME.as_secs_f32()).exp());
+        }
+        variance += PROCESS_NOISE_RATE * dt;
+
+        let mut estimate = ChannelEstimate {
+            rpm,
+            residual: None,
+            outlier: false,
+        };
+        if let Some(measured) = measured {
+            let innovation_variance = variance + MEASUREMENT_NOISE;
+            let residual = (measured - rpm) / innovation_variance.sqrt();
+            estimate.residual = Some(residual);
+
+            if residual.abs() <= OUTLIER_THRESHOLD {
+                let gain = variance / innovation_variance;
+                rpm += gain * (measured - rpm);
+                variance *= 1f32 - gain;
+                self.outliers = 0;
+            } else {
+                estimate.outlier = true;
+                self.outliers += 1;
+                if self.outliers >= MAX_CONSECUTIVE_OUTLIERS {
+                    rpm = measured;
+                    variance = MEASUREMENT_NOISE;
+                    self.outliers = 0;
+                }
+            }
+            estimate.rpm = rpm;
+        }
+
+        self.state = Some((rpm, variance));
+        Some(estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT_PERIOD: Duration = Duration::from_millis(500);
+
+    #[test]
+    fn test_starts_from_first_reading() {
+        let mut estimator = RpmEstimator::default();
+        let start = Instant::now();
+
+        assert_eq!(estimator.observe(Some(1000f32), None, start), None);
+        assert_eq!(
+            estimator.observe(Some(1000f32), Some(800f32), start),
+            Some(ChannelEstimate {
+                rpm: 800f32,
+                residual: Some(0f32),
+                outlier: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_smooths_noise() {
+        let mut estimator = RpmEstimator::default();
+        let start = Instant::now();
+
+        let mut last = None;
+        for i in 0..40 {
+            let noise = if i % 2 == 0 { 60f32 } else { -60f32 };
+            last = estimator.observe(
+                Some(1000f32),
+                Some(1000f32 + noise),
+                start + REPORT_PERIOD * i,
+            );
+        }
+
+        let last = last.expect("Failed to estimate.");
+        assert!((last.rpm - 1000f32).abs() < 30f32);
+        assert!(!last.outlier);
+    }
+
+    #[test]
+    fn test_predicts_toward_command() {
+        let mut estimator = RpmEstimator::default();
+        let start = Instant::now();
+
+        estimator.observe(Some(500f32), Some(500f32), start);
+        let estimate = estimator
+            .observe(Some(1500f32), None, start + Duration::from_secs(10))
+            .expect("Failed to estimate.");
+        assert!(estimate.rpm > 1400f32);
+        assert_eq!(estimate.residual, None);
+
+        let mut estimator = RpmEstimator::default();
+        estimator.observe(None, Some(500f32), start);
+        let estimate = estimator
+            .observe(None, None, start + Duration::from_secs(10))
+            .expect("Failed to estimate.");
+        assert_eq!(estimate.rpm, 500f32);
+    }
+
+    #[test]
+    fn test_outliers_rejected_until_persistent() {
+        let mut estimator = RpmEstimator::default();
+        let start = Instant::now();
+        for i in 0..10 {
+            estimator.observe(Some(1000f32), Some(1000f32), start + REPORT_PERIOD * i);
+        }
+
+        let glitch = estimator
+            .observe(Some(1000f32), Some(0f32), start + REPORT_PERIOD * 10)
+            .expect("Failed to estimate.");
+        assert!(glitch.outlier);
+        assert!(glitch.residual.expect("Expected a residual.") < -OUTLIER_THRESHOLD);
+        assert!(glitch.rpm > 950f32);
+
+        let stalled = estimator
+            .observe(Some(1000f32), Some(0f32), start + REPORT_PERIOD * 11)
+            .expect("Failed to estimate.");
+        assert!(stalled.outlier);
+        assert!(stalled.rpm > 900f32);
+
+        let stalled = estimator
+            .observe(Some(1000f32), Some(0f32), start + REPORT_PERIOD * 12)
+            .expect("Failed to estimate.");
+        assert!(stalled.outlier);
+        assert_eq!(stalled.rpm, 0f32);
+    }
+
+    #[test]
+    fn test_smoothed_stays_in_range() {
+        let measured = Rpm::new(2000f32, 1000f32).expect("Failed to get RPM.");
+        let estimate = |rpm| ChannelEstimate {
+            rpm,
+            residual: None,
+            outlier: false,
+        };
+
+        assert_eq!(estimate(1200f32).smoothed(measured).speed(), 1200f32);
+        assert_eq!(estimate(-5f32).smoothed(measured).speed(), 0f32);
+        assert_eq!(estimate(2100f32).smoothed(measured).speed(), 2000f32);
+        assert_eq!(estimate(1200f32).smoothed(measured).max_speed(), 2000f32);
+    }
+}
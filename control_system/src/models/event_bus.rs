@@ -0,0 +1,138 @@
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, SendError},
+    Receiver, Sender,
+};
+
+use super::{
+    alarm::Alarm, client_sensor_data::ClientSensorData, control_event::ControlEvent,
+    control_override::OverrideRequest, host_sensor_data::HostSensorData, journal::JournalRecord,
+};
+
+/// How many events each channel holds for a subscriber before it lags.
+const CHANNEL_CAPACITY: usize = 32;
+
+/// The typed channels the control system's tasks communicate over. A program
+/// embedding the control system with `runtime::run` can subscribe to its
+/// sensor data, control frames and alarms and publish override requests
+/// without rewiring its tasks. Clones share the same channels.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    pub(crate) client_sensor_data: Sender<ClientSensorData>,
+    pub(crate) host_sensor_data: Sender<HostSensorData>,
+    pub(crate) control_frames: Sender<ControlEvent>,
+    pub(crate) journal: Sender<JournalRecord>,
+    pub(crate) overrides: Sender<OverrideRequest>,
+}
+
+/// Receives alarms from an `EventBus` as they are raised, cleared or
+/// acknowledged.
+pub struct AlarmReceiver {
+    rx_journal: Receiver<JournalRecord>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            client_sensor_data: broadcast::channel(CHANNEL_CAPACITY).0,
+            host_sensor_data: broadcast::channel(CHANNEL_CAPACITY).0,
+            control_frames: broadcast::channel(CHANNEL_CAPACITY).0,
+            journal: broadcast::channel(CHANNEL_CAPACITY).0,
+            overrides: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// Receive the sensor data of every connected device.
+    pub fn subscribe_client_sensor_data(&self) -> Receiver<ClientSensorData> {
+        self.client_sensor_data.subscribe()
+    }
+
+    /// Receive the aggregated host temperature.
+    pub fn subscribe_host_sensor_data(&self) -> Receiver<HostSensorData> {
+        self.host_sensor_data.subscribe()
+    }
+
+    /// Receive every control frame the control loop sends, including parking
+    /// and overridden frames.
+    pub fn subscribe_control_frames(&self) -> Receiver<ControlEvent> {
+        self.control_frames.subscribe()
+    }
+
+    /// Receive every change to an alarm.
+    pub fn subscribe_alarms(&self) -> AlarmReceiver {
+        AlarmReceiver {
+            rx_journal: self.journal.subscribe(),
+        }
+    }
+
+    /// Ask the control loop to hold or release a device's outputs. Will
+    /// return a SendError if the control loop isn't running.
+    pub fn publish_override(
+        &self,
+        request: OverrideRequest,
+    ) -> Result<(), SendError<OverrideRequest>> {
+        self.overrides.send(request).map(|_| ())
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AlarmReceiver {
+    /// Wait for the next change to an alarm. Will return `RecvError::Lagged`
+    /// if events were missed, which may have included alarms, and
+    /// `RecvError::Closed` once the bus is dropped.
+    pub async fn recv(&mut self) -> Result<Alarm, RecvError> {
+        loop {
+            if let JournalRecord::Alarm(alarm) = self.rx_journal.recv().await? {
+                return Ok(alarm);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::physical::ValveState;
+
+    use super::*;
+    use crate::models::{
+        alarm::{AlarmKind, AlarmLog},
+        device_id::DeviceId,
+    };
+
+    #[tokio::test]
+    async fn test_alarm_receiver_skips_other_records() {
+        let bus = EventBus::new();
+        let mut rx_alarms = bus.subscribe_alarms();
+        let alarm = AlarmLog::default()
+            .occur(DeviceId::new("1324"), AlarmKind::WatchdogReset, 1000)
+            .expect("Expected an alarm.");
+
+        let frame = ControlEvent::new(DeviceId::new("1324"), 50f32, 25f32, ValveState::Open)
+            .expect("Failed to get ControlEvent.");
+        bus.journal
+            .send(JournalRecord::ControlFrame(frame))
+            .expect("Failed to journal.");
+        bus.journal
+            .send(JournalRecord::Alarm(alarm))
+            .expect("Failed to journal.");
+
+        assert_eq!(rx_alarms.recv().await.expect("Expected an alarm."), alarm);
+    }
+
+    #[tokio::test]
+    async fn test_publish_override() {
+        let bus = EventBus::new();
+        let request = OverrideRequest::Release(DeviceId::new("1324"));
+        assert!(bus.publish_override(request).is_err());
+
+        let mut rx_overrides = bus.overrides.subscribe();
+        bus.publish_override(request)
+            .expect("Failed to publish override.");
+        assert_eq!(rx_overrides.recv().await, Ok(request));
+    }
+}
@@ -0,0 +1,339 @@
+#[cfg(feature = "simulate")]
+use std::env;
+use std::{fmt::Display, time::Duration};
+
+use common::{
+    packet::{Packet, ReportRpmFastPacket, ReportSensorsPacket},
+    physical::Rpm,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+#[cfg(feature = "simulate")]
+/// Environment variable used to inject faults into the link to the embedded
+/// hardware, such as `drop=0.05,corrupt=0.01,stall=0.001,spike=0.02`.
+pub const FAULT_INJECTION_ENV_VAR: &str = "PRANDTL_FAULT_INJECTION";
+
+/// Default length of an injected transport stall. Longer than the default
+/// no-data timeout, so stalls exercise reconnection.
+pub const DEFAULT_STALL: Duration = Duration::from_secs(6);
+
+/// The rates faults are injected at, each the chance per opportunity from 0
+/// to 1. Meant for exercising alarms, failsafes and reconnection in CI and
+/// soak tests, never for real hardware. Disabled by default.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FaultInjection {
+    /// Chance each packet read or written is dropped.
+    pub drop_rate: f64,
+
+    /// Chance each frame written, or each chunk of bytes read, has a bit
+    /// flipped on the wire.
+    pub corrupt_rate: f64,
+
+    /// Chance each serial poll stalls the transport for `stall`.
+    pub stall_rate: f64,
+
+    /// How long an injected stall lasts.
+    pub stall: Duration,
+
+    /// Chance each sensor report has its fan or pump speed spiked to zero or
+    /// its maximum.
+    pub spike_rate: f64,
+
+    /// Seed for the random faults, so a failing run can be reproduced.
+    pub seed: Option<u64>,
+}
+
+impl Default for FaultInjection {
+    fn default() -> Self {
+        Self {
+            drop_rate: 0f64,
+            corrupt_rate: 0f64,
+            stall_rate: 0f64,
+            stall: DEFAULT_STALL,
+            spike_rate: 0f64,
+            seed: None,
+        }
+    }
+}
+
+impl FaultInjection {
+    /// Get the fault injection rates from the environment. Disabled if unset
+    /// or invalid.
+    #[cfg(feature = "simulate")]
+    pub fn from_env() -> Self {
+        let value = env::var(FAULT_INJECTION_ENV_VAR).ok();
+        parse_fault_injection(value.as_deref()).unwrap_or_default()
+    }
+
+    /// Whether any fault can be injected.
+    pub fn is_enabled(&self) -> bool {
+        [
+            self.drop_rate,
+            self.corrupt_rate,
+            self.stall_rate,
+            self.spike_rate,
+        ]
+        .iter()
+        .any(|&rate| rate > 0f64)
+    }
+}
+
+impl Display for FaultInjection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "drop={}, corrupt={}, stall={} for {:?}, spike={}",
+            self.drop_rate, self.corrupt_rate, self.stall_rate, self.stall, self.spike_rate
+        )
+    }
+}
+
+/// How many faults an injector has injected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct InjectedFaults {
+    pub dropped: u64,
+    pub corrupted: u64,
+    pub stalls: u64,
+    pub spikes: u64,
+}
+
+impl Display for InjectedFaults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "dropped={}, corrupted={}, stalls={}, spikes={}",
+            self.dropped, self.corrupted, self.stalls, self.spikes
+        )
+    }
+}
+
+/// Randomly injects faults into a single device's link at the rates of a
+/// `FaultInjection`. A disabled injector never injects anything.
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    config: FaultInjection,
+    rng: StdRng,
+    injected: InjectedFaults,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultInjection) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            config,
+            rng,
+            injected: InjectedFaults::default(),
+        }
+    }
+
+    /// Create an injector which never injects anything.
+    pub fn disabled() -> Self {
+        Self::new(FaultInjection {
+            seed: Some(0),
+            ..FaultInjection::default()
+        })
+    }
+
+    /// Whether any fault can be injected.
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_enabled()
+    }
+
+    /// Get how many faults have been injected so far.
+    pub fn injected(&self) -> InjectedFaults {
+        self.injected
+    }
+
+    /// Whether the next packet should be dropped.
+    pub fn drop_packet(&mut self) -> bool {
+        let drop = self.chance(self.config.drop_rate);
+        self.injected.dropped += drop as u64;
+        drop
+    }
+
+    /// Maybe flip a single bit of `frame`. Returns whether it was corrupted.
+    pub fn corrupt(&mut self, frame: &mut [u8]) -> bool {
+        if frame.is_empty() || !self.chance(self.config.corrupt_rate) {
+            return false;
+        }
+        let index = self.rng.gen_range(0..frame.len());
+        frame[index] ^= 1 << self.rng.gen_range(0..8);
+        self.injected.corrupted += 1;
+        true
+    }
+
+    /// Get how long the transport should stall for, if it should.
+    pub fn stall(&mut self) -> Option<Duration> {
+        if !self.chance(self.config.stall_rate) {
+            return None;
+        }
+        self.injected.stalls += 1;
+        Some(self.config.stall)
+    }
+
+    /// Maybe spike the first fan's or the pump's speed in a sensor report to
+    /// zero or its maximum. Returns whether it was spiked.
+    pub fn spike(&mut self, packet: &mut Packet) -> bool {
+        let (fan, pump) = match packet {
+            Packet::ReportSensors(ReportSensorsPacket {
+                fan_speeds_rpm,
+                pump_speed_rpm,
+                ..
+            })
+            | Packet::ReportRpmFast(ReportRpmFastPacket {
+                fan_speeds_rpm,
+                pump_speed_rpm,
+            }) => (fan_speeds_rpm.first_mut(), pump_speed_rpm),
+            _ => return false,
+        };
+        if !self.chance(self.config.spike_rate) {
+            return false;
+        }
+        let rpm = if self.rng.gen() { fan } else { pump };
+        let speed = if self.rng.gen() {
+            rpm.max_speed()
+        } else {
+            0f32
+        };
+        let Ok(spiked) = Rpm::new(rpm.max_speed(), speed) else {
+            return false;
+        };
+        *rpm = spiked;
+        self.injected.spikes += 1;
+        true
+    }
+
+    fn chance(&mut self, rate: f64) -> bool {
+        rate > 0f64 && self.rng.gen_bool(rate)
+    }
+}
+
+/// Parse comma separated `<fault>=<value>` pairs, where the faults are
+/// `drop`, `corrupt`, `stall` and `spike` with a rate from 0 to 1, plus
+/// `stall_ms` and `seed`. Returns `None` if it is missing or anything is
+/// invalid.
+#[cfg(feature = "simulate")]
+fn parse_fault_injection(value: Option<&str>) -> Option<FaultInjection> {
+    let mut config = FaultInjection::default();
+    for pair in value?
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (name, value) = pair.split_once('=')?;
+        let value = value.trim();
+        let rate = || {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| (0f64..=1f64).contains(rate))
+        };
+        match name.trim() {
+            "drop" => config.drop_rate = rate()?,
+            "corrupt" => config.corrupt_rate = rate()?,
+            "stall" => config.stall_rate = rate()?,
+            "spike" => config.spike_rate = rate()?,
+            "stall_ms" => config.stall = Duration::from_millis(value.parse().ok()?),
+            "seed" => config.seed = Some(value.parse().ok()?),
+            _ => return None,
+        }
+    }
+    Some(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        packet::{FanChannels, PingPacket},
+        physical::ValveState,
+    };
+
+    use super::*;
+
+    fn sensors() -> Packet {
+        Packet::ReportSensors(ReportSensorsPacket {
+            fan_speeds_rpm: FanChannels::single(Rpm::new(2000f32, 1000f32).unwrap()),
+            pump_speed_rpm: Rpm::new(3000f32, 1500f32).unwrap(),
+            valve_state: ValveState::Open,
+            board_temperature: None,
+            coolant_temperature: None,
+            pump_current: None,
+            fan_current: None,
+            flow_rate: None,
+            sampled_at_ms: None,
+        })
+    }
+
+    #[test]
+    #[cfg(feature = "simulate")]
+    fn test_parse_fault_injection() {
+        assert_eq!(
+            parse_fault_injection(Some("drop=0.5, corrupt=0.25,stall_ms=100,seed=7")),
+            Some(FaultInjection {
+                drop_rate: 0.5f64,
+                corrupt_rate: 0.25f64,
+                stall: Duration::from_millis(100),
+                seed: Some(7),
+                ..FaultInjection::default()
+            })
+        );
+        assert_eq!(parse_fault_injection(Some("drop=1.5")), None);
+        assert_eq!(parse_fault_injection(Some("drop")), None);
+        assert_eq!(parse_fault_injection(Some("flood=0.5")), None);
+        assert_eq!(parse_fault_injection(None), None);
+        assert!(!parse_fault_injection(Some("")).unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_disabled_injects_nothing() {
+        let mut injector = FaultInjector::disabled();
+        let mut frame = [0u8; 8];
+        let mut packet = sensors();
+        for _ in 0..100 {
+            assert!(!injector.drop_packet());
+            assert!(!injector.corrupt(&mut frame));
+            assert_eq!(injector.stall(), None);
+            assert!(!injector.spike(&mut packet));
+        }
+        assert_eq!(frame, [0u8; 8]);
+        assert_eq!(packet, sensors());
+        assert_eq!(injector.injected(), InjectedFaults::default());
+    }
+
+    #[test]
+    fn test_certain_faults_are_injected() {
+        let mut injector = FaultInjector::new(FaultInjection {
+            drop_rate: 1f64,
+            corrupt_rate: 1f64,
+            stall_rate: 1f64,
+            spike_rate: 1f64,
+            seed: Some(7),
+            ..FaultInjection::default()
+        });
+        let mut frame = [0u8; 8];
+        assert!(injector.drop_packet());
+        assert!(injector.corrupt(&mut frame));
+        assert_eq!(frame.iter().map(|byte| byte.count_ones()).sum::<u32>(), 1);
+        assert_eq!(injector.stall(), Some(DEFAULT_STALL));
+
+        let mut packet = sensors();
+        assert!(injector.spike(&mut packet));
+        assert_ne!(packet, sensors());
+        let mut ping = Packet::Ping(PingPacket { nonce: 1 });
+        assert!(!injector.spike(&mut ping));
+
+        assert_eq!(
+            injector.injected(),
+            InjectedFaults {
+                dropped: 1,
+                corrupted: 1,
+                stalls: 1,
+                spikes: 1,
+            }
+        );
+    }
+}
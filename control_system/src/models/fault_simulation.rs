@@ -0,0 +1,399 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+
+use common::{
+    packet::Packet,
+    physical::{FlowRate, Rpm, ValveState},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{capabilities::parse_flag, cli_args::ArgsError, device_id::DeviceId};
+
+/// Environment variable used to allow plant faults to be simulated from the
+/// CLI with `simulate-fault`.
+pub const FAULT_SIMULATION_ENV_VAR: &str = "PRANDTL_FAULT_SIMULATION";
+
+/// A fault of the cooling loop itself rather than of the link, simulated by
+/// rewriting what a device reports. Meant for watching alarms and fail-safes
+/// react end to end in CI and demos, never for real hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PlantFault {
+    /// The pump stops, so its speed and the flow rate read zero.
+    PumpStall,
+
+    /// The device stops reporting its sensors.
+    SensorDropout,
+
+    /// The valve stops moving, so its reported state is frozen at the one
+    /// reported when the fault started.
+    ValveStuck,
+}
+
+impl PlantFault {
+    pub const ALL: [PlantFault; 3] = [
+        PlantFault::PumpStall,
+        PlantFault::SensorDropout,
+        PlantFault::ValveStuck,
+    ];
+
+    /// Get the name the fault is given on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlantFault::PumpStall => "pump-stall",
+            PlantFault::SensorDropout => "sensor-dropout",
+            PlantFault::ValveStuck => "valve-stuck",
+        }
+    }
+}
+
+impl Display for PlantFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A fault being simulated on `device`, or on every device if `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulatedFault {
+    pub device: Option<DeviceId>,
+    pub fault: PlantFault,
+}
+
+/// The plant faults being simulated. A fault for every device applies to
+/// those without one of their own.
+#[derive(Debug, Clone, Default)]
+pub struct PlantFaults {
+    faults: HashMap<Option<DeviceId>, PlantFault>,
+}
+
+impl PlantFaults {
+    /// Start simulating `fault` on `device`, or on every device if `None`,
+    /// replacing the fault simulated there before.
+    pub fn simulate(&mut self, device: Option<DeviceId>, fault: PlantFault) {
+        self.faults.insert(device, fault);
+    }
+
+    /// Stop simulating faults on `device`, or on any device if `None`.
+    pub fn clear(&mut self, device: Option<DeviceId>) {
+        match device {
+            Some(device) => {
+                self.faults.remove(&Some(device));
+            }
+            None => self.faults.clear(),
+        }
+    }
+
+    /// Get the fault simulated on `device`, if any.
+    pub fn get(&self, device: DeviceId) -> Option<PlantFault> {
+        self.faults
+            .get(&Some(device))
+            .or_else(|| self.faults.get(&None))
+            .copied()
+    }
+
+    /// Get every simulated fault, the one for every device first.
+    pub fn list(&self) -> Vec<SimulatedFault> {
+        let mut faults = self
+            .faults
+            .iter()
+            .map(|(&device, &fault)| SimulatedFault { device, fault })
+            .collect::<Vec<_>>();
+        faults.sort_by_key(|fault| fault.device);
+        faults
+    }
+}
+
+/// The simulated plant faults, shared between the control socket and each
+/// client communication task.
+pub type SharedPlantFaults = Arc<Mutex<PlantFaults>>;
+
+/// Get shared plant faults for the control socket to set if fault simulation
+/// is on in the environment. Off if unset or invalid.
+pub fn plant_faults_from_env() -> Option<SharedPlantFaults> {
+    let value = env::var(FAULT_SIMULATION_ENV_VAR).ok();
+    parse_flag(value.as_deref())
+        .unwrap_or(false)
+        .then(SharedPlantFaults::default)
+}
+
+/// Applies a single device's simulated plant fault to the packets it
+/// reports.
+#[derive(Debug, Clone, Default)]
+pub struct PlantFaultSimulator {
+    /// The valve state reported when the valve got stuck.
+    stuck_valve: Option<ValveState>,
+}
+
+impl PlantFaultSimulator {
+    /// Rewrite `packet` as it would be reported under `fault`. Returns `None`
+    /// if it wouldn't be reported at all. Packets which aren't sensor
+    /// reports are passed through untouched.
+    pub fn apply(&mut self, fault: Option<PlantFault>, mut packet: Packet) -> Option<Packet> {
+        if fault != Some(PlantFault::ValveStuck) {
+            self.stuck_valve = None;
+        }
+        let Some(fault) = fault else {
+            return Some(packet);
+        };
+        match (fault, &mut packet) {
+            (
+                PlantFault::SensorDropout,
+                Packet::ReportSensors(_) | Packet::ReportRpmFast(_) | Packet::ReportSensorsBatch(_),
+            ) => return None,
+            (PlantFault::PumpStall, Packet::ReportSensors(report)) => {
+                report.pump_speed_rpm = stalled(report.pump_speed_rpm);
+                if report.flow_rate.is_some() {
+                    report.flow_rate = FlowRate::try_from(0f32).ok();
+                }
+            }
+            (PlantFault::PumpStall, Packet::ReportRpmFast(report)) => {
+                report.pump_speed_rpm = stalled(report.pump_speed_rpm);
+            }
+            (PlantFault::PumpStall, Packet::ReportSensorsBatch(batch)) => {
+                for sample in batch.samples.iter_mut() {
+                    sample.pump_rpm = 0;
+                }
+            }
+            (PlantFault::ValveStuck, Packet::ReportSensors(report)) => {
+                report.valve_state = *self.stuck_valve.get_or_insert(report.valve_state);
+            }
+            (PlantFault::ValveStuck, Packet::ReportSensorsBatch(batch)) => {
+                for sample in batch.samples.iter_mut() {
+                    sample.valve_state = *self.stuck_valve.get_or_insert(sample.valve_state);
+                }
+            }
+            _ => {}
+        }
+        Some(packet)
+    }
+}
+
+/// Get `rpm` stopped, keeping its maximum.
+fn stalled(rpm: Rpm) -> Rpm {
+    Rpm::new(rpm.max_speed(), 0f32).unwrap_or(rpm)
+}
+
+/// The faults `simulate-fault` understands, for errors.
+const FAULT_NAMES: &str = "pump-stall, sensor-dropout, valve-stuck or clear";
+
+/// What `simulate-fault` should do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulateFaultCommand {
+    /// The fault to simulate, or `None` to stop simulating faults.
+    pub fault: Option<PlantFault>,
+
+    /// The device to simulate it on, or every device if `None`.
+    pub device: Option<DeviceId>,
+}
+
+/// Parse the arguments to `simulate-fault`: a fault, or `clear`, optionally
+/// followed by a device's serial number.
+pub fn parse_simulate_fault_args(args: &[String]) -> Result<SimulateFaultCommand, ArgsError> {
+    let (fault, device) = match args {
+        [] => {
+            return Err(ArgsError::Missing(format!(
+                "a fault to simulate: {}",
+                FAULT_NAMES
+            )))
+        }
+        [fault] => (fault, None),
+        [fault, device] => (fault, Some(DeviceId::new(device))),
+        [_, _, unknown, ..] => return Err(ArgsError::UnknownArgument(unknown.clone())),
+    };
+    let fault = match fault.as_str() {
+        "clear" => None,
+        name => Some(
+            PlantFault::ALL
+                .into_iter()
+                .find(|fault| fault.name() == name)
+                .ok_or_else(|| {
+                    ArgsError::InvalidValue(
+                        "the fault".to_string(),
+                        name.to_string(),
+                        FAULT_NAMES.to_string(),
+                    )
+                })?,
+        ),
+    };
+    Ok(SimulateFaultCommand { fault, device })
+}
+
+#[cfg(test)]
+mod tests {
+    use common::packet::{
+        FanChannels, PingPacket, ReportSensorsBatchPacket, ReportSensorsPacket, SensorSample,
+    };
+
+    use super::*;
+    use crate::models::cli_args::args;
+
+    fn sensors(valve_state: ValveState) -> Packet {
+        Packet::ReportSensors(ReportSensorsPacket {
+            fan_speeds_rpm: FanChannels::single(Rpm::new(2000f32, 1000f32).unwrap()),
+            pump_speed_rpm: Rpm::new(3000f32, 1500f32).unwrap(),
+            valve_state,
+            board_temperature: None,
+            coolant_temperature: None,
+            pump_current: None,
+            fan_current: None,
+            flow_rate: Some(FlowRate::try_from(1.5f32).unwrap()),
+            sampled_at_ms: None,
+        })
+    }
+
+    #[test]
+    fn test_parse_simulate_fault_args() {
+        assert_eq!(
+            parse_simulate_fault_args(&args(&["pump-stall"])),
+            Ok(SimulateFaultCommand {
+                fault: Some(PlantFault::PumpStall),
+                device: None,
+            })
+        );
+        assert_eq!(
+            parse_simulate_fault_args(&args(&["valve-stuck", "1324"])),
+            Ok(SimulateFaultCommand {
+                fault: Some(PlantFault::ValveStuck),
+                device: Some(DeviceId::new("1324")),
+            })
+        );
+        assert_eq!(
+            parse_simulate_fault_args(&args(&["clear"])),
+            Ok(SimulateFaultCommand {
+                fault: None,
+                device: None,
+            })
+        );
+        assert_eq!(
+            parse_simulate_fault_args(&args(&[])),
+            Err(ArgsError::Missing(format!(
+                "a fault to simulate: {}",
+                FAULT_NAMES
+            )))
+        );
+        assert_eq!(
+            parse_simulate_fault_args(&args(&["fire"])),
+            Err(ArgsError::InvalidValue(
+                "the fault".to_string(),
+                "fire".to_string(),
+                FAULT_NAMES.to_string()
+            ))
+        );
+        assert_eq!(
+            parse_simulate_fault_args(&args(&["pump-stall", "1324", "now"])),
+            Err(ArgsError::UnknownArgument("now".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_plant_faults() {
+        let a = DeviceId::new("a");
+        let b = DeviceId::new("b");
+        let mut faults = PlantFaults::default();
+        faults.simulate(None, PlantFault::SensorDropout);
+        faults.simulate(Some(a), PlantFault::PumpStall);
+        assert_eq!(faults.get(a), Some(PlantFault::PumpStall));
+        assert_eq!(faults.get(b), Some(PlantFault::SensorDropout));
+        assert_eq!(
+            faults.list(),
+            vec![
+                SimulatedFault {
+                    device: None,
+                    fault: PlantFault::SensorDropout,
+                },
+                SimulatedFault {
+                    device: Some(a),
+                    fault: PlantFault::PumpStall,
+                },
+            ]
+        );
+
+        faults.clear(Some(a));
+        assert_eq!(faults.get(a), Some(PlantFault::SensorDropout));
+        faults.clear(None);
+        assert_eq!(faults.get(a), None);
+        assert!(faults.list().is_empty());
+    }
+
+    #[test]
+    fn test_pump_stall() {
+        let mut simulator = PlantFaultSimulator::default();
+        let Some(Packet::ReportSensors(report)) =
+            simulator.apply(Some(PlantFault::PumpStall), sensors(ValveState::Open))
+        else {
+            panic!("Expected a sensor report.");
+        };
+        assert_eq!(report.pump_speed_rpm.speed(), 0f32);
+        assert_eq!(report.pump_speed_rpm.max_speed(), 3000f32);
+        assert_eq!(report.flow_rate.map(|flow| flow.value()), Some(0f32));
+        assert_eq!(report.fan_speeds_rpm.first().speed(), 1000f32);
+
+        let mut batch = ReportSensorsBatchPacket {
+            started_at_ms: None,
+            fan_max_rpm: 2000,
+            pump_max_rpm: 3000,
+            samples: Default::default(),
+        };
+        batch
+            .samples
+            .push(SensorSample {
+                offset_ms: 0,
+                fan_rpm: 1000,
+                pump_rpm: 1500,
+                valve_state: ValveState::Open,
+            })
+            .unwrap();
+        let Some(Packet::ReportSensorsBatch(batch)) = simulator.apply(
+            Some(PlantFault::PumpStall),
+            Packet::ReportSensorsBatch(batch),
+        ) else {
+            panic!("Expected a sensor batch.");
+        };
+        assert_eq!(
+            (batch.samples[0].fan_rpm, batch.samples[0].pump_rpm),
+            (1000, 0)
+        );
+    }
+
+    #[test]
+    fn test_sensor_dropout() {
+        let mut simulator = PlantFaultSimulator::default();
+        assert_eq!(
+            simulator.apply(Some(PlantFault::SensorDropout), sensors(ValveState::Open)),
+            None
+        );
+        let ping = Packet::Ping(PingPacket { nonce: 1 });
+        assert_eq!(
+            simulator.apply(Some(PlantFault::SensorDropout), ping.clone()),
+            Some(ping)
+        );
+    }
+
+    #[test]
+    fn test_valve_stuck() {
+        let mut simulator = PlantFaultSimulator::default();
+        let stuck = Some(PlantFault::ValveStuck);
+        assert_eq!(
+            simulator.apply(stuck, sensors(ValveState::Closed)),
+            Some(sensors(ValveState::Closed))
+        );
+        assert_eq!(
+            simulator.apply(stuck, sensors(ValveState::Open)),
+            Some(sensors(ValveState::Closed))
+        );
+
+        // NOTE: Once cleared, the valve gets stuck afresh the next time.
+        assert_eq!(
+            simulator.apply(None, sensors(ValveState::Open)),
+            Some(sensors(ValveState::Open))
+        );
+        assert_eq!(
+            simulator.apply(stuck, sensors(ValveState::Open)),
+            Some(sensors(ValveState::Open))
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use common::packet::{
+    FirmwareConfig, MAX_PWM_FREQUENCY_HZ, MAX_TELEMETRY_RATE_HZ, MIN_FAILSAFE_DUTY_PERCENT,
+    MIN_PWM_FREQUENCY_HZ, MIN_TELEMETRY_RATE_HZ,
+};
+
+use super::{
+    cli_args::{Args, ArgsError, DeviceOptions},
+    injection::DEFAULT_HANDSHAKE_TIMEOUT,
+};
+
+/// Options for `config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigOptions {
+    /// The device, and how long to wait for it to report its configuration.
+    pub device: DeviceOptions,
+
+    /// What to change. Nothing is written if this is empty.
+    pub changes: ConfigChanges,
+
+    /// Write without asking for confirmation first.
+    pub yes: bool,
+}
+
+/// Changes to the firmware configuration. Anything left `None` is kept as
+/// the device reports it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConfigChanges {
+    pub pwm_frequency_hz: Option<u16>,
+    pub telemetry_rate_hz: Option<u8>,
+    pub pump_max_rpm: Option<u16>,
+    pub fan_max_rpm: Option<u16>,
+    pub failsafe_duty_percent: Option<u8>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Apply the changes to the configuration read from the device.
+    pub fn apply(&self, config: FirmwareConfig) -> FirmwareConfig {
+        FirmwareConfig {
+            pwm_frequency_hz: self.pwm_frequency_hz.unwrap_or(config.pwm_frequency_hz),
+            telemetry_rate_hz: self.telemetry_rate_hz.unwrap_or(config.telemetry_rate_hz),
+            pump_max_rpm: self.pump_max_rpm.unwrap_or(config.pump_max_rpm),
+            fan_max_rpm: self.fan_max_rpm.unwrap_or(config.fan_max_rpm),
+            failsafe_duty_percent: self
+                .failsafe_duty_percent
+                .unwrap_or(config.failsafe_duty_percent),
+        }
+    }
+}
+
+/// Parse the arguments to `config`: optionally `--port <PATH>`,
+/// `--timeout-ms <MS>` and `--yes`, along with any of `--pwm-hz <HZ>`,
+/// `--telemetry-hz <HZ>`, `--pump-max-rpm <RPM>`, `--fan-max-rpm <RPM>` and
+/// `--failsafe-duty <PERCENT>` to change.
+pub fn parse_config_args(args: &[String]) -> Result<ConfigOptions, ArgsError> {
+    let mut options = ConfigOptions {
+        device: DeviceOptions::new(DEFAULT_HANDSHAKE_TIMEOUT),
+        changes: ConfigChanges::default(),
+        yes: false,
+    };
+
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        let changes = &mut options.changes;
+        match arg.as_str() {
+            "--pwm-hz" => {
+                let range = MIN_PWM_FREQUENCY_HZ..=MAX_PWM_FREQUENCY_HZ;
+                changes.pwm_frequency_hz = Some(args.in_range(arg, range)?);
+            }
+            "--telemetry-hz" => {
+                let range = MIN_TELEMETRY_RATE_HZ..=MAX_TELEMETRY_RATE_HZ;
+                changes.telemetry_rate_hz = Some(args.in_range(arg, range)?);
+            }
+            "--pump-max-rpm" => changes.pump_max_rpm = Some(args.in_range(arg, 1..=u16::MAX)?),
+            "--fan-max-rpm" => changes.fan_max_rpm = Some(args.in_range(arg, 1..=u16::MAX)?),
+            "--failsafe-duty" => {
+                let range = MIN_FAILSAFE_DUTY_PERCENT..=100;
+                changes.failsafe_duty_percent = Some(args.in_range(arg, range)?);
+            }
+            "--yes" => options.yes = true,
+            _ if options.device.parse_arg(arg, &mut args)? => {}
+            _ => return Err(ArgsError::UnknownArgument(arg.clone())),
+        }
+    }
+    Ok(options)
+}
+
+/// Describe the firmware configuration for `config`, along with whether the
+/// device managed to persist it.
+pub fn describe_config(config: &FirmwareConfig, persisted: bool) -> String {
+    let persisted = if persisted {
+        "Persisted."
+    } else {
+        "NOT persisted, the device reverts to its stored settings when it resets."
+    };
+    [
+        format!("PWM frequency: {}Hz", config.pwm_frequency_hz),
+        format!("Telemetry rate: {}Hz", config.telemetry_rate_hz),
+        format!("Pump max speed: {}rpm", config.pump_max_rpm),
+        format!("Fan max speed: {}rpm", config.fan_max_rpm),
+        format!("Fail-safe duty: {}%", config.failsafe_duty_percent),
+        persisted.to_string(),
+    ]
+    .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_config_args() {
+        let options = parse_config_args(&[]).expect("Failed to parse arguments.");
+        assert!(options.changes.is_empty());
+        assert!(!options.yes);
+
+        let options = parse_config_args(&args(&[
+            "--port",
+            "/dev/ttyACM0",
+            "--pwm-hz",
+            "25000",
+            "--failsafe-duty",
+            "80",
+            "--yes",
+        ]))
+        .expect("Failed to parse arguments.");
+        assert_eq!(options.device.port, Some("/dev/ttyACM0".to_string()));
+        assert_eq!(
+            options.changes,
+            ConfigChanges {
+                pwm_frequency_hz: Some(25_000),
+                failsafe_duty_percent: Some(80),
+                ..ConfigChanges::default()
+            }
+        );
+        assert!(options.yes);
+
+        assert_eq!(
+            parse_config_args(&args(&["--pwm-hz", "100"])),
+            Err(ArgsError::InvalidValue(
+                "'--pwm-hz'".to_string(),
+                "100".to_string(),
+                format!("{} to {}", MIN_PWM_FREQUENCY_HZ, MAX_PWM_FREQUENCY_HZ)
+            ))
+        );
+        assert!(matches!(
+            parse_config_args(&args(&["--failsafe-duty", "20"])),
+            Err(ArgsError::InvalidValue(..))
+        ));
+        assert_eq!(
+            parse_config_args(&args(&["--fan-max-rpm"])),
+            Err(ArgsError::MissingValue("--fan-max-rpm".to_string()))
+        );
+        assert_eq!(
+            parse_config_args(&args(&["--force"])),
+            Err(ArgsError::UnknownArgument("--force".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let config = FirmwareConfig {
+            pwm_frequency_hz: 1000,
+            telemetry_rate_hz: 2,
+            pump_max_rpm: 2000,
+            fan_max_rpm: 1500,
+            failsafe_duty_percent: 100,
+        };
+        assert_eq!(ConfigChanges::default().apply(config), config);
+        assert_eq!(
+            ConfigChanges {
+                fan_max_rpm: Some(1800),
+                ..ConfigChanges::default()
+            }
+            .apply(config),
+            FirmwareConfig {
+                fan_max_rpm: 1800,
+                ..config
+            }
+        );
+    }
+}
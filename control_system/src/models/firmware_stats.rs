@@ -0,0 +1,131 @@
+use std::fmt::Display;
+
+use common::packet::{ReportStatsPacket, PACKET_QUEUE_CAPACITY};
+
+/// Something in a device's `ReportStats` worth raising, compared to the
+/// report before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFinding {
+    /// The uptime went backwards, so the device reset between reports, or
+    /// its uptime wrapped.
+    Restarted {
+        uptime_ms: u32,
+    },
+
+    /// More received frames failed to decode, and the packets in them were
+    /// dropped.
+    DecodeErrors {
+        new: u32,
+        total: u32,
+    },
+
+    /// A packet queue filled for the first time, so packets may have been
+    /// dropped.
+    IncomingQueueFull,
+    OutgoingQueueFull,
+}
+
+impl Display for StatsFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatsFinding::Restarted { uptime_ms } => {
+                write!(f, "reset between reports, up for {}ms", uptime_ms)
+            }
+            StatsFinding::DecodeErrors { new, total } => {
+                write!(f, "dropped {} corrupted frames, {} since reset", new, total)
+            }
+            StatsFinding::IncomingQueueFull => {
+                write!(
+                    f,
+                    "incoming packet queue filled, packets may have been dropped"
+                )
+            }
+            StatsFinding::OutgoingQueueFull => {
+                write!(
+                    f,
+                    "outgoing packet queue filled, packets may have been dropped"
+                )
+            }
+        }
+    }
+}
+
+/// Compare a device's stats to its `previous` report, if any. Counters are
+/// kept since reset, so after a reset they are judged from zero.
+pub fn check_stats(
+    previous: Option<&ReportStatsPacket>,
+    stats: &ReportStatsPacket,
+) -> Vec<StatsFinding> {
+    let mut findings = Vec::new();
+    let previous = match previous {
+        Some(previous) if stats.uptime_ms < previous.uptime_ms => {
+            findings.push(StatsFinding::Restarted {
+                uptime_ms: stats.uptime_ms,
+            });
+            None
+        }
+        previous => previous,
+    };
+    let previous_errors = previous.map_or(0, |previous| previous.decode_errors);
+    if stats.decode_errors > previous_errors {
+        findings.push(StatsFinding::DecodeErrors {
+            new: stats.decode_errors - previous_errors,
+            total: stats.decode_errors,
+        });
+    }
+    let became_full = |high_water: fn(&ReportStatsPacket) -> u8| {
+        let full = |stats| high_water(stats) as usize >= PACKET_QUEUE_CAPACITY;
+        full(stats) && !previous.is_some_and(full)
+    };
+    if became_full(|stats| stats.incoming_queue_high_water) {
+        findings.push(StatsFinding::IncomingQueueFull);
+    }
+    if became_full(|stats| stats.outgoing_queue_high_water) {
+        findings.push(StatsFinding::OutgoingQueueFull);
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(uptime_ms: u32, decode_errors: u32, outgoing: u8) -> ReportStatsPacket {
+        ReportStatsPacket {
+            supply_voltage_mv: None,
+            uptime_ms,
+            core_loop_period_ms: 10,
+            decode_errors,
+            incoming_queue_high_water: 2,
+            outgoing_queue_high_water: outgoing,
+        }
+    }
+
+    #[test]
+    fn test_check_stats() {
+        let first = stats(1000, 0, 4);
+        assert!(check_stats(None, &first).is_empty());
+
+        let second = stats(2000, 3, PACKET_QUEUE_CAPACITY as u8);
+        assert_eq!(
+            check_stats(Some(&first), &second),
+            vec![
+                StatsFinding::DecodeErrors { new: 3, total: 3 },
+                StatsFinding::OutgoingQueueFull
+            ]
+        );
+
+        // NOTE: Nothing new since the last report.
+        let third = stats(3000, 3, PACKET_QUEUE_CAPACITY as u8);
+        assert!(check_stats(Some(&second), &third).is_empty());
+
+        let after_reset = stats(500, 1, 3);
+        assert_eq!(
+            check_stats(Some(&third), &after_reset),
+            vec![
+                StatsFinding::Restarted { uptime_ms: 500 },
+                StatsFinding::DecodeErrors { new: 1, total: 1 }
+            ]
+        );
+    }
+}
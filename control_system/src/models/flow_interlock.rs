@@ -0,0 +1,209 @@
+use std::{collections::HashSet, env};
+
+use super::{
+    client_sensor_data::{ClientSensorData, ReadingQuality},
+    device_id::DeviceId,
+};
+
+/// Environment variable used to override the coolant flow, in L/min, below
+/// which the low flow interlock engages.
+pub const LOW_FLOW_ENV_VAR: &str = "PRANDTL_LOW_FLOW_LPM";
+
+/// Environment variable holding the pump speed, in RPM, below which the low
+/// flow interlock engages on devices without a flow meter. Off if unset.
+pub const LOW_FLOW_PUMP_RPM_ENV_VAR: &str = "PRANDTL_LOW_FLOW_PUMP_RPM";
+
+/// Default coolant flow below which the interlock engages. Well under what
+/// any pump running at its floor moves through a healthy loop.
+pub const DEFAULT_LOW_FLOW_LPM: f32 = 0.5f32;
+
+/// How far above its threshold a reading must recover, as a fraction of the
+/// threshold, before the interlock releases. Keeps a flow hovering at the
+/// threshold from toggling the valve.
+const LOW_FLOW_HYSTERESIS: f32 = 0.2f32;
+
+/// When the coolant flow is too low to trust the loop.
+///
+/// Flow is measured with the device's flow meter if it reports one,
+/// otherwise the pump speed stands in for it if `min_pump_rpm` is set. While
+/// engaged, the valve is held open rather than closed and the fan is run at
+/// full, so what flow there is still goes through the radiator, and an alarm
+/// is raised.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LowFlowPolicy {
+    /// Flow below which the interlock engages, in L/min.
+    pub min_flow_lpm: f32,
+
+    /// Pump speed below which the interlock engages when there is no flow
+    /// meter, in RPM.
+    pub min_pump_rpm: Option<f32>,
+}
+
+impl Default for LowFlowPolicy {
+    fn default() -> Self {
+        Self {
+            min_flow_lpm: DEFAULT_LOW_FLOW_LPM,
+            min_pump_rpm: None,
+        }
+    }
+}
+
+impl LowFlowPolicy {
+    /// Get the low flow policy from the environment, falling back to the
+    /// defaults for anything unset or invalid.
+    pub fn from_env() -> Self {
+        let flow = env::var(LOW_FLOW_ENV_VAR).ok();
+        let pump_rpm = env::var(LOW_FLOW_PUMP_RPM_ENV_VAR).ok();
+        Self {
+            min_flow_lpm: parse_threshold(flow.as_deref()).unwrap_or(DEFAULT_LOW_FLOW_LPM),
+            min_pump_rpm: parse_threshold(pump_rpm.as_deref()),
+        }
+    }
+
+    /// The reading which decides whether `data`'s flow is low, along with
+    /// the threshold it is compared to. `None` if there is nothing to go on.
+    fn measure(&self, data: &ClientSensorData) -> Option<(f32, f32)> {
+        if let Some(flow_rate) = data.flow_rate {
+            return Some((flow_rate.value(), self.min_flow_lpm));
+        }
+        let min_pump_rpm = self.min_pump_rpm?;
+        (data.quality.pump_speed == ReadingQuality::Good)
+            .then(|| (data.pump_speed.speed(), min_pump_rpm))
+    }
+}
+
+/// Parse a threshold. Returns `None` if it is missing, not a number or not
+/// positive.
+fn parse_threshold(value: Option<&str>) -> Option<f32> {
+    value?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|threshold: &f32| threshold.is_finite() && *threshold > 0f32)
+}
+
+/// Applies a `LowFlowPolicy` to the readings of every device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LowFlowInterlock {
+    policy: LowFlowPolicy,
+
+    /// The devices whose interlock is engaged.
+    engaged: HashSet<DeviceId>,
+}
+
+impl LowFlowInterlock {
+    pub fn new(policy: LowFlowPolicy) -> Self {
+        Self {
+            policy,
+            engaged: HashSet::new(),
+        }
+    }
+
+    /// Whether `device`'s interlock is engaged.
+    pub fn is_engaged(&self, device: DeviceId) -> bool {
+        self.engaged.contains(&device)
+    }
+
+    /// Observe the next full report of a device. The interlock engages once
+    /// the flow drops below the threshold and releases once it recovers past
+    /// the hysteresis, and stays as it is while there is nothing to measure.
+    /// Returns the report flagged with whether it is engaged, along with
+    /// `Some(true)` if it just engaged or `Some(false)` if it just released.
+    pub fn observe(&mut self, data: ClientSensorData) -> (ClientSensorData, Option<bool>) {
+        let was_engaged = self.is_engaged(data.device);
+        let engaged = match self.policy.measure(&data) {
+            None => was_engaged,
+            Some((value, threshold)) if was_engaged => {
+                value < threshold * (1f32 + LOW_FLOW_HYSTERESIS)
+            }
+            Some((value, threshold)) => value < threshold,
+        };
+        if engaged {
+            self.engaged.insert(data.device);
+        } else {
+            self.engaged.remove(&data.device);
+        }
+        (
+            ClientSensorData {
+                low_flow: engaged,
+                ..data
+            },
+            (engaged != was_engaged).then_some(engaged),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::physical::{FlowRate, Rpm, ValveState};
+
+    use super::*;
+
+    fn report(pump_rpm: f32, flow_lpm: Option<f32>) -> ClientSensorData {
+        ClientSensorData {
+            flow_rate: flow_lpm
+                .map(|flow_lpm| FlowRate::try_from(flow_lpm).expect("Failed to get FlowRate.")),
+            ..ClientSensorData::new(
+                DeviceId::new("1324"),
+                Rpm::new(2000f32, pump_rpm).unwrap(),
+                Rpm::new(2000f32, 1000f32).unwrap(),
+                ValveState::Closed,
+                None,
+                None,
+                None,
+            )
+        }
+    }
+
+    #[test]
+    fn test_parse_threshold() {
+        assert_eq!(parse_threshold(Some(" 0.75 ")), Some(0.75f32));
+        assert_eq!(parse_threshold(Some("0")), None);
+        assert_eq!(parse_threshold(Some("-1")), None);
+        assert_eq!(parse_threshold(Some("slow")), None);
+        assert_eq!(parse_threshold(None), None);
+    }
+
+    #[test]
+    fn test_low_flow_engages_with_hysteresis() {
+        let mut interlock = LowFlowInterlock::new(LowFlowPolicy::default());
+
+        let (data, change) = interlock.observe(report(1000f32, Some(1f32)));
+        assert!(!data.low_flow);
+        assert_eq!(change, None);
+
+        let (data, change) = interlock.observe(report(1000f32, Some(0.25f32)));
+        assert!(data.low_flow);
+        assert_eq!(change, Some(true));
+
+        // NOTE: Back over the threshold but within the hysteresis.
+        let (data, change) = interlock.observe(report(1000f32, Some(0.5625f32)));
+        assert!(data.low_flow);
+        assert_eq!(change, None);
+
+        let (data, change) = interlock.observe(report(1000f32, Some(0.75f32)));
+        assert!(!data.low_flow);
+        assert_eq!(change, Some(false));
+        assert!(!interlock.is_engaged(DeviceId::new("1324")));
+    }
+
+    #[test]
+    fn test_pump_speed_stands_in_for_flow() {
+        let mut interlock = LowFlowInterlock::new(LowFlowPolicy::default());
+        let (data, _) = interlock.observe(report(100f32, None));
+        assert!(!data.low_flow);
+
+        let mut interlock = LowFlowInterlock::new(LowFlowPolicy {
+            min_pump_rpm: Some(300f32),
+            ..LowFlowPolicy::default()
+        });
+        let (data, change) = interlock.observe(report(100f32, None));
+        assert!(data.low_flow);
+        assert_eq!(change, Some(true));
+
+        // NOTE: The flow meter wins when there is one.
+        let (data, change) = interlock.observe(report(100f32, Some(1f32)));
+        assert!(!data.low_flow);
+        assert_eq!(change, Some(false));
+    }
+}
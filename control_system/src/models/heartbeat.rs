@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How often supervised tasks are expected to beat.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Represents the health of a single supervised task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TaskHealth {
+    /// The task has beat within the timeout.
+    Alive,
+
+    /// The task stopped beating and is being restarted.
+    Stalled,
+}
+
+/// Represents the health of a single supervised task along with how often it
+/// has had to be restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskStatus {
+    pub health: TaskHealth,
+    pub restarts: u32,
+    last_beat: Instant,
+}
+
+/// Tracks when each supervised task last beat. Shared between the tasks and
+/// the watchdog which supervises them.
+#[derive(Debug, Default)]
+pub struct HeartbeatRegistry {
+    tasks: Mutex<HashMap<&'static str, TaskStatus>>,
+}
+
+/// A heartbeat registry shared between supervised tasks and their watchdog.
+pub type SharedHeartbeatRegistry = Arc<HeartbeatRegistry>;
+
+/// Handle a single supervised task uses to beat.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    name: &'static str,
+    registry: SharedHeartbeatRegistry,
+}
+
+impl HeartbeatRegistry {
+    /// Register a task, starting it as alive, and get its heartbeat handle.
+    /// Registering an existing task gives it a fresh timeout.
+    pub fn register(registry: &SharedHeartbeatRegistry, name: &'static str) -> Heartbeat {
+        registry.beat(name, Instant::now());
+        Heartbeat {
+            name,
+            registry: registry.clone(),
+        }
+    }
+
+    /// Record a beat for a task, marking it alive.
+    fn beat(&self, name: &'static str, now: Instant) {
+        let mut tasks = self
+            .tasks
+            .lock()
+            .expect("Heartbeat registry lock poisoned.");
+        let status = tasks.entry(name).or_insert(TaskStatus {
+            health: TaskHealth::Alive,
+            restarts: 0,
+            last_beat: now,
+        });
+        status.health = TaskHealth::Alive;
+        status.last_beat = now;
+    }
+
+    /// Get the tasks which are alive but haven't beat within `timeout`.
+    /// They are marked as stalled so each stall is only reported once.
+    pub fn take_stalled(&self, now: Instant, timeout: Duration) -> Vec<&'static str> {
+        let mut tasks = self
+            .tasks
+            .lock()
+            .expect("Heartbeat registry lock poisoned.");
+        tasks
+            .iter_mut()
+            .filter(|(_, status)| {
+                status.health == TaskHealth::Alive
+                    && now.duration_since(status.last_beat) >= timeout
+            })
+            .map(|(name, status)| {
+                status.health = TaskHealth::Stalled;
+                *name
+            })
+            .collect()
+    }
+
+    /// Count a restart of a task.
+    pub fn record_restart(&self, name: &'static str) {
+        let mut tasks = self
+            .tasks
+            .lock()
+            .expect("Heartbeat registry lock poisoned.");
+        if let Some(status) = tasks.get_mut(name) {
+            status.restarts += 1;
+        }
+    }
+
+    /// Get the current status of every supervised task.
+    pub fn health(&self) -> Vec<(&'static str, TaskStatus)> {
+        let tasks = self
+            .tasks
+            .lock()
+            .expect("Heartbeat registry lock poisoned.");
+        let mut health: Vec<_> = tasks
+            .iter()
+            .map(|(name, status)| (*name, *status))
+            .collect();
+        health.sort_by_key(|(name, _)| *name);
+        health
+    }
+
+    /// Check that every supervised task is alive.
+    pub fn is_healthy(&self) -> bool {
+        self.health()
+            .iter()
+            .all(|(_, status)| status.health == TaskHealth::Alive)
+    }
+}
+
+impl Heartbeat {
+    /// Signal that the task is still making progress.
+    pub fn beat(&self) {
+        self.registry.beat(self.name, Instant::now());
+    }
+}
+
+impl Display for TaskHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stalled_task_is_taken_once() {
+        let registry = SharedHeartbeatRegistry::default();
+        let start = Instant::now();
+        registry.beat("control", start);
+        registry.beat("sensors", start);
+
+        assert!(registry
+            .take_stalled(start + Duration::from_secs(2), Duration::from_secs(5))
+            .is_empty());
+
+        registry.beat("sensors", start + Duration::from_secs(4));
+        assert_eq!(
+            registry.take_stalled(start + Duration::from_secs(5), Duration::from_secs(5)),
+            vec!["control"]
+        );
+        assert!(!registry.is_healthy());
+        assert!(registry
+            .take_stalled(start + Duration::from_secs(6), Duration::from_secs(5))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_beat_recovers_task() {
+        let registry = SharedHeartbeatRegistry::default();
+        let heartbeat = HeartbeatRegistry::register(&registry, "control");
+        registry.take_stalled(
+            Instant::now() + Duration::from_secs(10),
+            Duration::from_secs(5),
+        );
+        registry.record_restart("control");
+        assert!(!registry.is_healthy());
+
+        heartbeat.beat();
+        assert!(registry.is_healthy());
+        let (name, status) = registry.health()[0];
+        assert_eq!(name, "control");
+        assert_eq!(status.health, TaskHealth::Alive);
+        assert_eq!(status.restarts, 1);
+    }
+}
@@ -1,6 +1,55 @@
-use super::temperature::Temperature;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug,Clone,Copy)]
+use super::temperature::{Temperature, TemperatureError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct HostSensorData {
     pub cpu_temperature: Temperature,
+
+    /// The cpu temperature before calibration, when any source read is
+    /// calibrated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_cpu_temperature: Option<Temperature>,
+}
+
+impl HostSensorData {
+    /// Construct host sensor data from a raw cpu temperature in degC.
+    /// Will return a `TemperatureError` if the temperature is invalid.
+    pub fn new(cpu_temperature: f32) -> Result<Self, TemperatureError> {
+        Ok(Self {
+            cpu_temperature: Temperature::try_from(cpu_temperature)?,
+            raw_cpu_temperature: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let data = HostSensorData::new(45f32).expect("Failed to get HostSensorData.");
+        assert_eq!(data.cpu_temperature.value, 45f32);
+
+        assert!(HostSensorData::new(150f32).is_err());
+    }
+
+    #[test]
+    fn test_serialization() {
+        let data = HostSensorData::new(45.5f32).expect("Failed to get HostSensorData.");
+        let data_ser = serde_json::to_string(&data).expect("Failed to serialize HostSensorData.");
+        let data_deser: HostSensorData =
+            serde_json::from_str(&data_ser).expect("Failed to deserialize HostSensorData.");
+        assert_eq!(data, data_deser);
+
+        let data = HostSensorData {
+            raw_cpu_temperature: Some(Temperature { value: 72.5f32 }),
+            ..data
+        };
+        let data_ser = serde_json::to_string(&data).expect("Failed to serialize HostSensorData.");
+        let data_deser: HostSensorData =
+            serde_json::from_str(&data_ser).expect("Failed to deserialize HostSensorData.");
+        assert_eq!(data, data_deser);
+    }
 }
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use common::packet::Packet;
+
+use super::cli_args::{Args, ArgsError, PORT_FLAG};
+
+/// How long to wait for the device to answer the handshake if not specified.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Options for `send`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendOptions {
+    /// The packet to write to the device.
+    pub packet: Packet,
+
+    /// The port to write to, as with
+    /// [`DeviceOptions::port`](super::cli_args::DeviceOptions::port).
+    pub port: Option<String>,
+
+    /// How long to wait for the device to report its capabilities before
+    /// sending anyway.
+    pub handshake_timeout: Duration,
+}
+
+/// Parse the arguments to `send`. The packet is given as JSON using the same
+/// field names as `Packet`, e.g. `{"SetParameter": {"parameter": {"TelemetryRateHz": 5}}}`.
+/// Percentages are fixed point, given as their raw bits in eighths of a
+/// percent, e.g. `{"value": {"bits": 200}}` for 25%.
+pub fn parse_send_args(args: &[String]) -> Result<SendOptions, ArgsError> {
+    let mut packet = None;
+    let mut port = None;
+    let mut handshake_timeout = DEFAULT_HANDSHAKE_TIMEOUT;
+
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--packet" => {
+                let json = args.value(arg)?;
+                packet = Some(serde_json::from_str(json).map_err(|e| {
+                    let expected = format!("a packet as JSON ({})", e);
+                    ArgsError::InvalidValue("'--packet'".to_string(), json.clone(), expected)
+                })?);
+            }
+            PORT_FLAG => port = Some(args.value(arg)?.clone()),
+            "--handshake-timeout-ms" => {
+                handshake_timeout = args
+                    .parse(arg, "a number of milliseconds", |_| true)
+                    .map(Duration::from_millis)?;
+            }
+            _ => return Err(ArgsError::UnknownArgument(arg.clone())),
+        }
+    }
+
+    Ok(SendOptions {
+        packet: packet
+            .ok_or_else(|| ArgsError::Missing("a packet to send with '--packet'".to_string()))?,
+        port,
+        handshake_timeout,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        packet::{FanChannels, Parameter, ReportControlTargetsPacket, SetParameterPacket},
+        physical::Percentage,
+    };
+
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_send_args() {
+        assert_eq!(
+            parse_send_args(&args(&[
+                "--packet",
+                r#"{"SetParameter": {"parameter": {"TelemetryRateHz": 5}}}"#,
+                "--port",
+                "/dev/ttyACM0",
+            ])),
+            Ok(SendOptions {
+                packet: Packet::SetParameter(SetParameterPacket {
+                    parameter: Parameter::TelemetryRateHz(5)
+                }),
+                port: Some("/dev/ttyACM0".to_string()),
+                handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            })
+        );
+        assert_eq!(
+            parse_send_args(&args(&[])),
+            Err(ArgsError::Missing(
+                "a packet to send with '--packet'".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_send_args(&args(&["--port"])),
+            Err(ArgsError::MissingValue("--port".to_string()))
+        );
+        assert_eq!(
+            parse_send_args(&args(&["--handshake-timeout-ms", "soon"])),
+            Err(ArgsError::InvalidValue(
+                "'--handshake-timeout-ms'".to_string(),
+                "soon".to_string(),
+                "a number of milliseconds".to_string()
+            ))
+        );
+        assert!(matches!(
+            parse_send_args(&args(&["--packet", "{}"])),
+            Err(ArgsError::InvalidValue(..))
+        ));
+        assert_eq!(
+            parse_send_args(&args(&["--verbose"])),
+            Err(ArgsError::UnknownArgument("--verbose".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_control_targets() {
+        let options = parse_send_args(&args(&[
+            "--packet",
+            r#"{"ReportControlTargets": {"fan_control_percents": {"first": {"value": {"bits": 200}}}, "pump_control_percent": {"value": {"bits": 600}}}}"#,
+        ]))
+        .expect("Failed to parse control targets.");
+
+        assert_eq!(
+            options.packet,
+            Packet::ReportControlTargets(ReportControlTargetsPacket {
+                fan_control_percents: FanChannels::single(Percentage::try_from(25f32).unwrap()),
+                pump_control_percent: Percentage::try_from(75f32).unwrap(),
+                sequence: 0,
+            })
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "recording")]
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use common::packet::{Capabilities, ReportLogLinePacket, ResetCause};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "recording")]
+use thiserror::Error;
+
+#[cfg(feature = "recording")]
+use super::alarm::AlarmHistory;
+use super::{
+    alarm::Alarm, client_sensor_data::ClientSensorData, control_event::ControlEvent,
+    control_override::OverrideRequest, control_timing::ControlTimingStats, device_id::DeviceId,
+    host_sensor_data::HostSensorData, link::LinkStats, retention::RetentionStats,
+    telemetry_summary::TelemetrySummary, temperature_failover::FailoverEvent,
+};
+
+#[cfg(feature = "recording")]
+/// Default location of the journal of recent telemetry and events.
+pub const JOURNAL_PATH: &str = "prandtl_journal.jsonl";
+
+#[cfg(feature = "recording")]
+/// How long journal entries are kept before being pruned.
+pub const JOURNAL_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Represents something worth keeping for diagnosing a bug report.
+/// Tasks can always send these; they are only written to disk with the
+/// `recording` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JournalRecord {
+    ClientSensors(ClientSensorData),
+    HostSensors(HostSensorData),
+    ControlFrame(ControlEvent),
+    LinkStats {
+        device: DeviceId,
+        stats: LinkStats,
+    },
+    DeviceInfo {
+        device: DeviceId,
+        capabilities: Capabilities,
+        /// NOTE: Missing from entries journaled before it was reported.
+        #[serde(default)]
+        reset_cause: ResetCause,
+    },
+    /// The alarm as it was after being raised, cleared or acknowledged.
+    Alarm(Alarm),
+    TelemetrySummary(TelemetrySummary),
+    /// The outcome of a journal maintenance run.
+    Retention(RetentionStats),
+    ControlTiming {
+        device: DeviceId,
+        stats: ControlTimingStats,
+    },
+    /// The cpu temperature started being read from another backend.
+    TemperatureFailover(FailoverEvent),
+    /// A device's outputs were taken over from the control loop or handed
+    /// back.
+    Override(OverrideRequest),
+    /// A log line reported by a device.
+    DeviceLog {
+        device: DeviceId,
+        log: ReportLogLinePacket,
+    },
+    /// A device's outputs started being slew limited because they were
+    /// oscillating. See `OscillationDamper`.
+    OscillationDamped {
+        device: DeviceId,
+    },
+}
+
+#[cfg(feature = "recording")]
+impl JournalRecord {
+    /// Whether this is raw telemetry, which can be left out of the journal
+    /// in favour of its summaries.
+    pub fn is_raw_telemetry(&self) -> bool {
+        matches!(
+            self,
+            JournalRecord::ClientSensors(_)
+                | JournalRecord::HostSensors(_)
+                | JournalRecord::ControlFrame(_)
+        )
+    }
+}
+
+#[cfg(feature = "recording")]
+/// A single journal line.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Milliseconds since the unix epoch when the record was journaled.
+    pub timestamp_ms: u64,
+    pub record: JournalRecord,
+}
+
+#[cfg(feature = "recording")]
+#[derive(Error, Debug)]
+pub enum JournalError {
+    /// This occurs if the journal file can't be read or written.
+    #[error("Failed to access journal file.")]
+    Io(io::Error),
+
+    /// This occurs if an entry can't be encoded.
+    #[error("Failed to encode journal entry.")]
+    Encode(serde_json::Error),
+
+    /// This occurs if the alarm history file contents are invalid.
+    #[error("Failed to parse alarm history.")]
+    History(serde_json::Error),
+
+    #[cfg(feature = "sqlite")]
+    /// This occurs if the journal database can't be read or written.
+    #[error("Failed to access journal database. Error: {0}")]
+    Database(rusqlite::Error),
+}
+
+#[cfg(feature = "recording")]
+impl JournalEntry {
+    /// Create an entry timestamped now.
+    pub fn now(record: JournalRecord) -> Self {
+        Self {
+            timestamp_ms: unix_time_ms(SystemTime::now()),
+            record,
+        }
+    }
+}
+
+/// Get milliseconds since the unix epoch. Times before the epoch are zero.
+pub fn unix_time_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(feature = "recording")]
+/// Append entries to the journal at `path`, one JSON document per line.
+pub fn append_entries(path: &Path, entries: &[JournalEntry]) -> Result<(), JournalError> {
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&serde_json::to_string(entry).map_err(JournalError::Encode)?);
+        contents.push('\n');
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(JournalError::Io)
+}
+
+#[cfg(feature = "recording")]
+/// Read every entry at or after `since_ms` from the journal at `path`.
+/// A missing journal has no entries. Lines which fail to parse, such as one
+/// cut short by a crash, are skipped.
+pub fn read_entries_since(path: &Path, since_ms: u64) -> Result<Vec<JournalEntry>, JournalError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(JournalError::Io(e)),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<JournalEntry>(line).ok())
+        .filter(|entry| entry.timestamp_ms >= since_ms)
+        .collect())
+}
+
+#[cfg(feature = "recording")]
+/// Drop every entry older than `since_ms` from the journal at `path`, except
+/// telemetry summaries, which are only dropped once older than
+/// `summaries_since_ms`.
+pub fn prune_entries_before(
+    path: &Path,
+    since_ms: u64,
+    summaries_since_ms: u64,
+) -> Result<(), JournalError> {
+    let entries = read_entries_since(path, since_ms.min(summaries_since_ms))?
+        .into_iter()
+        .filter(|entry| match entry.record {
+            JournalRecord::TelemetrySummary(_) => entry.timestamp_ms >= summaries_since_ms,
+            _ => entry.timestamp_ms >= since_ms,
+        })
+        .collect::<Vec<_>>();
+    fs::write(path, "").map_err(JournalError::Io)?;
+    append_entries(path, &entries)
+}
+
+#[cfg(feature = "recording")]
+/// Load the alarm history at `path`. A missing file has no occurrences.
+pub fn load_alarm_history(path: &Path) -> Result<AlarmHistory, JournalError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(AlarmHistory::default()),
+        Err(e) => return Err(JournalError::Io(e)),
+    };
+    serde_json::from_str(&contents).map_err(JournalError::History)
+}
+
+#[cfg(feature = "recording")]
+/// Count the alarms among `entries` in the alarm history at `path`. The file
+/// is only rewritten if there are any.
+pub fn record_alarm_history(path: &Path, entries: &[JournalEntry]) -> Result<(), JournalError> {
+    let mut alarms = entries
+        .iter()
+        .filter_map(|entry| match &entry.record {
+            JournalRecord::Alarm(alarm) => Some(alarm),
+            _ => None,
+        })
+        .peekable();
+    if alarms.peek().is_none() {
+        return Ok(());
+    }
+    let mut history = load_alarm_history(path)?;
+    for alarm in alarms {
+        history.record(alarm);
+    }
+    let contents = serde_json::to_string_pretty(&history).map_err(JournalError::Encode)?;
+    fs::write(path, contents).map_err(JournalError::Io)
+}
+
+#[cfg(all(test, feature = "recording"))]
+/// Build a control frame journaled at `timestamp_ms`, for tests.
+pub fn control_frame(timestamp_ms: u64) -> JournalEntry {
+    let event = ControlEvent::new(
+        DeviceId::new("1324"),
+        25f32,
+        50f32,
+        common::physical::ValveState::Open,
+    )
+    .expect("Failed to get ControlEvent.");
+    JournalEntry {
+        timestamp_ms,
+        record: JournalRecord::ControlFrame(event),
+    }
+}
+
+#[cfg(all(test, feature = "recording"))]
+/// Build an empty telemetry summary journaled at `timestamp_ms`, for tests.
+pub fn summary(timestamp_ms: u64) -> JournalEntry {
+    JournalEntry {
+        timestamp_ms,
+        record: JournalRecord::TelemetrySummary(TelemetrySummary {
+            start_ms: 0,
+            end_ms: timestamp_ms,
+            device: None,
+            temperature: None,
+            pump_rpm: None,
+            fan_rpm: None,
+            pump_duty: None,
+            fan_duty: None,
+        }),
+    }
+}
+
+#[cfg(all(test, feature = "recording"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_read_and_prune() {
+        let path =
+            std::env::temp_dir().join(format!("prandtl_journal_test_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        assert!(read_entries_since(&path, 0)
+            .expect("Failed to read journal.")
+            .is_empty());
+
+        append_entries(&path, &[control_frame(1000), control_frame(2000)])
+            .expect("Failed to append entries.");
+        append_entries(&path, &[control_frame(3000)]).expect("Failed to append entries.");
+        assert_eq!(
+            read_entries_since(&path, 2000).expect("Failed to read journal."),
+            vec![control_frame(2000), control_frame(3000)]
+        );
+
+        prune_entries_before(&path, 3000, 3000).expect("Failed to prune journal.");
+        let entries = read_entries_since(&path, 0).expect("Failed to read journal.");
+        let _ = fs::remove_file(&path);
+        assert_eq!(entries, vec![control_frame(3000)]);
+    }
+}
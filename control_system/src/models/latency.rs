@@ -0,0 +1,141 @@
+use std::{fmt::Display, time::Duration};
+
+use super::cli_args::{Args, ArgsError, DeviceOptions};
+
+/// How many pings are sent if not specified.
+pub const DEFAULT_PING_COUNT: u32 = 20;
+
+/// How long to wait for each pong if not specified.
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Options for `ping`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingOptions {
+    /// How many pings to send.
+    pub count: u32,
+
+    /// The device to ping, and how long to wait for each pong before
+    /// counting the ping as lost.
+    pub device: DeviceOptions,
+}
+
+/// Parse the arguments to `ping`: optionally `--count <N>`, `--port <PATH>`
+/// and `--timeout-ms <MS>`.
+pub fn parse_ping_args(args: &[String]) -> Result<PingOptions, ArgsError> {
+    let mut options = PingOptions {
+        count: DEFAULT_PING_COUNT,
+        device: DeviceOptions::new(DEFAULT_PING_TIMEOUT),
+    };
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--count" => options.count = args.positive_number(arg)?,
+            _ if options.device.parse_arg(arg, &mut args)? => {}
+            _ => return Err(ArgsError::UnknownArgument(arg.clone())),
+        }
+    }
+    Ok(options)
+}
+
+/// The distribution of round trip times over a run of pings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub sent: usize,
+    pub lost: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+impl LatencySummary {
+    /// Summarize the round trip times of the pings which were answered out
+    /// of `sent`. Returns `None` if none were answered.
+    pub fn from_samples(sent: usize, samples: &[Duration]) -> Option<Self> {
+        let mut samples = samples.to_vec();
+        samples.sort();
+
+        let total: Duration = samples.iter().sum();
+        Some(Self {
+            sent,
+            lost: sent.saturating_sub(samples.len()),
+            min: *samples.first()?,
+            median: percentile(&samples, 50),
+            p95: percentile(&samples, 95),
+            max: *samples.last()?,
+            mean: total / samples.len() as u32,
+        })
+    }
+}
+
+/// Get the nearest rank percentile of sorted, non-empty samples.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+impl Display for LatencySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} sent, {} lost, min {:?}, median {:?}, p95 {:?}, max {:?}, mean {:?}",
+            self.sent, self.lost, self.min, self.median, self.p95, self.max, self.mean
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_ping_args() {
+        assert_eq!(
+            parse_ping_args(&args(&[])),
+            Ok(PingOptions {
+                count: DEFAULT_PING_COUNT,
+                device: DeviceOptions::new(DEFAULT_PING_TIMEOUT),
+            })
+        );
+        assert_eq!(
+            parse_ping_args(&args(&["--count", "100", "--timeout-ms", "250"])),
+            Ok(PingOptions {
+                count: 100,
+                device: DeviceOptions::new(Duration::from_millis(250)),
+            })
+        );
+        assert_eq!(
+            parse_ping_args(&args(&["--count", "0"])),
+            Err(ArgsError::InvalidValue(
+                "'--count'".to_string(),
+                "0".to_string(),
+                "a positive number".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_ping_args(&args(&["--timeout-ms"])),
+            Err(ArgsError::MissingValue("--timeout-ms".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_latency_summary() {
+        let samples: Vec<Duration> = (1..=20).rev().map(Duration::from_millis).collect();
+        let summary = LatencySummary::from_samples(25, &samples).unwrap();
+
+        assert_eq!(summary.lost, 5);
+        assert_eq!(summary.min, Duration::from_millis(1));
+        assert_eq!(summary.median, Duration::from_millis(10));
+        assert_eq!(summary.p95, Duration::from_millis(19));
+        assert_eq!(summary.max, Duration::from_millis(20));
+        assert_eq!(summary.mean, Duration::from_micros(10_500));
+    }
+
+    #[test]
+    fn test_latency_summary_all_lost() {
+        assert_eq!(LatencySummary::from_samples(3, &[]), None);
+    }
+}
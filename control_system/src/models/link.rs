@@ -0,0 +1,307 @@
+use std::{
+    env,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use common::packet::AckPacket;
+use serde::{Deserialize, Serialize};
+
+use super::capabilities::parse_flag;
+
+/// Environment variable used to override the serial baud rate.
+pub const BAUD_RATE_ENV_VAR: &str = "PRANDTL_BAUD_RATE";
+
+/// Default serial baud rate. USB CDC-ACM ignores it, but real UART bridges
+/// need it high enough to keep up with batched telemetry.
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// Environment variable used to override how long a connected device may go
+/// without sending a valid packet before its port is reopened.
+pub const NO_DATA_TIMEOUT_ENV_VAR: &str = "PRANDTL_NO_DATA_TIMEOUT_MS";
+
+/// Default time without a valid packet before the port is reopened. Several
+/// report periods at the slowest telemetry rate, and many more of the
+/// heartbeats the firmware sends regardless.
+pub const DEFAULT_NO_DATA_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Environment variable used to have devices batch fast sensor samples into
+/// fewer, larger packets instead of sending each on its own.
+pub const SENSOR_BATCHING_ENV_VAR: &str = "PRANDTL_SENSOR_BATCHING";
+
+/// Environment variable used to turn off pacing writes to the link capacity.
+pub const WRITE_PACING_ENV_VAR: &str = "PRANDTL_WRITE_PACING";
+
+/// Configuration for the serial link to the embedded hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkConfig {
+    /// The baud rate requested when opening a port.
+    pub baud_rate: u32,
+
+    /// How long a connected device may go without sending a valid packet
+    /// before the port is closed and the device rediscovered.
+    pub no_data_timeout: Duration,
+
+    /// Whether connected devices are asked to batch fast sensor samples. Cuts
+    /// the number of USB transfers at the cost of up to
+    /// `SENSOR_BATCH_FLUSH_MS` of added latency on speeds.
+    pub sensor_batching: bool,
+
+    /// Whether writes to connected devices are paced to the link capacity,
+    /// so bursts don't outrun what the firmware drains per USB frame.
+    pub write_pacing: bool,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            baud_rate: DEFAULT_BAUD_RATE,
+            no_data_timeout: DEFAULT_NO_DATA_TIMEOUT,
+            sensor_batching: false,
+            write_pacing: true,
+        }
+    }
+}
+
+impl LinkConfig {
+    /// Build the link configuration from the environment, falling back to the
+    /// default for anything unset or invalid.
+    pub fn from_env() -> Self {
+        let baud_rate = env::var(BAUD_RATE_ENV_VAR).ok();
+        let no_data_timeout = env::var(NO_DATA_TIMEOUT_ENV_VAR).ok();
+        let sensor_batching = env::var(SENSOR_BATCHING_ENV_VAR).ok();
+        let write_pacing = env::var(WRITE_PACING_ENV_VAR).ok();
+        Self {
+            baud_rate: parse_baud_rate(baud_rate.as_deref()).unwrap_or(DEFAULT_BAUD_RATE),
+            no_data_timeout: parse_no_data_timeout(no_data_timeout.as_deref())
+                .unwrap_or(DEFAULT_NO_DATA_TIMEOUT),
+            sensor_batching: parse_flag(sensor_batching.as_deref()).unwrap_or(false),
+            write_pacing: parse_flag(write_pacing.as_deref()).unwrap_or(true),
+        }
+    }
+}
+
+/// Parse a baud rate. Returns `None` if it is missing, not a number or zero.
+fn parse_baud_rate(value: Option<&str>) -> Option<u32> {
+    value?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&baud_rate| baud_rate > 0)
+}
+
+/// Parse a no-data timeout in milliseconds. Returns `None` if it is missing,
+/// not a number or zero.
+fn parse_no_data_timeout(value: Option<&str>) -> Option<Duration> {
+    value?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&ms| ms > 0)
+        .map(Duration::from_millis)
+}
+
+/// Detects a link which is still open but has stopped delivering data. After
+/// USB suspend the port can keep reporting bytes to read while every read
+/// times out, so only valid packets count as activity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoDataWatchdog {
+    timeout: Duration,
+    last_packet: Instant,
+}
+
+impl NoDataWatchdog {
+    /// Start watching a link opened at `now`.
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            last_packet: now,
+        }
+    }
+
+    /// Record valid packets received at `now`.
+    pub fn record_packets(&mut self, packets: usize, now: Instant) {
+        if packets > 0 {
+            self.last_packet = now;
+        }
+    }
+
+    /// How long the link has gone without a valid packet, if that is longer
+    /// than the timeout.
+    pub fn starved_for(&self, now: Instant) -> Option<Duration> {
+        let silence = now.saturating_duration_since(self.last_packet);
+        (silence >= self.timeout).then_some(silence)
+    }
+}
+
+/// Running statistics for the serial link to a single device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkStats {
+    /// The baud rate the port reports actually using, which may differ from
+    /// the requested rate if the driver doesn't support it.
+    pub effective_baud_rate: Option<u32>,
+
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub write_failures: u64,
+    pub packets_received: u64,
+
+    /// Control frames the embedded hardware acknowledged.
+    #[serde(default)]
+    pub control_frames_acked: u64,
+
+    /// Control frames which went without an ack for `ACK_TIMEOUT`.
+    #[serde(default)]
+    pub control_frames_unacked: u64,
+
+    /// How many control frames the embedded hardware reported missing from
+    /// the sequence in its latest ack. Counted since it last reset.
+    #[serde(default)]
+    pub control_frames_missed: u64,
+}
+
+impl LinkStats {
+    /// Record a packet written to the port.
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.record_batch_sent(1, bytes);
+    }
+
+    /// Record `packets` written to the port in a single write of `bytes`.
+    pub fn record_batch_sent(&mut self, packets: usize, bytes: usize) {
+        self.packets_sent += packets as u64;
+        self.bytes_sent += bytes as u64;
+    }
+
+    /// Record a packet which failed to be written to the port.
+    pub fn record_write_failure(&mut self) {
+        self.record_batch_failure(1);
+    }
+
+    /// Record `packets` which failed to be written to the port together.
+    pub fn record_batch_failure(&mut self, packets: usize) {
+        self.write_failures += packets as u64;
+    }
+
+    /// Record packets read from the port.
+    pub fn record_received(&mut self, packets: usize) {
+        self.packets_received += packets as u64;
+    }
+
+    /// Record an ack from the embedded hardware, which only counts as
+    /// acknowledging a frame if it was `awaited`. Returns how many more
+    /// control frames the hardware reports missing than before.
+    pub fn record_ack(&mut self, ack: &AckPacket, awaited: bool) -> u64 {
+        if awaited {
+            self.control_frames_acked += 1;
+        }
+        let missed = ack.missed_control_frames as u64;
+        // NOTE: A lower count means the hardware reset and started over.
+        let newly_missed = missed
+            .checked_sub(self.control_frames_missed)
+            .unwrap_or(missed);
+        self.control_frames_missed = missed;
+        newly_missed
+    }
+
+    /// Record control frames which went unacknowledged.
+    pub fn record_unacked(&mut self, frames: usize) {
+        self.control_frames_unacked += frames as u64;
+    }
+}
+
+impl Display for LinkStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "(LinkStats: effective_baud_rate={:?}, packets_sent={}, bytes_sent={}, write_failures={}, packets_received={}, control_frames_acked={}, control_frames_unacked={}, control_frames_missed={})",
+            self.effective_baud_rate,
+            self.packets_sent,
+            self.bytes_sent,
+            self.write_failures,
+            self.packets_received,
+            self.control_frames_acked,
+            self.control_frames_unacked,
+            self.control_frames_missed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_baud_rate() {
+        assert_eq!(parse_baud_rate(Some("921600")), Some(921_600));
+        assert_eq!(parse_baud_rate(Some(" 9600 ")), Some(9600));
+        assert_eq!(parse_baud_rate(Some("0")), None);
+        assert_eq!(parse_baud_rate(Some("fast")), None);
+        assert_eq!(parse_baud_rate(None), None);
+    }
+
+    #[test]
+    fn test_parse_no_data_timeout() {
+        assert_eq!(
+            parse_no_data_timeout(Some("2500")),
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(parse_no_data_timeout(Some("0")), None);
+        assert_eq!(parse_no_data_timeout(Some("soon")), None);
+        assert_eq!(parse_no_data_timeout(None), None);
+    }
+
+    #[test]
+    fn test_no_data_watchdog() {
+        let start = Instant::now();
+        let mut watchdog = NoDataWatchdog::new(Duration::from_secs(5), start);
+        assert_eq!(watchdog.starved_for(start + Duration::from_secs(4)), None);
+
+        // NOTE: A read which decoded nothing doesn't count as activity.
+        watchdog.record_packets(0, start + Duration::from_secs(4));
+        assert_eq!(
+            watchdog.starved_for(start + Duration::from_secs(5)),
+            Some(Duration::from_secs(5))
+        );
+
+        watchdog.record_packets(2, start + Duration::from_secs(5));
+        assert_eq!(watchdog.starved_for(start + Duration::from_secs(9)), None);
+        assert!(watchdog
+            .starved_for(start + Duration::from_secs(10))
+            .is_some());
+    }
+
+    #[test]
+    fn test_record_stats() {
+        let mut stats = LinkStats::default();
+        stats.record_sent(12);
+        stats.record_sent(8);
+        stats.record_write_failure();
+        stats.record_received(3);
+
+        assert_eq!(stats.packets_sent, 2);
+        assert_eq!(stats.bytes_sent, 20);
+        assert_eq!(stats.write_failures, 1);
+        assert_eq!(stats.packets_received, 3);
+    }
+
+    #[test]
+    fn test_record_ack() {
+        let mut stats = LinkStats::default();
+        let ack = |missed_control_frames| AckPacket {
+            sequence: 0,
+            missed_control_frames,
+        };
+        assert_eq!(stats.record_ack(&ack(0), true), 0);
+        assert_eq!(stats.record_ack(&ack(3), false), 3);
+        assert_eq!(stats.record_ack(&ack(3), true), 0);
+        stats.record_unacked(2);
+
+        assert_eq!(stats.control_frames_acked, 2);
+        assert_eq!(stats.control_frames_unacked, 2);
+        assert_eq!(stats.control_frames_missed, 3);
+
+        // NOTE: The count starts over when the hardware resets.
+        assert_eq!(stats.record_ack(&ack(1), true), 1);
+        assert_eq!(stats.control_frames_missed, 1);
+    }
+}
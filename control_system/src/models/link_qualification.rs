@@ -0,0 +1,206 @@
+use std::{fmt::Display, time::Duration};
+
+use common::packet::{EchoPacket, EchoReplyPacket};
+
+use super::cli_args::{Args, ArgsError, DeviceOptions};
+
+/// How many echoes are sent if not specified.
+pub const DEFAULT_ECHO_COUNT: u32 = 1000;
+
+/// How long to wait for each echo reply if not specified.
+pub const DEFAULT_ECHO_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Options for `echo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EchoOptions {
+    /// How many echoes to send.
+    pub count: u32,
+
+    /// The device to qualify, and how long to wait for each reply before
+    /// counting the echo as lost.
+    pub device: DeviceOptions,
+}
+
+/// Parse the arguments to `echo`: optionally `--count <N>`, `--port <PATH>`
+/// and `--timeout-ms <MS>`.
+pub fn parse_echo_args(args: &[String]) -> Result<EchoOptions, ArgsError> {
+    let mut options = EchoOptions {
+        count: DEFAULT_ECHO_COUNT,
+        device: DeviceOptions::new(DEFAULT_ECHO_TIMEOUT),
+    };
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--count" => options.count = args.positive_number(arg)?,
+            _ if options.device.parse_arg(arg, &mut args)? => {}
+            _ => return Err(ArgsError::UnknownArgument(arg.clone())),
+        }
+    }
+    Ok(options)
+}
+
+/// How a single echo came back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EchoOutcome {
+    /// The payload came back unchanged.
+    Intact,
+
+    /// The payload was corrupted on the way to the device.
+    CorruptedToDevice,
+
+    /// The payload was corrupted on the way back from the device.
+    CorruptedFromDevice,
+
+    /// Every CRC checked out but the payload changed anyway, such as a reply
+    /// to an earlier echo arriving late.
+    Mismatched,
+
+    /// No reply arrived in time.
+    Lost,
+}
+
+impl EchoOutcome {
+    /// Decide how an echo came back from the packet sent and its reply.
+    pub fn classify(sent: &EchoPacket, reply: &EchoReplyPacket) -> Self {
+        if !reply.is_intact() {
+            EchoOutcome::CorruptedFromDevice
+        } else if !reply.received_intact {
+            EchoOutcome::CorruptedToDevice
+        } else if reply.payload != sent.payload {
+            EchoOutcome::Mismatched
+        } else {
+            EchoOutcome::Intact
+        }
+    }
+}
+
+/// Tally of echo outcomes over a qualification run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QualificationReport {
+    pub intact: u32,
+    pub corrupted_to_device: u32,
+    pub corrupted_from_device: u32,
+    pub mismatched: u32,
+    pub lost: u32,
+}
+
+impl QualificationReport {
+    /// Record how an echo came back.
+    pub fn record(&mut self, outcome: EchoOutcome) {
+        match outcome {
+            EchoOutcome::Intact => self.intact += 1,
+            EchoOutcome::CorruptedToDevice => self.corrupted_to_device += 1,
+            EchoOutcome::CorruptedFromDevice => self.corrupted_from_device += 1,
+            EchoOutcome::Mismatched => self.mismatched += 1,
+            EchoOutcome::Lost => self.lost += 1,
+        }
+    }
+
+    /// How many echoes were sent.
+    pub fn sent(&self) -> u32 {
+        self.intact
+            + self.corrupted_to_device
+            + self.corrupted_from_device
+            + self.mismatched
+            + self.lost
+    }
+
+    /// Check every echo came back intact.
+    pub fn passed(&self) -> bool {
+        self.intact == self.sent()
+    }
+}
+
+impl Display for QualificationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} intact, {} corrupted to device, {} corrupted from device, {} mismatched, {} lost",
+            self.intact,
+            self.sent(),
+            self.corrupted_to_device,
+            self.corrupted_from_device,
+            self.mismatched,
+            self.lost
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::packet::ECHO_PAYLOAD_LEN;
+
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_echo_args() {
+        assert_eq!(
+            parse_echo_args(&args(&["--count", "10", "--port", "/dev/ttyACM1"])),
+            Ok(EchoOptions {
+                count: 10,
+                device: DeviceOptions {
+                    port: Some("/dev/ttyACM1".to_string()),
+                    timeout: DEFAULT_ECHO_TIMEOUT,
+                },
+            })
+        );
+        assert_eq!(
+            parse_echo_args(&args(&["--timeout-ms", "0"])),
+            Err(ArgsError::InvalidValue(
+                "'--timeout-ms'".to_string(),
+                "0".to_string(),
+                "a positive number of milliseconds".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_echo_args(&args(&["--fast"])),
+            Err(ArgsError::UnknownArgument("--fast".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        let sent = EchoPacket::new([3; ECHO_PAYLOAD_LEN]);
+        assert_eq!(
+            EchoOutcome::classify(&sent, &sent.reply()),
+            EchoOutcome::Intact
+        );
+
+        let mut corrupted = sent;
+        corrupted.payload[0] = 4;
+        assert_eq!(
+            EchoOutcome::classify(&sent, &corrupted.reply()),
+            EchoOutcome::CorruptedToDevice
+        );
+
+        let mut reply = sent.reply();
+        reply.payload[0] = 4;
+        assert_eq!(
+            EchoOutcome::classify(&sent, &reply),
+            EchoOutcome::CorruptedFromDevice
+        );
+
+        let other = EchoPacket::new([5; ECHO_PAYLOAD_LEN]);
+        assert_eq!(
+            EchoOutcome::classify(&sent, &other.reply()),
+            EchoOutcome::Mismatched
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut report = QualificationReport::default();
+        report.record(EchoOutcome::Intact);
+        report.record(EchoOutcome::Intact);
+        assert!(report.passed());
+
+        report.record(EchoOutcome::Lost);
+        assert!(!report.passed());
+        assert_eq!(report.sent(), 3);
+        assert_eq!(
+            report.to_string(),
+            "2 of 3 intact, 0 corrupted to device, 0 corrupted from device, 0 mismatched, 1 lost"
+        );
+    }
+}
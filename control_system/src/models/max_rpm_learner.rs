@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+use common::physical::{Percentage, Rpm};
+
+/// Learns the maximum speed of a pump or fan by observing the peak reported
+/// speed while the channel is commanded to 100% for a full window.
+#[derive(Debug, Clone)]
+pub struct MaxRpmLearner {
+    /// How long the channel must be held at 100% before a value is learned.
+    window: Duration,
+
+    /// When the current 100% window started, if one is in progress.
+    window_start: Option<Instant>,
+
+    /// The peak speed observed during the current window.
+    peak_speed: f32,
+}
+
+impl MaxRpmLearner {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            window_start: None,
+            peak_speed: 0f32,
+        }
+    }
+
+    /// Observe the currently commanded activation and the measured speed.
+    /// Returns the learned maximum speed once the activation has been held at
+    /// 100% for a full window. Any lower activation restarts the window.
+    pub fn observe(&mut self, commanded: Percentage, measured: Rpm, now: Instant) -> Option<f32> {
+        let commanded: f32 = commanded.into();
+        if commanded < 100f32 {
+            self.window_start = None;
+            self.peak_speed = 0f32;
+            return None;
+        }
+
+        let window_start = *self.window_start.get_or_insert(now);
+        self.peak_speed = self.peak_speed.max(measured.speed());
+
+        if now.duration_since(window_start) < self.window {
+            return None;
+        }
+
+        let learned = self.peak_speed;
+        self.window_start = Some(now);
+        self.peak_speed = 0f32;
+
+        // NOTE: A stalled or disconnected channel must not be learned as 0 RPM.
+        if learned > 0f32 {
+            Some(learned)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn percent(value: f32) -> Percentage {
+        Percentage::try_from(value).expect("Failed to get Percentage.")
+    }
+
+    fn rpm(speed: f32) -> Rpm {
+        Rpm::new(2000f32, speed).expect("Failed to get RPM.")
+    }
+
+    #[test]
+    fn test_learns_peak_after_full_window() {
+        let mut learner = MaxRpmLearner::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert_eq!(learner.observe(percent(100f32), rpm(1200f32), start), None);
+        assert_eq!(
+            learner.observe(
+                percent(100f32),
+                rpm(1750f32),
+                start + Duration::from_secs(5)
+            ),
+            None
+        );
+        assert_eq!(
+            learner.observe(
+                percent(100f32),
+                rpm(1700f32),
+                start + Duration::from_secs(10)
+            ),
+            Some(1750f32)
+        );
+    }
+
+    #[test]
+    fn test_lower_activation_restarts_window() {
+        let mut learner = MaxRpmLearner::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        learner.observe(percent(100f32), rpm(1750f32), start);
+        learner.observe(percent(90f32), rpm(1600f32), start + Duration::from_secs(5));
+        assert_eq!(
+            learner.observe(
+                percent(100f32),
+                rpm(1700f32),
+                start + Duration::from_secs(10)
+            ),
+            None
+        );
+        assert_eq!(
+            learner.observe(
+                percent(100f32),
+                rpm(1720f32),
+                start + Duration::from_secs(20)
+            ),
+            Some(1720f32)
+        );
+    }
+
+    #[test]
+    fn test_stalled_channel_is_not_learned() {
+        let mut learner = MaxRpmLearner::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        learner.observe(percent(100f32), rpm(0f32), start);
+        assert_eq!(
+            learner.observe(percent(100f32), rpm(0f32), start + Duration::from_secs(10)),
+            None
+        );
+    }
+}
@@ -0,0 +1,535 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Display,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use common::physical::ValveState;
+use serde_json::{json, Value};
+
+use super::{cli_args::ArgsError, journal::JournalRecord, temperature::TemperatureUnit};
+
+/// Environment variable used to serve metrics over HTTP, such as
+/// `127.0.0.1:9464`. Metrics aren't served if it is unset.
+pub const METRICS_ADDR_ENV_VAR: &str = "PRANDTL_METRICS_ADDR";
+
+/// Get the address to serve metrics on from the environment. Returns `None`
+/// if it is unset or invalid.
+pub fn metrics_addr_from_env() -> Option<SocketAddr> {
+    env::var(METRICS_ADDR_ENV_VAR).ok()?.trim().parse().ok()
+}
+
+/// How a metric's value behaves, as Prometheus types it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// A value which can go up and down.
+    Gauge,
+
+    /// A total which only goes up while the control system runs.
+    Counter,
+}
+
+impl Display for MetricKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetricKind::Gauge => write!(f, "gauge"),
+            MetricKind::Counter => write!(f, "counter"),
+        }
+    }
+}
+
+/// A metric the exporter serves. The Grafana dashboard is generated from
+/// these, so it always matches what is served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+
+    /// Names of the labels each series has, in order.
+    pub labels: &'static [&'static str],
+
+    /// Grafana's name for the unit of the value, such as `celsius`.
+    pub unit: &'static str,
+}
+
+pub const CPU_TEMPERATURE: Metric = Metric {
+    name: "prandtl_cpu_temperature_celsius",
+    help: "Cpu temperature the control loop steers by.",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    unit: "celsius",
+};
+
+/// `CPU_TEMPERATURE`, served instead when temperatures are displayed in
+/// Fahrenheit.
+pub const CPU_TEMPERATURE_FAHRENHEIT: Metric = Metric {
+    name: "prandtl_cpu_temperature_fahrenheit",
+    unit: "fahrenheit",
+    ..CPU_TEMPERATURE
+};
+
+pub const PUMP_SPEED: Metric = Metric {
+    name: "prandtl_pump_speed_rpm",
+    help: "Pump speed reported by the device.",
+    kind: MetricKind::Gauge,
+    labels: &["device"],
+    unit: "rotrpm",
+};
+
+pub const FAN_SPEED: Metric = Metric {
+    name: "prandtl_fan_speed_rpm",
+    help: "Speed of the device's first fan.",
+    kind: MetricKind::Gauge,
+    labels: &["device"],
+    unit: "rotrpm",
+};
+
+pub const PUMP_DUTY: Metric = Metric {
+    name: "prandtl_pump_duty_percent",
+    help: "Pump duty last sent to the device.",
+    kind: MetricKind::Gauge,
+    labels: &["device"],
+    unit: "percent",
+};
+
+pub const FAN_DUTY: Metric = Metric {
+    name: "prandtl_fan_duty_percent",
+    help: "Fan duty last sent to the device.",
+    kind: MetricKind::Gauge,
+    labels: &["device"],
+    unit: "percent",
+};
+
+pub const VALVE_OPEN: Metric = Metric {
+    name: "prandtl_valve_open",
+    help: "Whether the valve was last commanded open.",
+    kind: MetricKind::Gauge,
+    labels: &["device"],
+    unit: "bool",
+};
+
+pub const CONTROL_FRAMES: Metric = Metric {
+    name: "prandtl_control_frames_total",
+    help: "Control frames generated for the device.",
+    kind: MetricKind::Counter,
+    labels: &["device"],
+    unit: "short",
+};
+
+pub const CONTROL_STEP_INTERVAL: Metric = Metric {
+    name: "prandtl_control_step_interval_ms",
+    help: "Mean interval between the device's control steps.",
+    kind: MetricKind::Gauge,
+    labels: &["device"],
+    unit: "ms",
+};
+
+pub const CONTROL_STEP_JITTER: Metric = Metric {
+    name: "prandtl_control_step_jitter_ms",
+    help: "Standard deviation of the intervals between the device's control steps.",
+    kind: MetricKind::Gauge,
+    labels: &["device"],
+    unit: "ms",
+};
+
+pub const CONTROL_STEPS_CLAMPED: Metric = Metric {
+    name: "prandtl_control_steps_clamped_total",
+    help: "Control steps whose interval was longer than the control loop credits.",
+    kind: MetricKind::Counter,
+    labels: &["device"],
+    unit: "short",
+};
+
+pub const JOURNAL_SIZE: Metric = Metric {
+    name: "prandtl_journal_size_bytes",
+    help: "Size of the journal after the last maintenance run.",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    unit: "bytes",
+};
+
+pub const JOURNAL_RECLAIMED: Metric = Metric {
+    name: "prandtl_journal_reclaimed_bytes_total",
+    help: "Space reclaimed from the journal by maintenance.",
+    kind: MetricKind::Counter,
+    labels: &[],
+    unit: "bytes",
+};
+
+/// Every metric the exporter serves with temperatures in Celsius, in the
+/// order they are served and laid out on the dashboard.
+pub const METRICS: &[Metric] = &[
+    CPU_TEMPERATURE,
+    PUMP_SPEED,
+    FAN_SPEED,
+    PUMP_DUTY,
+    FAN_DUTY,
+    VALVE_OPEN,
+    CONTROL_FRAMES,
+    CONTROL_STEP_INTERVAL,
+    CONTROL_STEP_JITTER,
+    CONTROL_STEPS_CLAMPED,
+    JOURNAL_SIZE,
+    JOURNAL_RECLAIMED,
+];
+
+/// Get the cpu temperature metric for temperatures displayed in `unit`.
+pub fn cpu_temperature(unit: TemperatureUnit) -> Metric {
+    match unit {
+        TemperatureUnit::Celsius => CPU_TEMPERATURE,
+        TemperatureUnit::Fahrenheit => CPU_TEMPERATURE_FAHRENHEIT,
+    }
+}
+
+/// Get every metric the exporter serves with temperatures in `unit`, in the
+/// order they are served and laid out on the dashboard.
+pub fn metrics(unit: TemperatureUnit) -> Vec<Metric> {
+    METRICS
+        .iter()
+        .map(|&metric| match metric == CPU_TEMPERATURE {
+            true => cpu_temperature(unit),
+            false => metric,
+        })
+        .collect()
+}
+
+/// The latest value of every series, by metric name and then label values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricValues {
+    values: BTreeMap<&'static str, BTreeMap<Vec<String>, f64>>,
+
+    /// The unit temperatures are served in.
+    unit: TemperatureUnit,
+}
+
+/// Metric values shared between the task collecting them and the exporter.
+pub type SharedMetrics = Arc<Mutex<MetricValues>>;
+
+impl MetricValues {
+    pub fn new(unit: TemperatureUnit) -> Self {
+        Self {
+            values: BTreeMap::new(),
+            unit,
+        }
+    }
+
+    /// Set the series of `metric` with `labels` to `value`.
+    pub fn set(&mut self, metric: &Metric, labels: &[&str], value: f64) {
+        *self.series(metric, labels) = value;
+    }
+
+    /// Add `value` to the series of `metric` with `labels`.
+    pub fn add(&mut self, metric: &Metric, labels: &[&str], value: f64) {
+        *self.series(metric, labels) += value;
+    }
+
+    /// Get the series of `metric` with `labels`, if it has a value.
+    pub fn get(&self, metric: &Metric, labels: &[&str]) -> Option<f64> {
+        let labels: Vec<String> = labels.iter().map(|label| label.to_string()).collect();
+        self.values.get(metric.name)?.get(&labels).copied()
+    }
+
+    fn series(&mut self, metric: &Metric, labels: &[&str]) -> &mut f64 {
+        debug_assert_eq!(labels.len(), metric.labels.len());
+        self.values
+            .entry(metric.name)
+            .or_default()
+            .entry(labels.iter().map(|label| label.to_string()).collect())
+            .or_default()
+    }
+
+    /// Update the metrics a record carries.
+    pub fn observe(&mut self, record: &JournalRecord) {
+        match record {
+            JournalRecord::HostSensors(data) => {
+                let temperature = data.cpu_temperature.in_unit(self.unit);
+                self.set(&cpu_temperature(self.unit), &[], temperature as f64)
+            }
+            JournalRecord::ClientSensors(data) => {
+                let device = [data.device.as_str()];
+                self.set(&PUMP_SPEED, &device, data.pump_speed.speed() as f64);
+                self.set(&FAN_SPEED, &device, data.fan_speed.speed() as f64);
+            }
+            JournalRecord::ControlFrame(event) => {
+                let device = [event.device.as_str()];
+                let pump: f32 = event.pump_activation.into();
+                let fan: f32 = event.fan_activation.into();
+                let open = matches!(event.valve_state, ValveState::Open);
+                self.set(&PUMP_DUTY, &device, pump as f64);
+                self.set(&FAN_DUTY, &device, fan as f64);
+                self.set(&VALVE_OPEN, &device, if open { 1f64 } else { 0f64 });
+                self.add(&CONTROL_FRAMES, &device, 1f64);
+            }
+            JournalRecord::ControlTiming { device, stats } => {
+                let device = [device.as_str()];
+                self.set(
+                    &CONTROL_STEP_INTERVAL,
+                    &device,
+                    stats.mean_interval_ms as f64,
+                );
+                self.set(&CONTROL_STEP_JITTER, &device, stats.jitter_ms as f64);
+                self.add(&CONTROL_STEPS_CLAMPED, &device, stats.clamped_steps as f64);
+            }
+            JournalRecord::Retention(stats) => {
+                self.set(&JOURNAL_SIZE, &[], stats.size_bytes as f64);
+                self.set(&JOURNAL_RECLAIMED, &[], stats.total_reclaimed_bytes as f64);
+            }
+            _ => {}
+        }
+    }
+
+    /// Render every metric with a value in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for metric in metrics(self.unit) {
+            let Some(series) = self.values.get(metric.name) else {
+                continue;
+            };
+            text.push_str(&format!("# HELP {} {}\n", metric.name, metric.help));
+            text.push_str(&format!("# TYPE {} {}\n", metric.name, metric.kind));
+            for (values, value) in series {
+                let labels = metric
+                    .labels
+                    .iter()
+                    .zip(values)
+                    .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+                    .collect::<Vec<_>>();
+                match labels.is_empty() {
+                    true => text.push_str(&format!("{} {}\n", metric.name, value)),
+                    false => text.push_str(&format!(
+                        "{}{{{}}} {}\n",
+                        metric.name,
+                        labels.join(","),
+                        value
+                    )),
+                }
+            }
+        }
+        text
+    }
+}
+
+/// Escape a label value for the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Build a Grafana dashboard with a panel for each of `metrics`, querying
+/// exactly the names and labels the exporter serves. The Prometheus data
+/// source is chosen when the dashboard is imported.
+pub fn grafana_dashboard(metrics: &[Metric]) -> Value {
+    let panels = metrics
+        .iter()
+        .enumerate()
+        .map(|(i, metric)| {
+            let expr = match metric.kind {
+                MetricKind::Gauge => metric.name.to_string(),
+                MetricKind::Counter => format!("rate({}[5m])", metric.name),
+            };
+            let legend = metric
+                .labels
+                .iter()
+                .map(|label| format!("{{{{{}}}}}", label))
+                .collect::<Vec<_>>()
+                .join(" ");
+            json!({
+                "id": i + 1,
+                "type": "timeseries",
+                "title": metric.help.trim_end_matches('.'),
+                "datasource": { "type": "prometheus", "uid": "${DS_PROMETHEUS}" },
+                "gridPos": { "h": 8, "w": 12, "x": (i % 2) * 12, "y": (i / 2) * 8 },
+                "fieldConfig": { "defaults": { "unit": metric.unit }, "overrides": [] },
+                "targets": [{
+                    "refId": "A",
+                    "datasource": { "type": "prometheus", "uid": "${DS_PROMETHEUS}" },
+                    "expr": expr,
+                    "legendFormat": if legend.is_empty() { metric.name.to_string() } else { legend },
+                }],
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "__inputs": [{
+            "name": "DS_PROMETHEUS",
+            "label": "Prometheus",
+            "type": "datasource",
+            "pluginId": "prometheus",
+            "pluginName": "Prometheus",
+        }],
+        "title": "Prandtl control system",
+        "uid": "prandtl-control-system",
+        "tags": ["prandtl"],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "refresh": "10s",
+        "time": { "from": "now-1h", "to": "now" },
+        "panels": panels,
+    })
+}
+
+/// Parse the arguments to `metrics`, of which only `grafana-dashboard` is
+/// understood.
+pub fn parse_metrics_args(args: &[String]) -> Result<(), ArgsError> {
+    match args {
+        [] => Err(ArgsError::Missing("'grafana-dashboard'".to_string())),
+        [command] if command == "grafana-dashboard" => Ok(()),
+        [command] => Err(ArgsError::UnknownArgument(command.clone())),
+        [_, unknown, ..] => Err(ArgsError::UnknownArgument(unknown.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::physical::Percentage;
+
+    use super::*;
+    use crate::models::cli_args::args;
+    use crate::models::{
+        control_event::ControlEvent, control_timing::ControlTimingStats, device_id::DeviceId,
+        host_sensor_data::HostSensorData, retention::RetentionStats,
+    };
+
+    #[test]
+    fn test_observe_and_render() {
+        let mut metrics = MetricValues::default();
+        assert_eq!(metrics.render(), "");
+
+        let host = HostSensorData::new(52.5f32).expect("Failed to get HostSensorData.");
+        metrics.observe(&JournalRecord::HostSensors(host));
+        let event = ControlEvent {
+            device: DeviceId::new("1324"),
+            fan_activation: Percentage::try_from(40f32).expect("Failed to get percentage."),
+            pump_activation: Percentage::try_from(75f32).expect("Failed to get percentage."),
+            valve_state: ValveState::Open,
+            fan_channels: 1,
+            sequence: None,
+            generation: 0,
+        };
+        metrics.observe(&JournalRecord::ControlFrame(event));
+        metrics.observe(&JournalRecord::ControlFrame(event));
+
+        assert_eq!(metrics.get(&CONTROL_FRAMES, &["1324"]), Some(2f64));
+        assert_eq!(
+            metrics.render(),
+            "# HELP prandtl_cpu_temperature_celsius Cpu temperature the control loop steers by.\n\
+             # TYPE prandtl_cpu_temperature_celsius gauge\n\
+             prandtl_cpu_temperature_celsius 52.5\n\
+             # HELP prandtl_pump_duty_percent Pump duty last sent to the device.\n\
+             # TYPE prandtl_pump_duty_percent gauge\n\
+             prandtl_pump_duty_percent{device=\"1324\"} 75\n\
+             # HELP prandtl_fan_duty_percent Fan duty last sent to the device.\n\
+             # TYPE prandtl_fan_duty_percent gauge\n\
+             prandtl_fan_duty_percent{device=\"1324\"} 40\n\
+             # HELP prandtl_valve_open Whether the valve was last commanded open.\n\
+             # TYPE prandtl_valve_open gauge\n\
+             prandtl_valve_open{device=\"1324\"} 1\n\
+             # HELP prandtl_control_frames_total Control frames generated for the device.\n\
+             # TYPE prandtl_control_frames_total counter\n\
+             prandtl_control_frames_total{device=\"1324\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn test_observe_in_fahrenheit() {
+        let mut metrics = MetricValues::new(TemperatureUnit::Fahrenheit);
+        let host = HostSensorData::new(50f32).expect("Failed to get HostSensorData.");
+        metrics.observe(&JournalRecord::HostSensors(host));
+
+        assert_eq!(metrics.get(&CPU_TEMPERATURE, &[]), None);
+        assert_eq!(
+            metrics.render(),
+            "# HELP prandtl_cpu_temperature_fahrenheit Cpu temperature the control loop steers by.\n\
+             # TYPE prandtl_cpu_temperature_fahrenheit gauge\n\
+             prandtl_cpu_temperature_fahrenheit 122\n"
+        );
+    }
+
+    #[test]
+    fn test_observe_control_timing() {
+        let mut metrics = MetricValues::default();
+        let device = DeviceId::new("1324");
+        let stats = ControlTimingStats {
+            steps: 20,
+            mean_interval_ms: 500f32,
+            min_interval_ms: 480f32,
+            max_interval_ms: 2500f32,
+            jitter_ms: 12.5f32,
+            clamped_steps: 1,
+        };
+        metrics.observe(&JournalRecord::ControlTiming { device, stats });
+        metrics.observe(&JournalRecord::ControlTiming { device, stats });
+        assert_eq!(metrics.get(&CONTROL_STEP_INTERVAL, &["1324"]), Some(500f64));
+        assert_eq!(metrics.get(&CONTROL_STEP_JITTER, &["1324"]), Some(12.5f64));
+        assert_eq!(metrics.get(&CONTROL_STEPS_CLAMPED, &["1324"]), Some(2f64));
+    }
+
+    #[test]
+    fn test_observe_retention() {
+        let mut metrics = MetricValues::default();
+        let stats = RetentionStats {
+            size_bytes: 1024,
+            reclaimed_bytes: 1024,
+            pruned_for_size: false,
+            runs: 2,
+            total_reclaimed_bytes: 4096,
+        };
+        metrics.observe(&JournalRecord::Retention(stats));
+        assert_eq!(metrics.get(&JOURNAL_SIZE, &[]), Some(1024f64));
+        assert_eq!(metrics.get(&JOURNAL_RECLAIMED, &[]), Some(4096f64));
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn test_grafana_dashboard_matches_metrics() {
+        let dashboard = grafana_dashboard(METRICS);
+        let panels = dashboard["panels"].as_array().expect("Expected panels.");
+        assert_eq!(panels.len(), METRICS.len());
+        for (panel, metric) in panels.iter().zip(METRICS) {
+            let expr = panel["targets"][0]["expr"]
+                .as_str()
+                .expect("Expected a query.");
+            assert!(expr.contains(metric.name));
+            for label in metric.labels {
+                let legend = panel["targets"][0]["legendFormat"]
+                    .as_str()
+                    .expect("Expected a legend.");
+                assert!(legend.contains(&format!("{{{{{}}}}}", label)));
+            }
+        }
+        assert_eq!(
+            panels[6]["targets"][0]["expr"],
+            "rate(prandtl_control_frames_total[5m])"
+        );
+
+        let dashboard = grafana_dashboard(&metrics(TemperatureUnit::Fahrenheit));
+        let panel = &dashboard["panels"][0];
+        assert_eq!(
+            panel["targets"][0]["expr"],
+            "prandtl_cpu_temperature_fahrenheit"
+        );
+        assert_eq!(panel["fieldConfig"]["defaults"]["unit"], "fahrenheit");
+    }
+
+    #[test]
+    fn test_parse_metrics_args() {
+        assert_eq!(parse_metrics_args(&args(&["grafana-dashboard"])), Ok(()));
+        assert_eq!(
+            parse_metrics_args(&args(&[])),
+            Err(ArgsError::Missing("'grafana-dashboard'".to_string()))
+        );
+        assert_eq!(
+            parse_metrics_args(&args(&["prometheus"])),
+            Err(ArgsError::UnknownArgument("prometheus".to_string()))
+        );
+    }
+}
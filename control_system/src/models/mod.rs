@@ -1,5 +1,72 @@
+pub mod addressed_packet;
+pub mod alarm;
+pub mod capabilities;
+pub mod cli_args;
 pub mod client_sensor_data;
+pub mod control_acks;
+#[cfg(feature = "control-socket")]
+pub mod control_auth;
 pub mod control_event;
+pub mod control_generation;
+pub mod control_loop;
+pub mod control_override;
+#[cfg(feature = "scripting")]
+pub mod control_script;
+#[cfg(feature = "control-socket")]
+pub mod control_socket;
+pub mod control_timing;
+pub mod control_trace;
+pub mod convergence_checker;
 pub mod curve;
+pub mod curve_preview;
+pub mod daemon_config;
+pub mod device_id;
+pub mod device_log;
+pub mod device_registry;
+#[cfg(feature = "recording")]
+pub mod diagnostics;
+pub mod duty_rpm_model;
+pub mod estimation;
+pub mod event_bus;
+pub mod fault_injection;
+#[cfg(feature = "simulate")]
+pub mod fault_simulation;
+pub mod firmware_config;
+pub mod firmware_stats;
+pub mod flow_interlock;
+pub mod heartbeat;
 pub mod host_sensor_data;
+pub mod injection;
+pub mod journal;
+pub mod latency;
+pub mod link;
+pub mod link_qualification;
+pub mod max_rpm_learner;
+#[cfg(feature = "http")]
+pub mod metrics;
+pub mod oscillation;
+pub mod outgoing_queue;
+pub mod parameter_sync;
+pub mod reboot;
+pub mod retention;
+#[cfg(feature = "recording")]
+pub mod rpm_trend;
+pub mod sensor_status;
+pub mod shutdown;
+pub mod sleep;
+pub mod startup_check;
+pub mod state_machine;
+pub mod telemetry_rate;
+#[cfg(feature = "recording")]
+pub mod telemetry_store;
+pub mod telemetry_summary;
 pub mod temperature;
+pub mod temperature_aggregation;
+pub mod temperature_calibration;
+pub mod temperature_failover;
+pub mod time_sync;
+pub mod timings;
+pub mod valve_model;
+pub mod valve_policy;
+pub mod write_batch;
+pub mod write_pacing;
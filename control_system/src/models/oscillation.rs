@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use common::physical::Percentage;
+
+use super::control_event::ControlEvent;
+
+/// How many of the latest generated duties are looked at for oscillation.
+const OSCILLATION_WINDOW: usize = 12;
+
+/// Standard deviation of a duty over the window, in percent, above which it
+/// swings too far to be noise.
+const OSCILLATION_MIN_SPREAD: f32 = 5f32;
+
+/// How many times a duty must change direction within the window to count as
+/// oscillating rather than ramping.
+const OSCILLATION_MIN_REVERSALS: usize = 4;
+
+/// How long outputs stay damped after oscillation was last seen.
+pub const OSCILLATION_DAMPING_HOLD: Duration = Duration::from_secs(60);
+
+/// Most a damped duty may move by per second, in percent.
+const DAMPED_SLEW_PERCENT_PER_SEC: f32 = 2f32;
+
+/// The latest duties generated for one output, oldest first once full.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct DutyWindow {
+    duties: [f32; OSCILLATION_WINDOW],
+    len: usize,
+    next: usize,
+}
+
+impl DutyWindow {
+    fn push(&mut self, duty: f32) {
+        self.duties[self.next] = duty;
+        self.next = (self.next + 1) % OSCILLATION_WINDOW;
+        self.len = (self.len + 1).min(OSCILLATION_WINDOW);
+    }
+
+    /// Whether a full window of duties both spreads widely and keeps
+    /// changing direction. A ramp spreads as widely but never reverses, and
+    /// noise reverses often but barely spreads.
+    fn is_oscillating(&self) -> bool {
+        if self.len < OSCILLATION_WINDOW {
+            return false;
+        }
+        let duties = (0..OSCILLATION_WINDOW)
+            .map(|index| self.duties[(self.next + index) % OSCILLATION_WINDOW])
+            .collect::<Vec<_>>();
+        let mean = duties.iter().sum::<f32>() / OSCILLATION_WINDOW as f32;
+        let variance = duties.iter().map(|duty| (duty - mean).powi(2)).sum::<f32>()
+            / OSCILLATION_WINDOW as f32;
+        if variance.sqrt() < OSCILLATION_MIN_SPREAD {
+            return false;
+        }
+        let mut directions = duties
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .filter(|change| *change != 0f32)
+            .map(f32::is_sign_positive);
+        let Some(mut direction) = directions.next() else {
+            return false;
+        };
+        let mut reversals = 0;
+        for next in directions {
+            if next != direction {
+                reversals += 1;
+                direction = next;
+            }
+        }
+        reversals >= OSCILLATION_MIN_REVERSALS
+    }
+}
+
+/// Watches the generated pump and fan duties of a device for oscillation,
+/// such as from gains set too aggressively, and slew limits the outputs
+/// while it is seen and for `OSCILLATION_DAMPING_HOLD` after.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OscillationDamper {
+    pump: DutyWindow,
+    fan: DutyWindow,
+
+    /// How much longer outputs are damped for, if they are.
+    remaining: Option<Duration>,
+}
+
+impl OscillationDamper {
+    /// Record the outputs generated `dt` after the previous ones, before any
+    /// damping, so damping lasts as long as whatever generates them still
+    /// oscillates.
+    pub fn observe(self, generated: &ControlEvent, dt: Duration) -> Self {
+        let mut damper = self;
+        damper.pump.push(generated.pump_activation.into());
+        damper.fan.push(generated.fan_activation.into());
+        damper.remaining = if damper.pump.is_oscillating() || damper.fan.is_oscillating() {
+            Some(OSCILLATION_DAMPING_HOLD)
+        } else {
+            self.remaining
+                .and_then(|remaining| remaining.checked_sub(dt))
+                .filter(|remaining| !remaining.is_zero())
+        };
+        damper
+    }
+
+    /// Whether outputs are being damped.
+    pub fn is_damping(&self) -> bool {
+        self.remaining.is_some()
+    }
+
+    /// Limit how far the pump and fan duties of `outputs` move from
+    /// `previous` over `dt`, if damping. The valve isn't limited.
+    pub fn damp(
+        &self,
+        previous: &ControlEvent,
+        outputs: ControlEvent,
+        dt: Duration,
+    ) -> ControlEvent {
+        if !self.is_damping() {
+            return outputs;
+        }
+        let max_change = DAMPED_SLEW_PERCENT_PER_SEC * dt.as_secs_f32();
+        let limit = |from: Percentage, to: Percentage| {
+            let raw_from: f32 = from.into();
+            let raw_to: f32 = to.into();
+            let limited = raw_to.clamp(raw_from - max_change, raw_from + max_change);
+            Percentage::try_from(limited).unwrap_or(from)
+        };
+        ControlEvent {
+            fan_activation: limit(previous.fan_activation, outputs.fan_activation),
+            pump_activation: limit(previous.pump_activation, outputs.pump_activation),
+            ..outputs
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::physical::ValveState;
+
+    use super::*;
+    use crate::models::device_id::DeviceId;
+
+    fn frame(pump: f32) -> ControlEvent {
+        ControlEvent::new(DeviceId::new("1324"), 50f32, pump, ValveState::Open)
+            .expect("Failed to get ControlEvent.")
+    }
+
+    fn window(duties: impl IntoIterator<Item = f32>) -> DutyWindow {
+        let mut window = DutyWindow::default();
+        for duty in duties {
+            window.push(duty);
+        }
+        window
+    }
+
+    #[test]
+    fn test_only_wide_reversing_swings_oscillate() {
+        let swinging =
+            (0..OSCILLATION_WINDOW).map(|step| if step % 2 == 0 { 30f32 } else { 90f32 });
+        assert!(window(swinging.clone()).is_oscillating());
+        assert!(!window(swinging.take(OSCILLATION_WINDOW - 1)).is_oscillating());
+
+        let ramp = (0..OSCILLATION_WINDOW).map(|step| step as f32 * 8f32);
+        assert!(!window(ramp).is_oscillating());
+
+        let noise = (0..OSCILLATION_WINDOW).map(|step| if step % 2 == 0 { 49f32 } else { 51f32 });
+        assert!(!window(noise).is_oscillating());
+    }
+
+    #[test]
+    fn test_damps_until_hold_passes() {
+        let dt = Duration::from_secs(1);
+        let mut damper = OscillationDamper::default();
+        for step in 0..OSCILLATION_WINDOW {
+            assert!(!damper.is_damping());
+            damper = damper.observe(&frame(if step % 2 == 0 { 30f32 } else { 90f32 }), dt);
+        }
+        assert!(damper.is_damping());
+
+        let damped = damper.damp(&frame(30f32), frame(90f32), dt);
+        assert_eq!(
+            damped.pump_activation,
+            Percentage::try_from(32f32).expect("Failed to get Percentage.")
+        );
+
+        // NOTE: Settled outputs soon stop oscillating, but the hold lasts.
+        for _ in 0..OSCILLATION_WINDOW {
+            damper = damper.observe(&frame(60f32), dt);
+        }
+        assert!(damper.is_damping());
+        damper = damper.observe(&frame(60f32), OSCILLATION_DAMPING_HOLD);
+        assert!(!damper.is_damping());
+        assert_eq!(damper.damp(&frame(30f32), frame(90f32), dt), frame(90f32));
+    }
+}
@@ -0,0 +1,136 @@
+use std::mem::{discriminant, Discriminant};
+
+use common::packet::{Packet, Parameter};
+
+use super::addressed_packet::AddressedPacket;
+
+/// Identifies packets which supersede each other. Only the newest packet for
+/// each key is worth sending.
+type CoalesceKey = (Discriminant<Packet>, Option<Discriminant<Parameter>>);
+
+/// Queue of packets waiting to be written to a device. Packets of the same
+/// kind are coalesced so the newest wins, which keeps a serial stall from
+/// replaying stale control targets once the link recovers.
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingQueue {
+    packets: Vec<AddressedPacket>,
+}
+
+impl OutgoingQueue {
+    /// Queue a packet, replacing any queued packet of the same kind.
+    pub fn push(&mut self, packet: AddressedPacket) {
+        let key = coalesce_key(&packet.packet);
+        match self
+            .packets
+            .iter_mut()
+            .find(|queued| coalesce_key(&queued.packet) == key)
+        {
+            Some(queued) => *queued = packet,
+            None => self.packets.push(packet),
+        }
+    }
+
+    /// Take the next packet to send. Control targets and valve commands go
+    /// first since they're what keeps the hardware cool, then everything
+    /// else in queued order.
+    pub fn pop(&mut self) -> Option<AddressedPacket> {
+        if self.packets.is_empty() {
+            return None;
+        }
+        let index = self
+            .packets
+            .iter()
+            .position(|queued| {
+                matches!(
+                    queued.packet,
+                    Packet::ReportControlTargets(_) | Packet::ValveCommand(_)
+                )
+            })
+            .unwrap_or(0);
+        Some(self.packets.remove(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+}
+
+/// Get the key a packet is coalesced by. Parameters are keyed individually so
+/// setting one doesn't drop a pending change to another.
+fn coalesce_key(packet: &Packet) -> CoalesceKey {
+    let parameter = match packet {
+        Packet::SetParameter(set_parameter) => Some(discriminant(&set_parameter.parameter)),
+        _ => None,
+    };
+    (discriminant(packet), parameter)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        packet::{FanChannels, ReportControlTargetsPacket, SetParameterPacket},
+        physical::Percentage,
+    };
+
+    use super::*;
+    use crate::models::device_id::DeviceId;
+
+    fn control_targets(percent: f32) -> AddressedPacket {
+        let percent = Percentage::try_from(percent).expect("Failed to get Percentage.");
+        let packet = Packet::ReportControlTargets(ReportControlTargetsPacket {
+            fan_control_percents: FanChannels::single(percent),
+            pump_control_percent: percent,
+            sequence: 0,
+        });
+        AddressedPacket::new(DeviceId::new("1324"), packet)
+    }
+
+    fn set_parameter(parameter: Parameter) -> AddressedPacket {
+        let packet = Packet::SetParameter(SetParameterPacket { parameter });
+        AddressedPacket::new(DeviceId::new("1324"), packet)
+    }
+
+    #[test]
+    fn test_newest_control_targets_win() {
+        let mut queue = OutgoingQueue::default();
+        queue.push(control_targets(30f32).with_sequence(1));
+        queue.push(control_targets(40f32).with_sequence(2));
+        queue.push(control_targets(50f32).with_sequence(3));
+
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop(), Some(control_targets(50f32).with_sequence(3)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_parameters_coalesce_per_parameter() {
+        let mut queue = OutgoingQueue::default();
+        queue.push(set_parameter(Parameter::PumpMaxRpm(1700)));
+        queue.push(set_parameter(Parameter::FanMaxRpm(1500)));
+        queue.push(set_parameter(Parameter::PumpMaxRpm(1750)));
+
+        assert_eq!(
+            queue.pop(),
+            Some(set_parameter(Parameter::PumpMaxRpm(1750)))
+        );
+        assert_eq!(queue.pop(), Some(set_parameter(Parameter::FanMaxRpm(1500))));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_control_targets_are_sent_first() {
+        let mut queue = OutgoingQueue::default();
+        queue.push(set_parameter(Parameter::TelemetryRateHz(10)));
+        queue.push(control_targets(60f32));
+
+        assert_eq!(queue.pop(), Some(control_targets(60f32)));
+        assert_eq!(
+            queue.pop(),
+            Some(set_parameter(Parameter::TelemetryRateHz(10)))
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use std::fmt::Display;
+
+use common::packet::{Parameter, ParameterKind};
+
+use super::{
+    capabilities::HardwareExpectations,
+    cli_args::{Args, ArgsError, DeviceOptions},
+    device_registry::DeviceRecord,
+    injection::DEFAULT_HANDSHAKE_TIMEOUT,
+    timings::Timings,
+};
+
+/// What `params` should do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamsCommand {
+    /// Show the firmware's settings next to the host config.
+    Diff,
+
+    /// Copy the firmware's settings which differ into the host config.
+    Pull,
+
+    /// Write the host config which differs to the firmware's settings.
+    Push,
+}
+
+/// Options for `params`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamsOptions {
+    pub command: ParamsCommand,
+
+    /// The device, and how long to wait for it to report its settings.
+    pub device: DeviceOptions,
+
+    /// Apply changes without asking for confirmation.
+    pub yes: bool,
+}
+
+/// Parse the arguments to `params`: `pull`, `push` or `diff`, optionally
+/// followed by `--port <PATH>`, `--timeout-ms <MS>` and `--yes`.
+pub fn parse_params_args(args: &[String]) -> Result<ParamsOptions, ArgsError> {
+    let (command, args) = match args {
+        [] => return Err(ArgsError::Missing("'pull', 'push' or 'diff'".to_string())),
+        [command, args @ ..] => match command.as_str() {
+            "diff" => (ParamsCommand::Diff, args),
+            "pull" => (ParamsCommand::Pull, args),
+            "push" => (ParamsCommand::Push, args),
+            _ => return Err(ArgsError::UnknownArgument(command.clone())),
+        },
+    };
+    let mut options = ParamsOptions {
+        command,
+        device: DeviceOptions::new(DEFAULT_HANDSHAKE_TIMEOUT),
+        yes: false,
+    };
+
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--yes" => options.yes = true,
+            _ if options.device.parse_arg(arg, &mut args)? => {}
+            _ => return Err(ArgsError::UnknownArgument(arg.clone())),
+        }
+    }
+    Ok(options)
+}
+
+/// The firmware settings the host config has a value for: the maximum speeds
+/// learned in the device registry, the core loop period from
+/// `PRANDTL_FIRMWARE_LOOP_MS` and the flow meter from
+/// `PRANDTL_FLOW_PULSES_PER_LITER`. The telemetry rate isn't configured on
+/// the host.
+pub fn host_parameters(
+    record: &DeviceRecord,
+    timings: &Timings,
+    expectations: &HardwareExpectations,
+) -> Vec<Parameter> {
+    let rpm = |max_rpm: f32| max_rpm.round().clamp(0f32, u16::MAX as f32) as u16;
+    let mut parameters = Vec::new();
+    if let Some(max_rpm) = record.pump_max_rpm {
+        parameters.push(Parameter::PumpMaxRpm(rpm(max_rpm)));
+    }
+    if let Some(max_rpm) = record.fan_max_rpm {
+        parameters.push(Parameter::FanMaxRpm(rpm(max_rpm)));
+    }
+    if let Some(period_ms) = timings.firmware_core_loop_ms {
+        parameters.push(Parameter::CoreLoopPeriodMs(period_ms));
+    }
+    if let Some(pulses) = expectations.flow_pulses_per_liter {
+        parameters.push(Parameter::FlowPulsesPerLiter(pulses));
+    }
+    parameters
+}
+
+/// A single firmware setting as the host config and the firmware have it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterDiff {
+    pub kind: ParameterKind,
+
+    /// `None` if the host config has no value for it.
+    pub host: Option<Parameter>,
+
+    /// `None` if the firmware didn't report it.
+    pub firmware: Option<Parameter>,
+}
+
+impl ParameterDiff {
+    /// Whether both sides have a value and they disagree. A setting only one
+    /// side has is left alone rather than synced.
+    pub fn differs(&self) -> bool {
+        matches!((self.host, self.firmware), (Some(host), Some(firmware)) if host != firmware)
+    }
+}
+
+impl Display for ParameterDiff {
+    /// e.g. `pump max rpm: host 2100, firmware 2000 (differs)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = |parameter: Option<Parameter>, missing: &str| match parameter {
+            Some(parameter) => parameter.value().to_string(),
+            None => missing.to_string(),
+        };
+        write!(
+            f,
+            "{}: host {}, firmware {}",
+            self.kind,
+            value(self.host, "unset"),
+            value(self.firmware, "unknown")
+        )?;
+        if self.differs() {
+            write!(f, " (differs)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Pair up the host config and firmware settings, one entry per kind of
+/// parameter.
+pub fn diff_parameters(host: &[Parameter], firmware: &[Parameter]) -> Vec<ParameterDiff> {
+    let find = |parameters: &[Parameter], kind| {
+        parameters
+            .iter()
+            .copied()
+            .find(|parameter| parameter.kind() == kind)
+    };
+    ParameterKind::ALL
+        .into_iter()
+        .map(|kind| ParameterDiff {
+            kind,
+            host: find(host, kind),
+            firmware: find(firmware, kind),
+        })
+        .collect()
+}
+
+/// The host config of every setting which differs, to write to the firmware.
+pub fn push_changes(diffs: &[ParameterDiff]) -> Vec<Parameter> {
+    diffs
+        .iter()
+        .filter(|diff| diff.differs())
+        .filter_map(|diff| diff.host)
+        .collect()
+}
+
+/// The firmware's value of every setting which differs, to copy into the host
+/// config.
+pub fn pull_changes(diffs: &[ParameterDiff]) -> Vec<Parameter> {
+    diffs
+        .iter()
+        .filter(|diff| diff.differs())
+        .filter_map(|diff| diff.firmware)
+        .collect()
+}
+
+/// Copy a firmware setting into the device's record. Returns `false` if the
+/// setting isn't kept in the device registry, such as the core loop period
+/// and flow meter which come from the environment.
+pub fn pull_into_record(record: &mut DeviceRecord, parameter: Parameter) -> bool {
+    match parameter {
+        Parameter::PumpMaxRpm(rpm) => record.pump_max_rpm = Some(rpm as f32),
+        Parameter::FanMaxRpm(rpm) => record.fan_max_rpm = Some(rpm as f32),
+        Parameter::TelemetryRateHz(_)
+        | Parameter::CoreLoopPeriodMs(_)
+        | Parameter::FlowPulsesPerLiter(_) => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_params_args() {
+        assert_eq!(
+            parse_params_args(&args(&["push", "--port", "/dev/ttyACM0", "--yes"])),
+            Ok(ParamsOptions {
+                command: ParamsCommand::Push,
+                device: DeviceOptions {
+                    port: Some("/dev/ttyACM0".to_string()),
+                    timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+                },
+                yes: true,
+            })
+        );
+        assert_eq!(
+            parse_params_args(&args(&["diff", "--timeout-ms", "500"])).map(|o| o.device.timeout),
+            Ok(Duration::from_millis(500))
+        );
+        assert_eq!(
+            parse_params_args(&[]),
+            Err(ArgsError::Missing("'pull', 'push' or 'diff'".to_string()))
+        );
+        assert_eq!(
+            parse_params_args(&args(&["sync"])),
+            Err(ArgsError::UnknownArgument("sync".to_string()))
+        );
+        assert_eq!(
+            parse_params_args(&args(&["pull", "--port"])),
+            Err(ArgsError::MissingValue("--port".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_host_parameters() {
+        let record = DeviceRecord {
+            pump_max_rpm: Some(2099.6f32),
+            ..DeviceRecord::default()
+        };
+        let timings = Timings {
+            firmware_core_loop_ms: Some(5),
+            ..Timings::default()
+        };
+        let expectations = HardwareExpectations {
+            flow_pulses_per_liter: Some(450),
+            ..HardwareExpectations::default()
+        };
+        assert_eq!(
+            host_parameters(&record, &timings, &expectations),
+            [
+                Parameter::PumpMaxRpm(2100),
+                Parameter::CoreLoopPeriodMs(5),
+                Parameter::FlowPulsesPerLiter(450)
+            ]
+        );
+        assert!(host_parameters(
+            &DeviceRecord::default(),
+            &Timings::default(),
+            &HardwareExpectations::default()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_diff_push_and_pull() {
+        let host = [Parameter::PumpMaxRpm(2100), Parameter::CoreLoopPeriodMs(5)];
+        let firmware = [
+            Parameter::PumpMaxRpm(2000),
+            Parameter::FanMaxRpm(1800),
+            Parameter::TelemetryRateHz(2),
+            Parameter::CoreLoopPeriodMs(5),
+        ];
+        let diffs = diff_parameters(&host, &firmware);
+        assert_eq!(diffs.len(), ParameterKind::ALL.len());
+        assert_eq!(
+            diffs[0].to_string(),
+            "pump max rpm: host 2100, firmware 2000 (differs)"
+        );
+        assert_eq!(
+            diffs[1].to_string(),
+            "fan max rpm: host unset, firmware 1800"
+        );
+        assert!(!diffs[3].differs());
+
+        assert_eq!(push_changes(&diffs), [Parameter::PumpMaxRpm(2100)]);
+        assert_eq!(pull_changes(&diffs), [Parameter::PumpMaxRpm(2000)]);
+    }
+
+    #[test]
+    fn test_pull_into_record() {
+        let mut record = DeviceRecord::default();
+        assert!(pull_into_record(&mut record, Parameter::FanMaxRpm(1500)));
+        assert_eq!(record.fan_max_rpm, Some(1500f32));
+        assert!(!pull_into_record(
+            &mut record,
+            Parameter::CoreLoopPeriodMs(5)
+        ));
+    }
+}
@@ -0,0 +1,64 @@
+use super::cli_args::{Args, ArgsError, PORT_FLAG};
+
+/// Options for `reboot` and `bootloader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebootOptions {
+    /// The port of the device, as with
+    /// [`DeviceOptions::port`](super::cli_args::DeviceOptions::port).
+    pub port: Option<String>,
+
+    /// Reboot without asking for confirmation first.
+    pub yes: bool,
+}
+
+/// Parse the arguments to `reboot` or `bootloader`: optionally
+/// `--port <PATH>` and `--yes`.
+pub fn parse_reboot_args(args: &[String]) -> Result<RebootOptions, ArgsError> {
+    let mut options = RebootOptions {
+        port: None,
+        yes: false,
+    };
+
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            PORT_FLAG => options.port = Some(args.value(arg)?.clone()),
+            "--yes" => options.yes = true,
+            _ => return Err(ArgsError::UnknownArgument(arg.clone())),
+        }
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_reboot_args() {
+        assert_eq!(
+            parse_reboot_args(&[]),
+            Ok(RebootOptions {
+                port: None,
+                yes: false,
+            })
+        );
+        assert_eq!(
+            parse_reboot_args(&args(&["--port", "/dev/ttyACM0", "--yes"])),
+            Ok(RebootOptions {
+                port: Some("/dev/ttyACM0".to_string()),
+                yes: true,
+            })
+        );
+        assert_eq!(
+            parse_reboot_args(&args(&["--port"])),
+            Err(ArgsError::MissingValue("--port".to_string()))
+        );
+        assert_eq!(
+            parse_reboot_args(&args(&["--force"])),
+            Err(ArgsError::UnknownArgument("--force".to_string()))
+        );
+    }
+}
@@ -0,0 +1,241 @@
+#[cfg(feature = "recording")]
+use std::{env, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "recording")]
+use super::{
+    journal::{JournalEntry, JournalRecord, JOURNAL_RETENTION},
+    telemetry_summary::SUMMARY_RETENTION,
+};
+
+#[cfg(feature = "recording")]
+/// Environment variable used to override how long journal entries are kept.
+pub const JOURNAL_MAX_AGE_ENV_VAR: &str = "PRANDTL_JOURNAL_MAX_AGE_MINUTES";
+
+#[cfg(feature = "recording")]
+/// Environment variable used to override how large the journal may grow.
+pub const JOURNAL_MAX_SIZE_ENV_VAR: &str = "PRANDTL_JOURNAL_MAX_SIZE_MB";
+
+#[cfg(feature = "recording")]
+/// Default size the journal is kept under, in bytes.
+pub const DEFAULT_JOURNAL_MAX_SIZE: u64 = 100 * 1024 * 1024;
+
+#[cfg(feature = "recording")]
+/// Fraction of the maximum size the journal is pruned down to once it grows
+/// past it, so the next few appends don't immediately need pruning again.
+const SIZE_LOW_WATER_MARK: f64 = 0.8;
+
+/// What a journal maintenance run did. Totals count since the control
+/// system started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionStats {
+    /// Size of the journal after the run, in bytes.
+    pub size_bytes: u64,
+
+    /// Bytes reclaimed by the run.
+    pub reclaimed_bytes: u64,
+
+    /// Whether entries younger than the maximum age had to be pruned to get
+    /// under the maximum size.
+    pub pruned_for_size: bool,
+
+    /// Maintenance runs so far.
+    pub runs: u64,
+
+    /// Bytes reclaimed over every run so far.
+    pub total_reclaimed_bytes: u64,
+}
+
+#[cfg(feature = "recording")]
+impl RetentionStats {
+    /// Account for a run which took the journal from `size_before` to
+    /// `size_after` bytes.
+    pub fn record_run(&mut self, size_before: u64, size_after: u64, pruned_for_size: bool) {
+        let reclaimed_bytes = size_before.saturating_sub(size_after);
+        self.size_bytes = size_after;
+        self.reclaimed_bytes = reclaimed_bytes;
+        self.pruned_for_size = pruned_for_size;
+        self.runs += 1;
+        self.total_reclaimed_bytes += reclaimed_bytes;
+    }
+}
+
+#[cfg(feature = "recording")]
+/// How long journal entries are kept and how large the journal may grow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// How long entries other than summaries are kept.
+    pub max_age: Duration,
+
+    /// How long telemetry summaries are kept.
+    pub summary_max_age: Duration,
+
+    /// Size in bytes past which the oldest entries are pruned early.
+    pub max_size: u64,
+}
+
+#[cfg(feature = "recording")]
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: JOURNAL_RETENTION,
+            summary_max_age: SUMMARY_RETENTION,
+            max_size: DEFAULT_JOURNAL_MAX_SIZE,
+        }
+    }
+}
+
+#[cfg(feature = "recording")]
+impl RetentionPolicy {
+    /// Get the retention policy from the environment, falling back to the
+    /// defaults for anything unset or invalid.
+    pub fn from_env() -> Self {
+        let max_age = env::var(JOURNAL_MAX_AGE_ENV_VAR).ok();
+        let max_size = env::var(JOURNAL_MAX_SIZE_ENV_VAR).ok();
+        let defaults = Self::default();
+        Self {
+            max_age: parse_positive(max_age.as_deref())
+                .map(|minutes| Duration::from_secs(minutes * 60))
+                .unwrap_or(defaults.max_age),
+            max_size: parse_positive(max_size.as_deref())
+                .map(|megabytes| megabytes * 1024 * 1024)
+                .unwrap_or(defaults.max_size),
+            ..defaults
+        }
+    }
+
+    /// Get the cutoffs, in milliseconds since the unix epoch, for pruning a
+    /// journal of `size` bytes holding `entries` at `now_ms`. Returns
+    /// `(since_ms, summaries_since_ms)` and whether the size forced entries
+    /// younger than their maximum age out. Raw entries are given up before
+    /// summaries. Entries are assumed to take roughly the same space each.
+    pub fn cutoffs(&self, entries: &[JournalEntry], size: u64, now_ms: u64) -> ((u64, u64), bool) {
+        let since_ms = now_ms.saturating_sub(self.max_age.as_millis() as u64);
+        let summaries_since_ms = now_ms.saturating_sub(self.summary_max_age.as_millis() as u64);
+        if size <= self.max_size || entries.is_empty() {
+            return ((since_ms, summaries_since_ms), false);
+        }
+
+        let is_summary =
+            |entry: &&JournalEntry| matches!(entry.record, JournalRecord::TelemetrySummary(_));
+        let mut raw = entries
+            .iter()
+            .filter(|entry| !is_summary(entry))
+            .filter(|entry| entry.timestamp_ms >= since_ms)
+            .map(|entry| entry.timestamp_ms)
+            .collect::<Vec<_>>();
+        let mut summaries = entries
+            .iter()
+            .filter(is_summary)
+            .filter(|entry| entry.timestamp_ms >= summaries_since_ms)
+            .map(|entry| entry.timestamp_ms)
+            .collect::<Vec<_>>();
+        raw.sort_unstable();
+        summaries.sort_unstable();
+
+        // NOTE: Entries already past their maximum age free space too.
+        let kept = raw.len() + summaries.len();
+        let entry_size = size as f64 / entries.len() as f64;
+        let target = (self.max_size as f64 * SIZE_LOW_WATER_MARK / entry_size) as usize;
+        let excess = kept.saturating_sub(target);
+        if excess == 0 {
+            return ((since_ms, summaries_since_ms), false);
+        }
+
+        // NOTE: Each cutoff keeps everything from the first entry not dropped.
+        let cutoff = |timestamps: &[u64], drop: usize, default: u64| match drop {
+            0 => default,
+            _ => timestamps
+                .get(drop)
+                .copied()
+                .unwrap_or_else(|| timestamps.last().map_or(default, |&last| last + 1)),
+        };
+        let raw_cutoff = cutoff(&raw, excess, since_ms);
+        let summary_cutoff = cutoff(
+            &summaries,
+            excess.saturating_sub(raw.len()),
+            summaries_since_ms,
+        );
+        ((raw_cutoff, summary_cutoff), true)
+    }
+}
+
+#[cfg(feature = "recording")]
+/// Parse a positive whole number. Returns `None` if it is missing, zero or
+/// not a number.
+fn parse_positive(value: Option<&str>) -> Option<u64> {
+    value?.trim().parse().ok().filter(|&value| value > 0)
+}
+
+#[cfg(all(test, feature = "recording"))]
+mod tests {
+    use super::*;
+    use crate::models::journal::{control_frame, summary};
+
+    const NOW_MS: u64 = 10_000_000;
+
+    fn policy(max_size: u64) -> RetentionPolicy {
+        RetentionPolicy {
+            max_age: Duration::from_secs(60),
+            summary_max_age: Duration::from_secs(600),
+            max_size,
+        }
+    }
+
+    #[test]
+    fn test_cutoffs_by_age() {
+        let entries = [control_frame(NOW_MS - 1000)];
+        assert_eq!(
+            policy(1000).cutoffs(&entries, 100, NOW_MS),
+            ((NOW_MS - 60_000, NOW_MS - 600_000), false)
+        );
+    }
+
+    #[test]
+    fn test_cutoffs_by_size_drop_raw_first() {
+        // NOTE: 10 raw entries and 2 summaries of 100 bytes each.
+        let mut entries = (0..10)
+            .map(|i| control_frame(NOW_MS - 10_000 + i * 1000))
+            .collect::<Vec<_>>();
+        entries.push(summary(NOW_MS - 5_000));
+        entries.push(summary(NOW_MS - 1_000));
+
+        // NOTE: Pruned down to 800 bytes, so 4 raw entries go.
+        let ((since_ms, summaries_since_ms), pruned_for_size) =
+            policy(1000).cutoffs(&entries, 1200, NOW_MS);
+        assert!(pruned_for_size);
+        assert_eq!(since_ms, NOW_MS - 6_000);
+        assert_eq!(summaries_since_ms, NOW_MS - 600_000);
+
+        // NOTE: Pruned down to 160 bytes, so every raw entry and a summary go.
+        let ((since_ms, summaries_since_ms), _) = policy(200).cutoffs(&entries, 1200, NOW_MS);
+        assert_eq!(since_ms, NOW_MS - 1_000 + 1);
+        assert_eq!(summaries_since_ms, NOW_MS - 1_000);
+    }
+
+    #[test]
+    fn test_record_run() {
+        let mut stats = RetentionStats::default();
+        stats.record_run(1000, 400, true);
+        stats.record_run(400, 500, false);
+        assert_eq!(
+            stats,
+            RetentionStats {
+                size_bytes: 500,
+                reclaimed_bytes: 0,
+                pruned_for_size: false,
+                runs: 2,
+                total_reclaimed_bytes: 600,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_positive() {
+        assert_eq!(parse_positive(Some("30")), Some(30));
+        assert_eq!(parse_positive(Some("0")), None);
+        assert_eq!(parse_positive(Some("lots")), None);
+        assert_eq!(parse_positive(None), None);
+    }
+}
@@ -0,0 +1,290 @@
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+
+use super::{
+    device_id::DeviceId,
+    telemetry_summary::{SummaryStats, TelemetrySummary, SUMMARY_RETENTION},
+};
+
+/// How far back speed trends are looked for. As long as summaries are kept.
+pub const RPM_TREND_WINDOW: Duration = SUMMARY_RETENTION;
+
+/// The shortest span of summaries a trend is judged over, so a single bad
+/// day can't raise an advisory.
+pub const MIN_RPM_TREND_SPAN: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// Relative drop in speed at the same duty over the trend which is worth a
+/// maintenance advisory. Clogging blocks and wearing bearings slow a channel
+/// by a few percent long before it stalls.
+pub const RPM_DRIFT_THRESHOLD: f32 = 0.05f32;
+
+/// Summaries are grouped into duty bins this wide, in percent, so only
+/// speeds at about the same duty are compared.
+const DUTY_BIN_WIDTH: f32 = 10f32;
+
+/// Summaries whose commanded duty varied more than this, in percent, aren't
+/// steady enough to compare.
+const MAX_DUTY_SPREAD: f32 = 2f32;
+
+/// The fewest summaries a duty bin needs before its trend is judged.
+const MIN_TREND_POINTS: usize = 12;
+
+/// Which output of a device a trend is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Channel {
+    Pump,
+    Fan,
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channel::Pump => write!(f, "pump"),
+            Channel::Fan => write!(f, "fan"),
+        }
+    }
+}
+
+/// The mean speed of a channel over a summary interval held at a steady duty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrendPoint {
+    /// Milliseconds since the unix epoch when the interval ended.
+    pub at_ms: u64,
+
+    /// Commanded duty in percent.
+    pub duty: f32,
+    pub rpm: f32,
+}
+
+/// A channel slowing down at the same duty over the trend.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RpmDrift {
+    /// The middle of the duty bin it was seen in, in percent.
+    pub duty: f32,
+
+    /// The fitted speed at the start and end of the trend.
+    pub start_rpm: f32,
+    pub end_rpm: f32,
+
+    /// How long the trend covers.
+    pub span: Duration,
+}
+
+impl RpmDrift {
+    /// How much slower the channel got, relative to where it started.
+    pub fn fraction(&self) -> f32 {
+        (self.start_rpm - self.end_rpm) / self.start_rpm
+    }
+}
+
+impl Display for RpmDrift {
+    /// e.g. `slowed 8% at 45% duty, from 1500 to 1380 rpm over 3d 4h`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hours = self.span.as_secs() / 3600;
+        write!(
+            f,
+            "slowed {:.0}% at {:.0}% duty, from {:.0} to {:.0} rpm over {}d {}h",
+            self.fraction() * 100f32,
+            self.duty,
+            self.start_rpm,
+            self.end_rpm,
+            hours / 24,
+            hours % 24
+        )
+    }
+}
+
+/// Collect the steady points of each device's channels from its summaries.
+pub fn trend_points(
+    summaries: &[TelemetrySummary],
+) -> BTreeMap<(DeviceId, Channel), Vec<TrendPoint>> {
+    let mut points: BTreeMap<(DeviceId, Channel), Vec<TrendPoint>> = BTreeMap::new();
+    for summary in summaries {
+        let Some(device) = summary.device else {
+            continue;
+        };
+        let channels = [
+            (Channel::Pump, summary.pump_duty, summary.pump_rpm),
+            (Channel::Fan, summary.fan_duty, summary.fan_rpm),
+        ];
+        for (channel, duty, rpm) in channels {
+            if let Some(point) = steady_point(summary.end_ms, duty, rpm) {
+                points.entry((device, channel)).or_default().push(point);
+            }
+        }
+    }
+    points
+}
+
+/// Get the point for a summary interval, if the duty was held steady.
+fn steady_point(
+    at_ms: u64,
+    duty: Option<SummaryStats>,
+    rpm: Option<SummaryStats>,
+) -> Option<TrendPoint> {
+    let (duty, rpm) = (duty?, rpm?);
+    if duty.max - duty.min > MAX_DUTY_SPREAD || !duty.mean.is_finite() || !rpm.mean.is_finite() {
+        return None;
+    }
+    Some(TrendPoint {
+        at_ms,
+        duty: duty.mean,
+        rpm: rpm.mean,
+    })
+}
+
+/// Find the duty at which a channel slowed down the most over its points, if
+/// by at least `RPM_DRIFT_THRESHOLD`. Each duty bin's speed is fit with a
+/// straight line over time, so the noise of single intervals averages out.
+/// Bins with fewer than `MIN_TREND_POINTS` or spanning less than
+/// `MIN_RPM_TREND_SPAN` aren't judged.
+pub fn find_rpm_drift(points: &[TrendPoint]) -> Option<RpmDrift> {
+    let mut bins: BTreeMap<i32, Vec<TrendPoint>> = BTreeMap::new();
+    for point in points {
+        let bin = (point.duty / DUTY_BIN_WIDTH).floor() as i32;
+        bins.entry(bin).or_default().push(*point);
+    }
+
+    bins.into_iter()
+        .filter_map(|(bin, points)| {
+            let mut drift = fit_drift(&points)?;
+            drift.duty = (bin as f32 + 0.5f32) * DUTY_BIN_WIDTH;
+            Some(drift)
+        })
+        .filter(|drift| drift.fraction() >= RPM_DRIFT_THRESHOLD)
+        .max_by(|a, b| a.fraction().total_cmp(&b.fraction()))
+}
+
+/// Fit the speed of a single duty bin over time with least squares.
+fn fit_drift(points: &[TrendPoint]) -> Option<RpmDrift> {
+    let first_ms = points.iter().map(|point| point.at_ms).min()?;
+    let last_ms = points.iter().map(|point| point.at_ms).max()?;
+    let span = Duration::from_millis(last_ms - first_ms);
+    if points.len() < MIN_TREND_POINTS || span < MIN_RPM_TREND_SPAN {
+        return None;
+    }
+
+    // NOTE: Time is taken in hours from the first point to keep the sums
+    //       well within f64 precision.
+    let hours = |point: &TrendPoint| (point.at_ms - first_ms) as f64 / 3_600_000f64;
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(hours).sum::<f64>() / n;
+    let mean_rpm = points.iter().map(|point| point.rpm as f64).sum::<f64>() / n;
+    let (covariance, variance) = points.iter().fold((0f64, 0f64), |(cov, var), point| {
+        let dt = hours(point) - mean_t;
+        (cov + dt * (point.rpm as f64 - mean_rpm), var + dt * dt)
+    });
+    let slope = covariance / variance;
+
+    let fitted = |t: f64| (mean_rpm + slope * (t - mean_t)) as f32;
+    let start_rpm = fitted(0f64);
+    if start_rpm <= 0f32 {
+        return None;
+    }
+    Some(RpmDrift {
+        duty: 0f32,
+        start_rpm,
+        end_rpm: fitted(span.as_secs_f64() / 3600f64),
+        span,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: u64 = 3_600_000;
+
+    /// A point every 6 hours for `days`, slowing linearly by `drop` rpm.
+    fn trend(days: u64, duty: f32, start_rpm: f32, drop: f32) -> Vec<TrendPoint> {
+        let count = days * 4;
+        (0..=count)
+            .map(|i| TrendPoint {
+                at_ms: i * 6 * HOUR_MS,
+                duty,
+                rpm: start_rpm - drop * i as f32 / count as f32,
+            })
+            .collect()
+    }
+
+    fn stats(min: f32, max: f32) -> Option<SummaryStats> {
+        Some(SummaryStats {
+            samples: 30,
+            min,
+            mean: (min + max) / 2f32,
+            max,
+            p95: max,
+        })
+    }
+
+    #[test]
+    fn test_finds_drift() {
+        let drift = find_rpm_drift(&trend(4, 43f32, 1500f32, 120f32)).expect("Should drift.");
+        assert_eq!(drift.duty, 45f32);
+        assert!((drift.start_rpm - 1500f32).abs() < 0.5f32);
+        assert!((drift.end_rpm - 1380f32).abs() < 0.5f32);
+        assert_eq!(drift.span, Duration::from_secs(4 * 24 * 3600));
+        assert_eq!(
+            drift.to_string(),
+            "slowed 8% at 45% duty, from 1500 to 1380 rpm over 4d 0h"
+        );
+    }
+
+    #[test]
+    fn test_ignores_small_or_short_drift() {
+        assert_eq!(find_rpm_drift(&trend(4, 43f32, 1500f32, 30f32)), None);
+        assert_eq!(find_rpm_drift(&trend(1, 43f32, 1500f32, 300f32)), None);
+
+        // NOTE: Speeding up is fine.
+        assert_eq!(find_rpm_drift(&trend(4, 43f32, 1500f32, -300f32)), None);
+    }
+
+    #[test]
+    fn test_compares_within_duty_bins() {
+        // NOTE: Alternating between duties, with no change at either.
+        let mut points = trend(4, 30f32, 1000f32, 0f32);
+        points.extend(
+            trend(4, 80f32, 2000f32, 0f32)
+                .into_iter()
+                .map(|point| TrendPoint {
+                    at_ms: point.at_ms + HOUR_MS,
+                    ..point
+                }),
+        );
+        assert_eq!(find_rpm_drift(&points), None);
+    }
+
+    #[test]
+    fn test_trend_points_skip_unsteady_duty() {
+        let summary = |pump_duty, fan_duty| TelemetrySummary {
+            start_ms: 0,
+            end_ms: 60_000,
+            device: Some(DeviceId::new("1324")),
+            temperature: None,
+            pump_rpm: stats(1400f32, 1600f32),
+            fan_rpm: stats(900f32, 1000f32),
+            pump_duty,
+            fan_duty,
+        };
+        let points = trend_points(&[
+            summary(stats(40f32, 41f32), stats(20f32, 60f32)),
+            TelemetrySummary {
+                device: None,
+                ..summary(stats(40f32, 41f32), None)
+            },
+        ]);
+
+        let device = DeviceId::new("1324");
+        assert_eq!(
+            points.get(&(device, Channel::Pump)).map(Vec::as_slice),
+            Some(
+                [TrendPoint {
+                    at_ms: 60_000,
+                    duty: 40.5f32,
+                    rpm: 1500f32,
+                }]
+                .as_slice()
+            )
+        );
+        assert_eq!(points.get(&(device, Channel::Fan)), None);
+    }
+}
@@ -0,0 +1,78 @@
+use common::packet::ReportRawAdcPacket;
+
+use super::{
+    cli_args::{Args, ArgsError, DeviceOptions},
+    injection::DEFAULT_HANDSHAKE_TIMEOUT,
+};
+
+/// The span of the firmware's 12-bit sense readings.
+const ADC_SPAN: f32 = 4096f32;
+
+/// Options for `status` and `rawadc`.
+pub type StatusOptions = DeviceOptions;
+
+/// Parse the arguments to `status` or `rawadc`: optionally `--port <PATH>` and
+/// `--timeout-ms <MS>`.
+pub fn parse_status_args(args: &[String]) -> Result<StatusOptions, ArgsError> {
+    let mut options = StatusOptions::new(DEFAULT_HANDSHAKE_TIMEOUT);
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        if !options.parse_arg(arg, &mut args)? {
+            return Err(ArgsError::UnknownArgument(arg.clone()));
+        }
+    }
+    Ok(options)
+}
+
+/// Describe raw sense readings for `rawadc`, each with the fraction of the
+/// ADC's span it reads, which is what the firmware maps to a speed.
+pub fn describe_raw_adc(report: &ReportRawAdcPacket) -> String {
+    let sense = |reading: Option<u16>| match reading {
+        Some(reading) => format!(
+            "{} ({:.1}% of span)",
+            reading,
+            reading as f32 * 100f32 / ADC_SPAN
+        ),
+        None => "unreadable".to_string(),
+    };
+    let level = |high: bool| if high { "high" } else { "low" };
+    format!(
+        "Pump sense: {}\nFan sense: {}\nValve sense pins: {}, {}",
+        sense(report.pump_sense),
+        sense(report.fan_sense),
+        level(report.valve_sense_1),
+        level(report.valve_sense_2)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_status_args() {
+        assert_eq!(
+            parse_status_args(&[]),
+            Ok(StatusOptions::new(DEFAULT_HANDSHAKE_TIMEOUT))
+        );
+        assert_eq!(
+            parse_status_args(&args(&["--timeout-ms", "500", "--yes"])),
+            Err(ArgsError::UnknownArgument("--yes".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_describe_raw_adc() {
+        let report = ReportRawAdcPacket {
+            pump_sense: Some(2048),
+            fan_sense: None,
+            valve_sense_1: true,
+            valve_sense_2: false,
+        };
+        assert_eq!(
+            describe_raw_adc(&report),
+            "Pump sense: 2048 (50.0% of span)\nFan sense: unreadable\nValve sense pins: high, low"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fmt::Display, time::Duration};
+
+/// The order tasks are shut down in. Stages are cancelled one at a time, and
+/// every task in a stage must exit (or be aborted) before the next stage is
+/// cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShutdownStage {
+    /// Tasks which talk to the hardware and make control decisions. Stopped
+    /// first so nothing new is commanded while the rest shut down.
+    Pipeline,
+
+    /// Tasks which only observe the pipeline, such as the learners and the
+    /// journal. Stopped last so they can record the final data.
+    Observers,
+}
+
+/// How a single task ended during shutdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskExit {
+    /// The task noticed its cancellation and returned within its deadline.
+    Clean,
+
+    /// The task panicked, either before or during shutdown.
+    Panicked,
+
+    /// The task ignored its cancellation past its deadline and was aborted.
+    Aborted { timeout: Duration },
+}
+
+/// How a single named task ended during shutdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskExitRecord {
+    pub name: &'static str,
+    pub stage: ShutdownStage,
+    pub exit: TaskExit,
+}
+
+/// Summary of a shutdown, logged on exit so tasks which don't honor
+/// cancellation are easy to spot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub tasks: Vec<TaskExitRecord>,
+}
+
+impl ShutdownReport {
+    /// Record how a task ended.
+    pub fn record(&mut self, name: &'static str, stage: ShutdownStage, exit: TaskExit) {
+        self.tasks.push(TaskExitRecord { name, stage, exit });
+    }
+
+    /// Check that every task exited cleanly.
+    pub fn is_clean(&self) -> bool {
+        self.tasks.iter().all(|task| task.exit == TaskExit::Clean)
+    }
+
+    /// The tasks which didn't exit cleanly.
+    pub fn unclean(&self) -> impl Iterator<Item = &TaskExitRecord> {
+        self.tasks
+            .iter()
+            .filter(|task| task.exit != TaskExit::Clean)
+    }
+}
+
+impl Display for TaskExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskExit::Clean => write!(f, "exited cleanly"),
+            TaskExit::Panicked => write!(f, "panicked"),
+            TaskExit::Aborted { timeout } => {
+                write!(f, "aborted after ignoring cancellation for {:?}", timeout)
+            }
+        }
+    }
+}
+
+impl Display for TaskExitRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({:?}) {}", self.name, self.stage, self.exit)
+    }
+}
+
+impl Display for ShutdownReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let clean = self
+            .tasks
+            .iter()
+            .filter(|task| task.exit == TaskExit::Clean)
+            .count();
+        write!(f, "{} of {} tasks exited cleanly", clean, self.tasks.len())?;
+        for task in self.unclean() {
+            write!(f, "; {}", task)?;
+        }
+        write!(f, ".")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_order() {
+        assert!(ShutdownStage::Pipeline < ShutdownStage::Observers);
+    }
+
+    #[test]
+    fn test_report_summary() {
+        let mut report = ShutdownReport::default();
+        report.record("supervisor", ShutdownStage::Pipeline, TaskExit::Clean);
+        assert!(report.is_clean());
+        assert_eq!(report.to_string(), "1 of 1 tasks exited cleanly.");
+
+        report.record(
+            "journal",
+            ShutdownStage::Observers,
+            TaskExit::Aborted {
+                timeout: Duration::from_secs(2),
+            },
+        );
+        assert!(!report.is_clean());
+        assert_eq!(report.unclean().count(), 1);
+        assert_eq!(
+            report.to_string(),
+            "1 of 2 tasks exited cleanly; journal (Observers) aborted after ignoring cancellation for 2s."
+        );
+    }
+}
@@ -0,0 +1,135 @@
+#[cfg(feature = "dbus")]
+use std::env;
+
+#[cfg(feature = "dbus")]
+/// Environment variable holding a command which prints a line for each logind
+/// `PrepareForSleep` signal. Set it to an empty string to stop watching for
+/// suspend.
+pub const SLEEP_MONITOR_COMMAND_ENV_VAR: &str = "PRANDTL_SLEEP_MONITOR_COMMAND";
+
+#[cfg(feature = "dbus")]
+/// Default command used to watch for suspend and resume.
+pub const DEFAULT_SLEEP_MONITOR_COMMAND: &str =
+    "gdbus monitor --system --dest org.freedesktop.login1 --object-path /org/freedesktop/login1";
+
+#[cfg(feature = "dbus")]
+/// Command which holds a delay inhibitor lock until it is killed, giving the
+/// control system time to park the pump before the host sleeps.
+pub const SLEEP_INHIBIT_COMMAND: [&str; 7] = [
+    "systemd-inhibit",
+    "--what=sleep",
+    "--mode=delay",
+    "--who=prandtl",
+    "--why=Parking the pump before sleep",
+    "sleep",
+    "infinity",
+];
+
+/// The host is about to sleep or has just woken up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepEvent {
+    Suspending,
+    Resumed,
+}
+
+#[cfg(feature = "dbus")]
+/// A command line split into its program and arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SleepMonitorCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+#[cfg(feature = "dbus")]
+impl SleepMonitorCommand {
+    /// Get the sleep monitor command from the environment, falling back to
+    /// the default if it is unset. Returns `None` if it is set but empty.
+    pub fn from_env() -> Option<Self> {
+        let command = env::var(SLEEP_MONITOR_COMMAND_ENV_VAR).ok();
+        parse_sleep_monitor_command(command.as_deref())
+    }
+}
+
+#[cfg(feature = "dbus")]
+fn parse_sleep_monitor_command(value: Option<&str>) -> Option<SleepMonitorCommand> {
+    let mut parts = value
+        .unwrap_or(DEFAULT_SLEEP_MONITOR_COMMAND)
+        .split_whitespace()
+        .map(str::to_string);
+    Some(SleepMonitorCommand {
+        program: parts.next()?,
+        args: parts.collect(),
+    })
+}
+
+#[cfg(feature = "dbus")]
+/// Parse a line printed by the sleep monitor, such as
+/// `/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (true,)`.
+/// Returns `None` for lines about anything else.
+pub fn parse_sleep_event(line: &str) -> Option<SleepEvent> {
+    let (_, arguments) = line.split_once("PrepareForSleep")?;
+    let arguments = arguments.trim_start();
+    if arguments.starts_with("(true") {
+        Some(SleepEvent::Suspending)
+    } else if arguments.starts_with("(false") {
+        Some(SleepEvent::Resumed)
+    } else {
+        None
+    }
+}
+
+#[cfg(all(test, feature = "dbus"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sleep_event() {
+        assert_eq!(
+            parse_sleep_event(
+                "/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (true,)"
+            ),
+            Some(SleepEvent::Suspending)
+        );
+        assert_eq!(
+            parse_sleep_event(
+                "/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (false,)"
+            ),
+            Some(SleepEvent::Resumed)
+        );
+        assert_eq!(
+            parse_sleep_event(
+                "/org/freedesktop/login1: org.freedesktop.login1.Manager.SessionNew ('3', '/org/freedesktop/login1/session/_33')"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_sleep_event(
+                "Monitoring signals from all objects owned by org.freedesktop.login1"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_sleep_monitor_command() {
+        assert_eq!(
+            parse_sleep_monitor_command(None),
+            Some(SleepMonitorCommand {
+                program: "gdbus".to_string(),
+                args: DEFAULT_SLEEP_MONITOR_COMMAND
+                    .split_whitespace()
+                    .skip(1)
+                    .map(str::to_string)
+                    .collect(),
+            })
+        );
+        assert_eq!(
+            parse_sleep_monitor_command(Some("busctl monitor")),
+            Some(SleepMonitorCommand {
+                program: "busctl".to_string(),
+                args: vec!["monitor".to_string()],
+            })
+        );
+        assert_eq!(parse_sleep_monitor_command(Some(" ")), None);
+    }
+}
@@ -0,0 +1,282 @@
+use std::fmt::Display;
+
+use common::packet::{Capabilities, PROTOCOL_VERSION};
+
+use super::{
+    capabilities::HardwareExpectations,
+    cli_args::{Args, ArgsError, DeviceOptions, PORT_FLAG},
+    injection::DEFAULT_HANDSHAKE_TIMEOUT,
+};
+
+/// Options for `check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckOptions {
+    /// Whether to handshake with the device as well.
+    pub handshake: bool,
+
+    /// The device, and how long to wait for it to reply to the handshake.
+    pub device: DeviceOptions,
+}
+
+/// Parse the arguments to `check`: optionally `--device` to handshake with
+/// the device, `--port <PATH>` and `--timeout-ms <MS>`. Giving a port implies
+/// `--device`.
+pub fn parse_check_args(args: &[String]) -> Result<CheckOptions, ArgsError> {
+    let mut options = CheckOptions {
+        handshake: false,
+        device: DeviceOptions::new(DEFAULT_HANDSHAKE_TIMEOUT),
+    };
+
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--device" => options.handshake = true,
+            _ if options.device.parse_arg(arg, &mut args)? => {
+                options.handshake |= arg == PORT_FLAG;
+            }
+            _ => return Err(ArgsError::UnknownArgument(arg.clone())),
+        }
+    }
+    Ok(options)
+}
+
+/// How a single check turned out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Ok,
+
+    /// Something which works but likely isn't what was meant.
+    Warning,
+
+    /// Something which would stop the control system working as configured.
+    Problem,
+}
+
+impl Display for CheckOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckOutcome::Ok => f.pad("ok"),
+            CheckOutcome::Warning => f.pad("warn"),
+            CheckOutcome::Problem => f.pad("FAIL"),
+        }
+    }
+}
+
+/// The outcome of checking a single part of the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckItem {
+    /// What was checked, e.g. `curves`.
+    pub subject: String,
+    pub outcome: CheckOutcome,
+    pub detail: String,
+}
+
+/// Everything `check` looked at, in the order it was checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    pub items: Vec<CheckItem>,
+}
+
+impl CheckReport {
+    pub fn push(
+        &mut self,
+        subject: impl Into<String>,
+        outcome: CheckOutcome,
+        detail: impl Into<String>,
+    ) {
+        self.items.push(CheckItem {
+            subject: subject.into(),
+            outcome,
+            detail: detail.into(),
+        });
+    }
+
+    /// How many checks turned out `outcome`.
+    pub fn count(&self, outcome: CheckOutcome) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.outcome == outcome)
+            .count()
+    }
+
+    /// Whether nothing is a problem. Warnings still pass.
+    pub fn passed(&self) -> bool {
+        self.count(CheckOutcome::Problem) == 0
+    }
+}
+
+impl Display for CheckReport {
+    /// One line per check, e.g. `[FAIL] control script: Line 2: ...`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for item in &self.items {
+            writeln!(f, "[{:>4}] {}: {}", item.outcome, item.subject, item.detail)?;
+        }
+        write!(
+            f,
+            "{} ok, {} warnings, {} problems.",
+            self.count(CheckOutcome::Ok),
+            self.count(CheckOutcome::Warning),
+            self.count(CheckOutcome::Problem)
+        )
+    }
+}
+
+/// What a device replied to the handshake of `check`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceProbe {
+    /// The protocol version it accepted the connection with, if it replied.
+    pub protocol_version: Option<u16>,
+
+    /// What it reported it can do, if it replied.
+    pub capabilities: Option<Capabilities>,
+}
+
+/// Judge a device's handshake against the host's protocol and `expectations`.
+/// Capability mismatches are problems only with a strict self-check, since
+/// the control system otherwise runs the device anyway.
+pub fn check_device(
+    report: &mut CheckReport,
+    probe: &DeviceProbe,
+    expectations: &HardwareExpectations,
+) {
+    match probe.protocol_version {
+        None => report.push(
+            "handshake",
+            CheckOutcome::Problem,
+            "The device didn't accept the connection.",
+        ),
+        Some(PROTOCOL_VERSION) => report.push(
+            "handshake",
+            CheckOutcome::Ok,
+            format!("Accepted with protocol version {}.", PROTOCOL_VERSION),
+        ),
+        Some(version) => report.push(
+            "handshake",
+            CheckOutcome::Problem,
+            format!(
+                "The device speaks protocol version {} but the host speaks {}.",
+                version, PROTOCOL_VERSION
+            ),
+        ),
+    }
+
+    let Some(capabilities) = probe.capabilities else {
+        report.push(
+            "capabilities",
+            CheckOutcome::Warning,
+            "The device didn't report its capabilities. Is the firmware up to date?",
+        );
+        return;
+    };
+    let mismatches = expectations.check(&capabilities);
+    if mismatches.is_empty() {
+        report.push(
+            "capabilities",
+            CheckOutcome::Ok,
+            format!("{:?}", capabilities),
+        );
+    }
+    let outcome = if expectations.strict {
+        CheckOutcome::Problem
+    } else {
+        CheckOutcome::Warning
+    };
+    for mismatch in mismatches {
+        report.push("capabilities", outcome, mismatch.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_check_args() {
+        assert_eq!(
+            parse_check_args(&[]),
+            Ok(CheckOptions {
+                handshake: false,
+                device: DeviceOptions::new(DEFAULT_HANDSHAKE_TIMEOUT),
+            })
+        );
+        assert_eq!(
+            parse_check_args(&args(&["--port", "/dev/ttyACM0", "--timeout-ms", "500"])),
+            Ok(CheckOptions {
+                handshake: true,
+                device: DeviceOptions {
+                    port: Some("/dev/ttyACM0".to_string()),
+                    timeout: Duration::from_millis(500),
+                },
+            })
+        );
+        assert_eq!(
+            parse_check_args(&args(&["--device"])).map(|options| options.handshake),
+            Ok(true)
+        );
+        assert_eq!(
+            parse_check_args(&args(&["--timeout-ms", "0"])),
+            Err(ArgsError::InvalidValue(
+                "'--timeout-ms'".to_string(),
+                "0".to_string(),
+                "a positive number of milliseconds".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_check_args(&args(&["--yes"])),
+            Err(ArgsError::UnknownArgument("--yes".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_check_device() {
+        let capabilities = Capabilities {
+            fan_channels: 1,
+            thermistors: 0,
+            valve_driver: true,
+            valve_sense: true,
+        };
+        let expectations = HardwareExpectations {
+            second_fan: true,
+            ..HardwareExpectations::default()
+        };
+
+        let mut report = CheckReport::default();
+        check_device(
+            &mut report,
+            &DeviceProbe {
+                protocol_version: Some(PROTOCOL_VERSION),
+                capabilities: Some(capabilities),
+            },
+            &expectations,
+        );
+        assert!(report.passed());
+        assert_eq!(report.count(CheckOutcome::Warning), 1);
+
+        let mut report = CheckReport::default();
+        check_device(
+            &mut report,
+            &DeviceProbe {
+                protocol_version: Some(PROTOCOL_VERSION + 1),
+                capabilities: Some(capabilities),
+            },
+            &HardwareExpectations {
+                strict: true,
+                ..expectations
+            },
+        );
+        assert_eq!(report.count(CheckOutcome::Problem), 2);
+
+        let mut report = CheckReport::default();
+        check_device(&mut report, &DeviceProbe::default(), &expectations);
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "[FAIL] handshake: The device didn't accept the connection.\n\
+             [warn] capabilities: The device didn't report its capabilities. Is the firmware up to date?\n\
+             0 ok, 1 warnings, 1 problems."
+        );
+    }
+}
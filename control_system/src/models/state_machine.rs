@@ -0,0 +1,318 @@
+use std::{fmt::Debug, fmt::Write, time::Duration};
+
+use common::physical::ValveState;
+use serde::{Deserialize, Serialize};
+
+use super::{control_trace::ControlTrace, device_id::DeviceId, valve_model::InferredValve};
+
+/// A change between two states of a `StateMachine`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateTransition {
+    pub from: String,
+    pub event: String,
+    pub to: String,
+}
+
+/// The states of part of the control system and the transitions between
+/// them, along with the state a device is in if known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateMachine {
+    pub name: String,
+    pub states: Vec<String>,
+    pub transitions: Vec<StateTransition>,
+    pub current: Option<String>,
+}
+
+impl StateMachine {
+    /// Build the state machine of a set of modes, in `current` if known.
+    pub fn from_modes<M: Modes>(name: &str, current: Option<M>) -> Self {
+        Self {
+            name: name.to_string(),
+            states: M::ALL.iter().map(|mode| format!("{:?}", mode)).collect(),
+            transitions: M::ALL
+                .iter()
+                .flat_map(|&from| {
+                    from.transitions()
+                        .iter()
+                        .map(move |&(to, event)| StateTransition {
+                            from: format!("{:?}", from),
+                            event: event.to_string(),
+                            to: format!("{:?}", to),
+                        })
+                })
+                .collect(),
+            current: current.map(|mode| format!("{:?}", mode)),
+        }
+    }
+
+    /// Build the state machine of the inferred valve by exploring
+    /// `InferredValve` itself, in `current` if known.
+    pub fn valve(travel: Duration, current: Option<ValveState>) -> Self {
+        let transitions = InferredValve::transitions(travel);
+        let mut states: Vec<String> = Vec::new();
+        for &(from, _, to) in &transitions {
+            for state in [from, to] {
+                let state = format!("{:?}", state);
+                if !states.contains(&state) {
+                    states.push(state);
+                }
+            }
+        }
+        Self {
+            name: "valve".to_string(),
+            states,
+            transitions: transitions
+                .into_iter()
+                .map(|(from, event, to)| StateTransition {
+                    from: format!("{:?}", from),
+                    event: event.to_string(),
+                    to: format!("{:?}", to),
+                })
+                .collect(),
+            current: current.map(|state| format!("{:?}", state)),
+        }
+    }
+}
+
+/// A set of modes the control system switches between, with the transitions
+/// out of each defined alongside them so an exhaustive match catches any mode
+/// added without them.
+pub trait Modes: Copy + Debug + 'static {
+    /// Every mode, in the order they are drawn.
+    const ALL: &'static [Self];
+
+    /// The modes reachable from this one, with what causes each switch.
+    fn transitions(&self) -> &'static [(Self, &'static str)];
+
+    /// The mode a device was in when `trace` was recorded.
+    fn from_trace(trace: &ControlTrace) -> Self;
+}
+
+/// What generates a device's outputs. See `task_core_system`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrategyMode {
+    /// The built-in curves.
+    Curves,
+
+    /// A control script. Falls back to the curves for a step which fails.
+    Script,
+
+    /// Blending from the outputs before a switch to the generated outputs.
+    Blending,
+
+    /// Held by an override request.
+    Overridden,
+
+    /// Parked while the host sleeps. No frames are generated.
+    Parked,
+}
+
+impl Modes for StrategyMode {
+    const ALL: &'static [Self] = &[
+        StrategyMode::Curves,
+        StrategyMode::Script,
+        StrategyMode::Blending,
+        StrategyMode::Overridden,
+        StrategyMode::Parked,
+    ];
+
+    fn transitions(&self) -> &'static [(Self, &'static str)] {
+        match self {
+            StrategyMode::Curves => &[
+                (StrategyMode::Script, "script step succeeds"),
+                (StrategyMode::Overridden, "override applied"),
+                (StrategyMode::Parked, "host suspending"),
+            ],
+            StrategyMode::Script => &[
+                (StrategyMode::Curves, "script step fails"),
+                (StrategyMode::Overridden, "override applied"),
+                (StrategyMode::Parked, "host suspending"),
+            ],
+            StrategyMode::Blending => &[
+                (StrategyMode::Curves, "blend complete"),
+                (StrategyMode::Script, "blend complete"),
+                (StrategyMode::Overridden, "override applied"),
+                (StrategyMode::Parked, "host suspending"),
+            ],
+            StrategyMode::Overridden => &[
+                (StrategyMode::Blending, "override released"),
+                (StrategyMode::Parked, "host suspending"),
+            ],
+            StrategyMode::Parked => &[(StrategyMode::Blending, "host resumed")],
+        }
+    }
+
+    fn from_trace(trace: &ControlTrace) -> Self {
+        if trace.overridden {
+            StrategyMode::Overridden
+        } else if trace.blend.is_some() {
+            StrategyMode::Blending
+        } else if trace.fan.scripted {
+            StrategyMode::Script
+        } else {
+            StrategyMode::Curves
+        }
+    }
+}
+
+/// Which fail-safe is holding a device's outputs, if any. See `ValvePolicy`
+/// and `LowFlowPolicy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailsafeMode {
+    /// Nothing is held.
+    Normal,
+
+    /// The valve command is held while its state is unknown.
+    ValveHeld,
+
+    /// The low flow interlock holds the valve open with the fan at full.
+    LowFlow,
+}
+
+impl Modes for FailsafeMode {
+    const ALL: &'static [Self] = &[
+        FailsafeMode::Normal,
+        FailsafeMode::ValveHeld,
+        FailsafeMode::LowFlow,
+    ];
+
+    fn transitions(&self) -> &'static [(Self, &'static str)] {
+        match self {
+            FailsafeMode::Normal => &[
+                (FailsafeMode::ValveHeld, "valve unknown too long"),
+                (FailsafeMode::LowFlow, "flow below threshold"),
+            ],
+            FailsafeMode::ValveHeld => &[
+                (FailsafeMode::Normal, "valve state known"),
+                (FailsafeMode::LowFlow, "flow below threshold"),
+            ],
+            FailsafeMode::LowFlow => &[
+                (FailsafeMode::Normal, "flow recovered"),
+                (FailsafeMode::ValveHeld, "flow recovered, valve unknown"),
+            ],
+        }
+    }
+
+    fn from_trace(trace: &ControlTrace) -> Self {
+        // NOTE: The interlock is applied last, so it wins over a held valve.
+        if trace.low_flow {
+            FailsafeMode::LowFlow
+        } else if trace.valve.held {
+            FailsafeMode::ValveHeld
+        } else {
+            FailsafeMode::Normal
+        }
+    }
+}
+
+/// Get the strategy, valve and fail-safe state machines, in the states
+/// `trace` was recorded in if given. The valve's state is the one last sent
+/// to it.
+pub fn state_machines(trace: Option<&ControlTrace>, valve_travel: Duration) -> Vec<StateMachine> {
+    vec![
+        StateMachine::from_modes("strategy", trace.map(StrategyMode::from_trace)),
+        StateMachine::valve(valve_travel, trace.map(|trace| trace.frame.valve_state)),
+        StateMachine::from_modes("failsafe", trace.map(FailsafeMode::from_trace)),
+    ]
+}
+
+/// Render the state machines of each device, or of no device in particular
+/// if `None`, as a single Graphviz DOT graph. Current states are filled in.
+pub fn to_dot(machines: &[(Option<DeviceId>, Vec<StateMachine>)]) -> String {
+    let mut dot = String::from("digraph prandtl {\n    compound=true;\n");
+    let clusters = machines
+        .iter()
+        .flat_map(|(device, machines)| machines.iter().map(move |machine| (device, machine)));
+    for (cluster, (device, machine)) in clusters.enumerate() {
+        let label = match device {
+            Some(device) => format!("{}: {}", device.as_str(), machine.name),
+            None => machine.name.clone(),
+        };
+        // NOTE: Writing to a String can't fail.
+        let _ = writeln!(dot, "    subgraph cluster_{} {{", cluster);
+        let _ = writeln!(dot, "        label=\"{}\";", escape(&label));
+        for state in &machine.states {
+            let style = if machine.current.as_ref() == Some(state) {
+                ", style=filled, fillcolor=lightblue"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                dot,
+                "        \"{}_{}\" [label=\"{}\"{}];",
+                cluster,
+                escape(state),
+                escape(state),
+                style
+            );
+        }
+        for transition in &machine.transitions {
+            let _ = writeln!(
+                dot,
+                "        \"{}_{}\" -> \"{}_{}\" [label=\"{}\"];",
+                cluster,
+                escape(&transition.from),
+                cluster,
+                escape(&transition.to),
+                escape(&transition.event)
+            );
+        }
+        dot.push_str("    }\n");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Escape a DOT quoted string.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions_stay_within_modes() {
+        let machines = state_machines(None, Duration::from_secs(5));
+        for machine in &machines {
+            assert!(machine.current.is_none());
+            for transition in &machine.transitions {
+                assert!(
+                    machine.states.contains(&transition.from),
+                    "{:?}",
+                    transition
+                );
+                assert!(machine.states.contains(&transition.to), "{:?}", transition);
+            }
+        }
+        // NOTE: Every mode can be left, so none is a dead end.
+        for &mode in StrategyMode::ALL {
+            assert!(!mode.transitions().is_empty(), "{:?}", mode);
+        }
+        for &mode in FailsafeMode::ALL {
+            assert!(!mode.transitions().is_empty(), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let machine = StateMachine {
+            name: "valve".to_string(),
+            states: vec!["Open".to_string(), "Closing".to_string()],
+            transitions: vec![StateTransition {
+                from: "Open".to_string(),
+                event: "close".to_string(),
+                to: "Closing".to_string(),
+            }],
+            current: Some("Open".to_string()),
+        };
+        let dot = to_dot(&[(Some(DeviceId::new("13\"24")), vec![machine])]);
+        assert!(dot.starts_with("digraph prandtl {"));
+        assert!(dot.contains("label=\"13\\\"24: valve\";"));
+        assert!(dot.contains("\"0_Open\" [label=\"Open\", style=filled, fillcolor=lightblue];"));
+        assert!(dot.contains("\"0_Closing\" [label=\"Closing\"];"));
+        assert!(dot.contains("\"0_Open\" -> \"0_Closing\" [label=\"close\"];"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
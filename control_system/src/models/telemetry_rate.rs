@@ -0,0 +1,77 @@
+use common::packet::{MAX_TELEMETRY_RATE_HZ, MIN_TELEMETRY_RATE_HZ};
+
+use super::{
+    cli_args::{parse_in_range, ArgsError},
+    device_id::DeviceId,
+};
+
+/// What `telemetry-rate` should do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelemetryRateCommand {
+    /// How often the device should report sensor data, in Hz.
+    pub rate_hz: u8,
+
+    /// The device to set it on.
+    pub device: DeviceId,
+}
+
+/// Parse the arguments to `telemetry-rate`: a rate in Hz followed by a
+/// device's serial number.
+pub fn parse_telemetry_rate_args(args: &[String]) -> Result<TelemetryRateCommand, ArgsError> {
+    let (rate_hz, device) = match args {
+        [rate_hz, device] => (rate_hz, DeviceId::new(device)),
+        [_, _, unknown, ..] => return Err(ArgsError::UnknownArgument(unknown.clone())),
+        _ => {
+            return Err(ArgsError::Missing(
+                "a rate in Hz and a device's serial number".to_string(),
+            ))
+        }
+    };
+    let range = MIN_TELEMETRY_RATE_HZ..=MAX_TELEMETRY_RATE_HZ;
+    let rate_hz = parse_in_range("the rate in Hz", rate_hz, range)?;
+    Ok(TelemetryRateCommand { rate_hz, device })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::cli_args::args;
+
+    #[test]
+    fn test_parse_telemetry_rate_args() {
+        assert_eq!(
+            parse_telemetry_rate_args(&args(&["10", "1324"])),
+            Ok(TelemetryRateCommand {
+                rate_hz: 10,
+                device: DeviceId::new("1324"),
+            })
+        );
+        assert_eq!(
+            parse_telemetry_rate_args(&args(&["10"])),
+            Err(ArgsError::Missing(
+                "a rate in Hz and a device's serial number".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_telemetry_rate_args(&args(&["0", "1324"])),
+            Err(ArgsError::InvalidValue(
+                "the rate in Hz".to_string(),
+                "0".to_string(),
+                format!("{} to {}", MIN_TELEMETRY_RATE_HZ, MAX_TELEMETRY_RATE_HZ)
+            ))
+        );
+        assert_eq!(
+            parse_telemetry_rate_args(&args(&["fast", "1324"])),
+            Err(ArgsError::InvalidValue(
+                "the rate in Hz".to_string(),
+                "fast".to_string(),
+                format!("{} to {}", MIN_TELEMETRY_RATE_HZ, MAX_TELEMETRY_RATE_HZ)
+            ))
+        );
+        assert_eq!(
+            parse_telemetry_rate_args(&args(&["10", "1324", "now"])),
+            Err(ArgsError::UnknownArgument("now".to_string()))
+        );
+    }
+}
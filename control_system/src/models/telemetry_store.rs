@@ -0,0 +1,480 @@
+use std::{
+    env,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+
+use super::{
+    alarm::AlarmOccurrences,
+    journal::{
+        append_entries, load_alarm_history, prune_entries_before, read_entries_since,
+        record_alarm_history, JournalEntry, JournalError, JOURNAL_PATH,
+    },
+};
+#[cfg(feature = "sqlite")]
+use super::{
+    alarm::{AlarmKind, AlarmSighting},
+    device_id::DeviceId,
+    journal::JournalRecord,
+};
+
+/// Environment variable used to choose where the journal is kept.
+pub const JOURNAL_BACKEND_ENV_VAR: &str = "PRANDTL_JOURNAL_BACKEND";
+
+#[cfg(feature = "sqlite")]
+/// Default location of the journal when kept in SQLite.
+pub const JOURNAL_DATABASE_PATH: &str = "prandtl_journal.sqlite3";
+
+/// Somewhere journal entries can be kept, queried and pruned.
+pub trait TelemetryStore: Send {
+    /// Append entries to the store. Alarms among them are also counted in
+    /// the alarm history.
+    fn append(&mut self, entries: &[JournalEntry]) -> Result<(), JournalError>;
+
+    /// Get how often every alarm ever journaled has occurred, in the order
+    /// they first occurred. Unlike entries, these are never pruned.
+    fn alarm_history(&self) -> Result<Vec<AlarmOccurrences>, JournalError>;
+
+    /// Get every entry from `from_ms` up to, but not including, `until_ms`,
+    /// in the order they were appended.
+    fn query(&self, from_ms: u64, until_ms: u64) -> Result<Vec<JournalEntry>, JournalError>;
+
+    /// Drop every entry older than `since_ms`, except telemetry summaries,
+    /// which are only dropped once older than `summaries_since_ms`.
+    fn prune(&mut self, since_ms: u64, summaries_since_ms: u64) -> Result<(), JournalError>;
+
+    /// Get how much space the store takes on disk, in bytes.
+    fn size_bytes(&self) -> Result<u64, JournalError>;
+
+    /// Give space freed by pruning back to the filesystem, for stores which
+    /// don't as they prune.
+    fn compact(&mut self) -> Result<(), JournalError> {
+        Ok(())
+    }
+}
+
+/// A store shared between the journaling and journal maintenance tasks.
+pub type SharedTelemetryStore = Arc<Mutex<Box<dyn TelemetryStore>>>;
+
+/// Lock `store`, recovering it if a task panicked while holding it. Every
+/// store operation is a single write or read of the backing file or
+/// database, so a panic can't leave the store itself half updated.
+pub fn lock_store(store: &SharedTelemetryStore) -> MutexGuard<'_, Box<dyn TelemetryStore>> {
+    store.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Get the size of the file at `path` in bytes. A missing file is empty.
+fn file_size(path: &Path) -> Result<u64, JournalError> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(JournalError::Io(e)),
+    }
+}
+
+/// Keeps the journal in a file with one JSON document per line. Appends are
+/// sequential, but pruning rewrites the whole file. The alarm history is
+/// kept in a JSON file next to it, e.g. `prandtl_journal.alarm_history.json`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonlStore {
+    path: PathBuf,
+    history_path: PathBuf,
+}
+
+impl JsonlStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            history_path: path.with_extension("alarm_history.json"),
+            path,
+        }
+    }
+}
+
+impl TelemetryStore for JsonlStore {
+    fn append(&mut self, entries: &[JournalEntry]) -> Result<(), JournalError> {
+        append_entries(&self.path, entries)?;
+        record_alarm_history(&self.history_path, entries)
+    }
+
+    fn alarm_history(&self) -> Result<Vec<AlarmOccurrences>, JournalError> {
+        Ok(load_alarm_history(&self.history_path)?
+            .occurrences()
+            .to_vec())
+    }
+
+    fn query(&self, from_ms: u64, until_ms: u64) -> Result<Vec<JournalEntry>, JournalError> {
+        let mut entries = read_entries_since(&self.path, from_ms)?;
+        entries.retain(|entry| entry.timestamp_ms < until_ms);
+        Ok(entries)
+    }
+
+    fn prune(&mut self, since_ms: u64, summaries_since_ms: u64) -> Result<(), JournalError> {
+        prune_entries_before(&self.path, since_ms, summaries_since_ms)
+    }
+
+    fn size_bytes(&self) -> Result<u64, JournalError> {
+        Ok(file_size(&self.path)? + file_size(&self.history_path)?)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+/// Keeps the journal in a SQLite database indexed by time. Pruning deletes
+/// old rows in place instead of rewriting everything that is kept.
+pub struct SqliteStore {
+    path: PathBuf,
+    connection: Connection,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// Open the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self, JournalError> {
+        let connection = Connection::open(path).map_err(JournalError::Database)?;
+        // NOTE: Write ahead logging batches page writes, which is kinder to
+        //       SD cards than rewriting pages in place.
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .map_err(JournalError::Database)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS journal (
+                    timestamp_ms INTEGER NOT NULL,
+                    summary INTEGER NOT NULL,
+                    record TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS journal_timestamp ON journal (timestamp_ms);
+                CREATE TABLE IF NOT EXISTS alarm_history (
+                    device TEXT NOT NULL,
+                    kind TEXT NOT NULL,
+                    first_seen_ms INTEGER NOT NULL,
+                    last_seen_ms INTEGER NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (device, kind)
+                );",
+            )
+            .map_err(JournalError::Database)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            connection,
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl TelemetryStore for SqliteStore {
+    fn append(&mut self, entries: &[JournalEntry]) -> Result<(), JournalError> {
+        let transaction = self
+            .connection
+            .transaction()
+            .map_err(JournalError::Database)?;
+        {
+            let mut insert = transaction
+                .prepare_cached(
+                    "INSERT INTO journal (timestamp_ms, summary, record) VALUES (?1, ?2, ?3)",
+                )
+                .map_err(JournalError::Database)?;
+            for entry in entries {
+                let record = serde_json::to_string(&entry.record).map_err(JournalError::Encode)?;
+                let summary = matches!(entry.record, JournalRecord::TelemetrySummary(_));
+                insert
+                    .execute(params![to_sql_ms(entry.timestamp_ms), summary, record])
+                    .map_err(JournalError::Database)?;
+            }
+
+            let mut occurred = transaction
+                .prepare_cached(
+                    "INSERT INTO alarm_history (device, kind, first_seen_ms, last_seen_ms, count)
+                    VALUES (?1, ?2, ?3, ?3, 1)
+                    ON CONFLICT (device, kind) DO UPDATE SET
+                        first_seen_ms = MIN(first_seen_ms, excluded.first_seen_ms),
+                        last_seen_ms = MAX(last_seen_ms, excluded.last_seen_ms),
+                        count = count + 1",
+                )
+                .map_err(JournalError::Database)?;
+            let mut cleared = transaction
+                .prepare_cached(
+                    "UPDATE alarm_history SET last_seen_ms = MAX(last_seen_ms, ?3)
+                    WHERE device = ?1 AND kind = ?2",
+                )
+                .map_err(JournalError::Database)?;
+            for entry in entries {
+                let JournalRecord::Alarm(alarm) = entry.record else {
+                    continue;
+                };
+                let kind = serde_json::to_string(&alarm.kind).map_err(JournalError::Encode)?;
+                let (statement, at_ms) = match alarm.sighting() {
+                    Some(AlarmSighting::Occurred { at_ms }) => (&mut occurred, at_ms),
+                    Some(AlarmSighting::Cleared { at_ms }) => (&mut cleared, at_ms),
+                    None => continue,
+                };
+                statement
+                    .execute(params![alarm.device.as_str(), kind, to_sql_ms(at_ms)])
+                    .map_err(JournalError::Database)?;
+            }
+        }
+        transaction.commit().map_err(JournalError::Database)
+    }
+
+    /// Rows which fail to parse, such as alarms from a newer version, are
+    /// skipped.
+    fn alarm_history(&self) -> Result<Vec<AlarmOccurrences>, JournalError> {
+        let mut select = self
+            .connection
+            .prepare_cached(
+                "SELECT device, kind, first_seen_ms, last_seen_ms, count FROM alarm_history
+                ORDER BY first_seen_ms, rowid",
+            )
+            .map_err(JournalError::Database)?;
+        let rows = select
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            })
+            .map_err(JournalError::Database)?;
+
+        let mut history = vec![];
+        for row in rows {
+            let (device, kind, first_seen_ms, last_seen_ms, count) =
+                row.map_err(JournalError::Database)?;
+            if let Ok(kind) = serde_json::from_str::<AlarmKind>(&kind) {
+                history.push(AlarmOccurrences {
+                    device: DeviceId::new(&device),
+                    kind,
+                    first_seen_ms: first_seen_ms.max(0) as u64,
+                    last_seen_ms: last_seen_ms.max(0) as u64,
+                    count: count.max(0) as u64,
+                });
+            }
+        }
+        Ok(history)
+    }
+
+    /// Rows which fail to parse, such as records from a newer version, are
+    /// skipped like unparseable lines in a JSONL journal.
+    fn query(&self, from_ms: u64, until_ms: u64) -> Result<Vec<JournalEntry>, JournalError> {
+        let mut select = self
+            .connection
+            .prepare_cached(
+                "SELECT timestamp_ms, record FROM journal
+                WHERE timestamp_ms >= ?1 AND timestamp_ms < ?2 ORDER BY rowid",
+            )
+            .map_err(JournalError::Database)?;
+        let rows = select
+            .query_map(params![to_sql_ms(from_ms), to_sql_ms(until_ms)], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(JournalError::Database)?;
+
+        let mut entries = vec![];
+        for row in rows {
+            let (timestamp_ms, record) = row.map_err(JournalError::Database)?;
+            if let Ok(record) = serde_json::from_str(&record) {
+                entries.push(JournalEntry {
+                    timestamp_ms: timestamp_ms.max(0) as u64,
+                    record,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn prune(&mut self, since_ms: u64, summaries_since_ms: u64) -> Result<(), JournalError> {
+        self.connection
+            .execute(
+                "DELETE FROM journal WHERE
+                (summary = 0 AND timestamp_ms < ?1) OR (summary = 1 AND timestamp_ms < ?2)",
+                params![to_sql_ms(since_ms), to_sql_ms(summaries_since_ms)],
+            )
+            .map(|_| ())
+            .map_err(JournalError::Database)
+    }
+
+    /// Includes the write ahead log, which holds recent appends until they
+    /// are checkpointed into the database.
+    fn size_bytes(&self) -> Result<u64, JournalError> {
+        let mut wal = self.path.clone().into_os_string();
+        wal.push("-wal");
+        Ok(file_size(&self.path)? + file_size(&PathBuf::from(wal))?)
+    }
+
+    /// Deleted rows leave free pages which are reused by later appends but
+    /// not returned to the filesystem until the database is vacuumed.
+    fn compact(&mut self) -> Result<(), JournalError> {
+        self.connection
+            .execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);")
+            .map_err(JournalError::Database)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+/// Convert a timestamp into SQLite's signed integers, saturating far in the
+/// future.
+fn to_sql_ms(timestamp_ms: u64) -> i64 {
+    i64::try_from(timestamp_ms).unwrap_or(i64::MAX)
+}
+
+/// Which `TelemetryStore` the journal is kept in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalBackend {
+    /// A JSONL file at `JOURNAL_PATH`.
+    Jsonl,
+
+    #[cfg(feature = "sqlite")]
+    /// A SQLite database at `JOURNAL_DATABASE_PATH`.
+    Sqlite,
+}
+
+impl Display for JournalBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalBackend::Jsonl => write!(f, "JSONL file at {}", JOURNAL_PATH),
+            #[cfg(feature = "sqlite")]
+            JournalBackend::Sqlite => write!(f, "SQLite database at {}", JOURNAL_DATABASE_PATH),
+        }
+    }
+}
+
+impl JournalBackend {
+    /// Get the journal backend from the environment, falling back to JSONL if
+    /// it is unset or unknown.
+    pub fn from_env() -> Self {
+        let backend = env::var(JOURNAL_BACKEND_ENV_VAR).ok();
+        parse_journal_backend(backend.as_deref()).unwrap_or(JournalBackend::Jsonl)
+    }
+
+    /// Open the store at this backend's default location.
+    pub fn open(self) -> Result<Box<dyn TelemetryStore>, JournalError> {
+        match self {
+            JournalBackend::Jsonl => Ok(Box::new(JsonlStore::new(JOURNAL_PATH))),
+            #[cfg(feature = "sqlite")]
+            JournalBackend::Sqlite => Ok(Box::new(SqliteStore::open(Path::new(
+                JOURNAL_DATABASE_PATH,
+            ))?)),
+        }
+    }
+}
+
+/// Parse a journal backend name. `sqlite` is only known when built with the
+/// `sqlite` feature.
+fn parse_journal_backend(value: Option<&str>) -> Option<JournalBackend> {
+    match value?.trim().to_lowercase().as_str() {
+        "jsonl" => Some(JournalBackend::Jsonl),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Some(JournalBackend::Sqlite),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        alarm::{AlarmKind, AlarmLog},
+        device_id::DeviceId,
+        journal::{control_frame, summary, JournalRecord},
+    };
+
+    /// Exercise a store the same way the journaling task and `diag bundle` do.
+    fn check_store(store: &mut dyn TelemetryStore) {
+        assert!(store
+            .query(0, u64::MAX)
+            .expect("Failed to query.")
+            .is_empty());
+        let empty_size = store.size_bytes().expect("Failed to get size.");
+
+        store
+            .append(&[control_frame(1000), summary(1500), control_frame(2000)])
+            .expect("Failed to append entries.");
+        store
+            .append(&[control_frame(3000)])
+            .expect("Failed to append entries.");
+        assert_eq!(
+            store.query(1500, 3000).expect("Failed to query."),
+            vec![summary(1500), control_frame(2000)]
+        );
+
+        assert!(store.size_bytes().expect("Failed to get size.") > empty_size);
+
+        store.prune(3000, 1000).expect("Failed to prune.");
+        store.compact().expect("Failed to compact.");
+        assert_eq!(
+            store.query(0, u64::MAX).expect("Failed to query."),
+            vec![summary(1500), control_frame(3000)]
+        );
+
+        let device = DeviceId::new("1324");
+        let mut log = AlarmLog::default();
+        let alarms = [
+            log.raise(device, AlarmKind::LowFlow, 4000),
+            log.clear(device, AlarmKind::LowFlow, 5000),
+            log.raise(device, AlarmKind::LowFlow, 6000),
+        ]
+        .map(|alarm| JournalEntry {
+            timestamp_ms: 6000,
+            record: JournalRecord::Alarm(alarm.expect("Alarm didn't change.")),
+        });
+        store.append(&alarms).expect("Failed to append entries.");
+        // NOTE: The history outlives the entries it was counted from.
+        store.prune(u64::MAX, u64::MAX).expect("Failed to prune.");
+        assert_eq!(
+            store.alarm_history().expect("Failed to get alarm history."),
+            vec![AlarmOccurrences {
+                device,
+                kind: AlarmKind::LowFlow,
+                first_seen_ms: 4000,
+                last_seen_ms: 6000,
+                count: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_jsonl_store() {
+        let path =
+            std::env::temp_dir().join(format!("prandtl_store_test_{}.jsonl", std::process::id()));
+        let store = JsonlStore::new(&path);
+        let _ = std::fs::remove_file(&store.path);
+        let _ = std::fs::remove_file(&store.history_path);
+        check_store(&mut store.clone());
+        let _ = std::fs::remove_file(&store.path);
+        let _ = std::fs::remove_file(&store.history_path);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_store() {
+        let path =
+            std::env::temp_dir().join(format!("prandtl_store_test_{}.sqlite3", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        check_store(&mut SqliteStore::open(&path).expect("Failed to open store."));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_parse_journal_backend() {
+        assert_eq!(
+            parse_journal_backend(Some("jsonl")),
+            Some(JournalBackend::Jsonl)
+        );
+        #[cfg(feature = "sqlite")]
+        assert_eq!(
+            parse_journal_backend(Some(" SQLite ")),
+            Some(JournalBackend::Sqlite)
+        );
+        assert_eq!(parse_journal_backend(Some("postgres")), None);
+        assert_eq!(parse_journal_backend(None), None);
+    }
+}
@@ -0,0 +1,306 @@
+#[cfg(feature = "recording")]
+use std::{collections::BTreeMap, env, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::device_id::DeviceId;
+#[cfg(feature = "recording")]
+use super::{capabilities::parse_flag, journal::JournalRecord};
+
+#[cfg(feature = "recording")]
+/// Environment variable used to override how often telemetry is summarized.
+pub const SUMMARY_INTERVAL_ENV_VAR: &str = "PRANDTL_SUMMARY_INTERVAL_MS";
+
+#[cfg(feature = "recording")]
+/// Environment variable used to stop journaling raw telemetry, leaving only
+/// the summaries.
+pub const RAW_TELEMETRY_ENV_VAR: &str = "PRANDTL_JOURNAL_RAW_TELEMETRY";
+
+#[cfg(feature = "recording")]
+/// Default interval telemetry is summarized over.
+pub const DEFAULT_SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[cfg(feature = "recording")]
+/// How long summaries are kept in the journal. Much longer than raw
+/// telemetry so a soak run can be reviewed after the fact.
+pub const SUMMARY_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Statistics of a single quantity over a summary interval.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SummaryStats {
+    pub samples: usize,
+    pub min: f32,
+    pub mean: f32,
+    pub max: f32,
+    pub p95: f32,
+}
+
+#[cfg(feature = "recording")]
+impl SummaryStats {
+    /// Summarize `samples`, ignoring any which aren't finite. Returns `None`
+    /// if there are none left.
+    pub fn from_samples(samples: &[f32]) -> Option<Self> {
+        let mut samples = samples
+            .iter()
+            .copied()
+            .filter(|sample| sample.is_finite())
+            .collect::<Vec<_>>();
+        samples.sort_by(f32::total_cmp);
+
+        Some(Self {
+            samples: samples.len(),
+            min: *samples.first()?,
+            mean: samples.iter().sum::<f32>() / samples.len() as f32,
+            max: *samples.last()?,
+            p95: percentile(&samples, 95),
+        })
+    }
+}
+
+#[cfg(feature = "recording")]
+/// Get the nearest rank percentile of sorted, non-empty samples.
+fn percentile(sorted: &[f32], percent: usize) -> f32 {
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// Telemetry summarized over an interval. The host summary, without a
+/// `device`, only has the control temperature. Device summaries have the
+/// speeds reported by the device and the duties commanded of it.
+/// Quantities without samples in the interval are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TelemetrySummary {
+    /// Milliseconds since the unix epoch when the interval started.
+    pub start_ms: u64,
+
+    /// Milliseconds since the unix epoch when the interval ended.
+    pub end_ms: u64,
+
+    pub device: Option<DeviceId>,
+
+    /// Control temperature in degC.
+    pub temperature: Option<SummaryStats>,
+    pub pump_rpm: Option<SummaryStats>,
+    pub fan_rpm: Option<SummaryStats>,
+
+    /// Commanded pump duty in percent.
+    pub pump_duty: Option<SummaryStats>,
+
+    /// Commanded fan duty in percent.
+    pub fan_duty: Option<SummaryStats>,
+}
+
+#[cfg(feature = "recording")]
+/// Samples collected from a single device over the current interval.
+#[derive(Debug, Default)]
+struct DeviceSamples {
+    pump_rpm: Vec<f32>,
+    fan_rpm: Vec<f32>,
+    pump_duty: Vec<f32>,
+    fan_duty: Vec<f32>,
+}
+
+#[cfg(feature = "recording")]
+/// Collects telemetry over an interval to be summarized.
+#[derive(Debug)]
+pub struct TelemetrySummarizer {
+    start_ms: u64,
+    temperature: Vec<f32>,
+    devices: BTreeMap<DeviceId, DeviceSamples>,
+}
+
+#[cfg(feature = "recording")]
+impl TelemetrySummarizer {
+    /// Start collecting an interval at `start_ms`.
+    pub fn new(start_ms: u64) -> Self {
+        Self {
+            start_ms,
+            temperature: vec![],
+            devices: BTreeMap::new(),
+        }
+    }
+
+    /// Collect the telemetry in `record`. Other records are ignored.
+    pub fn record(&mut self, record: &JournalRecord) {
+        match record {
+            JournalRecord::HostSensors(data) => self.temperature.push(data.cpu_temperature.value),
+            JournalRecord::ClientSensors(data) => {
+                let samples = self.devices.entry(data.device).or_default();
+                samples.pump_rpm.push(data.pump_speed.into());
+                samples.fan_rpm.push(data.fan_speed.into());
+            }
+            JournalRecord::ControlFrame(event) => {
+                let samples = self.devices.entry(event.device).or_default();
+                samples.pump_duty.push(event.pump_activation.into());
+                samples.fan_duty.push(event.fan_activation.into());
+            }
+            _ => {}
+        }
+    }
+
+    /// Summarize the interval ending at `end_ms` and start the next one.
+    /// Nothing is returned if no telemetry was collected.
+    pub fn finish(&mut self, end_ms: u64) -> Vec<TelemetrySummary> {
+        let start_ms = self.start_ms;
+        let finished = std::mem::replace(self, Self::new(end_ms));
+
+        let host =
+            SummaryStats::from_samples(&finished.temperature).map(|temperature| TelemetrySummary {
+                start_ms,
+                end_ms,
+                device: None,
+                temperature: Some(temperature),
+                pump_rpm: None,
+                fan_rpm: None,
+                pump_duty: None,
+                fan_duty: None,
+            });
+        let devices = finished
+            .devices
+            .into_iter()
+            .map(|(device, samples)| TelemetrySummary {
+                start_ms,
+                end_ms,
+                device: Some(device),
+                temperature: None,
+                pump_rpm: SummaryStats::from_samples(&samples.pump_rpm),
+                fan_rpm: SummaryStats::from_samples(&samples.fan_rpm),
+                pump_duty: SummaryStats::from_samples(&samples.pump_duty),
+                fan_duty: SummaryStats::from_samples(&samples.fan_duty),
+            });
+        host.into_iter().chain(devices).collect()
+    }
+}
+
+#[cfg(feature = "recording")]
+/// How telemetry is summarized and journaled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SummaryConfig {
+    /// How long each summary covers.
+    pub interval: Duration,
+
+    /// Whether raw telemetry is journaled alongside the summaries.
+    pub raw_telemetry: bool,
+}
+
+#[cfg(feature = "recording")]
+impl SummaryConfig {
+    /// Get the summary config from the environment, falling back to the
+    /// defaults for anything unset or invalid.
+    pub fn from_env() -> Self {
+        let interval = env::var(SUMMARY_INTERVAL_ENV_VAR).ok();
+        let raw_telemetry = env::var(RAW_TELEMETRY_ENV_VAR).ok();
+        Self {
+            interval: parse_summary_interval(interval.as_deref())
+                .unwrap_or(DEFAULT_SUMMARY_INTERVAL),
+            raw_telemetry: parse_flag(raw_telemetry.as_deref()).unwrap_or(true),
+        }
+    }
+}
+
+#[cfg(feature = "recording")]
+/// Parse a summary interval in milliseconds. Returns `None` if it is missing,
+/// not a number or under a second.
+fn parse_summary_interval(value: Option<&str>) -> Option<Duration> {
+    value?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&millis| millis >= 1000)
+        .map(Duration::from_millis)
+}
+
+#[cfg(all(test, feature = "recording"))]
+mod tests {
+    use common::physical::{Rpm, ValveState};
+
+    use super::*;
+    use crate::models::{
+        client_sensor_data::ClientSensorData, control_event::ControlEvent,
+        host_sensor_data::HostSensorData, temperature::Temperature,
+    };
+
+    #[test]
+    fn test_summary_stats() {
+        let samples = (1..=20).map(|sample| sample as f32).collect::<Vec<_>>();
+        assert_eq!(
+            SummaryStats::from_samples(&samples),
+            Some(SummaryStats {
+                samples: 20,
+                min: 1f32,
+                mean: 10.5f32,
+                max: 20f32,
+                p95: 19f32,
+            })
+        );
+        assert_eq!(
+            SummaryStats::from_samples(&[3f32, f32::NAN]),
+            Some(SummaryStats {
+                samples: 1,
+                min: 3f32,
+                mean: 3f32,
+                max: 3f32,
+                p95: 3f32,
+            })
+        );
+        assert_eq!(SummaryStats::from_samples(&[]), None);
+    }
+
+    #[test]
+    fn test_summarizer() {
+        let device = DeviceId::new("1324");
+        let mut summarizer = TelemetrySummarizer::new(1000);
+        for temperature in [40f32, 50f32] {
+            summarizer.record(&JournalRecord::HostSensors(HostSensorData {
+                cpu_temperature: Temperature::try_from(temperature)
+                    .expect("Failed to get temperature."),
+                raw_cpu_temperature: None,
+            }));
+        }
+        summarizer.record(&JournalRecord::ClientSensors(ClientSensorData::new(
+            device,
+            Rpm::new(2000f32, 1000f32).expect("Failed to get rpm."),
+            Rpm::new(2000f32, 500f32).expect("Failed to get rpm."),
+            ValveState::Open,
+            None,
+            None,
+            None,
+        )));
+        summarizer.record(&JournalRecord::ControlFrame(
+            ControlEvent::new(device, 25f32, 50f32, ValveState::Open)
+                .expect("Failed to get ControlEvent."),
+        ));
+
+        let summaries = summarizer.finish(61000);
+        assert_eq!(summaries.len(), 2);
+
+        let host = summaries[0];
+        assert_eq!(
+            (host.start_ms, host.end_ms, host.device),
+            (1000, 61000, None)
+        );
+        assert_eq!(host.temperature.map(|stats| stats.mean), Some(45f32));
+        assert_eq!(host.pump_rpm, None);
+
+        let client = summaries[1];
+        assert_eq!(client.device, Some(device));
+        assert_eq!(client.temperature, None);
+        assert_eq!(client.pump_rpm.map(|stats| stats.max), Some(1000f32));
+        assert_eq!(client.fan_rpm.map(|stats| stats.max), Some(500f32));
+        assert_eq!(client.fan_duty.map(|stats| stats.max), Some(25f32));
+        assert_eq!(client.pump_duty.map(|stats| stats.max), Some(50f32));
+
+        assert!(summarizer.finish(121000).is_empty());
+    }
+
+    #[test]
+    fn test_parse_summary_interval() {
+        assert_eq!(
+            parse_summary_interval(Some("30000")),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_summary_interval(Some("999")), None);
+        assert_eq!(parse_summary_interval(Some("minute")), None);
+        assert_eq!(parse_summary_interval(None), None);
+    }
+}
@@ -1,8 +1,13 @@
-use std::fmt::Display;
+use std::{env, fmt::Display};
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+/// Environment variable used to choose the units temperatures are displayed in.
+pub const TEMPERATURE_UNIT_ENV_VAR: &str = "PRANDTL_TEMPERATURE_UNIT";
+
+/// Temperatures are always Celsius internally.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Temperature {
     pub value: f32,
 }
@@ -11,6 +16,100 @@ pub struct Temperature {
 pub enum TemperatureError {
     #[error("Temperature too high")]
     TooHigh,
+
+    #[error("Temperature is not a finite number")]
+    NotFinite,
+}
+
+/// Units temperatures are displayed in. This only affects presentation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    /// Get the display unit from the environment, falling back to Celsius if
+    /// it is unset or invalid.
+    pub fn from_env() -> Self {
+        let unit = env::var(TEMPERATURE_UNIT_ENV_VAR).ok();
+        parse_temperature_unit(unit.as_deref()).unwrap_or_default()
+    }
+
+    /// Convert `value`, given in this unit, to degC.
+    pub fn to_celsius(&self, value: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => value,
+            TemperatureUnit::Fahrenheit => (value - 32f32) * 5f32 / 9f32,
+        }
+    }
+
+    /// Get the label used when displaying a temperature in this unit.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "degC",
+            TemperatureUnit::Fahrenheit => "degF",
+        }
+    }
+}
+
+/// Parse a display unit. Accepts `c`/`celsius` and `f`/`fahrenheit` in any
+/// case. Returns `None` if it is missing or unrecognized.
+fn parse_temperature_unit(value: Option<&str>) -> Option<TemperatureUnit> {
+    match value?.trim().to_lowercase().as_str() {
+        "c" | "celsius" => Some(TemperatureUnit::Celsius),
+        "f" | "fahrenheit" => Some(TemperatureUnit::Fahrenheit),
+        _ => None,
+    }
+}
+
+impl Temperature {
+    /// Get the temperature in degF.
+    pub fn fahrenheit(&self) -> f32 {
+        self.value * 9f32 / 5f32 + 32f32
+    }
+
+    /// Get the temperature converted to `unit`.
+    pub fn in_unit(&self, unit: TemperatureUnit) -> f32 {
+        match unit {
+            TemperatureUnit::Celsius => self.value,
+            TemperatureUnit::Fahrenheit => self.fahrenheit(),
+        }
+    }
+
+    /// Get a displayable form of the temperature in `unit`, such as
+    /// `(113.0 degF)`.
+    pub fn display(&self, unit: TemperatureUnit) -> TemperatureDisplay {
+        TemperatureDisplay {
+            temperature: *self,
+            unit,
+        }
+    }
+}
+
+/// A temperature formatted in a chosen unit. See `Temperature::display`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureDisplay {
+    temperature: Temperature,
+    unit: TemperatureUnit,
+}
+
+impl Display for TemperatureDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "({:.1} {})",
+            self.temperature.in_unit(self.unit),
+            self.unit.symbol()
+        )
+    }
+}
+
+impl Display for TemperatureUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
 }
 
 impl Into<f32> for Temperature {
@@ -23,6 +122,9 @@ impl TryFrom<f32> for Temperature {
     type Error = TemperatureError;
 
     fn try_from(value: f32) -> Result<Self, Self::Error> {
+        if !value.is_finite() {
+            return Err(TemperatureError::NotFinite);
+        }
         if value > 100f32 {
             return Err(TemperatureError::TooHigh);
         }
@@ -35,3 +137,58 @@ impl Display for Temperature {
         write!(f, "({} degC)", self.value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from() {
+        let temperature = Temperature::try_from(45f32).expect("Failed to get Temperature.");
+        assert_eq!(temperature.value, 45f32);
+
+        assert!(matches!(
+            Temperature::try_from(150f32),
+            Err(TemperatureError::TooHigh)
+        ));
+        assert!(matches!(
+            Temperature::try_from(f32::NAN),
+            Err(TemperatureError::NotFinite)
+        ));
+        assert!(matches!(
+            Temperature::try_from(f32::NEG_INFINITY),
+            Err(TemperatureError::NotFinite)
+        ));
+    }
+
+    #[test]
+    fn test_display_units() {
+        let temperature = Temperature::try_from(45f32).expect("Failed to get Temperature.");
+        assert_eq!(temperature.fahrenheit(), 113f32);
+        assert_eq!(temperature.in_unit(TemperatureUnit::Celsius), 45f32);
+        assert_eq!(TemperatureUnit::Fahrenheit.to_celsius(113f32), 45f32);
+        assert_eq!(TemperatureUnit::Celsius.to_celsius(45f32), 45f32);
+        assert_eq!(
+            temperature.display(TemperatureUnit::Celsius).to_string(),
+            "(45.0 degC)"
+        );
+        assert_eq!(
+            temperature.display(TemperatureUnit::Fahrenheit).to_string(),
+            "(113.0 degF)"
+        );
+    }
+
+    #[test]
+    fn test_parse_temperature_unit() {
+        assert_eq!(
+            parse_temperature_unit(Some("F")),
+            Some(TemperatureUnit::Fahrenheit)
+        );
+        assert_eq!(
+            parse_temperature_unit(Some(" celsius ")),
+            Some(TemperatureUnit::Celsius)
+        );
+        assert_eq!(parse_temperature_unit(Some("kelvin")), None);
+        assert_eq!(parse_temperature_unit(None), None);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{env, fmt::Display};
+
+use super::temperature::Temperature;
+
+/// Environment variable used to choose how host temperature sources are
+/// combined into the single control input.
+pub const TEMPERATURE_AGGREGATION_ENV_VAR: &str = "PRANDTL_TEMPERATURE_AGGREGATION";
+
+/// Name of the source reading the cpu temperature, through systemstat or
+/// `PRANDTL_SENSORS_COMMAND`.
+pub const CPU_SOURCE: &str = "cpu";
+
+/// Name of the source reading `PRANDTL_TEMPERATURE_COMMAND`.
+pub const COMMAND_SOURCE: &str = "command";
+
+/// How the readings of every host temperature source are combined into the
+/// single temperature the control loop runs on. Sources which fail to read
+/// are left out, so every strategy falls back to whatever is still working.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemperatureAggregation {
+    /// Use the hottest reading.
+    Max,
+
+    /// Use the weighted mean of the named sources. Weights are renormalized
+    /// over the sources which read successfully. Sources without a weight
+    /// are ignored.
+    Weighted(Vec<(String, f32)>),
+
+    /// Use the first named source which read successfully.
+    Priority(Vec<String>),
+}
+
+impl Default for TemperatureAggregation {
+    /// Prefer the temperature command when one is configured, falling back to
+    /// the cpu temperature.
+    fn default() -> Self {
+        TemperatureAggregation::Priority(vec![COMMAND_SOURCE.to_string(), CPU_SOURCE.to_string()])
+    }
+}
+
+impl TemperatureAggregation {
+    /// Get the aggregation from the environment, falling back to the default
+    /// if it is unset or invalid.
+    pub fn from_env() -> Self {
+        let aggregation = env::var(TEMPERATURE_AGGREGATION_ENV_VAR).ok();
+        parse_aggregation(aggregation.as_deref()).unwrap_or_default()
+    }
+
+    /// Combine the readings of each named source. Returns `None` if no source
+    /// the strategy uses has a reading.
+    pub fn aggregate(&self, readings: &[(&str, Temperature)]) -> Option<Temperature> {
+        let reading = |name: &str| {
+            readings
+                .iter()
+                .find(|(source, _)| *source == name)
+                .map(|(_, temperature)| temperature.value)
+        };
+
+        let value = match self {
+            TemperatureAggregation::Max => readings
+                .iter()
+                .map(|(_, temperature)| temperature.value)
+                .reduce(f32::max)?,
+            TemperatureAggregation::Weighted(weights) => {
+                let (sum, total_weight) = weights
+                    .iter()
+                    .filter_map(|(name, weight)| Some((reading(name)?, *weight)))
+                    .fold((0f32, 0f32), |(sum, total_weight), (value, weight)| {
+                        (sum + value * weight, total_weight + weight)
+                    });
+                if total_weight <= 0f32 {
+                    return None;
+                }
+                sum / total_weight
+            }
+            TemperatureAggregation::Priority(order) => {
+                order.iter().find_map(|name| reading(name))?
+            }
+        };
+        Some(Temperature { value })
+    }
+}
+
+/// Parse an aggregation strategy: `max`, `weighted:cpu=0.7,command=0.3` or
+/// `priority:command,cpu`. Returns `None` if it is missing or invalid, which
+/// includes weights which are negative, not numbers or all zero.
+fn parse_aggregation(value: Option<&str>) -> Option<TemperatureAggregation> {
+    let value = value?.trim();
+    let (strategy, sources) = value.split_once(':').unwrap_or((value, ""));
+    let sources = sources
+        .split(',')
+        .map(str::trim)
+        .filter(|source| !source.is_empty());
+
+    match strategy.trim().to_lowercase().as_str() {
+        "max" => Some(TemperatureAggregation::Max),
+        "weighted" => {
+            let weights = sources
+                .map(|source| {
+                    let (name, weight) = source.split_once('=')?;
+                    let weight: f32 = weight.trim().parse().ok()?;
+                    (weight.is_finite() && weight >= 0f32)
+                        .then(|| (name.trim().to_string(), weight))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            weights
+                .iter()
+                .any(|(_, weight)| *weight > 0f32)
+                .then_some(TemperatureAggregation::Weighted(weights))
+        }
+        "priority" => {
+            let order: Vec<String> = sources.map(str::to_string).collect();
+            (!order.is_empty()).then_some(TemperatureAggregation::Priority(order))
+        }
+        _ => None,
+    }
+}
+
+impl Display for TemperatureAggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemperatureAggregation::Max => write!(f, "max"),
+            TemperatureAggregation::Weighted(weights) => {
+                let weights: Vec<String> = weights
+                    .iter()
+                    .map(|(name, weight)| format!("{}={}", name, weight))
+                    .collect();
+                write!(f, "weighted:{}", weights.join(","))
+            }
+            TemperatureAggregation::Priority(order) => write!(f, "priority:{}", order.join(",")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temperature(value: f32) -> Temperature {
+        Temperature::try_from(value).expect("Failed to get Temperature.")
+    }
+
+    #[test]
+    fn test_parse_aggregation() {
+        assert_eq!(
+            parse_aggregation(Some("MAX")),
+            Some(TemperatureAggregation::Max)
+        );
+        assert_eq!(
+            parse_aggregation(Some("weighted: cpu=0.7, probe=0.3")),
+            Some(TemperatureAggregation::Weighted(vec![
+                ("cpu".to_string(), 0.7f32),
+                ("probe".to_string(), 0.3f32),
+            ]))
+        );
+        assert_eq!(
+            parse_aggregation(Some("priority:command,cpu")),
+            Some(TemperatureAggregation::default())
+        );
+
+        assert_eq!(parse_aggregation(Some("weighted:cpu=-1")), None);
+        assert_eq!(parse_aggregation(Some("weighted:cpu=0")), None);
+        assert_eq!(parse_aggregation(Some("weighted:cpu")), None);
+        assert_eq!(parse_aggregation(Some("priority:")), None);
+        assert_eq!(parse_aggregation(Some("average")), None);
+        assert_eq!(parse_aggregation(None), None);
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for aggregation in [
+            TemperatureAggregation::Max,
+            TemperatureAggregation::Weighted(vec![("cpu".to_string(), 0.5f32)]),
+            TemperatureAggregation::default(),
+        ] {
+            assert_eq!(
+                parse_aggregation(Some(&aggregation.to_string())),
+                Some(aggregation)
+            );
+        }
+    }
+
+    #[test]
+    fn test_max() {
+        let readings = [("cpu", temperature(55f32)), ("command", temperature(70f32))];
+        assert_eq!(
+            TemperatureAggregation::Max.aggregate(&readings),
+            Some(temperature(70f32))
+        );
+        assert_eq!(TemperatureAggregation::Max.aggregate(&[]), None);
+    }
+
+    #[test]
+    fn test_weighted() {
+        let aggregation = TemperatureAggregation::Weighted(vec![
+            ("cpu".to_string(), 3f32),
+            ("command".to_string(), 1f32),
+        ]);
+        let readings = [
+            ("cpu", temperature(60f32)),
+            ("command", temperature(40f32)),
+            ("unweighted", temperature(100f32)),
+        ];
+        assert_eq!(aggregation.aggregate(&readings), Some(temperature(55f32)));
+
+        // NOTE: A missing source's weight is shared among the rest.
+        assert_eq!(
+            aggregation.aggregate(&[("command", temperature(40f32))]),
+            Some(temperature(40f32))
+        );
+        assert_eq!(
+            aggregation.aggregate(&[("unweighted", temperature(100f32))]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_priority_falls_back() {
+        let aggregation = TemperatureAggregation::default();
+        assert_eq!(
+            aggregation.aggregate(&[("cpu", temperature(50f32)), ("command", temperature(65f32))]),
+            Some(temperature(65f32))
+        );
+        assert_eq!(
+            aggregation.aggregate(&[("cpu", temperature(50f32))]),
+            Some(temperature(50f32))
+        );
+        assert_eq!(
+            aggregation.aggregate(&[("probe", temperature(50f32))]),
+            None
+        );
+    }
+}
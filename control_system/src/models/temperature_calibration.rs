@@ -0,0 +1,163 @@
+use std::{env, fmt::Display};
+
+use super::temperature::{Temperature, TemperatureError};
+
+/// Environment variable holding the calibration of each host temperature
+/// source, for boards which report offset temperatures such as AMD's Tctl.
+pub const TEMPERATURE_CALIBRATION_ENV_VAR: &str = "PRANDTL_TEMPERATURE_CALIBRATION";
+
+/// A linear correction applied to a source's raw reading: the calibrated
+/// temperature is `raw * scale + offset`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureCalibration {
+    pub offset: f32,
+    pub scale: f32,
+}
+
+impl Default for TemperatureCalibration {
+    /// Leave readings as they are.
+    fn default() -> Self {
+        Self {
+            offset: 0f32,
+            scale: 1f32,
+        }
+    }
+}
+
+impl TemperatureCalibration {
+    /// Whether this leaves readings as they are.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Calibrate a raw reading. Will return a `TemperatureError` if the
+    /// calibrated temperature is invalid.
+    pub fn apply(&self, raw: Temperature) -> Result<Temperature, TemperatureError> {
+        Temperature::try_from(raw.value * self.scale + self.offset)
+    }
+}
+
+/// The calibration of each named host temperature source. Sources without
+/// one are left as they are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemperatureCalibrations(Vec<(String, TemperatureCalibration)>);
+
+impl TemperatureCalibrations {
+    /// Get the calibrations from the environment, falling back to none if it
+    /// is unset or invalid.
+    pub fn from_env() -> Self {
+        let calibrations = env::var(TEMPERATURE_CALIBRATION_ENV_VAR).ok();
+        parse_calibrations(calibrations.as_deref()).unwrap_or_default()
+    }
+
+    /// Get the calibration of the source called `name`.
+    pub fn get(&self, name: &str) -> TemperatureCalibration {
+        self.0
+            .iter()
+            .find(|(source, _)| source == name)
+            .map(|(_, calibration)| *calibration)
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Parse calibrations such as `cpu=-27,command=-1.5:1.02`: each source's
+/// offset in degC, optionally followed by a scale. Returns `None` if it is
+/// missing or invalid, which includes values which aren't finite numbers and
+/// scales which aren't positive.
+fn parse_calibrations(value: Option<&str>) -> Option<TemperatureCalibrations> {
+    let calibrations = value?
+        .split(',')
+        .map(str::trim)
+        .filter(|source| !source.is_empty())
+        .map(|source| {
+            let (name, calibration) = source.split_once('=')?;
+            let (offset, scale) = calibration.split_once(':').unwrap_or((calibration, "1"));
+            let offset: f32 = offset.trim().parse().ok()?;
+            let scale: f32 = scale.trim().parse().ok()?;
+            (offset.is_finite() && scale.is_finite() && scale > 0f32).then(|| {
+                (
+                    name.trim().to_string(),
+                    TemperatureCalibration { offset, scale },
+                )
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (!calibrations.is_empty()).then_some(TemperatureCalibrations(calibrations))
+}
+
+impl Display for TemperatureCalibrations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let calibrations: Vec<String> = self
+            .0
+            .iter()
+            .map(|(name, calibration)| {
+                format!("{}={}:{}", name, calibration.offset, calibration.scale)
+            })
+            .collect();
+        write!(f, "{}", calibrations.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temperature(value: f32) -> Temperature {
+        Temperature::try_from(value).expect("Failed to get Temperature.")
+    }
+
+    #[test]
+    fn test_parse_calibrations() {
+        let calibrations = parse_calibrations(Some("cpu=-27, command = -1.5:1.02"))
+            .expect("Failed to parse calibrations.");
+        assert_eq!(
+            calibrations.get("cpu"),
+            TemperatureCalibration {
+                offset: -27f32,
+                scale: 1f32,
+            }
+        );
+        assert_eq!(
+            calibrations.get("command"),
+            TemperatureCalibration {
+                offset: -1.5f32,
+                scale: 1.02f32,
+            }
+        );
+        assert!(calibrations.get("probe").is_identity());
+        assert_eq!(
+            parse_calibrations(Some(&calibrations.to_string())),
+            Some(calibrations)
+        );
+
+        assert_eq!(parse_calibrations(Some("cpu=-27:0")), None);
+        assert_eq!(parse_calibrations(Some("cpu=warm")), None);
+        assert_eq!(parse_calibrations(Some("cpu")), None);
+        assert_eq!(parse_calibrations(Some("")), None);
+        assert_eq!(parse_calibrations(None), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let calibration = TemperatureCalibration {
+            offset: -27f32,
+            scale: 1f32,
+        };
+        assert_eq!(
+            calibration
+                .apply(temperature(90f32))
+                .expect("Failed to calibrate."),
+            temperature(63f32)
+        );
+        assert!(TemperatureCalibration {
+            offset: 0f32,
+            scale: 2f32,
+        }
+        .apply(temperature(60f32))
+        .is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::{env, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+/// Environment variable used to choose the backends the cpu temperature is
+/// read from, in order of preference.
+pub const CPU_TEMPERATURE_BACKENDS_ENV_VAR: &str = "PRANDTL_CPU_TEMPERATURE_BACKENDS";
+
+/// Consecutive failed reads after which the active backend is failed over.
+const FAIL_OVER_AFTER: usize = 3;
+
+/// Consecutive successful reads after which a preferred backend is failed
+/// back to.
+const FAIL_BACK_AFTER: usize = 5;
+
+/// A way of reading the cpu temperature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TemperatureBackend {
+    /// The cpu chips in `/sys/class/hwmon`.
+    Hwmon,
+
+    /// The `systemstat` crate, which reads the first thermal zone on Linux.
+    Systemstat,
+
+    /// The command in `PRANDTL_SENSORS_COMMAND`, printing `sensors -j` JSON.
+    Sensors,
+
+    /// The command in `PRANDTL_TEMPERATURE_COMMAND`.
+    Command,
+}
+
+impl Display for TemperatureBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemperatureBackend::Hwmon => write!(f, "hwmon"),
+            TemperatureBackend::Systemstat => write!(f, "systemstat"),
+            TemperatureBackend::Sensors => write!(f, "sensors"),
+            TemperatureBackend::Command => write!(f, "command"),
+        }
+    }
+}
+
+impl TemperatureBackend {
+    /// Get the backends to read the cpu temperature from, in order of
+    /// preference, from the environment. Falls back to the sensors command if
+    /// `sensors_command` says it is configured, then systemstat and then hwmon
+    /// if unset or invalid.
+    pub fn chain_from_env(sensors_command: bool) -> Vec<Self> {
+        let backends = env::var(CPU_TEMPERATURE_BACKENDS_ENV_VAR).ok();
+        parse_backends(backends.as_deref()).unwrap_or_else(|| default_chain(sensors_command))
+    }
+}
+
+/// A change of the backend the cpu temperature is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailoverEvent {
+    /// The active backend failed persistently, so a less preferred one took
+    /// over.
+    FailedOver {
+        from: TemperatureBackend,
+        to: TemperatureBackend,
+    },
+
+    /// A more preferred backend recovered, so it took back over.
+    FailedBack {
+        from: TemperatureBackend,
+        to: TemperatureBackend,
+    },
+}
+
+impl Display for FailoverEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailoverEvent::FailedOver { from, to } => {
+                write!(f, "failed over from {} to {}", from, to)
+            }
+            FailoverEvent::FailedBack { from, to } => {
+                write!(f, "failed back from {} to {}", from, to)
+            }
+        }
+    }
+}
+
+/// Tracks which of an ordered chain of backends is read from. A backend
+/// which fails a read is bridged over by the next one that works, but is
+/// only failed over once it fails `FAIL_OVER_AFTER` reads in a row. More
+/// preferred backends keep being tried and are failed back to once they
+/// succeed `FAIL_BACK_AFTER` reads in a row, so a flaky backend doesn't make
+/// the reading flap between backends.
+#[derive(Debug, Clone)]
+pub struct TemperatureFailover {
+    backends: Vec<TemperatureBackend>,
+
+    /// Index of the backend currently read from.
+    active: usize,
+
+    /// Consecutive failed reads of the active backend.
+    failures: usize,
+
+    /// Consecutive successful reads of each backend more preferred than the
+    /// active one.
+    recoveries: Vec<usize>,
+}
+
+impl TemperatureFailover {
+    /// Start reading from the first of `backends`.
+    pub fn new(backends: Vec<TemperatureBackend>) -> Self {
+        Self {
+            recoveries: vec![0; backends.len()],
+            backends,
+            active: 0,
+            failures: 0,
+        }
+    }
+
+    /// Get the backend currently read from, if there are any.
+    pub fn active(&self) -> Option<TemperatureBackend> {
+        self.backends.get(self.active).copied()
+    }
+
+    /// Take a reading, calling `read` with the index of each backend to try.
+    /// `read` returns `None` if the backend failed. Returns the reading, if
+    /// any backend worked, and the change of backend, if there was one.
+    pub fn poll<T>(
+        &mut self,
+        mut read: impl FnMut(usize) -> Option<T>,
+    ) -> (Option<T>, Option<FailoverEvent>) {
+        // NOTE: The first preferred backend to work bridges a failed read.
+        let mut fallback = None;
+        for index in 0..self.active {
+            let Some(value) = read(index) else {
+                self.recoveries[index] = 0;
+                continue;
+            };
+            self.recoveries[index] += 1;
+            if self.recoveries[index] >= FAIL_BACK_AFTER {
+                let event = self.switch_to(index);
+                return (Some(value), Some(event));
+            }
+            if fallback.is_none() {
+                fallback = Some((index, value));
+            }
+        }
+
+        if self.active < self.backends.len() {
+            if let Some(value) = read(self.active) {
+                self.failures = 0;
+                return (Some(value), None);
+            }
+            self.failures += 1;
+        }
+
+        if fallback.is_none() {
+            fallback = (self.active + 1..self.backends.len())
+                .find_map(|index| read(index).map(|value| (index, value)));
+        }
+        let Some((index, value)) = fallback else {
+            return (None, None);
+        };
+        let event = (self.failures >= FAIL_OVER_AFTER).then(|| self.switch_to(index));
+        (Some(value), event)
+    }
+
+    /// Make the backend at `index` the active one.
+    fn switch_to(&mut self, index: usize) -> FailoverEvent {
+        let from = self.backends[self.active];
+        let to = self.backends[index];
+        let event = match index < self.active {
+            true => FailoverEvent::FailedBack { from, to },
+            false => FailoverEvent::FailedOver { from, to },
+        };
+        self.active = index;
+        self.failures = 0;
+        self.recoveries.fill(0);
+        event
+    }
+}
+
+/// Get the default chain. The sensors command is only used when configured,
+/// since it is meant for containers without sysfs.
+fn default_chain(sensors_command: bool) -> Vec<TemperatureBackend> {
+    let mut chain = vec![];
+    if sensors_command {
+        chain.push(TemperatureBackend::Sensors);
+    }
+    chain.extend([TemperatureBackend::Systemstat, TemperatureBackend::Hwmon]);
+    chain
+}
+
+/// Parse a comma separated chain of backends, such as
+/// `hwmon,systemstat,command`. Returns `None` if it is missing, empty, names
+/// an unknown backend or names one twice.
+fn parse_backends(value: Option<&str>) -> Option<Vec<TemperatureBackend>> {
+    let mut chain = vec![];
+    for name in value?
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let backend = match name.to_lowercase().as_str() {
+            "hwmon" => TemperatureBackend::Hwmon,
+            "systemstat" => TemperatureBackend::Systemstat,
+            "sensors" => TemperatureBackend::Sensors,
+            "command" => TemperatureBackend::Command,
+            _ => return None,
+        };
+        if chain.contains(&backend) {
+            return None;
+        }
+        chain.push(backend);
+    }
+    (!chain.is_empty()).then_some(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HWMON: TemperatureBackend = TemperatureBackend::Hwmon;
+    const SYSTEMSTAT: TemperatureBackend = TemperatureBackend::Systemstat;
+    const COMMAND: TemperatureBackend = TemperatureBackend::Command;
+
+    /// Poll with each backend reading its index, or failing if it is down.
+    fn poll(
+        failover: &mut TemperatureFailover,
+        down: &[usize],
+    ) -> (Option<usize>, Option<FailoverEvent>) {
+        failover.poll(|index| (!down.contains(&index)).then_some(index))
+    }
+
+    #[test]
+    fn test_parse_backends() {
+        assert_eq!(
+            parse_backends(Some("hwmon, Systemstat,command")),
+            Some(vec![HWMON, SYSTEMSTAT, COMMAND])
+        );
+        assert_eq!(parse_backends(Some("hwmon,hwmon")), None);
+        assert_eq!(parse_backends(Some("hwmon,acpi")), None);
+        assert_eq!(parse_backends(Some(" , ")), None);
+        assert_eq!(parse_backends(None), None);
+    }
+
+    #[test]
+    fn test_default_chain() {
+        assert_eq!(default_chain(false), vec![SYSTEMSTAT, HWMON]);
+        assert_eq!(
+            default_chain(true),
+            vec![TemperatureBackend::Sensors, SYSTEMSTAT, HWMON]
+        );
+    }
+
+    #[test]
+    fn test_flaky_backend_bridged_without_failover() {
+        let mut failover = TemperatureFailover::new(vec![HWMON, SYSTEMSTAT]);
+        assert_eq!(poll(&mut failover, &[]), (Some(0), None));
+        assert_eq!(poll(&mut failover, &[0]), (Some(1), None));
+        assert_eq!(poll(&mut failover, &[0]), (Some(1), None));
+        assert_eq!(poll(&mut failover, &[]), (Some(0), None));
+        assert_eq!(failover.active(), Some(HWMON));
+    }
+
+    #[test]
+    fn test_fails_over_and_back() {
+        let mut failover = TemperatureFailover::new(vec![HWMON, SYSTEMSTAT, COMMAND]);
+        poll(&mut failover, &[0]);
+        poll(&mut failover, &[0]);
+        assert_eq!(
+            poll(&mut failover, &[0]),
+            (
+                Some(1),
+                Some(FailoverEvent::FailedOver {
+                    from: HWMON,
+                    to: SYSTEMSTAT
+                })
+            )
+        );
+
+        // NOTE: Recovering needs a streak, so a single good read isn't enough.
+        assert_eq!(poll(&mut failover, &[]), (Some(1), None));
+        assert_eq!(poll(&mut failover, &[0]), (Some(1), None));
+        for _ in 0..FAIL_BACK_AFTER - 1 {
+            assert_eq!(poll(&mut failover, &[]), (Some(1), None));
+        }
+        assert_eq!(
+            poll(&mut failover, &[]),
+            (
+                Some(0),
+                Some(FailoverEvent::FailedBack {
+                    from: SYSTEMSTAT,
+                    to: HWMON
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_skips_down_backends_when_failing_over() {
+        let mut failover = TemperatureFailover::new(vec![HWMON, SYSTEMSTAT, COMMAND]);
+        for _ in 0..FAIL_OVER_AFTER - 1 {
+            poll(&mut failover, &[0, 1]);
+        }
+        assert_eq!(
+            poll(&mut failover, &[0, 1]),
+            (
+                Some(2),
+                Some(FailoverEvent::FailedOver {
+                    from: HWMON,
+                    to: COMMAND
+                })
+            )
+        );
+        assert_eq!(poll(&mut failover, &[0, 1, 2]), (None, None));
+    }
+}
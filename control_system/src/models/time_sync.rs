@@ -0,0 +1,187 @@
+use std::{collections::HashMap, time::Duration};
+
+use common::packet::{TimeSyncPacket, TimeSyncReplyPacket};
+
+use super::device_id::DeviceId;
+
+/// How often each device's clock is synced with the host's. The embedded
+/// hardware counts time in core loop periods, which drift by however long
+/// each loop's work takes, so it is synced often.
+pub const TIME_SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How far a device's clock may drift between syncs, in milliseconds, before
+/// it is warned about.
+pub const MAX_CLOCK_DRIFT_MS: u64 = 250;
+
+/// The outcome of a single sync, once the device replied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSyncOutcome {
+    /// How long the sync took to be replied to.
+    pub round_trip: Duration,
+
+    /// How far ahead, or behind if negative, the device's clock was of the
+    /// host's when the sync arrived, in milliseconds. `None` if the device
+    /// hadn't been synced since reset.
+    pub drift_ms: Option<i64>,
+}
+
+/// The sync state of a single device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct DeviceClock {
+    /// The `host_time_ms` of the sync awaiting a reply, if any.
+    pending: Option<u64>,
+
+    /// The one way latency measured by the latest sync, in milliseconds.
+    latency_ms: u16,
+
+    /// Whether the device has replied to a sync since it was last seen
+    /// reporting unsynced.
+    synced: bool,
+}
+
+/// Tracks the syncs sent to every device, measuring the latency to allow
+/// for in the next and how far each clock drifted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimeSyncTracker {
+    clocks: HashMap<DeviceId, DeviceClock>,
+}
+
+impl TimeSyncTracker {
+    /// Every device seen so far.
+    pub fn devices(&self) -> impl Iterator<Item = DeviceId> + '_ {
+        self.clocks.keys().copied()
+    }
+
+    /// Note a sensor report from `device`, and whether it was timestamped.
+    /// Returns `true` if the device should be synced right away: it's newly
+    /// seen, or reported unsynced after being synced, such as after a reset.
+    pub fn observe_report(&mut self, device: DeviceId, timestamped: bool) -> bool {
+        let Some(clock) = self.clocks.get_mut(&device) else {
+            self.clocks.insert(device, DeviceClock::default());
+            return true;
+        };
+        if timestamped || !clock.synced {
+            return false;
+        }
+        clock.synced = false;
+        true
+    }
+
+    /// Create the sync to send `device` at `now_ms`, allowing for the latency
+    /// measured by its previous sync.
+    pub fn request(&mut self, device: DeviceId, now_ms: u64) -> TimeSyncPacket {
+        let clock = self.clocks.entry(device).or_default();
+        clock.pending = Some(now_ms);
+        TimeSyncPacket {
+            host_time_ms: now_ms,
+            latency_ms: clock.latency_ms,
+        }
+    }
+
+    /// Observe a reply from `device`, received at `now_ms`. Returns `None` if
+    /// it isn't the reply to the sync awaited, such as one which arrived
+    /// after a newer sync was sent.
+    pub fn observe_reply(
+        &mut self,
+        device: DeviceId,
+        reply: TimeSyncReplyPacket,
+        now_ms: u64,
+    ) -> Option<TimeSyncOutcome> {
+        let clock = self.clocks.get_mut(&device)?;
+        if clock.pending != Some(reply.host_time_ms) {
+            return None;
+        }
+        clock.pending = None;
+        clock.synced = true;
+
+        let round_trip_ms = now_ms.saturating_sub(reply.host_time_ms);
+        clock.latency_ms = (round_trip_ms / 2).min(u16::MAX as u64) as u16;
+        // NOTE: Assumes the sync took half the round trip to arrive.
+        let arrived_at_ms = reply.host_time_ms + round_trip_ms / 2;
+        Some(TimeSyncOutcome {
+            round_trip: Duration::from_millis(round_trip_ms),
+            drift_ms: reply
+                .device_time_ms
+                .map(|device_time_ms| device_time_ms as i64 - arrived_at_ms as i64),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE: &str = "1324";
+
+    fn reply(host_time_ms: u64, device_time_ms: Option<u64>) -> TimeSyncReplyPacket {
+        TimeSyncReplyPacket {
+            host_time_ms,
+            device_time_ms,
+        }
+    }
+
+    #[test]
+    fn test_syncs_measure_latency_and_drift() {
+        let device = DeviceId::new(DEVICE);
+        let mut tracker = TimeSyncTracker::default();
+        assert!(tracker.observe_report(device, false));
+        assert!(!tracker.observe_report(device, false));
+
+        let sync = tracker.request(device, 1_000);
+        assert_eq!(sync.latency_ms, 0);
+        assert_eq!(
+            tracker.observe_reply(device, reply(1_000, None), 1_008),
+            Some(TimeSyncOutcome {
+                round_trip: Duration::from_millis(8),
+                drift_ms: None,
+            })
+        );
+
+        let sync = tracker.request(device, 11_000);
+        assert_eq!(sync.latency_ms, 4);
+        assert_eq!(
+            tracker
+                .observe_reply(device, reply(11_000, Some(10_954)), 11_010)
+                .map(|outcome| outcome.drift_ms),
+            Some(Some(-51))
+        );
+    }
+
+    #[test]
+    fn test_stale_replies_ignored() {
+        let device = DeviceId::new(DEVICE);
+        let mut tracker = TimeSyncTracker::default();
+        tracker.request(device, 1_000);
+        tracker.request(device, 2_000);
+        assert_eq!(
+            tracker.observe_reply(device, reply(1_000, None), 2_001),
+            None
+        );
+        assert!(tracker
+            .observe_reply(device, reply(2_000, None), 2_002)
+            .is_some());
+        assert_eq!(
+            tracker.observe_reply(device, reply(2_000, None), 2_003),
+            None
+        );
+        assert_eq!(
+            tracker.observe_reply(DeviceId::new("5678"), reply(2_000, None), 2_003),
+            None
+        );
+    }
+
+    #[test]
+    fn test_resync_after_reset() {
+        let device = DeviceId::new(DEVICE);
+        let mut tracker = TimeSyncTracker::default();
+        tracker.observe_report(device, false);
+        tracker.request(device, 1_000);
+        tracker.observe_reply(device, reply(1_000, None), 1_002);
+        assert!(!tracker.observe_report(device, true));
+
+        // NOTE: Only resynced once, not on every unsynced report after.
+        assert!(tracker.observe_report(device, false));
+        assert!(!tracker.observe_report(device, false));
+        assert_eq!(tracker.devices().collect::<Vec<_>>(), [device]);
+    }
+}
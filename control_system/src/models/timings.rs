@@ -0,0 +1,133 @@
+use std::{env, time::Duration};
+
+use common::packet::{MAX_CORE_LOOP_PERIOD_MS, MIN_CORE_LOOP_PERIOD_MS};
+
+/// Environment variable used to override how often host sensors are polled.
+pub const HOST_SENSOR_POLL_ENV_VAR: &str = "PRANDTL_HOST_SENSOR_POLL_MS";
+
+/// Environment variable used to override how often the serial port is polled.
+pub const SERIAL_POLL_ENV_VAR: &str = "PRANDTL_SERIAL_POLL_MS";
+
+/// Environment variable used to override how often ports are scanned for
+/// newly connected embedded hardware.
+pub const DEVICE_SCAN_ENV_VAR: &str = "PRANDTL_DEVICE_SCAN_MS";
+
+/// Environment variable used to set the firmware's core loop period.
+pub const FIRMWARE_LOOP_ENV_VAR: &str = "PRANDTL_FIRMWARE_LOOP_MS";
+
+/// Environment variable used to override how long outputs are blended for
+/// when switching profiles.
+pub const PROFILE_BLEND_ENV_VAR: &str = "PRANDTL_PROFILE_BLEND_MS";
+
+/// Environment variable used to override how long outputs are ramped up from
+/// their parked values after the host resumes from sleep.
+pub const RESUME_WARM_UP_ENV_VAR: &str = "PRANDTL_RESUME_WARM_UP_MS";
+
+/// Poll intervals for each task, tunable per deployment to trade latency
+/// against power use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timings {
+    /// How often host sensors are polled.
+    pub host_sensor_poll: Duration,
+
+    /// How long the communication task waits for outgoing packets before
+    /// checking the serial port for incoming ones.
+    pub serial_poll: Duration,
+
+    /// How often ports are scanned for newly connected embedded hardware.
+    pub device_scan: Duration,
+
+    /// The core loop period to request from the firmware when it connects.
+    /// `None` leaves the firmware's stored setting alone.
+    pub firmware_core_loop_ms: Option<u8>,
+
+    /// How long control outputs are blended from the outgoing profile to the
+    /// incoming one. See `ControlState::begin_transition`.
+    pub profile_blend: Duration,
+
+    /// How long control outputs are blended from their parked values after
+    /// the host resumes from sleep.
+    pub resume_warm_up: Duration,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self {
+            host_sensor_poll: Duration::from_millis(1500),
+            serial_poll: Duration::from_millis(500),
+            device_scan: Duration::from_millis(500),
+            firmware_core_loop_ms: None,
+            profile_blend: Duration::from_secs(10),
+            resume_warm_up: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Timings {
+    /// Build the timings from the environment, falling back to the defaults
+    /// for anything unset or invalid.
+    pub fn from_env() -> Self {
+        let var = |name| env::var(name).ok();
+        let defaults = Self::default();
+        Self {
+            host_sensor_poll: parse_interval(var(HOST_SENSOR_POLL_ENV_VAR).as_deref())
+                .unwrap_or(defaults.host_sensor_poll),
+            serial_poll: parse_interval(var(SERIAL_POLL_ENV_VAR).as_deref())
+                .unwrap_or(defaults.serial_poll),
+            device_scan: parse_interval(var(DEVICE_SCAN_ENV_VAR).as_deref())
+                .unwrap_or(defaults.device_scan),
+            firmware_core_loop_ms: parse_core_loop_period(var(FIRMWARE_LOOP_ENV_VAR).as_deref()),
+            profile_blend: parse_interval(var(PROFILE_BLEND_ENV_VAR).as_deref())
+                .unwrap_or(defaults.profile_blend),
+            resume_warm_up: parse_interval(var(RESUME_WARM_UP_ENV_VAR).as_deref())
+                .unwrap_or(defaults.resume_warm_up),
+        }
+    }
+}
+
+/// Parse an interval in milliseconds. Returns `None` if it is missing, not a
+/// number or zero.
+fn parse_interval(value: Option<&str>) -> Option<Duration> {
+    value?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&millis| millis > 0)
+        .map(Duration::from_millis)
+}
+
+/// Parse a firmware core loop period in milliseconds. Returns `None` if it is
+/// missing, not a number or outside the range the firmware honors.
+fn parse_core_loop_period(value: Option<&str>) -> Option<u8> {
+    value?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|millis| (MIN_CORE_LOOP_PERIOD_MS..=MAX_CORE_LOOP_PERIOD_MS).contains(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(
+            parse_interval(Some("250")),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(parse_interval(Some(" 1000 ")), Some(Duration::from_secs(1)));
+        assert_eq!(parse_interval(Some("0")), None);
+        assert_eq!(parse_interval(Some("-5")), None);
+        assert_eq!(parse_interval(Some("soon")), None);
+        assert_eq!(parse_interval(None), None);
+    }
+
+    #[test]
+    fn test_parse_core_loop_period() {
+        assert_eq!(parse_core_loop_period(Some("20")), Some(20));
+        assert_eq!(parse_core_loop_period(Some("0")), None);
+        assert_eq!(parse_core_loop_period(Some("255")), None);
+        assert_eq!(parse_core_loop_period(None), None);
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::VecDeque,
+    env,
+    time::{Duration, Instant},
+};
+
+use common::physical::ValveState;
+
+/// Environment variable used to override how long the valve takes to travel
+/// fully open or closed.
+pub const VALVE_TRAVEL_ENV_VAR: &str = "PRANDTL_VALVE_TRAVEL_MS";
+
+/// Default valve travel time. Typical of small motorized ball valves.
+pub const DEFAULT_VALVE_TRAVEL: Duration = Duration::from_secs(5);
+
+/// Get the valve travel time from the environment, falling back to the
+/// default if it is unset or invalid.
+pub fn valve_travel_from_env() -> Duration {
+    let travel = env::var(VALVE_TRAVEL_ENV_VAR).ok();
+    parse_valve_travel(travel.as_deref()).unwrap_or(DEFAULT_VALVE_TRAVEL)
+}
+
+/// Parse a valve travel time in milliseconds. Returns `None` if it is missing,
+/// not a number or zero.
+fn parse_valve_travel(value: Option<&str>) -> Option<Duration> {
+    value?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&millis| millis > 0)
+        .map(Duration::from_millis)
+}
+
+/// Infers the valve's state from the last command sent to it, for hardware
+/// without valve sense wiring. A commanded valve is assumed to be moving for
+/// `travel` and then to have arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InferredValve {
+    travel: Duration,
+
+    /// The last state the valve is known to have arrived at.
+    settled: ValveState,
+
+    /// The state the valve was last commanded to and when, while it is still
+    /// travelling there.
+    commanded: Option<(ValveState, Instant)>,
+}
+
+impl InferredValve {
+    /// Create a model of a valve in an unknown position. The position is only
+    /// known once the first command has had time to complete.
+    pub fn new(travel: Duration) -> Self {
+        Self {
+            travel,
+            settled: ValveState::Unknown,
+            commanded: None,
+        }
+    }
+
+    /// Record a command sent to the valve at `now`. Repeating the command the
+    /// valve is already carrying out or has completed changes nothing.
+    pub fn command(&mut self, target: ValveState, now: Instant) {
+        if !matches!(target, ValveState::Open | ValveState::Closed) {
+            return;
+        }
+        self.settle(now);
+        let in_progress = self.commanded.map(|(commanded, _)| commanded);
+        if in_progress == Some(target) || (in_progress.is_none() && self.settled == target) {
+            return;
+        }
+        self.commanded = Some((target, now));
+    }
+
+    /// Get the inferred state of the valve at `now`.
+    pub fn state(&mut self, now: Instant) -> ValveState {
+        self.settle(now);
+        match self.commanded {
+            None => self.settled,
+            Some((ValveState::Open, _)) => ValveState::Opening,
+            Some(_) => ValveState::Closing,
+        }
+    }
+
+    /// Get every change of inferred state the model makes, as
+    /// `(from, event, to)`, by driving it with each command and a full
+    /// travel from every state it can reach, starting from a new model.
+    pub fn transitions(travel: Duration) -> Vec<(ValveState, &'static str, ValveState)> {
+        let events = [
+            ("open", Some(ValveState::Open), Duration::ZERO),
+            ("close", Some(ValveState::Closed), Duration::ZERO),
+            ("travel complete", None, travel),
+        ];
+        let mut transitions = Vec::new();
+        let mut visited = Vec::new();
+        let mut pending = VecDeque::from([(Self::new(travel), Instant::now())]);
+        while let Some((mut valve, now)) = pending.pop_front() {
+            let from = valve.state(now);
+            if visited.contains(&from) {
+                continue;
+            }
+            visited.push(from);
+            for (event, command, wait) in events {
+                let mut next = valve;
+                let at = now + wait;
+                if let Some(target) = command {
+                    next.command(target, at);
+                }
+                let to = next.state(at);
+                if to != from {
+                    transitions.push((from, event, to));
+                    pending.push_back((next, at));
+                }
+            }
+        }
+        transitions
+    }
+
+    /// Complete the commanded travel if it has had long enough.
+    fn settle(&mut self, now: Instant) {
+        if let Some((commanded, since)) = self.commanded {
+            if now.saturating_duration_since(since) >= self.travel {
+                self.settled = commanded;
+                self.commanded = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRAVEL: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_parse_valve_travel() {
+        assert_eq!(
+            parse_valve_travel(Some("8000")),
+            Some(Duration::from_secs(8))
+        );
+        assert_eq!(parse_valve_travel(Some("0")), None);
+        assert_eq!(parse_valve_travel(Some("slow")), None);
+        assert_eq!(parse_valve_travel(None), None);
+    }
+
+    #[test]
+    fn test_travel() {
+        let start = Instant::now();
+        let mut valve = InferredValve::new(TRAVEL);
+        assert_eq!(valve.state(start), ValveState::Unknown);
+
+        valve.command(ValveState::Open, start);
+        assert_eq!(valve.state(start + TRAVEL / 2), ValveState::Opening);
+        assert_eq!(valve.state(start + TRAVEL), ValveState::Open);
+
+        let close = start + TRAVEL * 2;
+        valve.command(ValveState::Closed, close);
+        assert_eq!(valve.state(close), ValveState::Closing);
+        assert_eq!(valve.state(close + TRAVEL), ValveState::Closed);
+    }
+
+    #[test]
+    fn test_repeated_commands_dont_restart_travel() {
+        let start = Instant::now();
+        let mut valve = InferredValve::new(TRAVEL);
+
+        valve.command(ValveState::Open, start);
+        valve.command(ValveState::Open, start + TRAVEL / 2);
+        assert_eq!(valve.state(start + TRAVEL), ValveState::Open);
+
+        valve.command(ValveState::Open, start + TRAVEL * 2);
+        assert_eq!(valve.state(start + TRAVEL * 2), ValveState::Open);
+
+        valve.command(ValveState::Unknown, start + TRAVEL * 3);
+        assert_eq!(valve.state(start + TRAVEL * 3), ValveState::Open);
+    }
+
+    #[test]
+    fn test_transitions() {
+        let transitions = InferredValve::transitions(TRAVEL);
+        assert_eq!(transitions.len(), 8);
+        assert!(transitions.contains(&(ValveState::Unknown, "open", ValveState::Opening)));
+        assert!(transitions.contains(&(ValveState::Opening, "travel complete", ValveState::Open)));
+        assert!(transitions.contains(&(ValveState::Opening, "close", ValveState::Closing)));
+        assert!(!transitions
+            .iter()
+            .any(|&(_, _, to)| to == ValveState::Unknown));
+    }
+}
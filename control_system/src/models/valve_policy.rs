@@ -0,0 +1,180 @@
+use std::{collections::HashMap, env};
+
+use common::physical::ValveState;
+
+use super::{
+    client_sensor_data::{ClientSensorData, ReadingQuality},
+    device_id::DeviceId,
+};
+
+/// Environment variable used to override how many consecutive `Unknown`
+/// valve readings are tolerated before the valve is treated as faulted.
+pub const VALVE_UNKNOWN_FRAMES_ENV_VAR: &str = "PRANDTL_VALVE_UNKNOWN_FRAMES";
+
+/// Default number of consecutive `Unknown` readings tolerated. Enough to ride
+/// out the sense pins briefly disagreeing while the valve is mid travel.
+pub const DEFAULT_VALVE_UNKNOWN_FRAMES: u32 = 5;
+
+/// How a valve whose sense pins keep disagreeing is handled.
+///
+/// A reading of `Unknown` for `unknown_frames` reports in a row is treated as
+/// a wiring fault rather than a valve in motion: the valve is assumed open,
+/// which is the fail-safe position for cooling, an alarm is raised and valve
+/// commands are held until the valve reports a known state again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValvePolicy {
+    /// Consecutive `Unknown` readings tolerated before the valve is faulted.
+    pub unknown_frames: u32,
+}
+
+impl Default for ValvePolicy {
+    fn default() -> Self {
+        Self {
+            unknown_frames: DEFAULT_VALVE_UNKNOWN_FRAMES,
+        }
+    }
+}
+
+impl ValvePolicy {
+    /// Get the valve policy from the environment, falling back to the
+    /// defaults for anything unset or invalid.
+    pub fn from_env() -> Self {
+        let frames = env::var(VALVE_UNKNOWN_FRAMES_ENV_VAR).ok();
+        Self {
+            unknown_frames: parse_unknown_frames(frames.as_deref())
+                .unwrap_or(DEFAULT_VALVE_UNKNOWN_FRAMES),
+        }
+    }
+}
+
+/// Parse a number of frames. Returns `None` if it is missing, not a number or
+/// zero.
+fn parse_unknown_frames(value: Option<&str>) -> Option<u32> {
+    value?.trim().parse().ok().filter(|&frames| frames > 0)
+}
+
+/// Applies a `ValvePolicy` to the valve readings of every device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownValveGuard {
+    policy: ValvePolicy,
+
+    /// How many reports in a row have had an `Unknown` valve state, by device.
+    unknown_frames: HashMap<DeviceId, u32>,
+}
+
+impl UnknownValveGuard {
+    pub fn new(policy: ValvePolicy) -> Self {
+        Self {
+            policy,
+            unknown_frames: HashMap::new(),
+        }
+    }
+
+    /// Whether `device`'s valve has been `Unknown` for long enough to be
+    /// faulted.
+    pub fn is_faulted(&self, device: DeviceId) -> bool {
+        self.unknown_frames
+            .get(&device)
+            .is_some_and(|&frames| frames >= self.policy.unknown_frames)
+    }
+
+    /// Observe the next full report of a device. Once faulted, its valve state
+    /// is replaced with an assumed `Open`. Returns the report to use, along
+    /// with `Some(true)` if the valve just became faulted or `Some(false)` if
+    /// it just recovered.
+    pub fn observe(&mut self, data: ClientSensorData) -> (ClientSensorData, Option<bool>) {
+        if data.valve_state != ValveState::Unknown {
+            let was_faulted = self.is_faulted(data.device);
+            self.unknown_frames.remove(&data.device);
+            return (data, was_faulted.then_some(false));
+        }
+        let frames = self.unknown_frames.entry(data.device).or_default();
+        *frames = frames.saturating_add(1);
+        if *frames < self.policy.unknown_frames {
+            return (data, None);
+        }
+        let became_faulted = *frames == self.policy.unknown_frames;
+        (
+            data.with_assumed_valve_state(ValveState::Open),
+            became_faulted.then_some(true),
+        )
+    }
+}
+
+/// Whether a valve reading is one the policy assumed, so commands to the valve
+/// should be held.
+pub fn is_valve_assumed(data: &ClientSensorData) -> bool {
+    data.quality.valve_state == ReadingQuality::Assumed
+}
+
+#[cfg(test)]
+mod tests {
+    use common::physical::Rpm;
+
+    use super::*;
+
+    fn report(valve_state: ValveState) -> ClientSensorData {
+        report_from("1324", valve_state)
+    }
+
+    fn report_from(device: &str, valve_state: ValveState) -> ClientSensorData {
+        ClientSensorData::new(
+            DeviceId::new(device),
+            Rpm::new(2000f32, 1000f32).unwrap(),
+            Rpm::new(2000f32, 1000f32).unwrap(),
+            valve_state,
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_parse_unknown_frames() {
+        assert_eq!(parse_unknown_frames(Some(" 10 ")), Some(10));
+        assert_eq!(parse_unknown_frames(Some("0")), None);
+        assert_eq!(parse_unknown_frames(Some("many")), None);
+        assert_eq!(parse_unknown_frames(None), None);
+    }
+
+    #[test]
+    fn test_transient_unknown_passes_through() {
+        let mut guard = UnknownValveGuard::new(ValvePolicy { unknown_frames: 3 });
+        for _ in 0..2 {
+            let (data, change) = guard.observe(report(ValveState::Unknown));
+            assert_eq!(data.valve_state, ValveState::Unknown);
+            assert_eq!(data.quality.valve_state, ReadingQuality::Invalid);
+            assert_eq!(change, None);
+        }
+        let (data, change) = guard.observe(report(ValveState::Closed));
+        assert_eq!(data.valve_state, ValveState::Closed);
+        assert_eq!(change, None);
+        assert!(!guard.is_faulted(DeviceId::new("1324")));
+    }
+
+    #[test]
+    fn test_persistent_unknown_is_assumed_open() {
+        let mut guard = UnknownValveGuard::new(ValvePolicy { unknown_frames: 3 });
+        guard.observe(report(ValveState::Unknown));
+        guard.observe(report(ValveState::Unknown));
+
+        let (data, change) = guard.observe(report(ValveState::Unknown));
+        assert_eq!(data.valve_state, ValveState::Open);
+        assert!(is_valve_assumed(&data));
+        assert_eq!(change, Some(true));
+
+        let (data, change) = guard.observe(report(ValveState::Unknown));
+        assert!(is_valve_assumed(&data));
+        assert_eq!(change, None);
+
+        let (data, change) = guard.observe(report_from("5678", ValveState::Unknown));
+        assert!(!is_valve_assumed(&data));
+        assert_eq!(change, None);
+
+        let (data, change) = guard.observe(report(ValveState::Closing));
+        assert_eq!(data.valve_state, ValveState::Closing);
+        assert!(!is_valve_assumed(&data));
+        assert_eq!(change, Some(false));
+        assert!(!guard.is_faulted(DeviceId::new("1324")));
+    }
+}
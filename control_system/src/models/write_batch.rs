@@ -0,0 +1,88 @@
+use common::framing::DEVICE_READ_CHUNK_LEN;
+
+/// The most bytes written to a device at once. Matches what the firmware
+/// reads at once by default, so a batch is normally decoded in one read.
+pub const MAX_WRITE_BATCH_BYTES: usize = DEVICE_READ_CHUNK_LEN;
+
+/// Encoded frames waiting to go to a device in a single write, so a burst
+/// such as a control frame, parameters and a ping costs one syscall rather
+/// than one each.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteBatch {
+    bytes: Vec<u8>,
+    frames: usize,
+    max_bytes: usize,
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self {
+        Self::new(MAX_WRITE_BATCH_BYTES)
+    }
+}
+
+impl WriteBatch {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(max_bytes),
+            frames: 0,
+            max_bytes,
+        }
+    }
+
+    /// Whether `frame` can join the batch without it growing past its bound.
+    /// Anything fits an empty batch, so a frame larger than the bound is
+    /// written on its own.
+    pub fn fits(&self, frame: &[u8]) -> bool {
+        self.frames == 0 || self.bytes.len() + frame.len() <= self.max_bytes
+    }
+
+    /// Add a frame to the end of the batch. Check it `fits` first.
+    pub fn push(&mut self, frame: &[u8]) {
+        self.bytes.extend_from_slice(frame);
+        self.frames += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    /// How many frames are in the batch.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// The frames in the batch, back to back.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Empty the batch once it is written.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_bound() {
+        let mut batch = WriteBatch::new(8);
+        assert!(batch.is_empty());
+        assert!(batch.fits(&[1; 5]));
+        batch.push(&[1; 5]);
+        assert!(batch.fits(&[2; 3]));
+        batch.push(&[2; 3]);
+        assert!(!batch.fits(&[3]));
+        assert_eq!(batch.frames(), 2);
+        assert_eq!(batch.bytes(), &[1, 1, 1, 1, 1, 2, 2, 2]);
+
+        batch.clear();
+        assert!(batch.is_empty());
+        assert!(batch.bytes().is_empty());
+        // NOTE: Too big to batch, but still written on its own.
+        assert!(batch.fits(&[4; 12]));
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+/// How often a USB full-speed host polls the device, once per frame.
+pub const USB_FRAME_PERIOD: Duration = Duration::from_millis(1);
+
+/// How many bytes the firmware drains from its CDC endpoint per USB frame:
+/// one full-speed bulk packet.
+pub const USB_FRAME_BUDGET_BYTES: u32 = 64;
+
+/// The most bytes per second a link can take: whichever is lower of what the
+/// firmware drains over USB and what a UART bridge at `baud_rate` carries,
+/// at 10 bits a byte. Without a known baud rate only USB limits it.
+pub fn link_capacity(baud_rate: Option<u32>) -> u32 {
+    let usb = USB_FRAME_BUDGET_BYTES * (Duration::from_secs(1).as_micros() as u32)
+        / USB_FRAME_PERIOD.as_micros() as u32;
+    match baud_rate {
+        Some(baud_rate) => (baud_rate / 10).clamp(1, usb),
+        None => usb,
+    }
+}
+
+/// Paces writes to a device to `bytes_per_second`, so bursts such as a batch
+/// of parameters are spread out rather than stalling the firmware. Up to a
+/// USB frame's budget may be written back to back before pacing kicks in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WritePacer {
+    bytes_per_second: u32,
+
+    /// When the link will have drained everything written so far.
+    drained_at: Instant,
+}
+
+impl WritePacer {
+    pub fn new(bytes_per_second: u32, now: Instant) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            drained_at: now,
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u32 {
+        self.bytes_per_second
+    }
+
+    /// How long to wait at `now` before writing again. Zero if the link has
+    /// room for a burst.
+    pub fn delay(&self, now: Instant) -> Duration {
+        let burst = self.time_to_drain(USB_FRAME_BUDGET_BYTES as usize);
+        self.drained_at
+            .checked_sub(burst)
+            .map_or(Duration::ZERO, |ready_at| {
+                ready_at.saturating_duration_since(now)
+            })
+    }
+
+    /// Record `bytes` written at `now`.
+    pub fn record(&mut self, bytes: usize, now: Instant) {
+        self.drained_at = self.drained_at.max(now) + self.time_to_drain(bytes);
+    }
+
+    fn time_to_drain(&self, bytes: usize) -> Duration {
+        Duration::from_nanos(bytes as u64 * 1_000_000_000 / self.bytes_per_second as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_capacity() {
+        assert_eq!(link_capacity(None), 64_000);
+        assert_eq!(link_capacity(Some(115_200)), 11_520);
+        assert_eq!(link_capacity(Some(2_000_000)), 64_000);
+    }
+
+    #[test]
+    fn test_bursts_are_paced() {
+        let start = Instant::now();
+        let mut pacer = WritePacer::new(1_000, start);
+        assert_eq!(pacer.delay(start), Duration::ZERO);
+
+        // NOTE: A frame's budget goes out back to back.
+        pacer.record(32, start);
+        pacer.record(32, start);
+        assert_eq!(pacer.delay(start), Duration::ZERO);
+        pacer.record(32, start);
+        assert_eq!(pacer.delay(start), Duration::from_millis(32));
+
+        // NOTE: Time spent idle drains the backlog.
+        let later = start + Duration::from_millis(40);
+        assert_eq!(pacer.delay(later), Duration::ZERO);
+        pacer.record(100, later + Duration::from_secs(1));
+        assert_eq!(
+            pacer.delay(later + Duration::from_secs(1)),
+            Duration::from_millis(36)
+        );
+    }
+}
@@ -0,0 +1,635 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::controls::{ControlStrategy, CurveStrategy};
+use crate::models::alarm::{AlarmLog, ALARMS_PATH};
+use crate::models::capabilities::HardwareExpectations;
+use crate::models::control_generation::SharedGeneration;
+use crate::models::control_loop::ControlLoopMode;
+#[cfg(feature = "scripting")]
+use crate::models::control_script::{control_script_from_env, CONTROL_SCRIPT_ENV_VAR};
+#[cfg(feature = "control-socket")]
+use crate::models::control_socket::control_socket_path_from_env;
+use crate::models::control_trace::{control_traces_from_env, EXPLAIN_ENV_VAR};
+use crate::models::device_log::{
+    journal_device_logs_from_env, SharedDeviceLogs, JOURNAL_DEVICE_LOGS_ENV_VAR,
+};
+use crate::models::device_registry::{DeviceRegistry, DEVICE_REGISTRY_PATH};
+use crate::models::event_bus::EventBus;
+use crate::models::fault_injection::FaultInjection;
+#[cfg(feature = "simulate")]
+use crate::models::fault_simulation::{plant_faults_from_env, FAULT_SIMULATION_ENV_VAR};
+use crate::models::flow_interlock::LowFlowPolicy;
+use crate::models::heartbeat::SharedHeartbeatRegistry;
+use crate::models::journal::unix_time_ms;
+#[cfg(feature = "recording")]
+use crate::models::journal::JOURNAL_PATH;
+use crate::models::link::LinkConfig;
+#[cfg(feature = "http")]
+use crate::models::metrics::{metrics_addr_from_env, MetricValues, SharedMetrics};
+#[cfg(feature = "recording")]
+use crate::models::retention::RetentionPolicy;
+use crate::models::shutdown::ShutdownStage;
+#[cfg(feature = "dbus")]
+use crate::models::sleep::SleepMonitorCommand;
+#[cfg(feature = "recording")]
+use crate::models::telemetry_store::{JournalBackend, JsonlStore};
+#[cfg(feature = "recording")]
+use crate::models::telemetry_summary::SummaryConfig;
+use crate::models::temperature::TemperatureUnit;
+use crate::models::temperature_aggregation::{TemperatureAggregation, COMMAND_SOURCE, CPU_SOURCE};
+use crate::models::temperature_calibration::TemperatureCalibrations;
+use crate::models::temperature_failover::TemperatureBackend;
+use crate::models::timings::Timings;
+use crate::models::valve_model::valve_travel_from_env;
+use crate::models::valve_policy::ValvePolicy;
+use crate::tasks::alarms::task_latch_alarms;
+use crate::tasks::client_sensors::task::{
+    task_lifetime_management_of_client_communication_task, task_process_client_sensor_packets,
+    task_send_control_frames_to_client,
+};
+#[cfg(feature = "control-socket")]
+use crate::tasks::control_socket::task_serve_control_socket;
+use crate::tasks::control_system::task_core_system;
+use crate::tasks::convergence_checking::task_verify_control_convergence;
+use crate::tasks::device_log::task_forward_device_logs;
+use crate::tasks::duty_rpm_learning::task_learn_duty_rpm;
+use crate::tasks::estimation::task_estimate_rpm;
+use crate::tasks::host_sensors::{
+    services::{
+        HostCpuTemperatureServiceActual, HostCpuTemperatureServiceCommand,
+        HostCpuTemperatureServiceFailover, TemperatureSource, SENSORS_COMMAND_ENV_VAR,
+    },
+    task::task_poll_host_sensors,
+};
+#[cfg(feature = "recording")]
+use crate::tasks::journal_maintenance::task_maintain_journal;
+#[cfg(feature = "recording")]
+use crate::tasks::journaling::task_record_journal;
+use crate::tasks::max_rpm_learning::task_learn_max_rpm;
+#[cfg(feature = "http")]
+use crate::tasks::metrics::{task_collect_metrics, task_serve_metrics};
+#[cfg(feature = "recording")]
+use crate::tasks::rpm_trend::task_check_rpm_trends;
+use crate::tasks::shutdown::Shutdown;
+#[cfg(feature = "dbus")]
+use crate::tasks::sleep::task_monitor_sleep;
+use crate::tasks::time_sync::task_sync_device_clocks;
+use crate::tasks::watchdog::{task_supervise, SupervisedTask, SUPERVISED_SHUTDOWN_TIMEOUT};
+
+/// How long a pipeline task has to exit once cancelled before it is aborted.
+const PIPELINE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long an observer has to exit once cancelled before it is aborted.
+/// Longer than the pipeline so learners and the journal can flush to disk.
+const OBSERVER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run the control system, configured from the environment, until `token` is
+/// cancelled, then shut its tasks down in stages. The tasks communicate over
+/// `bus`, so a program embedding the control system can observe and steer it
+/// through a clone of it.
+pub async fn run(bus: EventBus, token: CancellationToken) {
+    let mut shutdown = Shutdown::new();
+
+    let registry = match DeviceRegistry::load(&PathBuf::from(DEVICE_REGISTRY_PATH)) {
+        Ok(registry) => registry,
+        Err(e) => {
+            tracing::error!(
+                "Failed to load device registry. Starting with an empty registry. Error: {}",
+                e
+            );
+            DeviceRegistry::default()
+        }
+    };
+    let registry = Arc::new(RwLock::new(registry));
+
+    let mut alarms = match AlarmLog::load(&PathBuf::from(ALARMS_PATH)) {
+        Ok(alarms) => alarms,
+        Err(e) => {
+            tracing::error!(
+                "Failed to load alarms. Starting without latched alarms. Error: {}",
+                e
+            );
+            AlarmLog::default()
+        }
+    };
+    // NOTE: Conditions still present are raised again once they are detected.
+    alarms.clear_all(unix_time_ms(std::time::SystemTime::now()));
+    let alarms = Arc::new(Mutex::new(alarms));
+
+    let tx_client_sensor_data = bus.client_sensor_data.clone();
+    let tx_host_sensor_data = bus.host_sensor_data.clone();
+    let tx_control_frame = bus.control_frames.clone();
+    let rx_control_frame = tx_control_frame.subscribe();
+
+    // NOTE: Used to handle packets received from embedded hardware.
+    let (tx_packets_from_hw, _) = broadcast::channel(32);
+
+    // NOTE: Used to handle packets to be sent to embedded hardware.
+    let (tx_send_packets_to_hw, rx_send_packets_to_hw) = broadcast::channel(32);
+
+    // NOTE: Used to journal records which aren't broadcast elsewhere.
+    let tx_journal = bus.journal.clone();
+
+    // NOTE: Used to take devices over from the control loop.
+    let tx_overrides = bus.overrides.clone();
+
+    // NOTE: Used to raise and clear alarms from the tasks detecting them.
+    let (tx_alarm_conditions, rx_alarm_conditions) = broadcast::channel(32);
+
+    // NOTE: Used to share smoothed pump and fan speeds.
+    let (tx_rpm_estimates, _) = broadcast::channel(32);
+
+    // NOTE: Used to park the pump around host suspend and resume.
+    let (tx_sleep_events, _) = broadcast::channel(8);
+
+    let timings = Timings::from_env();
+    tracing::info!("Using timings: {:?}", timings);
+
+    let control_loop = ControlLoopMode::from_env();
+    tracing::info!("Stepping the control loop on {}.", control_loop);
+
+    // NOTE: The core pipeline stages are restarted by the watchdog if they stall,
+    //       so each is spawned from a closure which can subscribe afresh.
+    let tx_client_sensor_data_clone = tx_client_sensor_data.clone();
+    let tx_host_sensor_data_clone = tx_host_sensor_data.clone();
+    let tx_sleep_events_clone = tx_sleep_events.clone();
+    let tx_overrides_clone = tx_overrides.clone();
+    let tx_control_frame_clone = tx_control_frame.clone();
+    let tx_journal_clone = tx_journal.clone();
+    // NOTE: Outlives the control task so a restart doesn't reuse generations.
+    let generation = SharedGeneration::default();
+    let control_traces = control_traces_from_env();
+    if control_traces.is_some() {
+        tracing::info!(
+            "Explain mode is on. Tracing every control frame, unset {} to stop.",
+            EXPLAIN_ENV_VAR
+        );
+    }
+    let control_traces_clone = control_traces.clone();
+    #[cfg(feature = "scripting")]
+    let strategy: Arc<dyn ControlStrategy> = match control_script_from_env() {
+        Some(script) => {
+            tracing::info!(
+                "Generating control frames with the control script from {}.",
+                CONTROL_SCRIPT_ENV_VAR
+            );
+            Arc::new(script)
+        }
+        None => Arc::new(CurveStrategy),
+    };
+    #[cfg(not(feature = "scripting"))]
+    let strategy: Arc<dyn ControlStrategy> = Arc::new(CurveStrategy);
+    let control_task = SupervisedTask::new("control", move |token, heartbeat| {
+        tokio::spawn(task_core_system(
+            token,
+            tx_client_sensor_data_clone.subscribe(),
+            tx_host_sensor_data_clone.subscribe(),
+            tx_sleep_events_clone.subscribe(),
+            tx_overrides_clone.subscribe(),
+            tx_control_frame_clone.clone(),
+            tx_journal_clone.clone(),
+            timings.resume_warm_up,
+            timings.profile_blend,
+            control_loop,
+            strategy.clone(),
+            generation.clone(),
+            control_traces_clone.clone(),
+            heartbeat,
+        ))
+    });
+
+    let temperature_unit = TemperatureUnit::from_env();
+    tracing::info!("Displaying temperatures in {}.", temperature_unit);
+
+    let temperature_aggregation = TemperatureAggregation::from_env();
+    tracing::info!(
+        "Aggregating host temperatures with {}.",
+        temperature_aggregation
+    );
+
+    let temperature_calibrations = TemperatureCalibrations::from_env();
+    if !temperature_calibrations.is_empty() {
+        tracing::info!(
+            "Calibrating host temperatures with {}.",
+            temperature_calibrations
+        );
+    }
+
+    // NOTE: Containers without sysfs can provide `sensors -j` output instead.
+    let cpu_temperature_backends =
+        TemperatureBackend::chain_from_env(std::env::var(SENSORS_COMMAND_ENV_VAR).is_ok());
+    tracing::info!(
+        "Reading the cpu temperature from {}.",
+        cpu_temperature_backends
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    );
+
+    let tx_host_sensor_data_clone = tx_host_sensor_data.clone();
+    let tx_journal_clone = tx_journal.clone();
+    let host_sensor_task = SupervisedTask::new("host_sensors", move |token, heartbeat| {
+        let tx_host_sensor_data = tx_host_sensor_data_clone.clone();
+        let temperature_aggregation = temperature_aggregation.clone();
+        let temperature_calibrations = temperature_calibrations.clone();
+        let cpu_temperature_backends = cpu_temperature_backends.clone();
+        let tx_journal = tx_journal_clone.clone();
+        tokio::spawn(async move {
+            let mut sources = vec![];
+            if let Some(service) = HostCpuTemperatureServiceCommand::from_env() {
+                sources.push(TemperatureSource::new(COMMAND_SOURCE, service));
+            }
+            match HostCpuTemperatureServiceFailover::new(&cpu_temperature_backends, tx_journal) {
+                Some(service) => sources.push(TemperatureSource::new(CPU_SOURCE, service)),
+                None => sources.push(TemperatureSource::new(
+                    CPU_SOURCE,
+                    HostCpuTemperatureServiceActual,
+                )),
+            }
+            let sources: Vec<TemperatureSource> = sources
+                .into_iter()
+                .map(|source| {
+                    let calibration = temperature_calibrations.get(source.name);
+                    source.with_calibration(calibration)
+                })
+                .collect();
+            task_poll_host_sensors(
+                token,
+                &sources,
+                &temperature_aggregation,
+                tx_host_sensor_data,
+                timings,
+                temperature_unit,
+                heartbeat,
+            )
+            .await
+        })
+    });
+
+    let link_config = LinkConfig::from_env();
+    tracing::info!("Using serial link config: {:?}", link_config);
+    #[cfg(feature = "simulate")]
+    let fault_injection = FaultInjection::from_env();
+    #[cfg(not(feature = "simulate"))]
+    let fault_injection = FaultInjection::default();
+    if fault_injection.is_enabled() {
+        tracing::warn!(
+            "Injecting faults into the link to the hardware: {}",
+            fault_injection
+        );
+    }
+    #[cfg(feature = "simulate")]
+    let plant_faults = plant_faults_from_env();
+    #[cfg(feature = "simulate")]
+    if plant_faults.is_some() {
+        tracing::warn!(
+            "Fault simulation is on. Plant faults can be simulated with simulate-fault, unset {} to stop.",
+            FAULT_SIMULATION_ENV_VAR
+        );
+    }
+    #[cfg(feature = "simulate")]
+    let plant_faults_clone = plant_faults.clone();
+    let expectations = HardwareExpectations::from_env();
+    tracing::info!("Expecting hardware: {:?}", expectations);
+    let tx_packets_from_hw_clone = tx_packets_from_hw.clone();
+    let tx_send_packets_to_hw_clone = tx_send_packets_to_hw.clone();
+    let tx_journal_clone = tx_journal.clone();
+    let comm_task = SupervisedTask::new("client_communication", move |token, heartbeat| {
+        tokio::spawn(task_lifetime_management_of_client_communication_task(
+            token,
+            tx_packets_from_hw_clone.clone(),
+            tx_send_packets_to_hw_clone.clone(),
+            link_config,
+            fault_injection,
+            #[cfg(feature = "simulate")]
+            plant_faults_clone.clone(),
+            timings,
+            expectations,
+            tx_journal_clone.clone(),
+            heartbeat,
+        ))
+    });
+
+    let valve_travel = valve_travel_from_env();
+    let valve_policy = ValvePolicy::from_env();
+    tracing::info!("Using valve policy: {:?}", valve_policy);
+    let low_flow_policy = LowFlowPolicy::from_env();
+    tracing::info!("Using low flow policy: {:?}", low_flow_policy);
+    let tx_alarm_conditions_clone = tx_alarm_conditions.clone();
+    let tx_client_sensor_data_clone = tx_client_sensor_data.clone();
+    let tx_packets_from_hw_clone = tx_packets_from_hw.clone();
+    let tx_control_frame_clone = tx_control_frame.clone();
+    let client_sensor_task = SupervisedTask::new("client_sensors", move |token, heartbeat| {
+        tokio::spawn(task_process_client_sensor_packets(
+            token,
+            tx_client_sensor_data_clone.clone(),
+            tx_packets_from_hw_clone.subscribe(),
+            tx_control_frame_clone.subscribe(),
+            valve_travel,
+            valve_policy,
+            low_flow_policy,
+            tx_alarm_conditions_clone.clone(),
+            heartbeat,
+        ))
+    });
+
+    let heartbeats = SharedHeartbeatRegistry::default();
+    // NOTE: The supervisor waits on its tasks together, so give it enough
+    //       time for them to overrun their own deadlines.
+    shutdown.spawn(
+        "supervisor",
+        ShutdownStage::Pipeline,
+        SUPERVISED_SHUTDOWN_TIMEOUT * 2,
+        |token| {
+            task_supervise(
+                token,
+                heartbeats,
+                vec![
+                    control_task,
+                    host_sensor_task,
+                    comm_task,
+                    client_sensor_task,
+                ],
+            )
+        },
+    );
+
+    let rx_packets_from_hw_clone = tx_packets_from_hw.subscribe();
+    let tx_send_packets_to_hw_clone = tx_send_packets_to_hw.clone();
+    shutdown.spawn(
+        "time_sync",
+        ShutdownStage::Pipeline,
+        PIPELINE_SHUTDOWN_TIMEOUT,
+        |token| {
+            task_sync_device_clocks(token, rx_packets_from_hw_clone, tx_send_packets_to_hw_clone)
+        },
+    );
+
+    let rx_client_sensor_data_clone = tx_client_sensor_data.subscribe();
+    let rx_control_frame_clone = tx_control_frame.subscribe();
+    let tx_send_packets_to_hw_clone = tx_send_packets_to_hw.clone();
+    let registry_clone = registry.clone();
+    shutdown.spawn(
+        "max_rpm_learner",
+        ShutdownStage::Observers,
+        OBSERVER_SHUTDOWN_TIMEOUT,
+        |token| {
+            task_learn_max_rpm(
+                token,
+                PathBuf::from(DEVICE_REGISTRY_PATH),
+                registry_clone,
+                rx_client_sensor_data_clone,
+                rx_control_frame_clone,
+                tx_send_packets_to_hw_clone,
+            )
+        },
+    );
+
+    let rx_client_sensor_data_clone = tx_client_sensor_data.subscribe();
+    let rx_control_frame_clone = tx_control_frame.subscribe();
+    let registry_clone = registry.clone();
+    shutdown.spawn(
+        "duty_rpm_learner",
+        ShutdownStage::Observers,
+        OBSERVER_SHUTDOWN_TIMEOUT,
+        |token| {
+            task_learn_duty_rpm(
+                token,
+                PathBuf::from(DEVICE_REGISTRY_PATH),
+                registry_clone,
+                rx_client_sensor_data_clone,
+                rx_control_frame_clone,
+            )
+        },
+    );
+
+    let rx_client_sensor_data_clone = tx_client_sensor_data.subscribe();
+    let rx_control_frame_clone = tx_control_frame.subscribe();
+    let registry_clone = registry.clone();
+    let tx_rpm_estimates_clone = tx_rpm_estimates.clone();
+    shutdown.spawn(
+        "rpm_estimator",
+        ShutdownStage::Observers,
+        OBSERVER_SHUTDOWN_TIMEOUT,
+        |token| {
+            task_estimate_rpm(
+                token,
+                registry_clone,
+                rx_client_sensor_data_clone,
+                rx_control_frame_clone,
+                tx_rpm_estimates_clone,
+            )
+        },
+    );
+
+    let rx_rpm_estimates_clone = tx_rpm_estimates.subscribe();
+    let rx_control_frame_clone = tx_control_frame.subscribe();
+    let registry_clone = registry.clone();
+    shutdown.spawn(
+        "convergence_checker",
+        ShutdownStage::Observers,
+        OBSERVER_SHUTDOWN_TIMEOUT,
+        |token| {
+            task_verify_control_convergence(
+                token,
+                registry_clone,
+                rx_rpm_estimates_clone,
+                rx_control_frame_clone,
+                tx_alarm_conditions.clone(),
+            )
+        },
+    );
+
+    let rx_packets_from_hw_clone = tx_packets_from_hw.subscribe();
+    let alarms_clone = alarms.clone();
+    let tx_journal_clone = tx_journal.clone();
+    shutdown.spawn(
+        "alarms",
+        ShutdownStage::Observers,
+        OBSERVER_SHUTDOWN_TIMEOUT,
+        |token| {
+            task_latch_alarms(
+                token,
+                PathBuf::from(ALARMS_PATH),
+                alarms_clone,
+                rx_alarm_conditions,
+                rx_packets_from_hw_clone,
+                tx_journal_clone,
+            )
+        },
+    );
+
+    let journal_device_logs = journal_device_logs_from_env();
+    if journal_device_logs {
+        tracing::info!(
+            "Journaling device log lines, unset {} to stop.",
+            JOURNAL_DEVICE_LOGS_ENV_VAR
+        );
+    }
+    let device_logs = SharedDeviceLogs::default();
+    let device_logs_clone = device_logs.clone();
+    let rx_packets_from_hw_clone = tx_packets_from_hw.subscribe();
+    let tx_journal_clone = tx_journal.clone();
+    shutdown.spawn(
+        "device_logs",
+        ShutdownStage::Observers,
+        OBSERVER_SHUTDOWN_TIMEOUT,
+        |token| {
+            task_forward_device_logs(
+                token,
+                rx_packets_from_hw_clone,
+                tx_journal_clone,
+                device_logs_clone,
+                journal_device_logs,
+            )
+        },
+    );
+
+    #[cfg(feature = "control-socket")]
+    {
+        let tx_send_packets_to_hw_clone = tx_send_packets_to_hw.clone();
+        let tx_journal_clone = tx_journal.clone();
+        shutdown.spawn(
+            "control_socket",
+            ShutdownStage::Observers,
+            OBSERVER_SHUTDOWN_TIMEOUT,
+            |token| {
+                task_serve_control_socket(
+                    token,
+                    control_socket_path_from_env(),
+                    PathBuf::from(ALARMS_PATH),
+                    alarms,
+                    control_traces,
+                    #[cfg(feature = "simulate")]
+                    plant_faults,
+                    device_logs,
+                    tx_send_packets_to_hw_clone,
+                    tx_journal_clone,
+                )
+            },
+        );
+    }
+
+    #[cfg(feature = "dbus")]
+    if let Some(command) = SleepMonitorCommand::from_env() {
+        shutdown.spawn(
+            "sleep_monitor",
+            ShutdownStage::Observers,
+            OBSERVER_SHUTDOWN_TIMEOUT,
+            |token| task_monitor_sleep(token, command, tx_sleep_events),
+        );
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(addr) = metrics_addr_from_env() {
+        let metrics: SharedMetrics = Arc::new(Mutex::new(MetricValues::new(temperature_unit)));
+        let metrics_clone = metrics.clone();
+        let rx_client_sensor_data_clone = tx_client_sensor_data.subscribe();
+        let rx_host_sensor_data_clone = tx_host_sensor_data.subscribe();
+        let rx_control_frame_clone = tx_control_frame.subscribe();
+        let rx_journal = tx_journal.subscribe();
+        shutdown.spawn(
+            "metrics",
+            ShutdownStage::Observers,
+            OBSERVER_SHUTDOWN_TIMEOUT,
+            |token| {
+                task_collect_metrics(
+                    token,
+                    metrics_clone,
+                    rx_client_sensor_data_clone,
+                    rx_host_sensor_data_clone,
+                    rx_control_frame_clone,
+                    rx_journal,
+                )
+            },
+        );
+        shutdown.spawn(
+            "metrics_exporter",
+            ShutdownStage::Observers,
+            OBSERVER_SHUTDOWN_TIMEOUT,
+            |token| task_serve_metrics(token, addr, metrics),
+        );
+    }
+
+    #[cfg(feature = "recording")]
+    {
+        let rx_client_sensor_data_clone = tx_client_sensor_data.subscribe();
+        let rx_host_sensor_data_clone = tx_host_sensor_data.subscribe();
+        let rx_control_frame_clone = tx_control_frame.subscribe();
+        let rx_journal = tx_journal.subscribe();
+        let summary_config = SummaryConfig::from_env();
+        let journal_backend = JournalBackend::from_env();
+        tracing::info!("Journaling to {}.", journal_backend);
+        let store = match journal_backend.open() {
+            Ok(store) => store,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to open journal. Falling back to {}. Error: {}",
+                    JOURNAL_PATH,
+                    e
+                );
+                Box::new(JsonlStore::new(JOURNAL_PATH))
+            }
+        };
+        let store = Arc::new(Mutex::new(store));
+        let store_clone = store.clone();
+        shutdown.spawn(
+            "journal",
+            ShutdownStage::Observers,
+            OBSERVER_SHUTDOWN_TIMEOUT,
+            |token| {
+                task_record_journal(
+                    token,
+                    store_clone,
+                    summary_config,
+                    rx_client_sensor_data_clone,
+                    rx_host_sensor_data_clone,
+                    rx_control_frame_clone,
+                    rx_journal,
+                )
+            },
+        );
+
+        let store_clone = store.clone();
+        shutdown.spawn(
+            "rpm_trends",
+            ShutdownStage::Observers,
+            OBSERVER_SHUTDOWN_TIMEOUT,
+            |token| task_check_rpm_trends(token, store_clone, tx_alarm_conditions),
+        );
+
+        let retention_policy = RetentionPolicy::from_env();
+        let tx_journal_clone = tx_journal.clone();
+        shutdown.spawn(
+            "journal_maintenance",
+            ShutdownStage::Observers,
+            OBSERVER_SHUTDOWN_TIMEOUT,
+            |token| task_maintain_journal(token, store, retention_policy, tx_journal_clone),
+        );
+    }
+
+    let rx_control_frame_clone = tx_control_frame.subscribe();
+    shutdown.spawn(
+        "control_frame_sender",
+        ShutdownStage::Pipeline,
+        PIPELINE_SHUTDOWN_TIMEOUT,
+        |token| {
+            task_send_control_frames_to_client(token, rx_control_frame_clone, tx_send_packets_to_hw)
+        },
+    );
+
+    token.cancelled().await;
+
+    let report = shutdown.run().await;
+    if report.is_clean() {
+        tracing::info!("Shutdown complete: {}", report);
+    } else {
+        tracing::warn!("Shutdown complete: {}", report);
+    }
+}
@@ -0,0 +1,214 @@
+use std::{path::Path, time::SystemTime};
+
+use common::packet::Packet;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::models::{
+    addressed_packet::AddressedPacket,
+    alarm::{Alarm, AlarmCondition, AlarmKind, SharedAlarmLog},
+    journal::{unix_time_ms, JournalRecord},
+};
+#[cfg(feature = "control-socket")]
+use crate::{
+    error::{ConfigError, ControlError, Error},
+    models::{
+        alarm::AlarmLog,
+        control_socket::{AlarmsCommand, ControlRequest, ControlResponse},
+    },
+};
+
+#[cfg(feature = "recording")]
+use crate::models::{journal::JournalError, telemetry_store::TelemetryStore};
+
+#[cfg(feature = "control-socket")]
+use super::control_socket::request;
+
+/// Task: Latch alarms raised by other tasks, faults reported by the firmware
+/// and watchdog or brown-out resets reported when a device connects. Every
+/// change is journaled and persisted to `alarms_path`, so alarms survive
+/// restarts until they are acknowledged.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_latch_alarms(
+    token: CancellationToken,
+    alarms_path: impl AsRef<Path>,
+    alarms: SharedAlarmLog,
+    mut rx_alarm_conditions: Receiver<AlarmCondition>,
+    mut rx_packets_from_hw: Receiver<AddressedPacket>,
+    tx_journal: Sender<JournalRecord>,
+) {
+    info!("Started.");
+
+    loop {
+        let now_ms = unix_time_ms(SystemTime::now());
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            Ok(condition) = rx_alarm_conditions.recv() => {
+                let changed = alarms
+                    .lock()
+                    .expect("Alarm log lock poisoned.")
+                    .update(condition, now_ms);
+                commit(alarms_path.as_ref(), &alarms, changed.as_slice(), &tx_journal);
+            },
+            Ok(packet) = rx_packets_from_hw.recv() => {
+                let kind = match packet.packet {
+                    Packet::ReportError(report) => {
+                        let Some(kind) = AlarmKind::from_firmware_error(report.error) else {
+                            continue;
+                        };
+                        kind
+                    }
+                    Packet::ReportDeviceInfo(info) => {
+                        let Some(kind) = AlarmKind::from_reset_cause(info.reset_cause) else {
+                            continue;
+                        };
+                        kind
+                    }
+                    _ => continue,
+                };
+                let changed = alarms
+                    .lock()
+                    .expect("Alarm log lock poisoned.")
+                    .occur(packet.device, kind, now_ms);
+                commit(alarms_path.as_ref(), &alarms, changed.as_slice(), &tx_journal);
+            },
+        }
+    }
+}
+
+/// Answer an alarm request from the control socket.
+#[cfg(feature = "control-socket")]
+pub fn handle_alarm_request(
+    alarms_path: &Path,
+    alarms: &SharedAlarmLog,
+    request: &ControlRequest,
+    tx_journal: &Sender<JournalRecord>,
+) -> ControlResponse {
+    let now_ms = unix_time_ms(SystemTime::now());
+    let acknowledged = {
+        let mut alarms = alarms.lock().expect("Alarm log lock poisoned.");
+        match request {
+            ControlRequest::ListAlarms => return ControlResponse::Alarms(alarms.alarms().to_vec()),
+            ControlRequest::AcknowledgeAlarm { id } => {
+                alarms.acknowledge(*id, now_ms).into_iter().collect()
+            }
+            ControlRequest::AcknowledgeAllAlarms => alarms.acknowledge_all(now_ms),
+            ControlRequest::EvaluateCurve { .. }
+            | ControlRequest::Explain { .. }
+            | ControlRequest::StateMachines { .. }
+            | ControlRequest::SetTelemetryRate { .. }
+            | ControlRequest::DaemonConfig
+            | ControlRequest::DeviceLogs => {
+                return ControlResponse::Error("Not an alarm request.".to_string())
+            }
+            #[cfg(feature = "simulate")]
+            ControlRequest::SimulateFault { .. } => {
+                return ControlResponse::Error("Not an alarm request.".to_string())
+            }
+        }
+    };
+    commit(alarms_path, alarms, &acknowledged, tx_journal);
+    ControlResponse::Acknowledged(acknowledged)
+}
+
+/// Log, journal and persist alarms which changed.
+fn commit(
+    alarms_path: &Path,
+    alarms: &SharedAlarmLog,
+    changed: &[Alarm],
+    tx_journal: &Sender<JournalRecord>,
+) {
+    if changed.is_empty() {
+        return;
+    }
+    let now_ms = unix_time_ms(SystemTime::now());
+    for alarm in changed {
+        if alarm.is_acknowledged() {
+            info!("Alarm acknowledged: {}", alarm.describe(now_ms));
+        } else if alarm.is_active() {
+            error!("Alarm raised: {}", alarm.describe(now_ms));
+        } else {
+            warn!(
+                "Alarm cleared, latched until acknowledged: {}",
+                alarm.describe(now_ms)
+            );
+        }
+        // NOTE: Nothing journals without the `recording` feature.
+        let _ = tx_journal.send(JournalRecord::Alarm(*alarm));
+    }
+
+    let alarms = alarms.lock().expect("Alarm log lock poisoned.").clone();
+    if let Err(e) = alarms.save(alarms_path) {
+        error!("Failed to persist alarms. Error: {}", e);
+    }
+}
+
+/// Handle `alarms`: list the latched alarms or acknowledge them through the
+/// running control system's control socket. Listing falls back to the alarms
+/// persisted at `alarms_path` if the control system isn't running.
+#[cfg(feature = "control-socket")]
+pub fn handle_alarms_command(
+    socket_path: &Path,
+    alarms_path: &Path,
+    command: AlarmsCommand,
+) -> Result<(), Error> {
+    let response = match request(socket_path, &command.into()) {
+        Ok(response) => response,
+        Err(e) if command == AlarmsCommand::List => {
+            println!(
+                "Control system isn't reachable at {}, listing persisted alarms. Error: {}",
+                socket_path.display(),
+                e
+            );
+            ControlResponse::Alarms(
+                AlarmLog::load(alarms_path)
+                    .map_err(ConfigError::from)?
+                    .alarms()
+                    .to_vec(),
+            )
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let now_ms = unix_time_ms(SystemTime::now());
+    match response {
+        ControlResponse::Alarms(alarms) if alarms.is_empty() => println!("No latched alarms."),
+        ControlResponse::Alarms(alarms) => {
+            for alarm in alarms {
+                println!("{}", alarm.describe(now_ms));
+            }
+        }
+        ControlResponse::Acknowledged(alarms) if alarms.is_empty() => {
+            println!("Nothing to acknowledge.")
+        }
+        ControlResponse::Acknowledged(alarms) => {
+            for alarm in alarms {
+                println!("Acknowledged {}", alarm.describe(now_ms));
+            }
+        }
+        response => return Err(ControlError::from_response(response).into()),
+    }
+    Ok(())
+}
+
+/// Handle `alarms history`: print how often every alarm counted in `store`
+/// has occurred, most frequent first. Read straight from the store, so it
+/// works whether or not the control system is running.
+#[cfg(feature = "recording")]
+pub fn handle_alarm_history_command(store: &dyn TelemetryStore) -> Result<(), JournalError> {
+    let mut history = store.alarm_history()?;
+    if history.is_empty() {
+        println!("No alarms have occurred.");
+        return Ok(());
+    }
+    history.sort_by_key(|occurrences| std::cmp::Reverse(occurrences.count));
+    let now_ms = unix_time_ms(SystemTime::now());
+    for occurrences in history {
+        println!("{}", occurrences.describe(now_ms));
+    }
+    Ok(())
+}
@@ -0,0 +1,515 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use common::codec::PacketCodec;
+use common::packet::{
+    EchoPacket, EnterBootloaderPacket, GetParameterPacket, Packet, Parameter, ParameterKind,
+    PingPacket, PongPacket, ReadConfigPacket, RebootPacket, ReportConfigPacket,
+    RequestConnectionPacket, RequestDeviceInfoPacket, RequestRawAdcPacket, RequestSensorsPacket,
+    SetEchoModePacket, SetParameterPacket, WriteConfigPacket, ECHO_PAYLOAD_LEN,
+};
+use serialport::SerialPort;
+use tokio_util::sync::CancellationToken;
+
+use super::task::{find_client_ports, read_packets_from_port, write_packet_to_port};
+use crate::error::{CommError, ConfigError, Error};
+use crate::models::{
+    capabilities::HardwareExpectations,
+    client_sensor_data::ClientSensorData,
+    device_id::DeviceId,
+    device_registry::{DeviceRegistry, DEVICE_REGISTRY_PATH},
+    firmware_config::{describe_config, ConfigOptions},
+    injection::SendOptions,
+    latency::{LatencySummary, PingOptions},
+    link::LinkConfig,
+    link_qualification::{EchoOptions, EchoOutcome, QualificationReport},
+    parameter_sync::{
+        diff_parameters, host_parameters, pull_changes, pull_into_record, push_changes,
+        ParamsCommand, ParamsOptions,
+    },
+    reboot::RebootOptions,
+    sensor_status::{describe_raw_adc, StatusOptions},
+    startup_check::DeviceProbe,
+    temperature::TemperatureUnit,
+    timings::{Timings, FIRMWARE_LOOP_ENV_VAR},
+};
+
+/// How long a read may block while waiting for the handshake.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often the port is checked for a reply. Short so the measured round trip
+/// is dominated by the link and firmware rather than this loop.
+const REPLY_POLL_INTERVAL: Duration = Duration::from_micros(250);
+
+/// Open the port at `port_name`, or the first connected device's port.
+fn open_device_port(port_name: &Option<String>) -> Result<Box<dyn SerialPort>, CommError> {
+    let (_, port) = open_device(port_name)?;
+    Ok(port)
+}
+
+/// Open the port at `port_name`, or the first connected device's port, along
+/// with which device it is. The device is `None` if nothing connected was
+/// found on the given port.
+fn open_device(
+    port_name: &Option<String>,
+) -> Result<(Option<DeviceId>, Box<dyn SerialPort>), CommError> {
+    let mut ports = find_client_ports(CancellationToken::new()).into_iter();
+    let (device, port_name) = match port_name {
+        Some(port_name) => (
+            ports
+                .find(|(_, port_info)| port_info.port_name == *port_name)
+                .map(|(device, _)| device),
+            port_name.clone(),
+        ),
+        None => {
+            let (device, port_info) = ports.next().ok_or(CommError::NoDevice)?;
+            println!("Using {} on {}.", device, port_info.port_name);
+            (Some(device), port_info.port_name)
+        }
+    };
+
+    let port = serialport::new(&port_name, LinkConfig::from_env().baud_rate)
+        .timeout(READ_TIMEOUT)
+        .open()?;
+    Ok((device, port))
+}
+
+/// Handle `send`: open the device, handshake by requesting its device info
+/// and write a single packet. Lets firmware be driven without running the
+/// control system.
+pub fn send_packet_to_device(options: &SendOptions) -> Result<(), CommError> {
+    let mut port = open_device_port(&options.port)?;
+    let mut codec = PacketCodec::new();
+
+    write_packet_to_port(
+        &mut port,
+        Packet::RequestDeviceInfo(RequestDeviceInfoPacket),
+    )?;
+    let deadline = Instant::now() + options.handshake_timeout;
+    let mut capabilities = None;
+    while capabilities.is_none() && Instant::now() < deadline {
+        capabilities = read_packets_from_port(&mut port, &mut codec)?
+            .into_iter()
+            .find_map(|packet| match packet {
+                Packet::ReportDeviceInfo(info) => Some(info.capabilities),
+                _ => None,
+            });
+        std::thread::sleep(READ_TIMEOUT);
+    }
+    match capabilities {
+        Some(capabilities) => println!("Device capabilities: {:?}", capabilities),
+        None => println!(
+            "Device didn't report its capabilities within {:?}. Sending anyway.",
+            options.handshake_timeout
+        ),
+    }
+
+    let length = write_packet_to_port(&mut port, options.packet.clone())?;
+    port.flush()?;
+    println!("Sent {:?} ({} bytes).", options.packet, length);
+    Ok(())
+}
+
+/// Handle `ping`: send pings to the device one at a time and report the
+/// distribution of round trip times. Includes up to one firmware core loop
+/// period of waiting for the ping to be processed.
+pub fn ping_device(options: &PingOptions) -> Result<(), CommError> {
+    let mut port = open_device_port(&options.device.port)?;
+    let mut codec = PacketCodec::new();
+
+    // NOTE: Random so pongs to an earlier run still in flight don't match.
+    let first_nonce: u32 = rand::random();
+    let mut samples = Vec::with_capacity(options.count as usize);
+    for nonce in (0..options.count).map(|i| first_nonce.wrapping_add(i)) {
+        let sent_at = Instant::now();
+        write_packet_to_port(&mut port, Packet::Ping(PingPacket { nonce }))?;
+
+        let round_trip = loop {
+            if sent_at.elapsed() >= options.device.timeout {
+                break None;
+            }
+            let packets = read_packets_from_port(&mut port, &mut codec)?;
+            if packets.contains(&Packet::Pong(PongPacket { nonce })) {
+                break Some(sent_at.elapsed());
+            }
+            std::thread::sleep(REPLY_POLL_INTERVAL);
+        };
+
+        match round_trip {
+            Some(round_trip) => {
+                println!("Pong {}: {:?}", nonce, round_trip);
+                samples.push(round_trip);
+            }
+            None => println!("Ping {} lost after {:?}.", nonce, options.device.timeout),
+        }
+    }
+
+    match LatencySummary::from_samples(options.count as usize, &samples) {
+        Some(summary) => println!("Round trip: {}", summary),
+        None => println!("No pings were answered. Is the firmware up to date?"),
+    }
+    Ok(())
+}
+
+/// Handle `echo`: put the device in echo mode and send it random payloads as
+/// fast as it replies, checking each comes back intact. Qualifies cables and
+/// hubs without engaging the actuators, which hold their last targets.
+pub fn qualify_link(options: &EchoOptions) -> Result<(), CommError> {
+    let mut port = open_device_port(&options.device.port)?;
+    let mut codec = PacketCodec::new();
+    write_packet_to_port(
+        &mut port,
+        Packet::SetEchoMode(SetEchoModePacket { enabled: true }),
+    )?;
+
+    let mut report = QualificationReport::default();
+    for _ in 0..options.count {
+        let sent = EchoPacket::new(rand::random::<[u8; ECHO_PAYLOAD_LEN]>());
+        let sent_at = Instant::now();
+        write_packet_to_port(&mut port, Packet::Echo(sent))?;
+
+        let outcome = loop {
+            if sent_at.elapsed() >= options.device.timeout {
+                break EchoOutcome::Lost;
+            }
+            // NOTE: Sensor reports queued before echo mode started are skipped.
+            let reply = read_packets_from_port(&mut port, &mut codec)?
+                .into_iter()
+                .find_map(|packet| match packet {
+                    Packet::EchoReply(reply) => Some(reply),
+                    _ => None,
+                });
+            if let Some(reply) = reply {
+                break EchoOutcome::classify(&sent, &reply);
+            }
+            std::thread::sleep(REPLY_POLL_INTERVAL);
+        };
+        if outcome != EchoOutcome::Intact {
+            println!("Echo {}: {:?}", report.sent(), outcome);
+        }
+        report.record(outcome);
+    }
+
+    write_packet_to_port(
+        &mut port,
+        Packet::SetEchoMode(SetEchoModePacket { enabled: false }),
+    )?;
+    println!("Link qualification: {}", report);
+    if !report.passed() {
+        return Err(CommError::LinkQualification);
+    }
+    Ok(())
+}
+
+/// Handle `params`: read the device's settings and compare them to the host
+/// config, then pull the differences into the device registry or push them
+/// to the device once confirmed.
+pub fn sync_parameters(options: &ParamsOptions) -> Result<(), Error> {
+    let (device, mut port) = open_device(&options.device.port)?;
+    let device = device.ok_or(CommError::UnknownDevice)?;
+    let firmware = read_parameters(&mut port, options.device.timeout)?;
+
+    let registry_path = PathBuf::from(DEVICE_REGISTRY_PATH);
+    let mut registry = DeviceRegistry::load(&registry_path).map_err(ConfigError::from)?;
+    let host = host_parameters(
+        &registry.record(device.as_str()),
+        &Timings::from_env(),
+        &HardwareExpectations::from_env(),
+    );
+    let diffs = diff_parameters(&host, &firmware);
+    for diff in &diffs {
+        println!("{}", diff);
+    }
+
+    match options.command {
+        ParamsCommand::Diff => {}
+        ParamsCommand::Push => {
+            let changes = push_changes(&diffs);
+            if changes.is_empty() {
+                println!("The firmware already matches the host config.");
+                return Ok(());
+            }
+            let prompt = format!("Write {} setting(s) to {}?", changes.len(), device);
+            if !confirm(&prompt, options.yes)? {
+                return Ok(());
+            }
+            for parameter in changes {
+                write_packet_to_port(
+                    &mut port,
+                    Packet::SetParameter(SetParameterPacket { parameter }),
+                )?;
+            }
+            port.flush().map_err(CommError::from)?;
+            println!("Pushed.");
+        }
+        ParamsCommand::Pull => {
+            let changes = pull_changes(&diffs);
+            if changes.is_empty() {
+                println!("The host config already matches the firmware.");
+                return Ok(());
+            }
+            let prompt = format!("Copy {} setting(s) from {}?", changes.len(), device);
+            if !confirm(&prompt, options.yes)? {
+                return Ok(());
+            }
+            let record = registry.record_mut(device.as_str());
+            for parameter in changes {
+                if !pull_into_record(record, parameter) {
+                    println!(
+                        "Set {}={} to pull the {}.",
+                        FIRMWARE_LOOP_ENV_VAR,
+                        parameter.value(),
+                        parameter.kind()
+                    );
+                }
+            }
+            registry.save(&registry_path).map_err(ConfigError::from)?;
+            println!("Pulled into {}.", registry_path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Handle `status`: ask the device for a sensor report right away rather
+/// than waiting for the next telemetry period, and print it validated the
+/// same way the control system does.
+pub fn report_sensor_status(options: &StatusOptions) -> Result<(), CommError> {
+    let (device, mut port) = open_device(&options.port)?;
+    let device = device.ok_or(CommError::UnknownDevice)?;
+    let mut codec = PacketCodec::new();
+    write_packet_to_port(&mut port, Packet::RequestSensors(RequestSensorsPacket))?;
+
+    let deadline = Instant::now() + options.timeout;
+    let report = loop {
+        if Instant::now() >= deadline {
+            return Err(CommError::NoReply("its sensors", options.timeout));
+        }
+        let report = read_packets_from_port(&mut port, &mut codec)?
+            .into_iter()
+            .find_map(|packet| match packet {
+                Packet::ReportSensors(report) => Some(report),
+                _ => None,
+            });
+        if let Some(report) = report {
+            break report;
+        }
+        std::thread::sleep(REPLY_POLL_INTERVAL);
+    };
+
+    let data = ClientSensorData::try_from((device, report))?;
+    println!("{}", data.describe(TemperatureUnit::from_env()));
+    if !data.quality.is_good() {
+        println!("Some readings are implausible. Quality: {}", data.quality);
+    }
+    Ok(())
+}
+
+/// Handle `rawadc`: ask the device for its sense inputs exactly as read,
+/// alongside the sensor report they turn into. For telling a wiring or ADC
+/// fault from a bad sense curve when the reported speeds look wrong.
+pub fn report_raw_adc(options: &StatusOptions) -> Result<(), CommError> {
+    let (device, mut port) = open_device(&options.port)?;
+    let device = device.ok_or(CommError::UnknownDevice)?;
+    let mut codec = PacketCodec::new();
+    write_packet_to_port(&mut port, Packet::RequestRawAdc(RequestRawAdcPacket))?;
+    write_packet_to_port(&mut port, Packet::RequestSensors(RequestSensorsPacket))?;
+
+    let deadline = Instant::now() + options.timeout;
+    let mut raw = None;
+    let mut report = None;
+    loop {
+        for packet in read_packets_from_port(&mut port, &mut codec)? {
+            match packet {
+                Packet::ReportRawAdc(packet) => raw = Some(packet),
+                Packet::ReportSensors(packet) => report = Some(packet),
+                _ => {}
+            }
+        }
+        if let (Some(raw), Some(report)) = (&raw, &report) {
+            println!("{}", describe_raw_adc(raw));
+            let data = ClientSensorData::try_from((device, report.clone()))?;
+            println!(
+                "Reported as: {}",
+                data.describe(TemperatureUnit::from_env())
+            );
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(CommError::NoReply("its raw ADC readings", options.timeout));
+        }
+        std::thread::sleep(REPLY_POLL_INTERVAL);
+    }
+}
+
+/// Handle `config`: read the device's firmware configuration and, once
+/// confirmed, write any changes to it. Prints the configuration the device
+/// applied and whether it managed to persist it.
+pub fn configure_device(options: &ConfigOptions) -> Result<(), Error> {
+    let (device, mut port) = open_device(&options.device.port)?;
+    let device = device.ok_or(CommError::UnknownDevice)?;
+    let mut codec = PacketCodec::new();
+    write_packet_to_port(&mut port, Packet::ReadConfig(ReadConfigPacket))?;
+    let mut report = read_config(&mut port, &mut codec, options.device.timeout)?;
+
+    let config = options.changes.apply(report.config);
+    if config != report.config {
+        println!("{}", describe_config(&report.config, report.persisted));
+        if !confirm(
+            &format!("Write the configuration to {}?", device),
+            options.yes,
+        )? {
+            return Ok(());
+        }
+        write_packet_to_port(&mut port, Packet::WriteConfig(WriteConfigPacket { config }))?;
+        report = read_config(&mut port, &mut codec, options.device.timeout)?;
+        println!("Written.");
+    }
+    println!("{}", describe_config(&report.config, report.persisted));
+    Ok(())
+}
+
+/// Wait for the device to report its configuration.
+fn read_config(
+    port: &mut Box<dyn SerialPort>,
+    codec: &mut PacketCodec,
+    timeout: Duration,
+) -> Result<ReportConfigPacket, CommError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        for packet in read_packets_from_port(port, codec)? {
+            if let Packet::ReportConfig(report) = packet {
+                return Ok(report);
+            }
+        }
+        if Instant::now() >= deadline {
+            return Err(CommError::NoReply("its configuration", timeout));
+        }
+        std::thread::sleep(REPLY_POLL_INTERVAL);
+    }
+}
+
+/// Handle `reboot`: reset the device once confirmed, parking its pump and
+/// fan at the fail-safe duty first. Recovers a wedged board without unplugging it.
+pub fn reboot_device(options: &RebootOptions) -> Result<(), Error> {
+    if let Some(device) = send_reset(options, Packet::Reboot(RebootPacket), "Reboot")? {
+        println!(
+            "Sent reboot. {} comes back reporting a software reset.",
+            device
+        );
+    }
+    Ok(())
+}
+
+/// Handle `bootloader`: reset the device into its bootloader once confirmed,
+/// parking its pump and fan at the fail-safe duty first. Lets new firmware be flashed
+/// without pressing the reset button.
+pub fn enter_bootloader(options: &RebootOptions) -> Result<(), Error> {
+    let packet = Packet::EnterBootloader(EnterBootloaderPacket);
+    if let Some(device) = send_reset(options, packet, "Enter the bootloader on")? {
+        println!(
+            "Sent. {} is in its bootloader, ready to be flashed.",
+            device
+        );
+    }
+    Ok(())
+}
+
+/// Send a reset `packet` to the device once `action` on it is confirmed.
+/// Returns which device it was sent to, or `None` if it wasn't confirmed.
+fn send_reset(
+    options: &RebootOptions,
+    packet: Packet,
+    action: &str,
+) -> Result<Option<DeviceId>, Error> {
+    let (device, mut port) = open_device(&options.port)?;
+    let device = device.ok_or(CommError::UnknownDevice)?;
+    if !confirm(&format!("{} {}?", action, device), options.yes)? {
+        return Ok(None);
+    }
+    write_packet_to_port(&mut port, packet)?;
+    port.flush().map_err(CommError::from)?;
+    Ok(Some(device))
+}
+
+/// Handshake with the device at `port_name`, or the first connected device,
+/// for `check`: request a connection and its device info, and collect
+/// whatever it replies within `timeout`.
+pub fn probe_device(
+    port_name: &Option<String>,
+    timeout: Duration,
+) -> Result<DeviceProbe, CommError> {
+    let mut port = open_device_port(port_name)?;
+    let mut codec = PacketCodec::new();
+    write_packet_to_port(&mut port, RequestConnectionPacket::new_packet())?;
+    write_packet_to_port(
+        &mut port,
+        Packet::RequestDeviceInfo(RequestDeviceInfoPacket),
+    )?;
+
+    let deadline = Instant::now() + timeout;
+    let mut probe = DeviceProbe::default();
+    while (probe.protocol_version.is_none() || probe.capabilities.is_none())
+        && Instant::now() < deadline
+    {
+        for packet in read_packets_from_port(&mut port, &mut codec)? {
+            match packet {
+                Packet::AcceptConnection(accept) => {
+                    probe.protocol_version = Some(accept.protocol_version)
+                }
+                Packet::ReportDeviceInfo(info) => probe.capabilities = Some(info.capabilities),
+                _ => {}
+            }
+        }
+        std::thread::sleep(REPLY_POLL_INTERVAL);
+    }
+    Ok(probe)
+}
+
+/// Ask the device for every setting, and collect those it reports within
+/// `timeout`.
+fn read_parameters(
+    port: &mut Box<dyn SerialPort>,
+    timeout: Duration,
+) -> Result<Vec<Parameter>, CommError> {
+    let mut codec = PacketCodec::new();
+    for kind in ParameterKind::ALL {
+        write_packet_to_port(port, Packet::GetParameter(GetParameterPacket { kind }))?;
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut parameters: Vec<Parameter> = Vec::new();
+    while parameters.len() < ParameterKind::ALL.len() && Instant::now() < deadline {
+        for packet in read_packets_from_port(port, &mut codec)? {
+            if let Packet::ReportParameter(report) = packet {
+                if parameters
+                    .iter()
+                    .all(|known| known.kind() != report.parameter.kind())
+                {
+                    parameters.push(report.parameter);
+                }
+            }
+        }
+        std::thread::sleep(REPLY_POLL_INTERVAL);
+    }
+    if parameters.is_empty() {
+        return Err(CommError::NoReply("its settings", timeout));
+    }
+    Ok(parameters)
+}
+
+/// Ask `prompt` on stdin, unless `yes` already confirms it. Returns whether
+/// the answer was yes.
+fn confirm(prompt: &str, yes: bool) -> Result<bool, Error> {
+    if yes {
+        return Ok(true);
+    }
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush().map_err(Error::Prompt)?;
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(Error::Prompt)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
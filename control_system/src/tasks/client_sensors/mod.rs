@@ -1 +1,2 @@
+pub mod injection;
 pub mod task;
@@ -1,70 +1,119 @@
-use anyhow::Result;
 use futures::StreamExt;
-use serialport::{SerialPort, SerialPortInfo};
-use std::{fmt::write, time::Duration};
+use serialport::{ClearBuffer, SerialPort, SerialPortInfo};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::write,
+    time::{Duration, Instant},
+};
 use tokio::{
     select,
-    sync::broadcast::{Receiver, Sender},
+    sync::broadcast::{error::TryRecvError, Receiver, Sender},
+    task::JoinHandle,
 };
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
-use tracing::{debug, error, info, instrument, trace, warn};
+use tracing::{debug, debug_span, error, info, instrument, trace, warn};
 
+use crate::error::CommError;
+#[cfg(feature = "simulate")]
+use crate::models::fault_simulation::{PlantFaultSimulator, SharedPlantFaults};
 use crate::models::{
+    addressed_packet::AddressedPacket,
+    alarm::{AlarmCondition, AlarmKind},
+    capabilities::{HardwareExpectations, DEVICE_INFO_TIMEOUT},
     client_sensor_data::{self, ClientSensorData},
+    control_acks::{ControlAcks, ACK_TIMEOUT},
     control_event::ControlEvent,
+    control_generation::GenerationFilter,
+    device_id::DeviceId,
+    fault_injection::{FaultInjection, FaultInjector},
+    firmware_stats::check_stats,
+    flow_interlock::{LowFlowInterlock, LowFlowPolicy},
+    heartbeat::{Heartbeat, HEARTBEAT_INTERVAL},
+    journal::JournalRecord,
+    link::{LinkConfig, LinkStats, NoDataWatchdog},
+    outgoing_queue::OutgoingQueue,
+    temperature::TemperatureUnit,
+    timings::Timings,
+    valve_model::InferredValve,
+    valve_policy::{is_valve_assumed, UnknownValveGuard, ValvePolicy},
+    write_batch::WriteBatch,
+    write_pacing::{link_capacity, WritePacer},
 };
 
+use common::codec::{encode_packet, PacketCodec};
+use common::framing::MAX_ENCODED_FRAME_LEN;
 use common::packet::*;
 
 const PRODUCT_NAME: &str = "Too Hot To Prandtl Controller";
-const SERIAL_NUMBER: &str = "1324";
+
+/// How often each device's link stats are logged and journaled.
+const LINK_STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a newly opened device has to accept the connection before it is
+/// run without a protocol version check.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often a heartbeat is sent to each device, so its firmware can tell a
+/// quiet link from a lost one.
+const HEARTBEAT_PERIOD: Duration = Duration::from_millis(1000 / HEARTBEAT_RATE_HZ as u64);
+
+/// Why a client communication task exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientTaskExit {
+    /// Communication was lost or the task was cancelled. The device will be
+    /// reconnected if it is rediscovered.
+    Disconnected,
+
+    /// The device speaks a different protocol version, or failed the startup
+    /// self-check in strict mode. It won't be reconnected until the control
+    /// system restarts.
+    Refused,
+}
 
 /// Check if a port is for the embedded hardware.
-/// Checks both the serial number and product name of the port.
+/// Checks the product name of the port and returns the `DeviceId` derived
+/// from its serial number.
 #[instrument(skip_all)]
-fn is_port_for_embedded_hardware(token: CancellationToken, port: SerialPortInfo) -> bool {
+fn device_id_for_port(token: CancellationToken, port: &SerialPortInfo) -> Option<DeviceId> {
     if token.is_cancelled() {
         warn!("Trying to request connection for a port but the token is cancelled. Aborting.");
-        return false;
+        return None;
     }
     trace!("Checking port '{}'.", port.port_name);
 
-    match port.port_type {
+    match &port.port_type {
         serialport::SerialPortType::UsbPort(usb_info) => {
-            if let Some(serial_number) = usb_info.serial_number {
-                if serial_number != SERIAL_NUMBER {
-                    debug!("Wrong serial number!");
-                    return false;
-                }
-            } else {
-                debug!("Failed to get serial number from port.");
-                return false;
-            }
-            if let Some(product_name) = usb_info.product {
+            if let Some(product_name) = &usb_info.product {
                 if product_name != PRODUCT_NAME {
                     debug!("Wrong product name!");
-                    return false;
+                    return None;
                 }
             } else {
                 debug!("Failed to get product name from port.");
-                return false;
+                return None;
+            }
+            if let Some(serial_number) = &usb_info.serial_number {
+                debug!("This port is a client port for device '{}'.", serial_number);
+                Some(DeviceId::new(serial_number))
+            } else {
+                debug!("Failed to get serial number from port.");
+                None
             }
         }
         _ => {
             debug!("Wrong port type.");
-            return false;
+            None
         }
     }
-    debug!("This port is the correct client port.");
-    true
 }
 
+/// Find every port which belongs to embedded hardware.
 #[instrument(skip_all)]
-fn find_client_port(token: CancellationToken) -> Option<SerialPortInfo> {
+pub(super) fn find_client_ports(token: CancellationToken) -> Vec<(DeviceId, SerialPortInfo)> {
     let ports = match serialport::available_ports() {
         Err(e) => {
             error!("Failed to get any ports! Error: {}", e);
-            return None;
+            return vec![];
         }
         Ok(ports) => ports,
     };
@@ -73,177 +122,830 @@ fn find_client_port(token: CancellationToken) -> Option<SerialPortInfo> {
 
     ports
         .into_iter()
-        .filter_map(|port| {
-            if is_port_for_embedded_hardware(token.clone(), port.clone()) {
-                Some(port)
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<SerialPortInfo>>()
-        .first()
-        .map(|x| x.clone())
-}
-
-#[instrument(skip_all)]
-async fn wait_for_client_port(token: CancellationToken) -> Result<SerialPortInfo, String> {
-    loop {
-        if token.is_cancelled() {
-            warn!("Token was cancelled.");
-            return Err("Cancelled".into());
-        }
-        trace!("Looking for client port.");
-        if let Some(port_name) = find_client_port(token.clone()) {
-            return Ok(port_name);
-        }
-        trace!("Sleeping briefly before checking again.");
-        tokio::time::sleep(Duration::from_millis(500)).await;
-    }
+        .filter_map(|port| device_id_for_port(token.clone(), &port).map(|device| (device, port)))
+        .collect()
 }
 
+/// This task discovers embedded hardware and runs one client communication
+/// task per connected device. Devices which disconnect are rediscovered and
+/// their communication task restarted, unless they were refused by the
+/// startup self-check. Newly connected devices are sent the configured
+/// firmware core loop period, if any, and asked to batch sensor samples if
+/// `link_config.sensor_batching` is set. Beats `heartbeat` every scan.
+#[allow(clippy::too_many_arguments)]
 pub async fn task_lifetime_management_of_client_communication_task(
     token: CancellationToken,
-    tx_packets_from_hw: Sender<Packet>,
-    tx_packets_to_hw: Sender<Packet>,
+    tx_packets_from_hw: Sender<AddressedPacket>,
+    tx_packets_to_hw: Sender<AddressedPacket>,
+    link_config: LinkConfig,
+    fault_injection: FaultInjection,
+    #[cfg(feature = "simulate")] plant_faults: Option<SharedPlantFaults>,
+    timings: Timings,
+    expectations: HardwareExpectations,
+    tx_journal: Sender<JournalRecord>,
+    heartbeat: Heartbeat,
 ) {
     info!("Started");
 
+    let mut connections: HashMap<DeviceId, JoinHandle<ClientTaskExit>> = HashMap::new();
+    let mut refused: HashSet<DeviceId> = HashSet::new();
+
     loop {
-        debug!("About to start client communication task.");
-        let tx_packets_from_hw_clone = tx_packets_from_hw.clone();
-        task_handle_client_communication(
-            token.clone(),
-            tx_packets_from_hw_clone.clone(),
-            tx_packets_to_hw.subscribe(),
-        )
-        .await;
-        warn!("Client communication task exited.");
-
-        if token.is_cancelled() {
-            warn!("Cancelled.");
-            break;
+        heartbeat.beat();
+
+        let finished: Vec<DeviceId> = connections
+            .iter()
+            .filter(|(_, handle)| handle.is_finished())
+            .map(|(device, _)| *device)
+            .collect();
+        for device in finished {
+            let Some(handle) = connections.remove(&device) else {
+                continue;
+            };
+            match handle.await {
+                Ok(ClientTaskExit::Refused) => {
+                    error!(
+                        "{} was refused by the self-check and won't be reconnected.",
+                        device
+                    );
+                    refused.insert(device);
+                }
+                Ok(ClientTaskExit::Disconnected) => {
+                    warn!("Client communication task for {} exited.", device)
+                }
+                Err(e) => error!(
+                    "Client communication task for {} failed. Error: {}",
+                    device, e
+                ),
+            }
+        }
+
+        trace!("Looking for client ports.");
+        for (device, port_info) in find_client_ports(token.clone()) {
+            if connections.contains_key(&device) || refused.contains(&device) {
+                continue;
+            }
+            info!("Starting client communication task for {}.", device);
+            let handle = tokio::spawn(task_handle_client_communication(
+                token.clone(),
+                device,
+                port_info,
+                link_config,
+                fault_injection,
+                #[cfg(feature = "simulate")]
+                plant_faults.clone(),
+                timings,
+                expectations,
+                tx_packets_from_hw.clone(),
+                tx_packets_to_hw.subscribe(),
+                tx_journal.clone(),
+            ));
+            connections.insert(device, handle);
+
+            // NOTE: Sent after spawning so the new task has already subscribed.
+            //       The firmware only writes its flash when a parameter changes,
+            //       so resending them on every connect doesn't wear it.
+            if let Some(period_ms) = timings.firmware_core_loop_ms {
+                let packet = Packet::SetParameter(SetParameterPacket {
+                    parameter: Parameter::CoreLoopPeriodMs(period_ms),
+                });
+                if let Err(e) = tx_packets_to_hw.send(AddressedPacket::new(device, packet)) {
+                    warn!("Failed to queue firmware core loop period. Error: {}", e);
+                }
+            }
+            if let Some(pulses) = expectations.flow_pulses_per_liter {
+                let packet = Packet::SetParameter(SetParameterPacket {
+                    parameter: Parameter::FlowPulsesPerLiter(pulses),
+                });
+                if let Err(e) = tx_packets_to_hw.send(AddressedPacket::new(device, packet)) {
+                    warn!("Failed to queue flow meter pulses per liter. Error: {}", e);
+                }
+            }
+            if link_config.sensor_batching {
+                let packet = Packet::SetSensorBatching(SetSensorBatchingPacket { enabled: true });
+                if let Err(e) = tx_packets_to_hw.send(AddressedPacket::new(device, packet)) {
+                    warn!("Failed to queue sensor batching. Error: {}", e);
+                }
+            }
+        }
+
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            _ = tokio::time::sleep(timings.device_scan) => {}
+        };
+    }
+
+    for (device, handle) in connections {
+        if let Err(e) = handle.await {
+            error!(
+                "Client communication task for {} failed. Error: {}",
+                device, e
+            );
         }
-        info!("Restarting client communication task.");
     }
 }
 
-/// This task handles finding, opening, and sending/receiving packets with
-/// the embedded hardware. This task polls to determine when packets are available
-/// to read. If not currently reading, it will send packets as they're queued for
-/// sending. If communication is lost the task will restart.
-#[tracing::instrument(skip_all)]
+/// This task handles opening and sending/receiving packets with a single
+/// embedded hardware device. This task polls to determine when packets are
+/// available to read. If not currently reading, it will send packets addressed
+/// to its device as they're queued for sending. Packets which pile up while a
+/// write is stalled are coalesced so only the freshest of each kind is sent,
+/// and control frames from a superseded generation are dropped. Packets
+/// queued together are written in batches of up to `MAX_WRITE_BATCH_BYTES`.
+/// The port is opened at the configured baud rate and the rate the port
+/// actually uses is reported in the link stats, which are periodically
+/// logged and journaled.
+/// On connect the device is asked to accept the connection, and refused if
+/// its protocol version differs, since packets between the two could be
+/// mis-decoded. Packets which arrive before it accepts are dropped. Firmware
+/// which doesn't accept within `CONNECTION_TIMEOUT` is run unchecked.
+/// Once accepted the device is asked for its capabilities, which are checked
+/// against `expectations`. Mismatches are warned about, or refuse the device
+/// in strict mode. Firmware which doesn't reply within `DEVICE_INFO_TIMEOUT`
+/// is run unchecked.
+/// Each received packet is assigned the next sequence number, which is
+/// carried through to any control frame generated from it and recorded on
+/// spans as `sequence`.
+/// Heartbeats are sent to the device every `HEARTBEAT_PERIOD`, and those it
+/// sends back only feed the no-data watchdog.
+/// With fault simulation on, received sensor reports are rewritten as they
+/// would be under the plant fault simulated on the device in `plant_faults`.
+/// If communication is lost, or no valid packet arrives for
+/// `link_config.no_data_timeout`, the task will exit and close the port so
+/// the device can be rediscovered.
+#[tracing::instrument(skip_all, fields(device = %device))]
+#[allow(clippy::too_many_arguments)]
 pub async fn task_handle_client_communication(
     token: CancellationToken,
-    tx_packets_from_hw: Sender<Packet>,
-    mut rx_packets_to_hw: Receiver<Packet>,
-) {
-    info!("Started.");
+    device: DeviceId,
+    port_info: SerialPortInfo,
+    link_config: LinkConfig,
+    fault_injection: FaultInjection,
+    #[cfg(feature = "simulate")] plant_faults: Option<SharedPlantFaults>,
+    timings: Timings,
+    expectations: HardwareExpectations,
+    tx_packets_from_hw: Sender<AddressedPacket>,
+    mut rx_packets_to_hw: Receiver<AddressedPacket>,
+    tx_journal: Sender<JournalRecord>,
+) -> ClientTaskExit {
+    info!("Started. Port: {}", port_info.port_name);
 
-    trace!("Waiting on client port to be identified.");
-    let port_info = match wait_for_client_port(token.clone()).await {
-        Err(e) => {
-            warn!("Failed to wait for a client port. Cancelling. Error: {}", e);
-            // NOTE: MIGHT NOT NEED THIS CHECK.
-            if !token.is_cancelled() {
-                token.cancel();
-            }
-            return;
-        }
-        Ok(port_name) => port_name,
-    };
-    info!("Found a client port! Name: {}", port_info.port_name);
-
-    let mut port = match serialport::new(port_info.port_name, 9600)
+    let mut port = match serialport::new(port_info.port_name, link_config.baud_rate)
         .timeout(Duration::from_millis(1000))
         .open()
     {
         Err(e) => {
             error!("Failed to open port to prandtl controller. Error: {}", e);
-            token.cancel();
-            return;
+            return ClientTaskExit::Disconnected;
         }
         Ok(port) => port,
     };
 
-    loop {
-        let packets = match read_packets_from_port(&mut port) {
-            Ok(packets) => packets,
+    let mut stats = LinkStats {
+        effective_baud_rate: negotiated_baud_rate(port.as_ref(), link_config),
+        ..LinkStats::default()
+    };
+    let mut pacer = link_config.write_pacing.then(|| {
+        let bytes_per_second = link_capacity(stats.effective_baud_rate);
+        info!("Pacing writes to {} bytes/s.", bytes_per_second);
+        WritePacer::new(bytes_per_second, Instant::now())
+    });
+    let mut stats_interval = tokio::time::interval(LINK_STATS_INTERVAL);
+    let mut heartbeat_interval = tokio::time::interval(HEARTBEAT_PERIOD);
+    let mut next_sequence: u64 = 0;
+    let mut generations = GenerationFilter::default();
+    let mut faults = FaultInjector::new(fault_injection);
+    #[cfg(feature = "simulate")]
+    let mut simulator = PlantFaultSimulator::default();
+    let mut codec = PacketCodec::new();
+    let mut acks = ControlAcks::default();
+    let mut exit = ClientTaskExit::Disconnected;
+
+    let request = RequestConnectionPacket::new_packet();
+    match write_packet_to_port_with_faults(&mut port, request, &mut faults) {
+        Err(e) => {
+            stats.record_write_failure();
+            warn!("Failed to request connection! Error: {}", e);
+        }
+        Ok(length) => stats.record_sent(length),
+    }
+    // NOTE: Cleared once the connection is accepted or given up on.
+    let mut connection_deadline = Some(Instant::now() + CONNECTION_TIMEOUT);
+    // NOTE: Set once the connection is accepted or given up on, and cleared
+    //       once the self-check has run or been given up on.
+    let mut device_info_deadline = None;
+    let mut no_data_watchdog = NoDataWatchdog::new(link_config.no_data_timeout, Instant::now());
+
+    'communication: loop {
+        if let Some(stall) = faults.stall() {
+            warn!("Injecting a {:?} transport stall.", stall);
+            select! {
+                _ = token.cancelled() => {
+                    warn!("Cancelled.");
+                    break;
+                },
+                _ = tokio::time::sleep(stall) => {}
+            }
+            // NOTE: A stalled transport doesn't deliver what arrived meanwhile.
+            if let Err(e) = port.clear(ClearBuffer::Input) {
+                warn!(
+                    "Failed to discard bytes received during the stall. Error: {}",
+                    e
+                );
+            }
+        }
+
+        let packets = match read_packets_from_port_with_faults(&mut port, &mut codec, &mut faults) {
+            Ok(packets) => inject_incoming_faults(&mut faults, packets),
             Err(e) => {
                 error!("Failed to read packets from port. Error: {}", e);
                 break;
             }
         };
+        #[cfg(feature = "simulate")]
+        let packets = simulate_plant_faults(plant_faults.as_ref(), device, &mut simulator, packets);
+
+        stats.record_received(packets.len());
+        no_data_watchdog.record_packets(packets.len(), Instant::now());
+        if let Some(silence) = no_data_watchdog.starved_for(Instant::now()) {
+            error!(
+                "No valid packets received for {:?}. Reopening the port.",
+                silence
+            );
+            break;
+        }
 
         for packet in packets {
+            let sequence = next_sequence;
+            next_sequence += 1;
+            let _span = debug_span!("received", sequence).entered();
             debug!("Received Communication Packet: {:?}", packet);
 
+            if let Packet::Heartbeat(_) = &packet {
+                trace!("Received heartbeat.");
+                continue;
+            }
+
+            if let Packet::AcceptConnection(accept) = &packet {
+                if connection_deadline.take().is_some() {
+                    if !accepts_protocol(device, accept) {
+                        exit = ClientTaskExit::Refused;
+                        break 'communication;
+                    }
+                    device_info_deadline =
+                        Some(request_device_info(&mut port, &mut stats, &mut faults));
+                }
+                continue;
+            }
+            if connection_deadline.is_some() {
+                debug!("Dropping packet received before the connection was accepted.");
+                continue;
+            }
+
+            if let Packet::Ack(ack) = &packet {
+                record_ack(&mut acks, &mut stats, device, ack);
+            }
+
+            if let Packet::ReportDeviceInfo(info) = &packet {
+                if device_info_deadline.take().is_some() {
+                    journal_device_info(&tx_journal, device, info);
+                    log_settings_origin(device, info.settings);
+                    log_reset_cause(device, info.reset_cause);
+                    if !passes_self_check(&expectations, &info.capabilities) {
+                        exit = ClientTaskExit::Refused;
+                        break 'communication;
+                    }
+                }
+            }
+
+            let packet = AddressedPacket::new(device, packet).with_sequence(sequence);
             match tx_packets_from_hw.send(packet) {
                 Err(e) => warn!("Failed to send packet over queue. Error: {}", e),
                 Ok(_) => trace!("Successfully sent packet over queue."),
             }
         }
 
+        let unacked = acks.expire(Instant::now());
+        if unacked > 0 {
+            stats.record_unacked(unacked);
+            warn!(
+                "{} control frames went unacknowledged for {:?}.",
+                unacked, ACK_TIMEOUT
+            );
+        }
+
+        if connection_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            connection_deadline = None;
+            warn!(
+                "Device didn't accept the connection within {:?}. Running without a protocol version check.",
+                CONNECTION_TIMEOUT
+            );
+            device_info_deadline = Some(request_device_info(&mut port, &mut stats, &mut faults));
+        }
+
+        if device_info_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            device_info_deadline = None;
+            warn!(
+                "Device didn't report its capabilities within {:?}. Running without a self-check.",
+                DEVICE_INFO_TIMEOUT
+            );
+        }
+
         tokio::select! {
             _ = token.cancelled() => {
                 warn!("Cancelled.");
                 break;
             },
             Ok(data) = rx_packets_to_hw.recv() => {
-                debug!("Received packet to write to port. Packet: {:?}",data);
                 // NOTE: Received a packet TO SEND to hw
-                if let Err(e) = write_packet_to_port(&mut port, data) {
-                    warn!("Failed to write packet to port! Error: {}", e);
-                } else {
-                    debug!("Successfully wrote packet to port!");
+                let mut outgoing = OutgoingQueue::default();
+                queue_outgoing_packet(&mut outgoing, &mut generations, device, data);
+                drain_outgoing_packets(&mut outgoing, &mut generations, device, &mut rx_packets_to_hw);
+
+                let mut batch = WriteBatch::default();
+                while let Some(mut data) = outgoing.pop() {
+                    let frame = {
+                        let _span = debug_span!("send", sequence = data.sequence).entered();
+                        if !generations.is_current(&data) {
+                            debug!("Dropping control frame from superseded generation {:?}.", data.generation);
+                            continue;
+                        }
+                        // NOTE: Stamped before injected drops so they show up as missed.
+                        acks.stamp(&mut data.packet, Instant::now());
+                        if faults.drop_packet() {
+                            debug!("Injected a dropped outgoing packet. Packet: {:?}", data.packet);
+                            continue;
+                        }
+                        debug!("Batching packet for the port. Packet: {:?}", data.packet);
+                        match encode_frame_with_faults(&data.packet, &mut faults) {
+                            Err(_) => {
+                                stats.record_write_failure();
+                                continue;
+                            }
+                            Ok(frame) => frame,
+                        }
+                    };
+                    if !batch.fits(&frame) {
+                        write_batch_to_port(&mut port, &mut batch, &mut stats, &mut pacer).await;
+                        // NOTE: Pick up anything queued while the write was blocked.
+                        drain_outgoing_packets(&mut outgoing, &mut generations, device, &mut rx_packets_to_hw);
+                    }
+                    batch.push(&frame);
+                }
+                write_batch_to_port(&mut port, &mut batch, &mut stats, &mut pacer).await;
+            },
+            _ = heartbeat_interval.tick() => {
+                match write_packet_to_port_with_faults(&mut port, Packet::Heartbeat(HeartbeatPacket), &mut faults) {
+                    Err(e) => {
+                        stats.record_write_failure();
+                        warn!("Failed to send heartbeat! Error: {}", e);
+                    }
+                    Ok(length) => {
+                        stats.record_sent(length);
+                        record_paced_write(&mut pacer, length);
+                    }
                 }
             },
-            _ = tokio::time::sleep(Duration::from_millis(500)) => {}
+            _ = stats_interval.tick() => {
+                info!("Link stats: {}", stats);
+                journal_link_stats(&tx_journal, device, stats);
+                if faults.is_enabled() {
+                    info!("Injected faults: {}", faults.injected());
+                }
+            },
+            _ = tokio::time::sleep(timings.serial_poll) => {}
         };
     }
+
+    info!("Final link stats: {}", stats);
+    journal_link_stats(&tx_journal, device, stats);
+    if faults.is_enabled() {
+        info!("Final injected faults: {}", faults.injected());
+    }
+    exit
+}
+
+/// Rewrite received packets as they would be reported under the plant fault
+/// simulated on `device`, if fault simulation is on.
+#[cfg(feature = "simulate")]
+fn simulate_plant_faults(
+    plant_faults: Option<&SharedPlantFaults>,
+    device: DeviceId,
+    simulator: &mut PlantFaultSimulator,
+    packets: Vec<Packet>,
+) -> Vec<Packet> {
+    let Some(plant_faults) = plant_faults else {
+        return packets;
+    };
+    let fault = match plant_faults.lock() {
+        Ok(plant_faults) => plant_faults.get(device),
+        Err(e) => {
+            warn!("Failed to lock simulated faults. Error: {}", e);
+            return packets;
+        }
+    };
+    packets
+        .into_iter()
+        .filter_map(|packet| simulator.apply(fault, packet))
+        .collect()
+}
+
+/// Drop and spike received packets at the rates `faults` injects them.
+fn inject_incoming_faults(faults: &mut FaultInjector, packets: Vec<Packet>) -> Vec<Packet> {
+    packets
+        .into_iter()
+        .filter_map(|mut packet| {
+            if faults.drop_packet() {
+                debug!("Injected a dropped incoming packet. Packet: {:?}", packet);
+                return None;
+            }
+            if faults.spike(&mut packet) {
+                debug!("Injected a sensor spike. Packet: {:?}", packet);
+            }
+            Some(packet)
+        })
+        .collect()
+}
+
+/// Record an ack from the device, warning if it reports control frames
+/// missing from the sequence.
+fn record_ack(acks: &mut ControlAcks, stats: &mut LinkStats, device: DeviceId, ack: &AckPacket) {
+    let round_trip = acks.acknowledge(ack.sequence, Instant::now());
+    match round_trip {
+        Some(round_trip) => trace!(
+            "Control frame {} acknowledged after {:?}.",
+            ack.sequence,
+            round_trip
+        ),
+        None => debug!(
+            "Control frame {} was acknowledged after it stopped being awaited.",
+            ack.sequence
+        ),
+    }
+    let newly_missed = stats.record_ack(ack, round_trip.is_some());
+    if newly_missed > 0 {
+        warn!(
+            "{} reports {} more control frames missed, {} in total.",
+            device, newly_missed, ack.missed_control_frames
+        );
+    }
+}
+
+/// Check a device's capabilities against what the configuration expects,
+/// warning about every mismatch. Returns `false` if the device should be
+/// refused.
+fn passes_self_check(expectations: &HardwareExpectations, capabilities: &Capabilities) -> bool {
+    let mismatches = expectations.check(capabilities);
+    if mismatches.is_empty() {
+        info!("Self-check passed. Capabilities: {:?}", capabilities);
+        return true;
+    }
+    for mismatch in &mismatches {
+        warn!("Self-check mismatch. {}", mismatch);
+    }
+    if expectations.strict {
+        error!("Refusing to run a device which failed the self-check.");
+        return false;
+    }
+    warn!("Running degraded. The firmware will ignore control of missing hardware.");
+    true
+}
+
+/// Log where a device loaded its settings from, warning if any bank failed its
+/// CRC check since the newest settings may have been lost.
+fn log_settings_origin(device: DeviceId, origin: SettingsOrigin) {
+    match origin {
+        SettingsOrigin::Defaults { corrupt_banks: 0 } => {
+            info!("{} is using its default settings.", device)
+        }
+        SettingsOrigin::Bank {
+            bank,
+            version,
+            corrupt_banks: 0,
+        } => info!(
+            "{} loaded settings version {} from bank {}.",
+            device, version, bank
+        ),
+        origin => warn!(
+            "{} has {} corrupt settings bank(s) and fell back to {:?}. Recent setting changes may need to be sent again.",
+            device,
+            origin.corrupt_banks(),
+            origin
+        ),
+    }
+}
+
+/// Log why a device last reset, warning if it points to a fault.
+fn log_reset_cause(device: DeviceId, reset_cause: ResetCause) {
+    match reset_cause {
+        ResetCause::Watchdog => warn!(
+            "{} was reset by its watchdog. The firmware may have hung.",
+            device
+        ),
+        ResetCause::BrownOut => warn!(
+            "{} was reset by a brown-out. Check its supply and USB hub.",
+            device
+        ),
+        reset_cause => info!("{} last reset from {:?}.", device, reset_cause),
+    }
+}
+
+/// Check a device speaks the same protocol version as the host, logging
+/// why not.
+fn accepts_protocol(device: DeviceId, accept: &AcceptConnectionPacket) -> bool {
+    if accept.is_compatible() {
+        info!(
+            "{} accepted the connection with protocol version {}.",
+            device, accept.protocol_version
+        );
+        return true;
+    }
+    error!(
+        "Refusing {}. It speaks protocol version {} but the host speaks {}. Update the firmware and host together.",
+        device, accept.protocol_version, PROTOCOL_VERSION
+    );
+    false
+}
+
+/// Ask a device for its capabilities. Returns when the self-check is given
+/// up on if it doesn't reply.
+fn request_device_info(
+    port: &mut Box<dyn SerialPort>,
+    stats: &mut LinkStats,
+    faults: &mut FaultInjector,
+) -> Instant {
+    let request = Packet::RequestDeviceInfo(RequestDeviceInfoPacket);
+    match write_packet_to_port_with_faults(port, request, faults) {
+        Err(e) => {
+            stats.record_write_failure();
+            warn!("Failed to request device info! Error: {}", e);
+        }
+        Ok(length) => stats.record_sent(length),
+    }
+    Instant::now() + DEVICE_INFO_TIMEOUT
+}
+
+/// Queue a device's capabilities and reset cause for journaling.
+fn journal_device_info(
+    tx_journal: &Sender<JournalRecord>,
+    device: DeviceId,
+    info: &ReportDeviceInfoPacket,
+) {
+    if let Err(e) = tx_journal.send(JournalRecord::DeviceInfo {
+        device,
+        capabilities: info.capabilities,
+        reset_cause: info.reset_cause,
+    }) {
+        trace!("Failed to queue device info for journaling. Error: {}", e);
+    }
+}
+
+/// Queue a device's link stats for journaling.
+fn journal_link_stats(tx_journal: &Sender<JournalRecord>, device: DeviceId, stats: LinkStats) {
+    if let Err(e) = tx_journal.send(JournalRecord::LinkStats { device, stats }) {
+        trace!("Failed to queue link stats for journaling. Error: {}", e);
+    }
+}
+
+/// Get the baud rate an open port actually uses. Warns if the driver didn't
+/// accept the requested rate.
+fn negotiated_baud_rate(port: &dyn SerialPort, link_config: LinkConfig) -> Option<u32> {
+    match port.baud_rate() {
+        Err(e) => {
+            warn!("Failed to get effective baud rate. Error: {}", e);
+            None
+        }
+        Ok(baud_rate) if baud_rate != link_config.baud_rate => {
+            warn!(
+                "Requested {} baud but the port is using {} baud.",
+                link_config.baud_rate, baud_rate
+            );
+            Some(baud_rate)
+        }
+        Ok(baud_rate) => {
+            info!("Opened port at {} baud.", baud_rate);
+            Some(baud_rate)
+        }
+    }
+}
+
+/// Queue a packet for writing if it is addressed to `device`. Control frames
+/// from a generation older than one already queued are dropped, so they
+/// can't replace a newer frame.
+fn queue_outgoing_packet(
+    outgoing: &mut OutgoingQueue,
+    generations: &mut GenerationFilter,
+    device: DeviceId,
+    data: AddressedPacket,
+) {
+    if data.device != device {
+        trace!("Ignoring packet addressed to {}.", data.device);
+        return;
+    }
+    if !generations.admit(&data) {
+        debug!(
+            "Dropping control frame from superseded generation {:?}.",
+            data.generation
+        );
+        return;
+    }
+    outgoing.push(data);
+}
+
+/// Move every packet already waiting in `rx_packets_to_hw` into the outgoing
+/// queue without blocking, coalescing stale packets away.
+fn drain_outgoing_packets(
+    outgoing: &mut OutgoingQueue,
+    generations: &mut GenerationFilter,
+    device: DeviceId,
+    rx_packets_to_hw: &mut Receiver<AddressedPacket>,
+) {
+    loop {
+        match rx_packets_to_hw.try_recv() {
+            Ok(data) => queue_outgoing_packet(outgoing, generations, device, data),
+            Err(TryRecvError::Lagged(skipped)) => {
+                warn!("Outgoing packet queue lagged. Skipped {} packets.", skipped)
+            }
+            Err(_) => break,
+        }
+    }
+    trace!("{} packets queued for writing.", outgoing.len());
+}
+
+/// Wait until `pacer` has room for another write, if writes are paced.
+async fn wait_for_pacer(pacer: &Option<WritePacer>) {
+    let Some(pacer) = pacer else {
+        return;
+    };
+    let delay = pacer.delay(Instant::now());
+    if !delay.is_zero() {
+        trace!("Pacing write by {:?}.", delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Count a write of `bytes` against `pacer`, if writes are paced.
+fn record_paced_write(pacer: &mut Option<WritePacer>, bytes: usize) {
+    if let Some(pacer) = pacer {
+        pacer.record(bytes, Instant::now());
+    }
+}
+
+/// Write every frame in `batch` to the port at once, once `pacer` has room,
+/// and empty it.
+async fn write_batch_to_port(
+    port: &mut Box<dyn SerialPort>,
+    batch: &mut WriteBatch,
+    stats: &mut LinkStats,
+    pacer: &mut Option<WritePacer>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    wait_for_pacer(pacer).await;
+    match port.write(batch.bytes()) {
+        Err(e) => {
+            stats.record_batch_failure(batch.frames());
+            warn!(
+                "Failed to write {} packets to port! Error: {}",
+                batch.frames(),
+                e
+            );
+        }
+        Ok(length) => {
+            stats.record_batch_sent(batch.frames(), length);
+            record_paced_write(pacer, length);
+            debug!(
+                "Successfully wrote {} packets in {} bytes to port!",
+                batch.frames(),
+                length
+            );
+        }
+    }
+    batch.clear();
+}
+
+/// Send a single packet of data to the embedded hardware, followed by its CRC.
+pub(super) fn write_packet_to_port(
+    port: &mut Box<dyn SerialPort>,
+    packet: Packet,
+) -> Result<usize, CommError> {
+    write_packet_to_port_with_faults(port, packet, &mut FaultInjector::disabled())
 }
 
-/// Send a single packet of data to the embedded hardware.
+/// Send a single packet of data to the embedded hardware, letting `faults`
+/// corrupt the frame on its way out.
 #[instrument(skip_all)]
-fn write_packet_to_port(port: &mut Box<dyn SerialPort>, packet: Packet) -> Result<usize> {
-    match postcard::to_vec::<Packet, 64>(&packet) {
+fn write_packet_to_port_with_faults(
+    port: &mut Box<dyn SerialPort>,
+    packet: Packet,
+    faults: &mut FaultInjector,
+) -> Result<usize, CommError> {
+    let frame = encode_frame_with_faults(&packet, faults)?;
+    match port.write(&frame) {
         Err(e) => {
-            warn!("Failed to encode packet to byte array. Error: {}", e);
+            error!("Failed to write byte buffer to port. Error: {}", e);
             Err(e.into())
         }
-        Ok(buffer) => match port.write(buffer.as_slice()) {
-            Err(e) => {
-                error!("Failed to write byte buffer to port. Error: {}", e);
-                Err(e.into())
-            }
-            Ok(length) => {
-                debug!("Successfully wrote {} bytes to port.", length);
-                Ok(length)
+        Ok(length) => {
+            debug!("Successfully wrote {} bytes to port.", length);
+            Ok(length)
+        }
+    }
+}
+
+/// Encode a packet into a frame, letting `faults` corrupt it on its way out.
+fn encode_frame_with_faults(
+    packet: &Packet,
+    faults: &mut FaultInjector,
+) -> Result<Vec<u8>, CommError> {
+    let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+    match encode_packet(packet, &mut buffer) {
+        Err(e) => {
+            warn!("Failed to encode packet to byte array. Error: {}", e);
+            Err(CommError::Encode(e))
+        }
+        Ok(frame) => {
+            let mut frame = frame.to_vec();
+            if faults.corrupt(&mut frame) {
+                debug!("Injected corruption into an outgoing frame.");
             }
-        },
+            Ok(frame)
+        }
     }
 }
 
 /// Listens for incoming client messages. Will convert `ReportSensors` messages
-/// into `ClientSensorData` models and transmit them.
+/// into `ClientSensorData` models and transmit them. `ReportRpmFast` messages
+/// are merged into each device's last full report and transmitted too, so
+/// speeds are fresh at the fast rate while everything else is at the
+/// telemetry rate. Each sample of a `ReportSensorsBatch` message is merged
+/// and transmitted in the same way, timestamped when it was taken.
+/// Devices which report no valve sense wiring have their valve state inferred
+/// from the control frames sent to them, assuming `valve_travel` to move.
+/// Valves which are sensed but keep reading `Unknown` are handled by
+/// `valve_policy`, raising their alarm over `tx_alarm_conditions`. Full
+/// reports whose flow is too low by `low_flow_policy` are flagged, so the
+/// control loop holds the valve open, and raise their alarm in the same way.
+/// Beats `heartbeat` while running.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(skip_all)]
 pub async fn task_process_client_sensor_packets(
     token: CancellationToken,
     tx_client_sensor_data: Sender<ClientSensorData>,
-    mut rx_packets_from_hw: Receiver<Packet>,
+    mut rx_packets_from_hw: Receiver<AddressedPacket>,
+    mut rx_control_frame: Receiver<ControlEvent>,
+    valve_travel: Duration,
+    valve_policy: ValvePolicy,
+    low_flow_policy: LowFlowPolicy,
+    tx_alarm_conditions: Sender<AlarmCondition>,
+    heartbeat: Heartbeat,
 ) {
     info!("Started.");
 
+    let mut heartbeat_interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    // NOTE: Capabilities are only reported on connect, so a restart of this
+    //       task falls back to the reported valve state until the device
+    //       reconnects.
+    let mut inferred_valves: HashMap<DeviceId, InferredValve> = HashMap::new();
+    let mut last_reports: HashMap<DeviceId, ClientSensorData> = HashMap::new();
+    let mut last_stats: HashMap<DeviceId, ReportStatsPacket> = HashMap::new();
+    let mut valve_guard = UnknownValveGuard::new(valve_policy);
+    let mut flow_interlock = LowFlowInterlock::new(low_flow_policy);
+
     loop {
         tokio::select! {
             _ = token.cancelled() => {
                 warn!("Cancelled.");
                 break;
             },
+            _ = heartbeat_interval.tick() => {
+                heartbeat.beat();
+            },
+            Ok(data) = rx_control_frame.recv() => {
+                if let Some(valve) = inferred_valves.get_mut(&data.device) {
+                    valve.command(data.valve_state, Instant::now());
+                }
+            },
             Ok(data) = rx_packets_from_hw.recv() => {
                 debug!("Got packet from hardware. Packet: {:?}",data);
                 // NOTE: MIGHT BE SUFFICIENT/PREFERRED TO CLONE THE TX SENDER RATHER
                 // RATHER THAN SEND A REF.
-                if let Err(e) = handle_report_sensor_packet(data, &tx_client_sensor_data) {
+                if let Err(e) = handle_report_sensor_packet(
+                    data,
+                    &tx_client_sensor_data,
+                    &mut inferred_valves,
+                    &mut last_reports,
+                    &mut last_stats,
+                    valve_travel,
+                    &mut valve_guard,
+                    &mut flow_interlock,
+                    &tx_alarm_conditions,
+                ) {
                     error!("Failed to handle report sensor packet. Error: {}", e);
                 } else {
                     debug!("Successfully handled report sensor packet.");
@@ -259,7 +961,7 @@ pub async fn task_process_client_sensor_packets(
 pub async fn task_send_control_frames_to_client(
     token: CancellationToken,
     mut rx_control_frame: Receiver<ControlEvent>,
-    tx_send_packets_to_hw: Sender<Packet>,
+    tx_send_packets_to_hw: Sender<AddressedPacket>,
 ) {
     info!("Started");
     loop {
@@ -282,55 +984,171 @@ pub async fn task_send_control_frames_to_client(
     }
 }
 
-/// Convert a control frame into a packet and queue it to be sent.
-/// Returns a result, ```Ok(())``` if the packet was converted and queued,
+/// Convert a control frame into its control targets and valve command
+/// packets addressed to the frame's device, and queue them to be sent.
+/// Returns a result, ```Ok(())``` if the packets were converted and queued,
 /// ```Err``` otherwise.
+#[instrument(skip_all, fields(device = %control_frame.device, sequence = control_frame.sequence))]
 fn convert_control_frame_to_packet_and_send_to_hardware(
     control_frame: ControlEvent,
-    tx_send_packets_to_hw: &Sender<Packet>,
-) -> Result<()> {
-    let packet = match Packet::try_from(control_frame) {
+    tx_send_packets_to_hw: &Sender<AddressedPacket>,
+) -> Result<(), CommError> {
+    let packets = match AddressedPacket::from_control_event(control_frame) {
         Err(e) => {
             return Err(e.into());
         }
-        Ok(packet) => packet,
+        Ok(packets) => packets,
     };
-    match tx_send_packets_to_hw.send(packet) {
-        Err(e) => Err(e.into()),
-        Ok(_) => Ok(()),
+    for packet in packets {
+        if tx_send_packets_to_hw.send(packet).is_err() {
+            return Err(CommError::Closed("packets"));
+        }
     }
+    Ok(())
 }
 
 /// Handle the processing for any incoming client packets.
-/// Will only respond to `ReportSensors` type.
+/// Will only respond to `ReportSensors`, `ReportRpmFast` and
+/// `ReportSensorsBatch` types. Each full report is kept in `last_reports` for
+/// fast rpm reports and each sample of a batch to be merged into. Fast rpm
+/// reports and batches from a device without a full report yet are dropped.
+/// `ReportStats`, `ReportError`, `FirmwareInfo` and `ValveTransitionComplete`
+/// packets are logged, and each `ReportStats` is checked against the
+/// device's previous one in `last_stats` for resets, corrupted frames and
+/// full packet queues. `ReportDeviceInfo` packets decide whether the device's
+/// valve state is inferred, in which case it replaces the reported state.
+/// Otherwise the reported state goes through `valve_guard`, which raises and
+/// clears the device's `ValveStateUnknown` alarm. Every full report then goes
+/// through `flow_interlock`, which raises and clears its `LowFlow` alarm.
 /// Will return an error if the `ReportSensors` packet failed to be converted
 /// to a `ClientSensorData` or if it failed to be sent over `tx_client_sensor_data`.
 /// If it returns an error, the underlying error will be returned.
 /// Returns `Ok(())` if either the packet wasn't of type `ReportSensors` or if
 /// it was able to successfully generate a `ClientSensorData` and send it.
+/// The packet's sequence number is carried onto the `ClientSensorData`.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(device = %packet.device, sequence = packet.sequence))]
 fn handle_report_sensor_packet(
-    packet: Packet,
+    packet: AddressedPacket,
     tx_client_sensor_data: &Sender<ClientSensorData>,
-) -> Result<()> {
-    match packet {
-        Packet::ReportSensors(packet) => {
-            trace!("Received report sensor packet: {:?}", packet);
-            let client_sensor_data = match ClientSensorData::try_from(packet) {
+    inferred_valves: &mut HashMap<DeviceId, InferredValve>,
+    last_reports: &mut HashMap<DeviceId, ClientSensorData>,
+    last_stats: &mut HashMap<DeviceId, ReportStatsPacket>,
+    valve_travel: Duration,
+    valve_guard: &mut UnknownValveGuard,
+    flow_interlock: &mut LowFlowInterlock,
+    tx_alarm_conditions: &Sender<AlarmCondition>,
+) -> Result<(), CommError> {
+    match packet.packet {
+        Packet::ReportSensors(report) => {
+            trace!("Received report sensor packet: {:?}", report);
+            let mut client_sensor_data = match ClientSensorData::try_from((packet.device, report)) {
                 Err(e) => {
                     return Err(e.into());
                 }
                 Ok(data) => data,
             };
-
-            trace!("Got a client sensor data packet converted. Packet: {}", client_sensor_data);
-            if let Err(e) = tx_client_sensor_data.send(client_sensor_data) {
-                return Err(e.into());
+            if !inferred_valves.contains_key(&packet.device) {
+                client_sensor_data =
+                    guard_valve_state(client_sensor_data, valve_guard, tx_alarm_conditions);
             }
-            debug!(
-                "Sent a client sensor data message. Message: {}",
-                client_sensor_data
+            client_sensor_data =
+                guard_flow(client_sensor_data, flow_interlock, tx_alarm_conditions);
+            last_reports.insert(packet.device, client_sensor_data);
+            send_client_sensor_data(
+                client_sensor_data,
+                packet.sequence,
+                tx_client_sensor_data,
+                inferred_valves,
+            )?;
+        }
+        Packet::ReportRpmFast(report) => {
+            trace!("Received fast rpm packet: {:?}", report);
+            let Some(last_report) = last_reports.get(&packet.device) else {
+                trace!("Dropping fast rpm packet received before a full report.");
+                return Ok(());
+            };
+            let client_sensor_data = ClientSensorData {
+                extra_fan_speeds: report.fan_speeds_rpm.rest(),
+                ..last_report.with_speeds(report.pump_speed_rpm, report.fan_speeds_rpm.first())
+            };
+            send_client_sensor_data(
+                client_sensor_data,
+                packet.sequence,
+                tx_client_sensor_data,
+                inferred_valves,
+            )?;
+        }
+        Packet::ReportSensorsBatch(batch) => {
+            trace!("Received sensor batch packet: {:?}", batch);
+            let Some(last_report) = last_reports.get(&packet.device).copied() else {
+                trace!("Dropping sensor batch packet received before a full report.");
+                return Ok(());
+            };
+            for sample in &batch.samples {
+                let (fan_speed, pump_speed) = batch
+                    .speeds(sample)
+                    .map_err(|_| client_sensor_data::ClientSensorDataError::Invalid)?;
+                let mut client_sensor_data = last_report.with_speeds(pump_speed, fan_speed);
+                // NOTE: Assumed valve states are held until the next full
+                //       report, which goes through the valve policy.
+                if !is_valve_assumed(&last_report) {
+                    client_sensor_data = client_sensor_data.with_valve_state(sample.valve_state);
+                }
+                client_sensor_data.sampled_at_ms = batch.sampled_at_ms(sample);
+                send_client_sensor_data(
+                    client_sensor_data,
+                    packet.sequence,
+                    tx_client_sensor_data,
+                    inferred_valves,
+                )?;
+            }
+        }
+        Packet::ReportDeviceInfo(info) => {
+            if info.capabilities.valve_sense {
+                inferred_valves.remove(&packet.device);
+            } else if let Entry::Vacant(entry) = inferred_valves.entry(packet.device) {
+                info!(
+                    "{} has no valve sense wiring. Inferring valve state from commands.",
+                    packet.device
+                );
+                entry.insert(InferredValve::new(valve_travel));
+            }
+        }
+        Packet::ReportStats(stats) => {
+            debug!("{} reported stats: {:?}", packet.device, stats);
+            for finding in check_stats(last_stats.get(&packet.device), &stats) {
+                warn!("{} {}.", packet.device, finding);
+            }
+            last_stats.insert(packet.device, stats);
+        }
+        Packet::ReportError(report) => {
+            error!(
+                "{} reported an error. Error: {}",
+                packet.device, report.error
             );
         }
+        Packet::FirmwareInfo(firmware) => {
+            info!("{} is running firmware {}.", packet.device, firmware);
+        }
+        Packet::ValveTransitionComplete(complete) => {
+            if complete.confirmed {
+                info!(
+                    "{} valve reached {} after {}ms.",
+                    packet.device, complete.state, complete.elapsed_ms
+                );
+            } else if inferred_valves.contains_key(&packet.device) {
+                debug!(
+                    "{} valve assumed to have reached {} after {}ms.",
+                    packet.device, complete.state, complete.elapsed_ms
+                );
+            } else {
+                warn!(
+                    "{} valve didn't reach {} within {}ms.",
+                    packet.device, complete.state, complete.elapsed_ms
+                );
+            }
+        }
         _ => {
             /* NOTE: NOT INTERESTED IN OTHER PACKET TYPES HERE. */
             trace!("Received packet other than sensor packet.");
@@ -340,8 +1158,108 @@ fn handle_report_sensor_packet(
     Ok(())
 }
 
+/// Apply the valve policy to a full report, raising or clearing the device's
+/// `ValveStateUnknown` alarm as its valve becomes faulted or recovers.
+fn guard_valve_state(
+    client_sensor_data: ClientSensorData,
+    valve_guard: &mut UnknownValveGuard,
+    tx_alarm_conditions: &Sender<AlarmCondition>,
+) -> ClientSensorData {
+    let device = client_sensor_data.device;
+    let (client_sensor_data, change) = valve_guard.observe(client_sensor_data);
+    let Some(present) = change else {
+        return client_sensor_data;
+    };
+    if present {
+        error!(
+            "{} {}. Assuming the valve is open and holding commands to it.",
+            device,
+            AlarmKind::ValveStateUnknown
+        );
+    } else {
+        info!(
+            "{} recovered from {}.",
+            device,
+            AlarmKind::ValveStateUnknown
+        );
+    }
+    if let Err(e) = tx_alarm_conditions.send(AlarmCondition {
+        device,
+        kind: AlarmKind::ValveStateUnknown,
+        present,
+    }) {
+        warn!("Failed to send alarm condition. Error: {}", e);
+    }
+    client_sensor_data
+}
+
+/// Apply the low flow interlock to a full report, raising or clearing the
+/// device's `LowFlow` alarm as it engages or releases.
+fn guard_flow(
+    client_sensor_data: ClientSensorData,
+    flow_interlock: &mut LowFlowInterlock,
+    tx_alarm_conditions: &Sender<AlarmCondition>,
+) -> ClientSensorData {
+    let device = client_sensor_data.device;
+    let (client_sensor_data, change) = flow_interlock.observe(client_sensor_data);
+    let Some(present) = change else {
+        return client_sensor_data;
+    };
+    if present {
+        error!(
+            "{} {}. Holding the valve open and running the fan at full.",
+            device,
+            AlarmKind::LowFlow
+        );
+    } else {
+        info!("{} recovered from {}.", device, AlarmKind::LowFlow);
+    }
+    if let Err(e) = tx_alarm_conditions.send(AlarmCondition {
+        device,
+        kind: AlarmKind::LowFlow,
+        present,
+    }) {
+        warn!("Failed to send alarm condition. Error: {}", e);
+    }
+    client_sensor_data
+}
+
+/// Stamp client sensor data with the sequence number of the packet it came
+/// from, replace its valve state if it is inferred, and transmit it.
+fn send_client_sensor_data(
+    mut client_sensor_data: ClientSensorData,
+    sequence: Option<u64>,
+    tx_client_sensor_data: &Sender<ClientSensorData>,
+    inferred_valves: &mut HashMap<DeviceId, InferredValve>,
+) -> Result<(), CommError> {
+    client_sensor_data.sequence = sequence;
+    if let Some(valve) = inferred_valves.get_mut(&client_sensor_data.device) {
+        client_sensor_data = client_sensor_data.with_valve_state(valve.state(Instant::now()));
+        trace!("Inferred valve state: {}", client_sensor_data.valve_state);
+    }
+
+    trace!(
+        "Got a client sensor data packet converted. Packet: {}",
+        client_sensor_data.describe(TemperatureUnit::from_env())
+    );
+    if !client_sensor_data.quality.is_good() {
+        warn!(
+            "Client sensor data has implausible readings. Quality: {}",
+            client_sensor_data.quality
+        );
+    }
+    if tx_client_sensor_data.send(client_sensor_data).is_err() {
+        return Err(CommError::Closed("client sensor data"));
+    }
+    debug!(
+        "Sent a client sensor data message. Message: {}",
+        client_sensor_data.describe(TemperatureUnit::from_env())
+    );
+    Ok(())
+}
+
 #[instrument(skip_all)]
-fn is_ready_to_read_from_port(port: &Box<dyn SerialPort>) -> Result<bool> {
+fn is_ready_to_read_from_port(port: &Box<dyn SerialPort>) -> Result<bool, CommError> {
     match port.bytes_to_read() {
         Ok(bytes) => {
             trace!("Found {} bytes ready to read from port.", bytes);
@@ -357,8 +1275,23 @@ fn is_ready_to_read_from_port(port: &Box<dyn SerialPort>) -> Result<bool> {
     }
 }
 
+/// Read packets from the embedded hardware. `codec` holds a frame split
+/// across reads until the rest of it is read.
+pub(super) fn read_packets_from_port(
+    port: &mut Box<dyn SerialPort>,
+    codec: &mut PacketCodec,
+) -> Result<Vec<Packet>, CommError> {
+    read_packets_from_port_with_faults(port, codec, &mut FaultInjector::disabled())
+}
+
+/// Read packets from the embedded hardware, letting `faults` corrupt the
+/// bytes read before they are decoded.
 #[instrument(skip_all)]
-fn read_packets_from_port(port: &mut Box<dyn SerialPort>) -> Result<Vec<Packet>> {
+fn read_packets_from_port_with_faults(
+    port: &mut Box<dyn SerialPort>,
+    codec: &mut PacketCodec,
+    faults: &mut FaultInjector,
+) -> Result<Vec<Packet>, CommError> {
     match is_ready_to_read_from_port(port) {
         Ok(true) => {
             trace!("Is ready to read from port.");
@@ -369,7 +1302,7 @@ fn read_packets_from_port(port: &mut Box<dyn SerialPort>) -> Result<Vec<Packet>>
         }
         Err(e) => {
             trace!("Not ready to read yet with error. Error: {}", e);
-            return Err(e.into());
+            return Err(e);
         }
     }
 
@@ -378,13 +1311,15 @@ fn read_packets_from_port(port: &mut Box<dyn SerialPort>) -> Result<Vec<Packet>>
     match port.read(&mut read_buffer) {
         Ok(bytes_read) => {
             trace!("Received {} bytes", bytes_read);
-            let (packets, remaining_bytes) =
-                decode_packets_from_buffer(&read_buffer[0..bytes_read]);
+            if faults.corrupt(&mut read_buffer[0..bytes_read]) {
+                debug!("Injected corruption into the bytes read.");
+            }
+            let packets = decode_packets_from_buffer(codec, &read_buffer[0..bytes_read]);
             debug!(
-                "Decoded {} packets from {} bytes with {} left over bytes.",
+                "Decoded {} packets from {} bytes with {} bytes of an unfinished frame.",
                 packets.len(),
                 bytes_read,
-                remaining_bytes.len()
+                codec.pending()
             );
 
             return Ok(packets);
@@ -397,16 +1332,45 @@ fn read_packets_from_port(port: &mut Box<dyn SerialPort>) -> Result<Vec<Packet>>
 }
 
 /// Decode as many packets as possible from a buffer.
-/// Returning the vector of packets and any unused bytes from the buffer.
-fn decode_packets_from_buffer(buffer: &[u8]) -> (Vec<Packet>, &[u8]) {
-    let mut remaining_buffer = buffer;
+/// Returning the vector of packets. Bytes of an unfinished frame are kept in
+/// `codec`. Frames which fail their CRC check are dropped rather than acted
+/// on.
+fn decode_packets_from_buffer(codec: &mut PacketCodec, buffer: &[u8]) -> Vec<Packet> {
     let mut packets: Vec<Packet> = vec![];
-    while let Ok((packet, extra)) = postcard::take_from_bytes::<Packet>(remaining_buffer) {
-        remaining_buffer = extra;
-        packets.push(packet);
+    let dropped = codec.decode(buffer, |packet| packets.push(packet));
+    if dropped > 0 {
+        warn!("Dropped {} corrupt frames while decoding packets.", dropped);
     }
-    if buffer.len() > 0 && packets.is_empty() {
+    if buffer.len() > 0 && packets.is_empty() && codec.pending() == 0 {
         warn!("Didn't decode a single packet from {} bytes!", buffer.len());
     }
-    (packets, remaining_buffer)
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::golden::{check_golden_packets, golden_packets};
+
+    #[test]
+    fn test_golden_packets() {
+        assert_eq!(check_golden_packets(), Ok(()));
+
+        let mut stream = vec![];
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        for golden in golden_packets() {
+            stream.extend_from_slice(golden.frame(&mut buffer).unwrap());
+        }
+        // NOTE: Split unevenly so frames straddle reads.
+        let mut codec = PacketCodec::new();
+        let packets = stream
+            .chunks(7)
+            .flat_map(|chunk| decode_packets_from_buffer(&mut codec, chunk))
+            .collect::<Vec<_>>();
+        let expected = golden_packets()
+            .into_iter()
+            .map(|golden| golden.packet)
+            .collect::<Vec<_>>();
+        assert_eq!(packets, expected);
+    }
 }
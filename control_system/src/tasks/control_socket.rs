@@ -0,0 +1,252 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{fs::MetadataExt, net::UnixStream},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader},
+    net::{UnixListener, UnixStream as AsyncUnixStream},
+    sync::broadcast::Sender,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+#[cfg(feature = "simulate")]
+use crate::models::fault_simulation::SharedPlantFaults;
+use crate::{
+    error::ControlError,
+    models::{
+        addressed_packet::AddressedPacket,
+        alarm::SharedAlarmLog,
+        control_auth::{control_token_from_env, ControlAuth},
+        control_socket::{ControlMessage, ControlRequest, ControlResponse},
+        control_trace::SharedControlTraces,
+        daemon_config::DaemonConfig,
+        device_log::SharedDeviceLogs,
+        journal::JournalRecord,
+    },
+};
+
+#[cfg(feature = "simulate")]
+use super::fault_simulation::handle_simulate_fault_request;
+use super::{
+    alarms::handle_alarm_request, curve_preview::handle_curve_request,
+    device_log::handle_device_logs_request, explain::handle_explain_request,
+    state_machines::handle_state_machines_request, telemetry_rate::handle_telemetry_rate_request,
+};
+
+/// How long the CLI waits for the control system to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Task: Serve requests from the CLI over a unix socket at `socket_path`.
+/// Each line received is a JSON `ControlMessage`, answered with a line of JSON
+/// `ControlResponse`. Requests which change state are refused unless the
+/// `ControlAuth` from the environment grants the client control. Traces are
+/// only served if the control loop records them into `traces`, and faults
+/// are only simulated if the client tasks apply `plant_faults`. Device logs
+/// are served from `device_logs`. Packets for the devices are queued on
+/// `tx_packets_to_hw`.
+/// The socket file is removed once cancelled.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn task_serve_control_socket(
+    token: CancellationToken,
+    socket_path: PathBuf,
+    alarms_path: PathBuf,
+    alarms: SharedAlarmLog,
+    traces: Option<SharedControlTraces>,
+    #[cfg(feature = "simulate")] plant_faults: Option<SharedPlantFaults>,
+    device_logs: SharedDeviceLogs,
+    tx_packets_to_hw: Sender<AddressedPacket>,
+    tx_journal: Sender<JournalRecord>,
+) {
+    info!("Started.");
+
+    // NOTE: A socket left behind by a crash would otherwise fail the bind.
+    let _ = std::fs::remove_file(&socket_path);
+    let listener = match UnixListener::bind(&socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Failed to bind control socket at {}. Error: {}",
+                socket_path.display(),
+                e
+            );
+            return;
+        }
+    };
+    info!("Listening on {}.", socket_path.display());
+
+    // NOTE: The socket is owned by whoever the control system runs as.
+    let owner_uid = match std::fs::metadata(&socket_path) {
+        Ok(metadata) => metadata.uid(),
+        Err(e) => {
+            error!("Failed to get control socket owner. Error: {}", e);
+            return;
+        }
+    };
+    let auth = ControlAuth::from_env(owner_uid);
+    info!(
+        "Granting control to uids {:?}{}.",
+        auth.control_uids,
+        if auth.token.is_some() {
+            " and token holders"
+        } else {
+            ""
+        }
+    );
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            res = listener.accept() => {
+                let stream = match res {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept control connection. Error: {}", e);
+                        continue;
+                    }
+                };
+                debug!("Accepted control connection.");
+                tokio::spawn(serve_connection(
+                    token.clone(),
+                    stream,
+                    auth.clone(),
+                    alarms_path.clone(),
+                    alarms.clone(),
+                    traces.clone(),
+                    #[cfg(feature = "simulate")]
+                    plant_faults.clone(),
+                    device_logs.clone(),
+                    tx_packets_to_hw.clone(),
+                    tx_journal.clone(),
+                ));
+            },
+        }
+    }
+
+    if let Err(e) = std::fs::remove_file(&socket_path) {
+        warn!("Failed to remove control socket. Error: {}", e);
+    }
+}
+
+/// Answer each request on a single connection until it closes.
+#[allow(clippy::too_many_arguments)]
+async fn serve_connection(
+    token: CancellationToken,
+    stream: AsyncUnixStream,
+    auth: ControlAuth,
+    alarms_path: PathBuf,
+    alarms: SharedAlarmLog,
+    traces: Option<SharedControlTraces>,
+    #[cfg(feature = "simulate")] plant_faults: Option<SharedPlantFaults>,
+    device_logs: SharedDeviceLogs,
+    tx_packets_to_hw: Sender<AddressedPacket>,
+    tx_journal: Sender<JournalRecord>,
+) {
+    // NOTE: Without the peer's uid it can only be granted control by token.
+    let peer_uid = stream.peer_cred().ok().map(|cred| cred.uid());
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = AsyncBufReader::new(reader).lines();
+    loop {
+        let line = tokio::select! {
+            _ = token.cancelled() => break,
+            line = lines.next_line() => line,
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Failed to read control request. Error: {}", e);
+                break;
+            }
+        };
+
+        let response = match serde_json::from_str::<ControlMessage>(&line) {
+            Ok(message) => {
+                let permission = auth.permission(peer_uid, message.token.as_deref());
+                let required = message.request.required_permission();
+                if permission < required {
+                    warn!(
+                        "Refused control request from uid {:?} with {} permission: {:?}",
+                        peer_uid, permission, message.request
+                    );
+                    ControlResponse::Forbidden { required }
+                } else {
+                    info!("Received control request: {:?}", message.request);
+                    match &message.request {
+                        ControlRequest::EvaluateCurve { curve, samples } => {
+                            handle_curve_request(curve, *samples)
+                        }
+                        ControlRequest::Explain { device } => {
+                            handle_explain_request(traces.as_ref(), *device)
+                        }
+                        ControlRequest::StateMachines { device } => {
+                            handle_state_machines_request(traces.as_ref(), *device)
+                        }
+                        #[cfg(feature = "simulate")]
+                        ControlRequest::SimulateFault { device, fault } => {
+                            handle_simulate_fault_request(plant_faults.as_ref(), *device, *fault)
+                        }
+                        ControlRequest::SetTelemetryRate { device, rate_hz } => {
+                            handle_telemetry_rate_request(&tx_packets_to_hw, *device, *rate_hz)
+                        }
+                        ControlRequest::DaemonConfig => {
+                            ControlResponse::DaemonConfig(DaemonConfig::from_env())
+                        }
+                        ControlRequest::DeviceLogs => {
+                            handle_device_logs_request(&device_logs, &tx_packets_to_hw).await
+                        }
+                        request => {
+                            handle_alarm_request(&alarms_path, &alarms, request, &tx_journal)
+                        }
+                    }
+                }
+            }
+            Err(e) => ControlResponse::Error(format!("Invalid request. Error: {}", e)),
+        };
+        let mut response = match serde_json::to_string(&response) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to encode control response. Error: {}", e);
+                break;
+            }
+        };
+        response.push('\n');
+        if let Err(e) = writer.write_all(response.as_bytes()).await {
+            warn!("Failed to write control response. Error: {}", e);
+            break;
+        }
+    }
+}
+
+/// Send a single request to the control system listening at `socket_path` and
+/// wait for its response. Carries `PRANDTL_CONTROL_TOKEN` if it is set.
+pub fn request(
+    socket_path: &Path,
+    request: &ControlRequest,
+) -> Result<ControlResponse, ControlError> {
+    let mut stream = UnixStream::connect(socket_path)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let message = ControlMessage {
+        request: request.clone(),
+        token: control_token_from_env(),
+    };
+    let mut line = serde_json::to_string(&message)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(ControlError::NoAnswer);
+    }
+    Ok(serde_json::from_str(&line)?)
+}
@@ -1,68 +1,406 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use tokio::sync::broadcast::{Receiver, Sender};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, instrument, trace, warn};
+use tracing::{debug, debug_span, error, info, instrument, trace, warn};
 
 use crate::{
-    controls::generate_control_frame,
+    controls::{park_outputs, step_with_strategy, ControlInputs, ControlState, ControlStrategy},
     models::{
-        client_sensor_data::ClientSensorData, control_event::ControlEvent,
+        client_sensor_data::ClientSensorData,
+        control_event::ControlEvent,
+        control_generation::SharedGeneration,
+        control_loop::ControlLoopMode,
+        control_override::{apply_override, OverrideRequest},
+        control_timing::ControlTiming,
+        control_trace::{ControlTrace, SharedControlTraces},
+        device_id::DeviceId,
+        heartbeat::{Heartbeat, HEARTBEAT_INTERVAL},
         host_sensor_data::HostSensorData,
+        journal::JournalRecord,
+        oscillation::OSCILLATION_DAMPING_HOLD,
+        sleep::SleepEvent,
     },
 };
 
+/// Longest interval a single step is credited with. Steps normally come at
+/// least every couple of seconds, so a longer gap means the host stalled and
+/// shouldn't fast-forward blends in progress.
+const MAX_STEP_DT: Duration = Duration::from_secs(2);
+
+/// How often the timing of each device's control steps is reported.
+const CONTROL_TIMING_INTERVAL: Duration = Duration::from_secs(30);
+
 /// Task: Activate when a host or client sensor data is emitted.
-/// Generate a control frame when both a client and host data have been
-/// emitted which is updated everytime a host or client data are emitted.
+/// Generate a control frame for every device once both its client data and
+/// host data have been emitted which is updated everytime a host or client
+/// data are emitted. Outputs are generated by `strategy`. With `ControlLoopMode::EventDriven`, client data only
+/// updates its own device's frame, and frames are only sent when they change
+/// or the keepalive passes.
+/// Every device is parked when the host is about to sleep, and no control
+/// frames are generated until it resumes. Outputs are then blended up from
+/// the parked values over `resume_warm_up`.
+/// Each step is credited with the measured time since the device's previous
+/// step, up to `MAX_STEP_DT`. The intervals and their jitter are logged and
+/// journaled every `CONTROL_TIMING_INTERVAL`.
+/// A device held by an override request is sent the held outputs until it
+/// is released, then blended back to the control loop's over
+/// `override_blend`.
+/// Parking, resuming and applying an override each advance `generation`, so
+/// frames generated before the switch are dropped if still in flight.
+/// With explain mode on, the trace of each device's latest emitted frame is
+/// kept in `traces`.
+/// Beats `heartbeat` while running.
 /// Can be cancelled.
 #[tracing::instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
 pub async fn task_core_system(
     token: CancellationToken,
     mut rx_client_sensor_data: Receiver<ClientSensorData>,
     mut rx_host_sensor_data: Receiver<HostSensorData>,
+    mut rx_sleep_events: Receiver<SleepEvent>,
+    mut rx_overrides: Receiver<OverrideRequest>,
     tx_control_frame: Sender<ControlEvent>,
+    tx_journal: Sender<JournalRecord>,
+    resume_warm_up: Duration,
+    override_blend: Duration,
+    control_loop: ControlLoopMode,
+    strategy: Arc<dyn ControlStrategy>,
+    generation: SharedGeneration,
+    traces: Option<SharedControlTraces>,
+    heartbeat: Heartbeat,
 ) {
     info!("Started.");
 
     let mut current_host_frame: Option<HostSensorData> = None;
-    let mut current_client_frame: Option<ClientSensorData> = None;
+    let mut current_client_frames: HashMap<DeviceId, ClientSensorData> = HashMap::new();
+    let mut control_states: HashMap<DeviceId, (ControlState, Instant)> = HashMap::new();
+    let mut last_sent: HashMap<DeviceId, (ControlEvent, Instant)> = HashMap::new();
+    let mut step_timings: HashMap<DeviceId, ControlTiming> = HashMap::new();
+    let mut overrides: HashMap<DeviceId, ControlEvent> = HashMap::new();
+    let mut heartbeat_interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut timing_interval = tokio::time::interval(CONTROL_TIMING_INTERVAL);
+    // NOTE: The first tick is immediate, which would report nothing.
+    timing_interval.tick().await;
+    // NOTE: Only polled when frames can be held back.
+    let keepalive_check_interval = control_loop.keepalive_check_interval();
+    let mut keepalive_interval =
+        tokio::time::interval(keepalive_check_interval.unwrap_or(HEARTBEAT_INTERVAL));
+    let mut suspended = false;
 
     loop {
-        business_logic(current_client_frame, current_host_frame, &tx_control_frame).await;
-
-        tokio::select! {
+        // NOTE: The device whose client data arrived, or `None` if every
+        //       device may need a new frame.
+        let updated_device = tokio::select! {
             _ = token.cancelled() => {
                 warn!("Canceled.");
                 break;
             },
+            _ = heartbeat_interval.tick() => {
+                heartbeat.beat();
+                // NOTE: Nothing changed, so there is no new control frame to generate.
+                continue;
+            },
+            _ = timing_interval.tick() => {
+                report_control_timing(&mut step_timings, &tx_journal);
+                continue;
+            },
+            _ = keepalive_interval.tick(), if keepalive_check_interval.is_some() => {
+                trace!("Checking for control frames past the keepalive.");
+                None
+            },
             Ok(data) = rx_client_sensor_data.recv() => {
-                current_client_frame = Some(data);
+                current_client_frames.insert(data.device, data);
                 trace!("Received client frame.");
+                Some(data.device)
             },
             Ok(data) = rx_host_sensor_data.recv() => {
                 current_host_frame = Some(data);
                 trace!("Received host frame.");
+                None
+            }
+            Ok(request) = rx_overrides.recv() => {
+                handle_override(
+                    request,
+                    &mut overrides,
+                    &mut control_states,
+                    override_blend,
+                    &generation,
+                    &tx_journal,
+                );
+                // NOTE: Step the device now so the request takes effect.
+                Some(request.device())
             }
+            Ok(event) = rx_sleep_events.recv() => {
+                match event {
+                    SleepEvent::Suspending => {
+                        suspended = true;
+                        park(
+                            &current_client_frames,
+                            &mut control_states,
+                            &mut last_sent,
+                            &generation,
+                            &tx_control_frame,
+                        );
+                    }
+                    SleepEvent::Resumed => {
+                        suspended = false;
+                        generation.advance();
+                        warm_up(&mut control_states, resume_warm_up);
+                    }
+                }
+                continue;
+            }
+        };
+
+        if suspended {
+            trace!("Suspended. Not generating control frames.");
+            continue;
         }
+
+        let only_device = match control_loop {
+            ControlLoopMode::EveryUpdate => None,
+            ControlLoopMode::EventDriven { .. } => updated_device,
+        };
+        business_logic(
+            &current_client_frames,
+            current_host_frame,
+            only_device,
+            control_loop,
+            strategy.as_ref(),
+            &mut control_states,
+            &mut last_sent,
+            &mut step_timings,
+            &overrides,
+            &generation,
+            traces.as_ref(),
+            &tx_control_frame,
+            &tx_journal,
+        )
+        .await;
     }
 }
 
-/// Perform task business logic. If both host and client data are available,
-/// generate a control frame and try to emit it.
+/// Perform task business logic. If host data is available, step the control
+/// loop with `strategy` for `only_device`, or every device with client data if `None`, and
+/// try to emit its control frame unless `control_loop` holds it back.
+/// Each device's control state is kept with the time it was last stepped,
+/// and its last sent frame with the time it was sent. The interval between
+/// steps is recorded in `step_timings`. Devices in `overrides` are sent
+/// their held outputs instead. Frames are tagged with the current
+/// `generation`. The trace of each emitted frame is kept in `traces`, if
+/// explain mode is on. A device's outputs starting to be damped for
+/// oscillating is logged and journaled.
 #[tracing::instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
 async fn business_logic(
-    current_client_frame: Option<ClientSensorData>,
+    current_client_frames: &HashMap<DeviceId, ClientSensorData>,
     current_host_frame: Option<HostSensorData>,
+    only_device: Option<DeviceId>,
+    control_loop: ControlLoopMode,
+    strategy: &dyn ControlStrategy,
+    control_states: &mut HashMap<DeviceId, (ControlState, Instant)>,
+    last_sent: &mut HashMap<DeviceId, (ControlEvent, Instant)>,
+    step_timings: &mut HashMap<DeviceId, ControlTiming>,
+    overrides: &HashMap<DeviceId, ControlEvent>,
+    generation: &SharedGeneration,
+    traces: Option<&SharedControlTraces>,
     tx_control_frame: &Sender<ControlEvent>,
+    tx_journal: &Sender<JournalRecord>,
 ) {
     trace!("Executing business logic.");
-    if let Some(client) = current_client_frame {
-        if let Some(host) = current_host_frame {
-            let control_event = generate_control_frame(client, host);
-            if let Err(e) = tx_control_frame.send(control_event) {
-                error!("Failed to broadcast control frame. Error: {}", e);
-            } else {
-                debug!("Sent a control frame.");
+    let Some(host) = current_host_frame else {
+        return;
+    };
+    let now = Instant::now();
+    let clients = current_client_frames
+        .values()
+        .filter(|client| only_device.is_none() || only_device == Some(client.device));
+    for client in clients {
+        let _span =
+            debug_span!("control", device = %client.device, sequence = client.sequence).entered();
+        let (state, last_step) = control_states
+            .entry(client.device)
+            .or_insert((ControlState::default(), now));
+        let inputs = ControlInputs {
+            client: *client,
+            host,
+        };
+        let interval = now.duration_since(*last_step);
+        // NOTE: The first step has no previous step to measure from.
+        if state.previous_outputs.is_some() {
+            step_timings
+                .entry(client.device)
+                .or_default()
+                .record(interval, interval > MAX_STEP_DT);
+        }
+        let (mut next_state, control_event, control_trace) =
+            step_with_strategy(strategy, *state, inputs, interval.min(MAX_STEP_DT));
+        match (
+            state.oscillation.is_damping(),
+            next_state.oscillation.is_damping(),
+        ) {
+            (false, true) => {
+                warn!(
+                    "Control oscillation damped. Slew limiting the outputs of {} for at least {:?}.",
+                    client.device, OSCILLATION_DAMPING_HOLD
+                );
+                if let Err(e) = tx_journal.send(JournalRecord::OscillationDamped {
+                    device: client.device,
+                }) {
+                    trace!("Failed to queue oscillation for journaling. Error: {}", e);
+                }
+            }
+            (true, false) => info!("Outputs of {} settled. No longer damping.", client.device),
+            _ => {}
+        }
+        let control_event = ControlEvent {
+            generation: generation.current(),
+            ..apply_override(overrides.get(&client.device), control_event)
+        };
+        if overrides.contains_key(&client.device) {
+            trace!("Overridden. Sending the held outputs.");
+            // NOTE: Releasing blends back from the held outputs.
+            next_state.previous_outputs = Some(control_event);
+            next_state.transition = None;
+        }
+        *state = next_state;
+        *last_step = now;
+
+        if !control_loop.should_send(last_sent.get(&client.device), &control_event, now) {
+            trace!("Control frame unchanged. Holding it back.");
+            continue;
+        }
+        if let Err(e) = tx_control_frame.send(control_event) {
+            error!("Failed to broadcast control frame. Error: {}", e);
+        } else {
+            debug!("Sent a control frame for {}.", client.device);
+            last_sent.insert(client.device, (control_event, now));
+            if let Some(traces) = traces {
+                let control_trace = ControlTrace {
+                    overridden: overrides.contains_key(&client.device),
+                    frame: control_event,
+                    ..control_trace
+                };
+                match traces.lock() {
+                    Ok(mut traces) => {
+                        traces.insert(client.device, control_trace);
+                    }
+                    Err(e) => error!("Failed to lock control traces. Error: {}", e),
+                }
+            }
+        }
+    }
+}
+
+/// Send every device its parked outputs and remember them, so the warm-up
+/// after resuming blends up from them and isn't held back as unchanged.
+/// Parking starts a new generation, superseding frames still in flight.
+fn park(
+    current_client_frames: &HashMap<DeviceId, ClientSensorData>,
+    control_states: &mut HashMap<DeviceId, (ControlState, Instant)>,
+    last_sent: &mut HashMap<DeviceId, (ControlEvent, Instant)>,
+    generation: &SharedGeneration,
+    tx_control_frame: &Sender<ControlEvent>,
+) {
+    let now = Instant::now();
+    let generation = generation.advance();
+    for device in current_client_frames.keys() {
+        let outputs = ControlEvent {
+            generation,
+            ..park_outputs(*device)
+        };
+        let (state, last_step) = control_states
+            .entry(*device)
+            .or_insert((ControlState::default(), now));
+        *state = ControlState {
+            previous_outputs: Some(outputs),
+            transition: None,
+            ..*state
+        };
+        *last_step = now;
+        if let Err(e) = tx_control_frame.send(outputs) {
+            error!("Failed to broadcast parking frame. Error: {}", e);
+        } else {
+            info!("Parked {} for sleep.", device);
+            last_sent.insert(*device, (outputs, now));
+        }
+    }
+}
+
+/// Begin blending every device up from its last outputs over `window`.
+/// The time asleep isn't counted as a step.
+fn warm_up(control_states: &mut HashMap<DeviceId, (ControlState, Instant)>, window: Duration) {
+    let now = Instant::now();
+    for (device, (state, last_step)) in control_states.iter_mut() {
+        *state = state.begin_transition(window);
+        *last_step = now;
+        info!("Warming up {} over {:?} after resuming.", device, window);
+    }
+}
+
+/// Hold or release a device's outputs and journal the request. Releasing
+/// begins blending the device back from the held outputs over `window`.
+/// Applying a request starts a new generation.
+fn handle_override(
+    request: OverrideRequest,
+    overrides: &mut HashMap<DeviceId, ControlEvent>,
+    control_states: &mut HashMap<DeviceId, (ControlState, Instant)>,
+    window: Duration,
+    generation: &SharedGeneration,
+    tx_journal: &Sender<JournalRecord>,
+) {
+    match request {
+        OverrideRequest::Hold(frame) => {
+            overrides.insert(frame.device, frame);
+        }
+        OverrideRequest::Release(device) => {
+            if overrides.remove(&device).is_none() {
+                debug!("{} isn't overridden. Nothing to release.", device);
+                return;
+            }
+            if let Some((state, _)) = control_states.get_mut(&device) {
+                *state = state.begin_transition(window);
             }
         }
     }
+    generation.advance();
+    info!("Override: {}.", request);
+    if let Err(e) = tx_journal.send(JournalRecord::Override(request)) {
+        trace!("Failed to queue override for journaling. Error: {}", e);
+    }
+}
+
+/// Log and journal the timing of each device's control steps since the last
+/// report, warning if any gap was too long to be fully credited.
+fn report_control_timing(
+    step_timings: &mut HashMap<DeviceId, ControlTiming>,
+    tx_journal: &Sender<JournalRecord>,
+) {
+    for (device, timing) in step_timings.iter_mut() {
+        let Some(stats) = timing.finish() else {
+            continue;
+        };
+        if stats.clamped_steps > 0 {
+            warn!(
+                "{} went up to {:.0} ms between control steps, only {:?} of which was credited. Control timing: {}",
+                device, stats.max_interval_ms, MAX_STEP_DT, stats
+            );
+        } else {
+            info!("{} control timing: {}", device, stats);
+        }
+        if let Err(e) = tx_journal.send(JournalRecord::ControlTiming {
+            device: *device,
+            stats,
+        }) {
+            trace!(
+                "Failed to queue control timing for journaling. Error: {}",
+                e
+            );
+        }
+    }
 }
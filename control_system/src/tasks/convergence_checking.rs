@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use common::physical::{Percentage, Rpm};
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, trace, warn};
+
+use crate::models::{
+    alarm::{AlarmCondition, AlarmKind},
+    client_sensor_data::ReadingQuality,
+    control_event::ControlEvent,
+    convergence_checker::{ConvergenceChecker, ConvergenceStatus},
+    device_id::DeviceId,
+    device_registry::SharedDeviceRegistry,
+    duty_rpm_model::DutyRpmModel,
+    estimation::RpmEstimate,
+};
+
+/// How long a channel's speed may stay away from its expected speed before
+/// the command is considered not to be taking effect.
+const CONVERGENCE_WINDOW: Duration = Duration::from_secs(15);
+
+/// Allowed difference between expected and measured speed, relative to the
+/// channel's maximum speed. Generous since duty to speed isn't exactly linear.
+const CONVERGENCE_TOLERANCE: f32 = 0.15f32;
+
+/// Activations below this aren't checked. Many pumps and fans have a minimum
+/// speed they hold regardless of duty, so low commands can't be verified.
+const MIN_CHECKED_ACTIVATION: f32 = 20f32;
+
+/// Task: Verify that each pump and fan speed converges toward the speed
+/// expected from its latest command, using the learned duty to speed models
+/// in the device registry where available. Speeds are taken from
+/// `rx_rpm_estimates` so a single noisy report doesn't reset the window. Raises a "command not taking effect"
+/// alarm over `tx_alarm_conditions` if it doesn't, which catches stalled pumps
+/// and firmware hangs where telemetry continues but the PWM output is frozen.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_verify_control_convergence(
+    token: CancellationToken,
+    registry: SharedDeviceRegistry,
+    mut rx_rpm_estimates: Receiver<RpmEstimate>,
+    mut rx_control_frame: Receiver<ControlEvent>,
+    tx_alarm_conditions: Sender<AlarmCondition>,
+) {
+    info!("Started.");
+
+    // NOTE: Checkers are kept per device as (pump, fan).
+    let mut checkers: HashMap<DeviceId, (ConvergenceChecker, ConvergenceChecker)> = HashMap::new();
+    let mut current_control_frames: HashMap<DeviceId, ControlEvent> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            Ok(data) = rx_control_frame.recv() => {
+                current_control_frames.insert(data.device, data);
+                trace!("Received control frame.");
+            },
+            Ok(estimate) = rx_rpm_estimates.recv() => {
+                trace!("Received rpm estimate.");
+                let data = estimate.data;
+                let Some(control_frame) = current_control_frames.get(&data.device) else {
+                    continue;
+                };
+                let now = Instant::now();
+                let record = registry
+                    .read()
+                    .expect("Device registry lock poisoned.")
+                    .record(data.device.as_str());
+
+                let (pump_checker, fan_checker) = checkers.entry(data.device).or_insert_with(|| {
+                    (
+                        ConvergenceChecker::new(CONVERGENCE_WINDOW, CONVERGENCE_TOLERANCE),
+                        ConvergenceChecker::new(CONVERGENCE_WINDOW, CONVERGENCE_TOLERANCE),
+                    )
+                });
+                let expected = expected_rpm(
+                    control_frame.pump_activation,
+                    data.pump_speed,
+                    data.quality.pump_speed,
+                    &record.pump_duty_rpm,
+                );
+                check_channel(
+                    pump_checker,
+                    expected,
+                    estimate.pump_speed(),
+                    now,
+                    data.device,
+                    AlarmKind::PumpNotTakingEffect,
+                    &tx_alarm_conditions,
+                );
+                let expected = expected_rpm(
+                    control_frame.fan_activation,
+                    data.fan_speed,
+                    data.quality.fan_speed,
+                    &record.fan_duty_rpm,
+                );
+                check_channel(
+                    fan_checker,
+                    expected,
+                    estimate.fan_speed(),
+                    now,
+                    data.device,
+                    AlarmKind::FanNotTakingEffect,
+                    &tx_alarm_conditions,
+                );
+            },
+        };
+    }
+}
+
+/// Feed a single channel's expected and measured speed into its checker and
+/// report any change in whether the command is taking effect as `alarm`.
+fn check_channel(
+    checker: &mut ConvergenceChecker,
+    expected: Option<f32>,
+    measured: Rpm,
+    now: Instant,
+    device: DeviceId,
+    alarm: AlarmKind,
+    tx_alarm_conditions: &Sender<AlarmCondition>,
+) {
+    let status = match expected {
+        Some(expected) => checker.observe(expected, measured, now),
+        None => {
+            // NOTE: An alarm can't be confirmed while unchecked, so it clears.
+            let was_alarmed = checker.is_alarmed();
+            checker.reset();
+            was_alarmed.then_some(ConvergenceStatus::Recovered)
+        }
+    };
+    let present = match status {
+        Some(ConvergenceStatus::NotTakingEffect { expected, measured }) => {
+            error!(
+                "{} {}. Expected ~{} RPM, measured {} RPM.",
+                device, alarm, expected, measured
+            );
+            true
+        }
+        Some(ConvergenceStatus::Recovered) => {
+            info!("{} recovered from {}.", device, alarm);
+            false
+        }
+        None => return,
+    };
+    if let Err(e) = tx_alarm_conditions.send(AlarmCondition {
+        device,
+        kind: alarm,
+        present,
+    }) {
+        warn!("Failed to send alarm condition. Error: {}", e);
+    }
+}
+
+/// Estimate the speed a channel should settle at for a commanded activation.
+/// Returns `None` if the command or reading can't be verified.
+/// NOTE: Outside of the learned duties this assumes speed scales linearly with
+///       duty up to the reported maximum, which the hardware keeps in sync
+///       with the learned maximum.
+fn expected_rpm(
+    commanded: Percentage,
+    measured: Rpm,
+    quality: ReadingQuality,
+    model: &DutyRpmModel,
+) -> Option<f32> {
+    let commanded: f32 = commanded.into();
+    if commanded < MIN_CHECKED_ACTIVATION || quality != ReadingQuality::Good {
+        return None;
+    }
+    model
+        .expected_rpm(commanded)
+        .or(Some(measured.max_speed() * commanded / 100f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn percent(value: f32) -> Percentage {
+        Percentage::try_from(value).expect("Failed to get Percentage.")
+    }
+
+    #[test]
+    fn test_expected_rpm() {
+        let measured = Rpm::new(2000f32, 500f32).expect("Failed to get RPM.");
+        let model = DutyRpmModel::default();
+        assert_eq!(
+            expected_rpm(percent(50f32), measured, ReadingQuality::Good, &model),
+            Some(1000f32)
+        );
+        assert_eq!(
+            expected_rpm(percent(100f32), measured, ReadingQuality::Good, &model),
+            Some(2000f32)
+        );
+        assert_eq!(
+            expected_rpm(percent(10f32), measured, ReadingQuality::Good, &model),
+            None
+        );
+        assert_eq!(
+            expected_rpm(percent(50f32), measured, ReadingQuality::Saturated, &model),
+            None
+        );
+    }
+
+    #[test]
+    fn test_expected_rpm_uses_learned_model() {
+        let measured = Rpm::new(2000f32, 500f32).expect("Failed to get RPM.");
+        let mut model = DutyRpmModel::default();
+        model.record(30f32, 900f32);
+        model.record(60f32, 1500f32);
+
+        assert_eq!(
+            expected_rpm(percent(50f32), measured, ReadingQuality::Good, &model),
+            Some(1300f32)
+        );
+        assert_eq!(
+            expected_rpm(percent(80f32), measured, ReadingQuality::Good, &model),
+            Some(1600f32)
+        );
+    }
+}
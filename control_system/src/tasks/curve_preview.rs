@@ -0,0 +1,60 @@
+use std::path::Path;
+
+use crate::{
+    error::ControlError,
+    models::{
+        control_socket::{ControlRequest, ControlResponse},
+        curve_preview::{CurveCommand, CurveDefinition, CurveOutput},
+        temperature::{Temperature, TemperatureUnit},
+    },
+};
+
+use super::control_socket::request;
+
+/// Answer a curve evaluation request from the control socket.
+pub fn handle_curve_request(curve: &CurveDefinition, samples: usize) -> ControlResponse {
+    match curve.sample(samples) {
+        Ok(samples) => ControlResponse::CurveSamples(samples),
+        Err(e) => ControlResponse::Error(e.to_string()),
+    }
+}
+
+/// Run `curve`: sample a candidate curve through the control system listening
+/// at `socket_path` and print each sample in the configured temperature
+/// unit. The curve is evaluated locally if the control system isn't running,
+/// which gives the same result.
+pub fn handle_curve_command(socket_path: &Path, command: CurveCommand) -> Result<(), ControlError> {
+    let curve_request = ControlRequest::EvaluateCurve {
+        curve: command.curve.clone(),
+        samples: command.samples,
+    };
+    let response = match request(socket_path, &curve_request) {
+        Ok(response) => response,
+        Err(e) => {
+            println!(
+                "Control system isn't reachable at {}, evaluating locally. Error: {}",
+                socket_path.display(),
+                e
+            );
+            handle_curve_request(&command.curve, command.samples)
+        }
+    };
+
+    match response {
+        ControlResponse::CurveSamples(samples) => {
+            let unit = TemperatureUnit::from_env();
+            for sample in samples {
+                let y = match (sample.y, command.curve.output) {
+                    (None, _) => "no output".to_string(),
+                    (Some(y), CurveOutput::Duty) => format!("{:.2}%", y),
+                    (Some(y), CurveOutput::Valve) if y < 0.5f32 => "closed".to_string(),
+                    (Some(_), CurveOutput::Valve) => "open".to_string(),
+                };
+                let x = Temperature { value: sample.x }.in_unit(unit);
+                println!("{:>7.2} {} -> {}", x, unit, y);
+            }
+        }
+        response => return Err(ControlError::from_response(response)),
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+#[cfg(feature = "control-socket")]
+use std::time::Duration;
+
+#[cfg(feature = "control-socket")]
+use common::packet::RequestLogsPacket;
+use common::packet::{LogLevel, Packet};
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, trace, warn};
+
+#[cfg(feature = "control-socket")]
+use crate::models::control_socket::ControlResponse;
+use crate::models::{
+    addressed_packet::AddressedPacket, device_log::SharedDeviceLogs, journal::JournalRecord,
+};
+
+/// How long devices are given to send their log buffers before they are
+/// served.
+#[cfg(feature = "control-socket")]
+const DEVICE_LOG_REPLAY_WAIT: Duration = Duration::from_millis(500);
+
+/// Task: Forward the log lines devices report into the host's logs, at the
+/// severity the device gave, with `device` and `module` fields saying which
+/// device and which part of its firmware it came from. Each line is kept in
+/// `logs`, which tracks every device once it reports its capabilities, and is
+/// also journaled if `journal` is set.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_forward_device_logs(
+    token: CancellationToken,
+    mut rx_packets_from_hw: Receiver<AddressedPacket>,
+    tx_journal: Sender<JournalRecord>,
+    logs: SharedDeviceLogs,
+    journal: bool,
+) {
+    info!("Started.");
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            Ok(data) = rx_packets_from_hw.recv() => {
+                let device = data.device;
+                let log = match data.packet {
+                    Packet::ReportLogLine(log) => log,
+                    Packet::ReportDeviceInfo(_) => {
+                        match logs.lock() {
+                            Ok(mut logs) => logs.track(device),
+                            Err(e) => error!("Failed to lock device logs. Error: {}", e),
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+                match logs.lock() {
+                    Ok(mut logs) => logs.record(device, log),
+                    Err(e) => error!("Failed to lock device logs. Error: {}", e),
+                }
+                let (module, message) = (log.module, log.message.as_str());
+                match log.level {
+                    LogLevel::Trace => trace!(device = %device, module, "{}", message),
+                    LogLevel::Info => info!(device = %device, module, "{}", message),
+                    LogLevel::Warn => warn!(device = %device, module, "{}", message),
+                    LogLevel::Error => error!(device = %device, module, "{}", message),
+                }
+                if !journal {
+                    continue;
+                }
+                if let Err(e) = tx_journal.send(JournalRecord::DeviceLog { device, log }) {
+                    trace!("Failed to queue device log line for journaling. Error: {}", e);
+                }
+            },
+        };
+    }
+}
+
+/// Answer a device logs request from the control socket. Asks every tracked
+/// device to send its log buffer again and serves what arrives within
+/// `DEVICE_LOG_REPLAY_WAIT`, so the lines are the devices' buffers as they
+/// are now.
+#[cfg(feature = "control-socket")]
+pub async fn handle_device_logs_request(
+    logs: &SharedDeviceLogs,
+    tx_packets_to_hw: &Sender<AddressedPacket>,
+) -> ControlResponse {
+    let devices = match logs.lock() {
+        Ok(mut logs) => logs.clear(),
+        Err(e) => {
+            return ControlResponse::Error(format!("Failed to lock device logs. Error: {}", e))
+        }
+    };
+    for device in devices {
+        let request = Packet::RequestLogs(RequestLogsPacket);
+        if let Err(e) = tx_packets_to_hw.send(AddressedPacket::new(device, request)) {
+            warn!("Failed to queue log request for {}. Error: {}", device, e);
+        }
+    }
+    tokio::time::sleep(DEVICE_LOG_REPLAY_WAIT).await;
+    match logs.lock() {
+        Ok(logs) => ControlResponse::DeviceLogs(logs.logs()),
+        Err(e) => ControlResponse::Error(format!("Failed to lock device logs. Error: {}", e)),
+    }
+}
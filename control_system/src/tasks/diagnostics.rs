@@ -0,0 +1,30 @@
+use std::path::Path;
+
+use crate::{
+    error::ControlError,
+    models::{
+        control_socket::{ControlRequest, ControlResponse},
+        daemon_config::DaemonConfig,
+        device_log::DeviceLog,
+    },
+};
+
+use super::control_socket::request;
+
+/// Ask the control system listening at `socket_path` for the configuration
+/// it is running with.
+pub fn request_daemon_config(socket_path: &Path) -> Result<DaemonConfig, ControlError> {
+    match request(socket_path, &ControlRequest::DaemonConfig)? {
+        ControlResponse::DaemonConfig(config) => Ok(config),
+        response => Err(ControlError::from_response(response)),
+    }
+}
+
+/// Ask the control system listening at `socket_path` for the log buffer of
+/// every device which has connected.
+pub fn request_device_logs(socket_path: &Path) -> Result<Vec<DeviceLog>, ControlError> {
+    match request(socket_path, &ControlRequest::DeviceLogs)? {
+        ControlResponse::DeviceLogs(logs) => Ok(logs),
+        response => Err(ControlError::from_response(response)),
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::broadcast::Receiver;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn};
+
+use crate::models::{
+    client_sensor_data::{ClientSensorData, ReadingQuality},
+    control_event::ControlEvent,
+    device_id::DeviceId,
+    device_registry::SharedDeviceRegistry,
+    duty_rpm_model::DutyRpmLearner,
+};
+
+/// How long a command must be held before its speed is considered settled.
+const DUTY_RPM_SETTLE_TIME: Duration = Duration::from_secs(8);
+
+/// How often newly learned duty to speed points are saved to the registry.
+/// Samples arrive with every sensor report, far too often to save each one.
+const DUTY_RPM_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Task: Learn how fast each pump and fan settles for every commanded duty.
+/// The resulting duty to speed models are kept in the shared device registry
+/// where strategies and the convergence checker can use them, and are saved
+/// periodically.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_learn_duty_rpm(
+    token: CancellationToken,
+    registry_path: PathBuf,
+    registry: SharedDeviceRegistry,
+    mut rx_client_sensor_data: Receiver<ClientSensorData>,
+    mut rx_control_frame: Receiver<ControlEvent>,
+) {
+    info!("Started.");
+
+    // NOTE: Learners are kept per device as (pump, fan).
+    let mut learners: HashMap<DeviceId, (DutyRpmLearner, DutyRpmLearner)> = HashMap::new();
+    let mut current_control_frames: HashMap<DeviceId, ControlEvent> = HashMap::new();
+    let mut unsaved = false;
+    let mut save_interval = tokio::time::interval(DUTY_RPM_SAVE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                if unsaved {
+                    save_registry(&registry, &registry_path);
+                }
+                break;
+            },
+            _ = save_interval.tick() => {
+                if unsaved {
+                    save_registry(&registry, &registry_path);
+                    unsaved = false;
+                }
+            },
+            Ok(data) = rx_control_frame.recv() => {
+                current_control_frames.insert(data.device, data);
+                trace!("Received control frame.");
+            },
+            Ok(data) = rx_client_sensor_data.recv() => {
+                trace!("Received client frame.");
+                let Some(control_frame) = current_control_frames.get(&data.device) else {
+                    continue;
+                };
+                let now = Instant::now();
+
+                let (pump_learner, fan_learner) = learners.entry(data.device).or_insert_with(|| {
+                    (
+                        DutyRpmLearner::new(DUTY_RPM_SETTLE_TIME),
+                        DutyRpmLearner::new(DUTY_RPM_SETTLE_TIME),
+                    )
+                });
+                let pump_sample = pump_learner.observe(control_frame.pump_activation, data.pump_speed, now);
+                let fan_sample = fan_learner.observe(control_frame.fan_activation, data.fan_speed, now);
+
+                let mut registry = registry.write().expect("Device registry lock poisoned.");
+                let record = registry.record_mut(data.device.as_str());
+                // NOTE: Untrustworthy readings would teach the model the wrong speed.
+                if let (Some((duty, rpm)), ReadingQuality::Good) = (pump_sample, data.quality.pump_speed) {
+                    record.pump_duty_rpm.record(duty, rpm);
+                    unsaved = true;
+                }
+                if let (Some((duty, rpm)), ReadingQuality::Good) = (fan_sample, data.quality.fan_speed) {
+                    record.fan_duty_rpm.record(duty, rpm);
+                    unsaved = true;
+                }
+            },
+        };
+    }
+}
+
+/// Save the shared registry, logging any failure.
+fn save_registry(registry: &SharedDeviceRegistry, registry_path: &Path) {
+    let registry = registry.read().expect("Device registry lock poisoned.");
+    match registry.save(registry_path) {
+        Err(e) => error!("Failed to save device registry. Error: {}", e),
+        Ok(_) => debug!("Saved learned duty to speed models to device registry."),
+    }
+}
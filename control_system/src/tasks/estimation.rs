@@ -0,0 +1,105 @@
+use std::{collections::HashMap, time::Instant};
+
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, trace, warn};
+
+use crate::models::{
+    client_sensor_data::{ClientSensorData, ReadingQuality},
+    control_event::ControlEvent,
+    device_id::DeviceId,
+    device_registry::SharedDeviceRegistry,
+    estimation::{ChannelEstimate, RpmEstimate, RpmEstimator},
+};
+
+/// Task: Estimate each pump and fan speed by fusing the speed expected from
+/// its command, using the learned duty to speed models in the device
+/// registry, with the reported speed. Estimates are sent over
+/// `tx_rpm_estimates` for every report, for strategies and health checks
+/// which want a smoothed speed. Reports too far from the estimate to be
+/// plausible are warned about and not fused.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_estimate_rpm(
+    token: CancellationToken,
+    registry: SharedDeviceRegistry,
+    mut rx_client_sensor_data: Receiver<ClientSensorData>,
+    mut rx_control_frame: Receiver<ControlEvent>,
+    tx_rpm_estimates: Sender<RpmEstimate>,
+) {
+    info!("Started.");
+
+    // NOTE: Estimators are kept per device as (pump, fan).
+    let mut estimators: HashMap<DeviceId, (RpmEstimator, RpmEstimator)> = HashMap::new();
+    let mut current_control_frames: HashMap<DeviceId, ControlEvent> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            Ok(data) = rx_control_frame.recv() => {
+                current_control_frames.insert(data.device, data);
+                trace!("Received control frame.");
+            },
+            Ok(data) = rx_client_sensor_data.recv() => {
+                trace!("Received client frame.");
+                let now = Instant::now();
+                let record = registry
+                    .read()
+                    .expect("Device registry lock poisoned.")
+                    .record(data.device.as_str());
+                let control_frame = current_control_frames.get(&data.device);
+
+                let (pump_estimator, fan_estimator) = estimators.entry(data.device).or_default();
+                let expected = control_frame
+                    .and_then(|frame| record.pump_duty_rpm.expected_rpm(frame.pump_activation.into()));
+                let pump = pump_estimator.observe(
+                    expected,
+                    trusted_speed(data.pump_speed.speed(), data.quality.pump_speed),
+                    now,
+                );
+                let expected = control_frame
+                    .and_then(|frame| record.fan_duty_rpm.expected_rpm(frame.fan_activation.into()));
+                let fan = fan_estimator.observe(
+                    expected,
+                    trusted_speed(data.fan_speed.speed(), data.quality.fan_speed),
+                    now,
+                );
+                warn_outlier(&data, "pump", data.pump_speed.speed(), pump);
+                warn_outlier(&data, "fan", data.fan_speed.speed(), fan);
+
+                if let Err(e) = tx_rpm_estimates.send(RpmEstimate { data, pump, fan }) {
+                    trace!("Failed to send rpm estimate. Error: {}", e);
+                }
+            },
+        };
+    }
+}
+
+/// Get a reported speed if its reading can be trusted.
+/// NOTE: Saturated readings would pull the estimate below the true speed.
+fn trusted_speed(speed: f32, quality: ReadingQuality) -> Option<f32> {
+    (quality == ReadingQuality::Good).then_some(speed)
+}
+
+/// Warn about a reported speed which was too implausible to be fused.
+fn warn_outlier(
+    data: &ClientSensorData,
+    channel: &str,
+    measured: f32,
+    estimate: Option<ChannelEstimate>,
+) {
+    let Some(estimate) = estimate.filter(|estimate| estimate.outlier) else {
+        return;
+    };
+    warn!(
+        "{} reported an implausible {} speed of {} RPM. Estimated {} RPM, residual {:.1} sigma.",
+        data.device,
+        channel,
+        measured,
+        estimate.rpm,
+        estimate.residual.unwrap_or_default()
+    );
+}
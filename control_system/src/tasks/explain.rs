@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use crate::{
+    error::ControlError,
+    models::{
+        control_socket::{ControlRequest, ControlResponse},
+        control_trace::{SharedControlTraces, EXPLAIN_ENV_VAR},
+        device_id::DeviceId,
+        temperature::TemperatureUnit,
+    },
+};
+
+use super::control_socket::request;
+
+/// Answer an explain request from the control socket with the latest trace
+/// of `device`, or of every device if `None`, ordered by device.
+pub fn handle_explain_request(
+    traces: Option<&SharedControlTraces>,
+    device: Option<DeviceId>,
+) -> ControlResponse {
+    let Some(traces) = traces else {
+        return ControlResponse::Error(format!(
+            "Explain mode is off. Set {}=1 to trace control frames.",
+            EXPLAIN_ENV_VAR
+        ));
+    };
+    let traces = match traces.lock() {
+        Ok(traces) => traces,
+        Err(e) => return ControlResponse::Error(format!("Failed to lock traces. Error: {}", e)),
+    };
+    let mut traces = traces
+        .values()
+        .filter(|trace| device.is_none() || device == Some(trace.device))
+        .copied()
+        .collect::<Vec<_>>();
+    traces.sort_by_key(|trace| trace.device);
+    ControlResponse::Traces(traces)
+}
+
+/// Run `explain`: print why the latest control frames of the control system
+/// listening at `socket_path` have the outputs they do.
+pub fn handle_explain_command(
+    socket_path: &Path,
+    device: Option<DeviceId>,
+) -> Result<(), ControlError> {
+    match request(socket_path, &ControlRequest::Explain { device })? {
+        ControlResponse::Traces(traces) if traces.is_empty() => {
+            println!("No control frames traced yet.")
+        }
+        ControlResponse::Traces(traces) => {
+            let unit = TemperatureUnit::from_env();
+            for trace in traces {
+                println!("{}", trace.describe(unit));
+            }
+        }
+        response => return Err(ControlError::from_response(response)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        controls::{step_explained, ControlInputs, ControlState},
+        models::{
+            client_sensor_data::{ClientSensorData, SensorQuality},
+            host_sensor_data::HostSensorData,
+            temperature::Temperature,
+        },
+    };
+    use common::physical::{Rpm, ValveState};
+
+    fn record(traces: &SharedControlTraces, device: &str) {
+        let inputs = ControlInputs {
+            client: ClientSensorData {
+                device: DeviceId::new(device),
+                pump_speed: Rpm::new(2000f32, 800f32).expect("Failed to get RPM."),
+                fan_speed: Rpm::new(2000f32, 500f32).expect("Failed to get RPM."),
+                extra_fan_speeds: [None; 3],
+                valve_state: ValveState::Open,
+                board_temperature: None,
+                coolant_temperature: None,
+                pump_current: None,
+                fan_current: None,
+                flow_rate: None,
+                low_flow: false,
+                quality: SensorQuality::GOOD,
+                sequence: None,
+                sampled_at_ms: None,
+            },
+            host: HostSensorData {
+                cpu_temperature: Temperature::try_from(70f32).expect("Failed to get Temperature."),
+                raw_cpu_temperature: None,
+            },
+        };
+        let (_, _, trace) = step_explained(ControlState::default(), inputs, Duration::ZERO);
+        traces.lock().unwrap().insert(trace.device, trace);
+    }
+
+    #[test]
+    fn test_handle_explain_request() {
+        assert!(matches!(
+            handle_explain_request(None, None),
+            ControlResponse::Error(_)
+        ));
+
+        let traces = SharedControlTraces::default();
+        record(&traces, "b");
+        record(&traces, "a");
+        let ControlResponse::Traces(all) = handle_explain_request(Some(&traces), None) else {
+            panic!("Expected traces.");
+        };
+        assert_eq!(
+            all.iter().map(|trace| trace.device).collect::<Vec<_>>(),
+            vec![DeviceId::new("a"), DeviceId::new("b")]
+        );
+
+        let ControlResponse::Traces(one) =
+            handle_explain_request(Some(&traces), Some(DeviceId::new("b")))
+        else {
+            panic!("Expected traces.");
+        };
+        assert_eq!(one.len(), 1);
+        assert_eq!(one[0].device, DeviceId::new("b"));
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::{
+    error::ControlError,
+    models::{
+        control_socket::{ControlRequest, ControlResponse},
+        device_id::DeviceId,
+        fault_simulation::{
+            PlantFault, SharedPlantFaults, SimulateFaultCommand, FAULT_SIMULATION_ENV_VAR,
+        },
+    },
+};
+
+use super::control_socket::request;
+
+/// Answer a simulate fault request from the control socket by simulating
+/// `fault` on `device`, or on every device if `None`, or clearing the faults
+/// there if `fault` is `None`. Refused unless fault simulation is on.
+pub fn handle_simulate_fault_request(
+    faults: Option<&SharedPlantFaults>,
+    device: Option<DeviceId>,
+    fault: Option<PlantFault>,
+) -> ControlResponse {
+    let Some(faults) = faults else {
+        return ControlResponse::Error(format!(
+            "Fault simulation is off. Set {}=1 to simulate faults.",
+            FAULT_SIMULATION_ENV_VAR
+        ));
+    };
+    let mut faults = match faults.lock() {
+        Ok(faults) => faults,
+        Err(e) => {
+            return ControlResponse::Error(format!("Failed to lock simulated faults. Error: {}", e))
+        }
+    };
+    let target = match device {
+        Some(device) => device.to_string(),
+        None => "every device".to_string(),
+    };
+    match fault {
+        Some(fault) => {
+            warn!("Simulating {} on {}.", fault, target);
+            faults.simulate(device, fault);
+        }
+        None => {
+            warn!("Stopped simulating faults on {}.", target);
+            faults.clear(device);
+        }
+    }
+    ControlResponse::SimulatedFaults(faults.list())
+}
+
+/// Run `simulate-fault`: simulate a plant fault on the control system
+/// listening at `socket_path`, then print every fault being simulated.
+pub fn handle_simulate_fault_command(
+    socket_path: &Path,
+    command: SimulateFaultCommand,
+) -> Result<(), ControlError> {
+    let simulate = ControlRequest::SimulateFault {
+        device: command.device,
+        fault: command.fault,
+    };
+    match request(socket_path, &simulate)? {
+        ControlResponse::SimulatedFaults(faults) if faults.is_empty() => {
+            println!("No faults simulated.")
+        }
+        ControlResponse::SimulatedFaults(faults) => {
+            for simulated in faults {
+                match simulated.device {
+                    Some(device) => {
+                        println!("Simulating {} on {}.", simulated.fault, device.as_str())
+                    }
+                    None => println!("Simulating {} on every device.", simulated.fault),
+                }
+            }
+        }
+        response => return Err(ControlError::from_response(response)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fault_simulation::SimulatedFault;
+
+    #[test]
+    fn test_handle_simulate_fault_request() {
+        assert!(matches!(
+            handle_simulate_fault_request(None, None, Some(PlantFault::PumpStall)),
+            ControlResponse::Error(_)
+        ));
+
+        let faults = SharedPlantFaults::default();
+        let device = DeviceId::new("1324");
+        assert_eq!(
+            handle_simulate_fault_request(
+                Some(&faults),
+                Some(device),
+                Some(PlantFault::ValveStuck)
+            ),
+            ControlResponse::SimulatedFaults(vec![SimulatedFault {
+                device: Some(device),
+                fault: PlantFault::ValveStuck,
+            }])
+        );
+        assert_eq!(
+            faults.lock().unwrap().get(device),
+            Some(PlantFault::ValveStuck)
+        );
+
+        assert_eq!(
+            handle_simulate_fault_request(Some(&faults), None, None),
+            ControlResponse::SimulatedFaults(vec![])
+        );
+        assert_eq!(faults.lock().unwrap().get(device), None);
+    }
+}
@@ -1,9 +1,21 @@
-use std::io;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
 
-use crate::models::temperature::{Temperature, TemperatureError};
-use anyhow::Result;
+use crate::models::{
+    journal::JournalRecord,
+    temperature::{Temperature, TemperatureError},
+    temperature_calibration::TemperatureCalibration,
+    temperature_failover::{FailoverEvent, TemperatureBackend, TemperatureFailover},
+};
+use serde_json::{Map, Value};
 use systemstat::{Platform, System};
 use thiserror::Error;
+use tokio::sync::broadcast::Sender;
+use tracing::{debug, info, trace, warn};
 
 /// This service allows separation of the external logic of getting
 /// the cpu temperature from the business logic which makes the system
@@ -15,8 +27,96 @@ pub trait HostCpuTemperatureService {
     fn get_cpu_temp(&self) -> Result<Temperature, CpuTemperatureServiceError>;
 }
 
+/// A host temperature service along with the name used to refer to it when
+/// aggregating, such as `cpu`, and the calibration applied to its readings.
+pub struct TemperatureSource {
+    pub name: &'static str,
+    pub service: Box<dyn HostCpuTemperatureService + Send + Sync>,
+    pub calibration: TemperatureCalibration,
+}
+
+impl TemperatureSource {
+    pub fn new(
+        name: &'static str,
+        service: impl HostCpuTemperatureService + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name,
+            service: Box::new(service),
+            calibration: TemperatureCalibration::default(),
+        }
+    }
+
+    pub fn with_calibration(self, calibration: TemperatureCalibration) -> Self {
+        Self {
+            calibration,
+            ..self
+        }
+    }
+
+    /// Read the source, returning the raw and the calibrated temperature.
+    /// Will return FailedToParse if the calibrated temperature is invalid.
+    pub fn read(&self) -> Result<(Temperature, Temperature), CpuTemperatureServiceError> {
+        let raw = self.service.get_cpu_temp()?;
+        let calibrated = self
+            .calibration
+            .apply(raw)
+            .map_err(CpuTemperatureServiceError::FailedToParse)?;
+        Ok((raw, calibrated))
+    }
+}
+
 pub struct HostCpuTemperatureServiceActual;
 
+/// Environment variable holding a command which prints `sensors -j` style
+/// JSON. When set, it is used instead of reading sysfs through systemstat.
+pub const SENSORS_COMMAND_ENV_VAR: &str = "PRANDTL_SENSORS_COMMAND";
+
+/// Environment variable holding a command which prints a single temperature in
+/// Celsius. When set, it is used instead of any other temperature source.
+pub const TEMPERATURE_COMMAND_ENV_VAR: &str = "PRANDTL_TEMPERATURE_COMMAND";
+
+/// Where the kernel exposes hardware monitoring chips.
+const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+/// Prefixes of lm-sensors and hwmon chip names which report cpu temperatures.
+const CPU_CHIP_PREFIXES: [&str; 5] = ["coretemp", "k10temp", "zenpower", "cpu_thermal", "cpu-"];
+
+/// Reads the cpu temperature by running a command which prints lm-sensors
+/// `sensors -j` JSON. Useful in containers where sysfs isn't mounted but
+/// `sensors` output is available through an exec hook.
+pub struct HostCpuTemperatureServiceLmSensors {
+    program: String,
+    args: Vec<String>,
+}
+
+/// Reads the temperature by running a user supplied command, so exotic sources
+/// such as IPMI, external probes or a GPU hotspot can be used without code
+/// changes. The command prints the temperature in Celsius either as a bare
+/// number or as JSON like `{"temperature": 52.5}`.
+pub struct HostCpuTemperatureServiceCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+/// Reads the cpu temperature straight from the hwmon chips in sysfs, without
+/// going through systemstat's choice of thermal zone.
+pub struct HostCpuTemperatureServiceHwmon {
+    root: PathBuf,
+}
+
+/// Reads the cpu temperature from an ordered chain of backends, failing over
+/// to the next one when the active backend keeps failing and back once a
+/// preferred one recovers. Each change is logged and journaled.
+pub struct HostCpuTemperatureServiceFailover {
+    backends: Vec<(
+        TemperatureBackend,
+        Box<dyn HostCpuTemperatureService + Send + Sync>,
+    )>,
+    failover: Mutex<TemperatureFailover>,
+    tx_journal: Sender<JournalRecord>,
+}
+
 #[derive(Error, Debug)]
 pub enum CpuTemperatureServiceError {
     /// This occurs if systemstat fails to report the temperature.
@@ -26,6 +126,23 @@ pub enum CpuTemperatureServiceError {
     /// This occurs if the Temperature model fails to parse the raw f32 temperature.
     #[error("Failed to parse cpu temperature.")]
     FailedToParse(TemperatureError),
+
+    /// This occurs if the sensors command exits unsuccessfully.
+    #[error("Sensors command failed. Status: {0}")]
+    CommandFailed(std::process::ExitStatus),
+
+    /// This occurs if the sensors command output isn't valid JSON.
+    #[error("Failed to parse sensors output.")]
+    FailedToParseOutput(serde_json::Error),
+
+    /// This occurs if the sensors output has no cpu temperature.
+    #[error("No cpu temperature found in sensors output.")]
+    NoCpuSensor,
+
+    /// This occurs if the temperature command's JSON output has no
+    /// temperature.
+    #[error("No temperature found in command output.")]
+    NoTemperature,
 }
 
 impl HostCpuTemperatureService for HostCpuTemperatureServiceActual {
@@ -44,3 +161,481 @@ impl HostCpuTemperatureService for HostCpuTemperatureServiceActual {
         Temperature::try_from(raw).map_err(|e| CpuTemperatureServiceError::FailedToParse(e))
     }
 }
+
+/// Split a whitespace separated command line into its program and arguments.
+/// Returns `None` if the command is empty.
+fn split_command(command: &str) -> Option<(String, Vec<String>)> {
+    let mut parts = command.split_whitespace().map(str::to_string);
+    Some((parts.next()?, parts.collect()))
+}
+
+/// Run a command and get its output. Will return a FailedToRead error if the
+/// command can't be run and CommandFailed if it exits unsuccessfully.
+fn run_command(program: &str, args: &[String]) -> Result<Vec<u8>, CpuTemperatureServiceError> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(CpuTemperatureServiceError::FailedToRead)?;
+    if !output.status.success() {
+        return Err(CpuTemperatureServiceError::CommandFailed(output.status));
+    }
+    Ok(output.stdout)
+}
+
+impl HostCpuTemperatureServiceLmSensors {
+    /// Create the service from a whitespace separated command line, such as
+    /// `sensors -j`. Returns `None` if the command is empty.
+    pub fn new(command: &str) -> Option<Self> {
+        let (program, args) = split_command(command)?;
+        Some(Self { program, args })
+    }
+
+    /// Create the service from `SENSORS_COMMAND_ENV_VAR`, if it is set.
+    pub fn from_env() -> Option<Self> {
+        Self::new(&std::env::var(SENSORS_COMMAND_ENV_VAR).ok()?)
+    }
+}
+
+impl HostCpuTemperatureService for HostCpuTemperatureServiceLmSensors {
+    /// Run the configured command and parse the cpu temperature from its
+    /// output. Will return a FailedToRead error if the command can't be run,
+    /// CommandFailed if it exits unsuccessfully, and any error from
+    /// `parse_sensors_output` otherwise.
+    fn get_cpu_temp(&self) -> Result<Temperature, CpuTemperatureServiceError> {
+        parse_sensors_output(&run_command(&self.program, &self.args)?)
+    }
+}
+
+impl HostCpuTemperatureServiceCommand {
+    /// Create the service from a whitespace separated command line, such as
+    /// `ipmi-temp --sensor cpu`. Returns `None` if the command is empty.
+    pub fn new(command: &str) -> Option<Self> {
+        let (program, args) = split_command(command)?;
+        Some(Self { program, args })
+    }
+
+    /// Create the service from `TEMPERATURE_COMMAND_ENV_VAR`, if it is set.
+    pub fn from_env() -> Option<Self> {
+        Self::new(&std::env::var(TEMPERATURE_COMMAND_ENV_VAR).ok()?)
+    }
+}
+
+impl HostCpuTemperatureService for HostCpuTemperatureServiceCommand {
+    /// Run the configured command and parse the temperature from its output.
+    /// Will return a FailedToRead error if the command can't be run,
+    /// CommandFailed if it exits unsuccessfully, and any error from
+    /// `parse_command_output` otherwise.
+    fn get_cpu_temp(&self) -> Result<Temperature, CpuTemperatureServiceError> {
+        parse_command_output(&run_command(&self.program, &self.args)?)
+    }
+}
+
+impl HostCpuTemperatureServiceHwmon {
+    /// Create the service reading from `HWMON_ROOT`.
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::from(HWMON_ROOT),
+        }
+    }
+}
+
+impl Default for HostCpuTemperatureServiceHwmon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostCpuTemperatureService for HostCpuTemperatureServiceHwmon {
+    /// Read the hottest cpu temperature under the hwmon root. Will return any
+    /// error from `read_hwmon_temperature`.
+    fn get_cpu_temp(&self) -> Result<Temperature, CpuTemperatureServiceError> {
+        read_hwmon_temperature(&self.root)
+    }
+}
+
+/// Read the hottest `temp*_input` of every hwmon chip under `root` whose name
+/// is a cpu chip. The kernel reports them in millidegrees Celsius.
+/// Will return FailedToRead if `root` can't be listed, NoCpuSensor if no cpu
+/// chip reports a temperature, and FailedToParse if the temperature is
+/// invalid.
+fn read_hwmon_temperature(root: &Path) -> Result<Temperature, CpuTemperatureServiceError> {
+    let mut hottest: Option<f32> = None;
+    for chip in fs::read_dir(root)
+        .map_err(CpuTemperatureServiceError::FailedToRead)?
+        .flatten()
+    {
+        let chip = chip.path();
+        let Ok(name) = fs::read_to_string(chip.join("name")) else {
+            continue;
+        };
+        if !CPU_CHIP_PREFIXES
+            .iter()
+            .any(|prefix| name.trim().starts_with(prefix))
+        {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&chip) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if !(file_name.starts_with("temp") && file_name.ends_with("_input")) {
+                continue;
+            }
+            let Some(millidegrees) = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|contents| contents.trim().parse::<f32>().ok())
+            else {
+                continue;
+            };
+            hottest = Some(hottest.map_or(millidegrees, |hottest| hottest.max(millidegrees)));
+        }
+    }
+
+    let raw = hottest.ok_or(CpuTemperatureServiceError::NoCpuSensor)? / 1000f32;
+    Temperature::try_from(raw).map_err(CpuTemperatureServiceError::FailedToParse)
+}
+
+/// Create the service reading from `backend`. Returns `None` if the backend
+/// runs a command which isn't configured.
+pub fn backend_service(
+    backend: TemperatureBackend,
+) -> Option<Box<dyn HostCpuTemperatureService + Send + Sync>> {
+    Some(match backend {
+        TemperatureBackend::Hwmon => Box::new(HostCpuTemperatureServiceHwmon::new()),
+        TemperatureBackend::Systemstat => Box::new(HostCpuTemperatureServiceActual),
+        TemperatureBackend::Sensors => Box::new(HostCpuTemperatureServiceLmSensors::from_env()?),
+        TemperatureBackend::Command => Box::new(HostCpuTemperatureServiceCommand::from_env()?),
+    })
+}
+
+/// Get the environment variable holding the command `backend` runs, if it
+/// runs one.
+pub fn backend_command_env_var(backend: TemperatureBackend) -> Option<&'static str> {
+    match backend {
+        TemperatureBackend::Hwmon | TemperatureBackend::Systemstat => None,
+        TemperatureBackend::Sensors => Some(SENSORS_COMMAND_ENV_VAR),
+        TemperatureBackend::Command => Some(TEMPERATURE_COMMAND_ENV_VAR),
+    }
+}
+
+impl HostCpuTemperatureServiceFailover {
+    /// Create the service reading from `backends` in order of preference.
+    /// Backends whose command isn't configured are left out with a warning.
+    /// Returns `None` if none are left.
+    pub fn new(backends: &[TemperatureBackend], tx_journal: Sender<JournalRecord>) -> Option<Self> {
+        let backends: Vec<(
+            TemperatureBackend,
+            Box<dyn HostCpuTemperatureService + Send + Sync>,
+        )> = backends
+            .iter()
+            .filter_map(|&backend| {
+                let Some(service) = backend_service(backend) else {
+                    warn!(
+                        "Skipping the {} backend since {} isn't set.",
+                        backend,
+                        backend_command_env_var(backend).unwrap_or_default()
+                    );
+                    return None;
+                };
+                Some((backend, service))
+            })
+            .collect();
+        if backends.is_empty() {
+            return None;
+        }
+
+        let failover =
+            TemperatureFailover::new(backends.iter().map(|(backend, _)| *backend).collect());
+        Some(Self {
+            backends,
+            failover: Mutex::new(failover),
+            tx_journal,
+        })
+    }
+}
+
+impl HostCpuTemperatureService for HostCpuTemperatureServiceFailover {
+    /// Read from the active backend, or the next one that works if it fails.
+    /// Will return the error of the last backend tried if none of them work.
+    fn get_cpu_temp(&self) -> Result<Temperature, CpuTemperatureServiceError> {
+        let mut failover = self
+            .failover
+            .lock()
+            .expect("Temperature failover lock poisoned.");
+        let mut last_error = None;
+        let (temperature, event) = failover.poll(|index| {
+            let (backend, service) = &self.backends[index];
+            match service.get_cpu_temp() {
+                Ok(temperature) => Some(temperature),
+                Err(e) => {
+                    debug!("Failed to read the {} backend. Error: {}", backend, e);
+                    last_error = Some(e);
+                    None
+                }
+            }
+        });
+
+        if let Some(event) = event {
+            match event {
+                FailoverEvent::FailedOver { .. } => warn!("Cpu temperature {}.", event),
+                FailoverEvent::FailedBack { .. } => info!("Cpu temperature {}.", event),
+            }
+            if let Err(e) = self
+                .tx_journal
+                .send(JournalRecord::TemperatureFailover(event))
+            {
+                trace!("Failed to queue failover for journaling. Error: {}", e);
+            }
+        }
+
+        // NOTE: A reading means some backend worked, so there is always an
+        //       error otherwise.
+        temperature.ok_or_else(|| last_error.expect("Expected a backend to have failed."))
+    }
+}
+
+/// Parse the temperature printed by a temperature command, either a bare
+/// number or a JSON object with a `temperature` field.
+/// Will return FailedToParseOutput if the output isn't JSON, NoTemperature if
+/// it has no temperature, and FailedToParse if the temperature is invalid.
+pub fn parse_command_output(output: &[u8]) -> Result<Temperature, CpuTemperatureServiceError> {
+    let value: Value =
+        serde_json::from_slice(output).map_err(CpuTemperatureServiceError::FailedToParseOutput)?;
+
+    let raw = match &value {
+        Value::Object(fields) => fields.get("temperature").and_then(Value::as_f64),
+        value => value.as_f64(),
+    }
+    .ok_or(CpuTemperatureServiceError::NoTemperature)?;
+
+    Temperature::try_from(raw as f32).map_err(CpuTemperatureServiceError::FailedToParse)
+}
+
+/// Parse the cpu temperature from `sensors -j` output. Uses the hottest
+/// `temp*_input` reading across every cpu chip so a package or Tctl reading
+/// wins over individual cores.
+/// Will return FailedToParseOutput if the output isn't JSON, NoCpuSensor if
+/// no cpu chip reports a temperature, and FailedToParse if the temperature
+/// is invalid.
+pub fn parse_sensors_output(output: &[u8]) -> Result<Temperature, CpuTemperatureServiceError> {
+    let chips: Map<String, Value> =
+        serde_json::from_slice(output).map_err(CpuTemperatureServiceError::FailedToParseOutput)?;
+
+    let raw = chips
+        .iter()
+        .filter(|(chip, _)| {
+            CPU_CHIP_PREFIXES
+                .iter()
+                .any(|prefix| chip.starts_with(prefix))
+        })
+        .filter_map(|(_, features)| features.as_object())
+        .flat_map(|features| features.values())
+        .filter_map(Value::as_object)
+        .flat_map(|subfeatures| subfeatures.iter())
+        .filter(|(name, _)| name.starts_with("temp") && name.ends_with("_input"))
+        .filter_map(|(_, value)| value.as_f64())
+        .map(|value| value as f32)
+        .reduce(f32::max)
+        .ok_or(CpuTemperatureServiceError::NoCpuSensor)?;
+
+    Temperature::try_from(raw).map_err(CpuTemperatureServiceError::FailedToParse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Captured from `sensors -j` on an Intel desktop.
+    const INTEL_OUTPUT: &str = r#"{
+   "acpitz-acpi-0":{
+      "Adapter": "ACPI interface",
+      "temp1":{
+         "temp1_input": 27.800,
+         "temp1_crit": 119.000
+      }
+   },
+   "coretemp-isa-0000":{
+      "Adapter": "ISA adapter",
+      "Package id 0":{
+         "temp1_input": 48.000,
+         "temp1_max": 80.000,
+         "temp1_crit": 100.000,
+         "temp1_crit_alarm": 0.000
+      },
+      "Core 0":{
+         "temp2_input": 46.000,
+         "temp2_max": 80.000,
+         "temp2_crit": 100.000,
+         "temp2_crit_alarm": 0.000
+      },
+      "Core 1":{
+         "temp3_input": 44.000,
+         "temp3_max": 80.000,
+         "temp3_crit": 100.000,
+         "temp3_crit_alarm": 0.000
+      }
+   }
+}"#;
+
+    /// Captured from `sensors -j` on an AMD desktop.
+    const AMD_OUTPUT: &str = r#"{
+   "nvme-pci-0100":{
+      "Adapter": "PCI adapter",
+      "Composite":{
+         "temp1_input": 38.850,
+         "temp1_max": 81.850
+      }
+   },
+   "k10temp-pci-00c3":{
+      "Adapter": "PCI adapter",
+      "Tctl":{
+         "temp1_input": 55.250
+      },
+      "Tccd1":{
+         "temp3_input": 51.500
+      }
+   },
+   "amdgpu-pci-0900":{
+      "Adapter": "PCI adapter",
+      "edge":{
+         "temp1_input": 61.000,
+         "temp1_crit": 100.000
+      }
+   }
+}"#;
+
+    #[test]
+    fn test_parse_intel_output() {
+        let temperature =
+            parse_sensors_output(INTEL_OUTPUT.as_bytes()).expect("Failed to get Temperature.");
+        assert_eq!(temperature.value, 48f32);
+    }
+
+    #[test]
+    fn test_parse_amd_output() {
+        let temperature =
+            parse_sensors_output(AMD_OUTPUT.as_bytes()).expect("Failed to get Temperature.");
+        assert_eq!(temperature.value, 55.25f32);
+    }
+
+    #[test]
+    fn test_parse_invalid_output() {
+        assert!(matches!(
+            parse_sensors_output(b"No sensors found!"),
+            Err(CpuTemperatureServiceError::FailedToParseOutput(_))
+        ));
+        assert!(matches!(
+            parse_sensors_output(br#"{"nvme-pci-0100":{"Composite":{"temp1_input":38.85}}}"#),
+            Err(CpuTemperatureServiceError::NoCpuSensor)
+        ));
+    }
+
+    #[test]
+    fn test_parse_command_output() {
+        let temperature = parse_command_output(b"52.5\n").expect("Failed to get Temperature.");
+        assert_eq!(temperature.value, 52.5f32);
+
+        let temperature = parse_command_output(br#"{"temperature": 61, "source": "ipmi"}"#)
+            .expect("Failed to get Temperature.");
+        assert_eq!(temperature.value, 61f32);
+    }
+
+    #[test]
+    fn test_parse_invalid_command_output() {
+        assert!(matches!(
+            parse_command_output(b"52.5 C"),
+            Err(CpuTemperatureServiceError::FailedToParseOutput(_))
+        ));
+        assert!(matches!(
+            parse_command_output(br#"{"temp": 52.5}"#),
+            Err(CpuTemperatureServiceError::NoTemperature)
+        ));
+        assert!(matches!(
+            parse_command_output(br#""hot""#),
+            Err(CpuTemperatureServiceError::NoTemperature)
+        ));
+    }
+
+    #[test]
+    fn test_read_hwmon_temperature() {
+        let root = std::env::temp_dir().join(format!("prandtl_hwmon_{}", std::process::id()));
+        for (chip, name, inputs) in [
+            ("hwmon0", "acpitz", &[("temp1_input", "27800")][..]),
+            (
+                "hwmon1",
+                "coretemp",
+                &[
+                    ("temp1_input", "48000"),
+                    ("temp2_input", "46000"),
+                    ("temp1_crit", "100000"),
+                ][..],
+            ),
+        ] {
+            let chip = root.join(chip);
+            fs::create_dir_all(&chip).expect("Failed to create hwmon chip.");
+            fs::write(chip.join("name"), format!("{}\n", name)).expect("Failed to write name.");
+            for (input, value) in inputs {
+                fs::write(chip.join(input), format!("{}\n", value))
+                    .expect("Failed to write input.");
+            }
+        }
+
+        let temperature = read_hwmon_temperature(&root).expect("Failed to get Temperature.");
+        assert_eq!(temperature.value, 48f32);
+
+        fs::remove_dir_all(root.join("hwmon1")).expect("Failed to remove hwmon chip.");
+        assert!(matches!(
+            read_hwmon_temperature(&root),
+            Err(CpuTemperatureServiceError::NoCpuSensor)
+        ));
+
+        fs::remove_dir_all(&root).expect("Failed to remove hwmon root.");
+        assert!(matches!(
+            read_hwmon_temperature(&root),
+            Err(CpuTemperatureServiceError::FailedToRead(_))
+        ));
+    }
+
+    #[test]
+    fn test_new_splits_command() {
+        let service = HostCpuTemperatureServiceLmSensors::new("docker exec host sensors -j")
+            .expect("Failed to get service.");
+        assert_eq!(service.program, "docker");
+        assert_eq!(service.args, vec!["exec", "host", "sensors", "-j"]);
+        assert!(HostCpuTemperatureServiceLmSensors::new("  ").is_none());
+    }
+
+    struct FixedTemperature(f32);
+
+    impl HostCpuTemperatureService for FixedTemperature {
+        fn get_cpu_temp(&self) -> Result<Temperature, CpuTemperatureServiceError> {
+            Temperature::try_from(self.0).map_err(CpuTemperatureServiceError::FailedToParse)
+        }
+    }
+
+    #[test]
+    fn test_source_read_calibrates() {
+        let source = TemperatureSource::new("cpu", FixedTemperature(90f32)).with_calibration(
+            TemperatureCalibration {
+                offset: -27f32,
+                scale: 1f32,
+            },
+        );
+        let (raw, calibrated) = source.read().expect("Failed to read source.");
+        assert_eq!(raw.value, 90f32);
+        assert_eq!(calibrated.value, 63f32);
+
+        let source = TemperatureSource::new("cpu", FixedTemperature(60f32)).with_calibration(
+            TemperatureCalibration {
+                offset: 50f32,
+                scale: 1f32,
+            },
+        );
+        assert!(matches!(
+            source.read(),
+            Err(CpuTemperatureServiceError::FailedToParse(_))
+        ));
+    }
+}
@@ -1,54 +1,87 @@
-use std::time::Duration;
-
 use tokio::sync::broadcast::Sender;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, trace, warn};
 
-use crate::models::host_sensor_data::HostSensorData;
+use crate::models::{
+    heartbeat::Heartbeat, host_sensor_data::HostSensorData, temperature::TemperatureUnit,
+    temperature_aggregation::TemperatureAggregation, timings::Timings,
+};
 
-use super::services::HostCpuTemperatureService;
+use super::services::TemperatureSource;
 
-/// Task: Runs periodically to poll host sensors and emit host sensor messages.
+/// Task: Runs every `timings.host_sensor_poll` to poll every host temperature
+/// source, combine them with `aggregation` and emit host sensor messages.
+/// Beats `heartbeat` every poll. Temperatures are logged in `unit`.
 /// Can be cancelled.
 #[tracing::instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
 pub async fn task_poll_host_sensors(
     token: CancellationToken,
-    service: &impl HostCpuTemperatureService,
+    sources: &[TemperatureSource],
+    aggregation: &TemperatureAggregation,
     tx_host_sensor_data: Sender<HostSensorData>,
+    timings: Timings,
+    unit: TemperatureUnit,
+    heartbeat: Heartbeat,
 ) {
     tracing::info!("Started.");
     loop {
-        business_logic(service, &tx_host_sensor_data).await;
+        heartbeat.beat();
+        business_logic(sources, aggregation, &tx_host_sensor_data, unit).await;
 
         tokio::select! {
             _ = token.cancelled() => {
                 warn!("Cancelled.");
                 break;
             },
-            _ = tokio::time::sleep(Duration::from_millis(1500)) => {}
+            _ = tokio::time::sleep(timings.host_sensor_poll) => {}
         };
     }
 }
 
 /// Perform task business logic.
-/// Poll current host sensor data and try to emit it.
+/// Poll each temperature source, aggregate the readings which succeeded and
+/// try to emit the result.
 #[tracing::instrument(skip_all)]
 async fn business_logic(
-    service: &impl HostCpuTemperatureService,
+    sources: &[TemperatureSource],
+    aggregation: &TemperatureAggregation,
     tx_host_sensor_data: &Sender<HostSensorData>,
+    unit: TemperatureUnit,
 ) {
     trace!("Executing business logic.");
-    let temperature_reading = match service.get_cpu_temp() {
-        Ok(t) => t,
-        Err(e) => {
-            error!("Failed to get cpu temperature. Error: {}", e);
-            return;
+    let mut readings = Vec::with_capacity(sources.len());
+    let mut raw_readings = Vec::with_capacity(sources.len());
+    let mut calibrated = false;
+    for source in sources {
+        match source.read() {
+            Ok((raw, t)) => {
+                trace!("Got {} temperature: {}", source.name, t.display(unit));
+                readings.push((source.name, t));
+                raw_readings.push((source.name, raw));
+                calibrated |= !source.calibration.is_identity();
+            }
+            Err(e) => error!("Failed to get {} temperature. Error: {}", source.name, e),
         }
+    }
+
+    let Some(temperature_reading) = aggregation.aggregate(&readings) else {
+        error!(
+            "No temperature source used by {} could be read. Sources: {:?}",
+            aggregation, readings
+        );
+        return;
     };
 
-    debug!("Got cpu temperature: {}", temperature_reading);
+    debug!("Got cpu temperature: {}", temperature_reading.display(unit));
+    // NOTE: The raw readings are aggregated the same way so recordings can
+    //       tell what the calibration changed.
+    let raw_cpu_temperature = calibrated
+        .then(|| aggregation.aggregate(&raw_readings))
+        .flatten();
     let data = HostSensorData {
         cpu_temperature: temperature_reading,
+        raw_cpu_temperature,
     };
     if let Err(e) = tx_host_sensor_data.send(data) {
         error!("Failed to broadcast host sensor data. Error: {}", e);
@@ -0,0 +1,154 @@
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::broadcast::Sender;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn};
+
+use crate::models::{
+    journal::{unix_time_ms, JournalError, JournalRecord},
+    retention::{RetentionPolicy, RetentionStats},
+    telemetry_store::{lock_store, SharedTelemetryStore, TelemetryStore},
+};
+
+/// How often the retention policy is enforced.
+const JOURNAL_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Task: Enforce `policy` on the journal's store, first on start and then
+/// every `JOURNAL_MAINTENANCE_INTERVAL`. Entries past their maximum age are
+/// pruned, and if the store is still over its maximum size the oldest
+/// entries are pruned early and the store compacted. The outcome of every
+/// run, including the space reclaimed, is logged and journaled, which also
+/// updates the journal metrics when they are served.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_maintain_journal(
+    token: CancellationToken,
+    store: SharedTelemetryStore,
+    policy: RetentionPolicy,
+    tx_journal: Sender<JournalRecord>,
+) {
+    info!("Started. Retention policy: {:?}", policy);
+
+    let mut maintenance_interval = tokio::time::interval(JOURNAL_MAINTENANCE_INTERVAL);
+    let mut stats = RetentionStats::default();
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            _ = maintenance_interval.tick() => {
+                // NOTE: Pruning, reading every entry to decide what to give up
+                // for size and compacting are all blocking file or database
+                // I/O, so they're kept off the runtime's workers.
+                let (store, last_stats) = (store.clone(), stats);
+                let run = tokio::task::spawn_blocking(move || maintain(&store, &policy, last_stats));
+                match run.await {
+                    Ok(Some(run_stats)) => stats = run_stats,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!("Journal maintenance run failed to finish. Error: {}", e);
+                        continue;
+                    }
+                }
+                log_stats(&policy, &stats);
+                if let Err(e) = tx_journal.send(JournalRecord::Retention(stats)) {
+                    trace!("Failed to queue retention stats for journaling. Error: {}", e);
+                }
+            },
+        };
+    }
+}
+
+/// Enforce `policy` on `store`, logging any failure. Returns `stats` with the
+/// run recorded, or `None` if it failed. Blocks on the store.
+fn maintain(
+    store: &SharedTelemetryStore,
+    policy: &RetentionPolicy,
+    mut stats: RetentionStats,
+) -> Option<RetentionStats> {
+    let mut store = lock_store(store);
+    if let Err(e) = enforce_policy(store.as_mut(), policy, &mut stats) {
+        error!("Failed to maintain journal. Error: {}", e);
+        return None;
+    }
+    Some(stats)
+}
+
+/// Prune `store` according to `policy` and record the run in `stats`.
+fn enforce_policy(
+    store: &mut dyn TelemetryStore,
+    policy: &RetentionPolicy,
+    stats: &mut RetentionStats,
+) -> Result<(), JournalError> {
+    let now_ms = unix_time_ms(SystemTime::now());
+    let size_before = store.size_bytes()?;
+    // NOTE: Entries are only needed to decide what to give up for size.
+    let entries = match size_before > policy.max_size {
+        true => store.query(0, u64::MAX)?,
+        false => vec![],
+    };
+
+    let ((since_ms, summaries_since_ms), pruned_for_size) =
+        policy.cutoffs(&entries, size_before, now_ms);
+    store.prune(since_ms, summaries_since_ms)?;
+    if pruned_for_size {
+        store.compact()?;
+    }
+
+    stats.record_run(size_before, store.size_bytes()?, pruned_for_size);
+    Ok(())
+}
+
+/// Log the outcome of a maintenance run.
+fn log_stats(policy: &RetentionPolicy, stats: &RetentionStats) {
+    if stats.pruned_for_size {
+        warn!(
+            "Journal grew past {} bytes so entries younger than {:?} were pruned. Consider a shorter maximum age. Stats: {:?}",
+            policy.max_size, policy.max_age, stats
+        );
+    } else if stats.reclaimed_bytes > 0 {
+        info!(
+            "Journal maintenance reclaimed {} bytes. Stats: {:?}",
+            stats.reclaimed_bytes, stats
+        );
+    } else {
+        debug!("Journal maintenance reclaimed nothing. Stats: {:?}", stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::models::telemetry_store::JsonlStore;
+
+    #[test]
+    fn test_maintain() {
+        let path = std::env::temp_dir().join(format!(
+            "prandtl_maintenance_test_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let store: SharedTelemetryStore = Arc::new(Mutex::new(Box::new(JsonlStore::new(&path))));
+        let policy = RetentionPolicy::default();
+
+        let stats = maintain(&store, &policy, RetentionStats::default())
+            .expect("Failed to maintain journal.");
+        assert_eq!(stats.runs, 1);
+
+        // NOTE: A panic while the store was held mustn't stop maintenance, so
+        // the store is recovered rather than the run skipped.
+        let poisoner = store.clone();
+        let _ = std::thread::spawn(move || {
+            let _store = poisoner.lock().unwrap();
+            panic!("Poisoning the store.");
+        })
+        .join();
+        let stats = maintain(&store, &policy, stats).expect("Failed to maintain journal.");
+        assert_eq!(stats.runs, 2);
+        let _ = std::fs::remove_file(&path);
+    }
+}
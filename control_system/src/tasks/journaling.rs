@@ -0,0 +1,93 @@
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::broadcast::Receiver;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn};
+
+use crate::models::{
+    client_sensor_data::ClientSensorData,
+    control_event::ControlEvent,
+    host_sensor_data::HostSensorData,
+    journal::{unix_time_ms, JournalEntry, JournalRecord},
+    telemetry_store::{lock_store, SharedTelemetryStore},
+    telemetry_summary::{SummaryConfig, TelemetrySummarizer},
+};
+
+/// How often buffered records are appended to the journal.
+const JOURNAL_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Task: Journal recent telemetry, control frames and link stats to `store` so
+/// `diag bundle` can include them in bug reports. Telemetry is also summarized
+/// every `summary_config.interval`, and raw telemetry is only journaled if
+/// `summary_config.raw_telemetry` is set. Pruning is left to
+/// `task_maintain_journal`.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_record_journal(
+    token: CancellationToken,
+    store: SharedTelemetryStore,
+    summary_config: SummaryConfig,
+    mut rx_client_sensor_data: Receiver<ClientSensorData>,
+    mut rx_host_sensor_data: Receiver<HostSensorData>,
+    mut rx_control_frame: Receiver<ControlEvent>,
+    mut rx_journal: Receiver<JournalRecord>,
+) {
+    info!("Started.");
+
+    let mut pending: Vec<JournalEntry> = vec![];
+    let mut flush_interval = tokio::time::interval(JOURNAL_FLUSH_INTERVAL);
+    let mut summary_interval = tokio::time::interval(summary_config.interval);
+    // NOTE: The first tick is immediate, which would summarize nothing.
+    summary_interval.tick().await;
+    let mut summarizer = TelemetrySummarizer::new(unix_time_ms(SystemTime::now()));
+
+    loop {
+        let record = tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                summarize(&mut summarizer, &mut pending);
+                flush(&store, &mut pending);
+                break;
+            },
+            _ = flush_interval.tick() => {
+                flush(&store, &mut pending);
+                continue;
+            },
+            _ = summary_interval.tick() => {
+                summarize(&mut summarizer, &mut pending);
+                continue;
+            },
+            Ok(data) = rx_client_sensor_data.recv() => JournalRecord::ClientSensors(data),
+            Ok(data) = rx_host_sensor_data.recv() => JournalRecord::HostSensors(data),
+            Ok(data) = rx_control_frame.recv() => JournalRecord::ControlFrame(data),
+            Ok(data) = rx_journal.recv() => data,
+        };
+        summarizer.record(&record);
+        if record.is_raw_telemetry() && !summary_config.raw_telemetry {
+            continue;
+        }
+        trace!("Journaling record.");
+        pending.push(JournalEntry::now(record));
+    }
+}
+
+/// Summarize the telemetry collected since the last summary into pending
+/// entries.
+fn summarize(summarizer: &mut TelemetrySummarizer, pending: &mut Vec<JournalEntry>) {
+    for summary in summarizer.finish(unix_time_ms(SystemTime::now())) {
+        debug!("Telemetry summary: {:?}", summary);
+        pending.push(JournalEntry::now(JournalRecord::TelemetrySummary(summary)));
+    }
+}
+
+/// Append every pending entry to the journal, logging any failure.
+fn flush(store: &SharedTelemetryStore, pending: &mut Vec<JournalEntry>) {
+    if pending.is_empty() {
+        return;
+    }
+    let result = lock_store(store).append(pending);
+    if let Err(e) = result {
+        error!("Failed to append to journal. Error: {}", e);
+    }
+    pending.clear();
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use common::{
+    packet::{Packet, Parameter, SetParameterPacket},
+    physical::Rpm,
+};
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn};
+
+use crate::models::{
+    addressed_packet::AddressedPacket,
+    client_sensor_data::ClientSensorData,
+    control_event::ControlEvent,
+    device_id::DeviceId,
+    device_registry::{DeviceRecord, SharedDeviceRegistry},
+    max_rpm_learner::MaxRpmLearner,
+};
+
+/// How long a channel must be held at 100% before its maximum speed is learned.
+const MAX_RPM_LEARNING_WINDOW: Duration = Duration::from_secs(10);
+
+/// Relative difference below which a learned maximum is considered unchanged.
+/// Avoids rewriting the registry and firmware flash for measurement noise.
+const MAX_RPM_TOLERANCE: f32 = 0.02f32;
+
+/// Task: Learn the maximum pump and fan speeds by observing the peak reported
+/// speed while a channel is commanded to 100%. Learned values are persisted in
+/// the device registry and pushed to the embedded hardware's settings.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_learn_max_rpm(
+    token: CancellationToken,
+    registry_path: PathBuf,
+    registry: SharedDeviceRegistry,
+    mut rx_client_sensor_data: Receiver<ClientSensorData>,
+    mut rx_control_frame: Receiver<ControlEvent>,
+    tx_send_packets_to_hw: Sender<AddressedPacket>,
+) {
+    info!("Started.");
+
+    // NOTE: Learners are kept per device as (pump, fan).
+    let mut learners: HashMap<DeviceId, (MaxRpmLearner, MaxRpmLearner)> = HashMap::new();
+    let mut current_control_frames: HashMap<DeviceId, ControlEvent> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            Ok(data) = rx_control_frame.recv() => {
+                current_control_frames.insert(data.device, data);
+                trace!("Received control frame.");
+            },
+            Ok(data) = rx_client_sensor_data.recv() => {
+                trace!("Received client frame.");
+                let Some(control_frame) = current_control_frames.get(&data.device) else {
+                    continue;
+                };
+                let now = Instant::now();
+
+                let (pump_learner, fan_learner) = learners.entry(data.device).or_insert_with(|| {
+                    (
+                        MaxRpmLearner::new(MAX_RPM_LEARNING_WINDOW),
+                        MaxRpmLearner::new(MAX_RPM_LEARNING_WINDOW),
+                    )
+                });
+                let learned_pump = pump_learner.observe(control_frame.pump_activation, data.pump_speed, now);
+                let learned_fan = fan_learner.observe(control_frame.fan_activation, data.fan_speed, now);
+
+                let record = {
+                    let mut registry = registry.write().expect("Device registry lock poisoned.");
+                    let record = registry.record_mut(data.device.as_str());
+                    let changed = update_learned_max(&mut record.pump_max_rpm, learned_pump, "pump")
+                        | update_learned_max(&mut record.fan_max_rpm, learned_fan, "fan");
+                    let record = record.clone();
+                    if changed {
+                        match registry.save(&registry_path) {
+                            Err(e) => error!("Failed to save device registry. Error: {}", e),
+                            Ok(_) => info!("Saved learned maximum speeds to device registry."),
+                        }
+                    }
+                    record
+                };
+
+                sync_hardware_max_rpm(&record, &data, &tx_send_packets_to_hw);
+            },
+        };
+    }
+}
+
+/// Replace `current` with `learned` if it differs meaningfully.
+/// Returns `true` if `current` was updated.
+fn update_learned_max(current: &mut Option<f32>, learned: Option<f32>, channel: &str) -> bool {
+    let Some(learned) = learned else {
+        return false;
+    };
+    if let Some(existing) = *current {
+        if !max_rpm_differs(existing, learned) {
+            debug!(
+                "Learned {} max RPM {} matches existing value.",
+                channel, learned
+            );
+            return false;
+        }
+    }
+    info!("Learned new {} max RPM: {}", channel, learned);
+    *current = Some(learned);
+    true
+}
+
+/// Push any learned maximum the hardware isn't using yet. The hardware
+/// reports its configured maximum in every `Rpm`, except while overspeeding.
+fn sync_hardware_max_rpm(
+    record: &DeviceRecord,
+    data: &ClientSensorData,
+    tx_send_packets_to_hw: &Sender<AddressedPacket>,
+) {
+    if let Some(learned) = record.pump_max_rpm {
+        if hardware_needs_max_rpm(learned, data.pump_speed) {
+            queue_parameter(
+                data.device,
+                Parameter::PumpMaxRpm(learned.round() as u16),
+                tx_send_packets_to_hw,
+            );
+        }
+    }
+    if let Some(learned) = record.fan_max_rpm {
+        if hardware_needs_max_rpm(learned, data.fan_speed) {
+            queue_parameter(
+                data.device,
+                Parameter::FanMaxRpm(learned.round() as u16),
+                tx_send_packets_to_hw,
+            );
+        }
+    }
+}
+
+/// Queue a `SetParameter` packet to be sent to a device.
+fn queue_parameter(
+    device: DeviceId,
+    parameter: Parameter,
+    tx_send_packets_to_hw: &Sender<AddressedPacket>,
+) {
+    let packet = AddressedPacket::new(
+        device,
+        Packet::SetParameter(SetParameterPacket { parameter }),
+    );
+    match tx_send_packets_to_hw.send(packet) {
+        Err(e) => error!("Failed to queue parameter {:?}. Error: {}", parameter, e),
+        Ok(_) => debug!("Queued parameter {:?} for {}.", parameter, device),
+    }
+}
+
+/// Check if the hardware is reporting with a different maximum than learned.
+fn hardware_needs_max_rpm(learned: f32, reported: Rpm) -> bool {
+    let overspeeding = reported.speed() >= reported.max_speed();
+    !overspeeding && max_rpm_differs(reported.max_speed(), learned)
+}
+
+/// Check if two maximum speeds differ by more than `MAX_RPM_TOLERANCE`.
+fn max_rpm_differs(a: f32, b: f32) -> bool {
+    (a - b).abs() > b * MAX_RPM_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_learned_max() {
+        let mut current = None;
+        assert!(!update_learned_max(&mut current, None, "pump"));
+        assert!(update_learned_max(&mut current, Some(1700f32), "pump"));
+        assert_eq!(current, Some(1700f32));
+
+        assert!(!update_learned_max(&mut current, Some(1710f32), "pump"));
+        assert_eq!(current, Some(1700f32));
+
+        assert!(update_learned_max(&mut current, Some(1900f32), "pump"));
+        assert_eq!(current, Some(1900f32));
+    }
+
+    #[test]
+    fn test_hardware_needs_max_rpm() {
+        let reported = Rpm::new(2000f32, 1000f32).expect("Failed to get RPM.");
+        assert!(hardware_needs_max_rpm(1700f32, reported));
+        assert!(!hardware_needs_max_rpm(1990f32, reported));
+
+        let overspeed = Rpm::new(1850f32, 1850f32).expect("Failed to get RPM.");
+        assert!(!hardware_needs_max_rpm(1700f32, overspeed));
+    }
+}
@@ -0,0 +1,173 @@
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::Receiver,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::models::{
+    client_sensor_data::ClientSensorData,
+    control_event::ControlEvent,
+    host_sensor_data::HostSensorData,
+    journal::JournalRecord,
+    metrics::{grafana_dashboard, metrics, SharedMetrics},
+    temperature::TemperatureUnit,
+};
+
+/// How long a scraper has to send its request before it is dropped.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Task: Keep `metrics` up to date with the telemetry, control frames and
+/// journal records as they are broadcast.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_collect_metrics(
+    token: CancellationToken,
+    metrics: SharedMetrics,
+    mut rx_client_sensor_data: Receiver<ClientSensorData>,
+    mut rx_host_sensor_data: Receiver<HostSensorData>,
+    mut rx_control_frame: Receiver<ControlEvent>,
+    mut rx_journal: Receiver<JournalRecord>,
+) {
+    info!("Started.");
+
+    loop {
+        let record = tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            Ok(data) = rx_client_sensor_data.recv() => JournalRecord::ClientSensors(data),
+            Ok(data) = rx_host_sensor_data.recv() => JournalRecord::HostSensors(data),
+            Ok(data) = rx_control_frame.recv() => JournalRecord::ControlFrame(data),
+            Ok(data) = rx_journal.recv() => data,
+        };
+        match metrics.lock() {
+            Ok(mut metrics) => metrics.observe(&record),
+            Err(e) => error!("Failed to lock metrics. Error: {}", e),
+        }
+    }
+}
+
+/// Task: Serve `metrics` in the Prometheus text format over HTTP on `addr`,
+/// at `/metrics`.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_serve_metrics(
+    token: CancellationToken,
+    addr: SocketAddr,
+    metrics: SharedMetrics,
+) {
+    info!("Started.");
+
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind metrics on {}. Error: {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving metrics on http://{}/metrics.", addr);
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            res = listener.accept() => {
+                let stream = match res {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept metrics connection. Error: {}", e);
+                        continue;
+                    }
+                };
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_scrape(stream, &metrics).await {
+                        debug!("Failed to serve metrics. Error: {}", e);
+                    }
+                });
+            },
+        }
+    }
+}
+
+/// Answer a single request for metrics, then close the connection.
+async fn serve_scrape(stream: TcpStream, metrics: &SharedMetrics) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    tokio::time::timeout(SCRAPE_TIMEOUT, async {
+        reader.read_line(&mut request_line).await?;
+        // NOTE: Closing with the headers unread would reset the connection
+        //       before the scraper reads the response.
+        let mut header = String::new();
+        while reader.read_line(&mut header).await? > 2 {
+            header.clear();
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await??;
+    let response = respond(&request_line, metrics);
+    let mut stream = reader.into_inner();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Build the HTTP response to a request starting with `request_line`.
+fn respond(request_line: &str, metrics: &SharedMetrics) -> String {
+    let (status, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => match metrics.lock() {
+            Ok(metrics) => ("200 OK", metrics.render()),
+            Err(e) => (
+                "500 Internal Server Error",
+                format!("Failed to lock metrics. Error: {}\n", e),
+            ),
+        },
+        [_, "/metrics"] => ("405 Method Not Allowed", String::new()),
+        _ => ("404 Not Found", String::new()),
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Run `metrics grafana-dashboard`: print a Grafana dashboard for the
+/// metrics the exporter serves, in the configured temperature unit, ready to
+/// import.
+pub fn handle_grafana_dashboard_command() -> Result<(), serde_json::Error> {
+    let metrics = metrics(TemperatureUnit::from_env());
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&grafana_dashboard(&metrics))?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::metrics::CPU_TEMPERATURE;
+
+    #[test]
+    fn test_respond() {
+        let metrics = SharedMetrics::default();
+        metrics.lock().unwrap().set(&CPU_TEMPERATURE, &[], 52.5f64);
+
+        let response = respond("GET /metrics HTTP/1.1\r\n", &metrics);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n# HELP prandtl_cpu_temperature_celsius Cpu temperature the control loop steers by.\n# TYPE prandtl_cpu_temperature_celsius gauge\nprandtl_cpu_temperature_celsius 52.5\n"));
+
+        assert!(respond("POST /metrics HTTP/1.1\r\n", &metrics)
+            .starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(respond("GET / HTTP/1.1\r\n", &metrics).starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(respond("", &metrics).starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
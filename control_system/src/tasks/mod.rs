@@ -1,3 +1,37 @@
+pub mod alarms;
 pub mod client_sensors;
+#[cfg(feature = "control-socket")]
+pub mod control_socket;
 pub mod control_system;
+pub mod convergence_checking;
+#[cfg(feature = "control-socket")]
+pub mod curve_preview;
+pub mod device_log;
+#[cfg(feature = "recording")]
+pub mod diagnostics;
+pub mod duty_rpm_learning;
+pub mod estimation;
+#[cfg(feature = "control-socket")]
+pub mod explain;
+#[cfg(feature = "simulate")]
+pub mod fault_simulation;
 pub mod host_sensors;
+#[cfg(feature = "recording")]
+pub mod journal_maintenance;
+#[cfg(feature = "recording")]
+pub mod journaling;
+pub mod max_rpm_learning;
+#[cfg(feature = "http")]
+pub mod metrics;
+#[cfg(feature = "recording")]
+pub mod rpm_trend;
+pub mod shutdown;
+#[cfg(feature = "dbus")]
+pub mod sleep;
+pub mod startup_check;
+#[cfg(feature = "control-socket")]
+pub mod state_machines;
+#[cfg(feature = "control-socket")]
+pub mod telemetry_rate;
+pub mod time_sync;
+pub mod watchdog;
@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use tokio::sync::broadcast::Sender;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::models::{
+    alarm::{AlarmCondition, AlarmKind},
+    device_id::DeviceId,
+    journal::{unix_time_ms, JournalError, JournalRecord},
+    rpm_trend::{find_rpm_drift, trend_points, Channel, RPM_TREND_WINDOW},
+    telemetry_store::{lock_store, SharedTelemetryStore, TelemetryStore},
+    telemetry_summary::TelemetrySummary,
+};
+
+/// How often the recorded speed trends are checked. Drift builds up over
+/// days, so there's no point checking often.
+const RPM_TREND_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Task: Look for pumps and fans slowly losing speed at the same duty in the
+/// telemetry summaries journaled to `store` over the last
+/// `RPM_TREND_WINDOW`, first on start and then every `RPM_TREND_INTERVAL`.
+/// Raises a "speed drifting down" advisory over `tx_alarm_conditions` for
+/// any which slowed by at least `RPM_DRIFT_THRESHOLD`, which hints at
+/// bearings wearing out or a loop clogging before either fails.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_check_rpm_trends(
+    token: CancellationToken,
+    store: SharedTelemetryStore,
+    tx_alarm_conditions: Sender<AlarmCondition>,
+) {
+    info!("Started.");
+
+    let mut trend_interval = tokio::time::interval(RPM_TREND_INTERVAL);
+    let mut drifting: HashMap<(DeviceId, Channel), bool> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            _ = trend_interval.tick() => {
+                let result = recent_summaries(lock_store(&store).as_ref());
+                match result {
+                    Ok(summaries) => check_trends(&summaries, &mut drifting, &tx_alarm_conditions),
+                    Err(e) => error!("Failed to read telemetry summaries. Error: {}", e),
+                }
+            },
+        };
+    }
+}
+
+/// Get the telemetry summaries journaled within the last `RPM_TREND_WINDOW`.
+fn recent_summaries(store: &dyn TelemetryStore) -> Result<Vec<TelemetrySummary>, JournalError> {
+    let now_ms = unix_time_ms(SystemTime::now());
+    let from_ms = now_ms.saturating_sub(RPM_TREND_WINDOW.as_millis() as u64);
+    Ok(store
+        .query(from_ms, u64::MAX)?
+        .into_iter()
+        .filter_map(|entry| match entry.record {
+            JournalRecord::TelemetrySummary(summary) => Some(summary),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Check the trend of every channel with summaries, sending an alarm
+/// condition for any which started or stopped drifting.
+/// NOTE: Channels without any steady summaries keep their last state, since
+///       a drift can't be ruled out without them.
+fn check_trends(
+    summaries: &[TelemetrySummary],
+    drifting: &mut HashMap<(DeviceId, Channel), bool>,
+    tx_alarm_conditions: &Sender<AlarmCondition>,
+) {
+    for ((device, channel), points) in trend_points(summaries) {
+        let drift = find_rpm_drift(&points);
+        let was_drifting = drifting.insert((device, channel), drift.is_some());
+        let alarm = drift_alarm(channel);
+        match drift {
+            Some(drift) if was_drifting != Some(true) => {
+                warn!("{} {}. It {}.", device, alarm, drift);
+            }
+            None if was_drifting == Some(true) => {
+                info!("{} recovered from {}.", device, alarm);
+            }
+            _ => {
+                debug!("{} {} trend unchanged.", device, channel);
+                continue;
+            }
+        }
+        if let Err(e) = tx_alarm_conditions.send(AlarmCondition {
+            device,
+            kind: alarm,
+            present: drift.is_some(),
+        }) {
+            warn!("Failed to send alarm condition. Error: {}", e);
+        }
+    }
+}
+
+/// Get the advisory raised for a channel drifting.
+fn drift_alarm(channel: Channel) -> AlarmKind {
+    match channel {
+        Channel::Pump => AlarmKind::PumpSpeedDrifting,
+        Channel::Fan => AlarmKind::FanSpeedDrifting,
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::BTreeMap, future::Future, time::Duration};
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::models::shutdown::{ShutdownReport, ShutdownStage, TaskExit};
+
+/// A task spawned under the shutdown coordinator.
+struct ShutdownTask {
+    name: &'static str,
+    timeout: Duration,
+    handle: JoinHandle<()>,
+}
+
+/// The tasks in a single shutdown stage, sharing a cancellation token.
+#[derive(Default)]
+struct Stage {
+    token: CancellationToken,
+    tasks: Vec<ShutdownTask>,
+}
+
+/// Spawns the top level tasks and shuts them down in stage order. Each task
+/// gets a deadline to honor its cancellation, after which it is aborted so a
+/// task stuck in a blocking call can't hang the process on exit.
+#[derive(Default)]
+pub struct Shutdown {
+    stages: BTreeMap<ShutdownStage, Stage>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn a task in `stage`. The task is given a token which is cancelled
+    /// when its stage is shut down, and is aborted if it hasn't exited
+    /// `timeout` later.
+    pub fn spawn<F, Fut>(
+        &mut self,
+        name: &'static str,
+        stage: ShutdownStage,
+        timeout: Duration,
+        task: F,
+    ) where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let stage = self.stages.entry(stage).or_default();
+        let handle = tokio::spawn(task(stage.token.clone()));
+        stage.tasks.push(ShutdownTask {
+            name,
+            timeout,
+            handle,
+        });
+    }
+
+    /// Cancel each stage in order and wait for its tasks to exit, aborting
+    /// any which overrun their deadline. Returns how every task exited.
+    pub async fn run(self) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+
+        for (stage, Stage { token, tasks }) in self.stages {
+            info!("Shutting down {:?} tasks.", stage);
+            token.cancel();
+
+            // NOTE: Wait on every task in the stage together so one slow task
+            //       doesn't eat into the deadlines of the others.
+            let exits = futures::future::join_all(tasks.into_iter().map(wait_for_exit)).await;
+            for (name, exit) in exits {
+                report.record(name, stage, exit);
+            }
+        }
+
+        report
+    }
+}
+
+/// Wait for a cancelled task to exit, aborting it if it overruns its deadline.
+async fn wait_for_exit(task: ShutdownTask) -> (&'static str, TaskExit) {
+    let ShutdownTask {
+        name,
+        timeout,
+        mut handle,
+    } = task;
+
+    let exit = match tokio::time::timeout(timeout, &mut handle).await {
+        Ok(Ok(())) => TaskExit::Clean,
+        Ok(Err(e)) => {
+            error!("Task {} failed. Error: {}", name, e);
+            TaskExit::Panicked
+        }
+        Err(_) => {
+            warn!(
+                "Task {} ignored cancellation for {:?}. Aborting it.",
+                name, timeout
+            );
+            handle.abort();
+            TaskExit::Aborted { timeout }
+        }
+    };
+    (name, exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stuck_task_is_aborted() {
+        let mut shutdown = Shutdown::new();
+        shutdown.spawn(
+            "polite",
+            ShutdownStage::Pipeline,
+            Duration::from_secs(1),
+            |token| async move { token.cancelled().await },
+        );
+        shutdown.spawn(
+            "stuck",
+            ShutdownStage::Observers,
+            Duration::from_millis(10),
+            |_token| async move { std::future::pending::<()>().await },
+        );
+
+        let report = shutdown.run().await;
+
+        assert_eq!(report.tasks.len(), 2);
+        assert_eq!(report.tasks[0].name, "polite");
+        assert_eq!(report.tasks[0].exit, TaskExit::Clean);
+        assert_eq!(report.tasks[1].name, "stuck");
+        assert_eq!(
+            report.tasks[1].exit,
+            TaskExit::Aborted {
+                timeout: Duration::from_millis(10)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stages_shut_down_in_order() {
+        let mut shutdown = Shutdown::new();
+        let pipeline = CancellationToken::new();
+
+        let pipeline_clone = pipeline.clone();
+        shutdown.spawn(
+            "observer",
+            ShutdownStage::Observers,
+            Duration::from_secs(1),
+            |token| async move {
+                token.cancelled().await;
+                assert!(pipeline_clone.is_cancelled());
+            },
+        );
+        shutdown.spawn(
+            "pipeline",
+            ShutdownStage::Pipeline,
+            Duration::from_secs(1),
+            |token| async move {
+                token.cancelled().await;
+                pipeline.cancel();
+            },
+        );
+
+        let report = shutdown.run().await;
+
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(report.tasks[0].name, "pipeline");
+        assert_eq!(report.tasks[1].name, "observer");
+    }
+}
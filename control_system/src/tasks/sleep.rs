@@ -0,0 +1,122 @@
+use std::{process::Stdio, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+    sync::broadcast::Sender,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::models::sleep::{
+    parse_sleep_event, SleepEvent, SleepMonitorCommand, SLEEP_INHIBIT_COMMAND,
+};
+
+/// How long sleep is delayed after parking, so the parking frames reach the
+/// devices before the host stops.
+const PARK_DELAY: Duration = Duration::from_secs(1);
+
+/// Task: Watch for the host suspending and resuming by running `command`,
+/// which prints logind's `PrepareForSleep` signals, and broadcast each as a
+/// `SleepEvent`. A delay inhibitor lock is held while awake so sleep waits
+/// `PARK_DELAY` after `SleepEvent::Suspending` for the pump to be parked.
+/// Stops if the command can't be run or exits.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_monitor_sleep(
+    token: CancellationToken,
+    command: SleepMonitorCommand,
+    tx_sleep_events: Sender<SleepEvent>,
+) {
+    info!("Started.");
+
+    let mut monitor = match Command::new(&command.program)
+        .args(&command.args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(monitor) => monitor,
+        Err(e) => {
+            warn!(
+                "Failed to run sleep monitor '{}'. Suspend won't be detected. Error: {}",
+                command.program, e
+            );
+            return;
+        }
+    };
+    let Some(stdout) = monitor.stdout.take() else {
+        error!("Sleep monitor has no output to read.");
+        return;
+    };
+    let mut lines = BufReader::new(stdout).lines();
+    let mut inhibitor = take_inhibitor();
+
+    loop {
+        let line = tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            line = lines.next_line() => line,
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                warn!("Sleep monitor exited. Suspend won't be detected.");
+                break;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to read sleep monitor. Suspend won't be detected. Error: {}",
+                    e
+                );
+                break;
+            }
+        };
+        let Some(event) = parse_sleep_event(&line) else {
+            continue;
+        };
+
+        info!("Host sleep event: {:?}", event);
+        if let Err(e) = tx_sleep_events.send(event) {
+            error!("Failed to broadcast sleep event. Error: {}", e);
+        }
+        match event {
+            SleepEvent::Suspending => {
+                tokio::time::sleep(PARK_DELAY).await;
+                // NOTE: Dropping the inhibitor kills it, releasing the lock.
+                inhibitor = None;
+            }
+            SleepEvent::Resumed => {
+                if inhibitor.is_none() {
+                    inhibitor = take_inhibitor();
+                }
+            }
+        }
+    }
+}
+
+/// Take a delay inhibitor lock, held until the returned child is dropped.
+/// Suspend is still detected without it, but the host may sleep before the
+/// pump is parked.
+fn take_inhibitor() -> Option<Child> {
+    let [program, args @ ..] = SLEEP_INHIBIT_COMMAND;
+    match Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => Some(child),
+        Err(e) => {
+            warn!(
+                "Failed to take a sleep inhibitor lock. Sleep won't wait for parking. Error: {}",
+                e
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,155 @@
+#[cfg(feature = "scripting")]
+use crate::models::control_script::load_control_script_from_env;
+use crate::{
+    controls::validate_controls,
+    error::ConfigError,
+    models::{
+        capabilities::HardwareExpectations,
+        control_loop::ControlLoopMode,
+        flow_interlock::LowFlowPolicy,
+        link::LinkConfig,
+        startup_check::{check_device, CheckOptions, CheckOutcome, CheckReport},
+        temperature::TemperatureUnit,
+        temperature_failover::{TemperatureBackend, CPU_TEMPERATURE_BACKENDS_ENV_VAR},
+        timings::Timings,
+        valve_policy::ValvePolicy,
+    },
+};
+
+use super::{
+    client_sensors::injection::probe_device,
+    host_sensors::services::{
+        backend_command_env_var, backend_service, HostCpuTemperatureService,
+        HostCpuTemperatureServiceCommand, SENSORS_COMMAND_ENV_VAR,
+    },
+};
+
+/// Run `check`: load the configuration the control system would start with,
+/// validate the curves, gains and control script, read each temperature
+/// backend once and, with `--device`, handshake with the device. Prints a
+/// report and fails if anything would stop the control system working as
+/// configured.
+pub fn handle_check_command(options: &CheckOptions) -> Result<(), ConfigError> {
+    let mut report = CheckReport::default();
+
+    match validate_controls() {
+        Ok(()) => report.push(
+            "curves",
+            CheckOutcome::Ok,
+            "Built-in curves and gains are valid.",
+        ),
+        Err(e) => report.push("curves", CheckOutcome::Problem, e.to_string()),
+    }
+    #[cfg(feature = "scripting")]
+    match load_control_script_from_env() {
+        None => report.push(
+            "control script",
+            CheckOutcome::Ok,
+            "None, using the curves.",
+        ),
+        Some(Ok(_)) => report.push("control script", CheckOutcome::Ok, "Parsed."),
+        Some(Err(e)) => report.push("control script", CheckOutcome::Problem, e.to_string()),
+    }
+
+    report.push(
+        "timings",
+        CheckOutcome::Ok,
+        format!("{:?}", Timings::from_env()),
+    );
+    report.push(
+        "link",
+        CheckOutcome::Ok,
+        format!("{:?}", LinkConfig::from_env()),
+    );
+    report.push(
+        "valve policy",
+        CheckOutcome::Ok,
+        format!("{:?}", ValvePolicy::from_env()),
+    );
+    report.push(
+        "low flow policy",
+        CheckOutcome::Ok,
+        format!("{:?}", LowFlowPolicy::from_env()),
+    );
+    report.push(
+        "control loop",
+        CheckOutcome::Ok,
+        format!("{:?}", ControlLoopMode::from_env()),
+    );
+    let expectations = HardwareExpectations::from_env();
+    report.push("hardware", CheckOutcome::Ok, format!("{:?}", expectations));
+
+    check_temperature_backends(&mut report);
+
+    if options.handshake {
+        match probe_device(&options.device.port, options.device.timeout) {
+            Ok(probe) => check_device(&mut report, &probe, &expectations),
+            Err(e) => report.push("device", CheckOutcome::Problem, e.to_string()),
+        }
+    }
+
+    println!("{}", report);
+    if !report.passed() {
+        return Err(ConfigError::Problems(report.count(CheckOutcome::Problem)));
+    }
+    Ok(())
+}
+
+/// Read the cpu temperature from every backend the control system would fail
+/// over between, and from the temperature command if it is read alongside.
+/// Only having no working backend at all is a problem.
+fn check_temperature_backends(report: &mut CheckReport) {
+    let unit = TemperatureUnit::from_env();
+    let backends =
+        TemperatureBackend::chain_from_env(std::env::var(SENSORS_COMMAND_ENV_VAR).is_ok());
+    let mut working = 0;
+    for &backend in &backends {
+        let subject = format!("{} backend", backend);
+        let Some(service) = backend_service(backend) else {
+            report.push(
+                subject,
+                CheckOutcome::Warning,
+                format!(
+                    "Skipped since {} isn't set.",
+                    backend_command_env_var(backend).unwrap_or_default()
+                ),
+            );
+            continue;
+        };
+        match service.get_cpu_temp() {
+            Ok(temperature) => {
+                working += 1;
+                report.push(
+                    subject,
+                    CheckOutcome::Ok,
+                    format!("Read {}.", temperature.display(unit)),
+                );
+            }
+            Err(e) => report.push(subject, CheckOutcome::Warning, e.to_string()),
+        }
+    }
+    if working == 0 {
+        report.push(
+            "cpu temperature",
+            CheckOutcome::Problem,
+            format!(
+                "None of the backends work. Check {}.",
+                CPU_TEMPERATURE_BACKENDS_ENV_VAR
+            ),
+        );
+    }
+
+    if backends.contains(&TemperatureBackend::Command) {
+        return;
+    }
+    if let Some(service) = HostCpuTemperatureServiceCommand::from_env() {
+        match service.get_cpu_temp() {
+            Ok(temperature) => report.push(
+                "temperature command",
+                CheckOutcome::Ok,
+                format!("Read {}.", temperature.display(unit)),
+            ),
+            Err(e) => report.push("temperature command", CheckOutcome::Problem, e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::path::Path;
+
+use crate::{
+    error::ControlError,
+    models::{
+        control_socket::{ControlRequest, ControlResponse},
+        control_trace::SharedControlTraces,
+        device_id::DeviceId,
+        state_machine::{state_machines, to_dot},
+        valve_model::valve_travel_from_env,
+    },
+};
+
+use super::control_socket::request;
+
+/// Answer a state machines request from the control socket with the state
+/// machines of `device`, or of every device if `None`, ordered by device.
+/// Without explain mode there are no traces to take the current states from,
+/// so the state machines are exported once with none filled in.
+pub fn handle_state_machines_request(
+    traces: Option<&SharedControlTraces>,
+    device: Option<DeviceId>,
+) -> ControlResponse {
+    let valve_travel = valve_travel_from_env();
+    let Some(traces) = traces else {
+        return ControlResponse::Dot(to_dot(&[(device, state_machines(None, valve_travel))]));
+    };
+    let traces = match traces.lock() {
+        Ok(traces) => traces,
+        Err(e) => return ControlResponse::Error(format!("Failed to lock traces. Error: {}", e)),
+    };
+    let mut machines = match device {
+        Some(device) => vec![(
+            Some(device),
+            state_machines(traces.get(&device), valve_travel),
+        )],
+        None => traces
+            .values()
+            .map(|trace| {
+                (
+                    Some(trace.device),
+                    state_machines(Some(trace), valve_travel),
+                )
+            })
+            .collect(),
+    };
+    if machines.is_empty() {
+        machines.push((None, state_machines(None, valve_travel)));
+    }
+    machines.sort_by_key(|(device, _)| *device);
+    ControlResponse::Dot(to_dot(&machines))
+}
+
+/// Run `states`: print the state machines of the control system listening at
+/// `socket_path` as a Graphviz DOT graph.
+pub fn handle_state_machines_command(
+    socket_path: &Path,
+    device: Option<DeviceId>,
+) -> Result<(), ControlError> {
+    match request(socket_path, &ControlRequest::StateMachines { device })? {
+        ControlResponse::Dot(dot) => print!("{}", dot),
+        response => return Err(ControlError::from_response(response)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        controls::{step_explained, ControlInputs, ControlState},
+        models::{
+            client_sensor_data::{ClientSensorData, SensorQuality},
+            host_sensor_data::HostSensorData,
+            temperature::Temperature,
+        },
+    };
+    use common::physical::{Rpm, ValveState};
+
+    fn record(traces: &SharedControlTraces, device: &str, low_flow: bool) {
+        let inputs = ControlInputs {
+            client: ClientSensorData {
+                device: DeviceId::new(device),
+                pump_speed: Rpm::new(2000f32, 800f32).expect("Failed to get RPM."),
+                fan_speed: Rpm::new(2000f32, 500f32).expect("Failed to get RPM."),
+                extra_fan_speeds: [None; 3],
+                valve_state: ValveState::Open,
+                board_temperature: None,
+                coolant_temperature: None,
+                pump_current: None,
+                fan_current: None,
+                flow_rate: None,
+                low_flow,
+                quality: SensorQuality::GOOD,
+                sequence: None,
+                sampled_at_ms: None,
+            },
+            host: HostSensorData {
+                cpu_temperature: Temperature::try_from(70f32).expect("Failed to get Temperature."),
+                raw_cpu_temperature: None,
+            },
+        };
+        let (_, _, trace) = step_explained(ControlState::default(), inputs, Duration::ZERO);
+        traces.lock().unwrap().insert(trace.device, trace);
+    }
+
+    fn dot(response: ControlResponse) -> String {
+        let ControlResponse::Dot(dot) = response else {
+            panic!("Expected a DOT graph.");
+        };
+        dot
+    }
+
+    #[test]
+    fn test_handle_state_machines_request() {
+        let dot_without_traces = dot(handle_state_machines_request(None, None));
+        assert!(dot_without_traces.contains("label=\"strategy\";"));
+        assert!(!dot_without_traces.contains("fillcolor"));
+
+        let traces = SharedControlTraces::default();
+        record(&traces, "b", true);
+        record(&traces, "a", false);
+        let all = dot(handle_state_machines_request(Some(&traces), None));
+        let a = all
+            .find("label=\"a: strategy\";")
+            .expect("Missing device a.");
+        let b = all
+            .find("label=\"b: strategy\";")
+            .expect("Missing device b.");
+        assert!(a < b);
+        // NOTE: Three machines per device, each with its current state filled.
+        assert_eq!(all.matches("fillcolor").count(), 6);
+        assert!(all.contains("\"5_LowFlow\" [label=\"LowFlow\", style=filled"));
+        assert!(all.contains("\"2_Normal\" [label=\"Normal\", style=filled"));
+
+        let one = dot(handle_state_machines_request(
+            Some(&traces),
+            Some(DeviceId::new("c")),
+        ));
+        assert!(one.contains("label=\"c: valve\";"));
+        assert!(!one.contains("fillcolor"));
+    }
+}
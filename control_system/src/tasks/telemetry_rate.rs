@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use common::packet::{Packet, Parameter, SetParameterPacket};
+use tokio::sync::broadcast::Sender;
+use tracing::info;
+
+use crate::{
+    error::ControlError,
+    models::{
+        addressed_packet::AddressedPacket,
+        control_socket::{ControlRequest, ControlResponse},
+        device_id::DeviceId,
+        telemetry_rate::TelemetryRateCommand,
+    },
+};
+
+use super::control_socket::request;
+
+/// Answer a telemetry rate request from the control socket by asking
+/// `device` to report sensor data at `rate_hz`. The firmware only keeps the
+/// rate until it resets, so it isn't written to flash each time.
+pub fn handle_telemetry_rate_request(
+    tx_packets_to_hw: &Sender<AddressedPacket>,
+    device: DeviceId,
+    rate_hz: u8,
+) -> ControlResponse {
+    let packet = Packet::SetParameter(SetParameterPacket {
+        parameter: Parameter::TelemetryRateHz(rate_hz),
+    });
+    if let Err(e) = tx_packets_to_hw.send(AddressedPacket::new(device, packet)) {
+        return ControlResponse::Error(format!(
+            "Failed to queue telemetry rate for {}. Error: {}",
+            device.as_str(),
+            e
+        ));
+    }
+    info!(
+        "Setting the telemetry rate of {} to {} Hz.",
+        device, rate_hz
+    );
+    ControlResponse::TelemetryRate { device, rate_hz }
+}
+
+/// Run `telemetry-rate`: ask the control system listening at `socket_path`
+/// to change how often a device reports sensor data.
+pub fn handle_telemetry_rate_command(
+    socket_path: &Path,
+    command: TelemetryRateCommand,
+) -> Result<(), ControlError> {
+    let set = ControlRequest::SetTelemetryRate {
+        device: command.device,
+        rate_hz: command.rate_hz,
+    };
+    match request(socket_path, &set)? {
+        ControlResponse::TelemetryRate { device, rate_hz } => println!(
+            "Asked {} to report sensor data at {} Hz until it resets.",
+            device.as_str(),
+            rate_hz
+        ),
+        response => return Err(ControlError::from_response(response)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast;
+
+    use super::*;
+
+    #[test]
+    fn test_handle_telemetry_rate_request() {
+        let device = DeviceId::new("1324");
+        let (tx_packets_to_hw, _) = broadcast::channel(4);
+        assert!(matches!(
+            handle_telemetry_rate_request(&tx_packets_to_hw, device, 10),
+            ControlResponse::Error(_)
+        ));
+
+        let mut rx_packets_to_hw = tx_packets_to_hw.subscribe();
+        assert_eq!(
+            handle_telemetry_rate_request(&tx_packets_to_hw, device, 10),
+            ControlResponse::TelemetryRate {
+                device,
+                rate_hz: 10
+            }
+        );
+        assert_eq!(
+            rx_packets_to_hw.try_recv(),
+            Ok(AddressedPacket::new(
+                device,
+                Packet::SetParameter(SetParameterPacket {
+                    parameter: Parameter::TelemetryRateHz(10),
+                })
+            ))
+        );
+    }
+}
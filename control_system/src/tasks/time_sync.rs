@@ -0,0 +1,85 @@
+use std::time::SystemTime;
+
+use common::packet::Packet;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::models::{
+    addressed_packet::AddressedPacket,
+    device_id::DeviceId,
+    journal::unix_time_ms,
+    time_sync::{TimeSyncTracker, MAX_CLOCK_DRIFT_MS, TIME_SYNC_INTERVAL},
+};
+
+/// Task: Sync the clock of every device which reports sensors with the
+/// host's, so their reports are timestamped in host time. Devices are synced
+/// when first seen, when they report unsynced again such as after a reset,
+/// and every `TIME_SYNC_INTERVAL`. Each sync allows for the latency measured
+/// by the previous one, and clocks drifting more than `MAX_CLOCK_DRIFT_MS`
+/// between syncs are warned about.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_sync_device_clocks(
+    token: CancellationToken,
+    mut rx_packets_from_hw: Receiver<AddressedPacket>,
+    tx_packets_to_hw: Sender<AddressedPacket>,
+) {
+    info!("Started.");
+
+    let mut sync_interval = tokio::time::interval(TIME_SYNC_INTERVAL);
+    let mut tracker = TimeSyncTracker::default();
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            Ok(data) = rx_packets_from_hw.recv() => {
+                let device = data.device;
+                match data.packet {
+                    Packet::ReportSensors(report)
+                        if tracker.observe_report(device, report.sampled_at_ms.is_some()) =>
+                    {
+                        send_sync(&mut tracker, device, &tx_packets_to_hw);
+                    }
+                    Packet::TimeSyncReply(reply) => {
+                        let now_ms = unix_time_ms(SystemTime::now());
+                        let Some(outcome) = tracker.observe_reply(device, reply, now_ms) else {
+                            debug!("Ignoring stale time sync reply from {}.", device);
+                            continue;
+                        };
+                        match outcome.drift_ms {
+                            Some(drift_ms) if drift_ms.unsigned_abs() > MAX_CLOCK_DRIFT_MS => warn!(
+                                "{} clock drifted {}ms since its last sync. Round trip: {:?}",
+                                device, drift_ms, outcome.round_trip
+                            ),
+                            _ => debug!("Synced {} clock. {:?}", device, outcome),
+                        }
+                    }
+                    _ => {}
+                }
+            },
+            _ = sync_interval.tick() => {
+                let devices: Vec<DeviceId> = tracker.devices().collect();
+                for device in devices {
+                    send_sync(&mut tracker, device, &tx_packets_to_hw);
+                }
+            },
+        };
+    }
+}
+
+/// Queue a sync of `device`'s clock to the host's.
+fn send_sync(
+    tracker: &mut TimeSyncTracker,
+    device: DeviceId,
+    tx_packets_to_hw: &Sender<AddressedPacket>,
+) {
+    let sync = tracker.request(device, unix_time_ms(SystemTime::now()));
+    let packet = AddressedPacket::new(device, Packet::TimeSync(sync));
+    if let Err(e) = tx_packets_to_hw.send(packet) {
+        warn!("Failed to queue time sync. Error: {}", e);
+    }
+}
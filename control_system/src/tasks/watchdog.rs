@@ -0,0 +1,166 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::models::heartbeat::{
+    Heartbeat, HeartbeatRegistry, SharedHeartbeatRegistry, HEARTBEAT_INTERVAL,
+};
+
+/// How long a task may go without beating before it is considered stalled.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a supervised task has to exit once cancelled before it is aborted.
+pub const SUPERVISED_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Spawns a supervised task given its cancellation token and heartbeat.
+pub type SpawnTask = Box<dyn Fn(CancellationToken, Heartbeat) -> JoinHandle<()> + Send>;
+
+/// A pipeline stage the watchdog keeps alive.
+pub struct SupervisedTask {
+    pub name: &'static str,
+    pub spawn: SpawnTask,
+}
+
+/// A running supervised task.
+struct RunningTask {
+    token: CancellationToken,
+    handle: JoinHandle<()>,
+}
+
+impl SupervisedTask {
+    pub fn new(
+        name: &'static str,
+        spawn: impl Fn(CancellationToken, Heartbeat) -> JoinHandle<()> + Send + 'static,
+    ) -> Self {
+        Self {
+            name,
+            spawn: Box::new(spawn),
+        }
+    }
+
+    /// Spawn the task with a fresh heartbeat and a token cancelled with `token`.
+    fn start(&self, token: &CancellationToken, registry: &SharedHeartbeatRegistry) -> RunningTask {
+        let token = token.child_token();
+        let heartbeat = HeartbeatRegistry::register(registry, self.name);
+        RunningTask {
+            handle: (self.spawn)(token.clone(), heartbeat),
+            token,
+        }
+    }
+}
+
+/// Task: Start every supervised task and verify each keeps beating its
+/// heartbeat. A task which stops beating or exits is logged as critical,
+/// marked stalled in the heartbeat registry and restarted. Without this a
+/// deadlocked task is invisible until the CPU overheats.
+/// Replaced tasks are kept until they exit, so every task the supervisor
+/// started is waited on at shutdown.
+/// Can be cancelled.
+#[tracing::instrument(skip_all)]
+pub async fn task_supervise(
+    token: CancellationToken,
+    registry: SharedHeartbeatRegistry,
+    tasks: Vec<SupervisedTask>,
+) {
+    info!("Started.");
+
+    let mut running: HashMap<&'static str, RunningTask> = tasks
+        .iter()
+        .map(|task| (task.name, task.start(&token, &registry)))
+        .collect();
+    // NOTE: Aborting only takes effect at the task's next await, so a task
+    //       stuck in a blocking call keeps running after it is replaced.
+    let mut retired: Vec<(&'static str, RunningTask)> = vec![];
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                warn!("Cancelled.");
+                break;
+            },
+            _ = tokio::time::sleep(HEARTBEAT_INTERVAL) => {}
+        };
+
+        let mut restart = registry.take_stalled(Instant::now(), HEARTBEAT_TIMEOUT);
+        for name in &restart {
+            error!(
+                "CRITICAL: {} task stopped beating for over {:?}. Restarting it.",
+                name, HEARTBEAT_TIMEOUT
+            );
+        }
+        for (name, task) in &running {
+            if task.handle.is_finished() && !restart.contains(name) {
+                error!(
+                    "CRITICAL: {} task exited unexpectedly. Restarting it.",
+                    name
+                );
+                restart.push(name);
+            }
+        }
+
+        for task in tasks.iter().filter(|task| restart.contains(&task.name)) {
+            if let Some(stalled) = running.remove(task.name) {
+                // NOTE: Cancel first so anything the task spawned shuts down too.
+                stalled.token.cancel();
+                stalled.handle.abort();
+                retired.push((task.name, stalled));
+            }
+            registry.record_restart(task.name);
+            running.insert(task.name, task.start(&token, &registry));
+        }
+
+        let (finished, unfinished) = retired
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, task)| task.handle.is_finished());
+        retired = unfinished;
+        for (name, task) in finished {
+            match task.handle.await {
+                Ok(()) => info!("Replaced {} task exited.", name),
+                Err(e) if e.is_cancelled() => info!("Replaced {} task was aborted.", name),
+                Err(e) => error!("Replaced {} task failed. Error: {}", name, e),
+            }
+        }
+
+        if !restart.is_empty() {
+            for (name, status) in registry.health() {
+                info!(
+                    "Task health: {} is {} after {} restarts.",
+                    name, status.health, status.restarts
+                );
+            }
+        }
+    }
+
+    // NOTE: Wait on every task together so the supervisor's own deadline
+    //       doesn't depend on how many tasks were replaced.
+    futures::future::join_all(
+        running
+            .into_iter()
+            .chain(retired)
+            .map(|(name, task)| wait_for_exit(name, task)),
+    )
+    .await;
+}
+
+/// Wait for a cancelled supervised task to exit, aborting it if it overruns
+/// `SUPERVISED_SHUTDOWN_TIMEOUT`.
+async fn wait_for_exit(name: &'static str, mut task: RunningTask) {
+    match tokio::time::timeout(SUPERVISED_SHUTDOWN_TIMEOUT, &mut task.handle).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) if e.is_cancelled() => {}
+        Ok(Err(e)) => error!("Supervised task {} failed. Error: {}", name, e),
+        Err(_) => {
+            // NOTE: The serial read blocks, so the comm task can miss its cancellation.
+            warn!(
+                "Supervised task {} ignored cancellation for {:?}. Aborting it.",
+                name, SUPERVISED_SHUTDOWN_TIMEOUT
+            );
+            task.handle.abort();
+        }
+    }
+}
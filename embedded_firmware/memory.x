@@ -1,5 +1,5 @@
 MEMORY
 {
-  FLASH (rx) : ORIGIN = 0x00000000+0x2000, LENGTH = 0x00040000-0x2000 /* bootloader 8kb */
+  FLASH (rx) : ORIGIN = 0x00000000+0x2000, LENGTH = 0x00040000-0x2000-0x200 /* bootloader 8kb, settings rows 2x256b */
   RAM (rwx) : ORIGIN = 0x20000000, LENGTH = 0x00008000
 }
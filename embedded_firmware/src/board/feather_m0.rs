@@ -0,0 +1,81 @@
+//! Pin assignments for an Adafruit Feather M0 wired to the controller
+//! peripherals.
+//!
+//! | Signal          | Pin  | Feather label |
+//! | --------------- | ---- | ------------- |
+//! | Pump PWM        | PA04 | A3            |
+//! | Fan PWM         | PA05 | A4            |
+//! | Pump sense      | PB08 | A1            |
+//! | Fan sense       | PB09 | A2            |
+//! | Pump current    | PA02 | A0            |
+//! | Fan current     | PB02 | A5            |
+//! | Valve sense 1   | PA20 | D6            |
+//! | Valve sense 2   | PA15 | D5            |
+//! | Valve control 1 | PA18 | D10           |
+//! | Valve control 2 | PA16 | D11           |
+//! | Flow pulse      | PA19 | D12           |
+//! | Coolant temp    | PA11 | D0            |
+//!
+//! NOTE: PA07 (D9) is tied to the battery voltage divider on the Feather, so
+//!       the speed senses use A1/A2 rather than the MKR Zero's PA06/PA07.
+
+use atsamd_hal::{
+    gpio::{
+        Alternate, Input, Output, Pin, Pins, PullDown, PullUp, PushPull, B, E, G, PA02, PA04, PA05,
+        PA11, PA15, PA16, PA18, PA19, PA20, PA24, PA25, PB02, PB08, PB09,
+    },
+    pac::PORT,
+};
+use common::packet::Capabilities;
+
+use super::BoardPins;
+
+/// Reported to the host along with the firmware version.
+pub const BOARD_NAME: &str = "feather_m0";
+
+/// The UF2 bootloader's double tap magic.
+pub const BOOTLOADER_MAGIC: u32 = 0xF016_69EF;
+
+/// What the Feather wiring above is wired for.
+pub const CAPABILITIES: Capabilities = Capabilities {
+    fan_channels: 1,
+    thermistors: 1,
+    valve_driver: true,
+    valve_sense: true,
+};
+
+pub type UsbDmPin = Pin<PA24, Alternate<G>>;
+pub type UsbDpPin = Pin<PA25, Alternate<G>>;
+pub type PumpPwmPin = Pin<PA04, Alternate<E>>;
+pub type FanPwmPin = Pin<PA05, Alternate<E>>;
+pub type PumpSensePin = Pin<PB08, Alternate<B>>;
+pub type FanSensePin = Pin<PB09, Alternate<B>>;
+pub type PumpCurrentPin = Pin<PA02, Alternate<B>>;
+pub type FanCurrentPin = Pin<PB02, Alternate<B>>;
+pub type ValveSense1Pin = Pin<PA20, Input<PullDown>>;
+pub type ValveSense2Pin = Pin<PA15, Input<PullDown>>;
+pub type ValveControl1Pin = Pin<PA18, Output<PushPull>>;
+pub type ValveControl2Pin = Pin<PA16, Output<PushPull>>;
+pub type FlowPulsePin = Pin<PA19, Input<PullUp>>;
+pub type CoolantThermistorPin = Pin<PA11, Alternate<B>>;
+
+/// Take and configure every pin the application uses.
+pub fn take_pins(port: PORT) -> BoardPins {
+    let pins = Pins::new(port);
+    BoardPins {
+        usb_dm: pins.pa24.into_mode(),
+        usb_dp: pins.pa25.into_mode(),
+        pump_pwm: pins.pa04.into_mode(),
+        fan_pwm: pins.pa05.into_mode(),
+        pump_sense: pins.pb08.into_mode(),
+        fan_sense: pins.pb09.into_mode(),
+        pump_current: pins.pa02.into_mode(),
+        fan_current: pins.pb02.into_mode(),
+        valve_sense_1: pins.pa20.into_pull_down_input(),
+        valve_sense_2: pins.pa15.into_pull_down_input(),
+        valve_control_1: pins.pa18.into_push_pull_output(),
+        valve_control_2: pins.pa16.into_push_pull_output(),
+        flow_pulse: pins.pa19.into_pull_up_input(),
+        coolant_thermistor: pins.pa11.into_mode(),
+    }
+}
@@ -0,0 +1,63 @@
+//! Pin assignments for the Arduino MKR Zero based controller PCB.
+
+use arduino_mkrzero as bsp;
+use atsamd_hal::{
+    gpio::{
+        Alternate, Input, Output, Pin, PullDown, PullUp, PushPull, B, E, G, PA02, PA04, PA05, PA06,
+        PA07, PA10, PA11, PA21, PA22, PA23, PA24, PA25, PB02, PB03,
+    },
+    pac::PORT,
+};
+use common::packet::Capabilities;
+
+use super::BoardPins;
+
+/// Reported to the host along with the firmware version.
+pub const BOARD_NAME: &str = "mkrzero";
+
+/// The Arduino SAM-BA bootloader's double tap magic.
+pub const BOOTLOADER_MAGIC: u32 = 0x0773_8135;
+
+/// What the controller PCB is wired for.
+pub const CAPABILITIES: Capabilities = Capabilities {
+    fan_channels: 1,
+    thermistors: 1,
+    valve_driver: true,
+    valve_sense: true,
+};
+
+pub type UsbDmPin = Pin<PA24, Alternate<G>>;
+pub type UsbDpPin = Pin<PA25, Alternate<G>>;
+pub type PumpPwmPin = Pin<PA04, Alternate<E>>;
+pub type FanPwmPin = Pin<PA05, Alternate<E>>;
+pub type PumpSensePin = Pin<PA06, Alternate<B>>;
+pub type FanSensePin = Pin<PA07, Alternate<B>>;
+pub type PumpCurrentPin = Pin<PA02, Alternate<B>>;
+pub type FanCurrentPin = Pin<PB02, Alternate<B>>;
+pub type ValveSense1Pin = Pin<PA10, Input<PullDown>>;
+pub type ValveSense2Pin = Pin<PA11, Input<PullDown>>;
+pub type ValveControl1Pin = Pin<PA22, Output<PushPull>>;
+pub type ValveControl2Pin = Pin<PA23, Output<PushPull>>;
+pub type FlowPulsePin = Pin<PA21, Input<PullUp>>;
+pub type CoolantThermistorPin = Pin<PB03, Alternate<B>>;
+
+/// Take and configure every pin the application uses.
+pub fn take_pins(port: PORT) -> BoardPins {
+    let pins = bsp::pins::Pins::new(port);
+    BoardPins {
+        usb_dm: bsp::pin_alias!(pins.usb_n).into(),
+        usb_dp: bsp::pin_alias!(pins.usb_p).into(),
+        pump_pwm: pins.pa04.into_mode(),
+        fan_pwm: pins.pa05.into_mode(),
+        pump_sense: pins.pa06.into_mode(),
+        fan_sense: pins.pa07.into_mode(),
+        pump_current: pins.pa02.into_mode(),
+        fan_current: pins.pb02.into_mode(),
+        valve_sense_1: pins.pa10.into_pull_down_input(),
+        valve_sense_2: pins.pa11.into_pull_down_input(),
+        valve_control_1: pins.pa22.into_push_pull_output(),
+        valve_control_2: pins.pa23.into_push_pull_output(),
+        flow_pulse: pins.pa21.into_pull_up_input(),
+        coolant_thermistor: pins.pb03.into_mode(),
+    }
+}
@@ -0,0 +1,87 @@
+//! Pin assignments for each supported board. Exactly one `board-*` feature
+//! selects which board the firmware is built for. Every board exposes the same
+//! pin type aliases, `take_pins`, `CAPABILITIES`, `BOARD_NAME` and
+//! `BOOTLOADER_MAGIC` so the rest of the firmware is unchanged.
+//!
+//! NOTE: Every supported board uses the SAMD21G18A, so clocks, TCC0 PWM, the
+//!       ADC and USB are shared. Only the pins each signal is routed to differ.
+
+use atsamd_hal::{
+    clock::GenericClockController,
+    pac::{PM, USB},
+    usb::UsbBus,
+};
+use usb_device::bus::UsbBusAllocator;
+
+/// Where the bootloader looks for `BOOTLOADER_MAGIC` after a reset: the last
+/// word of RAM, which it leaves alone when starting the firmware.
+const BOOTLOADER_MAGIC_ADDRESS: *mut u32 = 0x2000_7FFC as *mut u32;
+
+#[cfg(all(feature = "board-mkrzero", feature = "board-feather-m0"))]
+compile_error!("Only one `board-*` feature can be enabled.");
+
+#[cfg(not(any(feature = "board-mkrzero", feature = "board-feather-m0")))]
+compile_error!("A `board-*` feature must be enabled.");
+
+#[cfg(feature = "board-mkrzero")]
+mod mkrzero;
+#[cfg(feature = "board-mkrzero")]
+pub use mkrzero::*;
+
+#[cfg(feature = "board-feather-m0")]
+mod feather_m0;
+#[cfg(feature = "board-feather-m0")]
+pub use feather_m0::*;
+
+/// Every pin the application uses, configured for its function.
+pub struct BoardPins {
+    pub usb_dm: UsbDmPin,
+    pub usb_dp: UsbDpPin,
+
+    /// Routed to TCC0 WO[0], which is PWM channel 0.
+    pub pump_pwm: PumpPwmPin,
+
+    /// Routed to TCC0 WO[1], which is PWM channel 1.
+    pub fan_pwm: FanPwmPin,
+
+    pub pump_sense: PumpSensePin,
+    pub fan_sense: FanSensePin,
+    pub pump_current: PumpCurrentPin,
+    pub fan_current: FanCurrentPin,
+
+    pub valve_sense_1: ValveSense1Pin,
+    pub valve_sense_2: ValveSense2Pin,
+    pub valve_control_1: ValveControl1Pin,
+    pub valve_control_2: ValveControl2Pin,
+
+    /// The open collector output of a hall effect flow meter.
+    pub flow_pulse: FlowPulsePin,
+
+    /// The midpoint of a divider with an NTC thermistor in the coolant on
+    /// its low side.
+    pub coolant_thermistor: CoolantThermistorPin,
+}
+
+/// Reset into the bootloader, which then stays in it rather than starting the
+/// firmware, as if the reset button was double tapped.
+pub fn enter_bootloader() -> ! {
+    cortex_m::interrupt::disable();
+    // NOTE: Overwrites the top of the stack (the end of RAM, where the stack
+    //       starts growing down from), which is fine since nothing runs
+    //       between here and the reset.
+    unsafe { core::ptr::write_volatile(BOOTLOADER_MAGIC_ADDRESS, BOOTLOADER_MAGIC) };
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Create the USB bus allocator from the board's USB pins.
+pub fn usb_allocator(
+    usb: USB,
+    clocks: &mut GenericClockController,
+    pm: &mut PM,
+    usb_dm: UsbDmPin,
+    usb_dp: UsbDpPin,
+) -> UsbBusAllocator<UsbBus> {
+    let gclk0 = clocks.gclk0();
+    let usb_clock = &clocks.usb(&gclk0).unwrap();
+    UsbBusAllocator::new(UsbBus::new(usb_clock, pm, usb_dm, usb_dp, usb))
+}
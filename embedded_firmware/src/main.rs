@@ -1,83 +1,86 @@
 #![no_std]
 #![no_main]
 
-use arduino_mkrzero as bsp;
-use bsp::hal;
-use common::packet::Packet;
+use atsamd_hal as hal;
+use common::packet::{FirmwareInfoPacket, Packet};
 use cortex_m::peripheral::NVIC;
-use embedded_firmware_core::application::Application;
+use embedded_firmware_core::application::{Application, ResetRequest};
+use embedded_firmware_core::comms::Comms;
+use embedded_firmware_core::control::Control;
+use embedded_firmware_core::packet_io::UsbPacketIo;
+use embedded_firmware_core::reset_cause::from_samd21_rcause;
+use embedded_firmware_core::sensing::Sensing;
+use embedded_firmware_core::settings::DualBankStorage;
 use embedded_firmware_core::PrandtlAdc;
 use embedded_hal::adc::Channel as AdcChannel;
 use embedded_hal::blocking::delay::DelayMs;
 use embedded_hal::digital::v2::OutputPin;
 use hal::adc::Adc;
-use hal::gpio::{
-    Alternate, Input, Output, Pin, PullDown, PushPull, B, PA04, PA05, PA06, PA07, PA10, PA11, PA22, PA23,
-};
 use hal::pwm::{Channel, Pwm0, Pwm1};
 use panic_halt as _;
 
-use bsp::entry;
+use cortex_m_rt::entry;
 use hal::clock::GenericClockController;
 use hal::delay::Delay;
 use hal::pac::{interrupt, CorePeripherals, Peripherals, ADC};
+use hal::prelude::*;
 use hal::usb::UsbBus;
-use hal::{gpio, prelude::*};
 
 use usb_device::bus::UsbBusAllocator;
 
+mod board;
+mod nvmsettings;
 mod prandtladc;
+use nvmsettings::NvmSettingsBanks;
 use prandtladc::*;
 
 static mut BUS_ALLOCATOR: Option<UsbBusAllocator<UsbBus>> = None;
 static mut APPLICATION: Option<
     Application<
-        'static,
-        UsbBus,
+        UsbPacketIo<'static, UsbBus>,
         Delay,
         Pwm0,
         PrandtlPumpFanAdc,
-        Pin<PA10, Input<PullDown>>,
-        Pin<PA11, Input<PullDown>>,
-        Pin<PA22, Output<PushPull>>,
-        Pin<PA23, Output<PushPull>>,
+        board::ValveSense1Pin,
+        board::ValveSense2Pin,
+        board::ValveControl1Pin,
+        board::ValveControl2Pin,
+        DualBankStorage<NvmSettingsBanks>,
     >,
 > = None;
 
 fn initialize() {
     let mut peripherals = Peripherals::take().unwrap();
     let mut core = CorePeripherals::take().unwrap();
+    // NOTE: Latched until the next reset, read before anything else touches PM.
+    let reset_cause = from_samd21_rcause(peripherals.PM.rcause.read().bits());
     let mut clocks = GenericClockController::with_external_32kosc(
         peripherals.GCLK,
         &mut peripherals.PM,
         &mut peripherals.SYSCTRL,
         &mut peripherals.NVMCTRL,
     );
-    let pins = bsp::pins::Pins::new(peripherals.PORT);
+    let pins = board::take_pins(peripherals.PORT);
     let mut delay = Delay::new(core.SYST, &mut clocks);
 
-    // Setup the fan & pump pwm pins
-    // TODO: Extract to function
-    let _pump_ctrl_pwm0_pin = pins.pa04.into_mode::<hal::gpio::AlternateE>(); // pump ctrl pwm1
-    let _fan_ctrl_pwm0_pin = pins.pa05.into_mode::<hal::gpio::AlternateE>(); // fan ctrl pwm01
+    // NOTE: The pump and fan pwm pins only need to stay in their TCC0 mode.
+    let _pump_ctrl_pwm0_pin = pins.pump_pwm;
+    let _fan_ctrl_pwm0_pin = pins.fan_pwm;
 
-    let usb_n = bsp::pin_alias!(pins.usb_n);
-    let usb_p = bsp::pin_alias!(pins.usb_p);
+    let valve_sense_1_pin = pins.valve_sense_1;
+    let valve_sense_2_pin = pins.valve_sense_2;
 
-    let valve_sense_1_pin = pins.pa10.into_pull_down_input();
-    let valve_sense_2_pin = pins.pa11.into_pull_down_input();
-
-    let valve_control_1_pin = pins.pa22.into_push_pull_output();
-    let valve_control_2_pin = pins.pa23.into_push_pull_output();
+    let valve_control_1_pin = pins.valve_control_1;
+    let valve_control_2_pin = pins.valve_control_2;
 
     // this stays
     unsafe {
-        BUS_ALLOCATOR = Some(bsp::usb::usb_allocator(
+        BUS_ALLOCATOR = Some(board::usb_allocator(
             peripherals.USB,
             &mut clocks,
             &mut peripherals.PM,
-            usb_n.into(),
-            usb_p.into(),
+            pins.usb_dm,
+            pins.usb_dp,
         ));
     }
 
@@ -92,26 +95,45 @@ fn initialize() {
         &mut peripherals.PM,
     );
 
+    // NOTE: Power the internal temperature sensor used for board temperature.
+    peripherals.SYSCTRL.vref.modify(|_, w| w.tsen().set_bit());
+
     // NOTE: This is a 3v3 ADC. 0V -> 0 3.3V -> 4096
     let mut adc = Adc::adc(peripherals.ADC, &mut peripherals.PM, &mut clocks);
-    let mut pump_sense_channel = pins.pa06.into_mode::<gpio::AlternateB>();
-    let mut fan_sense_channel = pins.pa07.into_mode::<gpio::AlternateB>();
+    let padc = PrandtlPumpFanAdc::new(
+        adc,
+        pins.pump_sense,
+        pins.fan_sense,
+        pins.pump_current,
+        pins.fan_current,
+        pins.flow_pulse,
+        pins.coolant_thermistor,
+        12,
+    );
 
-    let padc = PrandtlPumpFanAdc::new(adc, pump_sense_channel, fan_sense_channel, 12);
+    let settings_storage = DualBankStorage::new(NvmSettingsBanks::new(peripherals.NVMCTRL));
 
     // NOTE: This must happen before we enable USB interrupt.
     unsafe {
         APPLICATION = Some(Application::new(
-            BUS_ALLOCATOR.as_ref().unwrap(),
+            Comms::new(UsbPacketIo::new(BUS_ALLOCATOR.as_ref().unwrap())),
+            Control::new(
+                pump_pwm,
+                Channel::_0,
+                Channel::_1,
+                valve_control_1_pin,
+                valve_control_2_pin,
+            ),
+            Sensing::new(padc, valve_sense_1_pin, valve_sense_2_pin),
             delay,
-            pump_pwm,
-            Channel::_0,
-            Channel::_1,
-            padc,
-            valve_sense_1_pin,
-            valve_sense_2_pin,
-            valve_control_1_pin,
-            valve_control_2_pin,
+            board::CAPABILITIES,
+            FirmwareInfoPacket::new(
+                env!("CARGO_PKG_VERSION"),
+                env!("PRANDTL_GIT_HASH"),
+                board::BOARD_NAME,
+            ),
+            reset_cause,
+            settings_storage,
         ));
     }
 
@@ -133,19 +155,36 @@ fn main() -> ! {
 
     loop {
         cortex_m::interrupt::free(|cs| unsafe {
-            app.read_packets_from_usb(cs);
-            app.write_packets_to_usb(cs);
+            app.read_packets(cs);
+            app.write_packets(cs);
         });
 
         app.core_loop();
 
-        app.delay.delay_ms(100u16);
+        // NOTE: Targets are output at once so nothing sees them half applied.
+        cortex_m::interrupt::free(|cs| app.apply_control_targets(cs));
+
+        // NOTE: Stored settings and the host can both change the frequency.
+        if let Some(pwm_frequency_hz) = app.take_pwm_frequency_hz() {
+            cortex_m::interrupt::free(|cs| app.set_pwm_period((pwm_frequency_hz as u32).Hz(), cs));
+        }
+
+        // NOTE: The pump and fan were just parked at the fail-safe duty.
+        match app.reset_requested() {
+            Some(ResetRequest::Reboot) => cortex_m::peripheral::SCB::sys_reset(),
+            Some(ResetRequest::Bootloader) => board::enter_bootloader(),
+            None => {}
+        }
+
+        // NOTE: Read each iteration since the host can change it at runtime.
+        let core_loop_period_ms = app.core_loop_period_ms();
+        app.delay.delay_ms(core_loop_period_ms);
     }
 }
 
 #[interrupt]
 fn USB() {
     unsafe {
-        APPLICATION.as_mut().unwrap().poll_usb();
+        APPLICATION.as_mut().unwrap().poll_io();
     }
 }
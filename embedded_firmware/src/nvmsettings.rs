@@ -0,0 +1,85 @@
+use atsamd_hal::pac::NVMCTRL;
+use embedded_firmware_core::settings::{SettingsBanks, SettingsStorageError, SETTINGS_BANK_SIZE};
+
+/// Address of the flash row holding each settings bank. These are the last two
+/// rows of flash and are excluded from the `FLASH` region in `memory.x`.
+/// NOTE: Each bank has its own row since a row is the smallest erasable unit.
+const SETTINGS_ROW_ADDRESSES: [u32; 2] = [0x0003_FE00, 0x0003_FF00];
+
+/// Size of a single flash page. Writes happen a page at a time.
+const PAGE_SIZE: usize = SETTINGS_BANK_SIZE;
+
+/// Stores each settings bank in the first page of its own row of internal flash.
+pub struct NvmSettingsBanks {
+    nvmctrl: NVMCTRL,
+}
+
+impl NvmSettingsBanks {
+    pub fn new(nvmctrl: NVMCTRL) -> Self {
+        // NOTE: Manual write mode so page writes only happen on the WP command.
+        nvmctrl.ctrlb.modify(|_, w| w.manw().set_bit());
+        Self { nvmctrl }
+    }
+
+    /// Block until the NVM controller is ready for the next command.
+    fn wait_ready(&self) {
+        while self.nvmctrl.intflag.read().ready().bit_is_clear() {}
+    }
+
+    /// Erase a row. Erased flash reads back as all 0xFF.
+    fn erase_row(&mut self, row_address: u32) {
+        self.wait_ready();
+        self.nvmctrl
+            .addr
+            .write(|w| unsafe { w.addr().bits(row_address >> 1) });
+        self.nvmctrl.ctrla.write(|w| w.cmdex().key().cmd().er());
+        self.wait_ready();
+    }
+
+    /// Write a single page worth of bytes to the start of a row.
+    fn write_page(&mut self, row_address: u32, page: &[u8; PAGE_SIZE]) {
+        self.wait_ready();
+        self.nvmctrl.ctrla.write(|w| w.cmdex().key().cmd().pbc());
+        self.wait_ready();
+
+        // NOTE: The page buffer must be written using 32-bit accesses.
+        for (index, word) in page.chunks_exact(4).enumerate() {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            let address = (row_address as usize + index * 4) as *mut u32;
+            unsafe { core::ptr::write_volatile(address, word) };
+        }
+
+        self.nvmctrl
+            .addr
+            .write(|w| unsafe { w.addr().bits(row_address >> 1) });
+        self.nvmctrl.ctrla.write(|w| w.cmdex().key().cmd().wp());
+        self.wait_ready();
+    }
+
+    /// Read the first page of a row.
+    fn read_page(&self, row_address: u32) -> [u8; PAGE_SIZE] {
+        let mut page = [0u8; PAGE_SIZE];
+        for (index, byte) in page.iter_mut().enumerate() {
+            let address = (row_address as usize + index) as *const u8;
+            *byte = unsafe { core::ptr::read_volatile(address) };
+        }
+        page
+    }
+}
+
+impl SettingsBanks for NvmSettingsBanks {
+    fn read_bank(&mut self, bank: u8) -> [u8; SETTINGS_BANK_SIZE] {
+        self.read_page(SETTINGS_ROW_ADDRESSES[bank as usize])
+    }
+
+    fn write_bank(
+        &mut self,
+        bank: u8,
+        bytes: &[u8; SETTINGS_BANK_SIZE],
+    ) -> Result<(), SettingsStorageError> {
+        let row_address = SETTINGS_ROW_ADDRESSES[bank as usize];
+        self.erase_row(row_address);
+        self.write_page(row_address, bytes);
+        Ok(())
+    }
+}
@@ -1,35 +1,139 @@
+use crate::board::{
+    CoolantThermistorPin, FanCurrentPin, FanSensePin, FlowPulsePin, PumpCurrentPin, PumpSensePin,
+};
 use crate::hal::prelude::*;
 use atsamd_hal::{
-    adc::Adc,
-    gpio::{Alternate, Pin, B, PA06, PA07},
+    adc::{Adc, Gain, Reference},
     pac::ADC,
 };
-use embedded_firmware_core::{convert_raw_to_normalized, PrandtlAdc};
+use embedded_firmware_core::{
+    board_temperature::TemperatureCalibration, convert_raw_to_normalized,
+    current_sense::CurrentSensorCalibration, thermistor::ThermistorCalibration, PrandtlAdc,
+};
+use embedded_hal::{adc::Channel, digital::v2::InputPin};
+
+/// Address of the NVM Temperature Log Row holding the factory calibration.
+const TEMPERATURE_LOG_ROW_ADDRESS: usize = 0x0080_6030;
 
-pub type PumpPin = Pin<PA06, Alternate<B>>;
-pub type FanPin = Pin<PA07, Alternate<B>>;
+/// The internal temperature sensor's ADC input.
+/// NOTE: SYSCTRL.VREF.TSEN must be set for the sensor to be powered.
+pub struct TemperatureSensor;
+
+impl Channel<ADC> for TemperatureSensor {
+    type ID = u8;
+    fn channel() -> u8 {
+        0x18
+    }
+}
+
+/// The ADC's internal 1/4 scaled I/O supply (VDDANA on this board) input.
+pub struct SupplyVoltageSensor;
+
+impl Channel<ADC> for SupplyVoltageSensor {
+    type ID = u8;
+    fn channel() -> u8 {
+        0x1B
+    }
+}
+
+/// The scaling applied to the supply voltage before it reaches the ADC.
+const SUPPLY_VOLTAGE_SCALE: f32 = 4f32;
+
+/// The voltage represented by a full scale external input reading.
+const ADC_FULL_SCALE_VOLTAGE: f32 = 3.3f32;
+
+/// The 5V current sensor outputs pass through a divider to fit the 3v3 ADC.
+const CURRENT_SENSE_DIVIDER: f32 = 2f32 / 3f32;
+
+/// ACS712-05B output with no current flowing, before the divider.
+const CURRENT_SENSE_ZERO_VOLTAGE: f32 = 2.5f32;
+
+/// ACS712-05B sensitivity, before the divider.
+const CURRENT_SENSE_VOLTS_PER_AMP: f32 = 0.185f32;
+
+/// The coolant thermistor is a 10k beta 3950 NTC, below a 10k resistor to
+/// the 3v3 rail the ADC reads against.
+const THERMISTOR_SERIES_OHMS: f32 = 10_000f32;
+const THERMISTOR_NOMINAL_OHMS: f32 = 10_000f32;
+const THERMISTOR_NOMINAL_TEMPERATURE: f32 = 25f32;
+const THERMISTOR_BETA: f32 = 3950f32;
 
 pub struct PrandtlPumpFanAdc {
     adc: Adc<ADC>,
-    pump_sense_channel: PumpPin,
-    fan_sense_channel: FanPin,
+    pump_sense_channel: PumpSensePin,
+    fan_sense_channel: FanSensePin,
+    pump_current_channel: PumpCurrentPin,
+    fan_current_channel: FanCurrentPin,
+    flow_pulse_pin: FlowPulsePin,
+    coolant_thermistor_channel: CoolantThermistorPin,
+    current_calibration: Option<CurrentSensorCalibration>,
+    thermistor_calibration: Option<ThermistorCalibration>,
+    temperature_calibration: Option<TemperatureCalibration>,
     resolution: u8,
 }
 
 impl PrandtlPumpFanAdc {
     pub fn new(
         adc: Adc<ADC>,
-        pump_sense_channel: PumpPin,
-        fan_sense_channel: FanPin,
+        pump_sense_channel: PumpSensePin,
+        fan_sense_channel: FanSensePin,
+        pump_current_channel: PumpCurrentPin,
+        fan_current_channel: FanCurrentPin,
+        flow_pulse_pin: FlowPulsePin,
+        coolant_thermistor_channel: CoolantThermistorPin,
         resolution: u8,
     ) -> Self {
         Self {
             adc,
             pump_sense_channel,
             fan_sense_channel,
+            pump_current_channel,
+            fan_current_channel,
+            flow_pulse_pin,
+            coolant_thermistor_channel,
+            current_calibration: CurrentSensorCalibration::new(
+                CURRENT_SENSE_ZERO_VOLTAGE * CURRENT_SENSE_DIVIDER,
+                CURRENT_SENSE_VOLTS_PER_AMP * CURRENT_SENSE_DIVIDER,
+            ),
+            thermistor_calibration: ThermistorCalibration::new(
+                THERMISTOR_SERIES_OHMS,
+                THERMISTOR_NOMINAL_OHMS,
+                THERMISTOR_NOMINAL_TEMPERATURE,
+                THERMISTOR_BETA,
+            ),
+            temperature_calibration: read_temperature_calibration(),
             resolution,
         }
     }
+
+    /// Read an internal channel against the 1V reference at 1x gain.
+    /// NOTE: Restores the pump and fan sense configuration afterwards.
+    fn read_internal<C: Channel<ADC, ID = u8>>(&mut self, channel: &mut C) -> Option<u16> {
+        self.adc.reference(Reference::INT1V);
+        self.adc.gain(Gain::_1X);
+        let raw: Result<u16, _> = self.adc.read(channel);
+        self.adc.gain(Gain::DIV2);
+        self.adc.reference(Reference::INTVCC1);
+        raw.ok()
+    }
+
+    /// Convert a raw current sense reading into amps.
+    fn convert_current(&self, raw: u16) -> Option<f32> {
+        let calibration = self.current_calibration?;
+        let voltage = convert_raw_to_normalized(raw, self.resolution) * ADC_FULL_SCALE_VOLTAGE;
+        Some(calibration.current(voltage))
+    }
+}
+
+/// Read the factory temperature sensor calibration from NVM.
+fn read_temperature_calibration() -> Option<TemperatureCalibration> {
+    let row = unsafe {
+        let words = TEMPERATURE_LOG_ROW_ADDRESS as *const u32;
+        let low = core::ptr::read_volatile(words) as u64;
+        let high = core::ptr::read_volatile(words.add(1)) as u64;
+        (high << 32) | low
+    };
+    TemperatureCalibration::from_log_row(row)
 }
 
 impl PrandtlAdc for PrandtlPumpFanAdc {
@@ -56,4 +160,40 @@ impl PrandtlAdc for PrandtlPumpFanAdc {
         self.read_fan_sense_raw()
             .map(|raw| convert_raw_to_normalized(raw, self.resolution))
     }
+
+    fn read_board_temperature(&mut self) -> Option<f32> {
+        // NOTE: The calibration was taken against the 1V reference at 1x gain.
+        let calibration = self.temperature_calibration?;
+        let mut sensor = TemperatureSensor;
+        self.read_internal(&mut sensor)
+            .map(|raw| calibration.temperature(raw))
+    }
+
+    fn read_coolant_temperature(&mut self) -> Option<f32> {
+        // NOTE: The divider and the ADC share the 3v3 rail, so the reading is
+        //       ratiometric and supply drift cancels out.
+        let calibration = self.thermistor_calibration?;
+        let raw: u16 = self.adc.read(&mut self.coolant_thermistor_channel).ok()?;
+        calibration.temperature(convert_raw_to_normalized(raw, self.resolution))
+    }
+
+    fn read_supply_voltage(&mut self) -> Option<f32> {
+        let mut sensor = SupplyVoltageSensor;
+        self.read_internal(&mut sensor)
+            .map(|raw| convert_raw_to_normalized(raw, self.resolution) * SUPPLY_VOLTAGE_SCALE)
+    }
+
+    fn read_pump_current(&mut self) -> Option<f32> {
+        let raw: u16 = self.adc.read(&mut self.pump_current_channel).ok()?;
+        self.convert_current(raw)
+    }
+
+    fn read_fan_current(&mut self) -> Option<f32> {
+        let raw: u16 = self.adc.read(&mut self.fan_current_channel).ok()?;
+        self.convert_current(raw)
+    }
+
+    fn read_flow_pulse(&mut self) -> Option<bool> {
+        self.flow_pulse_pin.is_high().ok()
+    }
 }
@@ -1,26 +1,91 @@
 use bare_metal::CriticalSection;
 use common::{
-    packet::Packet,
-    physical::{Rpm, ValveState},
+    packet::{
+        sequence_gap, AcceptConnectionPacket, AckPacket, Capabilities, FanChannels, FirmwareConfig,
+        FirmwareError, FirmwareInfoPacket, GetParameterPacket, HeartbeatPacket, LogLevel, Packet,
+        Parameter, PingPacket, PongPacket, ReportConfigPacket, ReportControlTargetsPacket,
+        ReportDeviceInfoPacket, ReportErrorPacket, ReportParameterPacket, ReportSensorsBatchPacket,
+        ReportStatsPacket, ResetCause, SetCalibrationPacket, SetEchoModePacket, SetParameterPacket,
+        SetSensorBatchingPacket, SetTuningPacket, SettingsOrigin, TimeSyncPacket,
+        TimeSyncReplyPacket, TuningParameters, ValveCommandPacket, ValveTransitionCompletePacket,
+        WriteConfigPacket, FAST_RPM_RATE_HZ, HEARTBEAT_RATE_HZ, PACKET_QUEUE_CAPACITY,
+        PROTOCOL_VERSION, SENSOR_BATCH_FLUSH_MS, SENSOR_BATCH_SAMPLE_RATE_HZ,
+        VALVE_TRANSITION_TIMEOUT_MS,
+    },
+    physical::{Percentage, ValveState},
 };
 use embedded_hal::{
     blocking::delay::DelayMs,
     digital::v2::{InputPin, OutputPin},
     Pwm,
 };
+use fixedstr::{str64, str_format};
 use heapless::Vec;
-use usb_device::{
-    bus::UsbBus,
-    class_prelude::UsbBusAllocator,
-    device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
+
+use crate::{
+    comms::Comms,
+    control::Control,
+    log_buffer::{LogBuffer, LOG_MODULE_APPLICATION, LOG_MODULE_COMMS, LOG_MODULE_SETTINGS},
+    packet_io::PacketIo,
+    scheduler::{Scheduler, Ticks},
+    sensing::Sensing,
+    settings::{Settings, SettingsStorage},
+    ApplicationError, PrandtlAdc,
 };
-use usbd_serial::{SerialPort, USB_CLASS_CDC};
 
-use crate::{ApplicationError, PrandtlAdc};
+/// Periodic work run from `core_loop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Job {
+    /// Report sensors and stats to the host at the telemetry rate.
+    ReportSensors,
+
+    /// Report just the fan and pump speeds at `FAST_RPM_RATE_HZ`.
+    ReportRpmFast,
+
+    /// Send a heartbeat to the host at `HEARTBEAT_RATE_HZ`.
+    Heartbeat,
+
+    /// Sample the speeds and valve state into the sensor batch at
+    /// `SENSOR_BATCH_SAMPLE_RATE_HZ`, while batching.
+    SampleSensors,
+
+    /// Send the sensor batch every `SENSOR_BATCH_FLUSH_MS`, while batching.
+    FlushSensorBatch,
+}
+
+/// How many jobs the scheduler has room for.
+const MAX_JOBS: usize = 6;
+
+/// How long the host may go without sending anything, in milliseconds,
+/// before the link is considered lost. Several heartbeat periods.
+pub const LINK_LOSS_TIMEOUT_MS: u32 = 2000;
+
+/// The targets output when the link to the host is lost: the pump and fan at
+/// the fail-safe duty, so the loop is cooled hard until the host is back.
+/// The valve is commanded open alongside them.
+pub fn safe_targets(settings: &Settings) -> ReportControlTargetsPacket {
+    let duty = Percentage::const_new(settings.failsafe_duty_percent.min(100));
+    ReportControlTargetsPacket {
+        fan_control_percents: FanChannels::single(duty),
+        pump_control_percent: duty,
+        sequence: 0,
+    }
+}
+
+/// A reset the host asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetRequest {
+    /// Restart the firmware.
+    Reboot,
+
+    /// Restart into the bootloader to take a firmware update.
+    Bootloader,
+}
 
+/// Orchestrates the components. Routes packets from `Comms` to `Control` and
+/// the settings, and schedules `Sensing` reports back to the host.
 pub struct Application<
-    'a,
-    B: UsbBus,
+    Io: PacketIo,
     D: DelayMs<u16>,
     P1: Pwm,
     PAdc: PrandtlAdc,
@@ -28,35 +93,118 @@ pub struct Application<
     ValveState2Pin: InputPin,
     ValveControl1Pin: OutputPin,
     ValveControl2Pin: OutputPin,
+    Storage: SettingsStorage,
 > {
-    pub serial_port: SerialPort<'a, B>,
-    pub usb_device: UsbDevice<'a, B>,
+    comms: Comms<Io>,
+    control: Control<P1, ValveControl1Pin, ValveControl2Pin>,
+    sensing: Sensing<PAdc, ValveState1Pin, ValveState2Pin>,
 
     pub delay: D,
 
-    valve_sense_1_pin: ValveState1Pin,
-    valve_sense_2_pin: ValveState2Pin,
-    valve_control_1_pin: ValveControl1Pin,
-    valve_control_2_pin: ValveControl2Pin,
+    /// What this build is wired for. Reported to the host on request.
+    capabilities: Capabilities,
+
+    /// Which build this is. Reported to the host with the capabilities.
+    firmware: FirmwareInfoPacket,
+
+    /// Persistent firmware configuration, applied immediately when changed.
+    settings: Settings,
+    settings_storage: Storage,
+
+    /// Whether the settings were persisted the last time they changed.
+    /// Reported to the host with the configuration.
+    settings_persisted: bool,
+
+    /// The sensor report rate to persist with `settings`, in Hz. A rate set
+    /// on its own only lasts until reset, since the host changes it often
+    /// and every store wears the flash. It is persisted by `WriteConfig`.
+    stored_telemetry_rate_hz: u8,
+
+    /// The PWM frequency to switch to, in Hz, waiting for the board to call
+    /// `set_pwm_period`. Set at boot so stored settings take effect.
+    pending_pwm_frequency_hz: Option<u16>,
+
+    /// Where `settings` were loaded from at boot. Reported to the host with
+    /// the capabilities.
+    settings_origin: SettingsOrigin,
+
+    /// Why the chip last reset, read at boot. Reported to the host with the
+    /// capabilities.
+    reset_cause: ResetCause,
+
+    /// Notable events since reset, sent to the host when it asks.
+    logs: LogBuffer,
 
-    pwm: P1,
-    pump_pwm_channel: P1::Channel,
-    fan_pwm_channel: P1::Channel,
+    /// The protocol version the host requested the connection with, if it
+    /// isn't this build's. Control targets and parameters are refused until
+    /// a host with a matching version connects, since they could have been
+    /// mis-decoded.
+    mismatched_protocol_version: Option<u16>,
 
-    padc: PAdc,
+    /// Whether packets are reflected back instead of acted on. Used to
+    /// qualify the link without engaging the actuators.
+    echo_mode: bool,
 
-    sensor_poll_timer: u8,
+    /// The reset the host asked for, if any. Latched, since the board resets
+    /// once the parked targets are output.
+    reset_request: Option<ResetRequest>,
 
-    /// Represents a queue of packets which have been received.
-    incoming_packets: Vec<Packet, 16>,
+    /// Whether speeds and valve state are sampled into batches instead of
+    /// sent as fast rpm reports.
+    sensor_batching: bool,
 
-    /// Represents a queue of packets which need to be sent.
-    outgoing_packets: Vec<Packet, 16>,
+    /// The batch being sampled into, if any samples have been taken since it
+    /// was last sent.
+    sensor_batch: Option<ReportSensorsBatchPacket>,
+
+    /// `uptime_ms` when the first sample of `sensor_batch` was taken.
+    sensor_batch_started_ms: u64,
+
+    /// The latest control targets received, waiting to be output by
+    /// `apply_control_targets`.
+    pending_targets: Option<ReportControlTargetsPacket>,
+
+    /// The valve state commanded since the last `apply_control_targets`,
+    /// waiting to be output by it.
+    pending_valve: Option<ValveState>,
+
+    /// The state the valve was last commanded to, if any.
+    commanded_valve: Option<ValveState>,
+
+    /// How long the transition to `commanded_valve` has taken so far, in
+    /// milliseconds, while it hasn't been reported complete.
+    valve_transition_ms: Option<u16>,
+
+    /// The sequence number of the newest control targets received, if any.
+    last_control_sequence: Option<u16>,
+
+    /// How many control frames were found missing from the sequence.
+    /// Reported to the host with every `Ack`.
+    missed_control_frames: u32,
+
+    /// Core loop iterations since anything was last received from the host,
+    /// or `None` if nothing has been yet.
+    ticks_since_host: Option<Ticks>,
+
+    /// Whether the link to the host was lost and `safe_targets` output. Left
+    /// once anything is received from the host again, though the safe
+    /// targets stay in effect until the host sends new ones.
+    link_lost: bool,
+
+    /// Milliseconds since reset, counted in core loop periods.
+    uptime_ms: u64,
+
+    /// Host time at reset, in milliseconds since the unix epoch, as of the
+    /// latest `TimeSync`. `None` until the host has sent one.
+    host_time_at_reset_ms: Option<u64>,
+
+    /// Core loop iterations so far.
+    ticks: Ticks,
+    scheduler: Scheduler<Job, MAX_JOBS>,
 }
 
 impl<
-        'a,
-        B: UsbBus,
+        Io: PacketIo,
         D: DelayMs<u16>,
         P1: Pwm<Channel = impl Clone, Duty = u32>,
         PAdc: PrandtlAdc,
@@ -64,10 +212,10 @@ impl<
         ValveState2Pin: InputPin,
         ValveControl1Pin: OutputPin,
         ValveControl2Pin: OutputPin,
+        Storage: SettingsStorage,
     >
     Application<
-        'a,
-        B,
+        Io,
         D,
         P1,
         PAdc,
@@ -75,196 +223,1668 @@ impl<
         ValveState2Pin,
         ValveControl1Pin,
         ValveControl2Pin,
+        Storage,
     >
 {
     pub fn new(
-        bus_allocator: &'a UsbBusAllocator<B>,
+        comms: Comms<Io>,
+        control: Control<P1, ValveControl1Pin, ValveControl2Pin>,
+        sensing: Sensing<PAdc, ValveState1Pin, ValveState2Pin>,
         delay: D,
-        mut pump_pwm: P1,
-        pump_channel: P1::Channel,
-        fan_channel: P1::Channel,
-        padc: PAdc,
-        valve_sense_1_pin: ValveState1Pin,
-        valve_sense_2_pin: ValveState2Pin,
-        valve_control_1_pin: ValveControl1Pin,
-        valve_control_2_pin: ValveControl2Pin,
+        capabilities: Capabilities,
+        firmware: FirmwareInfoPacket,
+        reset_cause: ResetCause,
+        mut settings_storage: Storage,
     ) -> Self {
-        pump_pwm.enable(pump_channel.clone());
-        pump_pwm.enable(fan_channel.clone());
+        // NOTE: Falls back to defaults if nothing valid has been stored yet.
+        let (settings, settings_origin) = settings_storage.load();
 
-        // Initialize pump and fan to 50%.
-        // This should prevent overheating while device boots.
-        pump_pwm.set_duty(
-            pump_channel.clone(),
-            ((pump_pwm.get_max_duty() as f32) * 0.5f32) as u32,
+        let mut logs = LogBuffer::new();
+        logs.log(
+            if reset_cause.is_fault() {
+                LogLevel::Warn
+            } else {
+                LogLevel::Info
+            },
+            LOG_MODULE_APPLICATION,
+            str_format!(str64, "Booted after a {:?} reset.", reset_cause),
         );
-        pump_pwm.set_duty(
-            fan_channel.clone(),
-            ((pump_pwm.get_max_duty() as f32) * 0.5f32) as u32,
+        logs.log(
+            if settings_origin.corrupt_banks() > 0 {
+                LogLevel::Warn
+            } else {
+                LogLevel::Info
+            },
+            LOG_MODULE_SETTINGS,
+            match settings_origin {
+                SettingsOrigin::Defaults { corrupt_banks } => str_format!(
+                    str64,
+                    "Loaded default settings, {} corrupt banks.",
+                    corrupt_banks
+                ),
+                SettingsOrigin::Bank {
+                    bank,
+                    version,
+                    corrupt_banks,
+                } => str_format!(
+                    str64,
+                    "Loaded settings v{} from bank {}, {} corrupt banks.",
+                    version,
+                    bank,
+                    corrupt_banks
+                ),
+            },
         );
 
-        // TODO: Set valve to PUMP-IN-LOOP
-        // TODO: Make sure pump doesn't come on before valve is open.
+        let mut scheduler = Scheduler::new();
+        // NOTE: Can't fail, the scheduler starts empty and has room for every
+        //       job.
+        for (job, period) in job_periods(&settings) {
+            let _ = scheduler.register(job, period, 0);
+        }
 
         Self {
-            serial_port: SerialPort::new(&bus_allocator),
-            usb_device: UsbDeviceBuilder::new(bus_allocator, UsbVidPid(0x2222, 0x3333))
-                .manufacturer("LA Tech")
-                .product("Too Hot To Prandtl Controller")
-                .serial_number("1324")
-                .device_class(USB_CLASS_CDC)
-                .build(),
+            comms,
+            control,
+            sensing,
             delay,
-            valve_sense_1_pin,
-            valve_sense_2_pin,
-            valve_control_1_pin,
-            valve_control_2_pin,
-            pwm: pump_pwm,
-            pump_pwm_channel: pump_channel,
-            fan_pwm_channel: fan_channel,
-            padc,
-            sensor_poll_timer: 0,
-            incoming_packets: Vec::new(),
-            outgoing_packets: Vec::new(),
+            capabilities,
+            firmware,
+            pending_pwm_frequency_hz: Some(settings.pwm_frequency_hz),
+            settings,
+            settings_storage,
+            settings_persisted: true,
+            stored_telemetry_rate_hz: settings.telemetry_rate_hz,
+            settings_origin,
+            reset_cause,
+            logs,
+            mismatched_protocol_version: None,
+            echo_mode: false,
+            reset_request: None,
+            sensor_batching: false,
+            sensor_batch: None,
+            sensor_batch_started_ms: 0,
+            pending_targets: None,
+            pending_valve: None,
+            commanded_valve: None,
+            valve_transition_ms: None,
+            last_control_sequence: None,
+            missed_control_frames: 0,
+            ticks_since_host: None,
+            link_lost: false,
+            uptime_ms: 0,
+            host_time_at_reset_ms: None,
+            ticks: 0,
+            scheduler,
         }
     }
 
-    /// Poll the USB Device. This should be called from the USB interrupt.
-    pub fn poll_usb(&mut self) {
-        self.usb_device.poll(&mut [&mut self.serial_port]);
+    /// Service the packet io. For USB this should be called from the USB
+    /// interrupt.
+    pub fn poll_io(&mut self) {
+        self.comms.poll();
     }
 
-    /// The core application loop.
-    /// TODO: TEST
-    pub fn core_loop(&mut self) {
-        self.process_incoming_packets();
+    /// This function will read as many packets from the packet io as ready.
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn read_packets(&mut self, cs: &CriticalSection) {
+        self.comms.read_packets(cs);
+    }
 
-        // NOTE: Approximately 0.5Hz.
-        //       Consider using hardware timer to schedule reporting sensor data
-        self.sensor_poll_timer += 1;
-        if self.sensor_poll_timer > 5 {
-            self.sensor_poll_timer -= 5;
+    /// Write all outgoing packets to the packet io.
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn write_packets(&mut self, cs: &CriticalSection) {
+        self.comms.write_packets(cs);
+    }
 
-            // NOTE: Ignoring errors.
-            let _ = self.report_sensors();
+    /// Output the latest control targets and valve command received since
+    /// the last call, if any. Earlier targets received in the meantime are
+    /// superseded, and the outputs are updated at once so reports never see
+    /// them half applied.
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn apply_control_targets(&mut self, cs: &CriticalSection) {
+        if let Some(targets) = self.pending_targets.take() {
+            self.control.apply_targets(&targets, cs);
+        }
+        if let Some(target) = self.pending_valve.take() {
+            self.control.command_valve(target, cs);
         }
     }
 
-    /// Poll the binary state of each valve sense pin.
-    /// TODO: TEST
-    fn poll_valve_state_pins(&self) -> Result<(bool, bool), ApplicationError> {
-        let is_open_high = self
-            .valve_sense_1_pin
-            .is_high()
-            .map_err(|_| ApplicationError::ValveReadFailure)?;
-        let is_close_high = self
-            .valve_sense_2_pin
-            .is_high()
-            .map_err(|_| ApplicationError::ValveReadFailure)?;
-        Ok((is_open_high, is_close_high))
+    /// The PWM frequency to switch to, in Hz, if it changed since the last
+    /// call. Convert it to the board's period and pass it to
+    /// `set_pwm_period`.
+    pub fn take_pwm_frequency_hz(&mut self) -> Option<u16> {
+        self.pending_pwm_frequency_hz.take()
     }
 
-    /// Create and push report sensor packet to outgoing packets queue.
-    /// TODO: TEST
-    pub fn report_sensors(&mut self) -> Result<(), ApplicationError> {
-        let pump_speed_raw = match self.padc.read_pump_sense_norm() {
-            None => return Err(ApplicationError::ReadAdcFailure),
-            Some(raw) => raw,
-        };
-        let fan_speed_raw = match self.padc.read_fan_sense_norm() {
-            None => return Err(ApplicationError::ReadAdcFailure),
-            Some(raw) => raw,
-        };
+    /// Change the period the pump and fan are driven at, keeping their duty.
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn set_pwm_period(&mut self, period: P1::Time, cs: &CriticalSection) {
+        self.control.set_period(period, cs);
+    }
+
+    /// The reset the host asked for, if any. Check after
+    /// `apply_control_targets` and reset the board as asked, so the pump and
+    /// fan are parked at `safe_targets` first.
+    pub fn reset_requested(&self) -> Option<ResetRequest> {
+        self.reset_request
+    }
+
+    /// How long to wait between calls to `core_loop`, in milliseconds.
+    /// Sensor reports are scheduled in ticks of this period.
+    pub fn core_loop_period_ms(&self) -> u16 {
+        self.settings.core_loop_period_ms as u16
+    }
+
+    /// The host's time now, in milliseconds since the unix epoch, if it has
+    /// been synced.
+    pub fn host_time_ms(&self) -> Option<u64> {
+        self.host_time_at_reset_ms
+            .map(|host_time_ms| host_time_ms.wrapping_add(self.uptime_ms))
+    }
+
+    /// The core application loop. Outputs `safe_targets` if the host has
+    /// gone quiet, even in echo mode. Samples the valve sense pins and the
+    /// flow meter every loop so the pins are debounced and no pulse is
+    /// missed, reports a valve transition complete once it is, and runs the
+    /// reports and heartbeats due.
+    /// Sensors aren't sampled or reported in echo mode so the link only
+    /// carries echoes. Errors are reported to the host rather than swallowed,
+    /// and errors on the link during echo mode once it is left.
+    pub fn core_loop(&mut self) {
+        self.uptime_ms = self
+            .uptime_ms
+            .wrapping_add(self.settings.core_loop_period_ms as u64);
+        self.process_incoming_packets();
+        self.check_link();
+        if self.echo_mode {
+            return;
+        }
+
+        for error in self.comms.take_errors().into_iter().flatten() {
+            self.report_error(error);
+        }
+
+        self.ticks = self.ticks.wrapping_add(1);
+        // NOTE: Ignoring errors, the report falls back to reading the pins
+        //       and reports the error if they still can't be read.
+        let _ = self.sensing.sample_valve_sense(self.core_loop_period_ms());
+        self.sensing.sample_flow_meter(self.core_loop_period_ms());
+        self.track_valve_transition();
+        let jobs = self.scheduler.run_due(self.ticks);
+        for &job in &jobs {
+            match job {
+                Job::ReportSensors => {
+                    if let Err(error) = self.report_sensors() {
+                        self.report_error(error.into());
+                    }
+                    self.report_stats();
+                }
+                // NOTE: The full report or the batch already carries the speeds.
+                Job::ReportRpmFast
+                    if self.sensor_batching || jobs.contains(&Job::ReportSensors) => {}
+                Job::ReportRpmFast => {
+                    if let Err(error) = self.report_rpm_fast() {
+                        self.report_error(error.into());
+                    }
+                }
+                Job::Heartbeat => {
+                    self.comms.send(Packet::Heartbeat(HeartbeatPacket));
+                }
+                Job::SampleSensors if self.sensor_batching => {
+                    if let Err(error) = self.sample_sensors() {
+                        self.report_error(error.into());
+                    }
+                }
+                Job::SampleSensors => {}
+                Job::FlushSensorBatch => self.flush_sensor_batch(),
+            }
+        }
+    }
 
-        let valve_state_raw = self.poll_valve_state_pins()?;
-        let valve_state = ValveState::from(valve_state_raw);
+    /// Count another core loop without hearing from the host, and output
+    /// `safe_targets` with the valve open once it has been quiet for
+    /// `LINK_LOSS_TIMEOUT_MS`.
+    /// A host which has never been heard from leaves the boot outputs alone.
+    fn check_link(&mut self) {
+        let Some(ticks_since_host) = self.ticks_since_host.as_mut() else {
+            return;
+        };
+        *ticks_since_host = ticks_since_host.saturating_add(1);
+        if !self.link_lost && *ticks_since_host >= link_loss_ticks(&self.settings) {
+            self.link_lost = true;
+            self.logs.log(
+                LogLevel::Warn,
+                LOG_MODULE_COMMS,
+                str64::from("Lost the link to the host, output safe targets."),
+            );
+            self.pending_targets = Some(safe_targets(&self.settings));
+            self.command_valve(ValveState::Open);
+        }
+    }
 
-        // NOTE: Hardcoding Rpm max values for now.
-        let pump_speed_rpm =
-            Rpm::new(2000f32, pump_speed_raw * 2000f32).map_err(|err| ApplicationError::RpmError(err))?;
-        let fan_speed_rpm =
-            Rpm::new(1800f32, fan_speed_raw * 1800f32).map_err(|err| ApplicationError::RpmError(err))?;
+    /// Start moving the valve to `target`, unless it was already commanded
+    /// there. Only `Open` and `Closed` are valid targets.
+    fn command_valve(&mut self, target: ValveState) {
+        if !matches!(target, ValveState::Open | ValveState::Closed)
+            || self.commanded_valve == Some(target)
+        {
+            return;
+        }
+        self.pending_valve = Some(target);
+        self.commanded_valve = Some(target);
+        self.valve_transition_ms = Some(0);
+    }
 
-        let _ = self.outgoing_packets.push(Packet::ReportSensors(
-            common::packet::ReportSensorsPacket {
-                pump_speed_rpm,
-                fan_speed_rpm,
-                valve_state,
+    /// Report the valve transition in progress, if any, complete once the
+    /// valve sense pins read its target, or once
+    /// `VALVE_TRANSITION_TIMEOUT_MS` has passed without them doing so.
+    fn track_valve_transition(&mut self) {
+        let (Some(target), Some(elapsed_ms)) = (self.commanded_valve, self.valve_transition_ms)
+        else {
+            return;
+        };
+        let elapsed_ms = elapsed_ms.saturating_add(self.core_loop_period_ms());
+        let confirmed =
+            self.capabilities.valve_sense && self.sensing.sensed_valve_state() == Some(target);
+        if !confirmed && elapsed_ms < VALVE_TRANSITION_TIMEOUT_MS {
+            self.valve_transition_ms = Some(elapsed_ms);
+            return;
+        }
+        self.valve_transition_ms = None;
+        self.comms.send(Packet::ValveTransitionComplete(
+            ValveTransitionCompletePacket {
+                state: target,
+                confirmed,
+                elapsed_ms,
             },
         ));
+    }
+
+    /// Create and push report sensor packet to outgoing packets queue,
+    /// timestamped in host time once synced.
+    pub fn report_sensors(&mut self) -> Result<(), ApplicationError> {
+        let mut report = self.sensing.read_sensors(&self.settings)?;
+        report.sampled_at_ms = self.host_time_ms();
+        self.comms.send(Packet::ReportSensors(report));
+        Ok(())
+    }
 
+    /// Create and push a fast rpm packet to outgoing packets queue.
+    pub fn report_rpm_fast(&mut self) -> Result<(), ApplicationError> {
+        let report = self.sensing.read_rpm(&self.settings)?;
+        self.comms.send(Packet::ReportRpmFast(report));
+        Ok(())
+    }
+
+    /// Take a sample into the sensor batch, starting a new batch if needed,
+    /// and send the batch once it is full.
+    fn sample_sensors(&mut self) -> Result<(), ApplicationError> {
+        let mut sample = self.sensing.read_sample(&self.settings)?;
+        let started_at_ms = self.host_time_ms();
+        let batch = self.sensor_batch.get_or_insert_with(|| {
+            self.sensor_batch_started_ms = self.uptime_ms;
+            ReportSensorsBatchPacket {
+                started_at_ms,
+                fan_max_rpm: self.settings.fan_max_rpm,
+                pump_max_rpm: self.settings.pump_max_rpm,
+                samples: Vec::new(),
+            }
+        });
+        sample.offset_ms =
+            (self.uptime_ms - self.sensor_batch_started_ms).min(u16::MAX as u64) as u16;
+        // NOTE: Can't fail, full batches are sent right away.
+        let _ = batch.samples.push(sample);
+        if batch.samples.is_full() {
+            self.flush_sensor_batch();
+        }
         Ok(())
     }
 
+    /// Push the sensor batch to the outgoing packets queue, if any samples
+    /// have been taken since it was last sent.
+    fn flush_sensor_batch(&mut self) {
+        if let Some(batch) = self.sensor_batch.take() {
+            self.comms.send(Packet::ReportSensorsBatch(batch));
+        }
+    }
+
+    /// Create and push report stats packet to outgoing packets queue, with
+    /// the supply voltage, uptime and the health of the link since reset.
+    /// Also raises an error packet when the supply voltage first droops.
+    pub fn report_stats(&mut self) {
+        let (supply_voltage_mv, error) = self.sensing.read_supply_voltage_mv();
+        if let Some(error) = error {
+            self.report_error(error);
+        }
+        let comms = self.comms.stats();
+        self.comms.send(Packet::ReportStats(ReportStatsPacket {
+            supply_voltage_mv,
+            uptime_ms: self.uptime_ms as u32,
+            core_loop_period_ms: self.core_loop_period_ms(),
+            decode_errors: comms.decode_errors,
+            incoming_queue_high_water: comms.incoming_high_water,
+            outgoing_queue_high_water: comms.outgoing_high_water,
+        }));
+    }
+
+    /// Push an error packet to the outgoing packets queue, and log it.
+    fn report_error(&mut self, error: FirmwareError) {
+        self.logs.log(
+            LogLevel::Error,
+            LOG_MODULE_APPLICATION,
+            str_format!(str64, "{}", error),
+        );
+        self.comms
+            .send(Packet::ReportError(ReportErrorPacket { error }));
+    }
+
     /// Clear the incoming packet queue and process each packet.
-    /// Control packets will trigger changes to the hardware state.
-    /// TODO: TEST
+    /// Connection requests are accepted with this build's protocol version.
+    /// Control packets are held for `apply_control_targets` and each is
+    /// acknowledged, along with how many were missed. So is the latest valve
+    /// command, unless it repeats the last one. Parameters are answered
+    /// with their current value. Time syncs set the clock reports are
+    /// timestamped with and are answered with what it read before. Stopping
+    /// sensor batching sends what was batched so far. Sensor requests are
+    /// answered with a full report right away, raw ADC requests with the
+    /// sense inputs as read, and log requests with every buffered line.
+    /// Calibration is persisted like parameters, and so is a written
+    /// configuration, which is answered like a read with the configuration as
+    /// applied. A reboot or bootloader request parks
+    /// the outputs at `safe_targets` with the valve open, overriding any
+    /// control packets or valve commands alongside it, until the board
+    /// resets. The bootloader wins if both arrive together. Control packets,
+    /// valve commands, parameters, tuning, calibration, configuration, time
+    /// syncs, sensor batching, sensor, raw ADC and log requests, reboots and
+    /// bootloader requests are ignored while the host's protocol version
+    /// differs, which is logged.
+    /// Device info requests are answered with this build's capabilities,
+    /// settings origin and reset cause, followed by which firmware it is, and
+    /// pings with a matching pong. Anything received, heartbeats included,
+    /// shows the link to the host is alive.
     pub fn process_incoming_packets(&mut self) {
-        while let Some(packet) = self.incoming_packets.pop() {
+        // NOTE: Packets come out in the order they arrived, so the last
+        //       targets are the latest and state changes apply in order.
+        let mut latest_targets = None;
+        let mut latest_valve = None;
+        // NOTE: A batch is at most a full incoming queue, so there is room to
+        //       ack every control frame in it.
+        let mut received_sequences: Vec<u16, PACKET_QUEUE_CAPACITY> = Vec::new();
+        while let Some(packet) = self.comms.receive() {
+            self.ticks_since_host = Some(0);
+            self.link_lost = false;
+            if self.echo_mode {
+                self.echo_packet(packet);
+                continue;
+            }
             match packet {
+                Packet::RequestConnection(request) => {
+                    self.mismatched_protocol_version = (request.protocol_version
+                        != PROTOCOL_VERSION)
+                        .then_some(request.protocol_version);
+                    if self.mismatched_protocol_version.is_some() {
+                        self.logs.log(
+                            LogLevel::Warn,
+                            LOG_MODULE_COMMS,
+                            str_format!(
+                                str64,
+                                "Host speaks protocol version {}, not {}.",
+                                request.protocol_version,
+                                PROTOCOL_VERSION
+                            ),
+                        );
+                    }
+                    self.comms
+                        .send(Packet::AcceptConnection(AcceptConnectionPacket::new()));
+                }
+                Packet::ReportControlTargets(_)
+                | Packet::ValveCommand(_)
+                | Packet::SetParameter(_)
+                | Packet::GetParameter(_)
+                | Packet::SetTuning(_)
+                | Packet::SetCalibration(_)
+                | Packet::ReadConfig(_)
+                | Packet::WriteConfig(_)
+                | Packet::TimeSync(_)
+                | Packet::SetSensorBatching(_)
+                | Packet::RequestSensors(_)
+                | Packet::RequestRawAdc(_)
+                | Packet::RequestLogs(_)
+                | Packet::Reboot(_)
+                | Packet::EnterBootloader(_)
+                    if self.mismatched_protocol_version.is_some() => {}
                 Packet::ReportControlTargets(control_packet) => {
-                    let pump_pwm_duty_norm: f32 = control_packet.pump_control_percent.into();
-                    let pump_pwm_duty =
-                        (pump_pwm_duty_norm * (self.pwm.get_max_duty() as f32)) as u32;
+                    let _ = received_sequences.push(control_packet.sequence);
+                    latest_targets = Some(control_packet);
+                }
+                Packet::ValveCommand(ValveCommandPacket { target }) => {
+                    latest_valve = Some(target);
+                }
+                Packet::SetParameter(SetParameterPacket { parameter }) => {
+                    self.apply_parameter(parameter);
+                }
+                Packet::GetParameter(GetParameterPacket { kind }) => {
+                    self.comms
+                        .send(Packet::ReportParameter(ReportParameterPacket {
+                            parameter: self.settings.get(kind),
+                        }));
+                }
+                Packet::SetTuning(SetTuningPacket { tuning }) => {
+                    self.apply_tuning(tuning);
+                }
+                Packet::SetCalibration(SetCalibrationPacket {
+                    pump_sense,
+                    fan_sense,
+                }) if self.settings.calibrate(pump_sense, fan_sense) => {
+                    self.persist_settings();
+                }
+                Packet::ReadConfig(_) => self.report_config(),
+                Packet::WriteConfig(WriteConfigPacket { config }) => {
+                    self.apply_config(config);
+                    self.report_config();
+                }
+                Packet::TimeSync(sync) => {
+                    self.sync_time(sync);
+                }
+                Packet::SetSensorBatching(SetSensorBatchingPacket { enabled }) => {
+                    self.sensor_batching = enabled;
+                    if !enabled {
+                        self.flush_sensor_batch();
+                    }
+                }
+                Packet::RequestSensors(_) => {
+                    if let Err(error) = self.report_sensors() {
+                        self.report_error(error.into());
+                    }
+                }
+                Packet::RequestRawAdc(_) => match self.sensing.read_raw() {
+                    Ok(report) => self.comms.send(Packet::ReportRawAdc(report)),
+                    Err(error) => self.report_error(error.into()),
+                },
+                Packet::RequestLogs(_) => {
+                    for line in self.logs.lines() {
+                        self.comms.send(Packet::ReportLogLine(*line));
+                    }
+                }
+                Packet::RequestDeviceInfo(_) => {
+                    self.comms
+                        .send(Packet::ReportDeviceInfo(ReportDeviceInfoPacket {
+                            capabilities: self.capabilities,
+                            settings: self.settings_origin,
+                            reset_cause: self.reset_cause,
+                        }));
+                    self.comms.send(Packet::FirmwareInfo(self.firmware.clone()));
+                }
+                Packet::Ping(PingPacket { nonce }) => {
+                    self.comms.send(Packet::Pong(PongPacket { nonce }));
+                }
+                Packet::SetEchoMode(SetEchoModePacket { enabled }) => {
+                    self.echo_mode = enabled;
+                }
+                Packet::Reboot(_) => {
+                    self.reset_request.get_or_insert(ResetRequest::Reboot);
+                }
+                Packet::EnterBootloader(_) => {
+                    self.reset_request = Some(ResetRequest::Bootloader);
+                }
+                _ => {}
+            }
+        }
+        if let Some(targets) = &latest_targets {
+            self.track_control_sequence(targets.sequence, received_sequences.len());
+            self.pending_targets = latest_targets;
+        }
+        if let Some(target) = latest_valve {
+            self.command_valve(target);
+        }
+        if self.reset_request.is_some() {
+            self.pending_targets = Some(safe_targets(&self.settings));
+            self.command_valve(ValveState::Open);
+        }
+        for &sequence in &received_sequences {
+            self.comms.send(Packet::Ack(AckPacket {
+                sequence,
+                missed_control_frames: self.missed_control_frames,
+            }));
+        }
+    }
 
-                    let fan_pwm_duty_norm: f32 = control_packet.fan_control_percent.into();
-                    let fan_pwm_duty =
-                        (fan_pwm_duty_norm * (self.pwm.get_max_duty() as f32)) as u32;
+    /// Count the control frames skipped since the last batch, given
+    /// `received` frames arrived in this batch with `newest` the latest.
+    fn track_control_sequence(&mut self, newest: u16, received: usize) {
+        if let Some(last) = self.last_control_sequence {
+            // NOTE: The older frames of the batch fill part of the gap.
+            let missed = (sequence_gap(last, newest) as u32).saturating_sub(received as u32 - 1);
+            self.missed_control_frames = self.missed_control_frames.saturating_add(missed);
+        }
+        self.last_control_sequence = Some(newest);
+    }
 
-                    let valve_state = control_packet.valve_control_state;
-                    let valve_state_raw: (bool, bool) = valve_state.into();
+    /// Reflect a packet back to the host while in echo mode. Echo payloads
+    /// are checked against their CRC, anything else is sent back untouched.
+    /// Only leaving echo mode is acted on.
+    fn echo_packet(&mut self, packet: Packet) {
+        match packet {
+            Packet::SetEchoMode(SetEchoModePacket { enabled }) => {
+                self.echo_mode = enabled;
+            }
+            Packet::Echo(echo) => {
+                self.comms.send(Packet::EchoReply(echo.reply()));
+            }
+            packet => self.comms.send(packet),
+        }
+    }
 
-                    self.pwm
-                        .set_duty(self.pump_pwm_channel.clone(), pump_pwm_duty);
-                    self.pwm
-                        .set_duty(self.fan_pwm_channel.clone(), fan_pwm_duty);
+    /// Apply a parameter change from the host and persist the settings.
+    /// Storage is only written when the settings actually changed, and not
+    /// at all for the telemetry rate.
+    fn apply_parameter(&mut self, parameter: Parameter) {
+        let periods = job_periods(&self.settings);
+        if !self.settings.apply(parameter) {
+            return;
+        }
+        self.reschedule(periods);
+        if !matches!(parameter, Parameter::TelemetryRateHz(_)) {
+            self.persist_settings();
+        }
+    }
 
-                    // NOTE: Ignore errors
-                    let _ = self.valve_control_1_pin.set_state(valve_state_raw.0.into());
-                    let _ = self.valve_control_2_pin.set_state(valve_state_raw.1.into());
-                }
-                _ => {}
+    /// Apply a configuration from the host and persist the settings, like
+    /// a parameter change. The telemetry rate is persisted too, even if it
+    /// was already set on its own. A new PWM frequency waits for the board.
+    fn apply_config(&mut self, config: FirmwareConfig) {
+        let periods = job_periods(&self.settings);
+        let pwm_frequency_hz = self.settings.pwm_frequency_hz;
+        if !self.settings.configure(config)
+            && self.settings.telemetry_rate_hz == self.stored_telemetry_rate_hz
+        {
+            return;
+        }
+        self.stored_telemetry_rate_hz = self.settings.telemetry_rate_hz;
+        self.reschedule(periods);
+        if self.settings.pwm_frequency_hz != pwm_frequency_hz {
+            self.pending_pwm_frequency_hz = Some(self.settings.pwm_frequency_hz);
+        }
+        self.persist_settings();
+    }
+
+    /// Push the configuration as persisted, and whether it was, to the
+    /// outgoing packets queue.
+    fn report_config(&mut self) {
+        self.comms.send(Packet::ReportConfig(ReportConfigPacket {
+            config: self.stored_settings().config(),
+            persisted: self.settings_persisted,
+        }));
+    }
+
+    /// Move any jobs whose period changed from `periods` onto their new
+    /// period.
+    fn reschedule(&mut self, periods: [(Job, Ticks); 5]) {
+        for ((job, period), (_, new_period)) in periods.into_iter().zip(job_periods(&self.settings))
+        {
+            if new_period != period {
+                self.scheduler.set_period(job, new_period, self.ticks);
             }
         }
     }
 
-    /// This function will read as many packets from USB as ready.
-    /// NOTE: This function MUST be called from a critical section.
-    /// TODO: TEST
-    pub fn read_packets_from_usb(&mut self, _cs: &CriticalSection) {
-        let mut buffer = [0u8; 128];
-        let recv_bytes = match self.serial_port.read(&mut buffer) {
-            Err(_) => return,
-            Ok(recv_bytes) => recv_bytes,
+    /// Store the settings, remembering whether it worked. Failures are
+    /// otherwise ignored, the settings still apply until reset.
+    fn persist_settings(&mut self) {
+        self.settings_persisted = self.settings_storage.store(&self.stored_settings()).is_ok();
+    }
+
+    /// The settings to persist, without any telemetry rate set on its own.
+    fn stored_settings(&self) -> Settings {
+        Settings {
+            telemetry_rate_hz: self.stored_telemetry_rate_hz,
+            ..self.settings
+        }
+    }
+
+    /// Set the clock to the host's time, allowing for the latency the host
+    /// expects, and reply with what it read before.
+    fn sync_time(&mut self, sync: TimeSyncPacket) {
+        let device_time_ms = self.host_time_ms();
+        let host_time_ms = sync.host_time_ms.wrapping_add(sync.latency_ms as u64);
+        self.host_time_at_reset_ms = Some(host_time_ms.wrapping_sub(self.uptime_ms));
+        self.comms.send(Packet::TimeSyncReply(TimeSyncReplyPacket {
+            host_time_ms: sync.host_time_ms,
+            device_time_ms,
+        }));
+    }
+
+    /// Apply tuning from the host. The sense curves replace the built in
+    /// ones until reset, and the maximum speeds are persisted like their
+    /// parameters. Invalid tuning is ignored as a whole.
+    fn apply_tuning(&mut self, tuning: TuningParameters) {
+        let TuningParameters {
+            pump_max_rpm,
+            fan_max_rpm,
+            pump_sense,
+            fan_sense,
+        } = tuning;
+        if !self.sensing.set_sense_curves(pump_sense, fan_sense) {
+            return;
+        }
+        // NOTE: Neither speed changes a schedule, and both are stored at once.
+        let pump_changed = self.settings.apply(Parameter::PumpMaxRpm(pump_max_rpm));
+        let fan_changed = self.settings.apply(Parameter::FanMaxRpm(fan_max_rpm));
+        if pump_changed || fan_changed {
+            self.persist_settings();
+        }
+    }
+}
+
+/// The period of every job in core loop ticks, in the order they run.
+fn job_periods(settings: &Settings) -> [(Job, Ticks); 5] {
+    [
+        (Job::ReportSensors, report_period_ticks(settings)),
+        (Job::ReportRpmFast, fast_rpm_period_ticks(settings)),
+        (Job::Heartbeat, heartbeat_period_ticks(settings)),
+        (
+            Job::SampleSensors,
+            rate_to_ticks(settings, SENSOR_BATCH_SAMPLE_RATE_HZ),
+        ),
+        (
+            Job::FlushSensorBatch,
+            ms_to_ticks(settings, SENSOR_BATCH_FLUSH_MS as u32),
+        ),
+    ]
+}
+
+/// Convert the telemetry rate into a number of core loop ticks.
+fn report_period_ticks(settings: &Settings) -> Ticks {
+    rate_to_ticks(settings, settings.telemetry_rate_hz)
+}
+
+/// Convert the fast rpm rate into a number of core loop ticks.
+fn fast_rpm_period_ticks(settings: &Settings) -> Ticks {
+    rate_to_ticks(settings, FAST_RPM_RATE_HZ)
+}
+
+/// Convert the heartbeat rate into a number of core loop ticks.
+fn heartbeat_period_ticks(settings: &Settings) -> Ticks {
+    rate_to_ticks(settings, HEARTBEAT_RATE_HZ)
+}
+
+/// Convert `LINK_LOSS_TIMEOUT_MS` into a number of core loop ticks, at least
+/// one.
+fn link_loss_ticks(settings: &Settings) -> Ticks {
+    ms_to_ticks(settings, LINK_LOSS_TIMEOUT_MS)
+}
+
+/// Convert a duration in milliseconds into a number of core loop ticks, at
+/// least one.
+fn ms_to_ticks(settings: &Settings, ms: u32) -> Ticks {
+    let core_loop_period_ms = settings.core_loop_period_ms.max(1) as Ticks;
+    (ms / core_loop_period_ms).max(1)
+}
+
+/// Convert a rate into a number of core loop ticks, at least one.
+fn rate_to_ticks(settings: &Settings, rate_hz: u8) -> Ticks {
+    let core_loop_period_ms = settings.core_loop_period_ms.max(1) as Ticks;
+    let rate_hz = rate_hz.max(1) as Ticks;
+    (1000 / core_loop_period_ms / rate_hz).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mocks::{MockAdc, MockDelay, MockPin, MockPwm, MockStorage},
+        packet_io::LoopbackPacketIo,
+        settings::{DEFAULT_CORE_LOOP_PERIOD_MS, DEFAULT_TELEMETRY_RATE_HZ},
+    };
+    use common::{
+        codec::{encode_packet, PacketCodec},
+        framing::MAX_ENCODED_FRAME_LEN,
+        packet::{
+            AckPacket, EchoPacket, EnterBootloaderPacket, ParameterKind, ReadConfigPacket,
+            RebootPacket, ReportControlTargetsPacket, ReportRawAdcPacket, RequestConnectionPacket,
+            RequestDeviceInfoPacket, RequestLogsPacket, RequestRawAdcPacket, RequestSensorsPacket,
+            SenseCalibration, SenseCurve, ECHO_PAYLOAD_LEN,
+        },
+        physical::{Percentage, ValveState},
+    };
+    use heapless::Vec;
+
+    type TestApplication = Application<
+        LoopbackPacketIo,
+        MockDelay,
+        MockPwm,
+        MockAdc,
+        MockPin,
+        MockPin,
+        MockPin,
+        MockPin,
+        MockStorage,
+    >;
+
+    const TEST_CAPABILITIES: Capabilities = Capabilities {
+        fan_channels: 1,
+        thermistors: 0,
+        valve_driver: true,
+        valve_sense: true,
+    };
+
+    fn test_firmware() -> FirmwareInfoPacket {
+        FirmwareInfoPacket::new("0.1.0", "1a2b3c4", "test")
+    }
+
+    fn test_application() -> TestApplication {
+        Application::new(
+            Comms::new(LoopbackPacketIo::new()),
+            Control::new(MockPwm::default(), 0, 1, MockPin(false), MockPin(false)),
+            Sensing::new(MockAdc::default(), MockPin(true), MockPin(false)),
+            MockDelay,
+            TEST_CAPABILITIES,
+            test_firmware(),
+            ResetCause::Watchdog,
+            MockStorage::default(),
+        )
+    }
+
+    /// Send packets from the host, run a single core loop, and decode
+    /// everything the application wrote back.
+    fn exchange(application: &mut TestApplication, packets: &[Packet]) -> Vec<Packet, 16> {
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        for packet in packets {
+            application
+                .comms
+                .io()
+                .send_to_device(encode_packet(packet, &mut buffer).unwrap());
+        }
+
+        // NOTE: Tests are single threaded so there is nothing to race with.
+        let cs = unsafe { CriticalSection::new() };
+        application.read_packets(&cs);
+        application.core_loop();
+        application.apply_control_targets(&cs);
+        application.write_packets(&cs);
+
+        let bytes = application.comms.io().take_from_device();
+        let mut received = Vec::new();
+        let dropped = PacketCodec::new().decode(&bytes, |packet| received.push(packet).unwrap());
+        assert_eq!(dropped, 0);
+        received
+    }
+
+    #[test]
+    fn test_report_period_ticks() {
+        let period = |core_loop_period_ms, telemetry_rate_hz| {
+            report_period_ticks(&Settings {
+                core_loop_period_ms,
+                telemetry_rate_hz,
+                ..Settings::default()
+            })
+        };
+        assert_eq!(period(10, 2), 50);
+        assert_eq!(period(10, 20), 5);
+        assert_eq!(period(10, 0), 100);
+        assert_eq!(period(10, 200), 1);
+
+        assert_eq!(period(50, 20), 1);
+        assert_eq!(period(1, 2), 500);
+        assert_eq!(period(0, 2), 500);
+    }
+
+    #[test]
+    fn test_device_info_request() {
+        let mut application = test_application();
+        let received = exchange(
+            &mut application,
+            &[Packet::RequestDeviceInfo(RequestDeviceInfoPacket)],
+        );
+        assert!(
+            received.contains(&Packet::ReportDeviceInfo(ReportDeviceInfoPacket {
+                capabilities: TEST_CAPABILITIES,
+                settings: SettingsOrigin::Defaults { corrupt_banks: 0 },
+                reset_cause: ResetCause::Watchdog,
+            }))
+        );
+        assert!(received.contains(&Packet::FirmwareInfo(test_firmware())));
+    }
+
+    #[test]
+    fn test_ping_answered() {
+        let mut application = test_application();
+        let received = exchange(
+            &mut application,
+            &[Packet::Ping(PingPacket { nonce: 0xC0FFEE })],
+        );
+        assert!(received.contains(&Packet::Pong(PongPacket { nonce: 0xC0FFEE })));
+    }
+
+    #[test]
+    fn test_echo_mode() {
+        let mut application = test_application();
+        exchange(
+            &mut application,
+            &[Packet::SetEchoMode(SetEchoModePacket { enabled: true })],
+        );
+
+        let mut echo = EchoPacket::new([7; ECHO_PAYLOAD_LEN]);
+        let targets = Packet::ReportControlTargets(ReportControlTargetsPacket {
+            fan_control_percents: FanChannels::single(Percentage::try_from(100f32).unwrap()),
+            pump_control_percent: Percentage::try_from(100f32).unwrap(),
+            sequence: 0,
+        });
+        let received = exchange(&mut application, &[Packet::Echo(echo), targets.clone()]);
+        assert_eq!(
+            received
+                .iter()
+                .find(|packet| matches!(packet, Packet::EchoReply(_))),
+            Some(&Packet::EchoReply(echo.reply()))
+        );
+        assert!(echo.reply().received_intact);
+        assert!(received.contains(&targets));
+        assert_eq!(application.control.pwm().get_duty(0), 500);
+
+        echo.payload[0] ^= 0x01;
+        let received = exchange(&mut application, &[Packet::Echo(echo)]);
+        assert!(matches!(
+            received.as_slice(),
+            [Packet::EchoReply(reply)] if !reply.received_intact && reply.is_intact()
+        ));
+
+        exchange(
+            &mut application,
+            &[Packet::SetEchoMode(SetEchoModePacket { enabled: false })],
+        );
+        assert!(!application.echo_mode);
+    }
+
+    #[test]
+    fn test_control_targets_applied_at_once() {
+        let mut application = test_application();
+        let targets = |fan: f32, pump: f32, sequence| {
+            Packet::ReportControlTargets(ReportControlTargetsPacket {
+                fan_control_percents: FanChannels::single(Percentage::try_from(fan).unwrap()),
+                pump_control_percent: Percentage::try_from(pump).unwrap(),
+                sequence,
+            })
         };
-        if recv_bytes != 0 {
-            self.decode_bytes(&buffer[0..recv_bytes]);
+        let valve = |target| Packet::ValveCommand(ValveCommandPacket { target });
+        let packets = [
+            targets(0.25f32, 0.75f32, 0),
+            valve(ValveState::Open),
+            Packet::Ping(PingPacket { nonce: 1 }),
+            targets(0.5f32, 1f32, 1),
+            valve(ValveState::Closed),
+        ];
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        for packet in &packets {
+            application
+                .comms
+                .io()
+                .send_to_device(encode_packet(packet, &mut buffer).unwrap());
         }
+        let writes_at_boot = application.control.pwm().writes.len();
+
+        // NOTE: Tests are single threaded so there is nothing to race with.
+        let cs = unsafe { CriticalSection::new() };
+        application.read_packets(&cs);
+        application.core_loop();
+        assert_eq!(application.control.pwm().writes.len(), writes_at_boot);
+
+        application.apply_control_targets(&cs);
+        let writes = &application.control.pwm().writes[writes_at_boot..];
+        assert_eq!(writes, &[(0, 1000), (1, 500)]);
+        let valve_state_raw: (bool, bool) = ValveState::Closed.into();
+        let (valve_control_1_pin, valve_control_2_pin) = application.control.valve_pins();
+        assert_eq!(
+            (valve_control_1_pin.0, valve_control_2_pin.0),
+            valve_state_raw
+        );
+
+        application.apply_control_targets(&cs);
+        assert_eq!(application.control.pwm().writes.len(), writes_at_boot + 2);
     }
 
-    /// Write all outgoing packets to USB. This function ignores write and flush
-    /// errors. (Packets may be dropped without warning).
-    /// NOTE: This function MUST be called from a critical section.
-    /// TODO: TEST
-    pub fn write_packets_to_usb(&mut self, _cs: &CriticalSection) {
-        while let Some(packet) = self.outgoing_packets.pop() {
-            let buffer: Vec<u8, 128> = postcard::to_vec(&packet).unwrap();
-            let _ = self.serial_port.write(&buffer);
-        }
-        let _ = self.serial_port.flush();
-    }
-
-    /// Decode as many packets as available from a buffer.
-    /// NOTE: The remaining unused bytes are thrown away.
-    /// In the case of strange alignment this COULD POTENTIALLY
-    /// drop data or cause corruption.
-    /// If the incoming packet vec is full then they will simply be ignored.
-    /// TODO: TEST
-    fn decode_bytes(&mut self, buffer: &[u8]) {
-        let mut remaining = buffer;
-        while let Ok((packet, other)) = postcard::take_from_bytes::<Packet>(remaining) {
-            remaining = other;
-            let _ = self.incoming_packets.push(packet);
+    #[test]
+    fn test_valve_transition_reported() {
+        // NOTE: The test application's valve sense pins read open.
+        let mut application = test_application();
+        let valve = |target| Packet::ValveCommand(ValveCommandPacket { target });
+        let completions = |received: &[Packet]| {
+            received
+                .iter()
+                .filter_map(|packet| match packet {
+                    Packet::ValveTransitionComplete(complete) => Some(*complete),
+                    _ => None,
+                })
+                .collect::<std::vec::Vec<_>>()
+        };
+
+        let mut received = exchange(&mut application, &[valve(ValveState::Open)]);
+        let mut loops = 1;
+        while completions(&received).is_empty() && loops < 10 {
+            received = exchange(&mut application, &[valve(ValveState::Open)]);
+            loops += 1;
+        }
+        assert!(matches!(
+            completions(&received).as_slice(),
+            [ValveTransitionCompletePacket {
+                state: ValveState::Open,
+                confirmed: true,
+                ..
+            }]
+        ));
+        assert!(completions(&exchange(&mut application, &[valve(ValveState::Open)])).is_empty());
+
+        // NOTE: The pins never read closed, so the transition times out.
+        let mut received = exchange(&mut application, &[valve(ValveState::Closed)]);
+        let mut elapsed_ms = application.core_loop_period_ms();
+        while completions(&received).is_empty() {
+            assert!(elapsed_ms < VALVE_TRANSITION_TIMEOUT_MS);
+            received = exchange(&mut application, &[Packet::Heartbeat(HeartbeatPacket)]);
+            elapsed_ms += application.core_loop_period_ms();
+        }
+        assert_eq!(
+            completions(&received).as_slice(),
+            &[ValveTransitionCompletePacket {
+                state: ValveState::Closed,
+                confirmed: false,
+                elapsed_ms: VALVE_TRANSITION_TIMEOUT_MS,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reboot_parks_outputs() {
+        let mut application = test_application();
+        let targets = Packet::ReportControlTargets(ReportControlTargetsPacket {
+            fan_control_percents: FanChannels::single(Percentage::try_from(0.25f32).unwrap()),
+            pump_control_percent: Percentage::try_from(0.25f32).unwrap(),
+            sequence: 0,
+        });
+        let writes_at_boot = application.control.pwm().writes.len();
+        assert_eq!(application.reset_requested(), None);
+
+        let received = exchange(&mut application, &[targets, Packet::Reboot(RebootPacket)]);
+        assert_eq!(application.reset_requested(), Some(ResetRequest::Reboot));
+        assert!(received
+            .iter()
+            .any(|packet| matches!(packet, Packet::Ack(_))));
+        let writes = &application.control.pwm().writes[writes_at_boot..];
+        assert_eq!(writes, &[(0, 100000), (1, 100000)]);
+    }
+
+    #[test]
+    fn test_bootloader_wins_over_reboot() {
+        let mut application = test_application();
+        exchange(
+            &mut application,
+            &[
+                Packet::EnterBootloader(EnterBootloaderPacket),
+                Packet::Reboot(RebootPacket),
+            ],
+        );
+        assert_eq!(
+            application.reset_requested(),
+            Some(ResetRequest::Bootloader)
+        );
+    }
+
+    #[test]
+    fn test_control_targets_acknowledged() {
+        let mut application = test_application();
+        let targets = |sequence| {
+            Packet::ReportControlTargets(ReportControlTargetsPacket {
+                fan_control_percents: FanChannels::single(Percentage::try_from(50f32).unwrap()),
+                pump_control_percent: Percentage::try_from(50f32).unwrap(),
+                sequence,
+            })
+        };
+        let ack = |sequence, missed_control_frames| {
+            Packet::Ack(AckPacket {
+                sequence,
+                missed_control_frames,
+            })
+        };
+
+        let received = exchange(&mut application, &[targets(7)]);
+        assert!(received.contains(&ack(7, 0)));
+
+        // NOTE: 10 and 11 never arrive.
+        let received = exchange(&mut application, &[targets(8), targets(9), targets(12)]);
+        for sequence in [8, 9, 12] {
+            assert!(received.contains(&ack(sequence, 2)));
+        }
+
+        // NOTE: A host which reconnects starts counting again.
+        let received = exchange(&mut application, &[targets(0)]);
+        assert!(received.contains(&ack(0, 2)));
+    }
+
+    #[test]
+    fn test_every_control_target_acknowledged() {
+        let mut application = test_application();
+        // NOTE: Past the first tick, so the periodic report isn't due.
+        exchange(&mut application, &[]);
+        let targets = (0..PACKET_QUEUE_CAPACITY as u16)
+            .map(|sequence| {
+                Packet::ReportControlTargets(ReportControlTargetsPacket {
+                    fan_control_percents: FanChannels::single(Percentage::try_from(50f32).unwrap()),
+                    pump_control_percent: Percentage::try_from(50f32).unwrap(),
+                    sequence,
+                })
+            })
+            .collect::<std::vec::Vec<_>>();
+
+        let received = exchange(&mut application, &targets);
+        let acked = received
+            .iter()
+            .filter_map(|packet| match packet {
+                Packet::Ack(ack) => Some(ack.sequence),
+                _ => None,
+            })
+            .collect::<std::vec::Vec<_>>();
+        assert_eq!(
+            acked,
+            (0..PACKET_QUEUE_CAPACITY as u16).collect::<std::vec::Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_packets_applied_in_order() {
+        let mut application = test_application();
+        let rate = |telemetry_rate_hz| {
+            Packet::SetParameter(SetParameterPacket {
+                parameter: Parameter::TelemetryRateHz(telemetry_rate_hz),
+            })
+        };
+        exchange(&mut application, &[rate(5), rate(10)]);
+        assert_eq!(application.settings.telemetry_rate_hz, 10);
+    }
+
+    #[test]
+    fn test_mismatched_protocol_refused() {
+        let mut application = test_application();
+        let targets = Packet::ReportControlTargets(ReportControlTargetsPacket {
+            fan_control_percents: FanChannels::single(Percentage::try_from(50f32).unwrap()),
+            pump_control_percent: Percentage::try_from(50f32).unwrap(),
+            sequence: 0,
+        });
+        let request = |protocol_version| {
+            Packet::RequestConnection(RequestConnectionPacket::with_protocol_version(
+                protocol_version,
+            ))
+        };
+
+        let received = exchange(&mut application, &[request(PROTOCOL_VERSION + 1)]);
+        assert_eq!(
+            received.as_slice(),
+            &[Packet::AcceptConnection(AcceptConnectionPacket::new())]
+        );
+        let received = exchange(&mut application, std::slice::from_ref(&targets));
+        assert!(received.is_empty());
+        assert_eq!(application.pending_targets, None);
+
+        exchange(&mut application, &[request(PROTOCOL_VERSION)]);
+        let received = exchange(&mut application, &[targets]);
+        assert!(matches!(received.as_slice(), [Packet::Ack(_)]));
+    }
+
+    #[test]
+    fn test_sensors_requested() {
+        let mut application = test_application();
+        let is_report = |packet: &&Packet| matches!(packet, Packet::ReportSensors(_));
+        // NOTE: Past the first tick, so the periodic report isn't due.
+        exchange(&mut application, &[]);
+        assert_eq!(
+            exchange(&mut application, &[])
+                .iter()
+                .filter(is_report)
+                .count(),
+            0
+        );
+
+        let received = exchange(
+            &mut application,
+            &[Packet::RequestSensors(RequestSensorsPacket)],
+        );
+        assert_eq!(received.iter().filter(is_report).count(), 1);
+    }
+
+    #[test]
+    fn test_raw_adc_requested() {
+        let mut application = test_application();
+        let received = exchange(
+            &mut application,
+            &[Packet::RequestRawAdc(RequestRawAdcPacket)],
+        );
+        let report = received
+            .iter()
+            .find_map(|packet| match packet {
+                Packet::ReportRawAdc(report) => Some(*report),
+                _ => None,
+            })
+            .expect("Raw ADC readings should have been reported.");
+
+        // NOTE: The mock ADC reads half and quarter scale.
+        assert_eq!(
+            report,
+            ReportRawAdcPacket {
+                pump_sense: Some(2048),
+                fan_sense: Some(1024),
+                valve_sense_1: true,
+                valve_sense_2: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_logs_requested() {
+        let mut application = test_application();
+        let logs = |received: Vec<Packet, 16>| {
+            received
+                .iter()
+                .filter_map(|packet| match packet {
+                    Packet::ReportLogLine(line) => Some((line.level, line.message)),
+                    _ => None,
+                })
+                .collect::<std::vec::Vec<_>>()
+        };
+        let request = |protocol_version| {
+            Packet::RequestConnection(RequestConnectionPacket::with_protocol_version(
+                protocol_version,
+            ))
+        };
+
+        exchange(&mut application, &[request(PROTOCOL_VERSION + 1)]);
+        let received = exchange(&mut application, &[Packet::RequestLogs(RequestLogsPacket)]);
+        assert_eq!(logs(received), []);
+
+        exchange(&mut application, &[request(PROTOCOL_VERSION)]);
+        let received = exchange(&mut application, &[Packet::RequestLogs(RequestLogsPacket)]);
+        assert_eq!(
+            logs(received),
+            [
+                (
+                    LogLevel::Warn,
+                    str64::from("Booted after a Watchdog reset.")
+                ),
+                (
+                    LogLevel::Info,
+                    str64::from("Loaded default settings, 0 corrupt banks.")
+                ),
+                (
+                    LogLevel::Warn,
+                    str_format!(
+                        str64,
+                        "Host speaks protocol version {}, not {}.",
+                        PROTOCOL_VERSION + 1,
+                        PROTOCOL_VERSION
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_parameter_persisted() {
+        let mut application = test_application();
+        exchange(
+            &mut application,
+            &[Packet::SetParameter(SetParameterPacket {
+                parameter: Parameter::FanMaxRpm(1500),
+            })],
+        );
+
+        assert_eq!(application.settings.fan_max_rpm, 1500);
+        let stored = application
+            .settings_storage
+            .stored
+            .expect("Settings should have been stored.");
+        assert_eq!(stored.fan_max_rpm, 1500);
+    }
+
+    #[test]
+    fn test_unchanged_parameter_not_persisted() {
+        let mut application = test_application();
+        let set = |parameter| Packet::SetParameter(SetParameterPacket { parameter });
+
+        // NOTE: The host sends the core loop period on every connect.
+        exchange(
+            &mut application,
+            &[set(Parameter::CoreLoopPeriodMs(
+                DEFAULT_CORE_LOOP_PERIOD_MS,
+            ))],
+        );
+        assert_eq!(application.settings_storage.stores, 0);
+
+        exchange(&mut application, &[set(Parameter::CoreLoopPeriodMs(25))]);
+        exchange(&mut application, &[set(Parameter::CoreLoopPeriodMs(25))]);
+        assert_eq!(application.settings_storage.stores, 1);
+    }
+
+    #[test]
+    fn test_telemetry_rate_not_persisted() {
+        let mut application = test_application();
+        let rate_hz = DEFAULT_TELEMETRY_RATE_HZ + 1;
+        exchange(
+            &mut application,
+            &[Packet::SetParameter(SetParameterPacket {
+                parameter: Parameter::TelemetryRateHz(rate_hz),
+            })],
+        );
+
+        assert_eq!(application.settings.telemetry_rate_hz, rate_hz);
+        assert_eq!(application.settings_storage.stored, None);
+
+        // NOTE: Storing another change keeps the stored rate.
+        exchange(
+            &mut application,
+            &[Packet::SetParameter(SetParameterPacket {
+                parameter: Parameter::FanMaxRpm(1500),
+            })],
+        );
+        let stored = application
+            .settings_storage
+            .stored
+            .expect("Settings should have been stored.");
+        assert_eq!(stored.telemetry_rate_hz, DEFAULT_TELEMETRY_RATE_HZ);
+        assert_eq!(application.settings.telemetry_rate_hz, rate_hz);
+
+        // NOTE: Writing the config persists it, even though it's unchanged.
+        let config = application.settings.config();
+        let received = exchange(
+            &mut application,
+            &[Packet::WriteConfig(WriteConfigPacket { config })],
+        );
+        assert!(received.contains(&Packet::ReportConfig(ReportConfigPacket {
+            config,
+            persisted: true,
+        })));
+        let stored = application
+            .settings_storage
+            .stored
+            .expect("Settings should have been stored.");
+        assert_eq!(stored.telemetry_rate_hz, rate_hz);
+    }
+
+    #[test]
+    fn test_write_config_applied() {
+        let mut application = test_application();
+        assert_eq!(
+            application.take_pwm_frequency_hz(),
+            Some(application.settings.pwm_frequency_hz)
+        );
+        let received = exchange(&mut application, &[Packet::ReadConfig(ReadConfigPacket)]);
+        assert_eq!(
+            received.as_slice(),
+            &[Packet::ReportConfig(ReportConfigPacket {
+                config: Settings::default().config(),
+                persisted: true,
+            })]
+        );
+
+        let config = FirmwareConfig {
+            pwm_frequency_hz: 25_000,
+            telemetry_rate_hz: DEFAULT_TELEMETRY_RATE_HZ + 1,
+            pump_max_rpm: 1234,
+            fan_max_rpm: 987,
+            failsafe_duty_percent: 80,
+        };
+        let received = exchange(
+            &mut application,
+            &[Packet::WriteConfig(WriteConfigPacket { config })],
+        );
+        assert!(received.contains(&Packet::ReportConfig(ReportConfigPacket {
+            config,
+            persisted: true,
+        })));
+        assert_eq!(application.take_pwm_frequency_hz(), Some(25_000));
+        assert_eq!(application.take_pwm_frequency_hz(), None);
+        assert_eq!(
+            report_period_ticks(&application.settings),
+            report_period_ticks(&Settings {
+                telemetry_rate_hz: DEFAULT_TELEMETRY_RATE_HZ + 1,
+                ..Settings::default()
+            })
+        );
+        let stored = application
+            .settings_storage
+            .stored
+            .expect("Settings should have been stored.");
+        assert_eq!(stored.config(), config);
+        assert_eq!(
+            safe_targets(&stored).fan_control_percent(0),
+            Percentage::const_new(80)
+        );
+    }
+
+    #[test]
+    fn test_get_parameter_answered() {
+        let mut application = test_application();
+        let get = |kind| Packet::GetParameter(GetParameterPacket { kind });
+        exchange(
+            &mut application,
+            &[Packet::SetParameter(SetParameterPacket {
+                parameter: Parameter::FanMaxRpm(1500),
+            })],
+        );
+
+        let received = exchange(
+            &mut application,
+            &[
+                get(ParameterKind::FanMaxRpm),
+                get(ParameterKind::TelemetryRateHz),
+            ],
+        );
+        assert!(
+            received.contains(&Packet::ReportParameter(ReportParameterPacket {
+                parameter: Parameter::FanMaxRpm(1500),
+            }))
+        );
+        assert!(
+            received.contains(&Packet::ReportParameter(ReportParameterPacket {
+                parameter: Parameter::TelemetryRateHz(DEFAULT_TELEMETRY_RATE_HZ),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_set_tuning_applied() {
+        let mut application = test_application();
+        let tuning = |pump_sense: SenseCurve| {
+            Packet::SetTuning(SetTuningPacket {
+                tuning: TuningParameters {
+                    pump_max_rpm: 3000,
+                    fan_max_rpm: 1200,
+                    pump_sense,
+                    fan_sense: SenseCurve::linear(1200),
+                },
+            })
+        };
+
+        let mut invalid = SenseCurve::linear(3000);
+        invalid.points.clear();
+        exchange(&mut application, &[tuning(invalid)]);
+        assert_eq!(application.settings, Settings::default());
+        assert!(application.settings_storage.stored.is_none());
+
+        exchange(&mut application, &[tuning(SenseCurve::linear(3000))]);
+        assert_eq!(application.settings.pump_max_rpm, 3000);
+        assert_eq!(application.settings.fan_max_rpm, 1200);
+        let stored = application
+            .settings_storage
+            .stored
+            .expect("Settings should have been stored.");
+        assert_eq!(stored.pump_max_rpm, 3000);
+
+        let rpm = application
+            .sensing
+            .read_rpm(&application.settings)
+            .expect("Failed to read rpm.");
+        assert_eq!(rpm.pump_speed_rpm.speed(), 1500f32);
+        assert_eq!(rpm.fan_speeds_rpm.first().speed(), 300f32);
+    }
+
+    #[test]
+    fn test_set_calibration_applied() {
+        let mut application = test_application();
+        let calibration = |gain| {
+            Packet::SetCalibration(SetCalibrationPacket {
+                pump_sense: SenseCalibration {
+                    offset: -2_500,
+                    gain,
+                },
+                fan_sense: SenseCalibration::IDENTITY,
+            })
+        };
+
+        exchange(&mut application, &[calibration(0)]);
+        assert_eq!(application.settings, Settings::default());
+        assert!(application.settings_storage.stored.is_none());
+
+        exchange(&mut application, &[calibration(20_000)]);
+        let stored = application
+            .settings_storage
+            .stored
+            .expect("Settings should have been stored.");
+        assert_eq!(stored.pump_sense_calibration.gain, 20_000);
+
+        let rpm = application
+            .sensing
+            .read_rpm(&application.settings)
+            .expect("Failed to read rpm.");
+        assert_eq!(rpm.pump_speed_rpm.speed(), 1000f32);
+        assert_eq!(rpm.fan_speeds_rpm.first().speed(), 450f32);
+    }
+
+    #[test]
+    fn test_sensors_reported_on_schedule() {
+        let mut application = test_application();
+        let ticks = report_period_ticks(&application.settings);
+
+        for _ in 1..ticks {
+            assert!(!exchange(&mut application, &[])
+                .iter()
+                .any(|packet| matches!(packet, Packet::ReportSensors(_))));
         }
+        let received = exchange(&mut application, &[]);
+
+        assert!(received
+            .iter()
+            .any(|packet| matches!(packet, Packet::ReportSensors(_))));
+        let stats = received
+            .iter()
+            .find_map(|packet| match packet {
+                Packet::ReportStats(stats) => Some(stats),
+                _ => None,
+            })
+            .expect("Expected stats.");
+        let period_ms = application.core_loop_period_ms();
+        assert_eq!(stats.core_loop_period_ms, period_ms);
+        assert_eq!(stats.uptime_ms, ticks as u32 * period_ms as u32);
+        assert_eq!(stats.decode_errors, 0);
+    }
+
+    #[test]
+    fn test_time_sync() {
+        let mut application = test_application();
+        let period_ms = application.settings.core_loop_period_ms as u64;
+        let sync = |host_time_ms| {
+            Packet::TimeSync(TimeSyncPacket {
+                host_time_ms,
+                latency_ms: 5,
+            })
+        };
+
+        let received = exchange(&mut application, &[sync(1_000_000)]);
+        assert!(
+            received.contains(&Packet::TimeSyncReply(TimeSyncReplyPacket {
+                host_time_ms: 1_000_000,
+                device_time_ms: None,
+            }))
+        );
+        assert_eq!(application.host_time_ms(), Some(1_000_005));
+
+        let ticks = report_period_ticks(&application.settings);
+        let report = (0..ticks)
+            .flat_map(|_| exchange(&mut application, &[]))
+            .find_map(|packet| match packet {
+                Packet::ReportSensors(report) => Some(report),
+                _ => None,
+            })
+            .expect("Sensors should have been reported.");
+        // NOTE: The sync was already received on the first tick.
+        let reported_at_ms = 1_000_005 + (ticks as u64 - 1) * period_ms;
+        assert_eq!(report.sampled_at_ms, Some(reported_at_ms));
+
+        // NOTE: The host's clock ran 100ms ahead meanwhile.
+        let received = exchange(&mut application, &[sync(reported_at_ms + 100)]);
+        assert!(
+            received.contains(&Packet::TimeSyncReply(TimeSyncReplyPacket {
+                host_time_ms: reported_at_ms + 100,
+                device_time_ms: Some(reported_at_ms + period_ms),
+            }))
+        );
+        assert_eq!(application.host_time_ms(), Some(reported_at_ms + 105));
+    }
+
+    #[test]
+    fn test_sensor_batching() {
+        let mut application = test_application();
+        let batching = |enabled| Packet::SetSensorBatching(SetSensorBatchingPacket { enabled });
+
+        let mut received = Vec::<Packet, 64>::new();
+        received.extend(exchange(&mut application, &[batching(true)]));
+        for _ in 0..60 {
+            received.extend(exchange(&mut application, &[]));
+        }
+        assert!(!received
+            .iter()
+            .any(|packet| matches!(packet, Packet::ReportRpmFast(_))));
+        let batch = received
+            .iter()
+            .find_map(|packet| match packet {
+                Packet::ReportSensorsBatch(batch) if batch.samples.is_full() => Some(batch),
+                _ => None,
+            })
+            .expect("A full batch should have been sent.");
+        assert_eq!(batch.started_at_ms, None);
+        let sample_period_ms = 1000 / SENSOR_BATCH_SAMPLE_RATE_HZ as u16;
+        for (i, sample) in batch.samples.iter().enumerate() {
+            assert_eq!(sample.offset_ms, i as u16 * sample_period_ms);
+        }
+
+        // NOTE: Stopping sends what was batched so far, and nothing after.
+        assert!(exchange(&mut application, &[batching(false)])
+            .iter()
+            .any(|packet| matches!(packet, Packet::ReportSensorsBatch(_))));
+        let mut received = Vec::<Packet, 64>::new();
+        for _ in 0..fast_rpm_period_ticks(&application.settings) {
+            received.extend(exchange(&mut application, &[]));
+        }
+        assert!(received
+            .iter()
+            .any(|packet| matches!(packet, Packet::ReportRpmFast(_))));
+        assert!(!received
+            .iter()
+            .any(|packet| matches!(packet, Packet::ReportSensorsBatch(_))));
+    }
+
+    #[test]
+    fn test_errors_reported() {
+        let mut application = test_application();
+        application.sensing = Sensing::new(
+            MockAdc {
+                pump_sense: None,
+                ..Default::default()
+            },
+            MockPin(true),
+            MockPin(false),
+        );
+        let adc_failure = Packet::ReportError(ReportErrorPacket {
+            error: FirmwareError::AdcFailure,
+        });
+
+        let mut received = Vec::<Packet, 64>::new();
+        for _ in 0..report_period_ticks(&application.settings) {
+            received.extend(exchange(&mut application, &[]));
+        }
+        assert!(received.contains(&adc_failure));
+        assert!(!received
+            .iter()
+            .any(|packet| matches!(packet, Packet::ReportSensors(_))));
+
+        // NOTE: A frame which ends straight away is malformed.
+        application.comms.io().send_to_device(&[1, 0]);
+        let received = exchange(&mut application, &[]);
+        assert!(received.contains(&Packet::ReportError(ReportErrorPacket {
+            error: FirmwareError::DecodeFailure { dropped: 1 },
+        })));
+    }
+
+    #[test]
+    fn test_rpm_reported_between_sensor_reports() {
+        let mut application = test_application();
+        let ticks = report_period_ticks(&application.settings);
+
+        let (mut sensor_reports, mut fast_reports) = (0, 0);
+        for _ in 0..ticks {
+            let received = exchange(&mut application, &[]);
+            let has_sensors = received
+                .iter()
+                .any(|packet| matches!(packet, Packet::ReportSensors(_)));
+            let has_rpm = received
+                .iter()
+                .any(|packet| matches!(packet, Packet::ReportRpmFast(_)));
+            assert!(!(has_sensors && has_rpm));
+            sensor_reports += has_sensors as u32;
+            fast_reports += has_rpm as u32;
+        }
+
+        assert_eq!(sensor_reports, 1);
+        assert_eq!(
+            fast_reports,
+            ticks / fast_rpm_period_ticks(&application.settings) - 1
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_sent_on_schedule() {
+        let mut application = test_application();
+        let ticks = heartbeat_period_ticks(&application.settings);
+
+        let mut heartbeats = 0;
+        for _ in 0..ticks * 2 {
+            heartbeats += exchange(&mut application, &[])
+                .iter()
+                .filter(|packet| matches!(packet, Packet::Heartbeat(_)))
+                .count();
+        }
+        assert_eq!(heartbeats, 2);
+    }
+
+    #[test]
+    fn test_link_loss_outputs_safe_targets() {
+        let mut application = test_application();
+        let ticks = link_loss_ticks(&application.settings);
+
+        // NOTE: A host which was never heard from can't be lost.
+        for _ in 0..ticks {
+            exchange(&mut application, &[]);
+        }
+        assert!(!application.link_lost);
+
+        exchange(&mut application, &[Packet::Heartbeat(HeartbeatPacket)]);
+        let writes_at_heartbeat = application.control.pwm().writes.len();
+        // NOTE: The loop which received the heartbeat counts as the first.
+        for _ in 2..ticks {
+            exchange(&mut application, &[]);
+        }
+        assert!(!application.link_lost);
+        assert_eq!(application.control.pwm().writes.len(), writes_at_heartbeat);
+
+        exchange(&mut application, &[]);
+        assert!(application.link_lost);
+        let safe = application
+            .control
+            .outputs_for(&safe_targets(&application.settings));
+        let writes = &application.control.pwm().writes[writes_at_heartbeat..];
+        assert_eq!(writes, &[(0, safe.pump_duty), (1, safe.fan_duties.first())]);
+        let valve_state_raw: (bool, bool) = ValveState::Open.into();
+        let (valve_control_1_pin, valve_control_2_pin) = application.control.valve_pins();
+        assert_eq!(
+            (valve_control_1_pin.0, valve_control_2_pin.0),
+            valve_state_raw
+        );
+
+        // NOTE: The safe targets are only output once per loss.
+        exchange(&mut application, &[]);
+        assert_eq!(
+            application.control.pwm().writes.len(),
+            writes_at_heartbeat + 2
+        );
+
+        exchange(&mut application, &[Packet::Heartbeat(HeartbeatPacket)]);
+        assert!(!application.link_lost);
     }
 }
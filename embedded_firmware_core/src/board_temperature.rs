@@ -0,0 +1,160 @@
+/// The full scale reading of the 12 bit ADC used during factory calibration.
+const CALIBRATION_ADC_FULL_SCALE: f32 = 4095f32;
+
+/// Factory calibration for the SAMD21 internal temperature sensor. Read from
+/// the NVM Temperature Log Row, see the datasheet's "Temperature Sensor
+/// Characteristics" section for the layout and conversion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureCalibration {
+    /// Room temperature the part was calibrated at, in degrees Celsius.
+    room_temp: f32,
+
+    /// Hot temperature the part was calibrated at, in degrees Celsius.
+    hot_temp: f32,
+
+    /// Actual internal 1V reference voltage at room temperature.
+    room_int1v: f32,
+
+    /// Actual internal 1V reference voltage at hot temperature.
+    hot_int1v: f32,
+
+    /// 12 bit ADC reading of the sensor at room temperature.
+    room_adc: f32,
+
+    /// 12 bit ADC reading of the sensor at hot temperature.
+    hot_adc: f32,
+}
+
+impl TemperatureCalibration {
+    /// Decode the 64 bit NVM Temperature Log Row.
+    /// Returns `None` if the row is erased or the values can't be used.
+    pub fn from_log_row(row: u64) -> Option<Self> {
+        let field = |offset: u32, width: u32| ((row >> offset) & ((1 << width) - 1)) as u32;
+
+        let room_temp = field(0, 8) as f32 + field(8, 4) as f32 / 10f32;
+        let hot_temp = field(12, 8) as f32 + field(20, 4) as f32 / 10f32;
+        // NOTE: The 1V reference drift is stored in mV as two's complement.
+        let room_int1v = 1f32 - (field(24, 8) as u8 as i8) as f32 / 1000f32;
+        let hot_int1v = 1f32 - (field(32, 8) as u8 as i8) as f32 / 1000f32;
+        let room_adc = field(40, 12) as f32;
+        let hot_adc = field(52, 12) as f32;
+
+        // NOTE: Identical points would divide by zero when interpolating.
+        if hot_temp <= room_temp || hot_adc == room_adc {
+            return None;
+        }
+
+        Some(Self {
+            room_temp,
+            hot_temp,
+            room_int1v,
+            hot_int1v,
+            room_adc,
+            hot_adc,
+        })
+    }
+
+    /// Convert a 12 bit ADC reading of the sensor, taken against the internal
+    /// 1V reference, into degrees Celsius. Uses the datasheet's two pass
+    /// method, correcting for the 1V reference's drift with temperature.
+    pub fn temperature(&self, adc: u16) -> f32 {
+        let room_voltage = self.room_adc * self.room_int1v / CALIBRATION_ADC_FULL_SCALE;
+        let hot_voltage = self.hot_adc * self.hot_int1v / CALIBRATION_ADC_FULL_SCALE;
+        let interpolate = |voltage: f32| {
+            self.room_temp
+                + (voltage - room_voltage) * (self.hot_temp - self.room_temp)
+                    / (hot_voltage - room_voltage)
+        };
+
+        // Coarse pass assumes the 1V reference is exactly 1V.
+        let coarse = interpolate(adc as f32 / CALIBRATION_ADC_FULL_SCALE);
+
+        let int1v = self.room_int1v
+            + (self.hot_int1v - self.room_int1v) * (coarse - self.room_temp)
+                / (self.hot_temp - self.room_temp);
+        interpolate(adc as f32 * int1v / CALIBRATION_ADC_FULL_SCALE)
+    }
+}
+
+/// RP2040 temperature sensor output at 27 degrees Celsius, in volts.
+const RP2040_SENSOR_VOLTAGE_AT_27C: f32 = 0.706f32;
+
+/// RP2040 temperature sensor slope, in volts per degree Celsius.
+const RP2040_SENSOR_VOLTS_PER_DEGREE: f32 = 0.001721f32;
+
+/// Convert an RP2040 internal temperature sensor voltage into degrees
+/// Celsius. The RP2040 has no factory calibration, so this uses the nominal
+/// values from the datasheet's "Temperature Sensor" section.
+pub fn rp2040_temperature(voltage: f32) -> f32 {
+    27f32 - (voltage - RP2040_SENSOR_VOLTAGE_AT_27C) / RP2040_SENSOR_VOLTS_PER_DEGREE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rp2040_temperature() {
+        assert!((rp2040_temperature(0.706f32) - 27f32).abs() < 0.01f32);
+        assert!((rp2040_temperature(0.706f32 - 0.01721f32) - 37f32).abs() < 0.01f32);
+        assert!((rp2040_temperature(0.706f32 + 0.01721f32) - 17f32).abs() < 0.01f32);
+    }
+
+    /// Build a log row from its fields.
+    fn log_row(
+        room_temp: (u64, u64),
+        hot_temp: (u64, u64),
+        int1v: (i8, i8),
+        adc: (u64, u64),
+    ) -> u64 {
+        room_temp.0
+            | room_temp.1 << 8
+            | hot_temp.0 << 12
+            | hot_temp.1 << 20
+            | (int1v.0 as u8 as u64) << 24
+            | (int1v.1 as u8 as u64) << 32
+            | adc.0 << 40
+            | adc.1 << 52
+    }
+
+    #[test]
+    fn test_from_log_row() {
+        let row = log_row((25, 5), (85, 2), (-3, 4), (2700, 3000));
+        let calibration =
+            TemperatureCalibration::from_log_row(row).expect("Failed to get calibration.");
+
+        assert_eq!(calibration.room_temp, 25.5f32);
+        assert_eq!(calibration.hot_temp, 85.2f32);
+        assert_eq!(calibration.room_int1v, 1.003f32);
+        assert_eq!(calibration.hot_int1v, 0.996f32);
+        assert_eq!(calibration.room_adc, 2700f32);
+        assert_eq!(calibration.hot_adc, 3000f32);
+    }
+
+    #[test]
+    fn test_erased_log_row() {
+        assert!(TemperatureCalibration::from_log_row(u64::MAX).is_none());
+        assert!(TemperatureCalibration::from_log_row(0).is_none());
+    }
+
+    #[test]
+    fn test_temperature_at_calibration_points() {
+        let row = log_row((25, 0), (85, 0), (0, 0), (2700, 3000));
+        let calibration =
+            TemperatureCalibration::from_log_row(row).expect("Failed to get calibration.");
+
+        assert!((calibration.temperature(2700) - 25f32).abs() < 0.01f32);
+        assert!((calibration.temperature(3000) - 85f32).abs() < 0.01f32);
+        assert!((calibration.temperature(2850) - 55f32).abs() < 0.01f32);
+    }
+
+    #[test]
+    fn test_temperature_corrects_reference_drift() {
+        let row = log_row((25, 0), (85, 0), (-5, 5), (2700, 3000));
+        let calibration =
+            TemperatureCalibration::from_log_row(row).expect("Failed to get calibration.");
+
+        assert!((calibration.temperature(2700) - 25f32).abs() < 0.5f32);
+        assert!((calibration.temperature(3000) - 85f32).abs() < 0.5f32);
+    }
+}
@@ -0,0 +1,394 @@
+use bare_metal::CriticalSection;
+use common::{
+    codec::{encode_packet, PacketCodec},
+    framing::{DEVICE_READ_CHUNK_LEN, MAX_ENCODED_FRAME_LEN},
+    packet::{FirmwareError, Packet, PACKET_QUEUE_CAPACITY},
+};
+use heapless::Vec;
+
+use crate::packet_io::PacketIo;
+
+/// The largest read chunk a `Comms` can be configured with. The chunk is
+/// read into a buffer on the stack, so this bounds the stack it takes.
+pub const MAX_READ_CHUNK_LEN: usize = 512;
+
+/// Health of the link since reset, for `ReportStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommsStats {
+    /// Received frames which failed to decode.
+    pub decode_errors: u32,
+
+    /// The most packets the incoming queue has held at once.
+    pub incoming_high_water: u8,
+
+    /// The most packets the outgoing queue has held at once.
+    pub outgoing_high_water: u8,
+}
+
+/// Owns the link to the host. Decodes received bytes into packets and
+/// encodes queued packets onto the packet io.
+pub struct Comms<Io: PacketIo> {
+    /// The byte stream packets are exchanged with the host over.
+    io: Io,
+
+    /// Holds a frame split across reads until it ends.
+    codec: PacketCodec,
+
+    /// Represents a queue of packets which have been received.
+    incoming_packets: Vec<Packet, PACKET_QUEUE_CAPACITY>,
+
+    /// Represents a queue of packets which need to be sent.
+    outgoing_packets: Vec<Packet, PACKET_QUEUE_CAPACITY>,
+
+    /// Packets dropped to a full queue, in either direction, since the
+    /// errors were last taken.
+    overflowed_packets: u16,
+
+    /// Received frames which failed to decode since the errors were last
+    /// taken.
+    corrupted_frames: u16,
+
+    /// Unlike the errors, never reset until the device is.
+    stats: CommsStats,
+
+    /// The most bytes taken from the packet io in a single read.
+    read_chunk_len: usize,
+}
+
+impl<Io: PacketIo> Comms<Io> {
+    /// Create comms reading `DEVICE_READ_CHUNK_LEN` bytes at a time.
+    pub fn new(io: Io) -> Self {
+        Self::with_read_chunk_len(io, DEVICE_READ_CHUNK_LEN)
+    }
+
+    /// Create comms reading `read_chunk_len` bytes at a time, clamped to
+    /// 1..=`MAX_READ_CHUNK_LEN`. Larger chunks take fewer reads to drain a
+    /// burst from the host but more stack.
+    pub fn with_read_chunk_len(io: Io, read_chunk_len: usize) -> Self {
+        Self {
+            io,
+            codec: PacketCodec::new(),
+            incoming_packets: Vec::new(),
+            outgoing_packets: Vec::new(),
+            overflowed_packets: 0,
+            corrupted_frames: 0,
+            stats: CommsStats::default(),
+            read_chunk_len: read_chunk_len.clamp(1, MAX_READ_CHUNK_LEN),
+        }
+    }
+
+    /// Service the packet io. For USB this should be called from the USB
+    /// interrupt.
+    pub fn poll(&mut self) {
+        self.io.poll();
+    }
+
+    /// The underlying packet io.
+    pub fn io(&mut self) -> &mut Io {
+        &mut self.io
+    }
+
+    /// Take the oldest received packet, if any.
+    pub fn receive(&mut self) -> Option<Packet> {
+        if self.incoming_packets.is_empty() {
+            return None;
+        }
+        Some(self.incoming_packets.remove(0))
+    }
+
+    /// Queue a packet to be sent on the next `write_packets`, after any
+    /// already queued.
+    /// If the outgoing packet vec is full the packet is dropped.
+    pub fn send(&mut self, packet: Packet) {
+        if self.outgoing_packets.push(packet).is_err() {
+            self.overflowed_packets = self.overflowed_packets.saturating_add(1);
+        }
+        let queued = self.outgoing_packets.len() as u8;
+        self.stats.outgoing_high_water = self.stats.outgoing_high_water.max(queued);
+    }
+
+    /// The health of the link since reset.
+    pub fn stats(&self) -> CommsStats {
+        self.stats
+    }
+
+    /// Take the errors on the link since they were last taken: packets
+    /// dropped to a full queue and frames which failed to decode.
+    pub fn take_errors(&mut self) -> [Option<FirmwareError>; 2] {
+        let overflowed = (self.overflowed_packets != 0).then_some(FirmwareError::QueueOverflow {
+            dropped: self.overflowed_packets,
+        });
+        let corrupted = (self.corrupted_frames != 0).then_some(FirmwareError::DecodeFailure {
+            dropped: self.corrupted_frames,
+        });
+        self.overflowed_packets = 0;
+        self.corrupted_frames = 0;
+        [overflowed, corrupted]
+    }
+
+    /// This function will read as many packets from the packet io as ready.
+    /// Reads a chunk at a time until the packet io is drained, so a burst
+    /// larger than a chunk isn't left behind. A frame split across chunks
+    /// is carried over to the next.
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn read_packets(&mut self, _cs: &CriticalSection) {
+        let mut buffer = [0u8; MAX_READ_CHUNK_LEN];
+        loop {
+            let recv_bytes = match self.io.read(&mut buffer[..self.read_chunk_len]) {
+                Err(_) => return,
+                Ok(recv_bytes) => recv_bytes,
+            };
+            if recv_bytes != 0 {
+                self.decode_bytes(&buffer[0..recv_bytes]);
+            }
+            if recv_bytes < self.read_chunk_len {
+                return;
+            }
+        }
+    }
+
+    /// Write all outgoing packets to the packet io. This function ignores write
+    /// and flush errors. (Packets may be dropped without warning).
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn write_packets(&mut self, _cs: &CriticalSection) {
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        for packet in &self.outgoing_packets {
+            let frame = encode_packet(packet, &mut buffer).unwrap();
+            let _ = self.io.write(frame);
+        }
+        self.outgoing_packets.clear();
+        let _ = self.io.flush();
+    }
+
+    /// Decode as many packets as available from a buffer.
+    /// Frames which fail their CRC check are skipped, so a corrupted frame
+    /// is never acted on, and the stream re-aligns at the next frame.
+    /// Bytes of a frame which hasn't ended are kept for the next read.
+    /// If the incoming packet vec is full then they will simply be ignored.
+    /// Both are counted for `take_errors`, and corrupted frames and the
+    /// queue's high-water mark for `stats`.
+    fn decode_bytes(&mut self, buffer: &[u8]) {
+        let incoming_packets = &mut self.incoming_packets;
+        let mut overflowed: u16 = 0;
+        let corrupted = self.codec.decode(buffer, |packet| {
+            if incoming_packets.push(packet).is_err() {
+                overflowed = overflowed.saturating_add(1);
+            }
+        });
+        self.overflowed_packets = self.overflowed_packets.saturating_add(overflowed);
+        self.corrupted_frames = self
+            .corrupted_frames
+            .saturating_add(corrupted.min(u16::MAX as usize) as u16);
+        self.stats.decode_errors = self
+            .stats
+            .decode_errors
+            .saturating_add(corrupted.min(u32::MAX as usize) as u32);
+        let queued = self.incoming_packets.len() as u8;
+        self.stats.incoming_high_water = self.stats.incoming_high_water.max(queued);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_io::LoopbackPacketIo;
+    use common::{
+        framing::decode_frame,
+        golden::{check_golden_packets, golden_packets},
+        packet::{Parameter, RequestDeviceInfoPacket, SetParameterPacket, PACKET_CRC_LEN},
+    };
+
+    #[test]
+    fn test_read_packets() {
+        let mut comms = Comms::new(LoopbackPacketIo::new());
+        let first = Packet::RequestDeviceInfo(RequestDeviceInfoPacket);
+        let second = Packet::SetParameter(SetParameterPacket {
+            parameter: Parameter::TelemetryRateHz(5),
+        });
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        for packet in [&first, &second] {
+            comms
+                .io()
+                .send_to_device(encode_packet(packet, &mut buffer).unwrap());
+        }
+
+        // NOTE: Tests are single threaded so there is nothing to race with.
+        let cs = unsafe { CriticalSection::new() };
+        comms.read_packets(&cs);
+
+        assert_eq!(comms.receive(), Some(first));
+        assert_eq!(comms.receive(), Some(second));
+        assert_eq!(comms.receive(), None);
+    }
+
+    #[test]
+    fn test_read_packets_ignores_garbage() {
+        let mut comms = Comms::new(LoopbackPacketIo::new());
+        comms.io().send_to_device(&[0xFF, 0xFF, 0xFF]);
+
+        let cs = unsafe { CriticalSection::new() };
+        comms.read_packets(&cs);
+
+        assert_eq!(comms.receive(), None);
+    }
+
+    #[test]
+    fn test_read_packets_rejects_corrupted_frame() {
+        let mut comms = Comms::new(LoopbackPacketIo::new());
+        let corrupted = Packet::SetParameter(SetParameterPacket {
+            parameter: Parameter::TelemetryRateHz(5),
+        });
+        let intact = Packet::RequestDeviceInfo(RequestDeviceInfoPacket);
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        let mut frame: Vec<u8, 128> =
+            Vec::from_slice(encode_packet(&corrupted, &mut buffer).unwrap()).unwrap();
+        // NOTE: Flipping a bit of the rate still decodes, but fails the CRC.
+        frame[3] ^= 0x02;
+        comms.io().send_to_device(&frame);
+        comms
+            .io()
+            .send_to_device(encode_packet(&intact, &mut buffer).unwrap());
+
+        let cs = unsafe { CriticalSection::new() };
+        comms.read_packets(&cs);
+
+        assert_eq!(comms.receive(), Some(intact));
+        assert_eq!(comms.receive(), None);
+        assert_eq!(
+            comms.take_errors(),
+            [None, Some(FirmwareError::DecodeFailure { dropped: 1 })]
+        );
+        assert_eq!(comms.take_errors(), [None, None]);
+        assert_eq!(
+            comms.stats(),
+            CommsStats {
+                decode_errors: 1,
+                incoming_high_water: 1,
+                outgoing_high_water: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_send_counts_overflow() {
+        let mut comms = Comms::new(LoopbackPacketIo::new());
+        for _ in 0..18 {
+            comms.send(Packet::RequestDeviceInfo(RequestDeviceInfoPacket));
+        }
+        assert_eq!(
+            comms.take_errors(),
+            [Some(FirmwareError::QueueOverflow { dropped: 2 }), None]
+        );
+
+        let cs = unsafe { CriticalSection::new() };
+        comms.write_packets(&cs);
+        comms.send(Packet::RequestDeviceInfo(RequestDeviceInfoPacket));
+        assert_eq!(comms.take_errors(), [None, None]);
+        assert_eq!(
+            comms.stats().outgoing_high_water,
+            PACKET_QUEUE_CAPACITY as u8
+        );
+    }
+
+    #[test]
+    fn test_read_packets_keeps_split_frames() {
+        let mut comms = Comms::new(LoopbackPacketIo::new());
+        let packet = Packet::SetParameter(SetParameterPacket {
+            parameter: Parameter::TelemetryRateHz(5),
+        });
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        let frame = encode_packet(&packet, &mut buffer).unwrap();
+        let (first, second) = frame.split_at(3);
+
+        let cs = unsafe { CriticalSection::new() };
+        comms.io().send_to_device(first);
+        comms.read_packets(&cs);
+        assert_eq!(comms.receive(), None);
+
+        comms.io().send_to_device(second);
+        comms.read_packets(&cs);
+        assert_eq!(comms.receive(), Some(packet));
+    }
+
+    #[test]
+    fn test_read_packets_drains_beyond_chunk() {
+        let mut comms = Comms::with_read_chunk_len(LoopbackPacketIo::new(), 4);
+        let packets = [
+            Packet::SetParameter(SetParameterPacket {
+                parameter: Parameter::TelemetryRateHz(5),
+            }),
+            Packet::RequestDeviceInfo(RequestDeviceInfoPacket),
+            Packet::SetParameter(SetParameterPacket {
+                parameter: Parameter::FanMaxRpm(2200),
+            }),
+        ];
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        for packet in &packets {
+            comms
+                .io()
+                .send_to_device(encode_packet(packet, &mut buffer).unwrap());
+        }
+
+        let cs = unsafe { CriticalSection::new() };
+        comms.read_packets(&cs);
+
+        for packet in packets {
+            assert_eq!(comms.receive(), Some(packet));
+        }
+        assert_eq!(comms.receive(), None);
+        assert_eq!(comms.take_errors(), [None, None]);
+    }
+
+    #[test]
+    fn test_write_packets() {
+        let mut comms = Comms::new(LoopbackPacketIo::new());
+        let packet = Packet::RequestDeviceInfo(RequestDeviceInfoPacket);
+        comms.send(packet.clone());
+
+        let cs = unsafe { CriticalSection::new() };
+        comms.write_packets(&cs);
+
+        let bytes = comms.io().take_from_device();
+        let mut codec = PacketCodec::new();
+        let mut written = None;
+        codec.decode(&bytes, |packet| written = Some(packet));
+        assert_eq!(written, Some(packet));
+        assert_eq!(codec.pending(), 0);
+
+        comms.write_packets(&cs);
+        assert!(comms.io().take_from_device().is_empty());
+    }
+
+    #[test]
+    fn test_golden_packets() {
+        assert_eq!(check_golden_packets(), Ok(()));
+
+        let cs = unsafe { CriticalSection::new() };
+        let mut buffer = [0u8; MAX_ENCODED_FRAME_LEN];
+        for golden in golden_packets() {
+            let mut comms = Comms::new(LoopbackPacketIo::new());
+            comms
+                .io()
+                .send_to_device(golden.frame(&mut buffer).unwrap());
+            comms.read_packets(&cs);
+            assert_eq!(
+                comms.receive(),
+                Some(golden.packet.clone()),
+                "{}",
+                golden.name
+            );
+
+            comms.send(golden.packet);
+            comms.write_packets(&cs);
+            let mut written = comms.io().take_from_device();
+            let (_, frame) = written.split_last_mut().unwrap();
+            let contents = decode_frame(frame).unwrap();
+            assert_eq!(
+                &contents[..contents.len() - PACKET_CRC_LEN],
+                golden.bytes,
+                "{}",
+                golden.name
+            );
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use bare_metal::CriticalSection;
+use common::{
+    packet::{FanChannels, ReportControlTargetsPacket, MAX_FAN_CHANNELS},
+    physical::ValveState,
+};
+use embedded_hal::{digital::v2::OutputPin, Pwm};
+use heapless::Vec;
+
+/// Owns the outputs. Drives the pump and fan PWM channels from the targets
+/// sent by the host, and the valve control pins from its valve commands.
+pub struct Control<P1: Pwm, ValveControl1Pin: OutputPin, ValveControl2Pin: OutputPin> {
+    pwm: P1,
+    pump_pwm_channel: P1::Channel,
+    /// One PWM channel per fan header, in the order the host numbers them.
+    fan_pwm_channels: Vec<P1::Channel, MAX_FAN_CHANNELS>,
+
+    valve_control_1_pin: ValveControl1Pin,
+    valve_control_2_pin: ValveControl2Pin,
+}
+
+/// Everything the outputs are set to for a set of targets, computed up front
+/// so the duties can be written back to back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlOutputs {
+    pub pump_duty: u32,
+    pub fan_duties: FanChannels<u32>,
+}
+
+impl<
+        P1: Pwm<Channel = impl Clone, Duty = u32>,
+        ValveControl1Pin: OutputPin,
+        ValveControl2Pin: OutputPin,
+    > Control<P1, ValveControl1Pin, ValveControl2Pin>
+{
+    pub fn new(
+        mut pwm: P1,
+        pump_channel: P1::Channel,
+        fan_channel: P1::Channel,
+        valve_control_1_pin: ValveControl1Pin,
+        valve_control_2_pin: ValveControl2Pin,
+    ) -> Self {
+        pwm.enable(pump_channel.clone());
+        pwm.enable(fan_channel.clone());
+
+        // Initialize pump and fan to 50%.
+        // This should prevent overheating while device boots.
+        pwm.set_duty(
+            pump_channel.clone(),
+            ((pwm.get_max_duty() as f32) * 0.5f32) as u32,
+        );
+        pwm.set_duty(
+            fan_channel.clone(),
+            ((pwm.get_max_duty() as f32) * 0.5f32) as u32,
+        );
+
+        // TODO: Set valve to PUMP-IN-LOOP
+        // TODO: Make sure pump doesn't come on before valve is open.
+
+        let mut fan_pwm_channels = Vec::new();
+        // NOTE: The first fan always fits.
+        let _ = fan_pwm_channels.push(fan_channel);
+
+        Self {
+            pwm,
+            pump_pwm_channel: pump_channel,
+            fan_pwm_channels,
+            valve_control_1_pin,
+            valve_control_2_pin,
+        }
+    }
+
+    /// Drive another fan header from `channel`, started at 50% like the
+    /// first. Gives the channel back if `MAX_FAN_CHANNELS` are driven already.
+    pub fn add_fan_channel(&mut self, channel: P1::Channel) -> Result<(), P1::Channel> {
+        self.fan_pwm_channels.push(channel.clone())?;
+        self.pwm.enable(channel.clone());
+        self.pwm
+            .set_duty(channel, ((self.pwm.get_max_duty() as f32) * 0.5f32) as u32);
+        Ok(())
+    }
+
+    /// The PWM driving the pump and fan.
+    #[cfg(test)]
+    pub fn pwm(&self) -> &P1 {
+        &self.pwm
+    }
+
+    /// The valve control pins.
+    #[cfg(test)]
+    pub fn valve_pins(&self) -> (&ValveControl1Pin, &ValveControl2Pin) {
+        (&self.valve_control_1_pin, &self.valve_control_2_pin)
+    }
+
+    /// Compute the outputs for the targets sent by the host. Fans the host
+    /// sent no target for follow the first.
+    pub fn outputs_for(&self, targets: &ReportControlTargetsPacket) -> ControlOutputs {
+        let max_duty = self.pwm.get_max_duty() as f32;
+        let duty = |norm: f32| (norm * max_duty) as u32;
+
+        let mut fan_duties = FanChannels::single(duty(targets.fan_control_percent(0).into()));
+        for channel in 1..self.fan_pwm_channels.len() {
+            // NOTE: There are never more fan PWM channels than fan channels.
+            let _ = fan_duties.push(duty(targets.fan_control_percent(channel).into()));
+        }
+
+        ControlOutputs {
+            pump_duty: duty(targets.pump_control_percent.into()),
+            fan_duties,
+        }
+    }
+
+    /// Immediately output the targets sent by the host. Every duty is
+    /// computed first and written back to back so nothing sees the pump at
+    /// its new duty with the fans still at their old ones.
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn apply_targets(&mut self, targets: &ReportControlTargetsPacket, _cs: &CriticalSection) {
+        let outputs = self.outputs_for(targets);
+
+        self.pwm
+            .set_duty(self.pump_pwm_channel.clone(), outputs.pump_duty);
+        for (channel, duty) in self.fan_pwm_channels.iter().zip(outputs.fan_duties.iter()) {
+            self.pwm.set_duty(channel.clone(), duty);
+        }
+    }
+
+    /// Start moving the valve towards `target`.
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn command_valve(&mut self, target: ValveState, _cs: &CriticalSection) {
+        let valve_state_raw: (bool, bool) = target.into();
+        // NOTE: Ignore errors
+        let _ = self.valve_control_1_pin.set_state(valve_state_raw.0.into());
+        let _ = self.valve_control_2_pin.set_state(valve_state_raw.1.into());
+    }
+
+    /// Change the PWM period, which may change the max duty. The pump and
+    /// fans are rescaled so they keep the same fraction of full duty.
+    /// NOTE: This function MUST be called from a critical section.
+    pub fn set_period(&mut self, period: P1::Time, _cs: &CriticalSection) {
+        let max_duty = self.pwm.get_max_duty().max(1) as u64;
+        let mut channels: Vec<(P1::Channel, u64), { MAX_FAN_CHANNELS + 1 }> = Vec::new();
+        for channel in core::iter::once(&self.pump_pwm_channel).chain(&self.fan_pwm_channels) {
+            // NOTE: There is room for the pump and every fan.
+            let _ = channels.push((channel.clone(), self.pwm.get_duty(channel.clone()) as u64));
+        }
+
+        self.pwm.set_period(period);
+
+        let new_max_duty = self.pwm.get_max_duty() as u64;
+        for (channel, duty) in channels {
+            self.pwm
+                .set_duty(channel, (duty * new_max_duty / max_duty) as u32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{MockPin, MockPwm};
+    use common::physical::Percentage;
+
+    #[test]
+    fn test_new_starts_at_half_duty() {
+        let control = Control::new(MockPwm::default(), 0, 1, MockPin(false), MockPin(false));
+
+        assert_eq!(control.pwm.get_duty(0), 500);
+        assert_eq!(control.pwm.get_duty(1), 500);
+    }
+
+    #[test]
+    fn test_apply_targets() {
+        let mut control = Control::new(MockPwm::default(), 0, 1, MockPin(false), MockPin(false));
+        // NOTE: Tests are single threaded so there is nothing to race with.
+        let cs = unsafe { CriticalSection::new() };
+        control.apply_targets(
+            &ReportControlTargetsPacket {
+                fan_control_percents: FanChannels::single(Percentage::try_from(0.25f32).unwrap()),
+                pump_control_percent: Percentage::try_from(0.75f32).unwrap(),
+                sequence: 0,
+            },
+            &cs,
+        );
+
+        assert_eq!(control.pwm.get_duty(0), 750);
+        assert_eq!(control.pwm.get_duty(1), 250);
+    }
+
+    #[test]
+    fn test_apply_targets_to_extra_fans() {
+        let mut control = Control::new(MockPwm::default(), 0, 1, MockPin(false), MockPin(false));
+        assert_eq!(control.add_fan_channel(2), Ok(()));
+        assert_eq!(control.add_fan_channel(3), Ok(()));
+        assert_eq!(control.pwm.get_duty(3), 500);
+        // NOTE: Tests are single threaded so there is nothing to race with.
+        let cs = unsafe { CriticalSection::new() };
+        let mut fan_control_percents = FanChannels::single(Percentage::try_from(0.25f32).unwrap());
+        fan_control_percents
+            .push(Percentage::try_from(0.5f32).unwrap())
+            .unwrap();
+        control.apply_targets(
+            &ReportControlTargetsPacket {
+                fan_control_percents,
+                pump_control_percent: Percentage::try_from(0.75f32).unwrap(),
+                sequence: 0,
+            },
+            &cs,
+        );
+
+        assert_eq!(control.pwm.get_duty(1), 250);
+        assert_eq!(control.pwm.get_duty(2), 500);
+        // NOTE: The host sent no target for the last fan, so it follows the first.
+        assert_eq!(control.pwm.get_duty(3), 250);
+
+        control.set_period(40, &cs);
+        assert_eq!(control.pwm.get_duty(2), 20);
+        assert_eq!(control.pwm.get_duty(3), 10);
+    }
+
+    #[test]
+    fn test_command_valve() {
+        let mut control = Control::new(MockPwm::default(), 0, 1, MockPin(false), MockPin(false));
+        // NOTE: Tests are single threaded so there is nothing to race with.
+        let cs = unsafe { CriticalSection::new() };
+        control.command_valve(ValveState::Open, &cs);
+
+        let valve_state_raw: (bool, bool) = ValveState::Open.into();
+        assert_eq!(control.valve_control_1_pin.0, valve_state_raw.0);
+        assert_eq!(control.valve_control_2_pin.0, valve_state_raw.1);
+    }
+
+    #[test]
+    fn test_set_period_keeps_duty() {
+        let mut control = Control::new(MockPwm::default(), 0, 1, MockPin(false), MockPin(false));
+        // NOTE: Tests are single threaded so there is nothing to race with.
+        let cs = unsafe { CriticalSection::new() };
+        control.pwm.set_duty(1, 250);
+
+        control.set_period(40, &cs);
+        assert_eq!(control.pwm.get_max_duty(), 40);
+        assert_eq!(control.pwm.get_duty(0), 20);
+        assert_eq!(control.pwm.get_duty(1), 10);
+    }
+}
@@ -0,0 +1,69 @@
+/// Linear calibration for a current sensor whose output voltage is
+/// proportional to the current through it. Covers both hall effect sensors
+/// like the ACS712, which idle at a mid-rail offset, and amplified shunts,
+/// which idle at 0V.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrentSensorCalibration {
+    /// Sensor output voltage with no current flowing, in volts.
+    zero_current_voltage: f32,
+
+    /// Change in sensor output voltage per amp, in volts.
+    volts_per_amp: f32,
+}
+
+impl CurrentSensorCalibration {
+    /// Construct a calibration from the sensor's zero current output and its
+    /// sensitivity. Returns `None` if the sensitivity can't be used.
+    pub fn new(zero_current_voltage: f32, volts_per_amp: f32) -> Option<Self> {
+        // NOTE: A zero sensitivity would divide by zero when converting.
+        if volts_per_amp == 0f32 || !volts_per_amp.is_finite() {
+            return None;
+        }
+        Some(Self {
+            zero_current_voltage,
+            volts_per_amp,
+        })
+    }
+
+    /// Calibration for a shunt resistor followed by an amplifier.
+    pub fn shunt(shunt_ohms: f32, gain: f32) -> Option<Self> {
+        Self::new(0f32, shunt_ohms * gain)
+    }
+
+    /// Convert a sensor output voltage into amps.
+    pub fn current(&self, voltage: f32) -> f32 {
+        (voltage - self.zero_current_voltage) / self.volts_per_amp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        assert!(CurrentSensorCalibration::new(2.5f32, 0f32).is_none());
+        assert!(CurrentSensorCalibration::new(2.5f32, f32::NAN).is_none());
+        assert!(CurrentSensorCalibration::new(2.5f32, 0.185f32).is_some());
+    }
+
+    #[test]
+    fn test_hall_effect_current() {
+        // NOTE: ACS712-05B idles at half of 5V with 185mV/A.
+        let calibration =
+            CurrentSensorCalibration::new(2.5f32, 0.185f32).expect("Failed to get calibration.");
+
+        assert!(calibration.current(2.5f32).abs() < 0.001f32);
+        assert!((calibration.current(2.87f32) - 2f32).abs() < 0.001f32);
+        assert!((calibration.current(2.315f32) + 1f32).abs() < 0.001f32);
+    }
+
+    #[test]
+    fn test_shunt_current() {
+        let calibration =
+            CurrentSensorCalibration::shunt(0.1f32, 20f32).expect("Failed to get calibration.");
+
+        assert!(calibration.current(0f32).abs() < 0.001f32);
+        assert!((calibration.current(1f32) - 0.5f32).abs() < 0.001f32);
+    }
+}
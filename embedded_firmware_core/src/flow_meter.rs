@@ -0,0 +1,116 @@
+use common::physical::FlowRate;
+
+/// How long, in milliseconds, pulses are counted over before the flow rate
+/// is updated. Long enough to see several pulses at low flow from a typical
+/// hall effect meter.
+pub const FLOW_METER_WINDOW_MS: u16 = 1000;
+
+/// Counts the pulses of a hall effect flow meter by sampling its output every
+/// core loop, and turns them into a flow rate once per window.
+///
+/// NOTE: Pulses are only counted if the output is sampled at least twice per
+///       pulse, so the fastest flow measured is limited by the core loop
+///       period.
+pub struct FlowMeter {
+    window_ms: u16,
+
+    /// The level of the previous sample, if any.
+    level: Option<bool>,
+
+    /// Rising edges and time counted in the current window.
+    pulses: u32,
+    elapsed_ms: u16,
+
+    /// Pulses per minute over the last complete window, if one has completed.
+    pulses_per_minute: Option<f32>,
+}
+
+impl Default for FlowMeter {
+    fn default() -> Self {
+        Self::new(FLOW_METER_WINDOW_MS)
+    }
+}
+
+impl FlowMeter {
+    /// NOTE: A zero window is treated as a 1ms window.
+    pub fn new(window_ms: u16) -> Self {
+        Self {
+            window_ms: window_ms.max(1),
+            level: None,
+            pulses: 0,
+            elapsed_ms: 0,
+            pulses_per_minute: None,
+        }
+    }
+
+    /// Feed a sample of the pulse output, read `period_ms` after the previous
+    /// one. Each rising edge is a pulse.
+    pub fn sample(&mut self, level: bool, period_ms: u16) {
+        if level && self.level == Some(false) {
+            self.pulses = self.pulses.saturating_add(1);
+        }
+        self.level = Some(level);
+
+        self.elapsed_ms = self.elapsed_ms.saturating_add(period_ms);
+        if self.elapsed_ms >= self.window_ms {
+            self.pulses_per_minute = Some(self.pulses as f32 * 60_000f32 / self.elapsed_ms as f32);
+            self.pulses = 0;
+            self.elapsed_ms = 0;
+        }
+    }
+
+    /// The flow rate over the last complete window for a meter giving
+    /// `pulses_per_liter`. `None` until a window has completed, or if there
+    /// are no pulses per liter configured.
+    pub fn flow_rate(&self, pulses_per_liter: u16) -> Option<FlowRate> {
+        if pulses_per_liter == 0 {
+            return None;
+        }
+        let pulses_per_minute = self.pulses_per_minute?;
+        FlowRate::try_from(pulses_per_minute / pulses_per_liter as f32).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed a square wave with `pulses` pulses over `duration_ms`, sampled
+    /// every `period_ms`.
+    fn run(meter: &mut FlowMeter, pulses: u32, duration_ms: u32, period_ms: u16) {
+        let samples = duration_ms / period_ms as u32;
+        let samples_per_pulse = samples / pulses;
+        for sample in 0..samples {
+            let level = sample % samples_per_pulse >= samples_per_pulse / 2;
+            meter.sample(level, period_ms);
+        }
+    }
+
+    #[test]
+    fn test_flow_rate() {
+        let mut meter = FlowMeter::default();
+        assert_eq!(meter.flow_rate(600), None);
+
+        // NOTE: 20 pulses a second at 600 pulses per liter is 2 L/min.
+        run(&mut meter, 20, 1000, 10);
+        let flow_rate = meter.flow_rate(600).expect("Failed to get flow rate.");
+        assert_eq!(flow_rate.value(), 2f32);
+        assert_eq!(meter.flow_rate(0), None);
+
+        run(&mut meter, 5, 1000, 10);
+        let flow_rate = meter.flow_rate(600).expect("Failed to get flow rate.");
+        assert_eq!(flow_rate.value(), 0.5f32);
+    }
+
+    #[test]
+    fn test_no_pulses_is_no_flow() {
+        let mut meter = FlowMeter::new(100);
+        for _ in 0..10 {
+            meter.sample(true, 10);
+        }
+        assert_eq!(
+            meter.flow_rate(600).map(|flow_rate| flow_rate.value()),
+            Some(0f32)
+        );
+    }
+}
@@ -1,5 +1,5 @@
 #![cfg_attr(not(test), no_std)]
-use common::physical::RpmError;
+use common::{packet::FirmwareError, physical::RpmError};
 use thiserror_no_std::Error;
 
 pub trait PrandtlAdc {
@@ -8,6 +8,42 @@ pub trait PrandtlAdc {
 
     fn read_pump_sense_norm(&mut self) -> Option<f32>;
     fn read_fan_sense_norm(&mut self) -> Option<f32>;
+
+    /// Read the board temperature in degrees Celsius.
+    /// Hardware without a temperature sensor reports `None`.
+    fn read_board_temperature(&mut self) -> Option<f32> {
+        None
+    }
+
+    /// Read the coolant thermistor in degrees Celsius.
+    /// Hardware without a coolant thermistor reports `None`.
+    fn read_coolant_temperature(&mut self) -> Option<f32> {
+        None
+    }
+
+    /// Read the supply voltage in volts.
+    /// Hardware that can't measure its supply reports `None`.
+    fn read_supply_voltage(&mut self) -> Option<f32> {
+        None
+    }
+
+    /// Read the current drawn by the pump in amps.
+    /// Hardware without a pump current sensor reports `None`.
+    fn read_pump_current(&mut self) -> Option<f32> {
+        None
+    }
+
+    /// Read the current drawn by the fan in amps.
+    /// Hardware without a fan current sensor reports `None`.
+    fn read_fan_current(&mut self) -> Option<f32> {
+        None
+    }
+
+    /// Read the level of the flow meter's pulse output.
+    /// Hardware without a flow meter input reports `None`.
+    fn read_flow_pulse(&mut self) -> Option<bool> {
+        None
+    }
 }
 
 #[derive(Debug, Error)]
@@ -20,12 +56,42 @@ pub enum ApplicationError {
     RpmError(RpmError),
 }
 
+impl From<ApplicationError> for FirmwareError {
+    /// Get the error to report to the host. A speed which isn't a valid
+    /// `Rpm` came from a bad ADC reading.
+    fn from(error: ApplicationError) -> Self {
+        match error {
+            ApplicationError::ReadAdcFailure | ApplicationError::RpmError(_) => {
+                FirmwareError::AdcFailure
+            }
+            ApplicationError::ValveReadFailure => FirmwareError::ValveReadFailure,
+        }
+    }
+}
+
 /// Convert a 0 -> 2^resolution into a 0 to 1 value.
 pub fn convert_raw_to_normalized(raw: u16, resolution: u8) -> f32 {
     (raw as f32) / (2i32.pow(resolution as u32) as f32)
 }
 
 pub mod application;
+pub mod board_temperature;
+pub mod comms;
+pub mod control;
+pub mod current_sense;
+pub mod flow_meter;
+pub mod log_buffer;
+pub mod packet_io;
+pub mod reset_cause;
+pub mod scheduler;
+pub mod sensing;
+pub mod settings;
+pub mod soft_pwm;
+pub mod thermistor;
+pub mod valve_sense;
+
+#[cfg(test)]
+mod mocks;
 
 #[cfg(test)]
 mod tests {
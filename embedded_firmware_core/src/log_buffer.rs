@@ -0,0 +1,68 @@
+use common::packet::{LogLevel, ReportLogLinePacket};
+use fixedstr::str64;
+use heapless::Deque;
+
+/// How many log lines are kept for the host. The oldest are dropped first.
+/// Small enough that replaying them all fits in the outgoing packet queue.
+pub const LOG_BUFFER_CAPACITY: usize = 8;
+
+/// Module numbers reported with each log line, saying which part of the
+/// firmware logged it.
+pub const LOG_MODULE_APPLICATION: u8 = 0;
+pub const LOG_MODULE_SETTINGS: u8 = 1;
+pub const LOG_MODULE_COMMS: u8 = 2;
+
+/// The most recent log lines, kept until the host asks for them with a
+/// `RequestLogs` packet. Lines aren't sent as they are logged, so a chatty
+/// fault can't crowd the link.
+#[derive(Debug, Default)]
+pub struct LogBuffer {
+    lines: Deque<ReportLogLinePacket, LOG_BUFFER_CAPACITY>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep a line, dropping the oldest if the buffer is full. Messages
+    /// longer than a `ReportLogLinePacket` holds are truncated.
+    pub fn log(&mut self, level: LogLevel, module: u8, message: str64) {
+        if self.lines.is_full() {
+            self.lines.pop_front();
+        }
+        // NOTE: Can't fail, there is room after dropping the oldest.
+        let _ = self.lines.push_back(ReportLogLinePacket {
+            level,
+            module,
+            message,
+        });
+    }
+
+    /// The buffered lines, oldest first.
+    pub fn lines(&self) -> impl DoubleEndedIterator<Item = &ReportLogLinePacket> {
+        self.lines.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_drops_oldest() {
+        let mut buffer = LogBuffer::new();
+        for index in 0..LOG_BUFFER_CAPACITY + 2 {
+            buffer.log(
+                LogLevel::Info,
+                LOG_MODULE_APPLICATION,
+                fixedstr::str_format!(str64, "line {}", index),
+            );
+        }
+
+        let lines: std::vec::Vec<_> = buffer.lines().map(|line| line.message).collect();
+        assert_eq!(lines.len(), LOG_BUFFER_CAPACITY);
+        assert_eq!(lines[0], "line 2");
+        assert_eq!(lines[LOG_BUFFER_CAPACITY - 1], "line 9");
+    }
+}
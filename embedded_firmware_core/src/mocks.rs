@@ -0,0 +1,237 @@
+//! Stand-ins for the hardware traits, shared by the unit tests.
+
+use core::{cell::Cell, convert::Infallible};
+use embedded_hal::{
+    blocking::delay::DelayMs,
+    digital::v2::{InputPin, OutputPin},
+    Pwm,
+};
+
+use common::packet::SettingsOrigin;
+
+use crate::{
+    settings::{
+        Settings, SettingsBanks, SettingsStorage, SettingsStorageError, SETTINGS_BANK_COUNT,
+        SETTINGS_BANK_SIZE,
+    },
+    PrandtlAdc,
+};
+
+pub struct MockDelay;
+
+impl DelayMs<u16> for MockDelay {
+    fn delay_ms(&mut self, _ms: u16) {}
+}
+
+/// A two channel PWM whose max duty is its period, 1000 by default.
+pub struct MockPwm {
+    duties: [u32; 5],
+    period: u32,
+
+    /// Every duty written, as `(channel, duty)`, in order.
+    pub writes: std::vec::Vec<(usize, u32)>,
+}
+
+impl Default for MockPwm {
+    fn default() -> Self {
+        Self {
+            duties: [0; 5],
+            period: 1000,
+            writes: std::vec::Vec::new(),
+        }
+    }
+}
+
+impl Pwm for MockPwm {
+    type Channel = usize;
+    type Time = u32;
+    type Duty = u32;
+
+    fn disable(&mut self, _channel: usize) {}
+    fn enable(&mut self, _channel: usize) {}
+    fn get_period(&self) -> u32 {
+        self.period
+    }
+    fn get_duty(&self, channel: usize) -> u32 {
+        self.duties[channel]
+    }
+    fn get_max_duty(&self) -> u32 {
+        self.period
+    }
+    fn set_duty(&mut self, channel: usize, duty: u32) {
+        self.duties[channel] = duty;
+        self.writes.push((channel, duty));
+    }
+    fn set_period<P: Into<u32>>(&mut self, period: P) {
+        self.period = period.into();
+    }
+}
+
+/// Reports fixed readings. Defaults to half pump speed and quarter fan speed
+/// with no optional sensors.
+pub struct MockAdc {
+    pub pump_sense: Option<f32>,
+    pub fan_sense: Option<f32>,
+    pub supply_voltage: Option<f32>,
+    pub coolant_temperature: Option<f32>,
+
+    /// The flow meter's pulse output, which toggles every read.
+    pub flow_pulse: Option<bool>,
+}
+
+impl Default for MockAdc {
+    fn default() -> Self {
+        Self {
+            pump_sense: Some(0.5f32),
+            fan_sense: Some(0.25f32),
+            supply_voltage: None,
+            coolant_temperature: None,
+            flow_pulse: None,
+        }
+    }
+}
+
+impl PrandtlAdc for MockAdc {
+    fn read_pump_sense_raw(&mut self) -> Option<u16> {
+        self.pump_sense.map(|norm| (norm * 4096f32) as u16)
+    }
+    fn read_fan_sense_raw(&mut self) -> Option<u16> {
+        self.fan_sense.map(|norm| (norm * 4096f32) as u16)
+    }
+    fn read_pump_sense_norm(&mut self) -> Option<f32> {
+        self.pump_sense
+    }
+    fn read_fan_sense_norm(&mut self) -> Option<f32> {
+        self.fan_sense
+    }
+    fn read_supply_voltage(&mut self) -> Option<f32> {
+        self.supply_voltage
+    }
+    fn read_coolant_temperature(&mut self) -> Option<f32> {
+        self.coolant_temperature
+    }
+    fn read_flow_pulse(&mut self) -> Option<bool> {
+        self.flow_pulse = self.flow_pulse.map(|level| !level);
+        self.flow_pulse
+    }
+}
+
+/// A pin which is both an input and an output, holding its level.
+pub struct MockPin(pub bool);
+
+impl InputPin for MockPin {
+    type Error = Infallible;
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(self.0)
+    }
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(!self.0)
+    }
+}
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.0 = false;
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.0 = true;
+        Ok(())
+    }
+}
+
+/// An input pin which reads a scripted sequence of levels, one per read,
+/// holding the last level once the script runs out.
+pub struct ScriptedPin {
+    levels: std::vec::Vec<bool>,
+    next: Cell<usize>,
+}
+
+impl ScriptedPin {
+    pub fn new(levels: &[bool]) -> Self {
+        Self {
+            levels: levels.to_vec(),
+            next: Cell::new(0),
+        }
+    }
+}
+
+impl InputPin for ScriptedPin {
+    type Error = Infallible;
+    fn is_high(&self) -> Result<bool, Infallible> {
+        let next = self.next.get();
+        self.next.set(next + 1);
+        Ok(self.levels[next.min(self.levels.len() - 1)])
+    }
+    fn is_low(&self) -> Result<bool, Infallible> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+#[derive(Default)]
+pub struct MockStorage {
+    pub stored: Option<Settings>,
+
+    /// How many times settings were stored.
+    pub stores: usize,
+}
+
+impl SettingsStorage for MockStorage {
+    fn load(&mut self) -> (Settings, SettingsOrigin) {
+        match self.stored {
+            Some(settings) => (
+                settings,
+                SettingsOrigin::Bank {
+                    bank: 0,
+                    version: 0,
+                    corrupt_banks: 0,
+                },
+            ),
+            None => (
+                Settings::default(),
+                SettingsOrigin::Defaults { corrupt_banks: 0 },
+            ),
+        }
+    }
+    fn store(&mut self, settings: &Settings) -> Result<(), SettingsStorageError> {
+        self.stored = Some(*settings);
+        self.stores += 1;
+        Ok(())
+    }
+}
+
+/// Settings banks held in RAM. Writes can be made to corrupt what they store
+/// to simulate a failing flash.
+#[derive(Debug)]
+pub struct MockBanks {
+    pub banks: [[u8; SETTINGS_BANK_SIZE]; SETTINGS_BANK_COUNT as usize],
+    pub fail_writes: bool,
+}
+
+impl Default for MockBanks {
+    fn default() -> Self {
+        Self {
+            banks: [[0xFF; SETTINGS_BANK_SIZE]; SETTINGS_BANK_COUNT as usize],
+            fail_writes: false,
+        }
+    }
+}
+
+impl SettingsBanks for MockBanks {
+    fn read_bank(&mut self, bank: u8) -> [u8; SETTINGS_BANK_SIZE] {
+        self.banks[bank as usize]
+    }
+    fn write_bank(
+        &mut self,
+        bank: u8,
+        bytes: &[u8; SETTINGS_BANK_SIZE],
+    ) -> Result<(), SettingsStorageError> {
+        self.banks[bank as usize] = *bytes;
+        if self.fail_writes {
+            // NOTE: Flips a byte of the payload, just past the header.
+            self.banks[bank as usize][8] ^= 0xFF;
+        }
+        Ok(())
+    }
+}
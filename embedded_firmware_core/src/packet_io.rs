@@ -0,0 +1,198 @@
+use heapless::{Deque, Vec};
+use thiserror_no_std::Error;
+use usb_device::{
+    bus::{UsbBus, UsbBusAllocator},
+    device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
+    UsbError,
+};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+/// This allows separation of the byte stream packets travel over (e.g. USB
+/// serial) from the business logic which makes the application easier to
+/// unit test.
+pub trait PacketIo {
+    /// Read as many bytes as are ready into `buffer`.
+    /// Returns the number of bytes read, which may be zero.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, PacketIoError>;
+
+    /// Write as many bytes of `bytes` as fit.
+    /// Returns the number of bytes written.
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, PacketIoError>;
+
+    /// Push any buffered bytes out.
+    fn flush(&mut self) -> Result<(), PacketIoError>;
+
+    /// Service the underlying transport.
+    /// Transports which don't need servicing can ignore this.
+    fn poll(&mut self) {}
+}
+
+/// Represents errors in moving bytes over a `PacketIo`.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PacketIoError {
+    /// Nothing could be transferred right now. Try again later.
+    #[error("Packet io would block.")]
+    WouldBlock,
+
+    /// The underlying transport failed.
+    #[error("Packet io transport failure.")]
+    Transport,
+}
+
+impl From<UsbError> for PacketIoError {
+    fn from(error: UsbError) -> Self {
+        match error {
+            UsbError::WouldBlock => PacketIoError::WouldBlock,
+            _ => PacketIoError::Transport,
+        }
+    }
+}
+
+/// Moves packets over a USB CDC serial port.
+pub struct UsbPacketIo<'a, B: UsbBus> {
+    pub serial_port: SerialPort<'a, B>,
+    pub usb_device: UsbDevice<'a, B>,
+}
+
+impl<'a, B: UsbBus> UsbPacketIo<'a, B> {
+    pub fn new(bus_allocator: &'a UsbBusAllocator<B>) -> Self {
+        Self {
+            serial_port: SerialPort::new(bus_allocator),
+            usb_device: UsbDeviceBuilder::new(bus_allocator, UsbVidPid(0x2222, 0x3333))
+                .manufacturer("LA Tech")
+                .product("Too Hot To Prandtl Controller")
+                .serial_number("1324")
+                .device_class(USB_CLASS_CDC)
+                .build(),
+        }
+    }
+}
+
+impl<'a, B: UsbBus> PacketIo for UsbPacketIo<'a, B> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, PacketIoError> {
+        Ok(self.serial_port.read(buffer)?)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, PacketIoError> {
+        Ok(self.serial_port.write(bytes)?)
+    }
+
+    fn flush(&mut self) -> Result<(), PacketIoError> {
+        Ok(self.serial_port.flush()?)
+    }
+
+    /// NOTE: This should be called from the USB interrupt.
+    fn poll(&mut self) {
+        self.usb_device.poll(&mut [&mut self.serial_port]);
+    }
+}
+
+/// The most bytes a `LoopbackPacketIo` buffers in each direction.
+pub const LOOPBACK_CAPACITY: usize = 512;
+
+/// An in-memory `PacketIo` standing in for the host end of the link.
+/// Bytes given to `send_to_device` are read by the application, and bytes the
+/// application writes are collected by `take_from_device`.
+pub struct LoopbackPacketIo {
+    to_device: Deque<u8, LOOPBACK_CAPACITY>,
+    from_device: Vec<u8, LOOPBACK_CAPACITY>,
+}
+
+impl LoopbackPacketIo {
+    pub fn new() -> Self {
+        Self {
+            to_device: Deque::new(),
+            from_device: Vec::new(),
+        }
+    }
+
+    /// Queue bytes for the application to read.
+    /// Returns the number of bytes queued, which is less than requested once
+    /// the buffer is full.
+    pub fn send_to_device(&mut self, bytes: &[u8]) -> usize {
+        bytes
+            .iter()
+            .take_while(|byte| self.to_device.push_back(**byte).is_ok())
+            .count()
+    }
+
+    /// Take every byte the application has written so far.
+    pub fn take_from_device(&mut self) -> Vec<u8, LOOPBACK_CAPACITY> {
+        core::mem::take(&mut self.from_device)
+    }
+}
+
+impl Default for LoopbackPacketIo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacketIo for LoopbackPacketIo {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, PacketIoError> {
+        if self.to_device.is_empty() {
+            return Err(PacketIoError::WouldBlock);
+        }
+        let mut count = 0;
+        for slot in buffer.iter_mut() {
+            match self.to_device.pop_front() {
+                Some(byte) => *slot = byte,
+                None => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, PacketIoError> {
+        let count = bytes.len().min(LOOPBACK_CAPACITY - self.from_device.len());
+        if count == 0 && !bytes.is_empty() {
+            return Err(PacketIoError::WouldBlock);
+        }
+        // NOTE: Can't fail, `count` fits in the remaining capacity.
+        let _ = self.from_device.extend_from_slice(&bytes[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> Result<(), PacketIoError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_read() {
+        let mut io = LoopbackPacketIo::new();
+        let mut buffer = [0u8; 4];
+        assert_eq!(io.read(&mut buffer), Err(PacketIoError::WouldBlock));
+
+        assert_eq!(io.send_to_device(&[1, 2, 3, 4, 5, 6]), 6);
+        assert_eq!(io.read(&mut buffer), Ok(4));
+        assert_eq!(buffer, [1, 2, 3, 4]);
+        assert_eq!(io.read(&mut buffer), Ok(2));
+        assert_eq!(&buffer[..2], &[5, 6]);
+        assert_eq!(io.read(&mut buffer), Err(PacketIoError::WouldBlock));
+    }
+
+    #[test]
+    fn test_loopback_write() {
+        let mut io = LoopbackPacketIo::new();
+        assert_eq!(io.write(&[1, 2, 3]), Ok(3));
+        assert_eq!(io.write(&[4]), Ok(1));
+        assert_eq!(&io.take_from_device()[..], &[1, 2, 3, 4]);
+        assert!(io.take_from_device().is_empty());
+    }
+
+    #[test]
+    fn test_loopback_full() {
+        let mut io = LoopbackPacketIo::new();
+        let bytes = [0u8; LOOPBACK_CAPACITY + 8];
+
+        assert_eq!(io.send_to_device(&bytes), LOOPBACK_CAPACITY);
+        assert_eq!(io.write(&bytes), Ok(LOOPBACK_CAPACITY));
+        assert_eq!(io.write(&bytes), Err(PacketIoError::WouldBlock));
+    }
+}
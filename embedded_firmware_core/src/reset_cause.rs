@@ -0,0 +1,87 @@
+use common::packet::ResetCause;
+
+/// SAMD21 `PM.RCAUSE` bits, see the datasheet's "Reset Cause" register.
+const SAMD21_RCAUSE_POR: u8 = 1 << 0;
+const SAMD21_RCAUSE_BOD12: u8 = 1 << 1;
+const SAMD21_RCAUSE_BOD33: u8 = 1 << 2;
+const SAMD21_RCAUSE_EXT: u8 = 1 << 4;
+const SAMD21_RCAUSE_WDT: u8 = 1 << 5;
+const SAMD21_RCAUSE_SYST: u8 = 1 << 6;
+
+/// RP2040 `VREG_AND_CHIP_RESET.CHIP_RESET` bits.
+const RP2040_CHIP_RESET_HAD_POR: u32 = 1 << 8;
+const RP2040_CHIP_RESET_HAD_RUN: u32 = 1 << 16;
+const RP2040_CHIP_RESET_HAD_PSM_RESTART: u32 = 1 << 20;
+
+/// RP2040 `WATCHDOG.REASON` bits.
+const RP2040_WATCHDOG_REASON_TIMER: u32 = 1 << 0;
+const RP2040_WATCHDOG_REASON_FORCE: u32 = 1 << 1;
+
+/// Decode the SAMD21 reset cause register.
+/// NOTE: The brown-out detectors can flag alongside a power-on reset while
+///       the supply ramps up, so power-on takes precedence.
+pub fn from_samd21_rcause(rcause: u8) -> ResetCause {
+    if rcause & SAMD21_RCAUSE_POR != 0 {
+        ResetCause::PowerOn
+    } else if rcause & (SAMD21_RCAUSE_BOD12 | SAMD21_RCAUSE_BOD33) != 0 {
+        ResetCause::BrownOut
+    } else if rcause & SAMD21_RCAUSE_WDT != 0 {
+        ResetCause::Watchdog
+    } else if rcause & SAMD21_RCAUSE_SYST != 0 {
+        ResetCause::Software
+    } else if rcause & SAMD21_RCAUSE_EXT != 0 {
+        ResetCause::External
+    } else {
+        ResetCause::Unknown
+    }
+}
+
+/// Decode the RP2040 chip reset and watchdog reason registers.
+/// NOTE: The RP2040's brown-out detector triggers a power-on reset, so brown
+///       outs can't be told apart from power cycles.
+pub fn from_rp2040_registers(chip_reset: u32, watchdog_reason: u32) -> ResetCause {
+    if watchdog_reason & RP2040_WATCHDOG_REASON_TIMER != 0 {
+        ResetCause::Watchdog
+    } else if watchdog_reason & RP2040_WATCHDOG_REASON_FORCE != 0 {
+        ResetCause::Software
+    } else if chip_reset & RP2040_CHIP_RESET_HAD_POR != 0 {
+        ResetCause::PowerOn
+    } else if chip_reset & RP2040_CHIP_RESET_HAD_RUN != 0 {
+        ResetCause::External
+    } else if chip_reset & RP2040_CHIP_RESET_HAD_PSM_RESTART != 0 {
+        ResetCause::Software
+    } else {
+        ResetCause::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samd21_rcause() {
+        assert_eq!(from_samd21_rcause(0x01), ResetCause::PowerOn);
+        assert_eq!(from_samd21_rcause(0x02), ResetCause::BrownOut);
+        assert_eq!(from_samd21_rcause(0x04), ResetCause::BrownOut);
+        assert_eq!(from_samd21_rcause(0x10), ResetCause::External);
+        assert_eq!(from_samd21_rcause(0x20), ResetCause::Watchdog);
+        assert_eq!(from_samd21_rcause(0x40), ResetCause::Software);
+        assert_eq!(from_samd21_rcause(0x00), ResetCause::Unknown);
+    }
+
+    #[test]
+    fn test_samd21_power_on_masks_brown_out() {
+        assert_eq!(from_samd21_rcause(0x07), ResetCause::PowerOn);
+    }
+
+    #[test]
+    fn test_rp2040_registers() {
+        assert_eq!(from_rp2040_registers(1 << 8, 0), ResetCause::PowerOn);
+        assert_eq!(from_rp2040_registers(1 << 16, 0), ResetCause::External);
+        assert_eq!(from_rp2040_registers(1 << 20, 0), ResetCause::Software);
+        assert_eq!(from_rp2040_registers(1 << 8, 1), ResetCause::Watchdog);
+        assert_eq!(from_rp2040_registers(0, 2), ResetCause::Software);
+        assert_eq!(from_rp2040_registers(0, 0), ResetCause::Unknown);
+    }
+}
@@ -0,0 +1,180 @@
+use heapless::Vec;
+use thiserror_no_std::Error;
+
+/// A count of core loop iterations. Wraps around, which the scheduler allows
+/// for as long as no period is longer than half the range.
+pub type Ticks = u32;
+
+/// Cooperative scheduler for periodic jobs. Jobs are only identifiers; the
+/// caller decides what running one means, so scheduling can be tested without
+/// any hardware.
+/// NOTE: A job which falls behind, e.g. because the loop stalled, runs once
+///       and is rescheduled a full period later rather than catching up.
+pub struct Scheduler<Job: Copy + PartialEq, const N: usize> {
+    jobs: Vec<ScheduledJob<Job>, N>,
+}
+
+struct ScheduledJob<Job> {
+    job: Job,
+    period: Ticks,
+    next_due: Ticks,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SchedulerError {
+    /// Every job slot is in use.
+    #[error("No room to register another job.")]
+    Full,
+
+    /// The job is already registered.
+    #[error("Job is already registered.")]
+    AlreadyRegistered,
+}
+
+impl<Job: Copy + PartialEq, const N: usize> Scheduler<Job, N> {
+    pub fn new() -> Self {
+        Self { jobs: Vec::new() }
+    }
+
+    /// Register a job to first run one period after `now`, then every period.
+    /// Periods of zero are treated as a single tick.
+    pub fn register(&mut self, job: Job, period: Ticks, now: Ticks) -> Result<(), SchedulerError> {
+        if self.jobs.iter().any(|scheduled| scheduled.job == job) {
+            return Err(SchedulerError::AlreadyRegistered);
+        }
+        let period = period.max(1);
+        self.jobs
+            .push(ScheduledJob {
+                job,
+                period,
+                next_due: now.wrapping_add(period),
+            })
+            .map_err(|_| SchedulerError::Full)
+    }
+
+    /// Change the period of a registered job, rescheduling it one new period
+    /// after `now`. Returns `false` if the job isn't registered.
+    pub fn set_period(&mut self, job: Job, period: Ticks, now: Ticks) -> bool {
+        let Some(scheduled) = self.jobs.iter_mut().find(|scheduled| scheduled.job == job) else {
+            return false;
+        };
+        scheduled.period = period.max(1);
+        scheduled.next_due = now.wrapping_add(scheduled.period);
+        true
+    }
+
+    /// Get every job due at `now`, in registration order, and schedule each
+    /// for its next period.
+    pub fn run_due(&mut self, now: Ticks) -> Vec<Job, N> {
+        let mut due = Vec::new();
+        for scheduled in self.jobs.iter_mut() {
+            // NOTE: Compared as a signed difference so wrapping is handled.
+            if (now.wrapping_sub(scheduled.next_due) as i32) < 0 {
+                continue;
+            }
+            scheduled.next_due = now.wrapping_add(scheduled.period);
+            // NOTE: Can't fail, there are never more due jobs than jobs.
+            let _ = due.push(scheduled.job);
+        }
+        due
+    }
+}
+
+impl<Job: Copy + PartialEq, const N: usize> Default for Scheduler<Job, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum TestJob {
+        Fast,
+        Slow,
+    }
+
+    /// Run every tick in `ticks`, collecting the jobs run at each.
+    fn run(
+        scheduler: &mut Scheduler<TestJob, 2>,
+        ticks: impl Iterator<Item = Ticks>,
+    ) -> std::vec::Vec<(Ticks, TestJob)> {
+        ticks
+            .flat_map(|now| {
+                scheduler
+                    .run_due(now)
+                    .into_iter()
+                    .map(move |job| (now, job))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_jobs_run_every_period() {
+        let mut scheduler = Scheduler::new();
+        scheduler.register(TestJob::Fast, 2, 0).unwrap();
+        scheduler.register(TestJob::Slow, 3, 0).unwrap();
+
+        assert_eq!(
+            run(&mut scheduler, 1..=6),
+            [
+                (2, TestJob::Fast),
+                (3, TestJob::Slow),
+                (4, TestJob::Fast),
+                (6, TestJob::Fast),
+                (6, TestJob::Slow),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_register_errors() {
+        let mut scheduler: Scheduler<TestJob, 1> = Scheduler::new();
+        scheduler.register(TestJob::Fast, 2, 0).unwrap();
+        assert_eq!(
+            scheduler.register(TestJob::Fast, 2, 0),
+            Err(SchedulerError::AlreadyRegistered)
+        );
+        assert_eq!(
+            scheduler.register(TestJob::Slow, 2, 0),
+            Err(SchedulerError::Full)
+        );
+    }
+
+    #[test]
+    fn test_late_job_does_not_catch_up() {
+        let mut scheduler = Scheduler::new();
+        scheduler.register(TestJob::Fast, 2, 0).unwrap();
+
+        assert_eq!(run(&mut scheduler, [9].into_iter()), [(9, TestJob::Fast)]);
+        assert_eq!(run(&mut scheduler, 10..=11), [(11, TestJob::Fast)]);
+    }
+
+    #[test]
+    fn test_set_period_reschedules() {
+        let mut scheduler = Scheduler::new();
+        scheduler.register(TestJob::Slow, 10, 0).unwrap();
+        assert!(scheduler.set_period(TestJob::Slow, 2, 5));
+        assert!(!scheduler.set_period(TestJob::Fast, 2, 5));
+
+        assert_eq!(
+            run(&mut scheduler, 6..=9),
+            [(7, TestJob::Slow), (9, TestJob::Slow)]
+        );
+    }
+
+    #[test]
+    fn test_ticks_wrap() {
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .register(TestJob::Fast, 3, Ticks::MAX - 1)
+            .unwrap();
+
+        assert_eq!(
+            run(&mut scheduler, [Ticks::MAX, 0, 1, 2, 3, 4].into_iter()),
+            [(1, TestJob::Fast), (4, TestJob::Fast)]
+        );
+    }
+}
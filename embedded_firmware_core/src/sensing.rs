@@ -0,0 +1,477 @@
+use common::{
+    packet::{
+        FanChannels, FirmwareError, ReportRawAdcPacket, ReportRpmFastPacket, ReportSensorsPacket,
+        SenseCurve, SensorSample,
+    },
+    physical::{Celsius, Current, Rpm, ValveState},
+};
+use embedded_hal::digital::v2::InputPin;
+
+use crate::{
+    flow_meter::FlowMeter, settings::Settings, valve_sense::ValveSenseDebouncer, ApplicationError,
+    PrandtlAdc,
+};
+
+/// The pump speed represented by a full scale pump sense reading, until the
+/// host retunes the pump sense curve.
+const PUMP_SENSE_FULL_SCALE_RPM: u16 = 2000;
+
+/// The fan speed represented by a full scale fan sense reading, until the
+/// host retunes the fan sense curve.
+const FAN_SENSE_FULL_SCALE_RPM: u16 = 1800;
+
+/// Supply voltage below which a brownout warning is raised.
+const SUPPLY_VOLTAGE_LOW_THRESHOLD: f32 = 3.0f32;
+
+/// How far the supply must recover above the threshold before the warning
+/// clears. Prevents a supply hovering at the threshold from flooding errors.
+const SUPPLY_VOLTAGE_HYSTERESIS: f32 = 0.1f32;
+
+/// Owns the inputs. Reads the ADC and valve sense pins into packets for the
+/// host.
+pub struct Sensing<PAdc: PrandtlAdc, ValveState1Pin: InputPin, ValveState2Pin: InputPin> {
+    padc: PAdc,
+
+    valve_sense_1_pin: ValveState1Pin,
+    valve_sense_2_pin: ValveState2Pin,
+    valve_sense: ValveSenseDebouncer,
+
+    flow_meter: FlowMeter,
+
+    /// Map the speed sense readings to speeds. Always valid.
+    pump_sense: SenseCurve,
+    fan_sense: SenseCurve,
+
+    /// Whether the supply voltage is currently below the warning threshold.
+    supply_voltage_low: bool,
+}
+
+impl<PAdc: PrandtlAdc, ValveState1Pin: InputPin, ValveState2Pin: InputPin>
+    Sensing<PAdc, ValveState1Pin, ValveState2Pin>
+{
+    pub fn new(
+        padc: PAdc,
+        valve_sense_1_pin: ValveState1Pin,
+        valve_sense_2_pin: ValveState2Pin,
+    ) -> Self {
+        Self {
+            padc,
+            valve_sense_1_pin,
+            valve_sense_2_pin,
+            valve_sense: ValveSenseDebouncer::default(),
+            flow_meter: FlowMeter::default(),
+            pump_sense: SenseCurve::linear(PUMP_SENSE_FULL_SCALE_RPM),
+            fan_sense: SenseCurve::linear(FAN_SENSE_FULL_SCALE_RPM),
+            supply_voltage_low: false,
+        }
+    }
+
+    /// Poll the binary state of each valve sense pin.
+    fn poll_valve_state_pins(&self) -> Result<(bool, bool), ApplicationError> {
+        let is_open_high = self
+            .valve_sense_1_pin
+            .is_high()
+            .map_err(|_| ApplicationError::ValveReadFailure)?;
+        let is_close_high = self
+            .valve_sense_2_pin
+            .is_high()
+            .map_err(|_| ApplicationError::ValveReadFailure)?;
+        Ok((is_open_high, is_close_high))
+    }
+
+    /// Sample the valve sense pins into their debounce filter. Should be
+    /// called every `period_ms`, such as once per core loop.
+    pub fn sample_valve_sense(&mut self, period_ms: u16) -> Result<(), ApplicationError> {
+        let pins = self.poll_valve_state_pins()?;
+        self.valve_sense.sample(pins, period_ms);
+        Ok(())
+    }
+
+    /// The debounced valve state, or `None` until the pins have first
+    /// settled.
+    pub fn sensed_valve_state(&self) -> Option<ValveState> {
+        self.valve_sense.state().map(ValveState::from)
+    }
+
+    /// Sample the flow meter's pulse output, if the hardware has one. Should
+    /// be called every `period_ms`, such as once per core loop.
+    pub fn sample_flow_meter(&mut self, period_ms: u16) {
+        if let Some(level) = self.padc.read_flow_pulse() {
+            self.flow_meter.sample(level, period_ms);
+        }
+    }
+
+    /// Replace the speed sense curves. Returns `false`, leaving the curves
+    /// as they were, if either is invalid.
+    pub fn set_sense_curves(&mut self, pump_sense: SenseCurve, fan_sense: SenseCurve) -> bool {
+        if !pump_sense.is_valid() || !fan_sense.is_valid() {
+            return false;
+        }
+        self.pump_sense = pump_sense;
+        self.fan_sense = fan_sense;
+        true
+    }
+
+    /// Read just the pump and fan speeds. Readings are trimmed by the sense
+    /// calibration in `settings`, mapped through the sense curves and scaled
+    /// against the maximum speeds in `settings`.
+    pub fn read_rpm(
+        &mut self,
+        settings: &Settings,
+    ) -> Result<ReportRpmFastPacket, ApplicationError> {
+        let pump_speed_raw = match self.padc.read_pump_sense_norm() {
+            None => return Err(ApplicationError::ReadAdcFailure),
+            Some(raw) => raw,
+        };
+        let fan_speed_raw = match self.padc.read_fan_sense_norm() {
+            None => return Err(ApplicationError::ReadAdcFailure),
+            Some(raw) => raw,
+        };
+
+        let pump_speed_raw = settings.pump_sense_calibration.apply(pump_speed_raw);
+        let fan_speed_raw = settings.fan_sense_calibration.apply(fan_speed_raw);
+
+        // NOTE: The curves are always valid, so only a NaN reading fails.
+        let pump_speed = self
+            .pump_sense
+            .rpm_at(pump_speed_raw)
+            .ok_or(ApplicationError::ReadAdcFailure)?;
+        let fan_speed = self
+            .fan_sense
+            .rpm_at(fan_speed_raw)
+            .ok_or(ApplicationError::ReadAdcFailure)?;
+
+        Ok(ReportRpmFastPacket {
+            pump_speed_rpm: speed_to_rpm(settings.pump_max_rpm, pump_speed)?,
+            // NOTE: Only a single fan is sensed.
+            fan_speeds_rpm: FanChannels::single(speed_to_rpm(settings.fan_max_rpm, fan_speed)?),
+        })
+    }
+
+    /// Take a snapshot of the sensors. Speeds are scaled against the maximum
+    /// speeds in `settings`. The valve state is debounced, falling back to
+    /// the pins as they read now until they have first settled.
+    pub fn read_sensors(
+        &mut self,
+        settings: &Settings,
+    ) -> Result<ReportSensorsPacket, ApplicationError> {
+        let ReportRpmFastPacket {
+            pump_speed_rpm,
+            fan_speeds_rpm,
+        } = self.read_rpm(settings)?;
+        let valve_state = self.read_valve_state()?;
+
+        // NOTE: The board temperature is optional, so a failed read is not an error.
+        let board_temperature = self
+            .padc
+            .read_board_temperature()
+            .and_then(|temperature| Celsius::try_from(temperature).ok());
+        let coolant_temperature = self
+            .padc
+            .read_coolant_temperature()
+            .and_then(|temperature| Celsius::try_from(temperature).ok());
+
+        // NOTE: Current sensing is optional, so a failed read is not an error.
+        let pump_current = self
+            .padc
+            .read_pump_current()
+            .and_then(|current| Current::try_from(current).ok());
+        let fan_current = self
+            .padc
+            .read_fan_current()
+            .and_then(|current| Current::try_from(current).ok());
+
+        Ok(ReportSensorsPacket {
+            pump_speed_rpm,
+            fan_speeds_rpm,
+            valve_state,
+            board_temperature,
+            coolant_temperature,
+            pump_current,
+            fan_current,
+            flow_rate: self.flow_meter.flow_rate(settings.flow_pulses_per_liter),
+            sampled_at_ms: None,
+        })
+    }
+
+    /// Read the sense inputs as they are, before calibration, sense curves
+    /// or debouncing, for diagnosing the hardware.
+    pub fn read_raw(&mut self) -> Result<ReportRawAdcPacket, ApplicationError> {
+        let (valve_sense_1, valve_sense_2) = self.poll_valve_state_pins()?;
+        Ok(ReportRawAdcPacket {
+            pump_sense: self.padc.read_pump_sense_raw(),
+            fan_sense: self.padc.read_fan_sense_raw(),
+            valve_sense_1,
+            valve_sense_2,
+        })
+    }
+
+    /// Take a single sample for a sensor batch, with speeds in whole RPM.
+    /// The offset into the batch is left for the caller to fill in.
+    pub fn read_sample(&mut self, settings: &Settings) -> Result<SensorSample, ApplicationError> {
+        let ReportRpmFastPacket {
+            pump_speed_rpm,
+            fan_speeds_rpm,
+        } = self.read_rpm(settings)?;
+        Ok(SensorSample {
+            offset_ms: 0,
+            fan_rpm: whole_rpm(fan_speeds_rpm.first()),
+            pump_rpm: whole_rpm(pump_speed_rpm),
+            valve_state: self.read_valve_state()?,
+        })
+    }
+
+    /// Get the debounced valve state, falling back to the pins as they read
+    /// now until they have first settled.
+    fn read_valve_state(&mut self) -> Result<ValveState, ApplicationError> {
+        let valve_state_raw = match self.valve_sense.state() {
+            Some(pins) => pins,
+            None => self.poll_valve_state_pins()?,
+        };
+        Ok(ValveState::from(valve_state_raw))
+    }
+
+    /// Read the supply voltage in millivolts for `ReportStats`. Also returns
+    /// an error when it first droops.
+    pub fn read_supply_voltage_mv(&mut self) -> (Option<u16>, Option<FirmwareError>) {
+        let mut error = None;
+        let supply_voltage_mv = self.padc.read_supply_voltage().map(|voltage| {
+            let supply_voltage_mv = (voltage * 1000f32) as u16;
+
+            let was_low = self.supply_voltage_low;
+            self.supply_voltage_low = is_supply_voltage_low(voltage, was_low);
+            if self.supply_voltage_low && !was_low {
+                error = Some(FirmwareError::SupplyVoltageLow { supply_voltage_mv });
+            }
+            supply_voltage_mv
+        });
+
+        (supply_voltage_mv, error)
+    }
+}
+
+/// Construct an `Rpm` from a measured speed and the configured maximum speed.
+/// A speed above the configured maximum raises the maximum so the host can
+/// observe the overspeed and learn the new maximum.
+fn speed_to_rpm(max_rpm: u16, speed: f32) -> Result<Rpm, ApplicationError> {
+    let max_speed = (max_rpm as f32).max(speed);
+    Rpm::new(max_speed, speed).map_err(ApplicationError::RpmError)
+}
+
+/// Round a speed to whole RPM, saturating at `u16::MAX`.
+fn whole_rpm(rpm: Rpm) -> u16 {
+    // NOTE: Speeds are never negative, so adding a half rounds them.
+    (rpm.speed() + 0.5f32) as u16
+}
+
+/// Check if the supply voltage is low, with hysteresis around the threshold.
+fn is_supply_voltage_low(supply_voltage: f32, was_low: bool) -> bool {
+    if was_low {
+        supply_voltage < SUPPLY_VOLTAGE_LOW_THRESHOLD + SUPPLY_VOLTAGE_HYSTERESIS
+    } else {
+        supply_voltage < SUPPLY_VOLTAGE_LOW_THRESHOLD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{MockAdc, MockPin, ScriptedPin};
+    use common::physical::Percentage;
+
+    #[test]
+    fn test_is_supply_voltage_low() {
+        assert!(!is_supply_voltage_low(3.3f32, false));
+        assert!(is_supply_voltage_low(2.9f32, false));
+
+        assert!(is_supply_voltage_low(3.05f32, true));
+        assert!(!is_supply_voltage_low(3.05f32, false));
+        assert!(!is_supply_voltage_low(3.2f32, true));
+    }
+
+    #[test]
+    fn test_read_sensors() {
+        let mut sensing = Sensing::new(MockAdc::default(), MockPin(true), MockPin(false));
+        let settings = Settings::default();
+
+        let report = sensing
+            .read_sensors(&settings)
+            .expect("Failed to read sensors.");
+
+        let pump_speed = report.pump_speed_rpm.speed();
+        let fan_speed = report.fan_speeds_rpm.first().speed();
+        assert!((pump_speed - 0.5f32 * PUMP_SENSE_FULL_SCALE_RPM as f32).abs() < 1f32);
+        assert!((fan_speed - 0.25f32 * FAN_SENSE_FULL_SCALE_RPM as f32).abs() < 1f32);
+        assert_eq!(report.valve_state, ValveState::from((true, false)));
+        assert_eq!(report.board_temperature, None);
+        assert_eq!(report.coolant_temperature, None);
+        assert_eq!(report.pump_current, None);
+        assert_eq!(report.flow_rate, None);
+    }
+
+    #[test]
+    fn test_read_sensors_reports_flow_rate() {
+        let mut sensing = Sensing::new(
+            MockAdc {
+                flow_pulse: Some(true),
+                ..Default::default()
+            },
+            MockPin(false),
+            MockPin(false),
+        );
+        let mut settings = Settings::default();
+
+        // NOTE: A pulse every other 10ms sample is 3000 pulses a minute.
+        for _ in 0..100 {
+            sensing.sample_flow_meter(10);
+        }
+        let report = sensing
+            .read_sensors(&settings)
+            .expect("Failed to read sensors.");
+        assert_eq!(report.flow_rate, None);
+
+        settings.flow_pulses_per_liter = 1000;
+        let report = sensing
+            .read_sensors(&settings)
+            .expect("Failed to read sensors.");
+        assert_eq!(
+            report.flow_rate.map(|flow_rate| flow_rate.value()),
+            Some(3f32)
+        );
+    }
+
+    #[test]
+    fn test_read_sensors_reports_coolant_temperature() {
+        let mut sensing = Sensing::new(
+            MockAdc {
+                coolant_temperature: Some(31.5f32),
+                ..Default::default()
+            },
+            MockPin(false),
+            MockPin(false),
+        );
+        let report = sensing
+            .read_sensors(&Settings::default())
+            .expect("Failed to read sensors.");
+        assert_eq!(
+            report
+                .coolant_temperature
+                .map(|temperature| temperature.value()),
+            Some(31.5f32)
+        );
+    }
+
+    #[test]
+    fn test_read_rpm_matches_read_sensors() {
+        let mut sensing = Sensing::new(MockAdc::default(), MockPin(true), MockPin(false));
+        let settings = Settings::default();
+
+        let rpm = sensing.read_rpm(&settings).expect("Failed to read rpm.");
+        let report = sensing
+            .read_sensors(&settings)
+            .expect("Failed to read sensors.");
+        assert_eq!(rpm.pump_speed_rpm, report.pump_speed_rpm);
+        assert_eq!(rpm.fan_speeds_rpm, report.fan_speeds_rpm);
+    }
+
+    #[test]
+    fn test_set_sense_curves() {
+        let mut sensing = Sensing::new(MockAdc::default(), MockPin(true), MockPin(false));
+        let settings = Settings::default();
+
+        let mut pump_sense = SenseCurve::linear(2000);
+        pump_sense.points[1].1 = 1500;
+        pump_sense.points[1].0 = Percentage::const_new(50);
+        let _ = pump_sense.points.push((Percentage::const_new(100), 2000));
+        assert!(sensing.set_sense_curves(pump_sense.clone(), SenseCurve::linear(1200)));
+        let rpm = sensing.read_rpm(&settings).expect("Failed to read rpm.");
+        assert_eq!(rpm.pump_speed_rpm.speed(), 1500f32);
+        assert_eq!(rpm.fan_speeds_rpm.first().speed(), 300f32);
+
+        // NOTE: An invalid curve leaves both as they were.
+        let mut invalid = pump_sense;
+        invalid.points.clear();
+        assert!(!sensing.set_sense_curves(SenseCurve::linear(1000), invalid));
+        let rpm = sensing.read_rpm(&settings).expect("Failed to read rpm.");
+        assert_eq!(rpm.pump_speed_rpm.speed(), 1500f32);
+    }
+
+    #[test]
+    fn test_read_sensors_debounces_valve_state() {
+        // NOTE: The valve settles closed, then the closed switch bounces as
+        //       the valve starts to open.
+        let mut sensing = Sensing::new(
+            MockAdc::default(),
+            ScriptedPin::new(&[false]),
+            ScriptedPin::new(&[true, true, true, false, true, false, false, false]),
+        );
+        let settings = Settings::default();
+        for _ in 0..3 {
+            sensing
+                .sample_valve_sense(10)
+                .expect("Failed to sample valve sense.");
+        }
+
+        let mut valve_states = std::vec::Vec::new();
+        for _ in 0..5 {
+            sensing
+                .sample_valve_sense(10)
+                .expect("Failed to sample valve sense.");
+            let report = sensing
+                .read_sensors(&settings)
+                .expect("Failed to read sensors.");
+            valve_states.push(report.valve_state);
+        }
+        assert_eq!(
+            valve_states,
+            [
+                ValveState::Closed,
+                ValveState::Closed,
+                ValveState::Closed,
+                ValveState::Closed,
+                ValveState::Unknown,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_sensors_adc_failure() {
+        let mut sensing = Sensing::new(
+            MockAdc {
+                pump_sense: None,
+                ..Default::default()
+            },
+            MockPin(false),
+            MockPin(false),
+        );
+
+        assert!(matches!(
+            sensing.read_sensors(&Settings::default()),
+            Err(ApplicationError::ReadAdcFailure)
+        ));
+    }
+
+    #[test]
+    fn test_read_supply_voltage_reports_droop_once() {
+        let mut sensing = Sensing::new(
+            MockAdc {
+                supply_voltage: Some(2.9f32),
+                ..Default::default()
+            },
+            MockPin(false),
+            MockPin(false),
+        );
+
+        let (supply_voltage_mv, error) = sensing.read_supply_voltage_mv();
+        assert_eq!(supply_voltage_mv, Some(2900));
+        assert_eq!(
+            error,
+            Some(FirmwareError::SupplyVoltageLow {
+                supply_voltage_mv: 2900
+            })
+        );
+
+        let (_, error) = sensing.read_supply_voltage_mv();
+        assert_eq!(error, None);
+    }
+}
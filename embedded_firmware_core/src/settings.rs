@@ -0,0 +1,777 @@
+use common::{
+    crc::crc16,
+    packet::{
+        FirmwareConfig, Parameter, ParameterKind, SenseCalibration, SettingsOrigin,
+        MAX_CORE_LOOP_PERIOD_MS, MAX_PWM_FREQUENCY_HZ, MAX_TELEMETRY_RATE_HZ,
+        MIN_CORE_LOOP_PERIOD_MS, MIN_FAILSAFE_DUTY_PERCENT, MIN_PWM_FREQUENCY_HZ,
+        MIN_TELEMETRY_RATE_HZ,
+    },
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror_no_std::Error;
+
+/// Default maximum pump speed used until the host has learned the real value.
+pub const DEFAULT_PUMP_MAX_RPM: u16 = 2000;
+
+/// Default maximum fan speed used until the host has learned the real value.
+pub const DEFAULT_FAN_MAX_RPM: u16 = 1800;
+
+/// Default sensor report rate used until the host requests a different one.
+pub const DEFAULT_TELEMETRY_RATE_HZ: u8 = 2;
+
+/// Default core loop period used until the host requests a different one.
+/// Kept short so packets from the host are handled within a few milliseconds.
+pub const DEFAULT_CORE_LOOP_PERIOD_MS: u8 = 10;
+
+/// Default frequency the pump and fan are driven at until the host
+/// configures a different one.
+pub const DEFAULT_PWM_FREQUENCY_HZ: u16 = 1000;
+
+/// Default duty output without the host: everything at full.
+pub const DEFAULT_FAILSAFE_DUTY_PERCENT: u8 = 100;
+
+/// Represents the persistent firmware configuration.
+/// These values survive a reset once stored through a `SettingsStorage`.
+/// NOTE: New fields must be added at the end, so settings stored by older
+///       firmware still load. See `decode_settings`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// The maximum speed the pump reaches at 100% duty, in whole RPM.
+    pub pump_max_rpm: u16,
+
+    /// The maximum speed the fan reaches at 100% duty, in whole RPM.
+    pub fan_max_rpm: u16,
+
+    /// How often sensor data is reported to the host, in Hz.
+    pub telemetry_rate_hz: u8,
+
+    /// How long to wait between core loop iterations, in milliseconds.
+    pub core_loop_period_ms: u8,
+
+    /// Trims the pump and fan speed sense readings. Always valid.
+    pub pump_sense_calibration: SenseCalibration,
+    pub fan_sense_calibration: SenseCalibration,
+
+    /// How many pulses the flow meter gives per liter. Zero if there is no
+    /// flow meter.
+    pub flow_pulses_per_liter: u16,
+
+    /// The frequency the pump and fan are driven at, in Hz.
+    pub pwm_frequency_hz: u16,
+
+    /// The pump and fan duty output without the host, in whole percent.
+    pub failsafe_duty_percent: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            pump_max_rpm: DEFAULT_PUMP_MAX_RPM,
+            fan_max_rpm: DEFAULT_FAN_MAX_RPM,
+            telemetry_rate_hz: DEFAULT_TELEMETRY_RATE_HZ,
+            core_loop_period_ms: DEFAULT_CORE_LOOP_PERIOD_MS,
+            pump_sense_calibration: SenseCalibration::IDENTITY,
+            fan_sense_calibration: SenseCalibration::IDENTITY,
+            flow_pulses_per_liter: 0,
+            pwm_frequency_hz: DEFAULT_PWM_FREQUENCY_HZ,
+            failsafe_duty_percent: DEFAULT_FAILSAFE_DUTY_PERCENT,
+        }
+    }
+}
+
+impl Settings {
+    /// Apply a single parameter change from the host.
+    /// Returns `true` if the settings actually changed.
+    pub fn apply(&mut self, parameter: Parameter) -> bool {
+        let previous = *self;
+        match parameter {
+            Parameter::PumpMaxRpm(rpm) => self.pump_max_rpm = rpm,
+            Parameter::FanMaxRpm(rpm) => self.fan_max_rpm = rpm,
+            Parameter::TelemetryRateHz(rate_hz) => {
+                self.telemetry_rate_hz = rate_hz.clamp(MIN_TELEMETRY_RATE_HZ, MAX_TELEMETRY_RATE_HZ)
+            }
+            Parameter::CoreLoopPeriodMs(period_ms) => {
+                self.core_loop_period_ms =
+                    period_ms.clamp(MIN_CORE_LOOP_PERIOD_MS, MAX_CORE_LOOP_PERIOD_MS)
+            }
+            Parameter::FlowPulsesPerLiter(pulses) => self.flow_pulses_per_liter = pulses,
+        }
+        previous != *self
+    }
+
+    /// Apply a sense calibration from the host. Returns `true` if the
+    /// settings actually changed, and `false` without changing them if either
+    /// calibration is invalid.
+    pub fn calibrate(&mut self, pump_sense: SenseCalibration, fan_sense: SenseCalibration) -> bool {
+        if !pump_sense.is_valid() || !fan_sense.is_valid() {
+            return false;
+        }
+        let previous = *self;
+        self.pump_sense_calibration = pump_sense;
+        self.fan_sense_calibration = fan_sense;
+        previous != *self
+    }
+
+    /// Replace the configuration the host reads and writes as a whole,
+    /// clamping each value to what is honored. Returns `true` if the
+    /// settings actually changed.
+    pub fn configure(&mut self, config: FirmwareConfig) -> bool {
+        let previous = *self;
+        self.pwm_frequency_hz = config
+            .pwm_frequency_hz
+            .clamp(MIN_PWM_FREQUENCY_HZ, MAX_PWM_FREQUENCY_HZ);
+        self.telemetry_rate_hz = config
+            .telemetry_rate_hz
+            .clamp(MIN_TELEMETRY_RATE_HZ, MAX_TELEMETRY_RATE_HZ);
+        self.pump_max_rpm = config.pump_max_rpm;
+        self.fan_max_rpm = config.fan_max_rpm;
+        self.failsafe_duty_percent = config
+            .failsafe_duty_percent
+            .clamp(MIN_FAILSAFE_DUTY_PERCENT, 100);
+        previous != *self
+    }
+
+    /// Get the configuration the host reads and writes as a whole.
+    pub fn config(&self) -> FirmwareConfig {
+        FirmwareConfig {
+            pwm_frequency_hz: self.pwm_frequency_hz,
+            telemetry_rate_hz: self.telemetry_rate_hz,
+            pump_max_rpm: self.pump_max_rpm,
+            fan_max_rpm: self.fan_max_rpm,
+            failsafe_duty_percent: self.failsafe_duty_percent,
+        }
+    }
+
+    /// Get the current value of a single setting, for the host.
+    pub fn get(&self, kind: ParameterKind) -> Parameter {
+        match kind {
+            ParameterKind::PumpMaxRpm => Parameter::PumpMaxRpm(self.pump_max_rpm),
+            ParameterKind::FanMaxRpm => Parameter::FanMaxRpm(self.fan_max_rpm),
+            ParameterKind::TelemetryRateHz => Parameter::TelemetryRateHz(self.telemetry_rate_hz),
+            ParameterKind::CoreLoopPeriodMs => {
+                Parameter::CoreLoopPeriodMs(self.core_loop_period_ms)
+            }
+            ParameterKind::FlowPulsesPerLiter => {
+                Parameter::FlowPulsesPerLiter(self.flow_pulses_per_liter)
+            }
+        }
+    }
+}
+
+/// This allows separation of where the settings are stored (e.g. flash)
+/// from the business logic which makes the application easier to unit test.
+pub trait SettingsStorage {
+    /// Load the stored settings and where they came from. Falls back to the
+    /// defaults if nothing valid is stored.
+    fn load(&mut self) -> (Settings, SettingsOrigin);
+
+    /// Persist the settings so they survive a reset.
+    fn store(&mut self, settings: &Settings) -> Result<(), SettingsStorageError>;
+}
+
+/// Represents errors in persisting settings.
+#[derive(Debug, Error)]
+pub enum SettingsStorageError {
+    /// The settings could not be serialized.
+    #[error("Failed to serialize settings.")]
+    Serialize,
+
+    /// The underlying storage failed to write.
+    #[error("Failed to write settings to storage.")]
+    Write,
+}
+
+/// Size of a single settings bank in bytes, matching a flash page.
+pub const SETTINGS_BANK_SIZE: usize = 64;
+
+/// How many settings banks there are.
+pub const SETTINGS_BANK_COUNT: u8 = 2;
+
+/// Marks a bank as holding settings in the versioned, CRC checked layout.
+const BANK_MAGIC: u16 = 0x5053;
+
+/// Bank layout: magic (2 bytes), version (4 bytes) and payload length
+/// (1 byte), then the payload and a CRC-16 of everything before it.
+const BANK_HEADER_SIZE: usize = 7;
+const BANK_CRC_SIZE: usize = 2;
+
+/// Raw storage for the settings banks, such as separately erasable flash rows.
+pub trait SettingsBanks {
+    /// Read the contents of a bank.
+    fn read_bank(&mut self, bank: u8) -> [u8; SETTINGS_BANK_SIZE];
+
+    /// Replace the contents of a bank.
+    fn write_bank(
+        &mut self,
+        bank: u8,
+        bytes: &[u8; SETTINGS_BANK_SIZE],
+    ) -> Result<(), SettingsStorageError>;
+}
+
+/// What a single settings bank holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BankContents {
+    /// Erased, or never written.
+    Erased,
+
+    /// Written but failed its checks, such as a write cut short by a reset.
+    Corrupt,
+
+    /// Settings which passed their CRC check.
+    Valid { version: u32, settings: Settings },
+}
+
+/// Encode settings into a bank image with `version`.
+pub fn encode_bank(
+    settings: &Settings,
+    version: u32,
+) -> Result<[u8; SETTINGS_BANK_SIZE], SettingsStorageError> {
+    let payload: heapless::Vec<u8, { SETTINGS_BANK_SIZE - BANK_HEADER_SIZE - BANK_CRC_SIZE }> =
+        postcard::to_vec(settings).map_err(|_| SettingsStorageError::Serialize)?;
+
+    let mut bank = [0xFFu8; SETTINGS_BANK_SIZE];
+    bank[0..2].copy_from_slice(&BANK_MAGIC.to_le_bytes());
+    bank[2..6].copy_from_slice(&version.to_le_bytes());
+    bank[6] = payload.len() as u8;
+    let crc_start = BANK_HEADER_SIZE + payload.len();
+    bank[BANK_HEADER_SIZE..crc_start].copy_from_slice(&payload);
+    let crc = crc16(&bank[..crc_start]);
+    bank[crc_start..crc_start + BANK_CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+    Ok(bank)
+}
+
+/// Decode a bank image, checking its magic, length and CRC.
+pub fn decode_bank(bank: &[u8; SETTINGS_BANK_SIZE]) -> BankContents {
+    if bank.iter().all(|&byte| byte == 0xFF) {
+        return BankContents::Erased;
+    }
+    let magic = u16::from_le_bytes([bank[0], bank[1]]);
+    if magic != BANK_MAGIC {
+        return BankContents::Corrupt;
+    }
+    let crc_start = BANK_HEADER_SIZE + bank[6] as usize;
+    if crc_start + BANK_CRC_SIZE > SETTINGS_BANK_SIZE {
+        return BankContents::Corrupt;
+    }
+    let crc = u16::from_le_bytes([bank[crc_start], bank[crc_start + 1]]);
+    if crc16(&bank[..crc_start]) != crc {
+        return BankContents::Corrupt;
+    }
+    let Some(settings) = decode_settings(&bank[BANK_HEADER_SIZE..crc_start]) else {
+        return BankContents::Corrupt;
+    };
+    BankContents::Valid {
+        version: u32::from_le_bytes([bank[2], bank[3], bank[4], bank[5]]),
+        settings,
+    }
+}
+
+/// Decode settings stored by this or older firmware. Fields are only ever
+/// added at the end of `Settings`, so older layouts are prefixes of newer
+/// ones, and fields missing from `payload` keep their defaults. Fields newer
+/// firmware added after these are ignored.
+/// Returns `None` if `payload` is empty or ends partway through a field.
+fn decode_settings(payload: &[u8]) -> Option<Settings> {
+    if payload.is_empty() {
+        return None;
+    }
+    let mut settings = Settings::default();
+    let mut rest = payload;
+    // NOTE: Each read is skipped once the payload runs out.
+    let _ = take_field(&mut rest, &mut settings.pump_max_rpm)?
+        && take_field(&mut rest, &mut settings.fan_max_rpm)?
+        && take_field(&mut rest, &mut settings.telemetry_rate_hz)?
+        && take_field(&mut rest, &mut settings.core_loop_period_ms)?
+        && take_field(&mut rest, &mut settings.pump_sense_calibration)?
+        && take_field(&mut rest, &mut settings.fan_sense_calibration)?
+        && take_field(&mut rest, &mut settings.flow_pulses_per_liter)?
+        && take_field(&mut rest, &mut settings.pwm_frequency_hz)?
+        && take_field(&mut rest, &mut settings.failsafe_duty_percent)?;
+    Some(settings)
+}
+
+/// Read the next field of a settings payload into `field`, advancing `rest`
+/// past it. Returns `Some(false)` without changing `field` if `rest` is
+/// empty, and `None` if the field can't be read.
+fn take_field<T: DeserializeOwned>(rest: &mut &[u8], field: &mut T) -> Option<bool> {
+    if rest.is_empty() {
+        return Some(false);
+    }
+    let (value, remaining) = postcard::take_from_bytes(rest).ok()?;
+    *field = value;
+    *rest = remaining;
+    Some(true)
+}
+
+/// Stores settings alternately in two banks. Each write goes to the bank not
+/// holding the newest settings with the next version, so if it is cut short
+/// the previous settings are still loaded from the other bank.
+pub struct DualBankStorage<Banks: SettingsBanks> {
+    banks: Banks,
+
+    /// The bank and version of the newest valid settings.
+    newest: Option<(u8, u32)>,
+}
+
+impl<Banks: SettingsBanks> DualBankStorage<Banks> {
+    pub fn new(banks: Banks) -> Self {
+        Self {
+            banks,
+            newest: None,
+        }
+    }
+}
+
+impl<Banks: SettingsBanks> SettingsStorage for DualBankStorage<Banks> {
+    fn load(&mut self) -> (Settings, SettingsOrigin) {
+        let mut corrupt_banks = 0;
+        let mut newest: Option<(u8, u32, Settings)> = None;
+        for bank in 0..SETTINGS_BANK_COUNT {
+            match decode_bank(&self.banks.read_bank(bank)) {
+                BankContents::Erased => {}
+                BankContents::Corrupt => corrupt_banks += 1,
+                BankContents::Valid { version, settings } => {
+                    let is_newer = match newest {
+                        Some((_, newest_version, _)) => version > newest_version,
+                        None => true,
+                    };
+                    if is_newer {
+                        newest = Some((bank, version, settings));
+                    }
+                }
+            }
+        }
+
+        self.newest = newest.map(|(bank, version, _)| (bank, version));
+        match newest {
+            Some((bank, version, settings)) => (
+                settings,
+                SettingsOrigin::Bank {
+                    bank,
+                    version,
+                    corrupt_banks,
+                },
+            ),
+            None => (
+                Settings::default(),
+                SettingsOrigin::Defaults { corrupt_banks },
+            ),
+        }
+    }
+
+    fn store(&mut self, settings: &Settings) -> Result<(), SettingsStorageError> {
+        let (bank, version) = match self.newest {
+            Some((bank, version)) => ((bank + 1) % SETTINGS_BANK_COUNT, version.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let image = encode_bank(settings, version)?;
+        self.banks.write_bank(bank, &image)?;
+
+        if decode_bank(&self.banks.read_bank(bank))
+            != (BankContents::Valid {
+                version,
+                settings: *settings,
+            })
+        {
+            return Err(SettingsStorageError::Write);
+        }
+        self.newest = Some((bank, version));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::MockBanks;
+
+    #[test]
+    fn test_apply() {
+        let mut settings = Settings::default();
+
+        assert!(settings.apply(Parameter::PumpMaxRpm(1700)));
+        assert_eq!(settings.pump_max_rpm, 1700);
+        assert_eq!(settings.fan_max_rpm, DEFAULT_FAN_MAX_RPM);
+
+        assert!(!settings.apply(Parameter::PumpMaxRpm(1700)));
+
+        assert!(settings.apply(Parameter::FanMaxRpm(1500)));
+        assert_eq!(settings.fan_max_rpm, 1500);
+
+        assert!(settings.apply(Parameter::TelemetryRateHz(10)));
+        assert_eq!(settings.telemetry_rate_hz, 10);
+
+        assert!(settings.apply(Parameter::TelemetryRateHz(0)));
+        assert_eq!(settings.telemetry_rate_hz, MIN_TELEMETRY_RATE_HZ);
+
+        assert!(settings.apply(Parameter::TelemetryRateHz(100)));
+        assert_eq!(settings.telemetry_rate_hz, MAX_TELEMETRY_RATE_HZ);
+
+        assert!(settings.apply(Parameter::CoreLoopPeriodMs(25)));
+        assert_eq!(settings.core_loop_period_ms, 25);
+
+        assert!(settings.apply(Parameter::CoreLoopPeriodMs(0)));
+        assert_eq!(settings.core_loop_period_ms, MIN_CORE_LOOP_PERIOD_MS);
+
+        assert!(settings.apply(Parameter::CoreLoopPeriodMs(200)));
+        assert_eq!(settings.core_loop_period_ms, MAX_CORE_LOOP_PERIOD_MS);
+    }
+
+    #[test]
+    fn test_get_matches_apply() {
+        let mut settings = Settings::default();
+        for parameter in [
+            Parameter::PumpMaxRpm(1700),
+            Parameter::FanMaxRpm(1500),
+            Parameter::TelemetryRateHz(10),
+            Parameter::CoreLoopPeriodMs(25),
+            Parameter::FlowPulsesPerLiter(450),
+        ] {
+            settings.apply(parameter);
+            assert_eq!(settings.get(parameter.kind()), parameter);
+        }
+    }
+
+    fn custom_settings() -> Settings {
+        Settings {
+            pump_max_rpm: 1234,
+            fan_max_rpm: 987,
+            telemetry_rate_hz: 5,
+            core_loop_period_ms: 20,
+            pump_sense_calibration: SenseCalibration {
+                offset: -100,
+                gain: 12_000,
+            },
+            fan_sense_calibration: SenseCalibration::IDENTITY,
+            flow_pulses_per_liter: 450,
+            pwm_frequency_hz: 25_000,
+            failsafe_duty_percent: 80,
+        }
+    }
+
+    /// Build a valid bank around a payload, as if written by older firmware.
+    fn bank_with_payload(payload: &[u8]) -> [u8; SETTINGS_BANK_SIZE] {
+        let mut bank = [0xFFu8; SETTINGS_BANK_SIZE];
+        bank[0..2].copy_from_slice(&BANK_MAGIC.to_le_bytes());
+        bank[2..6].copy_from_slice(&3u32.to_le_bytes());
+        bank[6] = payload.len() as u8;
+        let crc_start = BANK_HEADER_SIZE + payload.len();
+        bank[BANK_HEADER_SIZE..crc_start].copy_from_slice(payload);
+        let crc = crc16(&bank[..crc_start]);
+        bank[crc_start..crc_start + BANK_CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+        bank
+    }
+
+    #[test]
+    fn test_bank_round_trip() {
+        let settings = custom_settings();
+        let bank = encode_bank(&settings, 7).expect("Failed to encode bank.");
+        assert_eq!(
+            decode_bank(&bank),
+            BankContents::Valid {
+                version: 7,
+                settings
+            }
+        );
+        assert_eq!(
+            decode_bank(&[0xFF; SETTINGS_BANK_SIZE]),
+            BankContents::Erased
+        );
+
+        for index in [0, 3, BANK_HEADER_SIZE + 1] {
+            let mut corrupt = bank;
+            corrupt[index] ^= 0x10;
+            assert_eq!(decode_bank(&corrupt), BankContents::Corrupt);
+        }
+    }
+
+    #[test]
+    fn test_bank_without_calibration_loads() {
+        // NOTE: Written by firmware from before the sense calibration.
+        #[derive(Serialize)]
+        struct Stored(u16, u16, u8, u8);
+        let payload: heapless::Vec<u8, 16> =
+            postcard::to_vec(&Stored(1234, 987, 5, 20)).expect("Failed to serialize settings.");
+
+        assert_eq!(
+            decode_bank(&bank_with_payload(&payload)),
+            BankContents::Valid {
+                version: 3,
+                settings: Settings {
+                    pump_sense_calibration: SenseCalibration::IDENTITY,
+                    flow_pulses_per_liter: 0,
+                    pwm_frequency_hz: DEFAULT_PWM_FREQUENCY_HZ,
+                    failsafe_duty_percent: DEFAULT_FAILSAFE_DUTY_PERCENT,
+                    ..custom_settings()
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_bank_without_flow_meter_loads() {
+        // NOTE: Written by firmware from before the flow meter.
+        let settings = custom_settings();
+        let payload: heapless::Vec<u8, 16> = postcard::to_vec(&(
+            settings.pump_max_rpm,
+            settings.fan_max_rpm,
+            settings.telemetry_rate_hz,
+            settings.core_loop_period_ms,
+            settings.pump_sense_calibration,
+            settings.fan_sense_calibration,
+        ))
+        .expect("Failed to serialize settings.");
+
+        assert_eq!(
+            decode_bank(&bank_with_payload(&payload)),
+            BankContents::Valid {
+                version: 3,
+                settings: Settings {
+                    flow_pulses_per_liter: 0,
+                    pwm_frequency_hz: DEFAULT_PWM_FREQUENCY_HZ,
+                    failsafe_duty_percent: DEFAULT_FAILSAFE_DUTY_PERCENT,
+                    ..settings
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_bank_without_firmware_config_loads() {
+        // NOTE: Written by firmware from before the PWM frequency and
+        //       fail-safe duty.
+        let settings = custom_settings();
+        let payload: heapless::Vec<u8, 32> = postcard::to_vec(&(
+            settings.pump_max_rpm,
+            settings.fan_max_rpm,
+            settings.telemetry_rate_hz,
+            settings.core_loop_period_ms,
+            settings.pump_sense_calibration,
+            settings.fan_sense_calibration,
+            settings.flow_pulses_per_liter,
+        ))
+        .expect("Failed to serialize settings.");
+
+        assert_eq!(
+            decode_bank(&bank_with_payload(&payload)),
+            BankContents::Valid {
+                version: 3,
+                settings: Settings {
+                    pwm_frequency_hz: DEFAULT_PWM_FREQUENCY_HZ,
+                    failsafe_duty_percent: DEFAULT_FAILSAFE_DUTY_PERCENT,
+                    ..settings
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_bank_with_learned_speeds_only_loads() {
+        // NOTE: Written by firmware from before the telemetry rate, and then
+        //       before the core loop period.
+        let settings = custom_settings();
+        let payload: heapless::Vec<u8, 16> =
+            postcard::to_vec(&(settings.pump_max_rpm, settings.fan_max_rpm))
+                .expect("Failed to serialize settings.");
+        assert_eq!(
+            decode_bank(&bank_with_payload(&payload)),
+            BankContents::Valid {
+                version: 3,
+                settings: Settings {
+                    pump_max_rpm: settings.pump_max_rpm,
+                    fan_max_rpm: settings.fan_max_rpm,
+                    ..Settings::default()
+                }
+            }
+        );
+
+        let payload: heapless::Vec<u8, 16> = postcard::to_vec(&(
+            settings.pump_max_rpm,
+            settings.fan_max_rpm,
+            settings.telemetry_rate_hz,
+        ))
+        .expect("Failed to serialize settings.");
+        assert_eq!(
+            decode_bank(&bank_with_payload(&payload)),
+            BankContents::Valid {
+                version: 3,
+                settings: Settings {
+                    pump_max_rpm: settings.pump_max_rpm,
+                    fan_max_rpm: settings.fan_max_rpm,
+                    telemetry_rate_hz: settings.telemetry_rate_hz,
+                    ..Settings::default()
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_truncated_field_is_corrupt() {
+        let payload: heapless::Vec<u8, 64> =
+            postcard::to_vec(&custom_settings()).expect("Failed to serialize settings.");
+        // NOTE: The PWM frequency is a multi-byte varint, so this cuts it.
+        let truncated = &payload[..payload.len() - 2];
+        assert_eq!(
+            decode_bank(&bank_with_payload(truncated)),
+            BankContents::Corrupt
+        );
+        assert_eq!(decode_bank(&bank_with_payload(&[])), BankContents::Corrupt);
+    }
+
+    #[test]
+    fn test_configure_clamps() {
+        let mut settings = Settings::default();
+        let config = FirmwareConfig {
+            pwm_frequency_hz: 25_000,
+            telemetry_rate_hz: 5,
+            pump_max_rpm: 1234,
+            fan_max_rpm: 987,
+            failsafe_duty_percent: 80,
+        };
+        assert!(settings.configure(config));
+        assert_eq!(settings.config(), config);
+        assert!(!settings.configure(config));
+
+        assert!(settings.configure(FirmwareConfig {
+            pwm_frequency_hz: 10,
+            telemetry_rate_hz: 100,
+            failsafe_duty_percent: 0,
+            ..config
+        }));
+        assert_eq!(
+            settings.config(),
+            FirmwareConfig {
+                pwm_frequency_hz: MIN_PWM_FREQUENCY_HZ,
+                telemetry_rate_hz: MAX_TELEMETRY_RATE_HZ,
+                failsafe_duty_percent: MIN_FAILSAFE_DUTY_PERCENT,
+                ..config
+            }
+        );
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let mut settings = Settings::default();
+        let trimmed = SenseCalibration {
+            offset: 50,
+            gain: 9_500,
+        };
+        assert!(settings.calibrate(trimmed, SenseCalibration::IDENTITY));
+        assert_eq!(settings.pump_sense_calibration, trimmed);
+        assert!(!settings.calibrate(trimmed, SenseCalibration::IDENTITY));
+
+        let invalid = SenseCalibration { offset: 0, gain: 0 };
+        assert!(!settings.calibrate(SenseCalibration::IDENTITY, invalid));
+        assert_eq!(settings.pump_sense_calibration, trimmed);
+    }
+
+    #[test]
+    fn test_dual_bank_alternates() {
+        let mut storage = DualBankStorage::new(MockBanks::default());
+        assert_eq!(
+            storage.load(),
+            (
+                Settings::default(),
+                SettingsOrigin::Defaults { corrupt_banks: 0 }
+            )
+        );
+
+        let mut settings = custom_settings();
+        storage.store(&settings).expect("Failed to store settings.");
+        settings.telemetry_rate_hz = 10;
+        storage.store(&settings).expect("Failed to store settings.");
+
+        let mut storage = DualBankStorage::new(storage.banks);
+        assert_eq!(
+            storage.load(),
+            (
+                settings,
+                SettingsOrigin::Bank {
+                    bank: 1,
+                    version: 1,
+                    corrupt_banks: 0
+                }
+            )
+        );
+        storage.store(&settings).expect("Failed to store settings.");
+        assert_eq!(
+            storage.load().1,
+            SettingsOrigin::Bank {
+                bank: 0,
+                version: 2,
+                corrupt_banks: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_dual_bank_rolls_back_corrupt_bank() {
+        let mut storage = DualBankStorage::new(MockBanks::default());
+        let older = custom_settings();
+        storage.store(&older).expect("Failed to store settings.");
+        storage
+            .store(&Settings {
+                telemetry_rate_hz: 10,
+                ..older
+            })
+            .expect("Failed to store settings.");
+
+        // NOTE: Simulate a reset partway through writing the newer bank.
+        storage.banks.banks[1][8] ^= 0xFF;
+        assert_eq!(
+            storage.load(),
+            (
+                older,
+                SettingsOrigin::Bank {
+                    bank: 0,
+                    version: 0,
+                    corrupt_banks: 1
+                }
+            )
+        );
+
+        // NOTE: The corrupt bank is the one overwritten next.
+        storage.store(&older).expect("Failed to store settings.");
+        assert_eq!(
+            storage.load().1,
+            SettingsOrigin::Bank {
+                bank: 1,
+                version: 1,
+                corrupt_banks: 0
+            }
+        );
+
+        storage.banks.banks[0][0] = 0;
+        storage.banks.banks[1][0] = 0;
+        assert_eq!(
+            storage.load(),
+            (
+                Settings::default(),
+                SettingsOrigin::Defaults { corrupt_banks: 2 }
+            )
+        );
+    }
+
+    #[test]
+    fn test_dual_bank_verifies_writes() {
+        let mut storage = DualBankStorage::new(MockBanks {
+            fail_writes: true,
+            ..Default::default()
+        });
+        assert!(storage.store(&custom_settings()).is_err());
+        assert_eq!(
+            storage.load().1,
+            SettingsOrigin::Defaults { corrupt_banks: 1 }
+        );
+    }
+
+    #[test]
+    fn test_serialization() {
+        let settings = custom_settings();
+        let buffer: heapless::Vec<u8, 32> =
+            postcard::to_vec(&settings).expect("Failed to serialize settings.");
+        let settings_deser =
+            postcard::from_bytes::<Settings>(&buffer).expect("Failed to deserialize settings.");
+        assert_eq!(settings, settings_deser);
+    }
+}
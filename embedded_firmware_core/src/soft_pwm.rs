@@ -0,0 +1,165 @@
+use embedded_hal::{digital::v2::OutputPin, PwmPin};
+
+/// Drives a low frequency PWM output on any GPIO pin, for outputs on pins
+/// without a TCC channel (e.g. an LED strip or an auxiliary pump).
+/// `tick` must be called at a fixed rate, typically from a timer interrupt.
+/// The output frequency is the tick rate divided by `period_ticks`.
+pub struct SoftPwm<P: OutputPin> {
+    pin: P,
+
+    /// Number of ticks in a single PWM period. Also the maximum duty.
+    period_ticks: u16,
+
+    /// Number of ticks per period the pin is held high.
+    duty_ticks: u16,
+
+    /// Position within the current period.
+    counter: u16,
+
+    enabled: bool,
+}
+
+impl<P: OutputPin> SoftPwm<P> {
+    /// Construct a disabled soft PWM output at 0% duty.
+    /// NOTE: A zero period is treated as a single tick period.
+    pub fn new(mut pin: P, period_ticks: u16) -> Self {
+        // NOTE: Ignore errors
+        let _ = pin.set_low();
+        Self {
+            pin,
+            period_ticks: period_ticks.max(1),
+            duty_ticks: 0,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// Advance the output by a single tick.
+    /// This should be called from a timer interrupt.
+    pub fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        // NOTE: Ignore errors
+        let _ = if self.counter < self.duty_ticks {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        };
+
+        self.counter += 1;
+        if self.counter >= self.period_ticks {
+            self.counter = 0;
+        }
+    }
+
+    /// Release the underlying pin.
+    pub fn free(self) -> P {
+        self.pin
+    }
+}
+
+impl<P: OutputPin> PwmPin for SoftPwm<P> {
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        self.enabled = false;
+        // NOTE: Ignore errors
+        let _ = self.pin.set_low();
+    }
+
+    fn enable(&mut self) {
+        self.enabled = true;
+        self.counter = 0;
+    }
+
+    fn get_duty(&self) -> u16 {
+        self.duty_ticks
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        self.period_ticks
+    }
+
+    /// Set the duty in ticks. Values above the maximum duty are clamped.
+    /// Takes effect on the next tick.
+    fn set_duty(&mut self, duty: u16) {
+        self.duty_ticks = duty.min(self.period_ticks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use super::*;
+
+    /// Records the state of the pin.
+    struct TestPin {
+        is_high: bool,
+    }
+
+    impl OutputPin for TestPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.is_high = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.is_high = true;
+            Ok(())
+        }
+    }
+
+    /// Tick through a single period and count how many ticks the pin was high.
+    fn count_high_ticks(pwm: &mut SoftPwm<TestPin>) -> u16 {
+        let mut high_ticks = 0;
+        for _ in 0..pwm.get_max_duty() {
+            pwm.tick();
+            if pwm.pin.is_high {
+                high_ticks += 1;
+            }
+        }
+        high_ticks
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let mut pwm = SoftPwm::new(TestPin { is_high: true }, 10);
+        assert!(!pwm.pin.is_high);
+        pwm.enable();
+
+        assert_eq!(count_high_ticks(&mut pwm), 0);
+
+        pwm.set_duty(3);
+        assert_eq!(count_high_ticks(&mut pwm), 3);
+        assert_eq!(count_high_ticks(&mut pwm), 3);
+
+        pwm.set_duty(10);
+        assert_eq!(count_high_ticks(&mut pwm), 10);
+    }
+
+    #[test]
+    fn test_duty_is_clamped() {
+        let mut pwm = SoftPwm::new(TestPin { is_high: false }, 10);
+        pwm.set_duty(25);
+        assert_eq!(pwm.get_duty(), 10);
+    }
+
+    #[test]
+    fn test_disable_holds_pin_low() {
+        let mut pwm = SoftPwm::new(TestPin { is_high: false }, 10);
+        pwm.set_duty(10);
+        pwm.enable();
+        pwm.tick();
+        assert!(pwm.pin.is_high);
+
+        pwm.disable();
+        assert!(!pwm.pin.is_high);
+        pwm.tick();
+        assert!(!pwm.pin.is_high);
+    }
+}
@@ -0,0 +1,110 @@
+/// Offset from degrees Celsius to kelvin.
+const KELVIN_OFFSET: f32 = 273.15f32;
+
+/// Calibration for an NTC thermistor on the low side of a divider, with a
+/// fixed series resistor up to the ADC reference. Converts readings with the
+/// thermistor's beta equation, which is accurate to about a degree over the
+/// range coolant sees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermistorCalibration {
+    /// Resistance of the series resistor, in ohms.
+    series_ohms: f32,
+
+    /// Resistance of the thermistor at `nominal_temperature`, in ohms.
+    nominal_ohms: f32,
+
+    /// Temperature the nominal resistance is given at, in kelvin.
+    nominal_kelvin: f32,
+
+    /// The thermistor's beta coefficient, in kelvin.
+    beta: f32,
+}
+
+impl ThermistorCalibration {
+    /// Construct a calibration from the series resistor and the thermistor's
+    /// datasheet values, with `nominal_temperature` in degrees Celsius.
+    /// Returns `None` if any of them can't be used.
+    pub fn new(
+        series_ohms: f32,
+        nominal_ohms: f32,
+        nominal_temperature: f32,
+        beta: f32,
+    ) -> Option<Self> {
+        let nominal_kelvin = nominal_temperature + KELVIN_OFFSET;
+        // NOTE: Each of these is divided by when converting.
+        if [series_ohms, nominal_ohms, nominal_kelvin, beta]
+            .iter()
+            .any(|value| !value.is_finite() || *value <= 0f32)
+        {
+            return None;
+        }
+        Some(Self {
+            series_ohms,
+            nominal_ohms,
+            nominal_kelvin,
+            beta,
+        })
+    }
+
+    /// Convert a 0 to 1 reading of the divider's midpoint, taken against the
+    /// divider's own supply, into degrees Celsius. Returns `None` for a
+    /// reading at either end of the scale, as from an open or shorted
+    /// thermistor.
+    pub fn temperature(&self, normalized: f32) -> Option<f32> {
+        if !(normalized > 0f32 && normalized < 1f32) {
+            return None;
+        }
+        let ohms = self.series_ohms * normalized / (1f32 - normalized);
+        let inverse_kelvin =
+            1f32 / self.nominal_kelvin + libm::logf(ohms / self.nominal_ohms) / self.beta;
+        Some(1f32 / inverse_kelvin - KELVIN_OFFSET)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration() -> ThermistorCalibration {
+        ThermistorCalibration::new(10_000f32, 10_000f32, 25f32, 3950f32)
+            .expect("Failed to get calibration.")
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(ThermistorCalibration::new(10_000f32, 10_000f32, 25f32, 0f32).is_none());
+        assert!(ThermistorCalibration::new(0f32, 10_000f32, 25f32, 3950f32).is_none());
+        assert!(ThermistorCalibration::new(10_000f32, f32::NAN, 25f32, 3950f32).is_none());
+        assert!(ThermistorCalibration::new(10_000f32, 10_000f32, -300f32, 3950f32).is_none());
+    }
+
+    #[test]
+    fn test_temperature() {
+        let calibration = calibration();
+
+        // NOTE: Matching resistors put the nominal temperature at mid scale.
+        let nominal = calibration
+            .temperature(0.5f32)
+            .expect("Failed to get temperature.");
+        assert!((nominal - 25f32).abs() < 0.01f32);
+
+        // NOTE: A 10k beta 3950 thermistor is about 3.6k at 50C.
+        let warm = calibration
+            .temperature(3588f32 / 13_588f32)
+            .expect("Failed to get temperature.");
+        assert!((warm - 50f32).abs() < 0.1f32);
+
+        let cold = calibration
+            .temperature(0.75f32)
+            .expect("Failed to get temperature.");
+        assert!(cold < 25f32);
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let calibration = calibration();
+        assert_eq!(calibration.temperature(0f32), None);
+        assert_eq!(calibration.temperature(1f32), None);
+        assert_eq!(calibration.temperature(f32::NAN), None);
+    }
+}
@@ -0,0 +1,147 @@
+/// Consistent samples needed before a change of the valve sense pins is
+/// accepted.
+pub const VALVE_SENSE_DEBOUNCE_SAMPLES: u8 = 3;
+
+/// How long, in milliseconds, a change of the valve sense pins must be held
+/// before it is accepted. Longer than limit switches typically bounce for.
+pub const VALVE_SENSE_DEBOUNCE_MS: u16 = 30;
+
+/// Debounces the pair of valve sense pins, which are mechanical limit
+/// switches and bounce as the valve reaches either end of its travel.
+/// A new pin pair is only accepted once it has been read `min_samples` times
+/// in a row over at least `window_ms`.
+pub struct ValveSenseDebouncer {
+    min_samples: u8,
+    window_ms: u16,
+
+    /// The last accepted pin pair, if any has been accepted yet.
+    stable: Option<(bool, bool)>,
+
+    /// The pin pair differing from `stable`, how many times in a row it has
+    /// been read and for how long.
+    candidate: Option<((bool, bool), u8, u16)>,
+}
+
+impl Default for ValveSenseDebouncer {
+    fn default() -> Self {
+        Self::new(VALVE_SENSE_DEBOUNCE_SAMPLES, VALVE_SENSE_DEBOUNCE_MS)
+    }
+}
+
+impl ValveSenseDebouncer {
+    /// NOTE: Zero samples is treated as a single sample.
+    pub fn new(min_samples: u8, window_ms: u16) -> Self {
+        Self {
+            min_samples: min_samples.max(1),
+            window_ms,
+            stable: None,
+            candidate: None,
+        }
+    }
+
+    /// Feed a sample of the pins, read `period_ms` after the previous one.
+    /// Returns the debounced pin pair.
+    pub fn sample(&mut self, pins: (bool, bool), period_ms: u16) -> Option<(bool, bool)> {
+        if self.stable == Some(pins) {
+            self.candidate = None;
+            return self.stable;
+        }
+        let (samples, held_ms) = match self.candidate {
+            Some((candidate, samples, held_ms)) if candidate == pins => {
+                (samples.saturating_add(1), held_ms.saturating_add(period_ms))
+            }
+            _ => (1, period_ms),
+        };
+        if samples >= self.min_samples && held_ms >= self.window_ms {
+            self.stable = Some(pins);
+            self.candidate = None;
+        } else {
+            self.candidate = Some((pins, samples, held_ms));
+        }
+        self.stable
+    }
+
+    /// The debounced pin pair, or `None` until the pins have first settled.
+    pub fn state(&self) -> Option<(bool, bool)> {
+        self.stable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPEN: (bool, bool) = (true, false);
+    const CLOSED: (bool, bool) = (false, true);
+    const TRAVELLING: (bool, bool) = (false, false);
+
+    /// Feed `pins` in order, each `period_ms` apart, and collect the debounced
+    /// pin pair after each.
+    fn run(
+        debouncer: &mut ValveSenseDebouncer,
+        pins: &[(bool, bool)],
+        period_ms: u16,
+    ) -> std::vec::Vec<Option<(bool, bool)>> {
+        pins.iter()
+            .map(|&pins| debouncer.sample(pins, period_ms))
+            .collect()
+    }
+
+    #[test]
+    fn test_settles_after_consistent_samples() {
+        let mut debouncer = ValveSenseDebouncer::new(3, 30);
+        assert_eq!(debouncer.state(), None);
+        assert_eq!(
+            run(&mut debouncer, &[OPEN, OPEN, OPEN, OPEN], 10),
+            [None, None, Some(OPEN), Some(OPEN)]
+        );
+    }
+
+    #[test]
+    fn test_bounce_is_filtered() {
+        let mut debouncer = ValveSenseDebouncer::new(3, 30);
+        run(&mut debouncer, &[CLOSED; 3], 10);
+
+        // NOTE: The closed switch chatters as the valve leaves it.
+        let bounce = [TRAVELLING, CLOSED, TRAVELLING, TRAVELLING, CLOSED];
+        assert_eq!(run(&mut debouncer, &bounce, 10), [Some(CLOSED); 5]);
+
+        let arrive = [OPEN, TRAVELLING, OPEN, OPEN, OPEN];
+        assert_eq!(
+            run(&mut debouncer, &arrive, 10),
+            [
+                Some(CLOSED),
+                Some(CLOSED),
+                Some(CLOSED),
+                Some(CLOSED),
+                Some(OPEN)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_window_needs_enough_time() {
+        // NOTE: Enough samples, but sampled too quickly to cover the window.
+        let mut debouncer = ValveSenseDebouncer::new(3, 30);
+        assert_eq!(
+            run(&mut debouncer, &[OPEN; 6], 5),
+            [None, None, None, None, None, Some(OPEN)]
+        );
+
+        // NOTE: Enough time, but too few samples.
+        let mut debouncer = ValveSenseDebouncer::new(3, 30);
+        assert_eq!(
+            run(&mut debouncer, &[CLOSED; 3], 50),
+            [None, None, Some(CLOSED)]
+        );
+    }
+
+    #[test]
+    fn test_single_sample_accepts_immediately() {
+        let mut debouncer = ValveSenseDebouncer::new(0, 0);
+        assert_eq!(
+            run(&mut debouncer, &[OPEN, CLOSED, OPEN], 10),
+            [Some(OPEN), Some(CLOSED), Some(OPEN)]
+        );
+    }
+}
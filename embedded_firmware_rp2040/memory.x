@@ -0,0 +1,14 @@
+MEMORY
+{
+  BOOT2 (rx) : ORIGIN = 0x10000000, LENGTH = 0x100
+  FLASH (rx) : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+  RAM (rwx) : ORIGIN = 0x20000000, LENGTH = 256K
+}
+
+SECTIONS {
+  /* The second stage bootloader must be the first 256 bytes of flash. */
+  .boot2 ORIGIN(BOOT2) :
+  {
+    KEEP(*(.boot2));
+  } > BOOT2
+} INSERT BEFORE .text;
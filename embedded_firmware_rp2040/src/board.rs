@@ -0,0 +1,98 @@
+//! Pin assignments for a Raspberry Pi Pico wired to the controller
+//! peripherals.
+//!
+//! | Signal          | Pin    | Pico pin |
+//! | --------------- | ------ | -------- |
+//! | Pump PWM        | GPIO0  | 1        |
+//! | Fan PWM         | GPIO1  | 2        |
+//! | Valve sense 1   | GPIO2  | 4        |
+//! | Valve sense 2   | GPIO3  | 5        |
+//! | Valve control 1 | GPIO4  | 6        |
+//! | Valve control 2 | GPIO5  | 7        |
+//! | Flow pulse      | GPIO6  | 9        |
+//! | Pump sense      | GPIO26 | 31       |
+//! | Fan sense       | GPIO27 | 32       |
+//! | Pump current    | GPIO28 | 34       |
+//!
+//! NOTE: GPIO29 is tied to a VSYS/3 divider on the Pico and is used to
+//!       measure the supply voltage. With no fourth ADC pin there is no fan
+//!       current sense or coolant thermistor.
+
+use common::packet::Capabilities;
+use rp2040_hal::{
+    adc::AdcPin,
+    gpio::{
+        bank0::{Gpio0, Gpio1, Gpio2, Gpio26, Gpio27, Gpio28, Gpio29, Gpio3, Gpio4, Gpio5, Gpio6},
+        FunctionNull, FunctionSio, Pin, Pins, PullDown, PullUp, SioInput, SioOutput,
+    },
+    pac::{IO_BANK0, PADS_BANK0, RESETS},
+    sio::SioGpioBank0,
+};
+
+/// Reported to the host along with the firmware version.
+pub const BOARD_NAME: &str = "rp2040_pico";
+
+/// What the Pico wiring above is wired for.
+pub const CAPABILITIES: Capabilities = Capabilities {
+    fan_channels: 1,
+    thermistors: 0,
+    valve_driver: true,
+    valve_sense: true,
+};
+
+pub type PumpSensePin = AdcPin<Pin<Gpio26, FunctionNull, PullDown>>;
+pub type FanSensePin = AdcPin<Pin<Gpio27, FunctionNull, PullDown>>;
+pub type PumpCurrentPin = AdcPin<Pin<Gpio28, FunctionNull, PullDown>>;
+pub type SupplySensePin = AdcPin<Pin<Gpio29, FunctionNull, PullDown>>;
+pub type ValveSense1Pin = Pin<Gpio2, FunctionSio<SioInput>, PullDown>;
+pub type ValveSense2Pin = Pin<Gpio3, FunctionSio<SioInput>, PullDown>;
+pub type ValveControl1Pin = Pin<Gpio4, FunctionSio<SioOutput>, PullDown>;
+pub type ValveControl2Pin = Pin<Gpio5, FunctionSio<SioOutput>, PullDown>;
+pub type FlowPulsePin = Pin<Gpio6, FunctionSio<SioInput>, PullUp>;
+
+/// Every pin the application uses, configured for its function.
+/// NOTE: The PWM pins are still in their reset state. They are routed to the
+///       PWM slice with `output_to` when the slice is configured.
+pub struct BoardPins {
+    /// PWM slice 0 channel A.
+    pub pump_pwm: Pin<Gpio0, FunctionNull, PullDown>,
+
+    /// PWM slice 0 channel B.
+    pub fan_pwm: Pin<Gpio1, FunctionNull, PullDown>,
+
+    pub pump_sense: PumpSensePin,
+    pub fan_sense: FanSensePin,
+    pub pump_current: PumpCurrentPin,
+    pub supply_sense: SupplySensePin,
+
+    pub valve_sense_1: ValveSense1Pin,
+    pub valve_sense_2: ValveSense2Pin,
+    pub valve_control_1: ValveControl1Pin,
+    pub valve_control_2: ValveControl2Pin,
+
+    /// The open collector output of a hall effect flow meter.
+    pub flow_pulse: FlowPulsePin,
+}
+
+/// Take and configure every pin the application uses.
+pub fn take_pins(
+    io_bank0: IO_BANK0,
+    pads_bank0: PADS_BANK0,
+    sio_gpio_bank0: SioGpioBank0,
+    resets: &mut RESETS,
+) -> BoardPins {
+    let pins = Pins::new(io_bank0, pads_bank0, sio_gpio_bank0, resets);
+    BoardPins {
+        pump_pwm: pins.gpio0,
+        fan_pwm: pins.gpio1,
+        pump_sense: AdcPin::new(pins.gpio26),
+        fan_sense: AdcPin::new(pins.gpio27),
+        pump_current: AdcPin::new(pins.gpio28),
+        supply_sense: AdcPin::new(pins.gpio29),
+        valve_sense_1: pins.gpio2.into_pull_down_input(),
+        valve_sense_2: pins.gpio3.into_pull_down_input(),
+        valve_control_1: pins.gpio4.into_push_pull_output(),
+        valve_control_2: pins.gpio5.into_push_pull_output(),
+        flow_pulse: pins.gpio6.into_pull_up_input(),
+    }
+}
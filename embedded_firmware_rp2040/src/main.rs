@@ -0,0 +1,197 @@
+#![no_std]
+#![no_main]
+
+use common::packet::FirmwareInfoPacket;
+use cortex_m::delay::Delay;
+use cortex_m::peripheral::NVIC;
+use embedded_firmware_core::application::{Application, ResetRequest};
+use embedded_firmware_core::comms::Comms;
+use embedded_firmware_core::control::Control;
+use embedded_firmware_core::packet_io::UsbPacketIo;
+use embedded_firmware_core::reset_cause::from_rp2040_registers;
+use embedded_firmware_core::sensing::Sensing;
+use hal::clocks::{init_clocks_and_plls, Clock};
+use hal::pac::{interrupt, CorePeripherals, Peripherals};
+use hal::pwm::{Pwm0, Slices};
+use hal::usb::UsbBus;
+use hal::{Adc, Sio, Watchdog};
+use panic_halt as _;
+use rp2040_hal as hal;
+
+use usb_device::bus::UsbBusAllocator;
+
+mod board;
+mod prandtladc;
+mod ramsettings;
+mod slicepwm;
+use prandtladc::PrandtlPumpFanAdc;
+use ramsettings::RamSettingsStorage;
+use slicepwm::{SliceChannel, SlicePwm};
+
+/// The second stage bootloader, which sets up the external flash.
+#[link_section = ".boot2"]
+#[used]
+pub static BOOT2: [u8; 256] = rp2040_boot2::BOOT_LOADER_W25Q080;
+
+/// The Pico's crystal frequency.
+const XTAL_FREQ_HZ: u32 = 12_000_000;
+
+/// The system clock the PWM slices count.
+const SYSTEM_CLOCK_HZ: u32 = 125_000_000;
+
+/// Divides the 125MHz system clock so the PWM runs at 1kHz, like the SAMD21
+/// build.
+const PWM_DIVIDER: u8 = 2;
+const PWM_TOP: u16 = 62_499;
+
+/// The counter wrap value which runs the PWM at `frequency_hz`. Fits for
+/// every frequency from `MIN_PWM_FREQUENCY_HZ` up.
+fn pwm_top(frequency_hz: u16) -> u16 {
+    let counts = SYSTEM_CLOCK_HZ / PWM_DIVIDER as u32 / frequency_hz.max(1) as u32;
+    counts.saturating_sub(1).min(u16::MAX as u32) as u16
+}
+
+static mut BUS_ALLOCATOR: Option<UsbBusAllocator<UsbBus>> = None;
+static mut APPLICATION: Option<
+    Application<
+        UsbPacketIo<'static, UsbBus>,
+        Delay,
+        SlicePwm<Pwm0>,
+        PrandtlPumpFanAdc,
+        board::ValveSense1Pin,
+        board::ValveSense2Pin,
+        board::ValveControl1Pin,
+        board::ValveControl2Pin,
+        RamSettingsStorage,
+    >,
+> = None;
+
+fn initialize() {
+    let mut peripherals = Peripherals::take().unwrap();
+    let core = CorePeripherals::take().unwrap();
+    // NOTE: Read before the watchdog is set up, which may clear its reason.
+    let reset_cause = from_rp2040_registers(
+        peripherals.VREG_AND_CHIP_RESET.chip_reset.read().bits(),
+        peripherals.WATCHDOG.reason.read().bits(),
+    );
+    let mut watchdog = Watchdog::new(peripherals.WATCHDOG);
+    let clocks = init_clocks_and_plls(
+        XTAL_FREQ_HZ,
+        peripherals.XOSC,
+        peripherals.CLOCKS,
+        peripherals.PLL_SYS,
+        peripherals.PLL_USB,
+        &mut peripherals.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let delay = Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
+
+    let sio = Sio::new(peripherals.SIO);
+    let pins = board::take_pins(
+        peripherals.IO_BANK0,
+        peripherals.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut peripherals.RESETS,
+    );
+
+    unsafe {
+        BUS_ALLOCATOR = Some(UsbBusAllocator::new(UsbBus::new(
+            peripherals.USBCTRL_REGS,
+            peripherals.USBCTRL_DPRAM,
+            clocks.usb_clock,
+            true,
+            &mut peripherals.RESETS,
+        )));
+    }
+
+    // Setup PWM for pump and fan
+    let pwm_slices = Slices::new(peripherals.PWM, &mut peripherals.RESETS);
+    let mut pwm0 = pwm_slices.pwm0;
+    pwm0.channel_a.output_to(pins.pump_pwm);
+    pwm0.channel_b.output_to(pins.fan_pwm);
+    let pump_pwm = SlicePwm::new(pwm0, PWM_DIVIDER, PWM_TOP);
+
+    // NOTE: This is a 3v3 ADC. 0V -> 0 3.3V -> 4096
+    let adc = Adc::new(peripherals.ADC, &mut peripherals.RESETS);
+    let padc = PrandtlPumpFanAdc::new(
+        adc,
+        pins.pump_sense,
+        pins.fan_sense,
+        pins.pump_current,
+        pins.supply_sense,
+        pins.flow_pulse,
+        12,
+    );
+
+    // NOTE: This must happen before we enable USB interrupt.
+    unsafe {
+        APPLICATION = Some(Application::new(
+            Comms::new(UsbPacketIo::new(BUS_ALLOCATOR.as_ref().unwrap())),
+            Control::new(
+                pump_pwm,
+                SliceChannel::A,
+                SliceChannel::B,
+                pins.valve_control_1,
+                pins.valve_control_2,
+            ),
+            Sensing::new(padc, pins.valve_sense_1, pins.valve_sense_2),
+            delay,
+            board::CAPABILITIES,
+            FirmwareInfoPacket::new(
+                env!("CARGO_PKG_VERSION"),
+                env!("PRANDTL_GIT_HASH"),
+                board::BOARD_NAME,
+            ),
+            reset_cause,
+            RamSettingsStorage::new(),
+        ));
+    }
+
+    unsafe {
+        NVIC::unmask(hal::pac::Interrupt::USBCTRL_IRQ);
+    }
+}
+
+#[hal::entry]
+fn main() -> ! {
+    initialize();
+
+    let app = unsafe { APPLICATION.as_mut().unwrap() };
+
+    loop {
+        cortex_m::interrupt::free(|cs| {
+            app.read_packets(cs);
+            app.write_packets(cs);
+        });
+
+        app.core_loop();
+
+        // NOTE: Targets are output at once so nothing sees them half applied.
+        cortex_m::interrupt::free(|cs| app.apply_control_targets(cs));
+
+        // NOTE: Stored settings and the host can both change the frequency.
+        if let Some(pwm_frequency_hz) = app.take_pwm_frequency_hz() {
+            cortex_m::interrupt::free(|cs| app.set_pwm_period(pwm_top(pwm_frequency_hz), cs));
+        }
+
+        // NOTE: The pump and fan were just parked at the fail-safe duty.
+        match app.reset_requested() {
+            Some(ResetRequest::Reboot) => cortex_m::peripheral::SCB::sys_reset(),
+            Some(ResetRequest::Bootloader) => hal::rom_data::reset_to_usb_boot(0, 0),
+            None => {}
+        }
+
+        // NOTE: Read each iteration since the host can change it at runtime.
+        let core_loop_period_ms = app.core_loop_period_ms();
+        app.delay.delay_ms(core_loop_period_ms as u32);
+    }
+}
+
+#[interrupt]
+fn USBCTRL_IRQ() {
+    unsafe {
+        APPLICATION.as_mut().unwrap().poll_io();
+    }
+}
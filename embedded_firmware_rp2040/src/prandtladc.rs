@@ -0,0 +1,109 @@
+use crate::board::{FanSensePin, FlowPulsePin, PumpCurrentPin, PumpSensePin, SupplySensePin};
+use embedded_firmware_core::{
+    board_temperature::rp2040_temperature, convert_raw_to_normalized,
+    current_sense::CurrentSensorCalibration, PrandtlAdc,
+};
+use embedded_hal::{adc::OneShot, digital::v2::InputPin};
+use rp2040_hal::adc::{Adc, TempSense};
+
+/// The voltage represented by a full scale reading. The ADC reference is the
+/// 3v3 supply on the Pico.
+const ADC_FULL_SCALE_VOLTAGE: f32 = 3.3f32;
+
+/// The Pico measures VSYS through a divide by three on GPIO29.
+const SUPPLY_VOLTAGE_SCALE: f32 = 3f32;
+
+/// The 5V current sensor outputs pass through a divider to fit the 3v3 ADC.
+const CURRENT_SENSE_DIVIDER: f32 = 2f32 / 3f32;
+
+/// ACS712-05B output with no current flowing, before the divider.
+const CURRENT_SENSE_ZERO_VOLTAGE: f32 = 2.5f32;
+
+/// ACS712-05B sensitivity, before the divider.
+const CURRENT_SENSE_VOLTS_PER_AMP: f32 = 0.185f32;
+
+pub struct PrandtlPumpFanAdc {
+    adc: Adc,
+    pump_sense_channel: PumpSensePin,
+    fan_sense_channel: FanSensePin,
+    pump_current_channel: PumpCurrentPin,
+    supply_sense_channel: SupplySensePin,
+    flow_pulse_pin: FlowPulsePin,
+    temperature_sensor: Option<TempSense>,
+    current_calibration: Option<CurrentSensorCalibration>,
+    resolution: u8,
+}
+
+impl PrandtlPumpFanAdc {
+    pub fn new(
+        mut adc: Adc,
+        pump_sense_channel: PumpSensePin,
+        fan_sense_channel: FanSensePin,
+        pump_current_channel: PumpCurrentPin,
+        supply_sense_channel: SupplySensePin,
+        flow_pulse_pin: FlowPulsePin,
+        resolution: u8,
+    ) -> Self {
+        let temperature_sensor = adc.take_temp_sensor();
+        Self {
+            adc,
+            pump_sense_channel,
+            fan_sense_channel,
+            pump_current_channel,
+            supply_sense_channel,
+            flow_pulse_pin,
+            temperature_sensor,
+            current_calibration: CurrentSensorCalibration::new(
+                CURRENT_SENSE_ZERO_VOLTAGE * CURRENT_SENSE_DIVIDER,
+                CURRENT_SENSE_VOLTS_PER_AMP * CURRENT_SENSE_DIVIDER,
+            ),
+            resolution,
+        }
+    }
+
+    /// Convert a raw reading into the voltage at the ADC pin.
+    fn convert_voltage(&self, raw: u16) -> f32 {
+        convert_raw_to_normalized(raw, self.resolution) * ADC_FULL_SCALE_VOLTAGE
+    }
+}
+
+impl PrandtlAdc for PrandtlPumpFanAdc {
+    fn read_pump_sense_raw(&mut self) -> Option<u16> {
+        self.adc.read(&mut self.pump_sense_channel).ok()
+    }
+
+    fn read_fan_sense_raw(&mut self) -> Option<u16> {
+        self.adc.read(&mut self.fan_sense_channel).ok()
+    }
+
+    fn read_pump_sense_norm(&mut self) -> Option<f32> {
+        self.read_pump_sense_raw()
+            .map(|raw| convert_raw_to_normalized(raw, self.resolution))
+    }
+
+    fn read_fan_sense_norm(&mut self) -> Option<f32> {
+        self.read_fan_sense_raw()
+            .map(|raw| convert_raw_to_normalized(raw, self.resolution))
+    }
+
+    fn read_board_temperature(&mut self) -> Option<f32> {
+        let temperature_sensor = self.temperature_sensor.as_mut()?;
+        let raw: u16 = self.adc.read(temperature_sensor).ok()?;
+        Some(rp2040_temperature(self.convert_voltage(raw)))
+    }
+
+    fn read_supply_voltage(&mut self) -> Option<f32> {
+        let raw: u16 = self.adc.read(&mut self.supply_sense_channel).ok()?;
+        Some(self.convert_voltage(raw) * SUPPLY_VOLTAGE_SCALE)
+    }
+
+    fn read_pump_current(&mut self) -> Option<f32> {
+        let raw: u16 = self.adc.read(&mut self.pump_current_channel).ok()?;
+        let calibration = self.current_calibration?;
+        Some(calibration.current(self.convert_voltage(raw)))
+    }
+
+    fn read_flow_pulse(&mut self) -> Option<bool> {
+        self.flow_pulse_pin.is_high().ok()
+    }
+}
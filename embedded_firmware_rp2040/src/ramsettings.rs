@@ -0,0 +1,31 @@
+use common::packet::SettingsOrigin;
+use embedded_firmware_core::settings::{Settings, SettingsStorage, SettingsStorageError};
+
+/// Keeps the firmware settings in RAM. Changes from the host apply until the
+/// next reset, after which the defaults are used again.
+/// TODO: Persist to the last flash sector. Flash can't be read while it is
+///       being written, so this needs the write routine to run from RAM.
+pub struct RamSettingsStorage {
+    settings: Option<Settings>,
+}
+
+impl RamSettingsStorage {
+    pub fn new() -> Self {
+        Self { settings: None }
+    }
+}
+
+impl SettingsStorage for RamSettingsStorage {
+    fn load(&mut self) -> (Settings, SettingsOrigin) {
+        // NOTE: RAM is empty at boot, so this is always the defaults.
+        (
+            self.settings.unwrap_or_default(),
+            SettingsOrigin::Defaults { corrupt_banks: 0 },
+        )
+    }
+
+    fn store(&mut self, settings: &Settings) -> Result<(), SettingsStorageError> {
+        self.settings = Some(*settings);
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use embedded_hal::{Pwm, PwmPin};
+use rp2040_hal::pwm::{FreeRunning, Slice, SliceId, ValidSliceMode};
+
+/// One of the two outputs of an RP2040 PWM slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceChannel {
+    A,
+    B,
+}
+
+/// Exposes both channels of an RP2040 PWM slice through the multi-channel
+/// `Pwm` trait, matching how the SAMD21's TCC0 is driven.
+pub struct SlicePwm<I: SliceId>
+where
+    FreeRunning: ValidSliceMode<I>,
+{
+    slice: Slice<I, FreeRunning>,
+}
+
+impl<I: SliceId> SlicePwm<I>
+where
+    FreeRunning: ValidSliceMode<I>,
+{
+    /// Configure the slice to count from 0 to `top` with the system clock
+    /// divided by `divider`, then start it.
+    pub fn new(mut slice: Slice<I, FreeRunning>, divider: u8, top: u16) -> Self {
+        slice.set_div_int(divider);
+        slice.set_top(top);
+        slice.enable();
+        Self { slice }
+    }
+}
+
+impl<I: SliceId> Pwm for SlicePwm<I>
+where
+    FreeRunning: ValidSliceMode<I>,
+{
+    type Channel = SliceChannel;
+
+    /// The counter wrap value, in counts of the divided system clock.
+    type Time = u16;
+
+    type Duty = u32;
+
+    fn disable(&mut self, channel: Self::Channel) {
+        match channel {
+            SliceChannel::A => self.slice.channel_a.disable(),
+            SliceChannel::B => self.slice.channel_b.disable(),
+        }
+    }
+
+    fn enable(&mut self, channel: Self::Channel) {
+        match channel {
+            SliceChannel::A => self.slice.channel_a.enable(),
+            SliceChannel::B => self.slice.channel_b.enable(),
+        }
+    }
+
+    fn get_period(&self) -> Self::Time {
+        self.slice.get_top()
+    }
+
+    fn get_duty(&self, channel: Self::Channel) -> Self::Duty {
+        match channel {
+            SliceChannel::A => self.slice.channel_a.get_duty() as u32,
+            SliceChannel::B => self.slice.channel_b.get_duty() as u32,
+        }
+    }
+
+    fn get_max_duty(&self) -> Self::Duty {
+        self.slice.get_top() as u32
+    }
+
+    fn set_duty(&mut self, channel: Self::Channel, duty: Self::Duty) {
+        let duty = duty.min(u16::MAX as u32) as u16;
+        match channel {
+            SliceChannel::A => self.slice.channel_a.set_duty(duty),
+            SliceChannel::B => self.slice.channel_b.set_duty(duty),
+        }
+    }
+
+    fn set_period<P>(&mut self, period: P)
+    where
+        P: Into<Self::Time>,
+    {
+        self.slice.set_top(period.into());
+    }
+}